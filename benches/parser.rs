@@ -0,0 +1,90 @@
+use criterion::{black_box, criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use imap_client::message::{find_text_part, Headers};
+use imap_client::mock::synthetic_message;
+use imap_client::parser::parse_response;
+use imap_client::session::Session;
+
+/// A stream of `count` FETCH responses carrying messages of `size` bytes, as
+/// the batch loop receives them.
+fn fetch_stream(count: usize, size: usize) -> Vec<u8> {
+    let mut stream = Vec::new();
+    for i in 0..count {
+        let message = synthetic_message(i, size);
+        stream.extend_from_slice(
+            format!(
+                "* {} FETCH (UID {} X-GM-THRID {} BODY[] {{{}}}\r\n",
+                i + 1,
+                i + 1,
+                1_000_000 + i,
+                message.len()
+            )
+            .as_bytes(),
+        );
+        stream.extend_from_slice(&message);
+        stream.extend_from_slice(b")\r\n");
+    }
+    stream
+}
+
+fn parse_fetch_responses(c: &mut Criterion) {
+    let mut group = c.benchmark_group("parse_response");
+    for size in [1024, 20 * 1024, 1024 * 1024] {
+        let stream = fetch_stream(20, size);
+        group.throughput(Throughput::Bytes(stream.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &stream, |b, stream| {
+            b.iter(|| {
+                let mut input = &stream[..];
+                while !input.is_empty() {
+                    let (parsed, used) = parse_response(black_box(input)).unwrap();
+                    black_box(parsed);
+                    input = &input[used..];
+                }
+            })
+        });
+    }
+    group.finish();
+}
+
+fn read_fetch_responses(c: &mut Criterion) {
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .build()
+        .unwrap();
+    let mut group = c.benchmark_group("read_response");
+    for size in [1024, 20 * 1024, 1024 * 1024] {
+        let stream = fetch_stream(20, size);
+        group.throughput(Throughput::Bytes(stream.len() as u64));
+        group.bench_with_input(BenchmarkId::from_parameter(size), &stream, |b, stream| {
+            b.iter(|| {
+                runtime.block_on(async {
+                    let connection = tokio::io::join(&stream[..], tokio::io::sink());
+                    let mut session = Session::new(connection);
+                    for _ in 0..20 {
+                        black_box(session.read_response().await.unwrap());
+                    }
+                })
+            })
+        });
+    }
+    group.finish();
+}
+
+fn parse_messages(c: &mut Criterion) {
+    let message = synthetic_message(1, 20 * 1024);
+    let mut group = c.benchmark_group("message");
+    group.throughput(Throughput::Bytes(message.len() as u64));
+    group.bench_function("headers", |b| {
+        b.iter(|| Headers::parse(black_box(&message)))
+    });
+    group.bench_function("find_text_part", |b| {
+        b.iter(|| find_text_part(black_box(&message), "text/plain"))
+    });
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    parse_fetch_responses,
+    read_fetch_responses,
+    parse_messages
+);
+criterion_main!(benches);
@@ -0,0 +1,13 @@
+#![no_main]
+
+use imap_client::parser::parse_response;
+use libfuzzer_sys::fuzz_target;
+
+// Feed a stream of responses through the parser, as the fetch loop does.
+fuzz_target!(|data: &[u8]| {
+    let mut input = data;
+    while let Ok((_, used)) = parse_response(input) {
+        assert!(used > 0 && used <= input.len());
+        input = &input[used..];
+    }
+});
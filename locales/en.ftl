@@ -0,0 +1,58 @@
+# Messages printed by imap_client. Each translation in this directory gives
+# its own text for the same ids; ids it leaves out are shown in English.
+
+## Fetching
+
+fetch-partial = Email fetching finished with { $failed } failed batches
+fetch-completed = Email fetching completed successfully
+fetch-failed = Failed to fetch emails. Please try again.
+progress = Progress: { $accounts }
+progress-account = { $account } { $done }/{ $total }
+progress-failed-batches = ({ $failed } failed batches)
+progress-plain-account = { $account }: { $done } of { $total } messages, { $percent }%{ $failed ->
+    [0] {""}
+   *[other] , { $failed } failed batches
+}
+
+## Summary of a run
+
+summary = Summary
+summary-account = { $account }: { $saved } of { $found } emails saved, { $failed } failed batches
+summary-server = server: { $server }
+summary-alert = server alert: { $alert }
+summary-account-failed = { $account }: failed: { $error }
+
+## The report command
+
+report-counted = Counted { $messages } messages in { $archive }
+report-skipped = { $skipped } catalogued messages were left out, as their file is missing or holds several messages
+report-failed = Failed to report on { $archive }: { $error }
+
+## The HTML report
+
+html-title = Mail archive report
+html-last-run = Last run
+html-no-run = No fetch has been recorded in this archive.
+html-account = Account
+html-started = Started
+html-finished = Finished
+html-found = Messages found
+html-saved = Messages saved
+html-outcome = Outcome
+html-outcome-failed = Failed: { $error }
+html-outcome-completed = Completed
+html-outcome-partial = Completed with { $failed } failed batches
+html-errors = Errors
+html-no-errors = No messages are waiting to be fetched again.
+html-folder = Folder
+html-messages = Messages
+html-error = Error
+html-server-response = Server response
+html-archive = Archive
+html-totals = { $messages } messages, { $size }.
+html-top-senders = Top senders
+html-top-recipients = Top recipients
+html-per-month = Messages per month
+html-address = Address
+html-month = Month
+html-size = Size
@@ -0,0 +1,73 @@
+# Mensagens do imap_client em português do Brasil.
+
+## Download
+
+fetch-partial = { $failed ->
+    [one] Download de e-mails concluído com { $failed } lote com falha
+   *[other] Download de e-mails concluído com { $failed } lotes com falha
+}
+fetch-completed = Download de e-mails concluído com sucesso
+fetch-failed = Não foi possível baixar os e-mails. Tente novamente.
+progress = Progresso: { $accounts }
+progress-account = { $account } { $done }/{ $total }
+progress-failed-batches = { $failed ->
+    [one] ({ $failed } lote com falha)
+   *[other] ({ $failed } lotes com falha)
+}
+progress-plain-account = { $account }: { $done } de { $total } mensagens, { $percent }%{ $failed ->
+    [0] {""}
+    [one] , { $failed } lote com falha
+   *[other] , { $failed } lotes com falha
+}
+
+## Resumo da execução
+
+summary = Resumo
+summary-account = { $account }: { $saved } de { $found } e-mails salvos, { $failed ->
+    [one] { $failed } lote com falha
+   *[other] { $failed } lotes com falha
+}
+summary-server = servidor: { $server }
+summary-alert = alerta do servidor: { $alert }
+summary-account-failed = { $account }: falhou: { $error }
+
+## O comando report
+
+report-counted = { $messages } mensagens contadas em { $archive }
+report-skipped = { $skipped ->
+    [one] { $skipped } mensagem do catálogo ficou de fora, pois seu arquivo não existe ou contém várias mensagens
+   *[other] { $skipped } mensagens do catálogo ficaram de fora, pois seu arquivo não existe ou contém várias mensagens
+}
+report-failed = Não foi possível gerar o relatório de { $archive }: { $error }
+
+## O relatório em HTML
+
+html-title = Relatório do arquivo de e-mails
+html-last-run = Última execução
+html-no-run = Nenhum download foi registrado neste arquivo.
+html-account = Conta
+html-started = Início
+html-finished = Fim
+html-found = Mensagens encontradas
+html-saved = Mensagens salvas
+html-outcome = Resultado
+html-outcome-failed = Falhou: { $error }
+html-outcome-completed = Concluído
+html-outcome-partial = { $failed ->
+    [one] Concluído com { $failed } lote com falha
+   *[other] Concluído com { $failed } lotes com falha
+}
+html-errors = Erros
+html-no-errors = Nenhuma mensagem aguarda um novo download.
+html-folder = Pasta
+html-messages = Mensagens
+html-error = Erro
+html-server-response = Resposta do servidor
+html-archive = Arquivo
+html-totals = { $messages } mensagens, { $size }.
+html-top-senders = Principais remetentes
+html-top-recipients = Principais destinatários
+html-per-month = Mensagens por mês
+html-address = Endereço
+html-month = Mês
+html-size = Tamanho
@@ -0,0 +1,697 @@
+use crate::audit::load_signing_key;
+use crate::client::{Backend, FetchOrder};
+use crate::convert::ArchiveFormat;
+use crate::diskspace::parse_size;
+use crate::dns::Resolver;
+use crate::error_imap::ClientError;
+use crate::export::ExportFormat;
+use crate::input::ImapConfig;
+use crate::net::{parse_tor, IpPreference, TOR_PROXY};
+use crate::pacing::parse_delay;
+use crate::profile::Profile;
+use crate::progress::ProgressMode;
+use crate::provider::Provider;
+use crate::redact::Pattern;
+use crate::retention::RetentionPolicy;
+use crate::rules::RuleSet;
+use crate::scheduler::{parse_bandwidth, Limits};
+use crate::session::{parse_read_buffer, SequenceSet};
+use crate::storage::{ExistingFilePolicy, LineEndings, Organize, OutputFormat, ThreadMode};
+use crate::tls::{client_config, parse_cipher_suites, Pin, TlsOptions, TlsVersion};
+use crate::views::{parse_views, View};
+use crate::window::parse_bound;
+use crate::writer::ensure_io_uring;
+use rustls::CipherSuite;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+/// What the program was asked to do.
+#[derive(Debug, Default, PartialEq, Eq)]
+pub enum Command {
+    /// Download mail (the default).
+    #[default]
+    Fetch,
+    /// Report, and optionally remove, duplicate messages in an existing archive.
+    Dedupe { dir: String, remove: bool },
+    /// Print or save the single message with this Message-ID.
+    Get {
+        message_id: String,
+        output: Option<String>,
+    },
+    /// Copy an existing archive into another mail program's format.
+    Export {
+        dir: String,
+        output: PathBuf,
+        format: ExportFormat,
+    },
+    /// Add existing `.eml` files to an archive's catalog, so they aren't
+    /// downloaded again.
+    Import { dir: String, archive: PathBuf },
+    /// Count an archive's messages by sender, recipient and month, and write
+    /// the tables to `output` (`report/` in the archive by default). With
+    /// `html`, also write a page showing them with the last run and its
+    /// failures.
+    Report {
+        dir: String,
+        output: Option<PathBuf>,
+        top: usize,
+        html: bool,
+    },
+    /// Rewrite an existing archive in another storage format, offline.
+    Convert {
+        dir: String,
+        output: PathBuf,
+        to: ArchiveFormat,
+    },
+    /// Add or remove a Gmail label on the messages matching a search or UIDs.
+    Label {
+        label: String,
+        remove: bool,
+        search: Option<String>,
+        dry_run: bool,
+    },
+    /// Delete archived messages matching a search from the server.
+    Purge { search: String, dry_run: bool },
+    /// Check the archive against its catalog and fetch damaged messages again.
+    Repair { prune: bool, dry_run: bool },
+    /// Print the commands that would reverse the operations in a journal.
+    UndoPlan { path: PathBuf },
+    /// Parse captured server responses offline, filing the messages in them
+    /// into `output` if given.
+    Replay {
+        capture: PathBuf,
+        output: Option<PathBuf>,
+    },
+    /// Check the chain and signatures of an audit log.
+    VerifyAudit { path: PathBuf },
+    /// Compare each account's folders with the local archive, without
+    /// downloading messages.
+    Diff,
+    /// Report each account's quota usage, message counts and sizes,
+    /// downloading `sample` messages to project the archive's size.
+    Stats { sample: usize },
+    /// List each account's folders, their special use and whether they would
+    /// be fetched.
+    Folders,
+    /// Connect and sign in to each account, list the server's capabilities
+    /// and the account's folders, and stop.
+    Check,
+    /// Sign each account in to its provider in the browser and keep the
+    /// refresh token granted in the system keyring.
+    Login,
+}
+
+/// Options given on the command line. Anything not given here is prompted
+/// for interactively.
+#[derive(Debug, Default)]
+pub struct CliArgs {
+    pub command: Command,
+    /// File describing several accounts to fetch in parallel.
+    pub accounts_file: Option<String>,
+    pub provider: Option<Provider>,
+    /// IMAP server overriding the provider's.
+    pub server: Option<String>,
+    /// Name and version to send in the ID command.
+    pub client_name: Option<String>,
+    pub client_version: Option<String>,
+    /// OAuth client for providers signing in with the device flow.
+    pub oauth_client_id: Option<String>,
+    pub backend: Option<Backend>,
+    pub jmap_url: Option<String>,
+    /// Fetch every folder instead of just INBOX.
+    pub all_folders: bool,
+    /// Also fetch the provider's Sent and Drafts folders.
+    pub include_sent: bool,
+    pub include_drafts: bool,
+    /// Folder glob patterns applied to every account in all-folders mode.
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+    /// Per-message rules, replacing any from the accounts file.
+    pub rules: Option<RuleSet>,
+    /// WebAssembly plugins, run after any from the accounts file.
+    pub plugins: Vec<PathBuf>,
+    /// Redaction patterns, added to any from the accounts file.
+    pub redact: Vec<Pattern>,
+    pub format: Option<OutputFormat>,
+    pub line_endings: Option<LineEndings>,
+    pub threads: Option<ThreadMode>,
+    pub organize: Option<Organize>,
+    pub views: Option<Vec<View>>,
+    /// Write a UTF-8 plain-text copy of each message's body next to the `.eml`.
+    pub export_text: bool,
+    /// Fall back to a text rendering of the HTML body for the `.txt` copy.
+    pub html_text: bool,
+    pub skip_duplicates: bool,
+    pub if_exists: Option<ExistingFilePolicy>,
+    pub io_uring: bool,
+    pub group_writes: bool,
+    pub min_free_space: Option<u64>,
+    pub max_messages: Option<u32>,
+    pub max_bytes: Option<u64>,
+    pub order: Option<FetchOrder>,
+    pub uids: Option<SequenceSet>,
+    /// Bounds of `--since` and `--before`, as Unix timestamps.
+    pub since: Option<i64>,
+    pub before: Option<i64>,
+    pub by_month: bool,
+    /// Force sync state to disk after this many messages, or this many seconds.
+    pub sync_every: Option<u32>,
+    pub sync_interval: Option<u64>,
+    /// Measure fetch throughput against a local mock server instead of fetching.
+    pub bench_local: bool,
+    pub retry_from: Option<PathBuf>,
+    pub read_only: bool,
+    pub drain: bool,
+    pub drain_check: Option<String>,
+    /// Retention policies, replacing any from the accounts file.
+    pub retention: Vec<RetentionPolicy>,
+    pub audit_log: Option<PathBuf>,
+    pub audit_key: Option<PathBuf>,
+    /// Trace every IMAP command and response to this file.
+    pub trace_imap: Option<PathBuf>,
+    /// Show the interactive terminal UI instead of printing progress.
+    pub tui: bool,
+    /// Show a desktop notification when the run ends.
+    pub notify: bool,
+    /// Language of messages and reports, instead of the environment's.
+    pub locale: Option<String>,
+    pub progress: ProgressMode,
+    /// Tuning applied to every account, replacing the accounts file's.
+    pub profile: Option<Profile>,
+    pub batch_delay: Option<Duration>,
+    pub batch_jitter: Option<Duration>,
+    pub read_buffer: Option<usize>,
+    pub ip_preference: Option<IpPreference>,
+    pub resolver: Option<Resolver>,
+    /// Connect through Tor's SOCKS proxy at this address.
+    pub tor: Option<SocketAddr>,
+    /// Client certificate for mutual TLS, replacing the accounts file's.
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    /// Pinned keys, replacing the accounts file's.
+    pub pins: Vec<Pin>,
+    pub tls_min_version: Option<TlsVersion>,
+    pub tls_ciphers: Option<Vec<CipherSuite>>,
+    pub no_session_tickets: bool,
+    /// Log how each connection is made, whatever `RUST_LOG` says.
+    pub debug_connection: bool,
+    /// Connection and bandwidth limits shared by every account of the run.
+    pub limits: Limits,
+}
+
+impl CliArgs {
+    /// Applies the options given on the command line on top of an account's
+    /// own settings.
+    pub fn apply_to(&self, config: &mut ImapConfig) {
+        if let Some(profile) = self.profile {
+            profile.apply(config);
+        }
+        if let Some(delay) = self.batch_delay {
+            config.batch_delay = delay;
+        }
+        if let Some(jitter) = self.batch_jitter {
+            config.batch_jitter = jitter;
+        }
+        if let Some(size) = self.read_buffer {
+            config.read_buffer = size;
+        }
+        if let Some(preference) = self.ip_preference {
+            config.ip_preference = preference;
+        }
+        if let Some(resolver) = &self.resolver {
+            config.resolver = resolver.clone();
+        }
+        if self.tor.is_some() {
+            config.tor = self.tor;
+        }
+        config.tls = self.tls_options(&config.tls);
+        if let Some(provider) = self.provider {
+            config.provider = provider;
+        }
+        if let Some(server) = &self.server {
+            config.server = Some(server.clone());
+        }
+        if let Some(name) = &self.client_name {
+            config.identity.name = name.clone();
+        }
+        if let Some(version) = &self.client_version {
+            config.identity.version = version.clone();
+        }
+        if let Some(client_id) = &self.oauth_client_id {
+            config.oauth.get_or_insert_with(Default::default).client_id = client_id.clone();
+        }
+        if let Some(backend) = self.backend {
+            config.backend = backend;
+        }
+        if let Some(url) = &self.jmap_url {
+            config.jmap_url = url.clone();
+        }
+        config.all_folders |= self.all_folders;
+        config.include_sent |= self.include_sent;
+        config.include_drafts |= self.include_drafts;
+        config
+            .folder_filter
+            .include
+            .extend(self.include.iter().cloned());
+        config
+            .folder_filter
+            .exclude
+            .extend(self.exclude.iter().cloned());
+        if let Some(rules) = &self.rules {
+            config.rules = rules.clone();
+        }
+        config.plugins.extend(self.plugins.iter().cloned());
+        for pattern in &self.redact {
+            config.redact.push(pattern.clone());
+        }
+        if let Some(format) = self.format {
+            config.format = format;
+        }
+        if let Some(line_endings) = self.line_endings {
+            config.line_endings = line_endings;
+        }
+        if self.threads.is_some() {
+            config.threads = self.threads;
+        }
+        if self.organize.is_some() {
+            config.organize = self.organize;
+        }
+        if let Some(views) = &self.views {
+            config.views = views.clone();
+        }
+        config.export_text |= self.export_text;
+        config.html_text |= self.html_text;
+        config.skip_duplicates |= self.skip_duplicates;
+        config.io_uring |= self.io_uring;
+        config.group_writes |= self.group_writes;
+        if let Some(policy) = self.if_exists {
+            config.if_exists = policy;
+        }
+        if let Some(bytes) = self.min_free_space {
+            config.min_free_space = bytes;
+        }
+        if self.max_messages.is_some() {
+            config.max_messages = self.max_messages;
+        }
+        if self.max_bytes.is_some() {
+            config.max_bytes = self.max_bytes;
+        }
+        if let Some(order) = self.order {
+            config.order = order;
+        }
+        if self.uids.is_some() {
+            config.uids = self.uids.clone();
+        }
+        if self.since.is_some() {
+            config.dates.since = self.since;
+        }
+        if self.before.is_some() {
+            config.dates.before = self.before;
+        }
+        config.by_month |= self.by_month;
+        if let Some(records) = self.sync_every {
+            config.sync.records = records;
+        }
+        if let Some(seconds) = self.sync_interval {
+            config.sync.interval = Duration::from_secs(seconds);
+        }
+        if self.retry_from.is_some() {
+            config.retry_from = self.retry_from.clone();
+        }
+        config.read_only |= self.read_only;
+        config.drain |= self.drain;
+        if self.drain_check.is_some() {
+            config.drain_check = self.drain_check.clone();
+        }
+        if !self.retention.is_empty() {
+            config.retention = self.retention.clone();
+        }
+        if self.audit_log.is_some() {
+            config.audit_log = self.audit_log.clone();
+        }
+        if self.audit_key.is_some() {
+            config.audit_key = self.audit_key.clone();
+        }
+        if self.trace_imap.is_some() {
+            config.trace_imap = self.trace_imap.clone();
+        }
+        config.quiet |= self.tui;
+    }
+
+    /// The TLS options given on the command line on top of `base`. A client
+    /// certificate replaces the other's along with its key, and pins replace
+    /// all of the other's.
+    pub fn tls_options(&self, base: &TlsOptions) -> TlsOptions {
+        let mut options = base.clone();
+        if self.client_cert.is_some() || self.client_key.is_some() {
+            options.client_cert = self.client_cert.clone();
+            options.client_key = self.client_key.clone();
+        }
+        if !self.pins.is_empty() {
+            options.pins = self.pins.clone();
+        }
+        if let Some(version) = self.tls_min_version {
+            options.min_version = version;
+        }
+        if let Some(suites) = &self.tls_ciphers {
+            options.cipher_suites = suites.clone();
+        }
+        options.session_tickets &= !self.no_session_tickets;
+        options
+    }
+}
+
+pub fn parse_args<I>(args: I) -> Result<CliArgs, ClientError>
+where
+    I: IntoIterator<Item = String>,
+{
+    let mut parsed = CliArgs::default();
+    let mut args = args.into_iter();
+    let mut positional = Vec::new();
+    let mut remove = false;
+    let mut output = None;
+    let mut export_format = None;
+    let mut convert_to = None;
+    let mut search = None;
+    let mut dry_run = false;
+    let mut prune = false;
+    let mut sample = 0;
+    let mut top = 20;
+    let mut html = false;
+    let mut confirmed = false;
+    let mut undo_plan = None;
+    let mut replay = None;
+
+    while let Some(arg) = args.next() {
+        // Accept both `--flag value` and `--flag=value`
+        let (flag, inline_value) = match arg.split_once('=') {
+            Some((flag, value)) if flag.starts_with("--") => {
+                (flag.to_string(), Some(value.to_string()))
+            }
+            _ => (arg.clone(), None),
+        };
+        let mut value = || -> Result<String, ClientError> {
+            match inline_value.clone().or_else(|| args.next()) {
+                Some(value) => Ok(value),
+                None => Err(ClientError::InvalidArgument(format!(
+                    "{} requires a value",
+                    flag
+                ))),
+            }
+        };
+
+        match flag.as_str() {
+            "--accounts" => parsed.accounts_file = Some(value()?),
+            "--provider" => parsed.provider = Some(value()?.parse()?),
+            "--server" => parsed.server = Some(value()?),
+            "--client-name" => parsed.client_name = Some(value()?),
+            "--client-version" => parsed.client_version = Some(value()?),
+            "--oauth-client-id" => parsed.oauth_client_id = Some(value()?),
+            "--backend" => parsed.backend = Some(value()?.parse()?),
+            "--jmap-url" => parsed.jmap_url = Some(value()?),
+            "--all-folders" => parsed.all_folders = true,
+            "--include-sent" => parsed.include_sent = true,
+            "--include-drafts" => parsed.include_drafts = true,
+            "--include" => parsed.include.push(value()?),
+            "--exclude" => parsed.exclude.push(value()?),
+            "--rules" => parsed.rules = Some(RuleSet::load(Path::new(&value()?))?),
+            "--redact" => parsed.redact.push(value()?.parse()?),
+            "--plugin" if cfg!(feature = "wasm") => parsed.plugins.push(PathBuf::from(value()?)),
+            "--plugin" => {
+                return Err(ClientError::InvalidArgument(
+                    "--plugin is not available in this build; rebuild with --features wasm"
+                        .to_string(),
+                ))
+            }
+            "--format" => parsed.format = Some(value()?.parse()?),
+            "--line-endings" => parsed.line_endings = Some(value()?.parse()?),
+            "--threads" => parsed.threads = Some(value()?.parse()?),
+            "--organize" => parsed.organize = Some(value()?.parse()?),
+            "--views" => parsed.views = Some(parse_views(&value()?)?),
+            "--text" => parsed.export_text = true,
+            "--html-text" => {
+                parsed.export_text = true;
+                parsed.html_text = true;
+            }
+            "--skip-duplicates" => parsed.skip_duplicates = true,
+            "--if-exists" => parsed.if_exists = Some(value()?.parse()?),
+            "--group-writes" => parsed.group_writes = true,
+            "--io-uring" => {
+                ensure_io_uring()?;
+                parsed.io_uring = true;
+            }
+            "--min-free-space" => parsed.min_free_space = Some(parse_size(&value()?)?),
+            "--max-messages" => {
+                let value = value()?;
+                parsed.max_messages = Some(value.parse().map_err(|_| {
+                    ClientError::InvalidArgument(format!("Invalid message count: {}", value))
+                })?);
+            }
+            "--max-bytes" => parsed.max_bytes = Some(parse_size(&value()?)?),
+            "--order" => parsed.order = Some(value()?.parse()?),
+            "--uids" => parsed.uids = Some(value()?.parse()?),
+            "--since" => parsed.since = Some(parse_bound(&value()?)?),
+            "--before" => parsed.before = Some(parse_bound(&value()?)?),
+            "--by-month" => parsed.by_month = true,
+            "--sync-every" => {
+                let value = value()?;
+                parsed.sync_every =
+                    Some(value.parse().ok().filter(|&n| n > 0).ok_or_else(|| {
+                        ClientError::InvalidArgument(format!("Invalid message count: {}", value))
+                    })?);
+            }
+            "--sync-interval" => {
+                let value = value()?;
+                parsed.sync_interval = Some(value.parse().map_err(|_| {
+                    ClientError::InvalidArgument(format!("Invalid number of seconds: {}", value))
+                })?);
+            }
+            "--profile" => parsed.profile = Some(value()?.parse()?),
+            "--batch-delay" => parsed.batch_delay = Some(parse_delay(&value()?)?),
+            "--batch-jitter" => parsed.batch_jitter = Some(parse_delay(&value()?)?),
+            "--prefer-ipv4" => parsed.ip_preference = Some(IpPreference::Ipv4),
+            "--prefer-ipv6" => parsed.ip_preference = Some(IpPreference::Ipv6),
+            "--dns" => parsed.resolver = Some(value()?.parse()?),
+            "--tor" => parsed.tor = TOR_PROXY.parse().ok(),
+            "--tor-proxy" => parsed.tor = parse_tor(&value()?)?,
+            "--client-cert" => parsed.client_cert = Some(PathBuf::from(value()?)),
+            "--client-key" => parsed.client_key = Some(PathBuf::from(value()?)),
+            "--pin" => parsed.pins.push(value()?.parse()?),
+            "--tls-min-version" => parsed.tls_min_version = Some(value()?.parse()?),
+            "--tls-ciphers" => parsed.tls_ciphers = Some(parse_cipher_suites(&value()?)?),
+            "--no-session-tickets" => parsed.no_session_tickets = true,
+            "--debug-connection" => parsed.debug_connection = true,
+            "--read-buffer" => parsed.read_buffer = Some(parse_read_buffer(&value()?)?),
+            "--max-connections" => parsed.limits.connections = Some(parse_connections(&value()?)?),
+            "--domain-connections" => {
+                parsed.limits.domain_connections = Some(parse_connections(&value()?)?)
+            }
+            "--max-bandwidth" => parsed.limits.bandwidth = Some(parse_bandwidth(&value()?)?),
+            "--remove" => remove = true,
+            "--bench-local" if cfg!(feature = "bench") => parsed.bench_local = true,
+            "--bench-local" => {
+                return Err(ClientError::InvalidArgument(
+                    "--bench-local is not available in this build; rebuild with --features bench"
+                        .to_string(),
+                ))
+            }
+            "--read-only" => parsed.read_only = true,
+            "--drain" => parsed.drain = true,
+            "--drain-check" => parsed.drain_check = Some(value()?),
+            "--retention" => parsed.retention.push(value()?.parse()?),
+            "--audit-log" => parsed.audit_log = Some(PathBuf::from(value()?)),
+            "--audit-key" => {
+                // Check the key now rather than when the run is over
+                let path = PathBuf::from(value()?);
+                load_signing_key(&path)?;
+                parsed.audit_key = Some(path);
+            }
+            "--trace-imap" => parsed.trace_imap = Some(PathBuf::from(value()?)),
+            "--retry-from" => parsed.retry_from = Some(PathBuf::from(value()?)),
+            "--output" => output = Some(value()?),
+            "--emlx" => export_format = Some(ExportFormat::Emlx),
+            "--thunderbird" => export_format = Some(ExportFormat::Thunderbird),
+            "--to" => convert_to = Some(value()?.parse()?),
+            "--search" => search = Some(value()?),
+            "--dry-run" => dry_run = true,
+            "--prune" => prune = true,
+            "--top" => {
+                let value = value()?;
+                top = value.parse().ok().filter(|&n| n > 0).ok_or_else(|| {
+                    ClientError::InvalidArgument(format!("Invalid number of addresses: {}", value))
+                })?;
+            }
+            "--html" => html = true,
+            "--locale" => parsed.locale = Some(value()?),
+            "--progress" => parsed.progress = value()?.parse()?,
+            "--sample" => {
+                let value = value()?;
+                sample = value.parse().map_err(|_| {
+                    ClientError::InvalidArgument(format!("Invalid message count: {}", value))
+                })?;
+            }
+            "--yes-i-mean-it" => confirmed = true,
+            "--undo-plan" => undo_plan = Some(PathBuf::from(value()?)),
+            "--replay" => replay = Some(PathBuf::from(value()?)),
+            "--tui" if cfg!(feature = "tui") => parsed.tui = true,
+            "--tui" => {
+                return Err(ClientError::InvalidArgument(
+                    "--tui is not available in this build; rebuild with --features tui".to_string(),
+                ))
+            }
+            "--notify" if cfg!(feature = "notifications") => parsed.notify = true,
+            "--notify" => return Err(ClientError::InvalidArgument(
+                "--notify is not available in this build; rebuild with --features notifications"
+                    .to_string(),
+            )),
+            _ if !arg.starts_with("--") => positional.push(arg),
+            _ => {
+                return Err(ClientError::InvalidArgument(format!(
+                    "Unknown option: {}",
+                    arg
+                )))
+            }
+        }
+    }
+
+    let replay = replay.map(|capture| Command::Replay {
+        capture,
+        output: output.clone().map(PathBuf::from),
+    });
+    let mut positional = positional.into_iter();
+    parsed.command = match positional.next().as_deref() {
+        None | Some("fetch") => Command::Fetch,
+        Some("dedupe") => Command::Dedupe {
+            dir: positional.next().ok_or_else(|| {
+                ClientError::InvalidArgument("dedupe requires an archive directory".to_string())
+            })?,
+            remove,
+        },
+        Some("get") => Command::Get {
+            message_id: positional.next().ok_or_else(|| {
+                ClientError::InvalidArgument("get requires a Message-ID".to_string())
+            })?,
+            output,
+        },
+        Some("export") => Command::Export {
+            dir: positional.next().ok_or_else(|| {
+                ClientError::InvalidArgument("export requires an archive directory".to_string())
+            })?,
+            output: output.map(PathBuf::from).ok_or_else(|| {
+                ClientError::InvalidArgument("export requires --output".to_string())
+            })?,
+            format: export_format.ok_or_else(|| {
+                ClientError::InvalidArgument("export requires a format, such as --emlx".to_string())
+            })?,
+        },
+        Some("import") => {
+            let dir = positional.next().ok_or_else(|| {
+                ClientError::InvalidArgument("import requires a directory of messages".to_string())
+            })?;
+            Command::Import {
+                archive: output.map_or_else(|| PathBuf::from(&dir), PathBuf::from),
+                dir,
+            }
+        }
+        Some("report") => Command::Report {
+            dir: positional.next().ok_or_else(|| {
+                ClientError::InvalidArgument("report requires an archive directory".to_string())
+            })?,
+            output: output.map(PathBuf::from),
+            top,
+            html,
+        },
+        Some("convert") => Command::Convert {
+            dir: positional.next().ok_or_else(|| {
+                ClientError::InvalidArgument("convert requires an archive directory".to_string())
+            })?,
+            output: output.map(PathBuf::from).ok_or_else(|| {
+                ClientError::InvalidArgument("convert requires --output".to_string())
+            })?,
+            to: convert_to.ok_or_else(|| {
+                ClientError::InvalidArgument(
+                    "convert requires --to eml, mbox or maildir".to_string(),
+                )
+            })?,
+        },
+        Some("label") => Command::Label {
+            label: positional.next().ok_or_else(|| {
+                ClientError::InvalidArgument("label requires a label name".to_string())
+            })?,
+            remove,
+            search,
+            dry_run,
+        },
+        Some("purge") => {
+            let search = search
+                .filter(|search| !search.trim().is_empty())
+                .ok_or_else(|| {
+                    ClientError::InvalidArgument("purge requires --search".to_string())
+                })?;
+            if !dry_run && !confirmed {
+                return Err(ClientError::InvalidArgument(
+                    "purge deletes messages from the server; pass --yes-i-mean-it to go ahead"
+                        .to_string(),
+                ));
+            }
+            Command::Purge { search, dry_run }
+        }
+        Some("repair") => Command::Repair { prune, dry_run },
+        Some("folders") => Command::Folders,
+        Some("login") => Command::Login,
+        Some("check") => Command::Check,
+        Some("stats") => Command::Stats { sample },
+        Some("diff") => Command::Diff,
+        Some("verify-audit") => Command::VerifyAudit {
+            path: positional.next().map(PathBuf::from).ok_or_else(|| {
+                ClientError::InvalidArgument("verify-audit requires an audit log".to_string())
+            })?,
+        },
+        Some(other) => {
+            return Err(ClientError::InvalidArgument(format!(
+                "Unknown command: {}",
+                other
+            )))
+        }
+    };
+    // Check the TLS options go together, and the certificate can be read
+    client_config(&parsed.tls_options(&TlsOptions::default()))?;
+    // Limits given on their own win over the profile's
+    if let Some(profile) = parsed.profile {
+        parsed.limits = parsed.limits.or(profile.limits());
+    }
+    if let Some(path) = undo_plan {
+        if parsed.command != Command::Fetch {
+            return Err(ClientError::InvalidArgument(
+                "--undo-plan can't be combined with a command".to_string(),
+            ));
+        }
+        parsed.command = Command::UndoPlan { path };
+    }
+    if let Some(replay) = replay {
+        if parsed.command != Command::Fetch {
+            return Err(ClientError::InvalidArgument(
+                "--replay can't be combined with a command".to_string(),
+            ));
+        }
+        parsed.command = replay;
+    }
+    if let Some(extra) = positional.next() {
+        return Err(ClientError::InvalidArgument(format!(
+            "Unexpected argument: {}",
+            extra
+        )));
+    }
+
+    Ok(parsed)
+}
+
+/// A number of connections, at least one.
+fn parse_connections(value: &str) -> Result<usize, ClientError> {
+    value
+        .parse()
+        .ok()
+        .filter(|&n| n > 0)
+        .ok_or_else(|| ClientError::InvalidArgument(format!("Invalid connection count: {}", value)))
+}
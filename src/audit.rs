@@ -0,0 +1,223 @@
+use ed25519_dalek::{Signature, Signer, SigningKey, Verifier, VerifyingKey};
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::catalog::sha256_hex;
+use crate::error_imap::ClientError;
+
+/// Name of the audit log written to the root of an archive directory.
+pub const AUDIT_FILE: &str = "audit.log";
+
+/// Append-only chain-of-custody log: one
+/// `timestamp<TAB>connection<TAB>event<TAB>chain` line per event, where
+/// `chain` is the SHA-256 of the previous line's chain value followed by this
+/// line's first three fields. Editing, removing or reordering any line breaks
+/// every chain value after it. Connection 0 is used for events that don't
+/// belong to a connection.
+///
+/// With an operator key, [`AuditLog::seal`] adds a line signing the chain so
+/// far, so the log can't be rewritten wholesale either.
+///
+/// The file is opened on the first event, and a failure to write is an error
+/// rather than a warning: an audit trail with gaps proves nothing.
+pub struct AuditLog {
+    path: PathBuf,
+    key_file: Option<PathBuf>,
+    writer: Mutex<Option<Writer>>,
+    connections: AtomicU32,
+}
+
+struct Writer {
+    file: File,
+    /// Chain value of the last line written.
+    chain: String,
+}
+
+impl AuditLog {
+    /// A log appending to `path`, signed with the Ed25519 key in `key_file`
+    /// (see [`load_signing_key`]) when one is given.
+    pub fn new(path: PathBuf, key_file: Option<PathBuf>) -> Self {
+        AuditLog {
+            path,
+            key_file,
+            writer: Mutex::new(None),
+            connections: AtomicU32::new(0),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Numbers a new connection, logging that it was opened.
+    pub fn open_connection(&self, server: &str) -> Result<u32, ClientError> {
+        let connection = self.connections.fetch_add(1, Ordering::Relaxed) + 1;
+        self.record(connection, &format!("connect {}", server))?;
+        Ok(connection)
+    }
+
+    pub fn record(&self, connection: u32, event: &str) -> Result<(), ClientError> {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        // Keep one event per line and four fields per line whatever was sent
+        let event = event.replace(['\r', '\n', '\t'], " ");
+        let fields = format!(
+            "{}.{:03}\t{}\t{}",
+            timestamp.as_secs(),
+            timestamp.subsec_millis(),
+            connection,
+            event
+        );
+
+        let mut writer = self.writer.lock().map_err(|_| self.poisoned())?;
+        if writer.is_none() {
+            *writer = Some(self.open()?);
+        }
+        if let Some(writer) = writer.as_mut() {
+            let chain = chain_hash(&writer.chain, &fields);
+            writer
+                .file
+                .write_all(format!("{}\t{}\n", fields, chain).as_bytes())?;
+            writer.file.flush()?;
+            writer.chain = chain;
+        }
+        Ok(())
+    }
+
+    /// Marks the end of a run and, with an operator key, signs the chain up to
+    /// and including that line.
+    pub fn seal(&self) -> Result<(), ClientError> {
+        self.record(0, "end")?;
+        let Some(key_file) = &self.key_file else {
+            return Ok(());
+        };
+        let key = load_signing_key(key_file)?;
+        let chain = match self.writer.lock() {
+            Ok(writer) => writer.as_ref().map(|w| w.chain.clone()).unwrap_or_default(),
+            Err(_) => return Err(self.poisoned()),
+        };
+        let signature = key.sign(chain.as_bytes());
+        self.record(
+            0,
+            &format!(
+                "signature {} key {}",
+                to_hex(&signature.to_bytes()),
+                to_hex(key.verifying_key().as_bytes())
+            ),
+        )
+    }
+
+    /// The error for a writer left poisoned by a panic mid-write.
+    fn poisoned(&self) -> ClientError {
+        ClientError::io(&self.path, std::io::Error::other("audit log lock poisoned"))
+    }
+
+    /// Opens the file for appending, continuing the chain of any lines
+    /// already in it.
+    fn open(&self) -> Result<Writer, ClientError> {
+        let annotate = |e| ClientError::io(&self.path, e);
+        let chain = match std::fs::read_to_string(&self.path) {
+            Ok(contents) => contents
+                .lines()
+                .last()
+                .and_then(|line| line.rsplit('\t').next())
+                .unwrap_or_default()
+                .to_string(),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => String::new(),
+            Err(e) => return Err(annotate(e)),
+        };
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)
+            .map_err(annotate)?;
+        Ok(Writer { file, chain })
+    }
+}
+
+fn chain_hash(previous: &str, fields: &str) -> String {
+    sha256_hex(format!("{}\n{}", previous, fields).as_bytes())
+}
+
+/// Reads an operator key: a file holding the 32-byte Ed25519 secret key as
+/// 64 hex digits, e.g. made with `openssl rand -hex 32`.
+pub fn load_signing_key(path: &Path) -> Result<SigningKey, ClientError> {
+    let contents = std::fs::read_to_string(path).map_err(|e| ClientError::io(path, e))?;
+    let seed: [u8; 32] = from_hex(contents.trim())
+        .and_then(|bytes| bytes.try_into().ok())
+        .ok_or_else(|| {
+            ClientError::InvalidArgument(format!(
+                "{} does not hold a 32-byte key in hex",
+                path.display()
+            ))
+        })?;
+    Ok(SigningKey::from_bytes(&seed))
+}
+
+/// What [`verify_audit_log`] found.
+#[derive(Debug, Default)]
+pub struct AuditReport {
+    pub lines: usize,
+    /// Keys (hex) of the valid signatures found.
+    pub signed_by: Vec<String>,
+}
+
+/// Checks every chain value and signature in the audit log at `path`.
+/// Returns an error naming the first line that doesn't verify.
+pub fn verify_audit_log(path: &Path) -> Result<AuditReport, ClientError> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut report = AuditReport::default();
+    let mut previous = String::new();
+
+    for (index, line) in contents.lines().enumerate() {
+        let invalid =
+            |reason: &str| ClientError::InvalidArgument(format!("line {}: {}", index + 1, reason));
+        let (fields, chain) = line
+            .rsplit_once('\t')
+            .ok_or_else(|| invalid("missing chain value"))?;
+        if chain_hash(&previous, fields) != chain {
+            return Err(invalid("chain value does not match; the log was altered"));
+        }
+
+        let event = fields.splitn(3, '\t').nth(2).unwrap_or_default();
+        if let Some(rest) = event.strip_prefix("signature ") {
+            let (signature, key) = rest
+                .split_once(" key ")
+                .ok_or_else(|| invalid("malformed signature"))?;
+            let signature = from_hex(signature)
+                .and_then(|bytes| Signature::from_slice(&bytes).ok())
+                .ok_or_else(|| invalid("malformed signature"))?;
+            let verifying_key = from_hex(key)
+                .and_then(|bytes| <[u8; 32]>::try_from(bytes).ok())
+                .and_then(|bytes| VerifyingKey::from_bytes(&bytes).ok())
+                .ok_or_else(|| invalid("malformed key"))?;
+            verifying_key
+                .verify(previous.as_bytes(), &signature)
+                .map_err(|_| invalid("signature does not match"))?;
+            report.signed_by.push(key.to_string());
+        }
+
+        previous = chain.to_string();
+        report.lines += 1;
+    }
+    Ok(report)
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
@@ -0,0 +1,78 @@
+use std::time::{Duration, Instant};
+
+use crate::client::ImapClient;
+use crate::error_imap::ClientError;
+use crate::input::ImapConfig;
+use crate::mock::{synthetic_message, MockMailbox, MockServer};
+
+/// Messages served by the local benchmark.
+const MESSAGES: usize = 2000;
+/// Approximate size of each benchmark message.
+const MESSAGE_SIZE: usize = 20 * 1024;
+const BATCH_SIZES: [usize; 3] = [50, 200, 500];
+const CONCURRENCY: [usize; 3] = [1, 5, 10];
+
+/// Outcome of one end-to-end run against the mock server.
+#[derive(Debug, Clone)]
+pub struct BenchResult {
+    pub batch_size: usize,
+    pub max_concurrent: usize,
+    pub messages: u32,
+    pub elapsed: Duration,
+}
+
+impl BenchResult {
+    pub fn messages_per_second(&self) -> f64 {
+        self.messages as f64 / self.elapsed.as_secs_f64()
+    }
+}
+
+/// Fetches a synthetic mailbox from an in-process mock server with each
+/// combination of batch size and concurrency, timing the whole pipeline from
+/// connection to files on disk.
+pub async fn run_local_benchmark() -> Result<Vec<BenchResult>, ClientError> {
+    let messages = (0..MESSAGES)
+        .map(|i| synthetic_message(i, MESSAGE_SIZE))
+        .collect();
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages,
+    }])
+    .await?;
+
+    let mut results = Vec::new();
+    for batch_size in BATCH_SIZES {
+        for max_concurrent in CONCURRENCY {
+            let dir = std::env::temp_dir().join(format!(
+                "imap-bench-{}-{}-{}",
+                std::process::id(),
+                batch_size,
+                max_concurrent
+            ));
+            std::fs::create_dir_all(&dir)?;
+
+            let config = ImapConfig {
+                email: "bench@example.com".to_string(),
+                password: "bench".to_string(),
+                dir_path: dir.clone(),
+                max_concurrent,
+                batch_size,
+                ..ImapConfig::default()
+            };
+            let client = ImapClient::new(config, server.url());
+
+            let started = Instant::now();
+            let summary = client.fetch_all_emails().await;
+            let elapsed = started.elapsed();
+            std::fs::remove_dir_all(&dir)?;
+
+            results.push(BenchResult {
+                batch_size,
+                max_concurrent,
+                messages: summary?.saved,
+                elapsed,
+            });
+        }
+    }
+    Ok(results)
+}
@@ -0,0 +1,336 @@
+use rusqlite::{params, Connection, Row};
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, HashSet};
+use std::path::Path;
+use std::sync::Arc;
+
+use crate::error_imap::ClientError;
+use crate::state::{import_legacy, StateDb};
+use crate::storage::LineEndings;
+use crate::writer::WriteGroup;
+
+/// Name of the file older versions kept the catalog in, imported into the
+/// state database when found.
+pub const CATALOG_FILE: &str = ".catalog.tsv";
+
+/// One saved message as recorded in the catalog.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CatalogEntry {
+    /// SHA-256 of the Message-ID header, or empty when the message has none.
+    pub message_id_hash: String,
+    /// SHA-256 of the message bytes as stored, after line-ending conversion.
+    pub content_hash: String,
+    pub size: u64,
+    /// Line endings the message was stored with.
+    pub line_endings: LineEndings,
+    /// When the server received the message (its INTERNALDATE), as a Unix
+    /// timestamp.
+    pub internal_date: Option<i64>,
+    /// SHA-256 of the message as downloaded, when what was stored differs
+    /// (such as after redaction).
+    pub original_hash: Option<String>,
+    /// Path of the stored file, relative to the archive root.
+    pub path: String,
+}
+
+impl CatalogEntry {
+    /// Builds the entry for `message`, already converted to `line_endings` and
+    /// stored at `path` (relative to the archive root).
+    pub fn new(
+        message: &[u8],
+        message_id: Option<&str>,
+        line_endings: LineEndings,
+        path: String,
+    ) -> Self {
+        CatalogEntry {
+            message_id_hash: message_id.map(hash_message_id).unwrap_or_default(),
+            content_hash: sha256_hex(message),
+            size: message.len() as u64,
+            line_endings,
+            internal_date: None,
+            original_hash: None,
+            path,
+        }
+    }
+
+    /// Checks that the file this entry points to, under `root`, still has
+    /// the size and SHA-256 recorded when it was saved. Returns why not, if
+    /// not.
+    pub fn verify(&self, root: &Path) -> Result<(), String> {
+        let stored = std::fs::read(root.join(&self.path))
+            .map_err(|e| format!("can't read {}: {}", self.path, e))?;
+        if stored.len() as u64 != self.size {
+            return Err(format!(
+                "{} is {} bytes, not {}",
+                self.path,
+                stored.len(),
+                self.size
+            ));
+        }
+        if sha256_hex(&stored) != self.content_hash {
+            return Err(format!("{} doesn't match its catalogued hash", self.path));
+        }
+        Ok(())
+    }
+
+    fn insert(&self, connection: &Connection) -> rusqlite::Result<()> {
+        connection
+            .prepare_cached(
+                "INSERT INTO catalog (message_id_hash, content_hash, size, line_endings, \
+                 internal_date, original_hash, path) VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7)",
+            )?
+            .execute(params![
+                self.message_id_hash,
+                self.content_hash,
+                self.size,
+                self.line_endings.name(),
+                self.internal_date,
+                self.original_hash,
+                self.path
+            ])?;
+        Ok(())
+    }
+
+    fn from_row(row: &Row) -> rusqlite::Result<Self> {
+        let line_endings: String = row.get(3)?;
+        Ok(CatalogEntry {
+            message_id_hash: row.get(0)?,
+            content_hash: row.get(1)?,
+            size: row.get(2)?,
+            line_endings: line_endings.parse().unwrap_or(LineEndings::Keep),
+            internal_date: row.get(4)?,
+            original_hash: row.get(5)?,
+            path: row.get(6)?,
+        })
+    }
+
+    fn from_line(line: &str) -> Option<Self> {
+        let mut fields = line.splitn(4, '\t');
+        let message_id_hash = fields.next()?.to_string();
+        let content_hash = fields.next()?.to_string();
+        let size = fields.next()?.parse().ok()?;
+        let mut rest = fields.next()?;
+
+        // Older catalogs lack the line endings, internal date and original
+        // hash columns
+        let mut line_endings = LineEndings::Keep;
+        if let Some((endings, after)) = rest.split_once('\t') {
+            if let Ok(endings) = endings.parse() {
+                line_endings = endings;
+                rest = after;
+            }
+        }
+        let mut internal_date = None;
+        if let Some((date, after)) = rest.split_once('\t') {
+            if date.is_empty() || date.parse::<i64>().is_ok() {
+                internal_date = date.parse().ok();
+                rest = after;
+            }
+        }
+        let mut original_hash = None;
+        if let Some((hash, after)) = rest.split_once('\t') {
+            if hash.is_empty() || is_sha256_hex(hash) {
+                original_hash = Some(hash.to_string()).filter(|hash| !hash.is_empty());
+                rest = after;
+            }
+        }
+
+        Some(CatalogEntry {
+            message_id_hash,
+            content_hash,
+            size,
+            line_endings,
+            internal_date,
+            original_hash,
+            path: rest.to_string(),
+        })
+    }
+}
+
+/// Record of every message saved into an archive, used to detect duplicates
+/// across runs and folders.
+pub struct Catalog {
+    state: StateDb,
+    entries: Vec<CatalogEntry>,
+    message_ids: HashSet<String>,
+    contents: HashSet<String>,
+    /// Where content was first saved, by content hash.
+    paths: HashMap<String, String>,
+    /// Index of the latest entry by content hash, and by original hash for
+    /// messages stored changed.
+    copies: HashMap<String, usize>,
+}
+
+impl Catalog {
+    /// The group message files are held back in until the catalog next
+    /// commits.
+    pub(crate) fn write_group(&self) -> Result<Arc<WriteGroup>, ClientError> {
+        self.state.write_group()
+    }
+
+    /// Loads the catalog of the archive rooted at `dir`, creating an empty one
+    /// if it does not exist yet.
+    pub fn open(dir: &Path) -> Result<Self, ClientError> {
+        Self::load(&StateDb::open(dir)?)
+    }
+
+    /// Loads the catalog kept in `state`.
+    pub fn load(state: &StateDb) -> Result<Self, ClientError> {
+        import_legacy(state, CATALOG_FILE, "catalog", |connection, line| {
+            match CatalogEntry::from_line(line) {
+                Some(entry) => entry.insert(connection)?,
+                None => log::warn!("Ignoring malformed catalog line: {}", line),
+            }
+            Ok(())
+        })?;
+        let entries: Vec<CatalogEntry> = state.read(|connection| {
+            connection
+                .prepare(
+                    "SELECT message_id_hash, content_hash, size, line_endings, internal_date, \
+                     original_hash, path FROM catalog ORDER BY id",
+                )?
+                .query_map([], CatalogEntry::from_row)?
+                .collect()
+        })?;
+
+        let mut catalog = Catalog {
+            state: state.clone(),
+            entries: Vec::new(),
+            message_ids: HashSet::new(),
+            contents: HashSet::new(),
+            paths: HashMap::new(),
+            copies: HashMap::new(),
+        };
+        for entry in entries {
+            catalog.index(entry);
+        }
+        Ok(catalog)
+    }
+
+    pub fn entries(&self) -> &[CatalogEntry] {
+        &self.entries
+    }
+
+    /// Whether a message with the same Message-ID or identical content has
+    /// already been saved.
+    pub fn is_duplicate(&self, entry: &CatalogEntry) -> bool {
+        (!entry.message_id_hash.is_empty() && self.message_ids.contains(&entry.message_id_hash))
+            || self.contents.contains(&entry.content_hash)
+            || entry
+                .original_hash
+                .as_ref()
+                .is_some_and(|hash| self.contents.contains(hash))
+    }
+
+    /// Path, relative to the archive root, of the first file saved with
+    /// content hash `hash`.
+    pub fn path_of(&self, hash: &str) -> Option<&str> {
+        self.paths.get(hash).map(String::as_str)
+    }
+
+    /// The latest entry for a message stored with content hash `hash`, or
+    /// stored changed from a download with that hash.
+    pub fn copy_of(&self, hash: &str) -> Option<&CatalogEntry> {
+        self.copies.get(hash).map(|&index| &self.entries[index])
+    }
+
+    /// Whether a message with this Message-ID has already been saved.
+    pub fn has_message_id(&self, message_id: &str) -> bool {
+        self.has_message_id_hash(&hash_message_id(message_id))
+    }
+
+    /// Whether a message with a Message-ID of this hash has already been
+    /// saved.
+    pub fn has_message_id_hash(&self, hash: &str) -> bool {
+        self.message_ids.contains(hash)
+    }
+
+    /// Adds `entry` to the state database and the in-memory index.
+    pub fn record(&mut self, entry: CatalogEntry) -> Result<(), ClientError> {
+        self.state.write(|connection| entry.insert(connection))?;
+        self.index(entry);
+        Ok(())
+    }
+
+    /// Commits everything recorded so far, along with the checkpoint sharing
+    /// the state database.
+    pub fn sync(&mut self) -> Result<(), ClientError> {
+        self.state.commit()
+    }
+
+    /// Drops the entries for which `keep` returns false, in one commit.
+    pub fn retain<F: FnMut(&CatalogEntry) -> bool>(&mut self, keep: F) -> Result<(), ClientError> {
+        let kept: Vec<CatalogEntry> = self.entries.drain(..).filter(keep).collect();
+        self.message_ids.clear();
+        self.contents.clear();
+        self.paths.clear();
+        self.copies.clear();
+
+        self.state.commit()?;
+        self.state.write(|connection| {
+            connection.execute("DELETE FROM catalog", [])?;
+            for entry in &kept {
+                entry.insert(connection)?;
+            }
+            Ok(())
+        })?;
+        self.state.commit()?;
+
+        for entry in kept {
+            self.index(entry);
+        }
+        Ok(())
+    }
+
+    /// Points the entries for file `from` at `to`, both relative to the
+    /// archive root, for a file that was moved.
+    pub fn relocate(&mut self, from: &str, to: &str) -> Result<(), ClientError> {
+        self.state.write(|connection| {
+            connection.execute(
+                "UPDATE catalog SET path = ?2 WHERE path = ?1",
+                params![from, to],
+            )
+        })?;
+        for entry in self.entries.iter_mut().filter(|entry| entry.path == from) {
+            entry.path = to.to_string();
+        }
+        for path in self.paths.values_mut().filter(|path| *path == from) {
+            *path = to.to_string();
+        }
+        Ok(())
+    }
+
+    fn index(&mut self, entry: CatalogEntry) {
+        if !entry.message_id_hash.is_empty() {
+            self.message_ids.insert(entry.message_id_hash.clone());
+        }
+        self.contents.insert(entry.content_hash.clone());
+        self.paths
+            .entry(entry.content_hash.clone())
+            .or_insert_with(|| entry.path.clone());
+        self.copies
+            .insert(entry.content_hash.clone(), self.entries.len());
+        // A message stored redacted is still a duplicate of its original
+        if let Some(hash) = &entry.original_hash {
+            self.contents.insert(hash.clone());
+            self.copies.insert(hash.clone(), self.entries.len());
+        }
+        self.entries.push(entry);
+    }
+}
+
+pub fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+fn is_sha256_hex(value: &str) -> bool {
+    value.len() == 64 && value.bytes().all(|b| b.is_ascii_hexdigit())
+}
+
+/// Hash of a Message-ID with surrounding whitespace and angle brackets removed.
+pub fn hash_message_id(message_id: &str) -> String {
+    sha256_hex(message_id.trim().trim_matches(['<', '>']).as_bytes())
+}
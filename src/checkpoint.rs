@@ -0,0 +1,203 @@
+use rusqlite::params;
+use std::collections::HashSet;
+use std::path::Path;
+use std::time::{Duration, Instant};
+
+use crate::error_imap::ClientError;
+use crate::state::{import_legacy, StateDb};
+
+/// Name of the file older versions kept the checkpoint in, imported into
+/// the state database when found.
+pub const CHECKPOINT_FILE: &str = ".checkpoint.tsv";
+
+/// Record of the UIDs already fetched from each mailbox, so an interrupted
+/// or capped run picks up where it stopped.
+pub struct Checkpoint {
+    state: StateDb,
+    fetched: HashSet<(String, u32, u32)>,
+}
+
+impl Checkpoint {
+    /// Loads the checkpoint of the archive rooted at `dir`, starting empty if
+    /// there is none yet.
+    pub fn open(dir: &Path) -> Result<Self, ClientError> {
+        Self::load(&StateDb::open(dir)?)
+    }
+
+    /// Loads the checkpoint kept in `state`.
+    pub fn load(state: &StateDb) -> Result<Self, ClientError> {
+        import_legacy(state, CHECKPOINT_FILE, "checkpoint", |connection, line| {
+            match parse_line(line) {
+                Some((mailbox, uid_validity, uid)) => {
+                    insert(connection, &mailbox, uid_validity, uid)?;
+                }
+                None => log::warn!("Ignoring malformed checkpoint line: {}", line),
+            }
+            Ok(())
+        })?;
+        let fetched = state.read(|connection| {
+            connection
+                .prepare("SELECT mailbox, uid_validity, uid FROM checkpoint")?
+                .query_map([], |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)))?
+                .collect()
+        })?;
+        Ok(Checkpoint {
+            state: state.clone(),
+            fetched,
+        })
+    }
+
+    /// UIDs of `mailbox` fetched while it had the given UIDVALIDITY. UIDs
+    /// recorded under another UIDVALIDITY no longer identify the same messages.
+    pub fn fetched_uids(&self, mailbox: &str, uid_validity: u32) -> HashSet<u32> {
+        self.fetched
+            .iter()
+            .filter(|(name, validity, _)| name == mailbox && *validity == uid_validity)
+            .map(|(_, _, uid)| *uid)
+            .collect()
+    }
+
+    /// Marks a message as fetched.
+    pub fn record(
+        &mut self,
+        mailbox: &str,
+        uid_validity: u32,
+        uid: u32,
+    ) -> Result<(), ClientError> {
+        if !self
+            .fetched
+            .insert((mailbox.to_string(), uid_validity, uid))
+        {
+            return Ok(());
+        }
+        self.state
+            .write(|connection| insert(connection, mailbox, uid_validity, uid))
+    }
+
+    /// Commits everything recorded so far, along with the catalog sharing
+    /// the state database.
+    pub fn sync(&mut self) -> Result<(), ClientError> {
+        self.state.commit()
+    }
+}
+
+fn insert(
+    connection: &rusqlite::Connection,
+    mailbox: &str,
+    uid_validity: u32,
+    uid: u32,
+) -> rusqlite::Result<()> {
+    connection
+        .prepare_cached(
+            "INSERT OR IGNORE INTO checkpoint (mailbox, uid_validity, uid) VALUES (?1, ?2, ?3)",
+        )?
+        .execute(params![mailbox, uid_validity, uid])?;
+    Ok(())
+}
+
+/// How often sync state is forced to disk while a run goes on: after this
+/// many records or this long since the last time, whichever comes first.
+/// The state database also commits at the end of every batch; a crash of
+/// any kind loses at most the records since the last commit, and their
+/// messages are fetched again.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SyncSchedule {
+    pub records: u32,
+    pub interval: Duration,
+}
+
+impl Default for SyncSchedule {
+    fn default() -> Self {
+        SyncSchedule {
+            records: 100,
+            interval: Duration::from_secs(60),
+        }
+    }
+}
+
+/// Counts records written since a file was last forced to disk.
+#[derive(Debug)]
+pub struct SyncTimer {
+    schedule: SyncSchedule,
+    unsynced: u32,
+    since: Instant,
+}
+
+impl SyncTimer {
+    pub fn new(schedule: SyncSchedule) -> Self {
+        SyncTimer {
+            schedule,
+            unsynced: 0,
+            since: Instant::now(),
+        }
+    }
+
+    /// Counts one record, returning whether the file should now be forced
+    /// to disk, and if so starting the count over.
+    pub fn is_due(&mut self) -> bool {
+        self.unsynced += 1;
+        let due = self.unsynced >= self.schedule.records
+            || self.since.elapsed() >= self.schedule.interval;
+        if due {
+            self.reset();
+        }
+        due
+    }
+
+    pub fn reset(&mut self) {
+        self.unsynced = 0;
+        self.since = Instant::now();
+    }
+}
+
+impl Default for SyncTimer {
+    fn default() -> Self {
+        SyncTimer::new(SyncSchedule::default())
+    }
+}
+
+fn parse_line(line: &str) -> Option<(String, u32, u32)> {
+    let mut fields = line.splitn(3, '\t');
+    let uid_validity = fields.next()?.parse().ok()?;
+    let uid = fields.next()?.parse().ok()?;
+    Some((fields.next()?.to_string(), uid_validity, uid))
+}
+
+/// Upper bounds on how much one run downloads.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Quota {
+    pub max_messages: Option<u32>,
+    pub max_bytes: Option<u64>,
+    messages: u32,
+    bytes: u64,
+    exhausted: bool,
+}
+
+impl Quota {
+    pub fn new(max_messages: Option<u32>, max_bytes: Option<u64>) -> Self {
+        Quota {
+            max_messages,
+            max_bytes,
+            ..Default::default()
+        }
+    }
+
+    /// Reserves room for one message of `size` bytes. Once a message doesn't
+    /// fit, nothing further is taken so the next run continues in order.
+    pub fn take(&mut self, size: u64) -> bool {
+        let over_messages = self.max_messages.is_some_and(|max| self.messages >= max);
+        let over_bytes = self.max_bytes.is_some_and(|max| self.bytes + size > max);
+        if self.exhausted || over_messages || over_bytes {
+            self.exhausted = true;
+            return false;
+        }
+        self.messages += 1;
+        self.bytes += size;
+        true
+    }
+
+    /// Whether some message was left for a later run.
+    pub fn is_exhausted(&self) -> bool {
+        self.exhausted
+    }
+}
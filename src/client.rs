@@ -1,408 +1,4450 @@
 use rustls;
-use std::sync::Arc;
-use std::time::Duration;
-use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use std::borrow::Cow;
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::IsTerminal;
+use std::net::SocketAddr;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncWrite};
 use tokio::net::TcpStream;
-use tokio::sync::Semaphore;
+use tokio::task::JoinHandle;
 use tokio::time::sleep;
 use tokio_rustls::{client::TlsStream, TlsConnector};
 
-use crate::error_imap::ClientError;
+use crate::audit::{AuditLog, AUDIT_FILE};
+use crate::catalog::{hash_message_id, sha256_hex, Catalog, CatalogEntry};
+use crate::checkpoint::{Checkpoint, Quota};
+use crate::control::Control;
+use crate::dedupe::relative_path;
+use crate::diskspace::{ensure_free_space, format_size};
+use crate::dns::Resolver;
+use crate::drain::{Drain, DRAIN_JOURNAL};
+use crate::error_imap::{ClientError, ErrorContext, Phase};
+use crate::failures::{read_failures, write_failures, Failure};
+use crate::folders::{
+    decode_mailbox_name, find_special_folder, parse_list_response, parse_namespace_response,
+    parse_utf8_list_response, Mailbox, Namespace, SpecialUse,
+};
+use crate::gmail_api::{GmailApi, GmailState, Label, GMAIL_STATE_FILE};
+use crate::html::html_to_text;
+use crate::http;
+use crate::identity::{parse_id_response, ClientIdentity, ServerId};
 use crate::input::ImapConfig;
+use crate::jmap::{mailbox_path, JmapMailbox, JmapSession};
+use crate::journal::{Journal, JOURNAL_FILE};
+use crate::lock::RunLock;
+use crate::maildir;
+use crate::message::{
+    decode_encoded_words, find_text_part, format_timestamp, parse_date, parse_internal_date,
+    parse_utc_date, ContentType, Headers, Part,
+};
+use crate::net::{self, IpPreference, CONNECTION_LOG};
+use crate::oauth::{OAuthCredentials, GOOGLE_TOKEN_URL};
+use crate::pacing::{is_throttle, Pacing};
+use crate::parser::{parse_response, Fetch, Parsed, Value};
+use crate::partial;
+use crate::pool::SessionPool;
+use crate::processor::{BatchInfo, MessageInfo, MessageProcessor, Processed};
+use crate::progress::{
+    clear_status, draw_status, render_bar, render_combined, render_plain, Progress, ProgressMode,
+};
+use crate::provider::{AuthMethod, Provider};
+use crate::repair::{check_archive, current_path, in_maildir, Damage, RepairReport};
+use crate::replay::{self, ReplaySummary};
+use crate::report::{write_last_run, RunRecord};
+use crate::retention::{expired_files, policy_for, RetentionCount, Scope};
+use crate::rules::{Action, MessageFacts, RuleSet};
+use crate::scheduler::{Permit, Scheduler};
+use crate::secrets::{load_refresh_token, store_refresh_token};
+use crate::session::{
+    response_code_arg, sequence_set, strip_response_code, Arg, Interrupted, Response, SequenceSet,
+    Session, Status,
+};
+use crate::state::StateDb;
+use crate::stats::{
+    parse_quota_response, parse_status_messages, pick_samples, AccountStats, SizeHistogram,
+};
+use crate::storage::{
+    merge_thread_directories, resolve_target, sanitize_filename, thread_key, ExistingFilePolicy,
+    LineEndings, Organize, OutputFormat, ThreadMode,
+};
+use crate::tls::{self, TlsOptions};
+use crate::tr;
+use crate::trace::WireTrace;
+use crate::views::{link_object, object_path, View, ViewEntry};
+use crate::window::{imap_date, DateRange};
+use crate::workers;
+use crate::writer::{set_received_time, FileWriter};
+
+/// A connection to the server, over TLS or (for local test servers) plain TCP.
+pub trait Transport: AsyncRead + AsyncWrite + Unpin + Send {}
+
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> Transport for T {}
+
+type ImapSession = Session<Box<dyn Transport>>;
+
+/// The UIDs and task of each batch of a mailbox.
+type Batches = Vec<(Vec<u32>, JoinHandle<Result<u32, ClientError>>)>;
+
+/// Order in which a mailbox's messages are downloaded.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum FetchOrder {
+    #[default]
+    OldestFirst,
+    NewestFirst,
+}
+
+impl FromStr for FetchOrder {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "oldest-first" | "oldest" => Ok(FetchOrder::OldestFirst),
+            "newest-first" | "newest" => Ok(FetchOrder::NewestFirst),
+            other => Err(ClientError::InvalidArgument(format!(
+                "Unknown order `{}` (expected oldest-first or newest-first)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Protocol an account is fetched over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Backend {
+    #[default]
+    Imap,
+    /// JMAP (RFC 8620/8621), e.g. Fastmail. The password is an API token.
+    Jmap,
+    /// The Gmail REST API over OAuth 2.0, which isn't subject to IMAP's
+    /// connection limits.
+    GmailApi,
+}
+
+impl FromStr for Backend {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "imap" => Ok(Backend::Imap),
+            "jmap" => Ok(Backend::Jmap),
+            "gmail-api" => Ok(Backend::GmailApi),
+            other => Err(ClientError::InvalidArgument(format!(
+                "Unknown backend `{}` (expected imap, jmap or gmail-api)",
+                other
+            ))),
+        }
+    }
+}
+
+/// What a mailbox holds, as reported when it is selected.
+struct MailboxStatus {
+    uid_validity: u32,
+    count: u32,
+    /// UID and RFC822.SIZE of every message.
+    messages: Vec<(u32, u64)>,
+}
+
+/// A mailbox to fetch, with the directory it is saved into and, when retrying
+/// a failure report, the UIDVALIDITY and UIDs to fetch again.
+#[derive(Clone)]
+struct Target {
+    name: String,
+    dir: PathBuf,
+    retry: Option<(u32, HashSet<u32>)>,
+}
+
+/// The messages of one mailbox chosen for this run.
+struct MailboxPlan {
+    name: String,
+    dir: PathBuf,
+    /// The month planned, when fetching a month at a time.
+    window: Option<DateRange>,
+    uid_validity: u32,
+    count: u32,
+    uids: Vec<u32>,
+}
+
+/// What planning has taken up so far of the run's limits.
+struct Planning {
+    quota: Quota,
+    total_size: u64,
+    /// Messages left for a later run.
+    deferred: u32,
+}
 
 pub struct ImapClient {
     config: ImapConfig,
     server: String,
+    progress: Arc<Progress>,
+    control: Arc<Control>,
+    guard: SessionGuard,
+    /// Obtained on first use, so an OAuth sign-in happens once per run.
+    credentials: tokio::sync::OnceCell<Credentials>,
+    /// The personal namespace, learnt when listing mailboxes.
+    namespace: std::sync::OnceLock<Namespace>,
+    /// The server's answer to the first ID command of the run.
+    server_id: std::sync::OnceLock<ServerId>,
+    processors: Vec<Arc<dyn MessageProcessor>>,
+    /// Deletes verified messages from the server, with `--drain`.
+    drain: Option<Arc<Drain>>,
+    /// Batch sessions left open for the next batch.
+    pool: Arc<SessionPool<ImapSession>>,
+    /// When batches may start, slowed down while the server throttles.
+    pacing: Arc<Pacing>,
+    /// Writes the message files of every folder.
+    writer: Arc<FileWriter>,
+}
+
+/// What IMAP sessions sign in with.
+#[derive(Clone)]
+enum Credentials {
+    /// Sent with `LOGIN`.
+    Password(String),
+    /// An OAuth 2.0 access token, sent with `AUTHENTICATE XOAUTH2`.
+    AccessToken(String),
+}
+
+/// Restrictions applied to every session a client opens.
+#[derive(Clone, Default)]
+struct SessionGuard {
+    /// Refuse commands that could modify the account.
+    read_only: bool,
+    /// Log every command sent.
+    audit: Option<Arc<AuditLog>>,
+    /// Journal every command changing messages before sending it.
+    journal: Option<Arc<Journal>>,
+    /// Trace every line sent and received.
+    trace: Option<Arc<WireTrace>>,
+    /// How much is read from the network at once.
+    read_buffer: usize,
+    /// Which IP version to try first.
+    ip_preference: IpPreference,
+    /// Where the server's name is resolved.
+    resolver: Resolver,
+    /// The Tor SOCKS proxy connections go through, if any, and the SOCKS
+    /// user name keeping this account's circuits apart from others'.
+    tor: Option<(SocketAddr, String)>,
+    /// Client certificate, pinned keys and protocol settings.
+    tls: TlsOptions,
+    /// `[ALERT]`s received by any of the sessions, to show the user.
+    alerts: Arc<Mutex<Vec<String>>>,
+    /// The TLS configuration built from `tls`, shared by all connections so
+    /// they can resume each other's sessions.
+    tls_config: Arc<std::sync::OnceLock<Arc<rustls::ClientConfig>>>,
+}
+
+/// Outcome of fetching one account.
+#[derive(Debug, Clone)]
+pub struct FetchSummary {
+    pub account: String,
+    pub found: u32,
+    pub saved: u32,
+    pub failed_batches: u32,
+    /// How the IMAP server identified itself, if it supports ID.
+    pub server_id: Option<ServerId>,
+    /// `[ALERT]`s the server sent, which it expects the user to read.
+    pub alerts: Vec<String>,
+}
+
+/// Outcome of purging one mailbox.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PurgeCount {
+    pub mailbox: String,
+    /// Messages deleted from the server, or that would be on a dry run.
+    pub purged: usize,
+    /// Messages that matched but were kept because the local archive has no
+    /// copy of them.
+    pub unarchived: usize,
+}
+
+/// How one mailbox on the server compares with the local archive.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MailboxDiff {
+    pub mailbox: String,
+    /// UIDs of messages on the server that the archive has no copy of.
+    pub server_only: Vec<u32>,
+    /// UIDs fetched by earlier runs that are no longer on the server.
+    pub local_only: Vec<u32>,
+    /// Archived Maildir messages whose flags differ from the server's: the
+    /// file, and the Maildir flag letters locally and on the server.
+    pub flag_changes: Vec<(String, String, String)>,
+}
+
+/// What `check` found out about an account.
+#[derive(Debug, Clone)]
+pub struct CheckReport {
+    /// `host:port` of the IMAP server.
+    pub server: String,
+    /// How long connecting and signing in took.
+    pub sign_in_time: Duration,
+    /// How the server identified itself, if it supports ID.
+    pub server_id: Option<ServerId>,
+    /// Capabilities the server advertised once signed in, sorted.
+    pub capabilities: Vec<String>,
+    pub folders: Vec<Mailbox>,
+}
+
+/// The refresh token `login` obtained, and where it was kept.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Login {
+    pub refresh_token: String,
+    /// Whether the system keyring holds it; if not, it has to go in the
+    /// accounts file.
+    pub stored: bool,
+}
+
+impl ImapClient {
+    pub fn new(config: ImapConfig, server: String) -> Self {
+        let progress = Progress::new(&config.email);
+        let control = Control::new(config.max_concurrent);
+        // Read-only runs always keep a record showing nothing else was sent
+        let audit_path = match &config.audit_log {
+            Some(path) => Some(path.clone()),
+            None if config.read_only || config.audit_key.is_some() => {
+                Some(config.dir_path.join(AUDIT_FILE))
+            }
+            None => None,
+        };
+        let guard = SessionGuard {
+            read_only: config.read_only,
+            audit: audit_path.map(|path| Arc::new(AuditLog::new(path, config.audit_key.clone()))),
+            journal: (!config.read_only)
+                .then(|| Arc::new(Journal::new(config.dir_path.join(JOURNAL_FILE)))),
+            trace: config
+                .trace_imap
+                .as_ref()
+                .map(|path| Arc::new(WireTrace::new(path.clone()))),
+            read_buffer: config.read_buffer,
+            ip_preference: config.ip_preference,
+            resolver: config.resolver.clone(),
+            tor: config
+                .tor
+                .map(|proxy| (proxy, sha256_hex(config.email.as_bytes())[..16].to_string())),
+            alerts: Arc::default(),
+            tls: config.tls.clone(),
+            tls_config: Arc::default(),
+        };
+        // Built-in processors run before any added by the caller
+        let mut processors: Vec<Arc<dyn MessageProcessor>> = Vec::new();
+        if !config.redact.is_empty() {
+            processors.push(Arc::new(config.redact.clone()));
+        }
+        let pacing = Arc::new(Pacing::new(config.batch_delay, config.batch_jitter));
+        let writer = Arc::new(FileWriter::new(config.io_uring));
+        let drain = config.drain.then(|| {
+            Arc::new(Drain::new(
+                config.dir_path.join(DRAIN_JOURNAL),
+                config.audit_key.clone(),
+                config.drain_check.clone(),
+            ))
+        });
+        ImapClient {
+            config,
+            server,
+            progress,
+            control,
+            guard,
+            credentials: tokio::sync::OnceCell::new(),
+            namespace: std::sync::OnceLock::new(),
+            server_id: std::sync::OnceLock::new(),
+            processors,
+            drain,
+            pool: Arc::new(SessionPool::new()),
+            pacing,
+            writer,
+        }
+    }
+
+    /// Shares `scheduler`'s connection and bandwidth limits with the other
+    /// accounts of the run. Call it before handing out [`Self::control`].
+    pub fn with_scheduler(mut self, scheduler: Arc<Scheduler>) -> Self {
+        self.control =
+            Control::scheduled(self.config.max_concurrent, scheduler, &self.config.email);
+        self
+    }
+
+    /// Adds a processor that sees, and may rewrite, every message fetched.
+    /// Processors run in the order they were added.
+    pub fn with_processor(mut self, processor: Arc<dyn MessageProcessor>) -> Self {
+        self.processors.push(processor);
+        self
+    }
+
+    /// Connects to the server, once the run's scheduler has a slot for it.
+    /// The slot is held until the returned permit is dropped.
+    async fn connect(&self) -> Result<(ImapSession, Option<Permit>), ClientError> {
+        let slot = self.control.shared_connection().await?;
+        Ok((connect(&self.server, &self.guard).await?, slot))
+    }
+
+    /// Signs `session` in to the account.
+    async fn sign_in(&self, session: &mut ImapSession) -> Result<(), ClientError> {
+        let credentials = self.credentials().await?;
+        authenticate(
+            session,
+            &self.config.email,
+            credentials,
+            self.config.provider,
+        )
+        .await?;
+        if let Some(id) = identify(session, &self.config.identity).await? {
+            if self.server_id.get().is_none() {
+                log::info!("{} identifies as {}", self.server, id);
+            }
+            let _ = self.server_id.set(id);
+        }
+        Ok(())
+    }
+
+    /// The credentials sessions sign in with. For providers using OAuth this
+    /// exchanges the refresh token, from the accounts file or the system
+    /// keyring, for an access token or, without one, walks the user through
+    /// the device flow. Other providers offering OAuth use it once `login`
+    /// has granted a refresh token, and the password otherwise.
+    async fn credentials(&self) -> Result<&Credentials, ClientError> {
+        self.credentials
+            .get_or_try_init(|| async {
+                let refresh_token = match &self.config.oauth {
+                    Some(oauth) if oauth.refresh_token.is_empty() => {
+                        load_refresh_token(&self.config.email)?
+                    }
+                    Some(oauth) => Some(oauth.refresh_token.clone()),
+                    None => None,
+                };
+                let AuthMethod::OAuthDevice(flow) = self.config.provider.auth() else {
+                    let browser = self.config.provider.browser_flow();
+                    return match (&self.config.oauth, refresh_token, browser) {
+                        (Some(oauth), Some(refresh_token), Some(flow)) => {
+                            let oauth = OAuthCredentials {
+                                refresh_token,
+                                ..oauth.clone()
+                            };
+                            let token = oauth
+                                .access_token(
+                                    &http::client(self.config.tor)?,
+                                    flow.token_url,
+                                    Some(flow.scope),
+                                )
+                                .await?;
+                            Ok(Credentials::AccessToken(token))
+                        }
+                        (Some(_), None, Some(_)) => Err(no_refresh_token(&self.config.email)),
+                        _ => Ok(Credentials::Password(self.config.password.clone())),
+                    };
+                };
+                let Some(oauth) = &self.config.oauth else {
+                    return Err(ClientError::ActionRequired {
+                        message: format!(
+                            "{} accounts sign in with OAuth, which needs a client ID",
+                            self.config.provider.name()
+                        ),
+                        guidance: format!(
+                            "{}, then set oauth_client_id in the accounts file or pass \
+                             --oauth-client-id.",
+                            flow.registration
+                        ),
+                    });
+                };
+
+                let http = http::client(self.config.tor)?;
+                if let Some(refresh_token) = refresh_token {
+                    let oauth = OAuthCredentials {
+                        refresh_token,
+                        ..oauth.clone()
+                    };
+                    let token = oauth
+                        .access_token(&http, flow.token_url, Some(flow.scope))
+                        .await?;
+                    return Ok(Credentials::AccessToken(token));
+                }
+                let grant = oauth
+                    .device_flow(&http, &flow, |instructions| {
+                        println!("{}: {}", self.config.email, instructions)
+                    })
+                    .await?;
+                // The refresh token is a password in all but name: keep it in the
+                // keyring, where `load_refresh_token` finds it, and never print it
+                if let Some(refresh_token) = grant.refresh_token {
+                    match store_refresh_token(&self.config.email, &refresh_token) {
+                        Ok(()) => println!(
+                            "{}: signed in. The refresh token is kept in the system keyring, \
+                             so later runs skip this step.",
+                            self.config.email
+                        ),
+                        Err(e) => {
+                            log::warn!("{}: {}", self.config.email, e);
+                            println!(
+                                "{}: signed in, but the refresh token could not be kept, so \
+                                 the next run asks again.",
+                                self.config.email
+                            );
+                        }
+                    }
+                }
+                Ok(Credentials::AccessToken(grant.access_token))
+            })
+            .await
+    }
+
+    pub fn progress(&self) -> Arc<Progress> {
+        Arc::clone(&self.progress)
+    }
+
+    pub fn control(&self) -> Arc<Control> {
+        Arc::clone(&self.control)
+    }
+
+    /// A store saving into `dir`, with the client's processors.
+    fn store(&self, dir: PathBuf, catalog: &Arc<Mutex<Catalog>>) -> Store {
+        let group = match self.groups_writes() {
+            true => lock(catalog).and_then(|catalog| catalog.write_group()).ok(),
+            false => None,
+        };
+        Store {
+            processors: self.processors.clone(),
+            drain: self.drain.clone(),
+            writer: match group {
+                Some(group) => Arc::new(FileWriter::Grouped(group)),
+                None => Arc::clone(&self.writer),
+            },
+            ..Store::new(&self.config, dir, Arc::clone(catalog))
+        }
+    }
+
+    /// Whether small message files can wait for the next commit to be
+    /// written. Not when anything reads them back or changes the account
+    /// on the strength of them being archived before then: processors told
+    /// a file is saved, draining, or rules moving and deleting messages.
+    /// Nor when an existing file stops a message from being saved, as a
+    /// file held back doesn't exist yet.
+    fn groups_writes(&self) -> bool {
+        self.config.group_writes
+            && !self.config.io_uring
+            && self.config.if_exists == ExistingFilePolicy::Overwrite
+            && self.processors.is_empty()
+            && self.drain.is_none()
+            && !self.config.rules.modifies_account()
+    }
+
+    /// Replays the server responses in `capture` (see
+    /// [`replay::server_bytes`]) through the parser, offline. With `save`,
+    /// the messages in them are filed into the output directory as a fetch
+    /// of INBOX would, through the same rules and processors. A response
+    /// that doesn't parse stops the replay with [`ClientError::Desync`],
+    /// giving its offset in the replayed bytes.
+    pub async fn replay(&self, capture: &[u8], save: bool) -> Result<ReplaySummary, ClientError> {
+        let started = Instant::now();
+        let bytes = replay::server_bytes(capture);
+        let mut summary = ReplaySummary {
+            bytes: bytes.len(),
+            ..ReplaySummary::default()
+        };
+        let saving = match save {
+            true => {
+                let root = &self.config.dir_path;
+                std::fs::create_dir_all(root).map_err(|source| ClientError::DirectoryError {
+                    path: root.clone(),
+                    source,
+                })?;
+                let lock = RunLock::acquire(root)?;
+                let state = StateDb::open(&self.config.dir_path)?;
+                let catalog = Arc::new(Mutex::new(Catalog::load(&state)?));
+                let store = self.store(self.folder_dir(true, ["INBOX"])?, &catalog);
+                Some((lock, state, store))
+            }
+            false => None,
+        };
+
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let parsing = Instant::now();
+            let parsed = parse_response(&bytes[offset..]);
+            summary.parse_time += parsing.elapsed();
+            let (parsed, len) = parsed.map_err(|e| ClientError::Desync {
+                offset: offset as u64,
+                reason: e.to_string(),
+            })?;
+            offset += len;
+            summary.responses += 1;
+            let Parsed::Fetch(fetch) = parsed else {
+                continue;
+            };
+            let Some(body) = fetch.body().filter(|body| !body.is_empty()) else {
+                continue;
+            };
+            summary.messages += 1;
+            if let Some((_, _, store)) = &saving {
+                let mut message = fetched_message(&fetch, false);
+                message.body = body.to_vec();
+                if file_message(store, "INBOX", &message).await?.0.is_some() {
+                    summary.saved += 1;
+                }
+            }
+        }
+        if let Some((_lock, state, store)) = saving {
+            lock(&store.catalog)?.sync()?;
+            state.commit()?;
+        }
+        summary.elapsed = started.elapsed();
+        Ok(summary)
+    }
+
+    pub async fn fetch_all_emails(&self) -> Result<FetchSummary, ClientError> {
+        let _lock = RunLock::acquire(&self.config.dir_path)?;
+        let started = unix_time();
+        let result = self.fetch_all().await;
+        let record = match &result {
+            Ok(summary) => RunRecord {
+                account: summary.account.clone(),
+                started,
+                finished: unix_time(),
+                found: summary.found,
+                saved: summary.saved,
+                failed_batches: summary.failed_batches,
+                error: None,
+            },
+            Err(e) => RunRecord {
+                account: self.config.email.clone(),
+                started,
+                finished: unix_time(),
+                found: self.progress.total(),
+                saved: self.progress.saved(),
+                failed_batches: self.progress.failed_batches(),
+                error: Some(e.to_string()),
+            },
+        };
+        if let Err(e) = write_last_run(&self.config.dir_path, &record) {
+            log::warn!("Failed to record the run: {}", e);
+        }
+        for session in self.pool.drain() {
+            if let Err(e) = session.logout().await {
+                log::debug!("Failed to log out of an idle session: {}", e);
+            }
+        }
+        if let Some(log) = &self.guard.audit {
+            log.seal()?;
+            self.notice(&format!("Audit log written to {}", log.path().display()));
+        }
+        if let Some(drain) = &self.drain {
+            drain.journal().seal()?;
+            self.notice(&format!(
+                "Drain journal written to {}",
+                drain.journal().path().display()
+            ));
+        }
+        result
+    }
+
+    async fn fetch_all(&self) -> Result<FetchSummary, ClientError> {
+        log::info!(
+            "Using {} concurrent connections",
+            self.config.max_concurrent
+        );
+
+        if self.config.threads == Some(ThreadMode::File) && self.config.format != OutputFormat::Eml
+        {
+            return Err(ClientError::InvalidArgument(
+                "--threads file can only be combined with --format eml".to_string(),
+            ));
+        }
+        if self.config.organize.is_some() && self.config.threads.is_some() {
+            return Err(ClientError::InvalidArgument(
+                "--organize can't be combined with --threads".to_string(),
+            ));
+        }
+        if !self.config.views.is_empty()
+            && (self.config.organize.is_some() || self.config.threads.is_some())
+        {
+            return Err(ClientError::InvalidArgument(
+                "--views replaces --organize and --threads; use the sender view instead"
+                    .to_string(),
+            ));
+        }
+        if self.config.format == OutputFormat::Maildir {
+            if !self.config.views.is_empty() {
+                return Err(ClientError::InvalidArgument(
+                    "--views can't be combined with --format maildir".to_string(),
+                ));
+            }
+            if self.config.if_exists == ExistingFilePolicy::Rename {
+                return Err(ClientError::InvalidArgument(
+                    "--if-exists rename can't be combined with --format maildir".to_string(),
+                ));
+            }
+        }
+        if self.config.rules.modifies_account() {
+            if self.config.read_only {
+                return Err(ClientError::InvalidArgument(
+                    "rules that change messages on the server can't run in read-only mode"
+                        .to_string(),
+                ));
+            }
+            if self.config.backend != Backend::Imap {
+                return Err(ClientError::InvalidArgument(
+                    "rules that change messages on the server need the IMAP backend".to_string(),
+                ));
+            }
+        }
+
+        if !self.config.dates.is_empty() && self.config.backend != Backend::Imap {
+            return Err(ClientError::InvalidArgument(
+                "--since and --before need the IMAP backend".to_string(),
+            ));
+        }
+        if self.config.by_month && self.config.backend != Backend::Imap {
+            return Err(ClientError::InvalidArgument(
+                "--by-month needs the IMAP backend".to_string(),
+            ));
+        }
+        if let DateRange {
+            since: Some(since),
+            before: Some(before),
+        } = self.config.dates
+        {
+            if since >= before {
+                return Err(ClientError::InvalidArgument(
+                    "--since must be earlier than --before".to_string(),
+                ));
+            }
+        }
+        if self.config.drain_check.is_some() && !self.config.drain {
+            return Err(ClientError::InvalidArgument(
+                "--drain-check needs --drain".to_string(),
+            ));
+        }
+        if self.config.drain {
+            if self.config.read_only {
+                return Err(ClientError::InvalidArgument(
+                    "--drain can't run in read-only mode".to_string(),
+                ));
+            }
+            if self.config.backend != Backend::Imap {
+                return Err(ClientError::InvalidArgument(
+                    "--drain needs the IMAP backend".to_string(),
+                ));
+            }
+            // Copies are checked byte for byte, so each needs a file of its own
+            if self.config.format == OutputFormat::Pdf
+                || self.config.threads == Some(ThreadMode::File)
+            {
+                return Err(ClientError::InvalidArgument(
+                    "--drain needs one raw message per file; use --format eml or maildir without --threads file"
+                        .to_string(),
+                ));
+            }
+        }
+        if !self.config.retention.is_empty() {
+            if self.config.backend != Backend::Imap {
+                return Err(ClientError::InvalidArgument(
+                    "retention policies need the IMAP backend".to_string(),
+                ));
+            }
+            let applies_to = |scope| {
+                self.config
+                    .retention
+                    .iter()
+                    .any(|policy| policy.scope == scope)
+            };
+            if applies_to(Scope::Server) && self.config.read_only {
+                return Err(ClientError::InvalidArgument(
+                    "server retention policies can't run in read-only mode".to_string(),
+                ));
+            }
+            // Several views share one stored copy of each message
+            if applies_to(Scope::Local) && !self.config.views.is_empty() {
+                return Err(ClientError::InvalidArgument(
+                    "local retention policies can't be combined with --views".to_string(),
+                ));
+            }
+        }
+
+        let root = Path::new(&self.config.dir_path);
+        let state = StateDb::open(root)?;
+        state.set_sync_schedule(self.config.sync);
+        let catalog = Arc::new(Mutex::new(Catalog::load(&state)?));
+        if self.config.backend != Backend::Imap {
+            let fetched = match self.config.backend {
+                Backend::Jmap => self.fetch_jmap(&catalog).await,
+                _ => self.fetch_gmail_api(&catalog).await,
+            };
+            state.commit()?;
+            let found = fetched?;
+            self.notice(&format!(
+                "Email fetching completed! All emails saved to: {}",
+                self.config.dir_path.display()
+            ));
+            return Ok(self.summary(found));
+        }
+        let checkpoint = Arc::new(Mutex::new(Checkpoint::load(&state)?));
+
+        let targets = match &self.config.retry_from {
+            Some(path) => retry_targets(read_failures(path)?),
+            None => {
+                let mut targets = Vec::new();
+                for mailbox in self.mailboxes().await? {
+                    targets.push(Target {
+                        dir: self.mailbox_dir(&mailbox)?,
+                        name: mailbox.name,
+                        retry: None,
+                    });
+                }
+                uids_need_one_folder(self.config.uids.as_ref(), targets.len())?;
+                targets
+            }
+        };
+
+        // Step 1: Plan what to fetch, skipping messages fetched by earlier runs
+        // and stopping at the run's limits, and check it fits on disk
+        let mut planning = Planning {
+            quota: Quota::new(self.config.max_messages, self.config.max_bytes),
+            total_size: 0,
+            deferred: 0,
+        };
+        let mut failures = Vec::new();
+        let fetched = if self.config.by_month {
+            self.fetch_by_month(targets, &catalog, &checkpoint, &mut planning, &mut failures)
+                .await
+        } else {
+            let mut plans = Vec::new();
+            for target in targets {
+                let status = self
+                    .get_mailbox_status(&target.name, &self.config.dates)
+                    .await?;
+                self.skip_catalogued(&target, &status, &catalog, &checkpoint)
+                    .await?;
+                plans.push(self.plan(target, status, &checkpoint, &mut planning)?);
+            }
+            log::info!(
+                "Estimated download size: {}",
+                format_size(planning.total_size)
+            );
+            ensure_free_space(root, planning.total_size, self.config.min_free_space)?;
+
+            // Step 2: Fetch, then report whatever is still missing so it can be retried
+            self.fetch_plans(&plans, &catalog, &checkpoint, &mut failures)
+                .await
+        };
+        self.report_failures(failures, &checkpoint)?;
+        state.commit()?;
+        let found = fetched?;
+
+        if planning.quota.is_exhausted() {
+            self.notice(&format!(
+                "Reached the limit for this run; {} emails of {} left for the next run",
+                planning.deferred, self.config.email
+            ));
+        }
+        if !self.config.retention.is_empty() {
+            for count in self.apply_retention(&catalog).await? {
+                if count.local + count.server + count.kept > 0 {
+                    self.notice(&format!(
+                        "Retention in {}: {} archived files and {} server messages deleted, {} kept on the server",
+                        count.mailbox, count.local, count.server, count.kept
+                    ));
+                }
+            }
+        }
+
+        self.notice(&format!(
+            "Email fetching completed! All emails saved to: {}",
+            self.config.dir_path.display()
+        ));
+        Ok(self.summary(found))
+    }
+
+    /// Chooses the messages of `status` to fetch from `target`: those not
+    /// fetched by an earlier run, in the configured order, up to the run's
+    /// limits.
+    fn plan(
+        &self,
+        target: Target,
+        mut status: MailboxStatus,
+        checkpoint: &Mutex<Checkpoint>,
+        planning: &mut Planning,
+    ) -> Result<MailboxPlan, ClientError> {
+        // UIDs grow with arrival time, so reversing puts recent mail first,
+        // both within the run's limits and in the order batches start
+        if self.config.order == FetchOrder::NewestFirst {
+            status.messages.reverse();
+        }
+        // Messages asked for by UID are fetched again even if already checkpointed
+        let done = if self.config.uids.is_some() || target.retry.is_some() {
+            HashSet::new()
+        } else {
+            lock(checkpoint)?.fetched_uids(&target.name, status.uid_validity)
+        };
+        if let Some(selected) = &self.config.uids {
+            let largest = status.messages.iter().map(|&(uid, _)| uid).max();
+            status
+                .messages
+                .retain(|&(uid, _)| largest.is_some_and(|max| selected.contains(uid, max)));
+        }
+        if let Some((uid_validity, failed)) = &target.retry {
+            if *uid_validity == status.uid_validity {
+                status.messages.retain(|(uid, _)| failed.contains(uid));
+            } else {
+                log::warn!(
+                    "UIDVALIDITY of {} changed since the failed run; its UIDs no longer apply",
+                    target.name
+                );
+                status.messages.clear();
+            }
+        }
+        let mut uids = Vec::new();
+        let mut skipped = 0;
+        for (uid, size) in status.messages {
+            if done.contains(&uid) {
+                skipped += 1;
+                continue;
+            }
+            if planning.quota.take(size) {
+                uids.push(uid);
+                planning.total_size += size;
+            } else {
+                planning.deferred += 1;
+            }
+        }
+        if skipped > 0 {
+            log::info!(
+                "{} emails in {} were fetched by an earlier run",
+                skipped,
+                target.name
+            );
+        }
+        Ok(MailboxPlan {
+            name: target.name,
+            dir: target.dir,
+            window: None,
+            uid_validity: status.uid_validity,
+            count: status.count,
+            uids,
+        })
+    }
+
+    /// With `--skip-duplicates`, checkpoints the messages of `status` whose
+    /// Message-ID the catalog already has, such as imported ones, so they
+    /// aren't downloaded. Only their headers are fetched to find out.
+    async fn skip_catalogued(
+        &self,
+        target: &Target,
+        status: &MailboxStatus,
+        catalog: &Mutex<Catalog>,
+        checkpoint: &Mutex<Checkpoint>,
+    ) -> Result<(), ClientError> {
+        if !self.config.skip_duplicates
+            || self.config.uids.is_some()
+            || target.retry.is_some()
+            || lock(catalog)?.entries().is_empty()
+        {
+            return Ok(());
+        }
+        let done = lock(checkpoint)?.fetched_uids(&target.name, status.uid_validity);
+        let uids: Vec<u32> = status
+            .messages
+            .iter()
+            .map(|&(uid, _)| uid)
+            .filter(|uid| !done.contains(uid))
+            .collect();
+        if uids.is_empty() {
+            return Ok(());
+        }
+
+        let (mut session, _slot) = self.connect().await?;
+        self.sign_in(&mut session).await?;
+        session
+            .execute_args(&[
+                Arg::Raw("EXAMINE"),
+                Arg::String(&session.mailbox_name(&target.name)),
+            ])
+            .await?;
+        let message_ids = fetch_message_ids(&mut session, &uids).await?;
+        session.logout().await?;
+
+        let catalogued: Vec<u32> = {
+            let catalog = lock(catalog)?;
+            message_ids
+                .into_iter()
+                .filter(|(_, id)| id.as_ref().is_some_and(|id| catalog.has_message_id(id)))
+                .map(|(uid, _)| uid)
+                .collect()
+        };
+        let mut checkpoint = lock(checkpoint)?;
+        for &uid in &catalogued {
+            checkpoint.record(&target.name, status.uid_validity, uid)?;
+        }
+        if !catalogued.is_empty() {
+            log::info!(
+                "{} emails in {} are already in the catalog",
+                catalogued.len(),
+                target.name
+            );
+        }
+        Ok(())
+    }
+
+    /// Plans and fetches each mailbox a calendar month at a time, returning
+    /// how many messages they hold. Only one month's messages are held at
+    /// once, and each is fetched and checkpointed before the next is
+    /// searched, so an interrupted run picks up in the month it stopped in.
+    async fn fetch_by_month(
+        &self,
+        targets: Vec<Target>,
+        catalog: &Arc<Mutex<Catalog>>,
+        checkpoint: &Arc<Mutex<Checkpoint>>,
+        planning: &mut Planning,
+        failures: &mut Vec<Failure>,
+    ) -> Result<u32, ClientError> {
+        let now = unix_time();
+        let mut found = 0;
+        for target in targets {
+            let Some(first) = self.first_received(&target.name).await? else {
+                self.notice(&format!(
+                    "No emails found in {} of {}",
+                    target.name, self.config.email
+                ));
+                continue;
+            };
+            let mut windows = self.config.dates.months(first, now);
+            if self.config.order == FetchOrder::NewestFirst {
+                windows.reverse();
+            }
+            for window in windows {
+                let mut status = self.get_mailbox_status(&target.name, &window).await?;
+                self.skip_catalogued(&target, &status, catalog, checkpoint)
+                    .await?;
+                // Count what the month holds rather than the whole mailbox
+                status.count = status.messages.len() as u32;
+                let planned = planning.total_size;
+                let plan = MailboxPlan {
+                    window: Some(window),
+                    ..self.plan(target.clone(), status, checkpoint, planning)?
+                };
+                ensure_free_space(
+                    Path::new(&self.config.dir_path),
+                    planning.total_size - planned,
+                    self.config.min_free_space,
+                )?;
+                found += self
+                    .fetch_plans(std::slice::from_ref(&plan), catalog, checkpoint, failures)
+                    .await?;
+            }
+        }
+        Ok(found)
+    }
+
+    /// Fetches every planned mailbox, returning how many messages they hold.
+    ///
+    /// The batches of every mailbox are queued for the connection pool
+    /// before any is waited for, so folders are fetched side by side and a
+    /// run over many small labels keeps all its connections busy.
+    async fn fetch_plans(
+        &self,
+        plans: &[MailboxPlan],
+        catalog: &Arc<Mutex<Catalog>>,
+        checkpoint: &Arc<Mutex<Checkpoint>>,
+        failures: &mut Vec<Failure>,
+    ) -> Result<u32, ClientError> {
+        let mut found = 0;
+        let mut running = Vec::new();
+        for plan in plans {
+            found += plan.count;
+            self.progress.add_total(plan.uids.len() as u32);
+            let mailbox = match &plan.window {
+                Some(window) => format!("{} ({})", plan.name, window),
+                None => plan.name.clone(),
+            };
+
+            if plan.count == 0 {
+                self.notice(&format!(
+                    "No emails found in {} of {}",
+                    mailbox, self.config.email
+                ));
+                continue;
+            }
+
+            self.notice(&format!(
+                "Found {} emails in {} of {}, fetching {}",
+                plan.count,
+                mailbox,
+                self.config.email,
+                plan.uids.len()
+            ));
+            if plan.uids.is_empty() {
+                continue;
+            }
+
+            let batches = self.start_batches(plan, catalog, checkpoint).await?;
+            running.push((plan, mailbox, batches));
+        }
+
+        let mut out_of_space = None;
+        for (plan, mailbox, batches) in running {
+            // Once the disk is full, whatever hasn't run yet is left for a
+            // retry rather than started
+            if out_of_space.is_some() {
+                for (_, handle) in &batches {
+                    handle.abort();
+                }
+            }
+            if let Err(e) = self.finish_batches(plan, batches, failures).await {
+                out_of_space.get_or_insert(e);
+                continue;
+            }
+
+            let (done, total) = self.progress.folder(&plan.name);
+            self.notice(&format!(
+                "Fetched {} of {} emails in {} of {}",
+                done, total, mailbox, self.config.email
+            ));
+            if self.config.threads == Some(ThreadMode::File) {
+                merge_thread_directories(&Path::new(&plan.dir).join("threads"))?;
+            }
+        }
+        match out_of_space {
+            Some(e) => Err(e),
+            None => Ok(found),
+        }
+    }
+
+    /// Fetches the account over JMAP, returning how many messages its
+    /// mailboxes hold. JMAP has no UIDs to checkpoint, so messages whose
+    /// Message-ID is already in the catalog are what later runs skip.
+    async fn fetch_jmap(&self, catalog: &Arc<Mutex<Catalog>>) -> Result<u32, ClientError> {
+        self.check_http_backend()?;
+        let session = Arc::new(
+            JmapSession::connect(
+                http::client(self.config.tor)?,
+                &self.config.jmap_url,
+                &self.config.password,
+                self.guard.read_only,
+                self.guard.audit.clone(),
+            )
+            .await?,
+        );
+        let all = session.mailboxes().await?;
+        let mailboxes: Vec<(&JmapMailbox, Vec<String>)> = all
+            .iter()
+            .map(|mailbox| (mailbox, mailbox_path(mailbox, &all)))
+            .filter(|(mailbox, path)| {
+                let special = match mailbox.role.as_deref() {
+                    Some("sent") => self.config.include_sent,
+                    Some("drafts") => self.config.include_drafts,
+                    _ => false,
+                };
+                special
+                    || if self.config.all_folders {
+                        self.config.folder_filter.matches(&path.join("/"))
+                    } else {
+                        mailbox.role.as_deref() == Some("inbox")
+                    }
+            })
+            .collect();
+
+        let mut quota = Quota::new(self.config.max_messages, self.config.max_bytes);
+        let mut found = 0;
+        for (mailbox, path) in mailboxes {
+            let name = path.join("/");
+            let inbox = mailbox.role.as_deref() == Some("inbox");
+            let dir = self.folder_dir(inbox, path.iter().map(String::as_str))?;
+            let store = Arc::new(self.store(dir, catalog));
+            found += mailbox.total_emails;
+            self.notice(&format!(
+                "Found {} emails in {} of {}",
+                mailbox.total_emails, name, self.config.email
+            ));
+
+            let mut position = 0;
+            while !quota.is_exhausted() {
+                let ids = session
+                    .query(
+                        &mailbox.id,
+                        position,
+                        self.config.batch_size,
+                        self.config.order == FetchOrder::OldestFirst,
+                    )
+                    .await?;
+                if ids.is_empty() {
+                    break;
+                }
+                let emails = session.emails(&ids).await?;
+                let mut handles = Vec::new();
+                for (offset, email) in emails.into_iter().enumerate() {
+                    let seen = email.message_id.iter().flatten().next().is_some_and(|id| {
+                        lock(catalog).is_ok_and(|catalog| catalog.has_message_id(id))
+                    });
+                    if seen || !quota.take(email.size) {
+                        continue;
+                    }
+                    self.progress.add_total(1);
+                    let seq = (position + offset + 1) as u32;
+                    let session = Arc::clone(&session);
+                    let store = Arc::clone(&store);
+                    let progress = self.progress();
+                    let control = self.control();
+                    let name = name.clone();
+                    handles.push(tokio::spawn(async move {
+                        let _connection = control.connection().await?;
+                        control.wait_while_paused().await;
+                        let body = session.download(&email.blob_id).await?;
+                        control.transfer(body.len()).await;
+                        let message = FetchedMessage {
+                            seq,
+                            internal_date: email.received_at.as_deref().and_then(parse_utc_date),
+                            gmail_thread_id: email.thread_id,
+                            name: None,
+                            original_hash: None,
+                            flags: Vec::new(),
+                            labels: Vec::new(),
+                            body,
+                        };
+                        save_downloaded(&store, &progress, &name, &email.id, &message, |event| {
+                            session.audit(event)
+                        })
+                        .await
+                    }));
+                }
+                self.join_page(&store, &name, handles).await?;
+                position += ids.len();
+            }
+
+            if self.config.threads == Some(ThreadMode::File) {
+                merge_thread_directories(&store.dir_path.join("threads"))?;
+            }
+        }
+
+        if quota.is_exhausted() {
+            self.notice(&format!(
+                "Reached the limit for this run; the rest of {} is left for the next run",
+                self.config.email
+            ));
+        }
+        Ok(found)
+    }
+
+    /// Fetches the account through the Gmail API, returning how many messages
+    /// its labels hold. Message IDs take the place of UIDs in the checkpoint.
+    ///
+    /// After a complete run the mailbox's history ID is recorded, and the next
+    /// run only asks for the changes since then instead of listing every
+    /// label, falling back to a full listing once Gmail no longer has them.
+    async fn fetch_gmail_api(&self, catalog: &Arc<Mutex<Catalog>>) -> Result<u32, ClientError> {
+        self.check_http_backend()?;
+        let http = http::client(self.config.tor)?;
+        let token = match &self.config.oauth {
+            Some(credentials) if credentials.refresh_token.is_empty() => {
+                return Err(no_refresh_token(&self.config.email))
+            }
+            Some(credentials) => {
+                credentials
+                    .access_token(&http, GOOGLE_TOKEN_URL, None)
+                    .await?
+            }
+            None => self.config.password.clone(),
+        };
+        let api = Arc::new(GmailApi::new(
+            http,
+            &self.config.gmail_api_url,
+            token,
+            self.guard.audit.clone(),
+        )?);
+        let state = Arc::new(Mutex::new(GmailState::open(&self.config.dir_path)?));
+        lock(&state)?.set_sync_schedule(self.config.sync);
+
+        let labels: Vec<Label> = api
+            .labels()
+            .await?
+            .into_iter()
+            .filter(|label| {
+                let special = match label.id.as_str() {
+                    "SENT" => self.config.include_sent,
+                    "DRAFT" => self.config.include_drafts,
+                    _ => false,
+                };
+                special
+                    || if self.config.all_folders {
+                        label.is_folder() && self.config.folder_filter.matches(&label.name)
+                    } else {
+                        label.id == "INBOX"
+                    }
+            })
+            .collect();
+
+        let start = lock(&state)?.history_id().map(str::to_string);
+        let history = match &start {
+            Some(start) => api.history(start).await?,
+            None => None,
+        };
+        // Taken before listing, so whatever changes during the run is seen next time
+        let sync_point = match &history {
+            Some(history) => {
+                let changes = lock(&state)?.record_changes(history)?;
+                if changes > 0 {
+                    self.notice(&format!(
+                        "{} deletions and label changes in {} since the last run recorded in {}",
+                        changes, self.config.email, GMAIL_STATE_FILE
+                    ));
+                }
+                history.history_id.clone()
+            }
+            None => {
+                if start.is_some() {
+                    log::warn!(
+                        "Gmail no longer has the history since the last run of {}; listing every label",
+                        self.config.email
+                    );
+                }
+                api.history_id().await?
+            }
+        };
+
+        let failed_before = self.progress.failed_batches();
+        let mut quota = Quota::new(self.config.max_messages, self.config.max_bytes);
+        let mut found = 0;
+        for label in labels {
+            let total = api.label_total(&label.id).await?;
+            found += total;
+            let mut ids: Vec<(u32, String)> = match &history {
+                Some(history) => {
+                    // New to the label: added with it, or labelled with it since.
+                    // They are the label's newest messages, so number them last.
+                    let mut new: Vec<&String> = Vec::new();
+                    for (id, labels) in history.added.iter().chain(&history.labels_added) {
+                        if labels.contains(&label.id)
+                            && !new.contains(&id)
+                            && !history.deleted.contains(id)
+                        {
+                            new.push(id);
+                        }
+                    }
+                    let first = total.saturating_sub(new.len() as u32);
+                    new.into_iter()
+                        .enumerate()
+                        .map(|(index, id)| (first + index as u32 + 1, id.clone()))
+                        .collect()
+                }
+                // Listed newest first; number them oldest first like IMAP sequence numbers
+                None => api
+                    .message_ids(&label.id)
+                    .await?
+                    .into_iter()
+                    .rev()
+                    .enumerate()
+                    .map(|(index, id)| (index as u32 + 1, id))
+                    .collect(),
+            };
+            if self.config.order == FetchOrder::NewestFirst {
+                ids.reverse();
+            }
+            let planned: Vec<(u32, String)> = {
+                let state = lock(&state)?;
+                ids.into_iter()
+                    .filter(|(_, id)| !state.is_fetched(id))
+                    // Listing doesn't report sizes, so only the message count is capped
+                    .filter(|_| quota.take(0))
+                    .collect()
+            };
+            self.notice(&format!(
+                "Found {} emails in {} of {}, fetching {}",
+                total,
+                label.name,
+                self.config.email,
+                planned.len()
+            ));
+            self.progress.add_total(planned.len() as u32);
+
+            let dir = self.folder_dir(label.id == "INBOX", label.name.split('/'))?;
+            let store = Arc::new(self.store(dir, catalog));
+            for page in planned.chunks(self.config.batch_size) {
+                let mut handles = Vec::new();
+                for (seq, id) in page.iter().cloned() {
+                    let api = Arc::clone(&api);
+                    let state = Arc::clone(&state);
+                    let store = Arc::clone(&store);
+                    let progress = self.progress();
+                    let control = self.control();
+                    let name = label.name.clone();
+                    handles.push(tokio::spawn(async move {
+                        let _connection = control.connection().await?;
+                        control.wait_while_paused().await;
+                        let raw = api.message(&id).await?;
+                        control.transfer(raw.body.len()).await;
+                        let message = FetchedMessage {
+                            seq,
+                            body: raw.body,
+                            internal_date: raw.internal_date,
+                            gmail_thread_id: raw.thread_id,
+                            name: None,
+                            original_hash: None,
+                            flags: Vec::new(),
+                            labels: Vec::new(),
+                        };
+                        save_downloaded(&store, &progress, &name, &id, &message, |event| {
+                            api.audit(event)
+                        })
+                        .await?;
+                        lock(&state)?.record(&id)
+                    }));
+                }
+                self.join_page(&store, &label.name, handles).await?;
+            }
+
+            if self.config.threads == Some(ThreadMode::File) {
+                merge_thread_directories(&store.dir_path.join("threads"))?;
+            }
+        }
+
+        if quota.is_exhausted() {
+            self.notice(&format!(
+                "Reached the limit for this run; the rest of {} is left for the next run",
+                self.config.email
+            ));
+        } else if self.progress.failed_batches() == failed_before {
+            lock(&state)?.record_history_id(&sync_point)?;
+        }
+        lock(&state)?.sync()?;
+        Ok(found)
+    }
+
+    /// Rejects the options that only make sense for IMAP accounts.
+    fn check_http_backend(&self) -> Result<(), ClientError> {
+        if self.config.uids.is_some() || self.config.retry_from.is_some() {
+            return Err(ClientError::InvalidArgument(
+                "--uids and --retry-from only apply to IMAP accounts".to_string(),
+            ));
+        }
+        Ok(())
+    }
+
+    /// Waits for the per-message tasks of one page of an HTTP backend. A page
+    /// with any message that failed counts as one failed batch, and those
+    /// messages are fetched again by the next run; running out of space ends
+    /// the run.
+    async fn join_page(
+        &self,
+        store: &Store,
+        mailbox: &str,
+        handles: Vec<JoinHandle<Result<(), ClientError>>>,
+    ) -> Result<(), ClientError> {
+        let messages = handles.len();
+        let mut failure = None;
+        for handle in handles {
+            let error = match handle.await {
+                Ok(Ok(())) => continue,
+                Ok(Err(e)) => e,
+                Err(e) => ClientError::JoinError(Box::new(e)),
+            };
+            log::error!("Failed to fetch from {}: {}", mailbox, error);
+            if matches!(error, ClientError::InsufficientSpace { .. }) {
+                return Err(error);
+            }
+            self.progress.record_error(error.to_string());
+            failure.get_or_insert(error);
+        }
+        if failure.is_some() {
+            self.progress.add_failed_batch();
+        }
+        lock(&store.catalog)?.sync()?;
+        store.batch_complete(&BatchInfo {
+            mailbox,
+            messages,
+            error: failure.as_ref(),
+        });
+        Ok(())
+    }
+
+    /// Writes `failures.json` for the batches that failed, leaving out any of
+    /// their messages that were saved before the failure.
+    fn report_failures(
+        &self,
+        mut failures: Vec<Failure>,
+        checkpoint: &Mutex<Checkpoint>,
+    ) -> Result<(), ClientError> {
+        let checkpoint = lock(checkpoint)?;
+        for failure in &mut failures {
+            let fetched = checkpoint.fetched_uids(&failure.mailbox, failure.uid_validity);
+            failure.uids.retain(|uid| !fetched.contains(uid));
+        }
+        failures.retain(|failure| !failure.uids.is_empty());
+
+        if let Some(path) = write_failures(Path::new(&self.config.dir_path), &failures)? {
+            let missing: usize = failures.iter().map(|f| f.uids.len()).sum();
+            self.notice(&format!(
+                "{} emails of {} could not be fetched; retry them with --retry-from {}",
+                missing,
+                self.config.email,
+                path.display()
+            ));
+        }
+        Ok(())
+    }
+
+    /// Prints a status message, unless something else (the TUI) owns the terminal.
+    fn notice(&self, message: &str) {
+        log::info!("{}", message);
+        if !self.config.quiet {
+            clear_status();
+            println!("{}", message);
+        }
+    }
+
+    fn summary(&self, found: u32) -> FetchSummary {
+        FetchSummary {
+            account: self.config.email.clone(),
+            found,
+            saved: self.progress.saved(),
+            failed_batches: self.progress.failed_batches(),
+            server_id: self.server_id.get().cloned(),
+            alerts: lock(&self.guard.alerts)
+                .map(|alerts| alerts.clone())
+                .unwrap_or_default(),
+        }
+    }
+
+    /// Signs in to the provider in the browser, checks that the IMAP server
+    /// accepts the access token granted, and keeps the refresh token in the
+    /// system keyring for later runs. `open` is given the address of the
+    /// sign-in page.
+    pub async fn login(&self, open: impl Fn(&str)) -> Result<Login, ClientError> {
+        if self.config.backend != Backend::Imap {
+            return Err(ClientError::InvalidArgument(
+                "login only supports IMAP accounts".to_string(),
+            ));
+        }
+        let provider = self.config.provider;
+        let Some(flow) = provider.browser_flow() else {
+            return Err(ClientError::InvalidArgument(format!(
+                "{} accounts sign in with a password, not OAuth",
+                provider.display_name()
+            )));
+        };
+        let Some(oauth) = self
+            .config
+            .oauth
+            .as_ref()
+            .filter(|oauth| !oauth.client_id.is_empty())
+        else {
+            return Err(ClientError::ActionRequired {
+                message: format!(
+                    "Signing in to {} needs an OAuth client ID",
+                    provider.display_name()
+                ),
+                guidance: format!(
+                    "{}, then set oauth_client_id in the accounts file or pass \
+                     --oauth-client-id.",
+                    flow.registration
+                ),
+            });
+        };
+
+        let grant = oauth
+            .browser_flow(
+                &http::client(self.config.tor)?,
+                &flow,
+                &self.config.email,
+                open,
+            )
+            .await?;
+        let Some(refresh_token) = grant.refresh_token else {
+            return Err(ClientError::AuthenticationError(
+                "the provider granted no refresh token".to_string(),
+            ));
+        };
+        // Only keep a token the server accepts
+        let _ = self
+            .credentials
+            .set(Credentials::AccessToken(grant.access_token));
+        let (mut session, _slot) = self.connect().await?;
+        self.sign_in(&mut session).await?;
+        session.logout().await?;
+
+        let stored = match store_refresh_token(&self.config.email, &refresh_token) {
+            Ok(()) => true,
+            Err(e) => {
+                log::warn!("{}: {}", self.config.email, e);
+                false
+            }
+        };
+        Ok(Login {
+            refresh_token,
+            stored,
+        })
+    }
+
+    /// Searches the account's mailboxes for the message with the given
+    /// Message-ID and returns its raw bytes, or `None` if there is none.
+    pub async fn get_message(&self, message_id: &str) -> Result<Option<Vec<u8>>, ClientError> {
+        if self.config.backend != Backend::Imap {
+            return Err(ClientError::InvalidArgument(
+                "get only supports IMAP accounts".to_string(),
+            ));
+        }
+        let mailboxes = self.mailboxes().await?;
+
+        let (mut session, _slot) = self.connect().await?;
+        self.sign_in(&mut session).await?;
+
+        let mut found = None;
+        for mailbox in &mailboxes {
+            session
+                .execute_args(&[
+                    Arg::Raw("EXAMINE"),
+                    Arg::String(&session.mailbox_name(&mailbox.name)),
+                ])
+                .await?;
+            // Internationalized Message-IDs need a charset unless UTF8=ACCEPT
+            // made UTF-8 the only one
+            let search = if message_id.is_ascii() || session.utf8_enabled() {
+                "UID SEARCH HEADER Message-ID"
+            } else {
+                "UID SEARCH CHARSET UTF-8 HEADER Message-ID"
+            };
+            let untagged = session
+                .execute_args(&[Arg::Raw(search), Arg::String(message_id.trim())])
+                .await?;
+            let uid = untagged
+                .iter()
+                .filter_map(|line| line.strip_prefix("SEARCH"))
+                .flat_map(str::split_whitespace)
+                .find_map(|uid| uid.parse::<u32>().ok());
+
+            if let Some(uid) = uid {
+                log::info!("Found {} in {} (UID {})", message_id, mailbox.name, uid);
+                found = Some(fetch_message_body(&mut session, uid).await?);
+                break;
+            }
+        }
+
+        session.logout().await?;
+        Ok(found)
+    }
+
+    /// Adds (or with `add` false, removes) Gmail label `label` on the messages
+    /// that match `search`, raw IMAP search keys such as `BEFORE 1-Jan-2020`
+    /// or `X-GM-RAW "older_than:5y"`, and the configured UIDs, in the folders
+    /// a fetch would work on. With `dry_run`, messages are only counted.
+    /// Returns how many matched in each folder.
+    pub async fn label_messages(
+        &self,
+        label: &str,
+        add: bool,
+        search: Option<&str>,
+        dry_run: bool,
+    ) -> Result<Vec<(String, usize)>, ClientError> {
+        if self.config.backend != Backend::Imap {
+            return Err(ClientError::InvalidArgument(
+                "label only supports IMAP accounts".to_string(),
+            ));
+        }
+        if search.is_none() && self.config.uids.is_none() {
+            return Err(ClientError::InvalidArgument(
+                "label needs --search or --uids to choose messages".to_string(),
+            ));
+        }
+        let mailboxes = self.mailboxes().await?;
+        uids_need_one_folder(self.config.uids.as_ref(), mailboxes.len())?;
+
+        let (mut session, _slot) = self.connect().await?;
+        self.sign_in(&mut session).await?;
+        let mut counts = Vec::new();
+        for mailbox in &mailboxes {
+            session
+                .execute_args(&[
+                    Arg::Raw(if dry_run { "EXAMINE" } else { "SELECT" }),
+                    Arg::String(&session.mailbox_name(&mailbox.name)),
+                ])
+                .await?;
+            let uids = search_uids(&mut session, search, self.config.uids.as_ref()).await?;
+            if !dry_run && !uids.is_empty() {
+                store_label(&mut session, &uids, add, label).await?;
+                log::info!(
+                    "{}: {} label {} on {} messages",
+                    mailbox.name,
+                    if add { "added" } else { "removed" },
+                    label,
+                    uids.len()
+                );
+            }
+            counts.push((mailbox.name.clone(), uids.len()));
+        }
+
+        session.logout().await?;
+        Ok(counts)
+    }
+
+    /// Deletes from the server the messages that match `search` and the
+    /// configured UIDs in the folders a fetch would work on, to reclaim
+    /// storage once they are archived. Only messages whose Message-ID is in
+    /// the local catalog, with the file still on disk, are flagged `\Deleted`
+    /// and expunged; the rest are counted and left alone. With `dry_run`,
+    /// nothing is changed.
+    pub async fn purge_messages(
+        &self,
+        search: &str,
+        dry_run: bool,
+    ) -> Result<Vec<PurgeCount>, ClientError> {
+        if self.config.backend != Backend::Imap {
+            return Err(ClientError::InvalidArgument(
+                "purge only supports IMAP accounts".to_string(),
+            ));
+        }
+        if search.trim().is_empty() {
+            return Err(ClientError::InvalidArgument(
+                "purge needs --search to choose messages".to_string(),
+            ));
+        }
+        let root = Path::new(&self.config.dir_path);
+        let _lock = RunLock::acquire(root)?;
+        let archived: HashSet<String> = Catalog::open(root)?
+            .entries()
+            .iter()
+            .filter(|entry| !entry.message_id_hash.is_empty() && root.join(&entry.path).is_file())
+            .map(|entry| entry.message_id_hash.clone())
+            .collect();
+        let mailboxes = self.mailboxes().await?;
+        uids_need_one_folder(self.config.uids.as_ref(), mailboxes.len())?;
+
+        let (mut session, _slot) = self.connect().await?;
+        self.sign_in(&mut session).await?;
+        session.ensure_capabilities().await?;
+        let mut counts = Vec::new();
+        for mailbox in &mailboxes {
+            session
+                .execute_args(&[
+                    Arg::Raw(if dry_run { "EXAMINE" } else { "SELECT" }),
+                    Arg::String(&session.mailbox_name(&mailbox.name)),
+                ])
+                .await?;
+            let uids = search_uids(&mut session, Some(search), self.config.uids.as_ref()).await?;
+            let mut purged = Vec::new();
+            for (uid, message_id) in fetch_message_ids(&mut session, &uids).await? {
+                if message_id.is_some_and(|id| archived.contains(&hash_message_id(&id))) {
+                    purged.push(uid);
+                } else {
+                    log::warn!(
+                        "{}: keeping UID {}, which isn't in the local archive",
+                        mailbox.name,
+                        uid
+                    );
+                }
+            }
+            if !dry_run && !purged.is_empty() {
+                delete_messages(&mut session, &purged).await?;
+                log::info!("{}: purged {} messages", mailbox.name, purged.len());
+            }
+            counts.push(PurgeCount {
+                mailbox: mailbox.name.clone(),
+                purged: purged.len(),
+                unarchived: uids.len() - purged.len(),
+            });
+        }
+
+        session.logout().await?;
+        Ok(counts)
+    }
+
+    /// Deletes what the retention policies expire, once a fetch is done.
+    /// Archived files go first, and are dropped from the catalog; the
+    /// checkpoint keeps them from being fetched again. Then, as with purge,
+    /// expired messages are deleted from the server only if the archive
+    /// still has a copy of them that checks out.
+    async fn apply_retention(
+        &self,
+        catalog: &Mutex<Catalog>,
+    ) -> Result<Vec<RetentionCount>, ClientError> {
+        let policies = &self.config.retention;
+        let root = Path::new(&self.config.dir_path);
+        let mailboxes = self.mailboxes().await?;
+        let mut dirs = Vec::new();
+        for mailbox in &mailboxes {
+            if policy_for(policies, Scope::Local, &mailbox.name).is_some()
+                && policy_for(policies, Scope::Server, &mailbox.name).is_some()
+            {
+                return Err(ClientError::InvalidArgument(format!(
+                    "retention policies for {} would delete both its archived and its server copies",
+                    mailbox.name
+                )));
+            }
+            dirs.push((
+                mailbox.name.clone(),
+                relative_path(root, &self.mailbox_dir(mailbox)?),
+            ));
+        }
+        let now = unix_time();
+        let mut counts: Vec<RetentionCount> = mailboxes
+            .iter()
+            .map(|mailbox| RetentionCount {
+                mailbox: mailbox.name.clone(),
+                ..Default::default()
+            })
+            .collect();
+
+        let mut archived: HashMap<String, Vec<CatalogEntry>> = HashMap::new();
+        {
+            let mut catalog = lock(catalog)?;
+            let mut removed = HashSet::new();
+            let expired = expired_files(catalog.entries(), &dirs, policies, now);
+            for (count, (_, paths)) in counts.iter_mut().zip(expired) {
+                for path in paths {
+                    let file = root.join(&path);
+                    let mut files = vec![file.clone()];
+                    if file.extension().is_some_and(|extension| extension == "eml") {
+                        files.push(file.with_extension("txt"));
+                    }
+                    for file in files {
+                        match std::fs::remove_file(&file) {
+                            Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                                return Err(e.into())
+                            }
+                            _ => {}
+                        }
+                    }
+                    log::info!("{}: deleted {}, past retention", count.mailbox, path);
+                    count.local += 1;
+                    removed.insert(path);
+                }
+            }
+            if !removed.is_empty() {
+                catalog.retain(|entry| !removed.contains(&entry.path))?;
+            }
+            for entry in catalog.entries() {
+                if !entry.message_id_hash.is_empty() {
+                    archived
+                        .entry(entry.message_id_hash.clone())
+                        .or_default()
+                        .push(entry.clone());
+                }
+            }
+        }
+
+        let servers: Vec<_> = mailboxes
+            .iter()
+            .map(|mailbox| policy_for(policies, Scope::Server, &mailbox.name))
+            .collect();
+        if servers.iter().all(Option::is_none) {
+            return Ok(counts);
+        }
+        // Renamed Maildir files still count as copies
+        let intact = |entry: &CatalogEntry| {
+            current_path(root, entry).is_some_and(|path| {
+                CatalogEntry {
+                    path,
+                    ..entry.clone()
+                }
+                .verify(root)
+                .is_ok()
+            })
+        };
+        let (mut session, _slot) = self.connect().await?;
+        self.sign_in(&mut session).await?;
+        session.ensure_capabilities().await?;
+        for ((mailbox, policy), count) in mailboxes.iter().zip(servers).zip(&mut counts) {
+            let Some(policy) = policy else {
+                continue;
+            };
+            session
+                .execute_args(&[
+                    Arg::Raw("SELECT"),
+                    Arg::String(&session.mailbox_name(&mailbox.name)),
+                ])
+                .await?;
+            let before = format!("BEFORE {}", imap_date(policy.cutoff(now)));
+            let uids = search_uids(&mut session, Some(&before), None).await?;
+            let mut expired = Vec::new();
+            for (uid, message_id) in fetch_message_ids(&mut session, &uids).await? {
+                let copies = message_id.and_then(|id| archived.get(&hash_message_id(&id)));
+                if copies.is_some_and(|copies| copies.iter().any(intact)) {
+                    expired.push(uid);
+                } else {
+                    log::warn!(
+                        "{}: keeping UID {} past retention, as the archive has no intact copy",
+                        mailbox.name,
+                        uid
+                    );
+                    count.kept += 1;
+                }
+            }
+            if !expired.is_empty() {
+                delete_messages(&mut session, &expired).await?;
+                log::info!(
+                    "{}: deleted {} messages past retention",
+                    mailbox.name,
+                    expired.len()
+                );
+            }
+            count.server = expired.len();
+        }
+        session.logout().await?;
+        Ok(counts)
+    }
+
+    /// Checks the archive's files against the catalog, and downloads the
+    /// missing and corrupt ones again from the folders a fetch would work
+    /// on. Messages are found by Message-ID and fetched by UID, and a file is
+    /// only written back if the download matches its catalogued hash. With
+    /// `prune`, missing files count as deleted on purpose and their entries
+    /// are dropped from the catalog instead; the checkpoint keeps them from
+    /// being fetched again. With `dry_run`, the archive is only checked.
+    pub async fn repair_archive(
+        &self,
+        prune: bool,
+        dry_run: bool,
+    ) -> Result<RepairReport, ClientError> {
+        if self.config.backend != Backend::Imap {
+            return Err(ClientError::InvalidArgument(
+                "repair only supports IMAP accounts".to_string(),
+            ));
+        }
+        let root = Path::new(&self.config.dir_path);
+        let _lock = RunLock::acquire(root)?;
+        let mut catalog = Catalog::open(root)?;
+        let mut report = check_archive(root, &mut catalog)?;
+        catalog.sync()?;
+        if dry_run {
+            return Ok(report);
+        }
+
+        let mut wanted: HashMap<String, Vec<CatalogEntry>> = HashMap::new();
+        for damaged in &report.damaged {
+            let entry = &damaged.entry;
+            if prune && damaged.damage == Damage::Missing {
+                report.pruned.push(entry.path.clone());
+            } else if entry.message_id_hash.is_empty() {
+                report
+                    .unrecovered
+                    .push((entry.path.clone(), "it has no Message-ID".to_string()));
+            } else if entry.original_hash.is_some() {
+                report.unrecovered.push((
+                    entry.path.clone(),
+                    "it was stored changed from the download".to_string(),
+                ));
+            } else {
+                wanted
+                    .entry(entry.message_id_hash.clone())
+                    .or_default()
+                    .push(entry.clone());
+            }
+        }
+        if !report.pruned.is_empty() {
+            let pruned: HashSet<&String> = report.pruned.iter().collect();
+            catalog.retain(|entry| !pruned.contains(&entry.path))?;
+        }
+
+        let mut differing = HashSet::new();
+        if !wanted.is_empty() {
+            let mailboxes = self.mailboxes().await?;
+            let (mut session, _slot) = self.connect().await?;
+            self.sign_in(&mut session).await?;
+            for mailbox in &mailboxes {
+                if wanted.is_empty() {
+                    break;
+                }
+                session
+                    .execute_args(&[
+                        Arg::Raw("EXAMINE"),
+                        Arg::String(&session.mailbox_name(&mailbox.name)),
+                    ])
+                    .await?;
+                let uids = search_uids(&mut session, None, None).await?;
+                for (uid, message_id) in fetch_message_ids(&mut session, &uids).await? {
+                    let Some(hash) = message_id.map(|id| hash_message_id(&id)) else {
+                        continue;
+                    };
+                    let Some(entries) = wanted.remove(&hash) else {
+                        continue;
+                    };
+                    let body = fetch_message_body(&mut session, uid).await?;
+                    let mut unmatched = Vec::new();
+                    for entry in entries {
+                        let stored = entry.line_endings.apply(&body);
+                        if sha256_hex(&stored) != entry.content_hash {
+                            unmatched.push(entry);
+                            continue;
+                        }
+                        restore_file(root, &entry, &stored).await?;
+                        log::info!("Restored {} from {} UID {}", entry.path, mailbox.name, uid);
+                        report.restored.push(entry.path);
+                    }
+                    // Another message with this Message-ID may still match
+                    if !unmatched.is_empty() {
+                        differing.insert(hash.clone());
+                        wanted.insert(hash, unmatched);
+                    }
+                }
+            }
+            session.logout().await?;
+        }
+        for (hash, entries) in wanted {
+            let reason = if differing.contains(&hash) {
+                "the server's copy differs from the one archived"
+            } else {
+                "not found on the server"
+            };
+            for entry in entries {
+                report.unrecovered.push((entry.path, reason.to_string()));
+            }
+        }
+        report.unrecovered.sort();
+        Ok(report)
+    }
+
+    /// Compares the folders a fetch would work on with the local archive,
+    /// fetching only headers and flags. A server message counts as archived
+    /// when its UID is checkpointed or its Message-ID is in the catalog.
+    /// Flags are only compared for Maildir archives, whose file names carry
+    /// them.
+    pub async fn diff_archive(&self) -> Result<Vec<MailboxDiff>, ClientError> {
+        if self.config.backend != Backend::Imap {
+            return Err(ClientError::InvalidArgument(
+                "diff only supports IMAP accounts".to_string(),
+            ));
+        }
+        let root = Path::new(&self.config.dir_path);
+        let state = StateDb::open(root)?;
+        let catalog = Catalog::load(&state)?;
+        let checkpoint = Checkpoint::load(&state)?;
+        let mut maildir_copies: HashMap<&str, Vec<&CatalogEntry>> = HashMap::new();
+        for entry in catalog.entries() {
+            if !entry.message_id_hash.is_empty() && in_maildir(Path::new(&entry.path)) {
+                maildir_copies
+                    .entry(&entry.message_id_hash)
+                    .or_default()
+                    .push(entry);
+            }
+        }
+        let mailboxes = self.mailboxes().await?;
+
+        let (mut session, _slot) = self.connect().await?;
+        self.sign_in(&mut session).await?;
+        let mut diffs = Vec::new();
+        for mailbox in &mailboxes {
+            let untagged = session
+                .execute_args(&[
+                    Arg::Raw("EXAMINE"),
+                    Arg::String(&session.mailbox_name(&mailbox.name)),
+                ])
+                .await?;
+            let uid_validity = untagged
+                .iter()
+                .find_map(|line| response_code_arg(line, "UIDVALIDITY"))
+                .and_then(|value| value.parse().ok())
+                .unwrap_or(0);
+            let fetched = checkpoint.fetched_uids(&mailbox.name, uid_validity);
+            let uids = search_uids(&mut session, None, None).await?;
+            let messages = fetch_headers(&mut session, &uids, !maildir_copies.is_empty()).await?;
+            let folder = relative_path(root, &self.mailbox_dir(mailbox)?);
+
+            let mut diff = MailboxDiff {
+                mailbox: mailbox.name.clone(),
+                ..Default::default()
+            };
+            let on_server: HashSet<u32> = messages.iter().map(|message| message.uid).collect();
+            diff.local_only = fetched
+                .iter()
+                .copied()
+                .filter(|uid| !on_server.contains(uid))
+                .collect();
+            diff.local_only.sort_unstable();
+            for message in &messages {
+                let hash = message.message_id.as_deref().map(hash_message_id);
+                let catalogued = hash
+                    .as_ref()
+                    .is_some_and(|hash| catalog.has_message_id_hash(hash));
+                if !fetched.contains(&message.uid) && !catalogued {
+                    diff.server_only.push(message.uid);
+                }
+                let copies = hash.and_then(|hash| maildir_copies.get(hash.as_str()));
+                let server_flags = maildir::maildir_flags(&message.flags);
+                for entry in copies.into_iter().flatten() {
+                    let in_folder = Path::new(&entry.path)
+                        .parent()
+                        .and_then(Path::parent)
+                        .is_some_and(|dir| dir == Path::new(&folder));
+                    let Some(path) = current_path(root, entry).filter(|_| in_folder) else {
+                        continue;
+                    };
+                    let local_flags = maildir::info_flags(&path).to_string();
+                    if local_flags != server_flags {
+                        diff.flag_changes
+                            .push((path, local_flags, server_flags.clone()));
+                    }
+                }
+            }
+            diff.server_only.sort_unstable();
+            diffs.push(diff);
+        }
+        session.logout().await?;
+        Ok(diffs)
+    }
+
+    /// Every selectable folder of the account, with whether this account's
+    /// settings select it for fetching. The folders' [`Mailbox::special_use`]
+    /// tells Sent, Drafts, Junk and the like apart whatever their names.
+    pub async fn list_folders(&self) -> Result<Vec<(Mailbox, bool)>, ClientError> {
+        if self.config.backend != Backend::Imap {
+            return Err(ClientError::InvalidArgument(
+                "folders only supports IMAP accounts".to_string(),
+            ));
+        }
+        let all = self.list_all_mailboxes().await?;
+        let selected = self.select_mailboxes(&all);
+        Ok(all
+            .into_iter()
+            .map(|mailbox| {
+                let chosen = selected.iter().any(|s| s.name == mailbox.name);
+                (mailbox, chosen)
+            })
+            .collect())
+    }
+
+    /// Connects and signs in, then lists the server's capabilities and the
+    /// account's folders, to show that a fetch would get going.
+    pub async fn check(&self) -> Result<CheckReport, ClientError> {
+        if self.config.backend != Backend::Imap {
+            return Err(ClientError::InvalidArgument(
+                "check only supports IMAP accounts".to_string(),
+            ));
+        }
+        let started = Instant::now();
+        let (mut session, _slot) = self.connect().await?;
+        self.sign_in(&mut session).await?;
+        let sign_in_time = started.elapsed();
+        // Servers may advertise more once signed in
+        session.execute("CAPABILITY").await?;
+        let mut capabilities: Vec<String> = session.capabilities().iter().cloned().collect();
+        capabilities.sort_unstable();
+        let folders = self.list_mailboxes(&mut session).await?;
+        session.logout().await?;
+        Ok(CheckReport {
+            server: self.server.clone(),
+            sign_in_time,
+            server_id: self.server_id.get().cloned(),
+            capabilities,
+            folders,
+        })
+    }
+
+    /// Reports the account's quota usage, and how many messages the mailboxes
+    /// a fetch would work on hold and their sizes. `sample` messages spread
+    /// over the sizes are downloaded to project what other formats and
+    /// compression would take.
+    pub async fn stats(&self, sample: usize) -> Result<AccountStats, ClientError> {
+        if self.config.backend != Backend::Imap {
+            return Err(ClientError::InvalidArgument(
+                "stats only supports IMAP accounts".to_string(),
+            ));
+        }
+        let mailboxes = self.mailboxes().await?;
+
+        let (mut session, _slot) = self.connect().await?;
+        self.sign_in(&mut session).await?;
+        session.ensure_capabilities().await?;
+        let quota = if session.has_capability("QUOTA") {
+            let untagged = session
+                .execute_args(&[Arg::Raw("GETQUOTAROOT"), Arg::String("INBOX")])
+                .await?;
+            untagged
+                .iter()
+                .filter_map(|line| parse_quota_response(line))
+                .collect()
+        } else {
+            Vec::new()
+        };
+
+        let mut counts = Vec::new();
+        let mut sizes = SizeHistogram::default();
+        // Mailbox index, UID and size of every message
+        let mut messages: Vec<(usize, u32, u64)> = Vec::new();
+        for (index, mailbox) in mailboxes.iter().enumerate() {
+            let untagged = session
+                .execute_args(&[
+                    Arg::Raw("STATUS"),
+                    Arg::String(&session.mailbox_name(&mailbox.name)),
+                    Arg::Raw("(MESSAGES)"),
+                ])
+                .await?;
+            let Some((_, count)) = untagged.iter().find_map(|line| parse_status_messages(line))
+            else {
+                continue;
+            };
+            counts.push((mailbox.name.clone(), count));
+            if count == 0 {
+                continue;
+            }
+            session
+                .execute_args(&[
+                    Arg::Raw("EXAMINE"),
+                    Arg::String(&session.mailbox_name(&mailbox.name)),
+                ])
+                .await?;
+            for line in session.execute("UID FETCH 1:* (RFC822.SIZE)").await? {
+                let uid = fetch_item(&line, "UID").and_then(|uid| uid.parse().ok());
+                let size = fetch_item(&line, "RFC822.SIZE").and_then(|size| size.parse().ok());
+                if let (Some(uid), Some(size)) = (uid, size) {
+                    sizes.add(size);
+                    messages.push((index, uid, size));
+                }
+            }
+        }
+
+        let all: Vec<u64> = messages.iter().map(|&(_, _, size)| size).collect();
+        let mut selected = None;
+        for picked in pick_samples(&all, sample) {
+            let (index, uid, _) = messages[picked];
+            if selected != Some(index) {
+                session
+                    .execute_args(&[
+                        Arg::Raw("EXAMINE"),
+                        Arg::String(&session.mailbox_name(&mailboxes[index].name)),
+                    ])
+                    .await?;
+                selected = Some(index);
+            }
+            let raw = fetch_message_body(&mut session, uid).await?;
+            sizes.add_sample(&raw)?;
+        }
+        session.logout().await?;
+
+        Ok(AccountStats {
+            account: self.config.email.clone(),
+            quota,
+            mailboxes: counts,
+            sizes,
+        })
+    }
+
+    /// Mailboxes to work on: INBOX and the special folders asked for, or every
+    /// selected folder in all-folders mode.
+    async fn mailboxes(&self) -> Result<Vec<Mailbox>, ClientError> {
+        if !self.config.all_folders && !self.config.include_sent && !self.config.include_drafts {
+            return Ok(vec![inbox()]);
+        }
+        let all = self.list_all_mailboxes().await?;
+        Ok(self.select_mailboxes(&all))
+    }
+
+    /// The mailboxes of `all` to work on. In all-folders mode these are the
+    /// ones passing the folder filter, plus the special folders asked for.
+    fn select_mailboxes(&self, all: &[Mailbox]) -> Vec<Mailbox> {
+        let special = self.special_folders(all);
+        if !self.config.all_folders {
+            let extra = special
+                .into_iter()
+                .filter(|mailbox| !mailbox.name.eq_ignore_ascii_case("INBOX"));
+            return std::iter::once(inbox()).chain(extra).collect();
+        }
+
+        let mailboxes: Vec<Mailbox> = all
+            .iter()
+            .filter(|mailbox| {
+                let selected = special.iter().any(|s| s.name == mailbox.name)
+                    || self.config.folder_filter.matches_mailbox(mailbox);
+                if !selected {
+                    log::info!("Skipping mailbox {} (filtered out)", mailbox.name);
+                }
+                selected
+            })
+            .cloned()
+            .collect();
+
+        log::info!("Selected {} mailboxes", mailboxes.len());
+        mailboxes
+    }
+
+    /// Every selectable mailbox of the account, noting the personal namespace
+    /// on the way. Servers with XLIST but not SPECIAL-USE only mark special
+    /// folders in XLIST responses.
+    async fn list_all_mailboxes(&self) -> Result<Vec<Mailbox>, ClientError> {
+        log::info!("Listing mailboxes...");
+
+        let (mut session, _slot) = self.connect().await?;
+        self.sign_in(&mut session).await?;
+        let mailboxes = self.list_mailboxes(&mut session).await?;
+        session.logout().await?;
+        Ok(mailboxes)
+    }
+
+    /// Every selectable mailbox listed on `session`, which is signed in.
+    async fn list_mailboxes(&self, session: &mut ImapSession) -> Result<Vec<Mailbox>, ClientError> {
+        session.ensure_capabilities().await?;
+        if session.has_capability("NAMESPACE") {
+            let untagged = session.execute("NAMESPACE").await?;
+            if let Some(namespace) = untagged
+                .iter()
+                .find_map(|line| parse_namespace_response(line))
+            {
+                log::info!(
+                    "Personal namespace of {}: prefix {:?}, delimiter {:?}",
+                    self.config.email,
+                    namespace.prefix,
+                    namespace.delimiter
+                );
+                let _ = self.namespace.set(namespace);
+            }
+        }
+        let command = if session.has_capability("XLIST") && !session.has_capability("SPECIAL-USE") {
+            "XLIST \"\" \"*\""
+        } else {
+            "LIST \"\" \"*\""
+        };
+        let untagged = session.execute(command).await?;
+        let utf8 = session.utf8_enabled();
+        Ok(untagged
+            .iter()
+            .filter_map(|line| match utf8 {
+                true => parse_utf8_list_response(line),
+                false => parse_list_response(line),
+            })
+            .filter(|mailbox| mailbox.is_selectable())
+            .collect())
+    }
+
+    /// The special folders asked for with `--include-sent` and
+    /// `--include-drafts`, found by special-use attribute or else by the
+    /// provider's name for them.
+    fn special_folders(&self, mailboxes: &[Mailbox]) -> Vec<Mailbox> {
+        let names = self.config.provider.folders();
+        let namespace = self.namespace();
+        let wanted = [
+            (self.config.include_sent, SpecialUse::Sent, names.sent),
+            (self.config.include_drafts, SpecialUse::Drafts, names.drafts),
+        ];
+        wanted
+            .into_iter()
+            .filter(|(wanted, _, _)| *wanted)
+            .filter_map(|(_, special, name)| {
+                let found = find_special_folder(mailboxes, special, name, &namespace);
+                if found.is_none() {
+                    log::warn!("{} has no {} folder", self.config.email, special);
+                }
+                found.cloned()
+            })
+            .collect()
+    }
+
+    /// The personal namespace, or Gmail's if the server didn't say.
+    fn namespace(&self) -> Namespace {
+        self.namespace.get().cloned().unwrap_or_default()
+    }
+
+    /// Directory a mailbox is saved into. INBOX-only runs write INBOX straight
+    /// into the configured directory; other folders get a subdirectory each,
+    /// named by their path within the personal namespace.
+    fn mailbox_dir(&self, mailbox: &Mailbox) -> Result<PathBuf, ClientError> {
+        let inbox = mailbox.name.eq_ignore_ascii_case("INBOX");
+        self.folder_dir(inbox, mailbox.components_in(&self.namespace()))
+    }
+
+    /// Directory for a folder given by its path from the top of the
+    /// hierarchy: the output directory itself for the inbox unless fetching
+    /// all folders.
+    fn folder_dir<'a>(
+        &self,
+        inbox: bool,
+        components: impl IntoIterator<Item = &'a str>,
+    ) -> Result<PathBuf, ClientError> {
+        if inbox && !self.config.all_folders {
+            return Ok(self.config.dir_path.clone());
+        }
+
+        let mut path = self.config.dir_path.clone();
+        for component in components {
+            path.push(sanitize_filename(component));
+        }
+        std::fs::create_dir_all(&path).map_err(|source| ClientError::DirectoryError {
+            path: path.clone(),
+            source,
+        })?;
+        Ok(path)
+    }
+
+    /// When the first message in `mailbox` was received, or `None` if it is
+    /// empty.
+    async fn first_received(&self, mailbox: &str) -> Result<Option<i64>, ClientError> {
+        let (mut session, _slot) = self.connect().await?;
+        self.sign_in(&mut session).await?;
+        let untagged = session
+            .execute_args(&[
+                Arg::Raw("EXAMINE"),
+                Arg::String(&session.mailbox_name(mailbox)),
+            ])
+            .await?;
+        if exists_count(&untagged) == 0 {
+            session.logout().await?;
+            return Ok(None);
+        }
+        let received = session
+            .execute("FETCH 1 (INTERNALDATE)")
+            .await?
+            .iter()
+            .find_map(|line| quoted_fetch_item(line, "INTERNALDATE").and_then(parse_internal_date));
+        session.logout().await?;
+        // Without a date to start from, the range is a single window
+        Ok(Some(received.unwrap_or(i64::MAX)))
+    }
+
+    /// Selects a mailbox and lists the UID and size of every message in it
+    /// the server received in `dates`.
+    async fn get_mailbox_status(
+        &self,
+        mailbox: &str,
+        dates: &DateRange,
+    ) -> Result<MailboxStatus, ClientError> {
+        log::info!("Connecting to get email count of {}...", mailbox);
+
+        let (mut session, _slot) = self.connect().await?;
+        self.sign_in(&mut session).await?;
+        let untagged = session
+            .execute_args(&[
+                Arg::Raw("EXAMINE"),
+                Arg::String(&session.mailbox_name(mailbox)),
+            ])
+            .await?;
+        let email_count = exists_count(&untagged);
+
+        let uid_validity = untagged
+            .iter()
+            .find_map(|line| response_code_arg(line, "UIDVALIDITY"))
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(0);
+
+        let mut messages = Vec::new();
+        match dates.search_keys() {
+            _ if email_count == 0 => {}
+            None => {
+                for line in session.execute("UID FETCH 1:* (RFC822.SIZE)").await? {
+                    let uid = fetch_item(&line, "UID").and_then(|uid| uid.parse().ok());
+                    let size = fetch_item(&line, "RFC822.SIZE").and_then(|size| size.parse().ok());
+                    if let (Some(uid), Some(size)) = (uid, size) {
+                        messages.push((uid, size));
+                    }
+                }
+            }
+            // SEARCH narrows it down to whole days, then the exact
+            // INTERNALDATE decides
+            Some(keys) => {
+                let uids = search_uids(&mut session, Some(&keys), None).await?;
+                if !uids.is_empty() {
+                    let command = format!(
+                        "UID FETCH {} (RFC822.SIZE INTERNALDATE)",
+                        sequence_set(&uids)
+                    );
+                    for line in session.execute(&command).await? {
+                        let uid = fetch_item(&line, "UID").and_then(|uid| uid.parse().ok());
+                        let size =
+                            fetch_item(&line, "RFC822.SIZE").and_then(|size| size.parse().ok());
+                        let received =
+                            quoted_fetch_item(&line, "INTERNALDATE").and_then(parse_internal_date);
+                        let (Some(uid), Some(size)) = (uid, size) else {
+                            continue;
+                        };
+                        match received {
+                            Some(received) if !dates.contains(received) => {}
+                            // Without a date to check, trust the server's SEARCH
+                            _ => messages.push((uid, size)),
+                        }
+                    }
+                }
+                log::info!(
+                    "{} of {} emails in {} are in the date range",
+                    messages.len(),
+                    email_count,
+                    mailbox
+                );
+            }
+        }
+
+        session.logout().await?;
+
+        Ok(MailboxStatus {
+            uid_validity,
+            count: email_count,
+            messages,
+        })
+    }
+
+    /// Queues the batches of `plan` for the connection pool, returning the
+    /// UIDs and task of each.
+    async fn start_batches(
+        &self,
+        plan: &MailboxPlan,
+        catalog: &Arc<Mutex<Catalog>>,
+        checkpoint: &Arc<Mutex<Checkpoint>>,
+    ) -> Result<Batches, ClientError> {
+        self.progress
+            .add_folder_total(&plan.name, plan.uids.len() as u32);
+        let batch_size = self.config.batch_size;
+        let mut handles = Vec::new();
+        let context = Arc::new(BatchContext {
+            email: self.config.email.clone(),
+            credentials: self.credentials().await?.clone(),
+            provider: self.config.provider,
+            identity: self.config.identity.clone(),
+            server: self.server.clone(),
+            mailbox: plan.name.clone(),
+            uid_validity: plan.uid_validity,
+            store: self.store(plan.dir.clone(), catalog),
+            checkpoint: Arc::clone(checkpoint),
+            progress: self.progress(),
+            control: self.control(),
+            guard: self.guard.clone(),
+            pool: Arc::clone(&self.pool),
+            pacing: Arc::clone(&self.pacing),
+        });
+
+        log::info!(
+            "Fetching emails in batches of {} with {} concurrent connections...",
+            batch_size,
+            self.control.max_concurrent()
+        );
+
+        for batch in plan.uids.chunks(batch_size) {
+            let uids = batch.to_vec();
+            let (start, end) = (batch[0], batch[batch.len() - 1]);
+
+            let context = Arc::clone(&context);
+
+            let handle = tokio::spawn(async move {
+                match context.control.connection().await {
+                    Ok(_connection) => match run_batch(&uids, &context).await {
+                        Ok(count) => {
+                            log::info!(
+                                "Successfully fetched UIDs {} to {} ({} emails)",
+                                start,
+                                end,
+                                count
+                            );
+                            Ok(count)
+                        }
+                        Err(e) => {
+                            log::error!("Failed to fetch {}", e);
+                            context.progress.record_error(e.to_string());
+                            context.progress.add_failed_batch();
+                            Err(e)
+                        }
+                    },
+                    Err(e) => {
+                        log::error!("Failed to acquire a connection slot: {}", e);
+                        Err(e)
+                    }
+                }
+            });
+
+            handles.push((batch.to_vec(), handle));
+        }
+        Ok(handles)
+    }
+
+    /// Waits for the batches of `plan`, adding those that failed to
+    /// `failures`. Running out of space is returned as an error once every
+    /// batch is done.
+    async fn finish_batches(
+        &self,
+        plan: &MailboxPlan,
+        handles: Batches,
+        failures: &mut Vec<Failure>,
+    ) -> Result<(), ClientError> {
+        let mut total_fetched = 0;
+        let mut errors = 0;
+        let mut out_of_space = None;
+
+        for (uids, handle) in handles {
+            let error = match handle.await {
+                Ok(Ok(count)) => {
+                    total_fetched += count;
+                    continue;
+                }
+                Ok(Err(e)) => e,
+                Err(e) => {
+                    log::error!("Task join error: {}", e);
+                    ClientError::JoinError(Box::new(e))
+                }
+            };
+            errors += 1;
+            failures.push(Failure::new(
+                &plan.name,
+                &plan.dir,
+                plan.uid_validity,
+                uids,
+                &error,
+            ));
+            if matches!(error.root(), ClientError::InsufficientSpace { .. }) {
+                out_of_space.get_or_insert(error);
+            }
+        }
+
+        log::info!("Total emails fetched: {}", total_fetched);
+        if errors > 0 {
+            log::info!("Encountered {} errors during fetching", errors);
+        }
+
+        // Everything saved so far is in the catalog; stop here rather than
+        // moving on to the next mailbox
+        if let Some(e) = out_of_space {
+            return Err(e);
+        }
+
+        Ok(())
+    }
+}
+
+/// Fetches several accounts concurrently, each with its own connection pool
+/// and output directory, showing their progress as `mode` says while they
+/// run.
+pub async fn fetch_accounts(
+    clients: Vec<ImapClient>,
+    mode: ProgressMode,
+) -> Vec<(String, Result<FetchSummary, ClientError>)> {
+    let progress: Vec<Arc<Progress>> = clients.iter().map(|c| c.progress()).collect();
+    // Redrawing in place only works on a terminal
+    let bar = mode == ProgressMode::Bar && std::io::stdout().is_terminal();
+    let reporter = tokio::spawn(async move {
+        let every = Duration::from_secs(if bar { 1 } else { 5 });
+        loop {
+            sleep(every).await;
+            match mode {
+                ProgressMode::Off => {}
+                ProgressMode::Bar if bar => draw_status(&render_bar(&progress)),
+                ProgressMode::Bar => {
+                    println!("{}", tr!("progress", accounts = render_combined(&progress)))
+                }
+                ProgressMode::Plain => {
+                    println!("{}", tr!("progress", accounts = render_plain(&progress)))
+                }
+            }
+        }
+    });
+
+    let handles: Vec<_> = clients
+        .into_iter()
+        .map(|client| {
+            let account = client.config.email.clone();
+            (
+                account,
+                tokio::spawn(async move { client.fetch_all_emails().await }),
+            )
+        })
+        .collect();
+
+    let mut results = Vec::new();
+    for (account, handle) in handles {
+        let result = match handle.await {
+            Ok(result) => result,
+            Err(e) => Err(ClientError::JoinError(Box::new(e))),
+        };
+        results.push((account, result));
+    }
+
+    reporter.abort();
+    clear_status();
+    results
+}
+
+/// Refuses `--uids` unless exactly one of the `folders` selected: each
+/// folder numbers its messages on its own, so the same set would pick
+/// unrelated messages in the others.
+fn uids_need_one_folder(uids: Option<&SequenceSet>, folders: usize) -> Result<(), ClientError> {
+    if uids.is_some() && folders != 1 {
+        return Err(ClientError::InvalidArgument(format!(
+            "--uids needs exactly one folder, but {} are selected; pick one with --include",
+            folders
+        )));
+    }
+    Ok(())
+}
+
+/// Asks to sign in with `login` for an account configured for OAuth
+/// without a refresh token.
+fn no_refresh_token(email: &str) -> ClientError {
+    ClientError::ActionRequired {
+        message: format!("{} has no OAuth refresh token", email),
+        guidance: "Run `imap_client login` for the account to sign in, or set \
+                   oauth_refresh_token in the accounts file."
+            .to_string(),
+    }
+}
+
+/// Seconds since the Unix epoch.
+fn unix_time() -> i64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs() as i64)
+}
+
+/// The number of messages in a mailbox, from the `* n EXISTS` line of its
+/// SELECT or EXAMINE.
+fn exists_count(untagged: &[String]) -> u32 {
+    let mut count = 0;
+    for line in untagged {
+        let parts: Vec<&str> = line.split_whitespace().collect();
+        if parts.len() >= 2 && parts[1].eq_ignore_ascii_case("EXISTS") {
+            if let Ok(exists) = parts[0].parse::<u32>() {
+                count = exists;
+            }
+        }
+    }
+    count
+}
+
+/// INBOX, which every account has (RFC 3501 §5.1), without listing it.
+fn inbox() -> Mailbox {
+    Mailbox {
+        name: "INBOX".to_string(),
+        delimiter: None,
+        attributes: Vec::new(),
+    }
+}
+
+/// Groups the batches of a failure report back into mailboxes to fetch.
+fn retry_targets(failures: Vec<Failure>) -> Vec<Target> {
+    let mut targets: Vec<Target> = Vec::new();
+    for failure in failures {
+        let existing = targets.iter_mut().find(|target| {
+            target.name == failure.mailbox
+                && target.retry.as_ref().map(|(validity, _)| *validity)
+                    == Some(failure.uid_validity)
+        });
+        match existing {
+            Some(Target {
+                retry: Some((_, uids)),
+                ..
+            }) => uids.extend(failure.uids),
+            _ => targets.push(Target {
+                name: failure.mailbox,
+                dir: failure.dir,
+                retry: Some((failure.uid_validity, failure.uids.into_iter().collect())),
+            }),
+        }
+    }
+    targets
+}
+
+/// Where and how fetched messages are saved, whichever backend fetched them.
+pub(crate) struct Store {
+    pub(crate) dir_path: PathBuf,
+    format: OutputFormat,
+    line_endings: LineEndings,
+    threads: Option<ThreadMode>,
+    organize: Option<Organize>,
+    /// Views over a content-addressed store; when empty, messages are stored
+    /// as files in their folder's directory.
+    views: Vec<View>,
+    export_text: bool,
+    html_text: bool,
+    skip_duplicates: bool,
+    if_exists: ExistingFilePolicy,
+    pub(crate) min_free_space: u64,
+    archive_root: PathBuf,
+    pub(crate) catalog: Arc<Mutex<Catalog>>,
+    rules: RuleSet,
+    processors: Vec<Arc<dyn MessageProcessor>>,
+    drain: Option<Arc<Drain>>,
+    writer: Arc<FileWriter>,
+}
+
+impl Store {
+    /// Saves into `dir`, a directory under the archive root of `config`.
+    pub(crate) fn new(config: &ImapConfig, dir: PathBuf, catalog: Arc<Mutex<Catalog>>) -> Self {
+        Store {
+            dir_path: dir,
+            format: config.format,
+            line_endings: config.line_endings,
+            threads: config.threads,
+            organize: config.organize,
+            views: config.views.clone(),
+            export_text: config.export_text,
+            html_text: config.html_text,
+            skip_duplicates: config.skip_duplicates,
+            if_exists: config.if_exists,
+            min_free_space: config.min_free_space,
+            archive_root: config.dir_path.clone(),
+            catalog,
+            rules: config.rules.clone(),
+            processors: Vec::new(),
+            drain: None,
+            writer: Arc::new(FileWriter::Standard),
+        }
+    }
+
+    /// Directory a message with `headers` is stored in, creating it when
+    /// messages are organized by sender.
+    async fn message_dir(&self, headers: &Headers) -> Result<PathBuf, ClientError> {
+        let Some(organize) = self.organize else {
+            return Ok(self.dir_path.clone());
+        };
+        let dir = self
+            .dir_path
+            .join(organize.directory(headers.sender().as_ref()));
+        tokio::fs::create_dir_all(&dir).await?;
+        Ok(dir)
+    }
+
+    /// Line endings messages are stored with. They only matter for raw
+    /// messages; PDFs render from the original.
+    fn line_endings(&self) -> LineEndings {
+        match self.format {
+            OutputFormat::Eml | OutputFormat::Maildir => self.line_endings,
+            OutputFormat::Pdf => LineEndings::Keep,
+        }
+    }
+
+    fn batch_complete(&self, batch: &BatchInfo) {
+        for processor in &self.processors {
+            processor.on_batch_complete(batch);
+        }
+    }
+}
+
+/// Everything a batch task needs to open its own connection and save messages.
+struct BatchContext {
+    email: String,
+    credentials: Credentials,
+    provider: Provider,
+    identity: ClientIdentity,
+    server: String,
+    mailbox: String,
+    uid_validity: u32,
+    store: Store,
+    checkpoint: Arc<Mutex<Checkpoint>>,
+    progress: Arc<Progress>,
+    control: Arc<Control>,
+    guard: SessionGuard,
+    pool: Arc<SessionPool<ImapSession>>,
+    pacing: Arc<Pacing>,
+}
+
+/// Runs one batch once the fetch isn't paused, showing it as a connection's
+/// activity while it runs.
+async fn run_batch(uids: &[u32], context: &BatchContext) -> Result<u32, ClientError> {
+    context.control.wait_while_paused().await;
+    context.pacing.wait_turn().await;
+    let label = format!(
+        "{} UIDs {}:{}",
+        context.mailbox,
+        uids[0],
+        uids[uids.len() - 1]
+    );
+    let activity = context
+        .progress
+        .start_activity(format!("{}: connecting", label));
+    let mut state = BatchState::default();
+    let result = fetch_email_batch(uids, &mut state, context, activity, &label).await;
+    context.progress.end_activity(activity);
+    match &result {
+        Ok(_) => context.pacing.succeeded(),
+        Err(e) if is_throttle(e) => {
+            let backoff = context.pacing.throttled();
+            log::warn!(
+                "{}: the server is throttling {}; waiting {:?} more between batches",
+                label,
+                context.email,
+                backoff
+            );
+        }
+        Err(_) => {}
+    }
+    // What the batch saved is committed as one, whether or not it finished
+    let committed = lock(&context.checkpoint).and_then(|mut checkpoint| checkpoint.sync());
+    let failed = |error: ClientError, phase: Phase| ClientError::Batch {
+        context: ErrorContext {
+            mailbox: context.mailbox.clone(),
+            uids: (uids[0], uids[uids.len() - 1]),
+            phase,
+        },
+        source: Box::new(error),
+    };
+    let result = match (result, committed) {
+        (Ok(count), Ok(())) => Ok(count),
+        (Err(e), _) => Err(failed(e, state.phase)),
+        (Ok(_), Err(e)) => Err(failed(e, Phase::Commit)),
+    };
+    context.store.batch_complete(&BatchInfo {
+        mailbox: &context.mailbox,
+        messages: uids.len(),
+        error: result.as_ref().err(),
+    });
+    result
+}
+
+/// How many times a batch is restarted on another connection after losing
+/// sync with the server, or losing the connection partway through a message
+/// kept to resume, or at all through Tor.
+const BATCH_RESTARTS: u32 = 2;
+
+/// Messages of a batch handled so far, across connections.
+#[derive(Default)]
+struct BatchState {
+    done: HashSet<u32>,
+    saved: u32,
+    /// Archived messages that rules move, with the folder to move them to.
+    moves: Vec<(u32, String)>,
+    /// Archived messages that rules delete.
+    deletes: Vec<u32>,
+    /// Archived messages that rules flag, with the flag.
+    flags: Vec<(u32, String)>,
+    /// Archived messages that rules label, with the Gmail label.
+    labels: Vec<(u32, String)>,
+    /// Messages whose archived copy was verified for draining.
+    drained: Vec<u32>,
+    /// The message the last connection was lost partway through, if what
+    /// arrived of it was kept to resume.
+    kept: Option<u32>,
+    /// What the batch is doing, for the context of an error.
+    phase: Phase,
+}
+
+/// Fetches a batch, restarting it on another connection for the UIDs not
+/// yet handled if the response stream goes out of sync, or if the connection
+/// is lost partway through a large message, which is then resumed. Through
+/// Tor, whose circuits break now and then, any lost connection restarts it.
+async fn fetch_email_batch(
+    uids: &[u32],
+    state: &mut BatchState,
+    context: &BatchContext,
+    activity: u32,
+    label: &str,
+) -> Result<u32, ClientError> {
+    let mut attempt = 0;
+    loop {
+        let remaining: Vec<u32> = uids
+            .iter()
+            .copied()
+            .filter(|uid| !state.done.contains(uid))
+            .collect();
+        if remaining.is_empty() {
+            return Ok(state.saved);
+        }
+        state.kept = None;
+        match fetch_on_connection(&remaining, state, context, activity, label).await {
+            Ok(()) => return Ok(state.saved),
+            Err(e @ ClientError::Desync { .. }) if attempt < BATCH_RESTARTS => {
+                attempt += 1;
+                log::warn!(
+                    "{}: {}; retrying the remaining {} messages on another connection",
+                    label,
+                    e,
+                    uids.len() - state.done.len()
+                );
+            }
+            Err(e) if attempt < BATCH_RESTARTS && state.kept.is_some() => {
+                attempt += 1;
+                log::warn!(
+                    "{}: {}; resuming UID {} and the remaining {} messages on another connection",
+                    label,
+                    e,
+                    state.kept.unwrap_or_default(),
+                    uids.len() - state.done.len()
+                );
+            }
+            Err(e) if attempt < BATCH_RESTARTS && context.guard.tor.is_some() && is_lost(&e) => {
+                attempt += 1;
+                log::warn!(
+                    "{}: {}; retrying the remaining {} messages on another connection",
+                    label,
+                    e,
+                    uids.len() - state.done.len()
+                );
+            }
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Whether `error` is the connection failing, rather than the server
+/// refusing something.
+fn is_lost(error: &ClientError) -> bool {
+    matches!(
+        error,
+        ClientError::ConnectionError { .. } | ClientError::Io { .. }
+    )
+}
+
+/// Fetches a batch on an idle session from the pool, preferably one
+/// already in the batch's mailbox, or else on a new connection. The session
+/// goes back to the pool once the batch is done.
+async fn fetch_on_connection(
+    uids: &[u32],
+    state: &mut BatchState,
+    context: &BatchContext,
+    activity: u32,
+    label: &str,
+) -> Result<(), ClientError> {
+    // Don't start on a batch once the disk is nearly full
+    state.phase = Phase::Connect;
+    ensure_free_space(&context.store.dir_path, 0, context.store.min_free_space)?;
+
+    let reused = match context.pool.take(&context.mailbox) {
+        // NOOP checks an idle session is still alive; the server may have
+        // closed it in the meantime
+        Some((mut session, true)) => match session.execute("NOOP").await {
+            Ok(_) => Some(session),
+            Err(e) => {
+                log::debug!("{}: idle session is gone ({}), reconnecting", label, e);
+                None
+            }
+        },
+        Some((mut session, false)) => match open_mailbox(&mut session, context).await {
+            Ok(()) => Some(session),
+            Err(e) => {
+                log::debug!("{}: idle session is gone ({}), reconnecting", label, e);
+                None
+            }
+        },
+        None => None,
+    };
+    let mut session = match reused {
+        Some(session) => session,
+        None => {
+            let mut session = connect(&context.server, &context.guard).await?;
+            state.phase = Phase::Authenticate;
+            authenticate(
+                &mut session,
+                &context.email,
+                &context.credentials,
+                context.provider,
+            )
+            .await?;
+            identify(&mut session, &context.identity).await?;
+            state.phase = Phase::Select;
+            open_mailbox(&mut session, context).await?;
+            session
+        }
+    };
+
+    // Fetch emails in this batch, with Gmail's thread id when grouping by thread
+    // and with flags and labels for Maildir
+    state.phase = Phase::Fetch;
+    session.ensure_capabilities().await?;
+    let gmail = session.has_capability("X-GM-EXT-1");
+    let mut items = vec!["INTERNALDATE"];
+    if context.store.threads.is_some() && gmail {
+        items.push("X-GM-THRID");
+    }
+    if context.store.format == OutputFormat::Maildir {
+        items.push("FLAGS");
+        if gmail {
+            items.push("X-GM-LABELS");
+        }
+    }
+
+    // Finish the messages a lost connection left partly downloaded first
+    let kept = partial::pending(&context.store.dir_path, context.uid_validity, uids)?;
+    for part in &kept {
+        log::info!(
+            "{}: resuming UID {} from byte {} of {}",
+            label,
+            part.uid,
+            part.offset,
+            part.total
+        );
+        let tag = session
+            .send(&format!(
+                "UID FETCH {} ({} BODY.PEEK[]<{}.{}>)",
+                part.uid,
+                items.join(" "),
+                part.offset,
+                part.total - part.offset
+            ))
+            .await?;
+        process_batch_async(
+            &mut session,
+            &tag,
+            &[part.uid],
+            state,
+            context,
+            activity,
+            label,
+        )
+        .await?;
+    }
+    let uids: Vec<u32> = uids
+        .iter()
+        .copied()
+        .filter(|uid| !state.done.contains(uid))
+        .collect();
+
+    if !uids.is_empty() {
+        items.push("BODY.PEEK[]");
+        let tag = session
+            .send(&format!(
+                "UID FETCH {} ({})",
+                sequence_set(&uids),
+                items.join(" ")
+            ))
+            .await?;
+
+        // On a desync the session is dropped here: nothing more it sends can be trusted
+        process_batch_async(&mut session, &tag, &uids, state, context, activity, label).await?;
+    }
+    state.phase = Phase::Actions;
+    apply_rule_actions(&mut session, state, context).await?;
+
+    let limit = context.control.idle_limit();
+    if let Some(session) = context.pool.put(&context.mailbox, session, limit) {
+        session.logout().await?;
+    }
+
+    Ok(())
+}
+
+/// Keeps what arrived of the message a lost connection was cut off in, so
+/// the next connection can fetch just the rest.
+fn keep_interrupted(
+    interrupted: &Interrupted,
+    state: &mut BatchState,
+    context: &BatchContext,
+    label: &str,
+) {
+    match partial::keep(&context.store.dir_path, context.uid_validity, interrupted) {
+        Ok(Some(uid)) => {
+            log::info!(
+                "{}: kept {} bytes of UID {} to resume",
+                label,
+                interrupted.received.len(),
+                uid
+            );
+            state.kept = Some(uid);
+        }
+        Ok(None) => {}
+        Err(e) => log::warn!("{}: couldn't keep the interrupted message: {}", label, e),
+    }
 }
 
-impl ImapClient {
-    pub fn new(config: ImapConfig, server: String) -> Self {
-        ImapClient { config, server }
+/// Selects the batch's mailbox on `session`. EXAMINE opens it read-only, so
+/// fetching never marks mail as read; rules that move or delete, and
+/// draining, need it open read-write.
+async fn open_mailbox(
+    session: &mut ImapSession,
+    context: &BatchContext,
+) -> Result<(), ClientError> {
+    let open = if context.store.rules.modifies_account() || context.store.drain.is_some() {
+        "SELECT"
+    } else {
+        "EXAMINE"
+    };
+    session
+        .execute_args(&[
+            Arg::Raw(open),
+            Arg::String(&session.mailbox_name(&context.mailbox)),
+        ])
+        .await?;
+    Ok(())
+}
+
+/// Connects and reads the greeting. `server` is `host:port` for IMAP over TLS,
+/// or `imap://host:port` for an unencrypted local server such as the mock one.
+async fn connect(server: &str, guard: &SessionGuard) -> Result<ImapSession, ClientError> {
+    let stream: Box<dyn Transport> = match server.strip_prefix("imap://") {
+        Some(addr) => Box::new(connect_tcp(addr, guard).await?),
+        None => Box::new(create_tls_connection(server, guard).await?),
+    };
+    let mut session = Session::new(stream);
+    session.set_read_only(guard.read_only);
+    session.set_read_buffer(guard.read_buffer);
+    session.share_alerts(Arc::clone(&guard.alerts));
+    if let Some(log) = &guard.audit {
+        let connection = log.open_connection(server)?;
+        session.set_audit(Arc::clone(log), connection);
     }
+    if let Some(journal) = &guard.journal {
+        session.set_journal(Arc::clone(journal), journal.open_connection());
+    }
+    if let Some(trace) = &guard.trace {
+        session.set_trace(Arc::clone(trace), trace.open_connection(server));
+    }
+    let started = Instant::now();
+    let greeting = session.read_greeting().await?;
+    log::debug!("Server greeting: {}", greeting);
+    log::debug!(
+        target: CONNECTION_LOG,
+        "greeting after {:?}: {:?}",
+        started.elapsed(),
+        greeting
+    );
+    session.audit(&format!("greeting {}", greeting))?;
+    Ok(session)
+}
 
-    pub async fn fetch_all_emails(&self) -> Result<(), ClientError> {
-        println!("Gmail IMAP Email Fetcher (Async Version)");
-        println!("========================================");
+/// Connects to `server` (`host:port`) directly, or through Tor.
+async fn connect_tcp(server: &str, guard: &SessionGuard) -> Result<TcpStream, ClientError> {
+    match &guard.tor {
+        Some((proxy, isolation)) => net::connect_tor(*proxy, server, isolation).await,
+        None => net::connect(server, guard.ip_preference, &guard.resolver).await,
+    }
+}
 
-        log::info!(
-            "Using {} concurrent connections",
-            self.config.max_concurrent
-        );
+async fn create_tls_connection(
+    server: &str,
+    guard: &SessionGuard,
+) -> Result<TlsStream<TcpStream>, ClientError> {
+    // Establish TCP connection
+    let tcp_stream = connect_tcp(server, guard).await?;
 
-        // Step 1: Get email count
-        let email_count = self.get_email_count().await?;
+    // Set up TLS configuration
+    let config = match guard.tls_config.get() {
+        Some(config) => Arc::clone(config),
+        None => {
+            let config = Arc::new(tls::client_config(&guard.tls)?);
+            Arc::clone(guard.tls_config.get_or_init(|| config))
+        }
+    };
 
-        if email_count == 0 {
-            println!("No emails found in INBOX");
-            return Ok(());
+    let connector = TlsConnector::from(config);
+    let host = server.rsplit_once(':').map_or(server, |(host, _)| host);
+    let server_name = rustls::pki_types::ServerName::try_from(host)?.to_owned();
+    log::debug!(
+        target: CONNECTION_LOG,
+        "TLS handshake with {}, offering {}",
+        host,
+        tls::describe_options(&guard.tls)
+    );
+    let started = Instant::now();
+    let tls_stream = match connector.connect(server_name, tcp_stream).await {
+        Ok(stream) => stream,
+        Err(e) => {
+            log::debug!(
+                target: CONNECTION_LOG,
+                "TLS handshake failed after {:?}: {}",
+                started.elapsed(),
+                e
+            );
+            return Err(e.into());
         }
+    };
+    log::debug!(
+        target: CONNECTION_LOG,
+        "TLS handshake done in {:?}: {}",
+        started.elapsed(),
+        tls::describe_connection(tls_stream.get_ref().1)
+    );
 
-        println!("Found {} emails in INBOX", email_count);
+    Ok(tls_stream)
+}
 
-        // Step 2: Fetch emails concurrently
-        self.fetch_emails_concurrently(email_count).await?;
+async fn authenticate(
+    session: &mut ImapSession,
+    email: &str,
+    credentials: &Credentials,
+    provider: Provider,
+) -> Result<(), ClientError> {
+    let completion = match credentials {
+        Credentials::Password(password) => {
+            let tag = session
+                .send_args(&[Arg::Raw("LOGIN"), Arg::String(email), Arg::String(password)])
+                .await?;
+            session.wait_for(&tag).await?.1
+        }
+        Credentials::AccessToken(token) => session.authenticate_xoauth2(email, token).await?,
+    };
 
-        println!(
-            "Email fetching completed! All emails saved to: {}",
-            self.config.dir_path
-        );
+    if completion.status == Status::Ok {
+        session.audit_capabilities()?;
+        session.enable_utf8().await?;
         Ok(())
+    } else {
+        Err(auth_failure(&completion.text, provider))
+    }
+}
+
+/// Sends ID with the client's identity if the server supports it, returning
+/// the server's. A server refusing the command is not an error.
+async fn identify(
+    session: &mut ImapSession,
+    identity: &ClientIdentity,
+) -> Result<Option<ServerId>, ClientError> {
+    session.ensure_capabilities().await?;
+    let Some(command) = identity.command() else {
+        return Ok(None);
+    };
+    if !session.has_capability("ID") {
+        return Ok(None);
+    }
+    let tag = session.send(&command).await?;
+    let (untagged, completion) = session.wait_for(&tag).await?;
+    if completion.status != Status::Ok {
+        log::warn!("The server refused ID: {}", completion.text);
+        return Ok(None);
     }
+    Ok(untagged.iter().find_map(|line| parse_id_response(line)))
+}
 
-    async fn get_email_count(&self) -> Result<u32, ClientError> {
-        log::info!("Connecting to get email count...");
+/// Turns a rejected sign-in into an error, recognising the responses that the
+/// user has to act on (web sign-in, app passwords, IMAP turned off) so they
+/// get concrete guidance instead of a generic failure.
+pub fn auth_failure(text: &str, provider: Provider) -> ClientError {
+    let lower = text.to_ascii_lowercase();
+    let is_alert = lower.starts_with("[alert]") || lower.starts_with("[webalert");
+    let url = text
+        .split_whitespace()
+        .find(|word| word.starts_with("https://"))
+        .map(|word| word.trim_end_matches([')', ']', '.', ',']));
+    let message = match text.find(']') {
+        Some(end) if text.starts_with('[') => text[end + 1..].trim(),
+        _ => text.trim(),
+    };
 
-        let mut tls_stream = create_tls_connection(&self.server).await?;
-        authenticate(&mut tls_stream, &self.config.email, &self.config.password).await?;
-        send_inbox_cmd(&mut tls_stream).await?;
+    let app_passwords = provider
+        .app_passwords_url()
+        .map(|u| format!("at {}", u))
+        .unwrap_or_else(|| "in your account's security settings".to_string());
+    let guidance = if lower.contains("application-specific password") {
+        format!(
+            "This account requires an app password. Generate one {} and use it \
+             instead of your regular password.",
+            app_passwords
+        )
+    } else if lower.starts_with("[authenticationfailed]")
+        && provider != Provider::Gmail
+        && provider.app_passwords_url().is_some()
+    {
+        // Unlike Gmail, these providers don't say why; they only take app passwords
+        format!(
+            "{} only accepts app passwords over IMAP. Generate one {} and use it \
+             instead of your regular password.",
+            provider.display_name(),
+            app_passwords
+        )
+    } else if lower.contains("not enabled for imap") {
+        format!(
+            "IMAP access is turned off for this account. Turn it on in {}'s \
+             settings, then retry.",
+            provider.display_name()
+        )
+    } else if lower.contains("web browser") || lower.starts_with("[webalert") {
+        format!(
+            "{0} blocked this sign-in. Sign in to {0} from a web browser, \
+             confirm the activity if asked, then retry.{1}",
+            provider.display_name(),
+            url.map(|u| format!(" Details: {}", u)).unwrap_or_default()
+        )
+    } else if is_alert {
+        match url {
+            Some(u) => format!("Follow the instructions at {}", u),
+            None => format!(
+                "Check for security notifications from {}, then retry.",
+                provider.display_name()
+            ),
+        }
+    } else {
+        return ClientError::AuthenticationError(message.to_string());
+    };
 
-        let mut email_count = 0;
-        let mut response_buffer = Vec::new();
+    ClientError::ActionRequired {
+        message: message.to_string(),
+        guidance,
+    }
+}
 
-        loop {
-            let mut byte = [0; 1];
-            tls_stream.read_exact(&mut byte).await?;
-            response_buffer.push(byte[0]);
+/// One message received in a FETCH response.
+pub(crate) struct FetchedMessage {
+    pub(crate) seq: u32,
+    pub(crate) body: Vec<u8>,
+    /// INTERNALDATE as a Unix timestamp.
+    pub(crate) internal_date: Option<i64>,
+    pub(crate) gmail_thread_id: Option<String>,
+    /// File name a processor gave it, without an extension.
+    pub(crate) name: Option<String>,
+    /// Hash of the message as downloaded, when processors changed it.
+    pub(crate) original_hash: Option<String>,
+    /// IMAP flags, such as `\Seen`, when they were fetched.
+    pub(crate) flags: Vec<String>,
+    /// Gmail labels (X-GM-LABELS), when they were fetched.
+    pub(crate) labels: Vec<String>,
+}
 
-            if response_buffer.len() >= 2
-                && response_buffer[response_buffer.len() - 2] == b'\r'
-                && response_buffer[response_buffer.len() - 1] == b'\n'
-            {
-                let response = String::from_utf8_lossy(&response_buffer);
-
-                // Parse email count from "* XXXX EXISTS" line
-                if response.contains("EXISTS") {
-                    let parts: Vec<&str> = response.split_whitespace().collect();
-                    if parts.len() >= 2 {
-                        if let Ok(count) = parts[1].parse::<u32>() {
-                            email_count = count;
-                        }
+/// Saves the messages of a `UID FETCH` of `uids` as they arrive, recording
+/// each handled UID in `state`.
+///
+/// Every response is checked against what was asked for: it must parse, carry
+/// a requested UID, and not repeat one. Anything else means the stream is out
+/// of sync (a miscounted literal, say), and the batch stops with
+/// [`ClientError::Desync`] before a misread message is saved.
+async fn process_batch_async(
+    session: &mut ImapSession,
+    fetch_tag: &str,
+    uids: &[u32],
+    state: &mut BatchState,
+    context: &BatchContext,
+    activity: u32,
+    label: &str,
+) -> Result<(), ClientError> {
+    let requested: HashSet<u32> = uids.iter().copied().collect();
+    let mut offset: u64 = 0;
+
+    loop {
+        let raw = match session.read_response().await {
+            Ok(raw) => raw,
+            Err(e) => {
+                if let Some(interrupted) = session.take_interrupted() {
+                    keep_interrupted(&interrupted, state, context, label);
+                }
+                return Err(e);
+            }
+        };
+        let start = offset;
+        offset += raw.len() as u64;
+        let desync = |reason: String| ClientError::Desync {
+            offset: start,
+            reason,
+        };
+
+        let parsed = parse_response(&raw).map_err(|e| desync(e.to_string()))?;
+        let fetch = match parsed {
+            (Parsed::Fetch(fetch), _) => fetch,
+            (Parsed::Other(line), _) => {
+                let line = String::from_utf8_lossy(line).trim().to_string();
+                let response = match session.classify(line) {
+                    Ok(response) => response,
+                    Err(ClientError::ImapError(reason)) => return Err(desync(reason)),
+                    Err(e) => return Err(e),
+                };
+                if let Response::Tagged(completion) = response {
+                    if completion.tag != fetch_tag {
+                        continue;
                     }
+                    return match completion.status {
+                        Status::Ok => Ok(()),
+                        _ => Err(ClientError::CommandFailed {
+                            tag: completion.tag,
+                            command: completion.command,
+                            text: completion.text,
+                        }),
+                    };
                 }
+                continue;
+            }
+        };
 
-                if response.starts_with("A002") {
-                    if response.contains("OK") {
-                        break;
-                    } else {
-                        return Err(ClientError::ImapError("Failed to select INBOX".to_string()));
+        // Unsolicited FETCH responses (e.g. flag changes) carry no body
+        if !fetch.has_body() {
+            continue;
+        }
+        let uid = match fetch.uid() {
+            Some(uid) if !requested.contains(&uid) => {
+                return Err(desync(format!("got UID {}, which was not requested", uid)))
+            }
+            Some(uid) if state.done.contains(&uid) => {
+                return Err(desync(format!("UID {} was sent twice", uid)))
+            }
+            Some(uid) => uid,
+            None => return Err(desync(format!("message {} has no UID", fetch.seq))),
+        };
+        // Gmail answers NIL or an empty literal for messages it can't serve;
+        // leave them out of the checkpoint so a later run tries again
+        let body = match fetch.body() {
+            Some(body) if !body.is_empty() => body,
+            _ => {
+                log::warn!(
+                    "{}: server returned no content for UID {}, skipping",
+                    context.mailbox,
+                    uid
+                );
+                state.done.insert(uid);
+                context.progress.add_skipped(1);
+                context.progress.add_folder_done(&context.mailbox, 1);
+                continue;
+            }
+        };
+        context.control.transfer(body.len()).await;
+        let mut message = fetched_message(&fetch, session.utf8_enabled());
+        let origin = fetch.body_origin();
+        // A literal body is cut out of the response rather than copied
+        let body = match subslice_range(&raw, body) {
+            Some(range) => carve(raw, range),
+            None => body.to_vec(),
+        };
+        // The rest of a message follows the part kept from a lost connection
+        message.body = match origin {
+            0 => body,
+            origin => {
+                match partial::take(&context.store.dir_path, context.uid_validity, uid, origin)? {
+                    Some(mut kept) => {
+                        kept.extend_from_slice(&body);
+                        kept
+                    }
+                    None => {
+                        return Err(desync(format!(
+                            "got UID {} from byte {}, but no part of it is kept",
+                            uid, origin
+                        )))
                     }
                 }
-                response_buffer.clear();
             }
+        };
+        if context.control.is_paused() {
+            context
+                .progress
+                .set_activity(activity, format!("{}: paused", label));
+            context.control.wait_while_paused().await;
         }
-
-        logout_imap(tls_stream).await?;
-
-        Ok(email_count)
+        state.phase = Phase::Save;
+        let (saved, action) = file_message(&context.store, &context.mailbox, &message).await?;
+        match saved {
+            Some(path) => {
+                session.audit(&format!(
+                    "saved {} UID {} sha256 {} {} bytes as {}",
+                    context.mailbox,
+                    uid,
+                    sha256_hex(&message.body),
+                    message.body.len(),
+                    relative_path(&context.store.archive_root, &path)
+                ))?;
+                state.saved += 1;
+                context.progress.add_saved(1);
+                context.progress.add_folder_done(&context.mailbox, 1);
+            }
+            None => {
+                session.audit(&format!("skipped {} UID {}", context.mailbox, uid))?;
+                context.progress.add_skipped(1);
+                context.progress.add_folder_done(&context.mailbox, 1);
+            }
+        }
+        // Whether saved now or already archived, the message is safe to move
+        let removed_by_rule = matches!(action, Action::Move(_) | Action::Delete);
+        match action {
+            Action::Move(folder) => state.moves.push((uid, folder)),
+            Action::Delete => state.deletes.push(uid),
+            Action::Flag(flag) => state.flags.push((uid, flag)),
+            Action::AddLabel(label) => state.labels.push((uid, label)),
+            _ => {}
+        }
+        if let Some(drain) = &context.store.drain {
+            if !removed_by_rule
+                && drain_message(drain, &context.store, &context.mailbox, uid, &message).await?
+            {
+                state.drained.push(uid);
+            }
+        }
+        state.done.insert(uid);
+        context
+            .progress
+            .set_activity(activity, format!("{}: UID {} done", label, uid));
+        lock(&context.checkpoint)?.record(&context.mailbox, context.uid_validity, uid)?;
+        state.phase = Phase::Fetch;
     }
+}
 
-    async fn fetch_emails_concurrently(&self, email_count: u32) -> Result<(), ClientError> {
-        let batch_size = 500;
-        let semaphore = Arc::new(Semaphore::new(self.config.max_concurrent));
-        let mut handles = Vec::new();
+/// What `fetch` says about its message, all but the body. Labels are UTF-8
+/// once `utf8` is enabled, and modified UTF-7 before.
+fn fetched_message(fetch: &Fetch, utf8: bool) -> FetchedMessage {
+    FetchedMessage {
+        seq: fetch.seq,
+        body: Vec::new(),
+        internal_date: fetch
+            .get("INTERNALDATE")
+            .and_then(Value::as_bytes)
+            .and_then(|date| parse_internal_date(&String::from_utf8_lossy(date))),
+        gmail_thread_id: match fetch.get("X-GM-THRID") {
+            Some(Value::Atom(id)) => Some(id.to_string()),
+            _ => None,
+        },
+        name: None,
+        original_hash: None,
+        flags: fetch_strings(fetch.get("FLAGS")),
+        labels: fetch_strings(fetch.get("X-GM-LABELS"))
+            .into_iter()
+            .map(|label| match utf8 {
+                true => label,
+                false => decode_mailbox_name(&label),
+            })
+            .collect(),
+    }
+}
 
-        log::info!(
-            "Fetching emails in batches of {} with {} concurrent connections...",
-            batch_size,
-            self.config.max_concurrent
-        );
+/// Where `part` lies within `whole`, if it is one of its slices.
+fn subslice_range(whole: &[u8], part: &[u8]) -> Option<Range<usize>> {
+    let start = (part.as_ptr() as usize).checked_sub(whole.as_ptr() as usize)?;
+    let end = start + part.len();
+    (end <= whole.len()).then_some(start..end)
+}
 
-        for start in (1..=email_count).step_by(batch_size as usize) {
-            let end = std::cmp::min(start + batch_size - 1, email_count);
+/// `bytes[range]`, keeping the allocation of `bytes`.
+fn carve(mut bytes: Vec<u8>, range: Range<usize>) -> Vec<u8> {
+    bytes.truncate(range.end);
+    bytes.drain(..range.start);
+    bytes
+}
 
-            let semaphore = Arc::clone(&semaphore);
-            let email = self.config.email.clone();
-            let password = self.config.password.clone();
-            let dir_path = self.config.dir_path.clone();
-            let server = self.server.clone();
+/// Fetches the raw bytes of one message of the selected mailbox by UID.
+async fn fetch_message_body(session: &mut ImapSession, uid: u32) -> Result<Vec<u8>, ClientError> {
+    let tag = session
+        .send(&format!("UID FETCH {} (BODY.PEEK[])", uid))
+        .await?;
 
-            let handle = tokio::spawn(async move {
-                match semaphore.acquire().await {
-                    Ok(_permit) => {
-                        match fetch_email_batch(start, end, &email, &password, &dir_path, &server)
-                            .await
-                        {
-                            Ok(count) => {
-                                log::info!(
-                                    "Successfully fetched emails {} to {} ({} emails)",
-                                    start,
-                                    end,
-                                    count
-                                );
-                                Ok::<u32, String>(count)
-                            }
-                            Err(e) => {
-                                log::error!("Failed to fetch emails {} to {}: {}", start, end, e);
-                                Err(e.to_string())
-                            }
-                        }
-                    }
-                    Err(e) => {
-                        log::error!("Failed to acquire semaphore permit: {}", e);
-                        Err(e.to_string())
-                    }
+    let mut body = None;
+    loop {
+        let raw = session.read_response().await?;
+        let line = match parse_response(&raw)? {
+            (Parsed::Fetch(fetch), _) => {
+                if fetch.uid() == Some(uid) {
+                    body = fetch.body().filter(|b| !b.is_empty()).map(<[u8]>::to_vec);
                 }
-            });
-
-            handles.push(handle);
+                continue;
+            }
+            (Parsed::Other(line), _) => String::from_utf8_lossy(line).trim().to_string(),
+        };
 
-            // Small delay to avoid overwhelming the server
-            sleep(Duration::from_millis(50)).await;
+        if let Response::Tagged(completion) = session.classify(line)? {
+            if completion.tag != tag {
+                continue;
+            }
+            return match (completion.status, body) {
+                (Status::Ok, Some(body)) => Ok(body),
+                (Status::Ok, None) => Err(ClientError::ImapError(format!(
+                    "No body returned for UID {}",
+                    uid
+                ))),
+                _ => Err(ClientError::CommandFailed {
+                    tag: completion.tag,
+                    command: completion.command,
+                    text: completion.text,
+                }),
+            };
         }
+    }
+}
 
-        // Wait for all batches to complete
-        let mut total_fetched = 0;
-        let mut errors = 0;
+/// The atoms and strings of a FETCH list such as `FLAGS (\Seen \Flagged)`.
+fn fetch_strings(value: Option<&Value>) -> Vec<String> {
+    let Some(Value::List(values)) = value else {
+        return Vec::new();
+    };
+    values
+        .iter()
+        .filter_map(|value| match value {
+            Value::Atom(atom) => Some(atom.to_string()),
+            value => value
+                .as_bytes()
+                .map(|bytes| String::from_utf8_lossy(bytes).into_owned()),
+        })
+        .collect()
+}
 
-        for handle in handles {
-            match handle.await {
-                Ok(Ok(count)) => total_fetched += count,
-                Ok(Err(_)) => errors += 1,
-                Err(e) => {
-                    log::error!("Task join error: {}", e);
-                    errors += 1;
-                }
+/// Value of a simple FETCH item (e.g. `X-GM-THRID 1278455344230334865`).
+fn fetch_item<'a>(response: &'a str, name: &str) -> Option<&'a str> {
+    let mut words = response.split(|c: char| c.is_whitespace() || c == '(');
+    words.find(|word| word.eq_ignore_ascii_case(name))?;
+    words.next().map(|value| value.trim_end_matches(')'))
+}
+
+/// The quoted value of FETCH item `name` in `response`, such as the date in
+/// `INTERNALDATE "17-Jul-1996 02:44:25 -0700"`.
+fn quoted_fetch_item<'a>(response: &'a str, name: &str) -> Option<&'a str> {
+    let upper = response.to_ascii_uppercase();
+    let start = upper.find(&format!("{} \"", name.to_ascii_uppercase()))? + name.len() + 2;
+    let end = response[start..].find('"')?;
+    Some(&response[start..start + end])
+}
+
+/// Handles one message of `mailbox` as the rules say: saves it, its
+/// attachments alone or nothing, after the store's processors have rewritten
+/// it. Returns where it was saved, as [`save_message`] does, and the rule's
+/// action.
+pub(crate) async fn file_message(
+    store: &Store,
+    mailbox: &str,
+    message: &FetchedMessage,
+) -> Result<(Option<PathBuf>, Action), ClientError> {
+    let info = MessageInfo {
+        mailbox,
+        seq: message.seq,
+        size: message.body.len(),
+        internal_date: message.internal_date,
+    };
+    for processor in &store.processors {
+        processor.on_message_start(&info);
+    }
+    let action = rule_action(store, mailbox, message);
+    if action == Action::Skip {
+        return Ok((None, action));
+    }
+    let processed;
+    let message = if store.processors.is_empty() {
+        message
+    } else {
+        match process(store, &info, message).await? {
+            Some(message) => {
+                processed = message;
+                &processed
             }
+            None => {
+                log::info!("Skipping email {}: skipped by a processor", message.seq);
+                return Ok((None, Action::Skip));
+            }
+        }
+    };
+    let saved = if action == Action::AttachmentsOnly {
+        save_attachments(store, message).await?
+    } else {
+        save_message(store, message).await?
+    };
+    if let Some(path) = &saved {
+        for processor in &store.processors {
+            processor.on_message_saved(&info, path);
         }
+    }
+    Ok((saved, action))
+}
 
-        log::info!("Total emails fetched: {}", total_fetched);
-        if errors > 0 {
-            log::info!("Encountered {} errors during fetching", errors);
+/// `message` as the store's processors make of it, or `None` if one of them
+/// skips it. They run on a worker thread, as plugins may take a while.
+async fn process(
+    store: &Store,
+    info: &MessageInfo<'_>,
+    message: &FetchedMessage,
+) -> Result<Option<FetchedMessage>, ClientError> {
+    let processors = store.processors.clone();
+    let mailbox = info.mailbox.to_string();
+    let (seq, size, internal_date) = (info.seq, info.size, info.internal_date);
+    let body = message.body.clone();
+    let processed = workers::run(move || {
+        let info = MessageInfo {
+            mailbox: &mailbox,
+            seq,
+            size,
+            internal_date,
+        };
+        run_processors(&processors, &info, body)
+    })
+    .await??;
+    let Processed::Save { body, name } = processed else {
+        return Ok(None);
+    };
+    let original_hash =
+        (body != message.body).then(|| sha256_hex(&store.line_endings().apply(&message.body)));
+    Ok(Some(FetchedMessage {
+        seq: message.seq,
+        body,
+        internal_date: message.internal_date,
+        gmail_thread_id: message.gmail_thread_id.clone(),
+        name,
+        original_hash,
+        flags: message.flags.clone(),
+        labels: message.labels.clone(),
+    }))
+}
+
+/// What `processors` make of `body`, in turn.
+fn run_processors(
+    processors: &[Arc<dyn MessageProcessor>],
+    info: &MessageInfo,
+    mut body: Vec<u8>,
+) -> Result<Processed, ClientError> {
+    let mut name = None;
+    for processor in processors {
+        match processor.process(info, body)? {
+            Processed::Save {
+                body: processed,
+                name: renamed,
+            } => {
+                body = processed;
+                name = renamed.or(name);
+            }
+            Processed::Skip => return Ok(Processed::Skip),
         }
+    }
+    Ok(Processed::Save { body, name })
+}
 
-        Ok(())
+/// The action of the first rule `message` of `mailbox` matches; messages
+/// matching none are saved.
+fn rule_action(store: &Store, mailbox: &str, message: &FetchedMessage) -> Action {
+    if store.rules.is_empty() {
+        return Action::Save;
     }
+    let headers = Headers::parse(&message.body);
+    let sender = headers.sender();
+    let subject = headers.subject().unwrap_or_default();
+    let facts = MessageFacts::new(
+        &headers,
+        sender.as_ref().map(|address| address.email.as_str()),
+        &subject,
+        mailbox,
+        message.body.len(),
+        message.internal_date,
+    );
+    let Some(rule) = store.rules.matching(&facts) else {
+        return Action::Save;
+    };
+    log::debug!(
+        "Email {} ({}) matches rule on line {}: {}",
+        message.seq,
+        subject,
+        rule.line,
+        rule.action
+    );
+    if rule.action == Action::Skip {
+        log::info!(
+            "Skipping email {} ({}): rule on line {}",
+            message.seq,
+            subject,
+            rule.line
+        );
+    }
+    rule.action.clone()
 }
 
-async fn fetch_email_batch(
-    start: u32,
-    end: u32,
-    email: &str,
-    password: &str,
-    dir_path: &str,
-    server: &str,
-) -> Result<u32, ClientError> {
-    // Create a new connection for this batch
-    let mut tls_stream = create_tls_connection(server).await?;
-    authenticate(&mut tls_stream, email, password).await?;
-    select_inbox(&mut tls_stream).await?;
+/// Saves the attachments of a message into `email_NNNNN.attachments/`,
+/// returning that directory, or `None` if the message has none.
+async fn save_attachments(
+    store: &Store,
+    message: &FetchedMessage,
+) -> Result<Option<PathBuf>, ClientError> {
+    let mut attachments = Vec::new();
+    Part::parse(&message.body).walk(&mut |part| {
+        if part.is_attachment() {
+            attachments.push((attachment_name(part), part.decoded_body()));
+        }
+    });
+    if attachments.is_empty() {
+        log::info!("Email {} has no attachments to save", message.seq);
+        return Ok(None);
+    }
+
+    let dir = store
+        .message_dir(&Headers::parse(&message.body))
+        .await?
+        .join(format!("{}.attachments", message_stem(message)));
+    tokio::fs::create_dir_all(&dir).await?;
+    let mut used = HashSet::new();
+    for (index, (name, body)) in attachments.into_iter().enumerate() {
+        let name = sanitize_filename(&name.unwrap_or_else(|| format!("attachment_{}", index + 1)));
+        // Two attachments of one message may share a name
+        let name = match used.insert(name.clone()) {
+            true => name,
+            false => format!("{}_{}", index + 1, name),
+        };
+        ensure_free_space(&dir, body.len() as u64, store.min_free_space)?;
+        tokio::fs::write(dir.join(&name), &body).await?;
+        set_received_time(&dir.join(&name), message.internal_date);
+    }
+    log::info!(
+        "Saved the attachments of email {} to {}",
+        message.seq,
+        dir.display()
+    );
+    Ok(Some(dir))
+}
 
-    // Fetch emails in this batch
-    let fetch_cmd = format!("A003 FETCH {}:{} (BODY[])\r\n", start, end);
-    tls_stream.write_all(fetch_cmd.as_bytes()).await?;
-    tls_stream.flush().await?;
+/// File name of an attachment, from Content-Disposition or else Content-Type.
+fn attachment_name(part: &Part) -> Option<String> {
+    let disposition = part
+        .headers
+        .get("Content-Disposition")
+        .map(ContentType::parse);
+    let content_type = part.content_type();
+    let name = disposition
+        .as_ref()
+        .and_then(|d| d.param("filename"))
+        .or_else(|| content_type.param("name"))?;
+    let name = decode_encoded_words(name);
+    (!name.trim().is_empty()).then_some(name)
+}
 
-    let emails_saved = process_batch_async(&mut tls_stream, dir_path).await?;
+/// Flags, labels, moves and deletes the messages of a batch that rules asked
+/// for, now that they are in the archive.
+async fn apply_rule_actions(
+    session: &mut ImapSession,
+    state: &mut BatchState,
+    context: &BatchContext,
+) -> Result<(), ClientError> {
+    let mailbox = &context.mailbox;
+    for (flag, uids) in group_by_value(std::mem::take(&mut state.flags)) {
+        store_flag(session, &uids, true, &flag).await?;
+        log::info!("{}: flagged {} messages {}", mailbox, uids.len(), flag);
+    }
+    for (label, uids) in group_by_value(std::mem::take(&mut state.labels)) {
+        store_label(session, &uids, true, &label).await?;
+        log::info!("{}: labelled {} messages {}", mailbox, uids.len(), label);
+    }
 
-    logout_imap(tls_stream).await?;
+    for (folder, uids) in group_by_value(std::mem::take(&mut state.moves)) {
+        move_messages(session, &uids, &folder).await?;
+        log::info!("{}: moved {} messages to {}", mailbox, uids.len(), folder);
+    }
 
-    Ok(emails_saved)
-}
+    let deletes = std::mem::take(&mut state.deletes);
+    if !deletes.is_empty() {
+        delete_messages(session, &deletes).await?;
+        log::info!("{}: deleted {} messages", mailbox, deletes.len());
+    }
 
-async fn logout_imap(mut tls_stream: TlsStream<TcpStream>) -> Result<(), ClientError> {
-    let logout_cmd = "A999 LOGOUT\r\n";
-    tls_stream.write_all(logout_cmd.as_bytes()).await?;
-    tls_stream.flush().await?;
+    let drained = std::mem::take(&mut state.drained);
+    if let (Some(drain), false) = (&context.store.drain, drained.is_empty()) {
+        delete_messages(session, &drained).await?;
+        let outcome = if session.has_capability("UIDPLUS") {
+            "deleted"
+        } else {
+            "flagged deleted"
+        };
+        for uid in &drained {
+            drain.record(&format!("{} {} UID {}", outcome, mailbox, uid))?;
+        }
+        log::info!("{}: drained {} messages", mailbox, drained.len());
+    }
     Ok(())
 }
 
-async fn create_tls_connection(server: &str) -> Result<TlsStream<TcpStream>, ClientError> {
-    // Establish TCP connection
-    let tcp_stream = TcpStream::connect(server).await?;
-
-    // Set up TLS configuration
-    let root_store = rustls::RootCertStore {
-        roots: webpki_roots::TLS_SERVER_ROOTS.into(),
+/// Journals `message`, just fetched as `uid`, through the drain and checks
+/// its archived copy. Returns whether it can be deleted from the server;
+/// when it can't, the journal says why.
+async fn drain_message(
+    drain: &Drain,
+    store: &Store,
+    mailbox: &str,
+    uid: u32,
+    message: &FetchedMessage,
+) -> Result<bool, ClientError> {
+    drain.record(&format!(
+        "fetched {} UID {} sha256 {} {} bytes",
+        mailbox,
+        uid,
+        sha256_hex(&message.body),
+        message.body.len()
+    ))?;
+    let hash = sha256_hex(&store.line_endings().apply(&message.body));
+    let copy = lock(&store.catalog)?.copy_of(&hash).cloned();
+    let reason = match copy {
+        None => "not in the archive".to_string(),
+        Some(entry) => match drain.verify(&store.archive_root, &entry) {
+            Err(reason) => reason,
+            Ok(()) => {
+                drain.record(&format!(
+                    "verified {} UID {} as {}",
+                    mailbox, uid, entry.path
+                ))?;
+                match drain.check_replica(&store.archive_root, &entry).await {
+                    Ok(checked) => {
+                        if checked {
+                            drain.record(&format!("replicated {} UID {}", mailbox, uid))?;
+                        }
+                        return Ok(true);
+                    }
+                    Err(reason) => reason,
+                }
+            }
+        },
     };
-    let config = rustls::ClientConfig::builder()
-        .with_root_certificates(root_store)
-        .with_no_client_auth();
+    drain.record(&format!("kept {} UID {}: {}", mailbox, uid, reason))?;
+    log::warn!("{}: keeping UID {} on the server: {}", mailbox, uid, reason);
+    Ok(false)
+}
 
-    let connector = TlsConnector::from(Arc::new(config));
-    let server_name = rustls::pki_types::ServerName::try_from("imap.gmail.com")?;
-    let tls_stream = connector.connect(server_name, tcp_stream).await?;
+/// UIDs of the messages in the selected mailbox that match IMAP search keys
+/// `search` and lie in `uids`, or all of them when neither is given.
+async fn search_uids(
+    session: &mut ImapSession,
+    search: Option<&str>,
+    uids: Option<&SequenceSet>,
+) -> Result<Vec<u32>, ClientError> {
+    let search = search.map(str::trim).filter(|search| !search.is_empty());
+    if search.is_some_and(|search| search.contains(['\r', '\n'])) {
+        return Err(ClientError::InvalidArgument(
+            "search keys must be on one line".to_string(),
+        ));
+    }
+    let mut command = "UID SEARCH".to_string();
+    if search.is_some_and(|search| !search.is_ascii()) && !session.utf8_enabled() {
+        command.push_str(" CHARSET UTF-8");
+    }
+    if let Some(uids) = uids {
+        command.push_str(&format!(" UID {}", uids));
+    }
+    match search {
+        Some(search) => command.push_str(&format!(" {}", search)),
+        None if uids.is_none() => command.push_str(" ALL"),
+        None => {}
+    }
+    let untagged = session.execute(&command).await?;
+    Ok(untagged
+        .iter()
+        .filter_map(|line| line.strip_prefix("SEARCH"))
+        .flat_map(str::split_whitespace)
+        .filter_map(|uid| uid.parse().ok())
+        .collect())
+}
 
-    Ok(tls_stream)
+/// The Message-ID of each of `uids` in the selected mailbox, `None` for
+/// messages without one. UIDs the server doesn't return are left out.
+async fn fetch_message_ids(
+    session: &mut ImapSession,
+    uids: &[u32],
+) -> Result<Vec<(u32, Option<String>)>, ClientError> {
+    Ok(fetch_headers(session, uids, false)
+        .await?
+        .into_iter()
+        .map(|message| (message.uid, message.message_id))
+        .collect())
 }
 
-async fn authenticate(
-    tls_stream: &mut TlsStream<TcpStream>,
-    email: &str,
-    password: &str,
-) -> Result<(), ClientError> {
-    // Read initial server greeting
-    let mut buffer = [0; 1024];
-    tls_stream.read(&mut buffer).await?;
+/// What a FETCH without the body tells about one message.
+struct ServerMessage {
+    uid: u32,
+    message_id: Option<String>,
+    flags: Vec<String>,
+}
 
-    send_login_cmd(tls_stream, email, password).await?;
+/// The Message-ID, and with `with_flags` the flags, of each of `uids` in
+/// the selected mailbox, without downloading bodies. UIDs the server
+/// doesn't return are left out.
+async fn fetch_headers(
+    session: &mut ImapSession,
+    uids: &[u32],
+    with_flags: bool,
+) -> Result<Vec<ServerMessage>, ClientError> {
+    if uids.is_empty() {
+        return Ok(Vec::new());
+    }
+    let tag = session
+        .send(&format!(
+            "UID FETCH {} (UID {}BODY.PEEK[HEADER.FIELDS (MESSAGE-ID)])",
+            sequence_set(uids),
+            if with_flags { "FLAGS " } else { "" }
+        ))
+        .await?;
 
-    // Read LOGIN response
-    let mut response_buffer = Vec::new();
+    let wanted: HashSet<u32> = uids.iter().copied().collect();
+    let mut ids = Vec::new();
     loop {
-        let mut byte = [0; 1];
-        tls_stream.read_exact(&mut byte).await?;
-        response_buffer.push(byte[0]);
-
-        if response_buffer.len() >= 2
-            && response_buffer[response_buffer.len() - 2] == b'\r'
-            && response_buffer[response_buffer.len() - 1] == b'\n'
-        {
-            let response = String::from_utf8_lossy(&response_buffer);
-
-            if response.starts_with("A001") {
-                if response.contains("OK") {
-                    return Ok(());
-                } else {
-                    return Err(ClientError::AuthenticationError(
-                        "Authentication failed".to_string(),
-                    ));
+        let raw = session.read_response().await?;
+        let line = match parse_response(&raw)? {
+            (Parsed::Fetch(fetch), _) => {
+                let header = fetch
+                    .items
+                    .iter()
+                    .find(|(name, _)| name.to_ascii_uppercase().starts_with("BODY["))
+                    .and_then(|(_, value)| value.as_bytes());
+                if let Some(uid) = fetch.uid().filter(|uid| wanted.contains(uid)) {
+                    let message_id = header
+                        .and_then(|header| Headers::parse(header).message_id().map(str::to_string));
+                    ids.push(ServerMessage {
+                        uid,
+                        message_id,
+                        flags: fetch_strings(fetch.get("FLAGS")),
+                    });
                 }
+                continue;
+            }
+            (Parsed::Other(line), _) => String::from_utf8_lossy(line).trim().to_string(),
+        };
+
+        if let Response::Tagged(completion) = session.classify(line)? {
+            if completion.tag != tag {
+                continue;
             }
-            response_buffer.clear();
+            return match completion.status {
+                Status::Ok => Ok(ids),
+                _ => Err(ClientError::CommandFailed {
+                    tag: completion.tag,
+                    command: completion.command,
+                    text: completion.text,
+                }),
+            };
         }
     }
 }
 
-async fn send_login_cmd(
-    tls_stream: &mut TlsStream<TcpStream>,
-    email: &str,
-    password: &str,
+/// UIDs grouped by the value paired with them, such as the flag to set.
+fn group_by_value(pairs: Vec<(u32, String)>) -> BTreeMap<String, Vec<u32>> {
+    let mut groups: BTreeMap<String, Vec<u32>> = BTreeMap::new();
+    for (uid, value) in pairs {
+        groups.entry(value).or_default().push(uid);
+    }
+    groups
+}
+
+/// Sets (or with `add` false, clears) `flag` on messages of the selected
+/// mailbox.
+pub(crate) async fn store_flag(
+    session: &mut ImapSession,
+    uids: &[u32],
+    add: bool,
+    flag: &str,
 ) -> Result<(), ClientError> {
-    let login_cmd = format!("A001 LOGIN {} {}\r\n", email, password);
-    tls_stream.write_all(login_cmd.as_bytes()).await?;
-    tls_stream.flush().await?;
+    session
+        .execute(&format!(
+            "UID STORE {} {}FLAGS.SILENT ({})",
+            sequence_set(uids),
+            if add { '+' } else { '-' },
+            flag
+        ))
+        .await?;
     Ok(())
 }
 
-async fn select_inbox(tls_stream: &mut TlsStream<TcpStream>) -> Result<(), ClientError> {
-    send_inbox_cmd(tls_stream).await?;
+/// Adds (or with `add` false, removes) Gmail label `label` on messages of
+/// the selected mailbox, with X-GM-LABELS.
+pub(crate) async fn store_label(
+    session: &mut ImapSession,
+    uids: &[u32],
+    add: bool,
+    label: &str,
+) -> Result<(), ClientError> {
+    session.ensure_capabilities().await?;
+    if !session.has_capability("X-GM-EXT-1") {
+        return Err(ClientError::ImapError(
+            "labels need Gmail's IMAP extensions (X-GM-EXT-1), which the server lacks".to_string(),
+        ));
+    }
+    let command = format!(
+        "UID STORE {} {}X-GM-LABELS.SILENT",
+        sequence_set(uids),
+        if add { '+' } else { '-' }
+    );
+    let name = session.mailbox_name(label);
+    session
+        .execute_args(&[Arg::Raw(&command), Arg::String(&name)])
+        .await?;
+    Ok(())
+}
 
-    let mut response_buffer = Vec::new();
-    loop {
-        let mut byte = [0; 1];
-        tls_stream.read_exact(&mut byte).await?;
-        response_buffer.push(byte[0]);
+/// Moves messages of the selected mailbox to `folder`, creating it if the
+/// server asks to. Without MOVE (RFC 6851) they are copied, then deleted.
+async fn move_messages(
+    session: &mut ImapSession,
+    uids: &[u32],
+    folder: &str,
+) -> Result<(), ClientError> {
+    let has_move = session.has_capability("MOVE");
+    let command = format!(
+        "UID {} {}",
+        if has_move { "MOVE" } else { "COPY" },
+        sequence_set(uids)
+    );
+    let name = session.mailbox_name(folder);
+    let tag = session
+        .send_args(&[Arg::Raw(&command), Arg::String(&name)])
+        .await?;
+    let (_, completion) = session.wait_for(&tag).await?;
+    match completion.status {
+        Status::Ok => {}
+        _ if strip_response_code(&completion.text, "TRYCREATE").is_some() => {
+            session
+                .execute_args(&[Arg::Raw("CREATE"), Arg::String(&name)])
+                .await?;
+            session
+                .execute_args(&[Arg::Raw(&command), Arg::String(&name)])
+                .await?;
+        }
+        _ => {
+            return Err(ClientError::CommandFailed {
+                tag: completion.tag,
+                command: completion.command,
+                text: completion.text,
+            })
+        }
+    }
+    if !has_move {
+        delete_messages(session, uids).await?;
+    }
+    Ok(())
+}
 
-        if response_buffer.len() >= 2
-            && response_buffer[response_buffer.len() - 2] == b'\r'
-            && response_buffer[response_buffer.len() - 1] == b'\n'
-        {
-            let response = String::from_utf8_lossy(&response_buffer);
+/// Flags messages of the selected mailbox `\Deleted` and expunges them. Only
+/// UID EXPUNGE (UIDPLUS) is used, since a plain EXPUNGE would also remove
+/// whatever else is flagged in the mailbox.
+async fn delete_messages(session: &mut ImapSession, uids: &[u32]) -> Result<(), ClientError> {
+    let set = sequence_set(uids);
+    session
+        .execute(&format!("UID STORE {} +FLAGS.SILENT (\\Deleted)", set))
+        .await?;
+    if session.has_capability("UIDPLUS") {
+        session.execute(&format!("UID EXPUNGE {}", set)).await?;
+    } else {
+        log::warn!(
+            "The server lacks UIDPLUS; {} messages stay flagged \\Deleted until the mailbox is expunged",
+            uids.len()
+        );
+    }
+    Ok(())
+}
 
-            if response.starts_with("A002") {
-                if response.contains("OK") {
-                    return Ok(());
-                } else {
-                    return Err(ClientError::ImapError("Failed to select INBOX".to_string()));
-                }
-            }
-            response_buffer.clear();
+/// Name of a message's files without an extension: `email_NNNNN`, unless a
+/// processor named it.
+fn message_stem(message: &FetchedMessage) -> String {
+    match &message.name {
+        Some(name) => sanitize_filename(name),
+        None => format!("email_{:05}", message.seq),
+    }
+}
+
+/// Stores one message, returning where it was written, or `None` when it was
+/// skipped as a duplicate or because its file already exists.
+pub(crate) async fn save_message(
+    store: &Store,
+    message: &FetchedMessage,
+) -> Result<Option<PathBuf>, ClientError> {
+    let headers = Headers::parse(&message.body);
+    let mut dir_path = store.message_dir(&headers).await?;
+    let mut stem = message_stem(message);
+
+    if store.threads.is_some() {
+        let key = thread_key(message.gmail_thread_id.as_deref(), &headers);
+        dir_path = dir_path.join("threads").join(key);
+        tokio::fs::create_dir_all(&dir_path).await?;
+
+        // Prefix with the send time so files sort chronologically
+        let sent = headers.get("Date").and_then(parse_date).unwrap_or(0);
+        stem = format!("{}_{}", format_timestamp(sent), stem);
+    }
+
+    let target = match store.format {
+        OutputFormat::Maildir => maildir::prepare(&dir_path, &stem, &message.flags).await?,
+        format => dir_path.join(format!("{}.{}", stem, format.extension())),
+    };
+    let line_endings = store.line_endings();
+    let mut body = line_endings.apply(&message.body);
+    let mut original_hash = message.original_hash.clone();
+    if store.format == OutputFormat::Maildir && !message.labels.is_empty() {
+        original_hash.get_or_insert_with(|| sha256_hex(&body));
+        body = Cow::Owned(maildir::with_keywords(&body, &message.labels));
+    }
+    if !store.views.is_empty() {
+        return save_object(store, message, &headers, &body, &stem).await;
+    }
+    let mut entry = CatalogEntry::new(
+        &body,
+        headers.message_id(),
+        line_endings,
+        relative_path(&store.archive_root, &target),
+    );
+    if store.skip_duplicates && lock(&store.catalog)?.is_duplicate(&entry) {
+        log::info!(
+            "Skipping email {} ({}): duplicate of an archived message",
+            message.seq,
+            headers.subject().unwrap_or_default()
+        );
+        return Ok(None);
+    }
+
+    let Some(filename) = resolve_target(target, store.if_exists)? else {
+        log::info!("Skipping email {}: file already exists", message.seq);
+        return Ok(None);
+    };
+    entry.path = relative_path(&store.archive_root, &filename);
+    entry.internal_date = message.internal_date;
+    entry.original_hash = original_hash;
+    let stem = filename
+        .file_stem()
+        .unwrap_or_default()
+        .to_string_lossy()
+        .into_owned();
+    let rendered = match store.format {
+        // Rendering a PDF takes far longer than receiving the message
+        OutputFormat::Pdf => {
+            let raw = body.to_vec();
+            workers::run(move || OutputFormat::Pdf.render(&raw)).await?
+        }
+        format => format.render(&body),
+    };
+    let linked = store.if_exists == ExistingFilePolicy::Skip
+        && link_identical(store, &entry.content_hash, &rendered, &filename)?;
+    if !linked {
+        ensure_free_space(&dir_path, rendered.len() as u64, store.min_free_space)?;
+        store
+            .writer
+            .write(&filename, rendered, message.internal_date)
+            .await?;
+    }
+    lock(&store.catalog)?.record(entry)?;
+    if store.export_text {
+        write_text_copy(
+            message,
+            store.html_text,
+            &dir_path.join(format!("{}.txt", stem)),
+        )
+        .await?;
+    }
+
+    log::info!(
+        "{} email {} ({}) to {}",
+        if linked { "Linked" } else { "Saved" },
+        message.seq,
+        headers.subject().unwrap_or_default(),
+        filename.display()
+    );
+    Ok(Some(filename))
+}
+
+/// Hard links `filename` to the archived file with content hash `hash`, if
+/// there is one elsewhere and it still holds `rendered`. Returns whether it
+/// did; when linking isn't possible (another filesystem, no support) the
+/// caller writes a copy as usual.
+fn link_identical(
+    store: &Store,
+    hash: &str,
+    rendered: &[u8],
+    filename: &Path,
+) -> Result<bool, ClientError> {
+    let Some(existing) = lock(&store.catalog)?
+        .path_of(hash)
+        .map(|path| store.archive_root.join(path))
+    else {
+        return Ok(false);
+    };
+    // It may have been edited, converted or removed since it was catalogued
+    if existing == filename || std::fs::read(&existing).ok().as_deref() != Some(rendered) {
+        return Ok(false);
+    }
+    match std::fs::hard_link(&existing, filename) {
+        Ok(()) => Ok(true),
+        Err(e) => {
+            log::debug!(
+                "Copying instead of linking {} to {}: {}",
+                filename.display(),
+                existing.display(),
+                e
+            );
+            Ok(false)
         }
     }
 }
 
-async fn send_inbox_cmd(tls_stream: &mut TlsStream<TcpStream>) -> Result<(), ClientError> {
-    let select_cmd = "A002 SELECT INBOX\r\n";
-    tls_stream.write_all(select_cmd.as_bytes()).await?;
-    tls_stream.flush().await?;
+/// Writes the text body of `message` to `path`, if it has one.
+async fn write_text_copy(
+    message: &FetchedMessage,
+    html_text: bool,
+    path: &Path,
+) -> Result<(), ClientError> {
+    let text = find_text_part(&message.body, "text/plain").or_else(|| {
+        html_text
+            .then(|| find_text_part(&message.body, "text/html"))
+            .flatten()
+            .map(|html| html_to_text(&html))
+    });
+    if let Some(text) = text {
+        tokio::fs::write(path, text).await?;
+        set_received_time(path, message.internal_date);
+    }
     Ok(())
 }
 
-async fn process_batch_async(
-    tls_stream: &mut TlsStream<TcpStream>,
-    dir_path: &str,
-) -> Result<u32, ClientError> {
-    let mut response_buffer = Vec::new();
-    let mut current_email_data = Vec::new();
-    let mut reading_email_body = false;
-    let mut email_body_size = 0;
-    let mut body_bytes_read = 0;
-    let mut current_email_id = 0;
-    let mut emails_saved = 0;
-    let mut expecting_closing_paren = false;
+/// Stores `body`, the message converted for storage, in the content-addressed
+/// store unless it is already there, and links it into every view. Returns
+/// where it is stored, or `None` when it was skipped as a duplicate.
+async fn save_object(
+    store: &Store,
+    message: &FetchedMessage,
+    headers: &Headers,
+    body: &[u8],
+    stem: &str,
+) -> Result<Option<PathBuf>, ClientError> {
+    let root = &store.archive_root;
+    let extension = store.format.extension();
+    let hash = sha256_hex(body);
+    let object = object_path(root, &hash, extension);
+    let mut entry = CatalogEntry::new(
+        body,
+        headers.message_id(),
+        store.line_endings(),
+        relative_path(root, &object),
+    );
+    if store.skip_duplicates && lock(&store.catalog)?.is_duplicate(&entry) {
+        log::info!(
+            "Skipping email {} ({}): duplicate of an archived message",
+            message.seq,
+            headers.subject().unwrap_or_default()
+        );
+        return Ok(None);
+    }
+    entry.internal_date = message.internal_date;
+    entry.original_hash = message.original_hash.clone();
 
-    loop {
-        let mut buffer = [0; 4096];
-        match tls_stream.read(&mut buffer).await {
-            Ok(0) => break,
-            Ok(n) => {
-                for i in 0..n {
-                    let byte = buffer[i];
-
-                    if reading_email_body {
-                        current_email_data.push(byte);
-                        body_bytes_read += 1;
-
-                        if body_bytes_read >= email_body_size {
-                            // Save email
-                            let filename =
-                                format!("{}/email_{:05}.eml", dir_path, current_email_id);
-                            tokio::fs::write(&filename, &current_email_data).await?;
-                            log::info!("Saved email {} to {}", current_email_id, filename);
-
-                            emails_saved += 1;
-                            reading_email_body = false;
-                            expecting_closing_paren = true;
-                            current_email_data.clear();
-                        }
-                    } else if expecting_closing_paren {
-                        if byte == b')' {
-                            expecting_closing_paren = false;
-                        }
-                    } else {
-                        response_buffer.push(byte);
+    if object.exists() {
+        log::debug!(
+            "Email {} is already stored as {}",
+            message.seq,
+            object.display()
+        );
+    } else {
+        let dir = object.parent().unwrap_or(root);
+        tokio::fs::create_dir_all(dir).await?;
+        ensure_free_space(dir, body.len() as u64, store.min_free_space)?;
+        // Written under a temporary name, so an object that exists is complete
+        let temp = dir.join(format!("{}.{}.tmp", hash, message.seq));
+        tokio::fs::write(&temp, store.format.render(body)).await?;
+        tokio::fs::rename(&temp, &object).await?;
+        set_received_time(&object, message.internal_date);
+        if store.export_text {
+            write_text_copy(message, store.html_text, &object.with_extension("txt")).await?;
+        }
+    }
+    lock(&store.catalog)?.record(entry)?;
 
-                        if response_buffer.len() >= 2
-                            && response_buffer[response_buffer.len() - 2] == b'\r'
-                            && response_buffer[response_buffer.len() - 1] == b'\n'
-                        {
-                            let line = String::from_utf8_lossy(&response_buffer);
-                            let line_str = line.trim();
-
-                            if line_str.contains("FETCH") && line_str.contains("{") {
-                                // Extract email ID
-                                if let Some(fetch_start) = line_str.find("* ") {
-                                    if let Some(fetch_end) = line_str.find(" FETCH") {
-                                        if let Ok(id) =
-                                            line_str[fetch_start + 2..fetch_end].parse::<u32>()
-                                        {
-                                            current_email_id = id;
-                                        }
-                                    }
-                                }
-
-                                // Extract body size
-                                if let Some(size_start) = line_str.find("{") {
-                                    if let Some(size_end) = line_str.find("}") {
-                                        if let Ok(size) =
-                                            line_str[size_start + 1..size_end].parse::<usize>()
-                                        {
-                                            email_body_size = size;
-                                            body_bytes_read = 0;
-                                            reading_email_body = true;
-                                            current_email_data.clear();
-                                        }
-                                    }
-                                }
-                            } else if line_str.starts_with("A003") {
-                                if line_str.contains("OK") {
-                                    return Ok(emails_saved);
-                                } else if line_str.contains("BAD") || line_str.contains("NO") {
-                                    return Err(ClientError::ImapError(format!(
-                                        "FETCH command failed: {}",
-                                        line_str
-                                    )));
-                                }
-                            }
+    let sender = headers.sender();
+    let view_entry = ViewEntry {
+        folder: store.dir_path.strip_prefix(root).unwrap_or(Path::new("")),
+        stem,
+        hash: &hash,
+        extension,
+        date: message
+            .internal_date
+            .or_else(|| headers.get("Date").and_then(parse_date)),
+        sender: sender.as_ref(),
+    };
+    for view in &store.views {
+        link_object(root, &object, &view.link_path(root, &view_entry))?;
+    }
 
-                            response_buffer.clear();
-                        }
-                    }
-                }
-            }
-            Err(e) => return Err(ClientError::ConnectionError(e.to_string())),
+    log::info!(
+        "Saved email {} ({}) to {}",
+        message.seq,
+        headers.subject().unwrap_or_default(),
+        object.display()
+    );
+    Ok(Some(object))
+}
+
+/// Saves a message downloaded by an HTTP backend, auditing it under the
+/// backend's `id` for it.
+async fn save_downloaded(
+    store: &Store,
+    progress: &Progress,
+    mailbox: &str,
+    id: &str,
+    message: &FetchedMessage,
+    audit: impl Fn(&str) -> Result<(), ClientError>,
+) -> Result<(), ClientError> {
+    match file_message(store, mailbox, message).await?.0 {
+        Some(path) => {
+            audit(&format!(
+                "saved {} message {} sha256 {} {} bytes as {}",
+                mailbox,
+                id,
+                sha256_hex(&message.body),
+                message.body.len(),
+                relative_path(&store.archive_root, &path)
+            ))?;
+            progress.add_saved(1);
+        }
+        None => {
+            audit(&format!("skipped {} message {}", mailbox, id))?;
+            progress.add_skipped(1);
         }
     }
+    Ok(())
+}
+
+/// Writes `stored`, the bytes catalogued for `entry`, back to its file
+/// under `root`, under a temporary name first so a partial write never
+/// passes for the file.
+async fn restore_file(root: &Path, entry: &CatalogEntry, stored: &[u8]) -> Result<(), ClientError> {
+    let path = root.join(&entry.path);
+    if let Some(dir) = path.parent() {
+        tokio::fs::create_dir_all(dir).await?;
+    }
+    let temp = path.with_extension("repair.tmp");
+    tokio::fs::write(&temp, stored).await?;
+    tokio::fs::rename(&temp, &path).await?;
+    set_received_time(&path, entry.internal_date);
+    Ok(())
+}
 
-    Ok(emails_saved)
+fn lock<T>(mutex: &Mutex<T>) -> Result<MutexGuard<'_, T>, ClientError> {
+    mutex
+        .lock()
+        .map_err(|_| std::io::Error::other("archive state lock poisoned").into())
 }
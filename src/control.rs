@@ -0,0 +1,133 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::sync::{watch, OwnedSemaphorePermit, Semaphore};
+
+use crate::error_imap::ClientError;
+use crate::scheduler::{self, Scheduler};
+
+/// Runtime controls for one account's fetch: pausing and the number of
+/// connections allowed at once. Shared by the batch tasks and whoever drives
+/// the controls (e.g. the TUI).
+pub struct Control {
+    paused: watch::Sender<bool>,
+    connections: Arc<Semaphore>,
+    max_concurrent: AtomicUsize,
+    /// The run's scheduler and this account's domain, when accounts share
+    /// limits.
+    scheduler: Option<(Arc<Scheduler>, String)>,
+}
+
+/// A connection slot, given back on drop.
+pub struct Connection {
+    _account: OwnedSemaphorePermit,
+    _shared: Option<scheduler::Permit>,
+}
+
+impl Control {
+    pub fn new(max_concurrent: usize) -> Arc<Self> {
+        Arc::new(Control {
+            paused: watch::Sender::new(false),
+            connections: Arc::new(Semaphore::new(max_concurrent)),
+            max_concurrent: AtomicUsize::new(max_concurrent),
+            scheduler: None,
+        })
+    }
+
+    /// Controls for the account `email` that also keep to the limits of
+    /// `scheduler`.
+    pub fn scheduled(max_concurrent: usize, scheduler: Arc<Scheduler>, email: &str) -> Arc<Self> {
+        Arc::new(Control {
+            paused: watch::Sender::new(false),
+            connections: Arc::new(Semaphore::new(max_concurrent)),
+            max_concurrent: AtomicUsize::new(max_concurrent),
+            scheduler: Some((scheduler, scheduler::domain_of(email).to_string())),
+        })
+    }
+
+    /// Connection slots; a batch holds one permit while it is connected.
+    pub fn connections(&self) -> Arc<Semaphore> {
+        Arc::clone(&self.connections)
+    }
+
+    /// Waits for one of this account's connection slots and, with a
+    /// scheduler, for one of the run's and the domain's.
+    pub async fn connection(&self) -> Result<Connection, ClientError> {
+        let account = self
+            .connections()
+            .acquire_owned()
+            .await
+            .map_err(|e| ClientError::JoinError(Box::new(e)))?;
+        Ok(Connection {
+            _account: account,
+            _shared: self.shared_connection().await?,
+        })
+    }
+
+    /// Waits for a slot from the scheduler alone, for connections made
+    /// outside of the batches, such as for planning.
+    pub async fn shared_connection(&self) -> Result<Option<scheduler::Permit>, ClientError> {
+        match &self.scheduler {
+            Some((scheduler, domain)) => Ok(Some(scheduler.connection(domain).await?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Accounts for `bytes` downloaded, waiting if the run is over its
+    /// bandwidth limit.
+    pub async fn transfer(&self, bytes: usize) {
+        if let Some((scheduler, _)) = &self.scheduler {
+            scheduler.transfer(bytes).await;
+        }
+    }
+
+    /// How many signed-in sessions may be kept open between batches. Idle
+    /// sessions still count against the server's limits, so none are kept
+    /// when connections are shared with other accounts.
+    pub fn idle_limit(&self) -> usize {
+        match &self.scheduler {
+            Some((scheduler, _)) if scheduler.limits_connections() => 0,
+            _ => self.max_concurrent(),
+        }
+    }
+
+    pub fn max_concurrent(&self) -> usize {
+        self.max_concurrent.load(Ordering::Relaxed)
+    }
+
+    /// Changes how many connections may be open at once. Lowering it takes
+    /// effect as running batches finish.
+    pub fn set_max_concurrent(&self, max_concurrent: usize) {
+        let max_concurrent = max_concurrent.max(1);
+        let previous = self.max_concurrent.swap(max_concurrent, Ordering::Relaxed);
+        if max_concurrent > previous {
+            self.connections.add_permits(max_concurrent - previous);
+        } else if max_concurrent < previous {
+            let connections = Arc::clone(&self.connections);
+            let surplus = (previous - max_concurrent) as u32;
+            tokio::spawn(async move {
+                if let Ok(permits) = connections.acquire_many(surplus).await {
+                    permits.forget();
+                }
+            });
+        }
+    }
+
+    pub fn pause(&self) {
+        self.paused.send_replace(true);
+    }
+
+    pub fn resume(&self) {
+        self.paused.send_replace(false);
+    }
+
+    pub fn is_paused(&self) -> bool {
+        *self.paused.borrow()
+    }
+
+    /// Returns once the fetch is not paused.
+    pub async fn wait_while_paused(&self) {
+        let mut paused = self.paused.subscribe();
+        // The sender lives as long as `self`, so this can't fail
+        let _ = paused.wait_for(|paused| !paused).await;
+    }
+}
@@ -0,0 +1,260 @@
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::catalog::{Catalog, CatalogEntry};
+use crate::dedupe::relative_path;
+use crate::error_imap::ClientError;
+use crate::lock::RunLock;
+use crate::maildir::{info_flags, INFO_SEPARATOR};
+use crate::message::Headers;
+use crate::storage::{append_mbox, read_mbox, LineEndings};
+use crate::views::VIEWS_DIR;
+
+/// How the messages of a local archive are laid out on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArchiveFormat {
+    /// One `.eml` file per message in a directory per folder.
+    Eml,
+    /// One `<folder>.mbox` file per folder.
+    Mbox,
+    /// A Maildir per folder, keeping flags in file names.
+    Maildir,
+}
+
+impl FromStr for ArchiveFormat {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "eml" => Ok(ArchiveFormat::Eml),
+            "mbox" => Ok(ArchiveFormat::Mbox),
+            "maildir" => Ok(ArchiveFormat::Maildir),
+            other => Err(ClientError::InvalidArgument(format!(
+                "Unknown archive format `{}` (expected eml, mbox or maildir)",
+                other
+            ))),
+        }
+    }
+}
+
+/// What a conversion wrote.
+#[derive(Debug, Default)]
+pub struct ConvertReport {
+    pub messages: usize,
+    pub folders: usize,
+}
+
+/// Where a folder's messages are read from.
+enum Source {
+    /// A message file, with its Maildir flags if it came from one.
+    File {
+        path: PathBuf,
+        flags: String,
+    },
+    Mbox(PathBuf),
+}
+
+/// Converts the archive at `dir`, in any mix of `.eml` files, `.mbox` files
+/// and Maildirs, into a new archive at `output` laid out as `to`. Folders
+/// and Maildir flags are kept, messages are read one at a time, and `output`
+/// gets its own catalog. Nothing is fetched and `dir` is left untouched.
+pub fn convert_archive(
+    dir: &Path,
+    output: &Path,
+    to: ArchiveFormat,
+) -> Result<ConvertReport, ClientError> {
+    if output
+        .read_dir()
+        .is_ok_and(|mut entries| entries.next().is_some())
+    {
+        return Err(ClientError::InvalidArgument(format!(
+            "{} already exists and isn't empty",
+            output.display()
+        )));
+    }
+    let mut folders = BTreeMap::new();
+    collect_sources(dir, dir, &mut folders)?;
+
+    let _lock = RunLock::acquire(output)?;
+    let mut catalog = Catalog::open(output)?;
+    let mut report = ConvertReport::default();
+    for (folder, sources) in &folders {
+        let mut writer = FolderWriter::new(output, folder, to)?;
+        for source in sources {
+            match source {
+                Source::File { path, flags } => {
+                    let raw = std::fs::read(path)?;
+                    let written = writer.write(&raw, flags)?;
+                    // Keep the received time fetches set as the file's
+                    if to != ArchiveFormat::Mbox {
+                        let modified = std::fs::metadata(path)?.modified()?;
+                        File::options()
+                            .write(true)
+                            .open(&written)?
+                            .set_modified(modified)?;
+                    }
+                    writer.record(&mut catalog, &raw, &written)?;
+                }
+                Source::Mbox(path) => {
+                    read_mbox(BufReader::new(File::open(path)?), |raw| {
+                        let written = writer.write(&raw, "")?;
+                        writer.record(&mut catalog, &raw, &written)
+                    })?;
+                }
+            }
+        }
+        log::info!(
+            "Converted {} messages of {}",
+            writer.count,
+            folder.display()
+        );
+        report.messages += writer.count;
+        report.folders += 1;
+        writer.finish()?;
+    }
+    catalog.sync()?;
+    Ok(report)
+}
+
+/// Finds the messages under `dir`, grouped by folder relative to `root`.
+/// Views, which only link to messages stored elsewhere, are left out.
+fn collect_sources(
+    root: &Path,
+    dir: &Path,
+    folders: &mut BTreeMap<PathBuf, Vec<Source>>,
+) -> Result<(), ClientError> {
+    let folder = dir.strip_prefix(root).unwrap_or(dir).to_path_buf();
+    let is_maildir = dir.join("cur").is_dir();
+    let mut entries: Vec<_> = std::fs::read_dir(dir)?.collect::<Result<_, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+
+    for entry in entries {
+        let path = entry.path();
+        let name = entry.file_name().to_string_lossy().into_owned();
+        if entry.file_type()?.is_symlink() || name.starts_with('.') {
+            continue;
+        }
+        if path.is_dir() {
+            if is_maildir && matches!(name.as_str(), "cur" | "new") {
+                let mut files: Vec<_> = std::fs::read_dir(&path)?.collect::<Result<_, _>>()?;
+                files.sort_by_key(|file| file.file_name());
+                let sources = folders.entry(folder.clone()).or_default();
+                for file in files.into_iter().filter(|file| file.path().is_file()) {
+                    let name = file.file_name().to_string_lossy().into_owned();
+                    sources.push(Source::File {
+                        path: file.path(),
+                        flags: info_flags(&name).to_string(),
+                    });
+                }
+            } else {
+                let skipped = (is_maildir && name == "tmp") || (dir == root && name == VIEWS_DIR);
+                if !skipped {
+                    collect_sources(root, &path, folders)?;
+                }
+            }
+        } else if path.extension().is_some_and(|ext| ext == "eml") {
+            folders
+                .entry(folder.clone())
+                .or_default()
+                .push(Source::File {
+                    path,
+                    flags: String::new(),
+                });
+        } else if path.extension().is_some_and(|ext| ext == "mbox") {
+            folders
+                .entry(folder.join(path.file_stem().unwrap_or_default()))
+                .or_default()
+                .push(Source::Mbox(path));
+        }
+    }
+    Ok(())
+}
+
+/// Writes the messages of one folder of the new archive.
+struct FolderWriter {
+    root: PathBuf,
+    format: ArchiveFormat,
+    dir: PathBuf,
+    mbox: Option<(PathBuf, BufWriter<File>)>,
+    count: usize,
+}
+
+impl FolderWriter {
+    fn new(root: &Path, folder: &Path, format: ArchiveFormat) -> Result<Self, ClientError> {
+        let dir = root.join(folder);
+        let mut mbox = None;
+        match format {
+            ArchiveFormat::Eml => std::fs::create_dir_all(&dir)?,
+            ArchiveFormat::Maildir => {
+                for sub in ["cur", "new", "tmp"] {
+                    std::fs::create_dir_all(dir.join(sub))?;
+                }
+            }
+            ArchiveFormat::Mbox => {
+                // The archive root holds INBOX
+                let path = match folder.file_name() {
+                    Some(name) => dir.with_file_name(format!("{}.mbox", name.to_string_lossy())),
+                    None => root.join("INBOX.mbox"),
+                };
+                if let Some(parent) = path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                mbox = Some((path.clone(), BufWriter::new(File::create(&path)?)));
+            }
+        }
+        Ok(FolderWriter {
+            root: root.to_path_buf(),
+            format,
+            dir,
+            mbox,
+            count: 0,
+        })
+    }
+
+    /// Adds `raw` with Maildir flag letters `flags`, returning the file it
+    /// went into.
+    fn write(&mut self, raw: &[u8], flags: &str) -> Result<PathBuf, ClientError> {
+        self.count += 1;
+        let stem = format!("email_{:05}", self.count);
+        let path = match self.format {
+            ArchiveFormat::Eml => self.dir.join(format!("{}.eml", stem)),
+            ArchiveFormat::Maildir => self
+                .dir
+                .join("cur")
+                .join(format!("{}{}2,{}", stem, INFO_SEPARATOR, flags)),
+            ArchiveFormat::Mbox => {
+                let (path, writer) = self.mbox.as_mut().expect("mbox folders have a writer");
+                let mut message = Vec::new();
+                append_mbox(&mut message, raw);
+                writer.write_all(&message)?;
+                return Ok(path.clone());
+            }
+        };
+        std::fs::write(&path, raw)?;
+        Ok(path)
+    }
+
+    /// Records `raw`, written to `path`, in the new archive's catalog.
+    fn record(&self, catalog: &mut Catalog, raw: &[u8], path: &Path) -> Result<(), ClientError> {
+        let (stored, line_endings) = match self.format {
+            ArchiveFormat::Mbox => (LineEndings::Lf.apply(raw), LineEndings::Lf),
+            _ => (raw.into(), LineEndings::Keep),
+        };
+        catalog.record(CatalogEntry::new(
+            &stored,
+            Headers::parse(raw).message_id(),
+            line_endings,
+            relative_path(&self.root, path),
+        ))
+    }
+
+    fn finish(self) -> Result<(), ClientError> {
+        if let Some((_, mut writer)) = self.mbox {
+            writer.flush()?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,130 @@
+use std::collections::{BTreeMap, HashSet};
+use std::path::{Path, PathBuf};
+
+use crate::catalog::{hash_message_id, sha256_hex, Catalog};
+use crate::error_imap::ClientError;
+use crate::lock::RunLock;
+use crate::message::Headers;
+use crate::views::VIEWS_DIR;
+
+/// Groups of files in an archive that hold the same message.
+#[derive(Debug, Default)]
+pub struct DedupeReport {
+    pub scanned: usize,
+    /// Each group lists the kept file first, followed by its duplicates.
+    pub groups: Vec<Vec<PathBuf>>,
+    /// Duplicates by Message-ID whose content differs from the kept file.
+    /// They are never removed.
+    pub differing: HashSet<PathBuf>,
+    pub removed: usize,
+}
+
+/// Scans every `.eml` file under `dir` for messages sharing a Message-ID (or,
+/// without one, identical content) within a folder. With `remove`, the files
+/// of each group with the same content as the first are deleted, along with
+/// their `.txt` copies, and dropped from the catalog. Copies in other folders
+/// and copies whose content differs are kept.
+pub fn dedupe_archive(dir: &Path, remove: bool) -> Result<DedupeReport, ClientError> {
+    let _lock = RunLock::acquire(dir)?;
+    let mut files = Vec::new();
+    collect_eml_files(dir, &mut files)?;
+    files.sort();
+
+    let mut by_key: BTreeMap<(PathBuf, String), Vec<(PathBuf, String)>> = BTreeMap::new();
+    for file in &files {
+        let raw = std::fs::read(file).map_err(|e| ClientError::io(file, e))?;
+        let content = sha256_hex(&raw);
+        let key = match Headers::parse(&raw).message_id() {
+            Some(id) => format!("id:{}", hash_message_id(id)),
+            None => format!("content:{}", content),
+        };
+        let folder = file.parent().unwrap_or(dir).to_path_buf();
+        by_key
+            .entry((folder, key))
+            .or_default()
+            .push((file.clone(), content));
+    }
+
+    let mut report = DedupeReport {
+        scanned: files.len(),
+        ..Default::default()
+    };
+    for group in by_key.into_values().filter(|g| g.len() > 1) {
+        let kept = &group[0].1;
+        report.differing.extend(
+            group[1..]
+                .iter()
+                .filter(|(_, content)| content != kept)
+                .map(|(file, _)| file.clone()),
+        );
+        report
+            .groups
+            .push(group.into_iter().map(|(file, _)| file).collect());
+    }
+
+    if remove {
+        let mut removed_paths = HashSet::new();
+        for group in &report.groups {
+            for duplicate in &group[1..] {
+                if report.differing.contains(duplicate) {
+                    log::warn!(
+                        "Keeping {}: same Message-ID as {}, different content",
+                        duplicate.display(),
+                        group[0].display()
+                    );
+                    continue;
+                }
+                for file in [duplicate.clone(), duplicate.with_extension("txt")] {
+                    match std::fs::remove_file(&file) {
+                        Err(e) if e.kind() != std::io::ErrorKind::NotFound => {
+                            return Err(ClientError::io(&file, e))
+                        }
+                        _ => {}
+                    }
+                }
+                log::info!("Removed duplicate {}", duplicate.display());
+                removed_paths.insert(relative_path(dir, duplicate));
+            }
+        }
+        report.removed = removed_paths.len();
+
+        let mut catalog = Catalog::open(dir)?;
+        catalog.retain(|entry| !removed_paths.contains(&entry.path))?;
+    }
+
+    Ok(report)
+}
+
+/// Collects the `.eml` files of the archive at `root`, leaving out views,
+/// whose entries are links to messages stored elsewhere in the archive.
+pub(crate) fn collect_eml_files(root: &Path, files: &mut Vec<PathBuf>) -> Result<(), ClientError> {
+    collect_messages(root, &root.join(VIEWS_DIR), files)
+}
+
+fn collect_messages(dir: &Path, views: &Path, files: &mut Vec<PathBuf>) -> Result<(), ClientError> {
+    for entry in std::fs::read_dir(dir)? {
+        let entry = entry?;
+        // Views link to messages stored elsewhere in the archive, with hard
+        // links where symlinks can't be made
+        let path = entry.path();
+        if entry.file_type()?.is_symlink() || path == views {
+            continue;
+        }
+        if path.is_dir() {
+            collect_messages(&path, views, files)?;
+        } else if path.extension().is_some_and(|ext| ext == "eml") {
+            files.push(path);
+        }
+    }
+    Ok(())
+}
+
+/// `path` relative to `root`, with `/` separators, as stored in the catalog.
+pub fn relative_path(root: &Path, path: &Path) -> String {
+    let relative = path.strip_prefix(root).unwrap_or(path);
+    relative
+        .components()
+        .map(|c| c.as_os_str().to_string_lossy())
+        .collect::<Vec<_>>()
+        .join("/")
+}
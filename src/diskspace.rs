@@ -0,0 +1,68 @@
+use std::path::Path;
+
+use crate::error_imap::ClientError;
+
+/// Space kept free on the target filesystem unless `--min-free-space` says otherwise.
+pub const DEFAULT_MIN_FREE_SPACE: u64 = 100 * 1024 * 1024;
+
+/// Bytes available to this user on the filesystem holding `path`.
+pub fn free_space(path: &Path) -> Result<u64, ClientError> {
+    fs2::available_space(path).map_err(|e| ClientError::io(path, e))
+}
+
+/// Fails unless `needed` bytes can be written under `path` while still leaving
+/// `reserve` bytes free.
+pub fn ensure_free_space(path: &Path, needed: u64, reserve: u64) -> Result<(), ClientError> {
+    let available = free_space(path)?;
+    if available < needed.saturating_add(reserve) {
+        return Err(ClientError::InsufficientSpace {
+            path: path.to_path_buf(),
+            needed: format_size(needed.saturating_add(reserve)),
+            available: format_size(available),
+        });
+    }
+    Ok(())
+}
+
+/// Parses sizes such as `500`, `64K`, `100MB` or `2G` (powers of 1024).
+pub fn parse_size(value: &str) -> Result<u64, ClientError> {
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let multiplier: u64 = match unit.trim().to_ascii_uppercase().as_str() {
+        "" | "B" => 1,
+        "K" | "KB" | "KIB" => 1 << 10,
+        "M" | "MB" | "MIB" => 1 << 20,
+        "G" | "GB" | "GIB" => 1 << 30,
+        "T" | "TB" | "TIB" => 1 << 40,
+        _ => {
+            return Err(ClientError::InvalidArgument(format!(
+                "Invalid size: {}",
+                value
+            )))
+        }
+    };
+    number
+        .parse::<u64>()
+        .ok()
+        .and_then(|n| n.checked_mul(multiplier))
+        .ok_or_else(|| ClientError::InvalidArgument(format!("Invalid size: {}", value)))
+}
+
+/// Formats a byte count for messages, e.g. `1.5 GiB`.
+pub fn format_size(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KiB", "MiB", "GiB", "TiB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} B", bytes)
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
@@ -0,0 +1,306 @@
+use std::io;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::str::FromStr;
+use std::time::Duration;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::{TcpStream, UdpSocket};
+
+use crate::error_imap::ClientError;
+use crate::http::{check_status, http_error};
+
+/// How long a DNS server gets to answer each attempt at a query.
+pub const QUERY_TIMEOUT: Duration = Duration::from_secs(3);
+
+/// Times a query is sent over UDP, which may lose it, before giving up.
+const UDP_ATTEMPTS: usize = 3;
+
+pub const TYPE_A: u16 = 1;
+pub const TYPE_AAAA: u16 = 28;
+const CLASS_IN: u16 = 1;
+
+/// Flag bits and fields of a DNS header (RFC 1035, section 4.1.1).
+const FLAG_RESPONSE: u16 = 0x8000;
+const FLAG_TRUNCATED: u16 = 0x0200;
+const FLAG_RECURSION: u16 = 0x0100;
+const RCODE_MASK: u16 = 0x000f;
+const RCODE_NAME_ERROR: u16 = 3;
+
+/// Where the server's name is resolved.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Resolver {
+    /// The system's resolver, as configured in `/etc/resolv.conf` or its
+    /// equivalent.
+    #[default]
+    System,
+    /// A DNS server asked directly, over UDP and TCP.
+    Dns(SocketAddr),
+    /// A DNS-over-HTTPS server (RFC 8484), given by its query URL.
+    Https(String),
+}
+
+impl FromStr for Resolver {
+    type Err = ClientError;
+
+    /// Accepts `system`, an address with an optional port, as in `9.9.9.9`
+    /// or `[2620:fe::fe]:53`, or an `https://` URL.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s.eq_ignore_ascii_case("system") {
+            return Ok(Resolver::System);
+        }
+        if s.starts_with("https://") {
+            return match reqwest::Url::parse(s) {
+                Ok(url) if url.host().is_some() => Ok(Resolver::Https(s.to_string())),
+                _ => Err(ClientError::InvalidArgument(format!(
+                    "Invalid DNS-over-HTTPS URL `{}`",
+                    s
+                ))),
+            };
+        }
+        if let Ok(addr) = s.parse::<SocketAddr>() {
+            return Ok(Resolver::Dns(addr));
+        }
+        match s.parse::<IpAddr>() {
+            Ok(ip) => Ok(Resolver::Dns(SocketAddr::new(ip, 53))),
+            Err(_) => Err(ClientError::InvalidArgument(format!(
+                "Invalid DNS resolver `{}` (expected an IP address, an https:// URL or system)",
+                s
+            ))),
+        }
+    }
+}
+
+impl Resolver {
+    /// The addresses of `server` (`host:port`), IPv6 ones first.
+    pub async fn resolve(&self, server: &str) -> Result<Vec<SocketAddr>, ClientError> {
+        let failed =
+            |e: io::Error| ClientError::connection_caused(format!("cannot resolve {}", server), e);
+        let (host, port) = split_server(server).map_err(failed)?;
+        if let Ok(ip) = host.parse::<IpAddr>() {
+            return Ok(vec![SocketAddr::new(ip, port)]);
+        }
+        if *self == Resolver::System {
+            let addrs = tokio::net::lookup_host((host, port))
+                .await
+                .map_err(failed)?;
+            return Ok(addrs.collect());
+        }
+        let (v6, v4) = tokio::join!(self.lookup(host, TYPE_AAAA), self.lookup(host, TYPE_A));
+        let addrs = match (v6, v4) {
+            (Err(e), Err(_)) => return Err(failed(e)),
+            (v6, v4) => v6.into_iter().chain(v4).flatten(),
+        };
+        Ok(addrs.map(|ip| SocketAddr::new(ip, port)).collect())
+    }
+
+    /// The addresses of type `qtype` the server has for `host`.
+    async fn lookup(&self, host: &str, qtype: u16) -> io::Result<Vec<IpAddr>> {
+        let response = match self {
+            Resolver::System => unreachable!("the system resolver is asked through tokio"),
+            Resolver::Dns(server) => {
+                let id = query_id();
+                let query = query(id, host, qtype)?;
+                ask(*server, &query, id).await?
+            }
+            // RFC 8484 asks for ID 0, so identical queries can be cached
+            Resolver::Https(url) => ask_https(url, query(0, host, qtype)?).await?,
+        };
+        answers(&response, qtype)
+    }
+}
+
+/// Splits `host:port`, where an IPv6 host is in brackets.
+fn split_server(server: &str) -> io::Result<(&str, u16)> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, "expected host:port");
+    let (host, port) = server.rsplit_once(':').ok_or_else(invalid)?;
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+    Ok((host, port.parse().map_err(|_| invalid())?))
+}
+
+fn query_id() -> u16 {
+    let mut bytes = [0u8; 2];
+    // Only guards against stray answers to earlier queries, so a fixed ID
+    // will do if there is no randomness to be had
+    let _ = getrandom::getrandom(&mut bytes);
+    u16::from_be_bytes(bytes)
+}
+
+/// A recursive query `id` for the records of type `qtype` of `host`.
+pub fn query(id: u16, host: &str, qtype: u16) -> io::Result<Vec<u8>> {
+    let mut query = Vec::with_capacity(18 + host.len());
+    for field in [id, FLAG_RECURSION, 1, 0, 0, 0] {
+        query.extend_from_slice(&field.to_be_bytes());
+    }
+    let host = host.strip_suffix('.').unwrap_or(host);
+    if host.len() > 253 {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "host name too long",
+        ));
+    }
+    for label in host.split('.') {
+        if label.is_empty() || label.len() > 63 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("invalid host name {}", host),
+            ));
+        }
+        query.push(label.len() as u8);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&qtype.to_be_bytes());
+    query.extend_from_slice(&CLASS_IN.to_be_bytes());
+    Ok(query)
+}
+
+/// Sends `query` to `server` over UDP, and again over TCP if the answer
+/// didn't fit in a datagram.
+async fn ask(server: SocketAddr, query: &[u8], id: u16) -> io::Result<Vec<u8>> {
+    let response = ask_udp(server, query, id).await?;
+    if flags(&response) & FLAG_TRUNCATED == 0 {
+        return Ok(response);
+    }
+    let ask_tcp = async {
+        let mut stream = TcpStream::connect(server).await?;
+        let mut framed = (query.len() as u16).to_be_bytes().to_vec();
+        framed.extend_from_slice(query);
+        stream.write_all(&framed).await?;
+        let len = stream.read_u16().await?;
+        let mut response = vec![0; len as usize];
+        stream.read_exact(&mut response).await?;
+        Ok::<_, io::Error>(response)
+    };
+    let response = tokio::time::timeout(QUERY_TIMEOUT, ask_tcp)
+        .await
+        .map_err(|_| io::Error::new(io::ErrorKind::TimedOut, "the DNS server didn't answer"))??;
+    match response.get(..2) {
+        Some(got) if got == id.to_be_bytes() => Ok(response),
+        _ => Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "mismatched DNS answer",
+        )),
+    }
+}
+
+async fn ask_udp(server: SocketAddr, query: &[u8], id: u16) -> io::Result<Vec<u8>> {
+    let local = match server {
+        SocketAddr::V4(_) => SocketAddr::from((Ipv4Addr::UNSPECIFIED, 0)),
+        SocketAddr::V6(_) => SocketAddr::from((Ipv6Addr::UNSPECIFIED, 0)),
+    };
+    let socket = UdpSocket::bind(local).await?;
+    socket.connect(server).await?;
+    let mut buf = vec![0; 4096];
+    for _ in 0..UDP_ATTEMPTS {
+        socket.send(query).await?;
+        let deadline = tokio::time::Instant::now() + QUERY_TIMEOUT;
+        // Datagrams that aren't the answer, such as late answers to an
+        // earlier attempt, are skipped
+        while let Ok(received) = tokio::time::timeout_at(deadline, socket.recv(&mut buf)).await {
+            let len = received?;
+            if buf[..len].starts_with(&id.to_be_bytes()) {
+                buf.truncate(len);
+                return Ok(buf);
+            }
+        }
+    }
+    Err(io::Error::new(
+        io::ErrorKind::TimedOut,
+        "the DNS server didn't answer",
+    ))
+}
+
+async fn ask_https(url: &str, query: Vec<u8>) -> io::Result<Vec<u8>> {
+    const DNS_MESSAGE: &str = "application/dns-message";
+    let response = reqwest::Client::new()
+        .post(url)
+        .header(reqwest::header::CONTENT_TYPE, DNS_MESSAGE)
+        .header(reqwest::header::ACCEPT, DNS_MESSAGE)
+        .timeout(QUERY_TIMEOUT)
+        .body(query)
+        .send()
+        .await
+        .map_err(http_error)
+        .and_then(check_status)
+        .map_err(|e| io::Error::other(e.to_string()))?;
+    let body = response
+        .bytes()
+        .await
+        .map_err(|e| io::Error::other(http_error(e).to_string()))?;
+    Ok(body.to_vec())
+}
+
+fn flags(message: &[u8]) -> u16 {
+    message
+        .get(2..4)
+        .map_or(0, |flags| u16::from_be_bytes([flags[0], flags[1]]))
+}
+
+/// The addresses of type `qtype` answering in DNS message `response`. The
+/// records of any aliases the name goes through come in the same answer,
+/// so every address record in it counts.
+pub fn answers(response: &[u8], qtype: u16) -> io::Result<Vec<IpAddr>> {
+    let malformed = || io::Error::new(io::ErrorKind::InvalidData, "malformed DNS answer");
+    let flags = flags(response);
+    if response.len() < 12 || flags & FLAG_RESPONSE == 0 {
+        return Err(malformed());
+    }
+    match flags & RCODE_MASK {
+        0 => {}
+        RCODE_NAME_ERROR => {
+            return Err(io::Error::new(io::ErrorKind::NotFound, "no such host"));
+        }
+        rcode => {
+            return Err(io::Error::other(format!(
+                "the DNS server failed the query (RCODE {})",
+                rcode
+            )))
+        }
+    }
+    let count = |at: usize| u16::from_be_bytes([response[at], response[at + 1]]);
+    let (questions, records) = (count(4), count(6));
+    let mut at = 12;
+    for _ in 0..questions {
+        at = skip_name(response, at).ok_or_else(malformed)? + 4;
+    }
+    let mut addresses = Vec::new();
+    for _ in 0..records {
+        at = skip_name(response, at).ok_or_else(malformed)?;
+        let fixed = response.get(at..at + 10).ok_or_else(malformed)?;
+        let rtype = u16::from_be_bytes([fixed[0], fixed[1]]);
+        let class = u16::from_be_bytes([fixed[2], fixed[3]]);
+        let len = u16::from_be_bytes([fixed[8], fixed[9]]) as usize;
+        let data = response.get(at + 10..at + 10 + len).ok_or_else(malformed)?;
+        at += 10 + len;
+        if rtype != qtype || class != CLASS_IN {
+            continue;
+        }
+        match (rtype, data.len()) {
+            (TYPE_A, 4) => {
+                let octets: [u8; 4] = data.try_into().map_err(|_| malformed())?;
+                addresses.push(IpAddr::from(octets));
+            }
+            (TYPE_AAAA, 16) => {
+                let octets: [u8; 16] = data.try_into().map_err(|_| malformed())?;
+                addresses.push(IpAddr::from(octets));
+            }
+            _ => return Err(malformed()),
+        }
+    }
+    Ok(addresses)
+}
+
+/// Where the name starting at `at` ends. A name ends at its root label, or
+/// at a pointer to the rest of it elsewhere in the message.
+fn skip_name(message: &[u8], mut at: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(at)? as usize;
+        match len {
+            0 => return Some(at + 1),
+            _ if len & 0xc0 == 0xc0 => return Some(at + 2),
+            _ => at += 1 + len,
+        }
+    }
+}
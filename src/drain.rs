@@ -0,0 +1,78 @@
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+
+use crate::audit::AuditLog;
+use crate::catalog::CatalogEntry;
+use crate::error_imap::ClientError;
+
+/// Name of the drain journal written to the root of an archive directory.
+pub const DRAIN_JOURNAL: &str = "drain.journal";
+
+/// Deletes messages from the server once their archived copy checks out.
+///
+/// Every step a message goes through (fetched, verified, replicated, then
+/// deleted, or kept and why) is written to a journal with the same
+/// hash-chained format as the audit log, so `verify-audit` can check it.
+pub struct Drain {
+    journal: AuditLog,
+    /// Shell command that must succeed before a message counts as replicated.
+    check: Option<String>,
+}
+
+impl Drain {
+    /// A drain journaling to `journal`, signed with `key_file` when given,
+    /// that also runs `check` on every archived copy.
+    pub fn new(journal: PathBuf, key_file: Option<PathBuf>, check: Option<String>) -> Self {
+        Drain {
+            journal: AuditLog::new(journal, key_file),
+            check,
+        }
+    }
+
+    pub fn journal(&self) -> &AuditLog {
+        &self.journal
+    }
+
+    /// Appends one transition to the journal.
+    pub fn record(&self, event: &str) -> Result<(), ClientError> {
+        self.journal.record(0, event)
+    }
+
+    /// Checks that the file `entry` points to, under `root`, still has the
+    /// size and SHA-256 recorded when it was saved. Returns why not, if not.
+    pub fn verify(&self, root: &Path, entry: &CatalogEntry) -> Result<(), String> {
+        entry.verify(root)
+    }
+
+    /// Runs the replication check, if there is one, on the archived copy
+    /// `entry`. The command gets the file's path as `DRAIN_FILE`, its path
+    /// relative to the archive as `DRAIN_PATH`, and its hash and size as
+    /// `DRAIN_SHA256` and `DRAIN_SIZE`. Returns whether a check ran, or why
+    /// it failed.
+    pub async fn check_replica(&self, root: &Path, entry: &CatalogEntry) -> Result<bool, String> {
+        let Some(check) = &self.check else {
+            return Ok(false);
+        };
+        let (shell, flag) = if cfg!(windows) {
+            ("cmd", "/C")
+        } else {
+            ("sh", "-c")
+        };
+        let status = tokio::process::Command::new(shell)
+            .arg(flag)
+            .arg(check)
+            .env("DRAIN_FILE", root.join(&entry.path))
+            .env("DRAIN_PATH", &entry.path)
+            .env("DRAIN_SHA256", &entry.content_hash)
+            .env("DRAIN_SIZE", entry.size.to_string())
+            .stdin(Stdio::null())
+            .status()
+            .await
+            .map_err(|e| format!("can't run the replication check: {}", e))?;
+        if status.success() {
+            Ok(true)
+        } else {
+            Err(format!("replication check failed ({})", status))
+        }
+    }
+}
@@ -1,9 +1,37 @@
+use std::error::Error as StdError;
+use std::fmt;
+use std::path::PathBuf;
 use thiserror::Error;
 
+/// An underlying error kept as the source of a [`ClientError`], to be
+/// downcast by whoever needs to know more than its text.
+pub type Cause = Box<dyn StdError + Send + Sync>;
+
 #[derive(Error, Debug)]
 pub enum ClientError {
     #[error("Failed to read user input: {0}")]
-    InputError(#[from] std::io::Error),
+    InputError(#[source] std::io::Error),
+
+    /// Any other I/O error, with the file it happened on when known. Plain
+    /// `?` on an [`std::io::Error`] gives one without a path.
+    #[error("{}", describe_io(.path, .source))]
+    Io {
+        path: Option<PathBuf>,
+        #[source]
+        source: std::io::Error,
+    },
+
+    /// A JSON file of the archive, such as `failures.json`, that can't be
+    /// written or read back.
+    #[error("Invalid JSON in {}: {source}", .path.display())]
+    Json {
+        path: PathBuf,
+        #[source]
+        source: serde_json::Error,
+    },
+
+    #[error("Invalid argument: {0}")]
+    InvalidArgument(String),
 
     #[error("Empty input provided for {field}")]
     EmptyInput { field: String },
@@ -14,15 +42,56 @@ pub enum ClientError {
     #[error("IMAP server responded with error: {0}")]
     ImapError(String),
 
-    #[error("TLS error: {0}")]
-    TlsError(String),
+    #[error("JMAP server responded with error: {0}")]
+    JmapError(String),
+
+    #[error("JMAP server sent a malformed response: {}", describe(.message, .source))]
+    JmapMalformed {
+        message: String,
+        #[source]
+        source: Option<Cause>,
+    },
+
+    #[error("{command} failed (tag {tag}): {text}")]
+    CommandFailed {
+        tag: String,
+        command: String,
+        text: String,
+    },
+
+    #[error("Refusing to send `{0}` in read-only mode")]
+    ReadOnly(String),
 
-    #[error("Failed to connect to IMAP server: {0}")]
-    ConnectionError(String),
+    #[error("Server closed the connection: {0}")]
+    ServerBye(String),
+
+    #[error("TLS error: {}", describe(.message, .source))]
+    TlsError {
+        message: String,
+        #[source]
+        source: Option<Cause>,
+    },
+
+    #[error("Failed to connect to IMAP server: {}", describe(.message, .source))]
+    ConnectionError {
+        message: String,
+        #[source]
+        source: Option<Cause>,
+    },
+
+    #[error("HTTP request failed: {}", describe(.message, .source))]
+    HttpError {
+        message: String,
+        #[source]
+        source: Option<Cause>,
+    },
 
     #[error("Authentication failed: {0}")]
     AuthenticationError(String),
 
+    #[error("Action required: {message}\n{guidance}")]
+    ActionRequired { message: String, guidance: String },
+
     #[error("Invalid DNS name: {0}")]
     InvalidDnsName(#[from] rustls::pki_types::InvalidDnsNameError),
 
@@ -32,12 +101,321 @@ pub enum ClientError {
     #[error("Failed to parse email count")]
     ParseError,
 
-    #[error("Directory creation failed: {0}")]
-    DirectoryError(String),
+    #[error("Malformed server response: {0}")]
+    MalformedResponse(#[from] crate::parser::ParseError),
+
+    /// The response stream stopped making sense, so everything after this
+    /// point on the connection is suspect.
+    #[error("Lost sync with the server at byte {offset} of the response: {reason}")]
+    Desync { offset: u64, reason: String },
+
+    #[error("Directory creation failed: {}: {source}", .path.display())]
+    DirectoryError {
+        path: PathBuf,
+        #[source]
+        source: std::io::Error,
+    },
 
-    #[error("File operation failed: {0}")]
-    FileError(String),
+    #[error("State database error: {0}")]
+    StateError(#[from] rusqlite::Error),
+
+    #[error("Not enough free space in {}: {needed} required, {available} available", .path.display())]
+    InsufficientSpace {
+        path: PathBuf,
+        needed: String,
+        available: String,
+    },
+
+    #[error("Another run is using {} ({holder})", .path.display())]
+    Locked { path: PathBuf, holder: String },
+
+    #[error("System keyring error: {}", describe(.message, .source))]
+    KeyringError {
+        message: String,
+        #[source]
+        source: Option<Cause>,
+    },
 
     #[error("Join error: {0}")]
-    JoinError(String),
+    JoinError(#[source] Cause),
+
+    #[error("Plugin {plugin} failed: {reason}")]
+    PluginError { plugin: String, reason: String },
+
+    /// An error while fetching one batch, with where in the batch it
+    /// happened.
+    #[error("{context}: {source}")]
+    Batch {
+        context: ErrorContext,
+        /// Always a [`ClientError`], boxed as a [`Cause`] so that it
+        /// downcasts to one.
+        #[source]
+        source: Cause,
+    },
+}
+
+/// `message`, followed by the text of `source` if there is one, so the
+/// error reads whole without walking its sources. Either may be left out
+/// when the other says it all.
+fn describe(message: &str, source: &Option<Cause>) -> String {
+    match source {
+        Some(source) if message.is_empty() => source.to_string(),
+        Some(source) => format!("{}: {}", message, source),
+        None => message.to_string(),
+    }
+}
+
+fn describe_io(path: &Option<PathBuf>, source: &std::io::Error) -> String {
+    match path {
+        Some(path) => format!("File operation failed: {}: {}", path.display(), source),
+        None => format!("I/O error: {}", source),
+    }
+}
+
+impl From<std::io::Error> for ClientError {
+    fn from(source: std::io::Error) -> Self {
+        ClientError::Io { path: None, source }
+    }
+}
+
+/// What a batch was doing when it failed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Phase {
+    #[default]
+    Connect,
+    Authenticate,
+    /// Opening the batch's mailbox.
+    Select,
+    Fetch,
+    /// Saving a message that arrived.
+    Save,
+    /// Moving, deleting, flagging or labelling messages on the server, as
+    /// rules asked for.
+    Actions,
+    /// Committing the checkpoint and catalog once the batch is done.
+    Commit,
+}
+
+impl Phase {
+    pub fn name(self) -> &'static str {
+        match self {
+            Phase::Connect => "connect",
+            Phase::Authenticate => "authenticate",
+            Phase::Select => "select",
+            Phase::Fetch => "fetch",
+            Phase::Save => "save",
+            Phase::Actions => "actions",
+            Phase::Commit => "commit",
+        }
+    }
+}
+
+/// The batch an error happened in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ErrorContext {
+    pub mailbox: String,
+    /// First and last UID of the batch.
+    pub uids: (u32, u32),
+    pub phase: Phase,
+}
+
+impl fmt::Display for ErrorContext {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{}, UIDs {}:{} ({})",
+            self.mailbox,
+            self.uids.0,
+            self.uids.1,
+            self.phase.name()
+        )
+    }
+}
+
+/// Broad class of an error, used to pick the process exit status.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorKind {
+    Other,
+    Auth,
+    Network,
+    /// The run finished but some batches failed.
+    Partial,
+    Storage,
+    Server,
+    /// Another run holds the archive.
+    Locked,
+    Usage,
+}
+
+impl ErrorKind {
+    pub fn name(self) -> &'static str {
+        match self {
+            ErrorKind::Other => "other",
+            ErrorKind::Auth => "auth",
+            ErrorKind::Network => "network",
+            ErrorKind::Partial => "partial",
+            ErrorKind::Storage => "storage",
+            ErrorKind::Server => "server",
+            ErrorKind::Locked => "locked",
+            ErrorKind::Usage => "usage",
+        }
+    }
+
+    pub fn exit_code(self) -> u8 {
+        match self {
+            ErrorKind::Other => 1,
+            ErrorKind::Auth => 2,
+            ErrorKind::Network => 3,
+            ErrorKind::Partial => 4,
+            ErrorKind::Storage => 5,
+            ErrorKind::Server => 6,
+            ErrorKind::Locked => 7,
+            ErrorKind::Usage => 64,
+        }
+    }
+}
+
+impl ClientError {
+    /// An I/O error on the file or directory at `path`.
+    pub fn io(path: impl Into<PathBuf>, source: std::io::Error) -> Self {
+        ClientError::Io {
+            path: Some(path.into()),
+            source,
+        }
+    }
+
+    /// A connection failure with no underlying error.
+    pub fn connection(message: impl Into<String>) -> Self {
+        ClientError::ConnectionError {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    /// A connection failure caused by `source`, such as an I/O error.
+    pub fn connection_caused(message: impl Into<String>, source: impl Into<Cause>) -> Self {
+        ClientError::ConnectionError {
+            message: message.into(),
+            source: Some(source.into()),
+        }
+    }
+
+    pub fn tls(message: impl Into<String>) -> Self {
+        ClientError::TlsError {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    pub fn tls_caused(message: impl Into<String>, source: impl Into<Cause>) -> Self {
+        ClientError::TlsError {
+            message: message.into(),
+            source: Some(source.into()),
+        }
+    }
+
+    pub fn http(message: impl Into<String>) -> Self {
+        ClientError::HttpError {
+            message: message.into(),
+            source: None,
+        }
+    }
+
+    pub fn http_caused(message: impl Into<String>, source: impl Into<Cause>) -> Self {
+        ClientError::HttpError {
+            message: message.into(),
+            source: Some(source.into()),
+        }
+    }
+
+    /// A failure of the system keyring, kept as the source.
+    pub fn keyring(source: impl Into<Cause>) -> Self {
+        ClientError::KeyringError {
+            message: String::new(),
+            source: Some(source.into()),
+        }
+    }
+
+    /// The batch the error happened in, if it happened in one.
+    pub fn context(&self) -> Option<&ErrorContext> {
+        match self {
+            ClientError::Batch { context, .. } => Some(context),
+            _ => None,
+        }
+    }
+
+    /// The first error of type `E` among this one and its sources, such as
+    /// the [`std::io::Error`] a connection failed with.
+    pub fn find<E: StdError + 'static>(&self) -> Option<&E> {
+        let mut error: Option<&(dyn StdError + 'static)> = Some(self);
+        while let Some(current) = error {
+            if let Some(found) = current.downcast_ref::<E>() {
+                return Some(found);
+            }
+            error = current.source();
+        }
+        None
+    }
+
+    /// The underlying error, without any batch context.
+    pub fn root(&self) -> &ClientError {
+        match self {
+            ClientError::Batch { source, .. } => match source.downcast_ref::<ClientError>() {
+                Some(error) => error.root(),
+                None => self,
+            },
+            other => other,
+        }
+    }
+
+    pub fn kind(&self) -> ErrorKind {
+        match self.root() {
+            ClientError::Io { source, .. } => match source.kind() {
+                std::io::ErrorKind::ConnectionRefused
+                | std::io::ErrorKind::ConnectionReset
+                | std::io::ErrorKind::ConnectionAborted
+                | std::io::ErrorKind::NotConnected
+                | std::io::ErrorKind::AddrNotAvailable
+                | std::io::ErrorKind::BrokenPipe
+                | std::io::ErrorKind::TimedOut
+                | std::io::ErrorKind::HostUnreachable
+                | std::io::ErrorKind::NetworkUnreachable => ErrorKind::Network,
+                _ => ErrorKind::Storage,
+            },
+            ClientError::InputError(_)
+            | ClientError::InvalidArgument(_)
+            | ClientError::EmptyInput { .. } => ErrorKind::Usage,
+            ClientError::AuthenticationError(_) | ClientError::ActionRequired { .. } => {
+                ErrorKind::Auth
+            }
+            ClientError::ServerBye(_)
+            | ClientError::TlsError { .. }
+            | ClientError::ConnectionError { .. }
+            | ClientError::HttpError { .. }
+            | ClientError::InvalidDnsName(_)
+            | ClientError::TlsConnectionFailed(_) => ErrorKind::Network,
+            ClientError::ImapError(_)
+            | ClientError::JmapError(_)
+            | ClientError::JmapMalformed { .. }
+            | ClientError::CommandFailed { .. }
+            | ClientError::ParseError
+            | ClientError::MalformedResponse(_)
+            | ClientError::Desync { .. } => ErrorKind::Server,
+            ClientError::DirectoryError { .. }
+            | ClientError::Json { .. }
+            | ClientError::StateError(_)
+            | ClientError::InsufficientSpace { .. } => ErrorKind::Storage,
+            ClientError::Locked { .. } => ErrorKind::Locked,
+            ClientError::UserCancelled
+            | ClientError::JoinError(_)
+            | ClientError::KeyringError { .. }
+            | ClientError::ReadOnly(_)
+            | ClientError::PluginError { .. }
+            | ClientError::Batch { .. } => ErrorKind::Other,
+        }
+    }
+
+    pub fn exit_code(&self) -> u8 {
+        self.kind().exit_code()
+    }
 }
@@ -0,0 +1,176 @@
+use std::collections::{BTreeMap, HashMap};
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::catalog::Catalog;
+use crate::dedupe::{collect_eml_files, relative_path};
+use crate::error_imap::ClientError;
+use crate::message::{parse_date, Headers};
+use crate::storage::{append_mbox, LineEndings};
+
+/// What an archive can be exported as for another mail program.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExportFormat {
+    /// Apple Mail mailboxes: a `<folder>.mbox/Messages/` directory of
+    /// `.emlx` files per folder, as File > Import Mailboxes reads them.
+    Emlx,
+    /// Thunderbird's Local Folders: an mbox file per folder, named after it
+    /// without an extension, with subfolders in a `<folder>.sbd` directory.
+    Thunderbird,
+}
+
+impl FromStr for ExportFormat {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "emlx" | "apple-mail" => Ok(ExportFormat::Emlx),
+            "thunderbird" => Ok(ExportFormat::Thunderbird),
+            other => Err(ClientError::InvalidArgument(format!(
+                "Unknown export format `{}` (expected emlx or thunderbird)",
+                other
+            ))),
+        }
+    }
+}
+
+/// What an export wrote.
+#[derive(Debug, Default)]
+pub struct ExportReport {
+    pub messages: usize,
+    pub folders: usize,
+}
+
+/// Exports every message of the archive at `dir` into `output` as `format`,
+/// keeping the archive's folders. The archive itself is left untouched.
+pub fn export_archive(
+    dir: &Path,
+    output: &Path,
+    format: ExportFormat,
+) -> Result<ExportReport, ClientError> {
+    let mut files = Vec::new();
+    collect_eml_files(dir, &mut files)?;
+    files.sort();
+
+    // Folders in the order they sort, each with its messages
+    let mut folders: BTreeMap<PathBuf, Vec<PathBuf>> = BTreeMap::new();
+    for file in files {
+        let folder = file
+            .parent()
+            .and_then(|parent| parent.strip_prefix(dir).ok())
+            .unwrap_or(Path::new(""))
+            .to_path_buf();
+        folders.entry(folder).or_default().push(file);
+    }
+
+    let received: HashMap<String, i64> = Catalog::open(dir)?
+        .entries()
+        .iter()
+        .filter_map(|entry| Some((entry.path.clone(), entry.internal_date?)))
+        .collect();
+
+    let mut report = ExportReport::default();
+    for (folder, files) in &folders {
+        match format {
+            ExportFormat::Emlx => {
+                let messages = emlx_mailbox(output, folder).join("Messages");
+                std::fs::create_dir_all(&messages)?;
+                for (index, file) in files.iter().enumerate() {
+                    let raw = std::fs::read(file)?;
+                    let date = received
+                        .get(&relative_path(dir, file))
+                        .copied()
+                        .or_else(|| Headers::parse(&raw).get("Date").and_then(parse_date));
+                    let target = messages.join(format!("{}.emlx", index + 1));
+                    std::fs::write(&target, emlx(&raw, date))?;
+                    log::debug!("Exported {} to {}", file.display(), target.display());
+                }
+            }
+            ExportFormat::Thunderbird => {
+                let mbox = thunderbird_mailbox(output, folder)?;
+                let mut writer = BufWriter::new(File::create(&mbox)?);
+                for file in files {
+                    let mut message = Vec::new();
+                    append_mbox(&mut message, &std::fs::read(file)?);
+                    writer.write_all(&message)?;
+                }
+                writer.flush()?;
+                log::debug!("Exported {} messages to {}", files.len(), mbox.display());
+            }
+        }
+        report.messages += files.len();
+        report.folders += 1;
+    }
+    Ok(report)
+}
+
+/// Path of the Thunderbird mbox for archive `folder` under `output`, creating
+/// the `.sbd` directories of its parents. Thunderbird only lists a subfolder
+/// whose parent has an mbox too, so an empty one is made where needed.
+fn thunderbird_mailbox(output: &Path, folder: &Path) -> Result<PathBuf, ClientError> {
+    std::fs::create_dir_all(output)?;
+    let mut names: Vec<String> = folder
+        .components()
+        .map(|component| component.as_os_str().to_string_lossy().into_owned())
+        .collect();
+    // Thunderbird's own name for the inbox
+    match names.first_mut() {
+        Some(first) if first == "INBOX" => *first = "Inbox".to_string(),
+        Some(_) => {}
+        None => return Ok(output.join("Inbox")),
+    }
+
+    let mut dir = output.to_path_buf();
+    for name in &names[..names.len() - 1] {
+        let mbox = dir.join(name);
+        if !mbox.exists() {
+            File::create(&mbox)?;
+        }
+        dir = dir.join(format!("{}.sbd", name));
+        std::fs::create_dir_all(&dir)?;
+    }
+    Ok(dir.join(&names[names.len() - 1]))
+}
+
+/// Directory of the Apple Mail mailbox for archive `folder` under `output`.
+/// INBOX, at the archive root, is `INBOX.mbox`; subfolders nest inside their
+/// parent's mailbox, as Mail keeps them.
+fn emlx_mailbox(output: &Path, folder: &Path) -> PathBuf {
+    if folder.as_os_str().is_empty() {
+        return output.join("INBOX.mbox");
+    }
+    folder
+        .components()
+        .fold(output.to_path_buf(), |path, component| {
+            path.join(format!("{}.mbox", component.as_os_str().to_string_lossy()))
+        })
+}
+
+/// An `.emlx` file: the length of the message on its own line, the message
+/// with LF line endings, then a property list of what Mail keeps about it.
+pub fn emlx(raw: &[u8], received: Option<i64>) -> Vec<u8> {
+    let message = LineEndings::Lf.apply(raw);
+    // Mail pads the length to ten columns
+    let mut emlx = format!("{:<10}\n", message.len()).into_bytes();
+    emlx.extend_from_slice(&message);
+
+    let mut plist = String::from(
+        "<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n\
+         <!DOCTYPE plist PUBLIC \"-//Apple//DTD PLIST 1.0//EN\" \
+         \"http://www.apple.com/DTDs/PropertyList-1.0.dtd\">\n\
+         <plist version=\"1.0\">\n\
+         <dict>\n",
+    );
+    if let Some(received) = received {
+        plist.push_str(&format!(
+            "\t<key>date-received</key>\n\t<integer>{}</integer>\n",
+            received
+        ));
+    }
+    // Bit 0 marks the message read; an archive is history, not new mail
+    plist.push_str("\t<key>flags</key>\n\t<integer>1</integer>\n</dict>\n</plist>\n");
+    emlx.extend_from_slice(plist.as_bytes());
+    emlx
+}
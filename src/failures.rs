@@ -0,0 +1,82 @@
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+use crate::error_imap::ClientError;
+
+/// Name of the failure report written to the root of an archive directory.
+pub const FAILURES_FILE: &str = "failures.json";
+
+/// Messages of one batch that could not be fetched.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Failure {
+    pub mailbox: String,
+    /// Directory the mailbox is saved into.
+    pub dir: PathBuf,
+    pub uid_validity: u32,
+    pub uids: Vec<u32>,
+    /// Error class, e.g. `network` or `server`.
+    pub error_class: String,
+    pub error: String,
+    /// Text of the server's response, when the server rejected a command.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub server_response: Option<String>,
+    /// What the batch was doing when it failed, e.g. `select` or `save`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub phase: Option<String>,
+}
+
+impl Failure {
+    pub fn new(
+        mailbox: &str,
+        dir: &Path,
+        uid_validity: u32,
+        uids: Vec<u32>,
+        error: &ClientError,
+    ) -> Self {
+        let server_response = match error.root() {
+            ClientError::CommandFailed { text, .. }
+            | ClientError::ImapError(text)
+            | ClientError::ServerBye(text) => Some(text.clone()),
+            _ => None,
+        };
+        Failure {
+            mailbox: mailbox.to_string(),
+            dir: dir.to_path_buf(),
+            uid_validity,
+            uids,
+            error_class: error.kind().name().to_string(),
+            error: error.to_string(),
+            server_response,
+            phase: error
+                .context()
+                .map(|context| context.phase.name().to_string()),
+        }
+    }
+}
+
+/// Writes the failure report for the archive rooted at `dir`, or removes a
+/// stale one when nothing failed. Returns the report's path if one was written.
+pub fn write_failures(dir: &Path, failures: &[Failure]) -> Result<Option<PathBuf>, ClientError> {
+    let path = dir.join(FAILURES_FILE);
+    if failures.is_empty() {
+        if path.exists() {
+            std::fs::remove_file(&path).map_err(|e| ClientError::io(&path, e))?;
+        }
+        return Ok(None);
+    }
+
+    let json = serde_json::to_string_pretty(failures).map_err(|source| ClientError::Json {
+        path: path.clone(),
+        source,
+    })?;
+    std::fs::write(&path, json + "\n").map_err(|e| ClientError::io(&path, e))?;
+    Ok(Some(path))
+}
+
+pub fn read_failures(path: &Path) -> Result<Vec<Failure>, ClientError> {
+    let json = std::fs::read_to_string(path).map_err(|e| ClientError::io(path, e))?;
+    serde_json::from_str(&json).map_err(|source| ClientError::Json {
+        path: path.to_path_buf(),
+        source,
+    })
+}
@@ -0,0 +1,102 @@
+use std::time::Duration;
+
+use crate::fixtures::Rng;
+
+/// Failures [`crate::mock::MockServer::start_faulty`] injects into its
+/// responses, each with a probability per command, so tests can check that
+/// retries and resumed runs still end with every message archived once.
+///
+/// Connection `n` draws its faults from the seed and `n`, so a run over
+/// one connection at a time meets the same faults every time. With several
+/// at once, which connection gets which number depends on timing.
+#[derive(Debug, Clone)]
+pub struct Faults {
+    pub seed: u64,
+    /// Chance that the connection is dropped partway through a response.
+    pub drop: f64,
+    /// Chance that a response is held back for up to `max_delay`.
+    pub delay: f64,
+    pub max_delay: Duration,
+    /// Chance that a command fails with NO, as a busy server answers.
+    pub no: f64,
+    /// Chance that a command is refused with BAD.
+    pub bad: f64,
+    /// Chance that the first body in a FETCH response is announced at half
+    /// its length, as a server miscounting a literal would.
+    pub miscount: f64,
+    /// Commands faults are injected into, such as `FETCH`; all if empty.
+    pub commands: Vec<String>,
+}
+
+impl Default for Faults {
+    fn default() -> Self {
+        Faults {
+            seed: 1,
+            drop: 0.0,
+            delay: 0.0,
+            max_delay: Duration::from_millis(50),
+            no: 0.0,
+            bad: 0.0,
+            miscount: 0.0,
+            commands: Vec::new(),
+        }
+    }
+}
+
+/// What happens to the response to one command.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Fault {
+    /// The connection is dropped after this fraction of the response.
+    Drop(f64),
+    Delay(Duration),
+    No,
+    Bad,
+    Miscount,
+}
+
+impl Faults {
+    /// The faults of connection `connection`.
+    pub fn injector(&self, connection: usize) -> Injector {
+        Injector {
+            faults: self.clone(),
+            rng: Rng::new(self.seed, connection),
+        }
+    }
+}
+
+/// Draws the faults of one connection, command by command.
+pub struct Injector {
+    faults: Faults,
+    rng: Rng,
+}
+
+impl Injector {
+    /// The fault injected into the response to `command`, if any.
+    pub fn next(&mut self, command: &str) -> Option<Fault> {
+        let faults = &self.faults;
+        if !faults.commands.is_empty()
+            && !faults
+                .commands
+                .iter()
+                .any(|name| name.eq_ignore_ascii_case(command))
+        {
+            return None;
+        }
+        // One draw picks at most one fault, in the order of the fields
+        let draw = self.rng.fraction();
+        let fault = if draw < faults.drop {
+            Fault::Drop(self.rng.fraction())
+        } else if draw < faults.drop + faults.delay {
+            Fault::Delay(faults.max_delay.mul_f64(self.rng.fraction()))
+        } else if draw < faults.drop + faults.delay + faults.no {
+            Fault::No
+        } else if draw < faults.drop + faults.delay + faults.no + faults.bad {
+            Fault::Bad
+        } else if draw < faults.drop + faults.delay + faults.no + faults.bad + faults.miscount {
+            Fault::Miscount
+        } else {
+            return None;
+        };
+        Some(fault)
+    }
+}
@@ -0,0 +1,540 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use std::ops::RangeInclusive;
+
+use crate::message::civil_from_days;
+use crate::mock::MockMailbox;
+
+/// How a generated message's MIME parts are laid out.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Structure {
+    /// A single `text/plain` part.
+    Plain,
+    /// A single `text/html` part.
+    Html,
+    /// `multipart/alternative` with the same text as plain text and HTML.
+    Alternative,
+    /// `multipart/mixed` with a text part and a binary attachment.
+    Attachment,
+    /// `multipart/mixed` holding a `multipart/alternative` and an
+    /// attachment, as most mail programs send an attachment.
+    Nested,
+    /// `multipart/mixed` with a text part and a forwarded `message/rfc822`.
+    Forwarded,
+}
+
+impl Structure {
+    pub const ALL: [Structure; 6] = [
+        Structure::Plain,
+        Structure::Html,
+        Structure::Alternative,
+        Structure::Attachment,
+        Structure::Nested,
+        Structure::Forwarded,
+    ];
+}
+
+/// The charset a generated message's text is in, and with it the
+/// transfer encoding and language of the text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Charset {
+    /// Plain ASCII, sent as 7bit.
+    Ascii,
+    /// UTF-8, sent as 8bit or base64.
+    Utf8,
+    /// ISO-8859-1, sent as quoted-printable.
+    Latin1,
+    /// Shift_JIS, sent as base64.
+    ShiftJis,
+    /// KOI8-R, sent as 8bit.
+    Koi8R,
+}
+
+impl Charset {
+    pub const ALL: [Charset; 5] = [
+        Charset::Ascii,
+        Charset::Utf8,
+        Charset::Latin1,
+        Charset::ShiftJis,
+        Charset::Koi8R,
+    ];
+
+    /// The name given in `charset=` parameters and encoded-words.
+    pub fn label(self) -> &'static str {
+        match self {
+            Charset::Ascii => "us-ascii",
+            Charset::Utf8 => "utf-8",
+            Charset::Latin1 => "iso-8859-1",
+            Charset::ShiftJis => "shift_jis",
+            Charset::Koi8R => "koi8-r",
+        }
+    }
+
+    /// Words text in this charset is made of.
+    fn words(self) -> &'static [&'static str] {
+        match self {
+            Charset::Ascii => &[
+                "project", "meeting", "report", "budget", "schedule", "review", "draft", "update",
+                "quarter", "invoice", "team", "launch", "notes", "agenda",
+            ],
+            Charset::Utf8 => &[
+                "café",
+                "naïve",
+                "résumé",
+                "Grüße",
+                "Straße",
+                "niño",
+                "smörgåsbord",
+                "déjà",
+                "Zürich",
+                "📬",
+                "€100",
+                "—",
+                "Ελλάδα",
+                "日本",
+            ],
+            Charset::Latin1 => &[
+                "façade", "crème", "brûlée", "garçon", "über", "año", "señor", "Øresund", "fête",
+                "naïve", "Ærø", "£20",
+            ],
+            Charset::ShiftJis => &[
+                "会議",
+                "報告書",
+                "予定",
+                "確認",
+                "お願いします",
+                "ありがとう",
+                "資料",
+                "来週",
+                "カレンダー",
+                "東京",
+            ],
+            Charset::Koi8R => &[
+                "встреча",
+                "отчёт",
+                "бюджет",
+                "проект",
+                "спасибо",
+                "неделя",
+                "команда",
+                "Москва",
+                "письмо",
+            ],
+        }
+    }
+
+    /// `text` in this charset, or `None` if it has characters the charset
+    /// lacks.
+    fn encode(self, text: &str) -> Option<Vec<u8>> {
+        let encoding = match self {
+            Charset::Ascii => return text.is_ascii().then(|| text.as_bytes().to_vec()),
+            Charset::Utf8 => return Some(text.as_bytes().to_vec()),
+            Charset::Latin1 => encoding_rs::WINDOWS_1252,
+            Charset::ShiftJis => encoding_rs::SHIFT_JIS,
+            Charset::Koi8R => encoding_rs::KOI8_R,
+        };
+        let (bytes, _, unmappable) = encoding.encode(text);
+        (!unmappable).then(|| bytes.into_owned())
+    }
+}
+
+/// Synthesizes a mailbox of messages for the mock server, the same every
+/// time for the same settings: message `i` depends only on the seed and
+/// `i`, so a fixture with more messages starts with those of one with
+/// fewer.
+///
+/// ```
+/// use imap_client::fixtures::{Charset, Fixture};
+///
+/// let fixture = Fixture {
+///     messages: 50,
+///     charsets: vec![Charset::Latin1],
+///     duplicate_every: 10,
+///     ..Fixture::default()
+/// };
+/// assert_eq!(fixture.message(7), fixture.message(7));
+/// assert_eq!(fixture.mailbox("INBOX").messages.len(), 50);
+/// ```
+#[derive(Debug, Clone)]
+pub struct Fixture {
+    pub seed: u64,
+    pub messages: usize,
+    /// Sizes in bytes messages are spread over. Each comes within a line of
+    /// text under its size, or past it when one line is already more.
+    pub sizes: RangeInclusive<usize>,
+    /// Layouts messages are given, picked at random.
+    pub structures: Vec<Structure>,
+    /// Charsets messages are written in, picked at random.
+    pub charsets: Vec<Charset>,
+    /// Every this many messages, one is an exact copy of an earlier one,
+    /// Message-ID and all; 0 for none.
+    pub duplicate_every: usize,
+}
+
+impl Default for Fixture {
+    fn default() -> Self {
+        Fixture {
+            seed: 1,
+            messages: 100,
+            sizes: 2 << 10..=20 << 10,
+            structures: Structure::ALL.to_vec(),
+            charsets: Charset::ALL.to_vec(),
+            duplicate_every: 0,
+        }
+    }
+}
+
+/// Where generated messages are dated from: 2024-01-01 00:00 UTC.
+const EPOCH: i64 = 1_704_067_200;
+
+impl Fixture {
+    pub fn mailbox(&self, name: &str) -> MockMailbox {
+        MockMailbox {
+            name: name.to_string(),
+            messages: (0..self.messages).map(|i| self.message(i)).collect(),
+        }
+    }
+
+    /// The messages spread over mailboxes named `names`, message `i` going
+    /// to mailbox `i % names.len()`.
+    pub fn mailboxes(&self, names: &[&str]) -> Vec<MockMailbox> {
+        let mut mailboxes: Vec<MockMailbox> = names
+            .iter()
+            .map(|name| MockMailbox {
+                name: name.to_string(),
+                messages: Vec::new(),
+            })
+            .collect();
+        for i in 0..self.messages {
+            mailboxes[i % names.len()].messages.push(self.message(i));
+        }
+        mailboxes
+    }
+
+    /// Whether message `i` is a copy of an earlier one.
+    pub fn is_duplicate(&self, i: usize) -> bool {
+        self.duplicate_every > 0 && i > 0 && i % self.duplicate_every == self.duplicate_every - 1
+    }
+
+    /// Message `i` of the fixture.
+    pub fn message(&self, i: usize) -> Vec<u8> {
+        let mut rng = Rng::new(self.seed, i);
+        if self.is_duplicate(i) {
+            return self.message(rng.below(i));
+        }
+        let (min, max) = (*self.sizes.start(), *self.sizes.end());
+        let size = min + rng.below(max.saturating_sub(min) + 1);
+        let spec = Spec {
+            index: i,
+            seed: self.seed,
+            structure: *rng.pick(&self.structures).unwrap_or(&Structure::Plain),
+            charset: *rng.pick(&self.charsets).unwrap_or(&Charset::Ascii),
+            base64_utf8: rng.below(2) == 0,
+            sender: rng.below(SENDERS.len()),
+            date: EPOCH + (i as i64) * 3_607 + rng.below(3_600) as i64,
+            attachment: size / 3,
+            text_seed: rng.next(),
+        };
+        // The most lines of text the message holds within its size
+        let (mut fits, mut over) = (1, 2);
+        while spec.build(over).len() <= size {
+            (fits, over) = (over, over * 2);
+        }
+        while over - fits > 1 {
+            let mid = (fits + over) / 2;
+            match spec.build(mid).len() <= size {
+                true => fits = mid,
+                false => over = mid,
+            }
+        }
+        spec.build(fits)
+    }
+}
+
+/// Senders' display names and addresses; the names are in the message's
+/// charset when they are not ASCII.
+const SENDERS: [(&str, &str); 6] = [
+    ("Alice Martin", "alice@example.com"),
+    ("Bob Chen", "bob@example.org"),
+    ("Renée Dubois", "renee@example.fr"),
+    ("Jürgen Weiß", "juergen@example.de"),
+    ("Notifications", "no-reply@example.net"),
+    ("Carol O'Brien", "carol@example.ie"),
+];
+
+/// Everything picked for one message, from which it is built with a given
+/// number of lines of text.
+struct Spec {
+    index: usize,
+    seed: u64,
+    structure: Structure,
+    charset: Charset,
+    base64_utf8: bool,
+    sender: usize,
+    date: i64,
+    /// Bytes of attachment, before encoding.
+    attachment: usize,
+    text_seed: u64,
+}
+
+impl Spec {
+    fn build(&self, lines: usize) -> Vec<u8> {
+        let mut rng = Rng(self.text_seed);
+        let subject = sentence(&mut rng, self.charset);
+        let text: Vec<String> = (0..lines)
+            .map(|_| sentence(&mut rng, self.charset))
+            .collect();
+        let (name, address) = SENDERS[self.sender];
+        let mut message = format!(
+            "From: {} <{}>\r\n\
+             To: fixtures@example.com\r\n\
+             Subject: {}\r\n\
+             Date: {}\r\n\
+             Message-ID: <{}.{}@fixtures.example.com>\r\n\
+             MIME-Version: 1.0\r\n",
+            self.header_text(name),
+            address,
+            self.header_text(&subject),
+            rfc2822_date(self.date),
+            self.seed,
+            self.index
+        )
+        .into_bytes();
+
+        let boundary = |kind: &str| format!("=_{}_{}_{}", kind, self.seed, self.index);
+        let plain = || self.text_part("plain", &text.join("\r\n"));
+        let html = || {
+            let paragraphs: Vec<String> =
+                text.iter().map(|line| format!("<p>{}</p>", line)).collect();
+            let html = format!(
+                "<html><body>\r\n{}\r\n</body></html>",
+                paragraphs.join("\r\n")
+            );
+            self.text_part("html", &html)
+        };
+        let alternative = || multipart("alternative", &boundary("alt"), vec![plain(), html()]);
+        let entity = match self.structure {
+            Structure::Plain => plain(),
+            Structure::Html => html(),
+            Structure::Alternative => alternative(),
+            Structure::Attachment => multipart(
+                "mixed",
+                &boundary("mix"),
+                vec![plain(), self.attachment_part()],
+            ),
+            Structure::Nested => multipart(
+                "mixed",
+                &boundary("mix"),
+                vec![alternative(), self.attachment_part()],
+            ),
+            Structure::Forwarded => {
+                let forwarded = format!(
+                    "Content-Type: message/rfc822\r\n\
+                     Content-Disposition: inline\r\n\r\n\
+                     From: Dana Park <dana@example.com>\r\n\
+                     Subject: Original message {}\r\n\
+                     Date: {}\r\n\
+                     Message-ID: <{}.{}.inner@fixtures.example.com>\r\n\
+                     Content-Type: text/plain; charset=us-ascii\r\n\r\n\
+                     The forwarded message, as it was sent.\r\n",
+                    self.index,
+                    rfc2822_date(self.date - 86_400),
+                    self.seed,
+                    self.index
+                );
+                multipart(
+                    "mixed",
+                    &boundary("mix"),
+                    vec![plain(), forwarded.into_bytes()],
+                )
+            }
+        };
+        message.extend_from_slice(&entity);
+        message
+    }
+
+    /// `text` for a header: as it is in ASCII, or as an RFC 2047
+    /// encoded-word in the message's charset. Names the charset lacks are sent in UTF-8, as mail programs do.
+    fn header_text(&self, text: &str) -> String {
+        if text.is_ascii() {
+            return text.to_string();
+        }
+        let (charset, bytes) = match self.charset.encode(text) {
+            Some(bytes) => (self.charset, bytes),
+            None => (Charset::Utf8, text.as_bytes().to_vec()),
+        };
+        match charset {
+            Charset::Latin1 => {
+                let encoded: String = bytes
+                    .iter()
+                    .map(|&b| match b {
+                        b' ' => "_".to_string(),
+                        b'0'..=b'9' | b'A'..=b'Z' | b'a'..=b'z' => (b as char).to_string(),
+                        _ => format!("={:02X}", b),
+                    })
+                    .collect();
+                format!("=?{}?Q?{}?=", charset.label(), encoded)
+            }
+            _ => format!("=?{}?B?{}?=", charset.label(), STANDARD.encode(bytes)),
+        }
+    }
+
+    /// A `text/{subtype}` part holding `text`, encoded for the charset.
+    fn text_part(&self, subtype: &str, text: &str) -> Vec<u8> {
+        // Text is made of the charset's own words
+        let bytes = self.charset.encode(text).unwrap_or_default();
+        let (encoding, body) = match self.charset {
+            Charset::Ascii => ("7bit", bytes),
+            Charset::Utf8 if !self.base64_utf8 => ("8bit", bytes),
+            Charset::Koi8R => ("8bit", bytes),
+            Charset::Utf8 | Charset::ShiftJis => ("base64", wrapped_base64(&bytes)),
+            Charset::Latin1 => ("quoted-printable", quoted_printable(&bytes)),
+        };
+        let mut part = format!(
+            "Content-Type: text/{}; charset={}\r\nContent-Transfer-Encoding: {}\r\n\r\n",
+            subtype,
+            self.charset.label(),
+            encoding
+        )
+        .into_bytes();
+        part.extend_from_slice(&body);
+        part.extend_from_slice(b"\r\n");
+        part
+    }
+
+    fn attachment_part(&self) -> Vec<u8> {
+        let mut rng = Rng(!self.text_seed);
+        let data: Vec<u8> = (0..self.attachment).map(|_| rng.next() as u8).collect();
+        let mut part = format!(
+            "Content-Type: application/octet-stream; name=\"data-{0}.bin\"\r\n\
+             Content-Disposition: attachment; filename=\"data-{0}.bin\"\r\n\
+             Content-Transfer-Encoding: base64\r\n\r\n",
+            self.index
+        )
+        .into_bytes();
+        part.extend_from_slice(&wrapped_base64(&data));
+        part.extend_from_slice(b"\r\n");
+        part
+    }
+}
+
+/// A `multipart/{subtype}` entity of `parts`, each with its own headers.
+fn multipart(subtype: &str, boundary: &str, parts: Vec<Vec<u8>>) -> Vec<u8> {
+    let mut entity = format!(
+        "Content-Type: multipart/{}; boundary=\"{}\"\r\n\r\n\
+         This is a multi-part message in MIME format.\r\n",
+        subtype, boundary
+    )
+    .into_bytes();
+    for part in parts {
+        entity.extend_from_slice(format!("--{}\r\n", boundary).as_bytes());
+        entity.extend_from_slice(&part);
+    }
+    entity.extend_from_slice(format!("--{}--\r\n", boundary).as_bytes());
+    entity
+}
+
+/// A line of five to twelve words in `charset`.
+fn sentence(rng: &mut Rng, charset: Charset) -> String {
+    let words = charset.words();
+    let count = 5 + rng.below(8);
+    let mut sentence: Vec<&str> = (0..count).map(|_| words[rng.below(words.len())]).collect();
+    // Each line also holds some ASCII, as real text in any charset does
+    sentence.insert(rng.below(count), "ok");
+    sentence.join(" ")
+}
+
+fn wrapped_base64(bytes: &[u8]) -> Vec<u8> {
+    let encoded = STANDARD.encode(bytes);
+    let lines: Vec<&[u8]> = encoded.as_bytes().chunks(76).collect();
+    lines.join(&b"\r\n"[..])
+}
+
+/// `bytes` as quoted-printable (RFC 2045, section 6.7), keeping CRLF line
+/// breaks and adding soft ones to keep lines within 76 characters.
+fn quoted_printable(bytes: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(bytes.len() * 2);
+    for (n, line) in bytes.split(|&b| b == b'\n').enumerate() {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        if n > 0 {
+            encoded.extend_from_slice(b"\r\n");
+        }
+        let mut width = 0;
+        for (i, &b) in line.iter().enumerate() {
+            let last = i == line.len() - 1;
+            let literal = matches!(b, b'!'..=b'<' | b'>'..=b'~') || (b == b' ' && !last);
+            let piece = match literal {
+                true => vec![b],
+                false => format!("={:02X}", b).into_bytes(),
+            };
+            if width + piece.len() > 75 {
+                encoded.extend_from_slice(b"=\r\n");
+                width = 0;
+            }
+            width += piece.len();
+            encoded.extend_from_slice(&piece);
+        }
+    }
+    encoded
+}
+
+/// `timestamp` as an RFC 5322 date in UTC, as in `Mon, 1 Jan 2024 12:00:00 +0000`.
+fn rfc2822_date(timestamp: i64) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let days = timestamp.div_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    let seconds = timestamp.rem_euclid(86_400);
+    format!(
+        "{}, {} {} {} {:02}:{:02}:{:02} +0000",
+        DAYS[days.rem_euclid(7) as usize],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
+
+/// SplitMix64: small, fast and the same on every platform, which is all
+/// fixtures and injected faults need from their randomness.
+pub(crate) struct Rng(u64);
+
+impl Rng {
+    /// The generator for item `index` of what is seeded `seed`, such as
+    /// a fixture's messages.
+    pub(crate) fn new(seed: u64, index: usize) -> Self {
+        let mut rng = Rng(seed ^ (index as u64).wrapping_mul(0xd1b5_4a32_d192_ed03));
+        rng.next();
+        rng
+    }
+
+    pub(crate) fn next(&mut self) -> u64 {
+        self.0 = self.0.wrapping_add(0x9e37_79b9_7f4a_7c15);
+        let mut z = self.0;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// A number from 0 up to, but not including, 1.
+    #[cfg_attr(not(feature = "faults"), allow(dead_code))]
+    pub(crate) fn fraction(&mut self) -> f64 {
+        (self.next() >> 11) as f64 / (1u64 << 53) as f64
+    }
+
+    /// A number below `n`, which must not be 0.
+    fn below(&mut self, n: usize) -> usize {
+        (self.next() % n as u64) as usize
+    }
+
+    fn pick<'a, T>(&mut self, items: &'a [T]) -> Option<&'a T> {
+        match items.len() {
+            0 => None,
+            len => items.get(self.below(len)),
+        }
+    }
+}
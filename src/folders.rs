@@ -0,0 +1,413 @@
+use base64::alphabet::Alphabet;
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig};
+use base64::engine::DecodePaddingMode;
+use base64::Engine;
+use std::fmt;
+
+/// A mailbox as reported by a LIST response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mailbox {
+    pub name: String,
+    pub delimiter: Option<char>,
+    pub attributes: Vec<String>,
+}
+
+impl Mailbox {
+    /// Whether the mailbox can be SELECTed (i.e. it is not `\Noselect` or `\NonExistent`).
+    pub fn is_selectable(&self) -> bool {
+        !self.attributes.iter().any(|a| {
+            a.eq_ignore_ascii_case("\\Noselect") || a.eq_ignore_ascii_case("\\NonExistent")
+        })
+    }
+
+    pub fn has_attribute(&self, attribute: &str) -> bool {
+        self.attributes
+            .iter()
+            .any(|a| a.eq_ignore_ascii_case(attribute))
+    }
+
+    /// What the server marked the mailbox as being for, if anything.
+    pub fn special_use(&self) -> Option<SpecialUse> {
+        self.attributes
+            .iter()
+            .find_map(|a| SpecialUse::from_attribute(a))
+    }
+
+    /// Path components of the mailbox name, split on its hierarchy delimiter.
+    pub fn components(&self) -> Vec<&str> {
+        match self.delimiter {
+            Some(delimiter) => self.name.split(delimiter).collect(),
+            None => vec![self.name.as_str()],
+        }
+    }
+
+    /// Path components of the mailbox name within the personal `namespace`,
+    /// so that Cyrus's `INBOX.Work` is just `Work`.
+    pub fn components_in(&self, namespace: &Namespace) -> Vec<&str> {
+        let name = namespace.relative(&self.name);
+        match self.delimiter.or(namespace.delimiter) {
+            Some(delimiter) => name.split(delimiter).collect(),
+            None => vec![name],
+        }
+    }
+}
+
+/// The personal namespace of an account (RFC 2342): the prefix its own
+/// folders are named under and their hierarchy delimiter. Gmail's is `""`
+/// and `/`; Cyrus and some Exchange setups use `INBOX.` and `.`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Namespace {
+    pub prefix: String,
+    pub delimiter: Option<char>,
+}
+
+impl Default for Namespace {
+    fn default() -> Self {
+        Namespace {
+            prefix: String::new(),
+            delimiter: Some('/'),
+        }
+    }
+}
+
+impl Namespace {
+    /// `name` without the namespace prefix. INBOX itself, and folders outside
+    /// the namespace, are left as they are.
+    pub fn relative<'a>(&self, name: &'a str) -> &'a str {
+        let len = self.prefix.len();
+        let prefixed = len > 0
+            && name.len() > len
+            && name.is_char_boundary(len)
+            // The INBOX part of a prefix is case-insensitive like INBOX itself
+            && name[..len].eq_ignore_ascii_case(&self.prefix);
+        if prefixed {
+            &name[len..]
+        } else {
+            name
+        }
+    }
+
+    /// The full name of the folder `relative` to the namespace, given with
+    /// `/` between levels.
+    pub fn full_name(&self, relative: &str) -> String {
+        let relative = match self.delimiter {
+            Some(delimiter) => relative.replace('/', &delimiter.to_string()),
+            None => relative.to_string(),
+        };
+        format!("{}{}", self.prefix, relative)
+    }
+}
+
+/// Parses the personal namespace out of the untagged part of a NAMESPACE
+/// response, e.g. `NAMESPACE (("INBOX." ".")) NIL (("shared." "."))`.
+/// Returns `None` if there is none.
+pub fn parse_namespace_response(line: &str) -> Option<Namespace> {
+    let rest = strip_keyword(line, "NAMESPACE")?.trim_start();
+    let rest = rest.strip_prefix("((")?;
+    let (prefix, rest) = parse_string(rest.trim_start())?;
+    let (delimiter, _) = parse_string(rest.trim_start())?;
+    Some(Namespace {
+        prefix: prefix.map(|p| decode_mailbox_name(&p)).unwrap_or_default(),
+        delimiter: delimiter.and_then(|d| d.chars().next()),
+    })
+}
+
+/// Parses the untagged part of a LIST response, e.g.
+/// `LIST (\HasNoChildren) "/" "[Gmail]/Sent Mail"`.
+pub fn parse_list_response(line: &str) -> Option<Mailbox> {
+    parse_list(line, false)
+}
+
+/// Like [`parse_list_response`], for a session with UTF8=ACCEPT enabled,
+/// where names are UTF-8 and an `&` is just an `&`.
+pub fn parse_utf8_list_response(line: &str) -> Option<Mailbox> {
+    parse_list(line, true)
+}
+
+fn parse_list(line: &str, utf8: bool) -> Option<Mailbox> {
+    let rest = strip_keyword(line, "LIST").or_else(|| strip_keyword(line, "XLIST"))?;
+
+    let rest = rest.trim_start().strip_prefix('(')?;
+    let close = rest.find(')')?;
+    let attributes = rest[..close]
+        .split_whitespace()
+        .map(|a| a.to_string())
+        .collect();
+
+    let (delimiter, rest) = parse_string(rest[close + 1..].trim_start())?;
+    let delimiter = delimiter.and_then(|d| d.chars().next());
+    let (name, _) = parse_string(rest.trim_start())?;
+
+    let name = name?;
+    Some(Mailbox {
+        name: if utf8 {
+            name
+        } else {
+            decode_mailbox_name(&name)
+        },
+        delimiter,
+        attributes,
+    })
+}
+
+/// What a special folder is for, from its LIST attributes (RFC 6154).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SpecialUse {
+    /// Every message, as in Gmail's All Mail.
+    All,
+    Archive,
+    Drafts,
+    /// Flagged messages, as in Gmail's Starred.
+    Flagged,
+    Junk,
+    Sent,
+    Trash,
+}
+
+impl SpecialUse {
+    pub const ALL: [SpecialUse; 7] = [
+        SpecialUse::All,
+        SpecialUse::Archive,
+        SpecialUse::Drafts,
+        SpecialUse::Flagged,
+        SpecialUse::Junk,
+        SpecialUse::Sent,
+        SpecialUse::Trash,
+    ];
+
+    /// The LIST attribute, e.g. `\Sent`.
+    pub fn attribute(self) -> &'static str {
+        match self {
+            SpecialUse::All => "\\All",
+            SpecialUse::Archive => "\\Archive",
+            SpecialUse::Drafts => "\\Drafts",
+            SpecialUse::Flagged => "\\Flagged",
+            SpecialUse::Junk => "\\Junk",
+            SpecialUse::Sent => "\\Sent",
+            SpecialUse::Trash => "\\Trash",
+        }
+    }
+
+    /// Parses a LIST attribute, accepting the names XLIST used before
+    /// SPECIAL-USE (`\AllMail`, `\Spam`, `\Starred`).
+    pub fn from_attribute(attribute: &str) -> Option<Self> {
+        let name = attribute.strip_prefix('\\')?;
+        let special = match name.to_ascii_lowercase().as_str() {
+            "allmail" => SpecialUse::All,
+            "spam" => SpecialUse::Junk,
+            "starred" => SpecialUse::Flagged,
+            _ => *Self::ALL
+                .iter()
+                .find(|special| special.attribute()[1..].eq_ignore_ascii_case(name))?,
+        };
+        Some(special)
+    }
+}
+
+impl fmt::Display for SpecialUse {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.attribute())
+    }
+}
+
+/// Finds the folder marked for `special` use, falling back to the one called
+/// `name` within `namespace` on servers that mark none. Folder names are
+/// localized, so the attribute wins whenever a server has it.
+pub fn find_special_folder<'a>(
+    mailboxes: &'a [Mailbox],
+    special: SpecialUse,
+    name: &str,
+    namespace: &Namespace,
+) -> Option<&'a Mailbox> {
+    let full_name = namespace.full_name(name);
+    mailboxes
+        .iter()
+        .find(|mailbox| mailbox.special_use() == Some(special))
+        .or_else(|| {
+            mailboxes.iter().find(|mailbox| {
+                mailbox.name.eq_ignore_ascii_case(&full_name)
+                    || mailbox.name.eq_ignore_ascii_case(name)
+            })
+        })
+}
+
+fn strip_keyword<'a>(line: &'a str, keyword: &str) -> Option<&'a str> {
+    let (word, rest) = line.split_once(' ')?;
+    word.eq_ignore_ascii_case(keyword).then_some(rest)
+}
+
+/// Parses a quoted string, `NIL` or an atom at the start of `input`,
+/// returning its value and the remaining input.
+pub(crate) fn parse_string(input: &str) -> Option<(Option<String>, &str)> {
+    if let Some(quoted) = input.strip_prefix('"') {
+        let mut value = String::new();
+        let mut chars = quoted.char_indices();
+        while let Some((i, c)) = chars.next() {
+            match c {
+                '\\' => value.push(chars.next()?.1),
+                '"' => return Some((Some(value), &quoted[i + 1..])),
+                _ => value.push(c),
+            }
+        }
+        return None;
+    }
+
+    // An atom can't contain `)`, so one may end a list
+    let end = input.find([' ', ')']).unwrap_or(input.len());
+    let atom = &input[..end];
+    if atom.is_empty() {
+        return None;
+    }
+    if atom.eq_ignore_ascii_case("NIL") {
+        return Some((None, &input[end..]));
+    }
+    Some((Some(atom.to_string()), &input[end..]))
+}
+
+/// Matches `name` against a glob `pattern`, where `*` matches any run of
+/// characters (including the hierarchy delimiter) and `?` matches exactly one.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+    let (mut p, mut n) = (0, 0);
+    let mut backtrack: Option<(usize, usize)> = None;
+
+    while n < name.len() {
+        if p < pattern.len() && (pattern[p] == '?' || pattern[p] == name[n]) {
+            p += 1;
+            n += 1;
+        } else if p < pattern.len() && pattern[p] == '*' {
+            backtrack = Some((p, n));
+            p += 1;
+        } else if let Some((star, matched)) = backtrack {
+            p = star + 1;
+            n = matched + 1;
+            backtrack = Some((star, matched + 1));
+        } else {
+            return false;
+        }
+    }
+
+    pattern[p..].iter().all(|&c| c == '*')
+}
+
+/// Include/exclude rules for mailbox names. A mailbox is selected when it
+/// matches at least one include pattern (or there are none) and no exclude pattern.
+/// A pattern naming a special-use attribute, such as `\Junk`, matches the
+/// folder marked with it whatever its name.
+#[derive(Debug, Clone, Default)]
+pub struct FolderFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl FolderFilter {
+    pub fn matches(&self, name: &str) -> bool {
+        self.selects(name, None)
+    }
+
+    /// Like [`FolderFilter::matches`], also matching special-use patterns.
+    pub fn matches_mailbox(&self, mailbox: &Mailbox) -> bool {
+        self.selects(&mailbox.name, mailbox.special_use())
+    }
+
+    fn selects(&self, name: &str, special: Option<SpecialUse>) -> bool {
+        let matches = |pattern: &String| match SpecialUse::from_attribute(pattern) {
+            Some(wanted) => special == Some(wanted),
+            None => glob_match(pattern, name),
+        };
+        let included = self.include.is_empty() || self.include.iter().any(matches);
+        included && !self.exclude.iter().any(matches)
+    }
+}
+
+/// Base64 variant used by modified UTF-7 (RFC 3501 5.1.3): `,` replaces `/`
+/// and no padding is used.
+const IMAP_UTF7_ALPHABET: Alphabet =
+    match Alphabet::new("ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+,") {
+        Ok(alphabet) => alphabet,
+        Err(_) => panic!("invalid modified UTF-7 alphabet"),
+    };
+
+const IMAP_UTF7: GeneralPurpose = GeneralPurpose::new(
+    &IMAP_UTF7_ALPHABET,
+    GeneralPurposeConfig::new()
+        .with_encode_padding(false)
+        .with_decode_padding_mode(DecodePaddingMode::RequireNone),
+);
+
+/// Decodes a mailbox name from modified UTF-7 as sent by LIST, e.g.
+/// `Facturas Espa&APE-a` becomes `Facturas España`. Malformed shifted
+/// sequences are kept verbatim rather than failing the whole listing.
+pub fn decode_mailbox_name(name: &str) -> String {
+    let mut decoded = String::with_capacity(name.len());
+    let mut rest = name;
+
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        let shifted = &rest[amp + 1..];
+        let Some(dash) = shifted.find('-') else {
+            decoded.push_str(&rest[amp..]);
+            return decoded;
+        };
+
+        let encoded = &shifted[..dash];
+        if encoded.is_empty() {
+            decoded.push('&');
+        } else {
+            match decode_utf16_run(encoded) {
+                Some(text) => decoded.push_str(&text),
+                None => decoded.push_str(&rest[amp..amp + dash + 2]),
+            }
+        }
+        rest = &shifted[dash + 1..];
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+fn decode_utf16_run(encoded: &str) -> Option<String> {
+    let bytes = IMAP_UTF7.decode(encoded).ok()?;
+    if bytes.len() % 2 != 0 {
+        return None;
+    }
+    let units: Vec<u16> = bytes
+        .chunks(2)
+        .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+        .collect();
+    String::from_utf16(&units).ok()
+}
+
+/// Encodes a UTF-8 mailbox name into modified UTF-7 for SELECT and friends.
+pub fn encode_mailbox_name(name: &str) -> String {
+    let mut encoded = String::with_capacity(name.len());
+    let mut pending: Vec<u16> = Vec::new();
+
+    let flush = |pending: &mut Vec<u16>, encoded: &mut String| {
+        if pending.is_empty() {
+            return;
+        }
+        let bytes: Vec<u8> = pending.iter().flat_map(|unit| unit.to_be_bytes()).collect();
+        encoded.push('&');
+        encoded.push_str(&IMAP_UTF7.encode(bytes));
+        encoded.push('-');
+        pending.clear();
+    };
+
+    for c in name.chars() {
+        if (' '..='~').contains(&c) {
+            flush(&mut pending, &mut encoded);
+            if c == '&' {
+                encoded.push_str("&-");
+            } else {
+                encoded.push(c);
+            }
+        } else {
+            let mut units = [0u16; 2];
+            pending.extend_from_slice(c.encode_utf16(&mut units));
+        }
+    }
+    flush(&mut pending, &mut encoded);
+    encoded
+}
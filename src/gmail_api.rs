@@ -0,0 +1,443 @@
+use base64::Engine;
+use serde::Deserialize;
+use std::collections::HashSet;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::audit::AuditLog;
+use crate::checkpoint::{SyncSchedule, SyncTimer};
+use crate::error_imap::ClientError;
+use crate::http::{check_status, http_error};
+
+/// Base URL of the Gmail API for the authenticated user.
+pub const DEFAULT_GMAIL_API_URL: &str = "https://gmail.googleapis.com/gmail/v1/users/me";
+
+/// Largest page `users.messages.list` returns.
+const MAX_PAGE: usize = 500;
+
+/// System labels that correspond to IMAP folders. The others (`UNREAD`,
+/// `STARRED`, `CATEGORY_*`, ...) only tag messages that live elsewhere.
+const FOLDER_LABELS: [&str; 5] = ["INBOX", "SENT", "DRAFT", "SPAM", "TRASH"];
+
+/// A label as returned by `users.labels.list`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct Label {
+    pub id: String,
+    pub name: String,
+    /// `system` or `user`.
+    #[serde(rename = "type", default)]
+    pub kind: String,
+}
+
+impl Label {
+    /// Whether the label stands for a folder worth archiving.
+    pub fn is_folder(&self) -> bool {
+        self.kind != "system" || FOLDER_LABELS.contains(&self.id.as_str())
+    }
+}
+
+/// What changed in the mailbox since a history ID, from `users.history.list`.
+#[derive(Debug, Default)]
+pub struct History {
+    /// Messages added, oldest first, with their labels.
+    pub added: Vec<(String, Vec<String>)>,
+    /// Messages that gained labels, with the labels added.
+    pub labels_added: Vec<(String, Vec<String>)>,
+    pub labels_removed: Vec<(String, Vec<String>)>,
+    pub deleted: Vec<String>,
+    /// The mailbox's history ID as of the listing, to start the next sync from.
+    pub history_id: String,
+}
+
+/// A message in `format=raw`.
+#[derive(Debug, Clone)]
+pub struct RawMessage {
+    pub thread_id: Option<String>,
+    /// When Gmail received the message, as a Unix timestamp.
+    pub internal_date: Option<i64>,
+    pub body: Vec<u8>,
+}
+
+/// A session with the Gmail API (`users.labels`, `users.messages`) for one
+/// account, authenticated with an OAuth 2.0 access token.
+///
+/// Only GET requests are made, so it can't modify the account.
+pub struct GmailApi {
+    http: reqwest::Client,
+    base_url: String,
+    token: String,
+    audit: Option<(Arc<AuditLog>, u32)>,
+}
+
+impl GmailApi {
+    pub fn new(
+        http: reqwest::Client,
+        base_url: &str,
+        token: String,
+        audit: Option<Arc<AuditLog>>,
+    ) -> Result<Self, ClientError> {
+        let audit = match audit {
+            Some(log) => {
+                let connection = log.open_connection(base_url)?;
+                Some((log, connection))
+            }
+            None => None,
+        };
+        Ok(GmailApi {
+            http,
+            base_url: base_url.trim_end_matches('/').to_string(),
+            token,
+            audit,
+        })
+    }
+
+    /// Records `event` against this session if it is audited.
+    pub fn audit(&self, event: &str) -> Result<(), ClientError> {
+        match &self.audit {
+            Some((log, connection)) => log.record(*connection, event),
+            None => Ok(()),
+        }
+    }
+
+    pub async fn labels(&self) -> Result<Vec<Label>, ClientError> {
+        #[derive(Deserialize)]
+        struct Labels {
+            #[serde(default)]
+            labels: Vec<Label>,
+        }
+        let labels: Labels = self.get("labels", &[]).await?;
+        Ok(labels.labels)
+    }
+
+    /// Number of messages with the label.
+    pub async fn label_total(&self, label_id: &str) -> Result<u32, ClientError> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct LabelDetails {
+            #[serde(default)]
+            messages_total: u32,
+        }
+        let details: LabelDetails = self.get(&format!("labels/{}", label_id), &[]).await?;
+        Ok(details.messages_total)
+    }
+
+    /// IDs of every message with the label, newest first.
+    pub async fn message_ids(&self, label_id: &str) -> Result<Vec<String>, ClientError> {
+        #[derive(Deserialize)]
+        struct Id {
+            id: String,
+        }
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Page {
+            #[serde(default)]
+            messages: Vec<Id>,
+            next_page_token: Option<String>,
+        }
+
+        let mut ids = Vec::new();
+        let mut page_token = None;
+        loop {
+            let max = MAX_PAGE.to_string();
+            let mut query = vec![
+                ("labelIds", label_id),
+                ("maxResults", max.as_str()),
+                ("includeSpamTrash", "true"),
+            ];
+            if let Some(token) = page_token.as_deref() {
+                query.push(("pageToken", token));
+            }
+            let page: Page = self.get("messages", &query).await?;
+            ids.extend(page.messages.into_iter().map(|m| m.id));
+            match page.next_page_token {
+                Some(token) => page_token = Some(token),
+                None => return Ok(ids),
+            }
+        }
+    }
+
+    /// The mailbox's current history ID.
+    pub async fn history_id(&self) -> Result<String, ClientError> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Profile {
+            history_id: String,
+        }
+        let profile: Profile = self.get("profile", &[]).await?;
+        Ok(profile.history_id)
+    }
+
+    /// Changes since `start`, or `None` if that history ID is too old for
+    /// Gmail to still have the changes and a full listing is needed.
+    pub async fn history(&self, start: &str) -> Result<Option<History>, ClientError> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Message {
+            id: String,
+            #[serde(default)]
+            label_ids: Vec<String>,
+        }
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Change {
+            message: Message,
+            #[serde(default)]
+            label_ids: Vec<String>,
+        }
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Record {
+            #[serde(default)]
+            messages_added: Vec<Change>,
+            #[serde(default)]
+            messages_deleted: Vec<Change>,
+            #[serde(default)]
+            labels_added: Vec<Change>,
+            #[serde(default)]
+            labels_removed: Vec<Change>,
+        }
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Page {
+            #[serde(default)]
+            history: Vec<Record>,
+            next_page_token: Option<String>,
+            history_id: String,
+        }
+
+        let mut history = History::default();
+        let mut page_token = None;
+        loop {
+            let max = MAX_PAGE.to_string();
+            let mut query = vec![("startHistoryId", start), ("maxResults", max.as_str())];
+            for kind in [
+                "messageAdded",
+                "messageDeleted",
+                "labelAdded",
+                "labelRemoved",
+            ] {
+                query.push(("historyTypes", kind));
+            }
+            if let Some(token) = page_token.as_deref() {
+                query.push(("pageToken", token));
+            }
+            // Gmail answers 404 once the start is older than the history it keeps
+            let Some(page) = self.get_optional::<Page>("history", &query).await? else {
+                return Ok(None);
+            };
+            for record in page.history {
+                let changes = |changes: Vec<Change>| {
+                    changes
+                        .into_iter()
+                        .map(|change| (change.message.id, change.label_ids))
+                };
+                history.added.extend(
+                    record
+                        .messages_added
+                        .into_iter()
+                        .map(|change| (change.message.id, change.message.label_ids)),
+                );
+                history.deleted.extend(
+                    record
+                        .messages_deleted
+                        .into_iter()
+                        .map(|change| change.message.id),
+                );
+                history.labels_added.extend(changes(record.labels_added));
+                history
+                    .labels_removed
+                    .extend(changes(record.labels_removed));
+            }
+            history.history_id = page.history_id;
+            match page.next_page_token {
+                Some(token) => page_token = Some(token),
+                None => return Ok(Some(history)),
+            }
+        }
+    }
+
+    /// Downloads a message as the raw RFC 5322 bytes Gmail stores.
+    pub async fn message(&self, id: &str) -> Result<RawMessage, ClientError> {
+        #[derive(Deserialize)]
+        #[serde(rename_all = "camelCase")]
+        struct Message {
+            thread_id: Option<String>,
+            /// Milliseconds since the epoch, as a string.
+            internal_date: Option<String>,
+            #[serde(default)]
+            raw: String,
+        }
+        let message: Message = self
+            .get(&format!("messages/{}", id), &[("format", "raw")])
+            .await?;
+        let body = base64::engine::general_purpose::URL_SAFE_NO_PAD
+            .decode(message.raw.trim_end_matches('='))
+            .map_err(|e| ClientError::http_caused(format!("message {}", id), e))?;
+        Ok(RawMessage {
+            thread_id: message.thread_id,
+            internal_date: message
+                .internal_date
+                .and_then(|ms| ms.parse::<i64>().ok())
+                .map(|ms| ms.div_euclid(1000)),
+            body,
+        })
+    }
+
+    async fn get<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<T, ClientError> {
+        self.get_optional(path, query)
+            .await?
+            .ok_or_else(|| ClientError::http(format!("{}/{}: 404 Not Found", self.base_url, path)))
+    }
+
+    /// Like `get`, but `None` for a 404.
+    async fn get_optional<T: serde::de::DeserializeOwned>(
+        &self,
+        path: &str,
+        query: &[(&str, &str)],
+    ) -> Result<Option<T>, ClientError> {
+        self.audit(&format!("request GET {}", path))?;
+        let response = self
+            .http
+            .get(format!("{}/{}", self.base_url, path))
+            .bearer_auth(&self.token)
+            .query(query)
+            .send()
+            .await
+            .map_err(http_error)?;
+        if response.status() == reqwest::StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+        check_status(response)?
+            .json()
+            .await
+            .map(Some)
+            .map_err(http_error)
+    }
+}
+
+/// Name of the file recording which Gmail API messages were fetched.
+pub const GMAIL_STATE_FILE: &str = ".gmail-api.tsv";
+
+/// Append-only record of an archive's sync with the Gmail API, the
+/// counterpart of the IMAP checkpoint. One tab-separated line per event:
+///
+/// - `message ID`: a message was fetched. IDs are unique and stable within an
+///   account, whatever labels a message has;
+/// - `history ID`: everything up to this history ID has been synced;
+/// - `deleted ID`, `labels ID +LABEL -LABEL ...`: changes seen in the history
+///   since. The archive keeps deleted messages, so these are only recorded.
+pub struct GmailState {
+    path: PathBuf,
+    fetched: HashSet<String>,
+    history_id: Option<String>,
+    file: Option<File>,
+    sync: SyncTimer,
+}
+
+impl GmailState {
+    pub fn open(dir: &Path) -> Result<Self, ClientError> {
+        let mut state = GmailState {
+            path: dir.join(GMAIL_STATE_FILE),
+            fetched: HashSet::new(),
+            history_id: None,
+            file: None,
+            sync: SyncTimer::default(),
+        };
+        let contents = match std::fs::read_to_string(&state.path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(state),
+            Err(e) => return Err(e.into()),
+        };
+        for line in contents.lines() {
+            match line.split_once('\t') {
+                Some(("message", id)) => {
+                    state.fetched.insert(id.to_string());
+                }
+                Some(("history", id)) => state.history_id = Some(id.to_string()),
+                Some(("deleted" | "labels", _)) => {}
+                _ => log::warn!("Ignoring malformed Gmail API state line: {}", line),
+            }
+        }
+        Ok(state)
+    }
+
+    pub fn is_fetched(&self, id: &str) -> bool {
+        self.fetched.contains(id)
+    }
+
+    /// History ID of the last complete sync.
+    pub fn history_id(&self) -> Option<&str> {
+        self.history_id.as_deref()
+    }
+
+    pub fn record(&mut self, id: &str) -> Result<(), ClientError> {
+        if !self.fetched.insert(id.to_string()) {
+            return Ok(());
+        }
+        self.append(&format!("message\t{}", id))
+    }
+
+    pub fn record_history_id(&mut self, id: &str) -> Result<(), ClientError> {
+        self.history_id = Some(id.to_string());
+        self.append(&format!("history\t{}", id))
+    }
+
+    /// Records the deletions and label changes in `history`, returning how
+    /// many there were.
+    pub fn record_changes(&mut self, history: &History) -> Result<usize, ClientError> {
+        let mut changes = 0;
+        for id in &history.deleted {
+            self.append(&format!("deleted\t{}", id))?;
+            changes += 1;
+        }
+        for (id, labels) in &history.labels_added {
+            let labels: Vec<String> = labels.iter().map(|l| format!("+{}", l)).collect();
+            self.append(&format!("labels\t{}\t{}", id, labels.join(" ")))?;
+            changes += 1;
+        }
+        for (id, labels) in &history.labels_removed {
+            let labels: Vec<String> = labels.iter().map(|l| format!("-{}", l)).collect();
+            self.append(&format!("labels\t{}\t{}", id, labels.join(" ")))?;
+            changes += 1;
+        }
+        Ok(changes)
+    }
+
+    fn append(&mut self, line: &str) -> Result<(), ClientError> {
+        if self.file.is_none() {
+            self.file = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?,
+            );
+        }
+        if let Some(file) = self.file.as_mut() {
+            writeln!(file, "{}", line)?;
+            if self.sync.is_due() {
+                file.sync_data()?;
+            }
+        }
+        Ok(())
+    }
+
+    pub fn set_sync_schedule(&mut self, schedule: SyncSchedule) {
+        self.sync = SyncTimer::new(schedule);
+    }
+
+    /// Forces everything recorded so far to disk.
+    pub fn sync(&mut self) -> Result<(), ClientError> {
+        self.sync.reset();
+        match &self.file {
+            Some(file) => Ok(file.sync_data()?),
+            None => Ok(()),
+        }
+    }
+}
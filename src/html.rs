@@ -0,0 +1,263 @@
+/// Renders an HTML document as plain text for grepping and indexing: tags are
+/// dropped, block elements become line breaks, `<script>`/`<style>` content is
+/// skipped, entities are decoded and links are kept as `text (url)`.
+pub fn html_to_text(html: &str) -> String {
+    let mut out = String::with_capacity(html.len() / 2);
+    let mut link: Option<(String, usize)> = None;
+    let mut in_pre = false;
+    let mut rest = html;
+
+    while !rest.is_empty() {
+        if let Some(after) = rest.strip_prefix("<!--") {
+            rest = after.find("-->").map_or("", |end| &after[end + 3..]);
+            continue;
+        }
+
+        if rest.starts_with('<') {
+            let Some(end) = rest.find('>') else {
+                push_text(&mut out, rest, in_pre);
+                break;
+            };
+            let tag = Tag::parse(&rest[1..end]);
+            rest = &rest[end + 1..];
+
+            match (tag.name.as_str(), tag.closing) {
+                ("script" | "style" | "head" | "title", false) => {
+                    let close = format!("</{}", tag.name);
+                    rest = find_ignore_case(rest, &close).map_or("", |pos| &rest[pos..]);
+                }
+                ("a", false) => {
+                    link = tag.attribute("href").map(|href| (href, out.len()));
+                }
+                ("a", true) => {
+                    if let Some((href, start)) = link.take() {
+                        let anchor = out[start..].trim();
+                        if !href.is_empty()
+                            && !href.starts_with('#')
+                            && anchor != href
+                            && !href.eq_ignore_ascii_case(&format!("mailto:{}", anchor))
+                        {
+                            out.push_str(&format!(" ({})", href));
+                        }
+                    }
+                }
+                ("br", _) => out.push('\n'),
+                ("li", false) => {
+                    newline(&mut out);
+                    out.push_str("- ");
+                }
+                ("pre", closing) => {
+                    in_pre = !closing;
+                    newline(&mut out);
+                }
+                ("td" | "th", true) => out.push('\t'),
+                ("hr", _) => {
+                    newline(&mut out);
+                    out.push_str("---\n");
+                }
+                (name, _) if is_block(name) => paragraph(&mut out),
+                _ => {}
+            }
+            continue;
+        }
+
+        let end = rest.find('<').unwrap_or(rest.len());
+        push_text(&mut out, &rest[..end], in_pre);
+        rest = &rest[end..];
+    }
+
+    tidy(&out)
+}
+
+struct Tag {
+    name: String,
+    closing: bool,
+    raw: String,
+}
+
+impl Tag {
+    fn parse(inner: &str) -> Self {
+        let inner = inner.trim();
+        let (closing, inner) = match inner.strip_prefix('/') {
+            Some(rest) => (true, rest),
+            None => (false, inner),
+        };
+        let name = inner
+            .split(|c: char| c.is_whitespace() || c == '/')
+            .next()
+            .unwrap_or_default()
+            .to_ascii_lowercase();
+        Tag {
+            name,
+            closing,
+            raw: inner.to_string(),
+        }
+    }
+
+    fn attribute(&self, name: &str) -> Option<String> {
+        let lower = self.raw.to_ascii_lowercase();
+        let mut search = 0;
+        while let Some(pos) = lower[search..].find(name) {
+            let start = search + pos;
+            search = start + name.len();
+            let preceded_by_space = lower[..start].ends_with(char::is_whitespace);
+            let after = lower[search..].trim_start();
+            if !preceded_by_space || !after.starts_with('=') {
+                continue;
+            }
+
+            let value_start = self.raw.len() - after.len() + 1;
+            let value = self.raw[value_start..].trim_start();
+            let value = match value.chars().next() {
+                Some(quote @ ('"' | '\'')) => {
+                    let body = &value[1..];
+                    &body[..body.find(quote).unwrap_or(body.len())]
+                }
+                _ => &value[..value.find(char::is_whitespace).unwrap_or(value.len())],
+            };
+            return Some(decode_entities(value));
+        }
+        None
+    }
+}
+
+fn is_block(name: &str) -> bool {
+    matches!(
+        name,
+        "p" | "div"
+            | "tr"
+            | "table"
+            | "ul"
+            | "ol"
+            | "blockquote"
+            | "section"
+            | "article"
+            | "header"
+            | "footer"
+            | "h1"
+            | "h2"
+            | "h3"
+            | "h4"
+            | "h5"
+            | "h6"
+    )
+}
+
+fn find_ignore_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .to_ascii_lowercase()
+        .find(&needle.to_ascii_lowercase())
+}
+
+fn push_text(out: &mut String, text: &str, in_pre: bool) {
+    let text = decode_entities(text);
+    if in_pre {
+        out.push_str(&text);
+        return;
+    }
+    for c in text.chars() {
+        if c.is_whitespace() && c != '\u{a0}' {
+            if !out.is_empty() && !out.ends_with([' ', '\n', '\t']) {
+                out.push(' ');
+            }
+        } else {
+            out.push(if c == '\u{a0}' { ' ' } else { c });
+        }
+    }
+}
+
+fn newline(out: &mut String) {
+    let trimmed = out.trim_end_matches(' ').len();
+    out.truncate(trimmed);
+    if !out.is_empty() && !out.ends_with('\n') {
+        out.push('\n');
+    }
+}
+
+fn paragraph(out: &mut String) {
+    newline(out);
+    if !out.is_empty() && !out.ends_with("\n\n") {
+        out.push('\n');
+    }
+}
+
+/// Trims trailing spaces and collapses runs of blank lines.
+fn tidy(text: &str) -> String {
+    let mut result = String::with_capacity(text.len());
+    let mut blank_run = 0;
+    for line in text.trim().lines() {
+        let line = line.trim_end();
+        if line.is_empty() {
+            blank_run += 1;
+            if blank_run > 1 {
+                continue;
+            }
+        } else {
+            blank_run = 0;
+        }
+        result.push_str(line);
+        result.push('\n');
+    }
+    result
+}
+
+/// Decodes the named entities common in email HTML plus numeric references.
+pub fn decode_entities(text: &str) -> String {
+    let mut decoded = String::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(amp) = rest.find('&') {
+        decoded.push_str(&rest[..amp]);
+        let candidate = &rest[amp + 1..];
+        let entity = candidate
+            .find(';')
+            .filter(|&end| end <= 10)
+            .and_then(|end| entity_char(&candidate[..end]).map(|c| (c, end)));
+        match entity {
+            Some((c, end)) => {
+                decoded.push(c);
+                rest = &candidate[end + 1..];
+            }
+            None => {
+                decoded.push('&');
+                rest = candidate;
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+fn entity_char(name: &str) -> Option<char> {
+    if let Some(number) = name.strip_prefix('#') {
+        let code = match number.strip_prefix(['x', 'X']) {
+            Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+            None => number.parse().ok()?,
+        };
+        return char::from_u32(code);
+    }
+    Some(match name {
+        "amp" => '&',
+        "lt" => '<',
+        "gt" => '>',
+        "quot" => '"',
+        "apos" => '\'',
+        "nbsp" => '\u{a0}',
+        "copy" => '©',
+        "reg" => '®',
+        "trade" => '™',
+        "euro" => '€',
+        "pound" => '£',
+        "mdash" => '—',
+        "ndash" => '–',
+        "hellip" => '…',
+        "lsquo" => '‘',
+        "rsquo" => '’',
+        "ldquo" => '“',
+        "rdquo" => '”',
+        "bull" => '•',
+        "middot" => '·',
+        _ => return None,
+    })
+}
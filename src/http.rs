@@ -0,0 +1,36 @@
+use std::net::SocketAddr;
+
+use crate::error_imap::ClientError;
+use crate::net::TOR_CONNECT_TIMEOUT;
+
+/// An HTTP client, going through the Tor SOCKS proxy `tor` if given. Host
+/// names are then resolved by Tor as well.
+pub fn client(tor: Option<SocketAddr>) -> Result<reqwest::Client, ClientError> {
+    let Some(proxy) = tor else {
+        return Ok(reqwest::Client::new());
+    };
+    let proxy = reqwest::Proxy::all(format!("socks5h://{}", proxy)).map_err(http_error)?;
+    reqwest::Client::builder()
+        .proxy(proxy)
+        .connect_timeout(TOR_CONNECT_TIMEOUT)
+        .build()
+        .map_err(http_error)
+}
+
+/// Fails on an HTTP error status; 401 and 403 mean the token was rejected.
+pub fn check_status(response: reqwest::Response) -> Result<reqwest::Response, ClientError> {
+    let status = response.status();
+    if status == reqwest::StatusCode::UNAUTHORIZED || status == reqwest::StatusCode::FORBIDDEN {
+        return Err(ClientError::AuthenticationError(format!(
+            "{} rejected the access token ({})",
+            response.url(),
+            status
+        )));
+    }
+    response.error_for_status().map_err(http_error)
+}
+
+pub fn http_error(error: reqwest::Error) -> ClientError {
+    // The error says what failed, and for which URL
+    ClientError::http_caused("", error)
+}
@@ -0,0 +1,256 @@
+use fluent_bundle::concurrent::FluentBundle;
+use fluent_bundle::{FluentArgs, FluentResource};
+use std::sync::OnceLock;
+use unic_langid::LanguageIdentifier;
+
+pub use fluent_bundle::FluentValue;
+
+use crate::diskspace::format_size;
+use crate::message::civil_from_days;
+
+/// Messages in English, which every other language falls back to.
+const ENGLISH: &str = include_str!("../locales/en.ftl");
+
+/// Languages with a translation, by tag, with how they write numbers and
+/// dates.
+const TRANSLATIONS: &[(&str, &str, Conventions)] = &[
+    ("en", ENGLISH, ENGLISH_CONVENTIONS),
+    (
+        "pt-BR",
+        include_str!("../locales/pt-BR.ftl"),
+        Conventions {
+            group: '.',
+            decimal: ',',
+            day_first: true,
+            separator: '/',
+        },
+    ),
+];
+
+const ENGLISH_CONVENTIONS: Conventions = Conventions {
+    group: ',',
+    decimal: '.',
+    day_first: false,
+    separator: '-',
+};
+
+/// How a language writes numbers and dates.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+struct Conventions {
+    /// Between groups of three digits, as in `1,234`.
+    group: char,
+    /// Before the fractional part, as in `1.5`.
+    decimal: char,
+    /// Dates written day, month, year instead of year, month, day.
+    day_first: bool,
+    /// Between the parts of a date.
+    separator: char,
+}
+
+impl Conventions {
+    /// `digits` (as in `-1234.5`) with groups of thousands marked and this
+    /// language's decimal mark.
+    fn number(&self, digits: &str, grouping: bool) -> String {
+        let (sign, digits) = match digits.strip_prefix('-') {
+            Some(rest) => ("-", rest),
+            None => ("", digits),
+        };
+        let (whole, fraction) = match digits.split_once('.') {
+            Some((whole, fraction)) => (whole, Some(fraction)),
+            None => (digits, None),
+        };
+        let mut out = sign.to_string();
+        for (i, digit) in whole.chars().enumerate() {
+            if grouping && i > 0 && (whole.len() - i) % 3 == 0 {
+                out.push(self.group);
+            }
+            out.push(digit);
+        }
+        if let Some(fraction) = fraction {
+            out.push(self.decimal);
+            out.push_str(fraction);
+        }
+        out
+    }
+}
+
+/// The language messages, numbers and dates are shown in.
+pub struct Locale {
+    tag: &'static str,
+    conventions: Conventions,
+    bundle: FluentBundle<FluentResource>,
+}
+
+impl Locale {
+    /// The supported language closest to `tag` (as in `pt-BR` or `pt_BR`):
+    /// the same language and region, or else the same language, or else
+    /// English.
+    pub fn new(tag: &str) -> Self {
+        let &(tag, source, conventions) = tag
+            .replace('_', "-")
+            .parse()
+            .ok()
+            .and_then(|wanted| translation(&wanted))
+            .unwrap_or(&TRANSLATIONS[0]);
+
+        let id = tag.parse().unwrap_or_default();
+        let mut bundle = FluentBundle::new_concurrent(vec![id]);
+        // Isolation marks around arguments would show up in terminals
+        bundle.set_use_isolating(false);
+        bundle.set_formatter(Some(if conventions.group == '.' {
+            dot_grouped
+        } else {
+            comma_grouped
+        }));
+        let _ = bundle.add_resource(resource(ENGLISH));
+        if source != ENGLISH {
+            bundle.add_resource_overriding(resource(source));
+        }
+        Locale {
+            tag,
+            conventions,
+            bundle,
+        }
+    }
+
+    /// The language asked for by `LC_ALL`, `LC_MESSAGES` or `LANG`, as in
+    /// `pt_BR.UTF-8`.
+    pub fn from_env() -> Self {
+        let tag = ["LC_ALL", "LC_MESSAGES", "LANG"]
+            .iter()
+            .filter_map(|name| std::env::var(name).ok())
+            .find(|value| !value.is_empty())
+            .unwrap_or_default();
+        // Drop the encoding and modifier
+        let tag = tag.split(['.', '@']).next().unwrap_or_default();
+        Locale::new(tag)
+    }
+
+    /// The tag of the language used, as in `pt-BR`.
+    pub fn tag(&self) -> &str {
+        self.tag
+    }
+
+    /// The message `id` with `args` filled in, or `id` itself if no
+    /// translation has it.
+    pub fn message(&self, id: &str, args: &[(&str, FluentValue)]) -> String {
+        let Some(pattern) = self.bundle.get_message(id).and_then(|m| m.value()) else {
+            log::warn!("No message {} in {}", id, self.tag);
+            return id.to_string();
+        };
+        let mut fluent_args = FluentArgs::with_capacity(args.len());
+        for (name, value) in args {
+            fluent_args.set(*name, value.clone());
+        }
+        let mut errors = Vec::new();
+        let text = self
+            .bundle
+            .format_pattern(pattern, Some(&fluent_args), &mut errors);
+        for error in errors {
+            log::warn!("In message {} in {}: {}", id, self.tag, error);
+        }
+        text.into_owned()
+    }
+
+    /// `n` with its thousands marked, as in `1,234` or `1.234`.
+    pub fn number(&self, n: u64) -> String {
+        self.conventions.number(&n.to_string(), true)
+    }
+
+    /// A size in bytes, as in `1.5 MiB` or `1,5 MiB`.
+    pub fn size(&self, bytes: u64) -> String {
+        format_size(bytes).replace('.', &self.conventions.decimal.to_string())
+    }
+
+    /// A Unix timestamp as a date and time in UTC, as in
+    /// `2024-01-02 09:00 UTC` or `02/01/2024 09:00 UTC`.
+    pub fn datetime(&self, timestamp: i64) -> String {
+        let (year, month, day) = civil_from_days(timestamp.div_euclid(86_400));
+        let seconds = timestamp.rem_euclid(86_400);
+        let separator = self.conventions.separator;
+        let date = if self.conventions.day_first {
+            format!("{day:02}{separator}{month:02}{separator}{year:04}")
+        } else {
+            format!("{year:04}{separator}{month:02}{separator}{day:02}")
+        };
+        format!(
+            "{} {:02}:{:02} UTC",
+            date,
+            seconds / 3600,
+            seconds % 3600 / 60
+        )
+    }
+}
+
+/// The translation for `wanted`, or else one for its language in another
+/// region.
+fn translation(
+    wanted: &LanguageIdentifier,
+) -> Option<&'static (&'static str, &'static str, Conventions)> {
+    let id = |tag: &str| tag.parse::<LanguageIdentifier>().ok();
+    TRANSLATIONS
+        .iter()
+        .find(|(tag, _, _)| id(tag).as_ref() == Some(wanted))
+        .or_else(|| {
+            TRANSLATIONS
+                .iter()
+                .find(|(tag, _, _)| id(tag).is_some_and(|id| id.language == wanted.language))
+        })
+}
+
+fn resource(source: &'static str) -> FluentResource {
+    // The messages are built in, so a syntax error is a bug; keep what parsed
+    FluentResource::try_new(source.to_string()).unwrap_or_else(|(resource, errors)| {
+        log::warn!("Invalid built-in messages: {:?}", errors);
+        resource
+    })
+}
+
+fn comma_grouped<M>(value: &FluentValue, _: &M) -> Option<String> {
+    grouped(value, ENGLISH_CONVENTIONS)
+}
+
+fn dot_grouped<M>(value: &FluentValue, _: &M) -> Option<String> {
+    grouped(
+        value,
+        Conventions {
+            group: '.',
+            decimal: ',',
+            ..ENGLISH_CONVENTIONS
+        },
+    )
+}
+
+fn grouped(value: &FluentValue, conventions: Conventions) -> Option<String> {
+    match value {
+        FluentValue::Number(n) => Some(conventions.number(&n.as_string(), n.options.use_grouping)),
+        _ => None,
+    }
+}
+
+static LOCALE: OnceLock<Locale> = OnceLock::new();
+
+/// Shows messages in `locale` from now on. Only the first call counts.
+pub fn set_locale(locale: Locale) {
+    let _ = LOCALE.set(locale);
+}
+
+/// The language set with [`set_locale`], English until then.
+pub fn locale() -> &'static Locale {
+    LOCALE.get_or_init(|| Locale::new("en"))
+}
+
+/// The message `id` in the current language, with arguments given as
+/// `name = value`.
+#[macro_export]
+macro_rules! tr {
+    ($id:expr) => {
+        $crate::i18n::locale().message($id, &[])
+    };
+    ($id:expr, $($name:ident = $value:expr),+ $(,)?) => {
+        $crate::i18n::locale().message(
+            $id,
+            &[$((stringify!($name), $crate::i18n::FluentValue::from($value))),+],
+        )
+    };
+}
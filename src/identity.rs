@@ -0,0 +1,107 @@
+use std::fmt;
+
+use crate::folders::parse_string;
+use crate::session::quote_string;
+
+/// What the fetcher calls itself in the IMAP ID command (RFC 2971). Some
+/// providers require the command before they let a client select mailboxes,
+/// and others record it with the sign-in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ClientIdentity {
+    /// Empty to not send ID at all.
+    pub name: String,
+    pub version: String,
+}
+
+impl Default for ClientIdentity {
+    fn default() -> Self {
+        ClientIdentity {
+            name: env!("CARGO_PKG_NAME").to_string(),
+            version: env!("CARGO_PKG_VERSION").to_string(),
+        }
+    }
+}
+
+impl ClientIdentity {
+    /// The ID command for this identity, or `None` if it shouldn't be sent.
+    /// Values that can't be quoted are left out rather than sent as literals.
+    pub fn command(&self) -> Option<String> {
+        if self.name.is_empty() {
+            return None;
+        }
+        let fields: Vec<String> = [("name", &self.name), ("version", &self.version)]
+            .into_iter()
+            .filter(|(_, value)| !value.is_empty())
+            .filter_map(|(field, value)| Some(format!("\"{}\" {}", field, quote_string(value)?)))
+            .collect();
+        if fields.is_empty() {
+            return None;
+        }
+        Some(format!("ID ({})", fields.join(" ")))
+    }
+}
+
+/// The fields a server identified itself with, in the order it sent them.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ServerId {
+    pub fields: Vec<(String, String)>,
+}
+
+impl ServerId {
+    pub fn get(&self, field: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(name, _)| name.eq_ignore_ascii_case(field))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+impl fmt::Display for ServerId {
+    /// `name version` followed by the remaining fields, e.g.
+    /// `Dovecot 2.3.21 (vendor: Open-Xchange)`.
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let title: Vec<&str> = ["name", "version"]
+            .into_iter()
+            .filter_map(|field| self.get(field))
+            .collect();
+        let rest: Vec<String> = self
+            .fields
+            .iter()
+            .filter(|(name, _)| {
+                !name.eq_ignore_ascii_case("name") && !name.eq_ignore_ascii_case("version")
+            })
+            .map(|(name, value)| format!("{}: {}", name, value))
+            .collect();
+        match (title.is_empty(), rest.is_empty()) {
+            (_, true) => write!(f, "{}", title.join(" ")),
+            (true, false) => write!(f, "{}", rest.join(", ")),
+            (false, false) => write!(f, "{} ({})", title.join(" "), rest.join(", ")),
+        }
+    }
+}
+
+/// Parses the untagged part of an ID response, e.g.
+/// `ID ("name" "Cyrus" "version" "1.5" "os" NIL)`. Fields without a value
+/// are dropped; `ID NIL` gives an empty [`ServerId`].
+pub fn parse_id_response(line: &str) -> Option<ServerId> {
+    let (word, rest) = line.split_once(' ')?;
+    if !word.eq_ignore_ascii_case("ID") {
+        return None;
+    }
+    let rest = rest.trim();
+    if rest.eq_ignore_ascii_case("NIL") {
+        return Some(ServerId::default());
+    }
+
+    let mut rest = rest.strip_prefix('(')?.trim_start();
+    let mut id = ServerId::default();
+    while !rest.starts_with(')') {
+        let (field, after) = parse_string(rest)?;
+        let (value, after) = parse_string(after.trim_start())?;
+        if let (Some(field), Some(value)) = (field, value) {
+            id.fields.push((field, value));
+        }
+        rest = after.trim_start();
+    }
+    Some(id)
+}
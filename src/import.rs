@@ -0,0 +1,59 @@
+use std::path::Path;
+
+use crate::catalog::{Catalog, CatalogEntry};
+use crate::dedupe::{collect_eml_files, relative_path};
+use crate::error_imap::ClientError;
+use crate::lock::RunLock;
+use crate::message::Headers;
+use crate::storage::LineEndings;
+
+/// What importing a directory of messages found.
+#[derive(Debug, Default)]
+pub struct ImportReport {
+    pub scanned: usize,
+    /// Messages added to the catalog.
+    pub added: usize,
+    /// Messages the catalog already had, by Message-ID or content.
+    pub known: usize,
+    /// Messages added without a Message-ID, which only match by content.
+    pub without_message_id: usize,
+}
+
+/// Adds every `.eml` file under `dir` to the catalog of the archive at
+/// `archive`, so fetches with `--skip-duplicates` leave those messages on
+/// the server. Files stay where they are: entries point at them relative to
+/// the archive root when they are inside it, and by full path otherwise.
+pub fn import_messages(dir: &Path, archive: &Path) -> Result<ImportReport, ClientError> {
+    let _lock = RunLock::acquire(archive)?;
+    let root = archive.canonicalize()?;
+    let mut files = Vec::new();
+    collect_eml_files(&dir.canonicalize()?, &mut files)?;
+    files.sort();
+
+    let mut catalog = Catalog::open(archive)?;
+    let mut report = ImportReport {
+        scanned: files.len(),
+        ..Default::default()
+    };
+    for file in &files {
+        let raw = std::fs::read(file)?;
+        let headers = Headers::parse(&raw);
+        let path = if file.starts_with(&root) {
+            relative_path(&root, file)
+        } else {
+            file.to_string_lossy().into_owned()
+        };
+        let entry = CatalogEntry::new(&raw, headers.message_id(), LineEndings::Keep, path);
+        if catalog.is_duplicate(&entry) {
+            report.known += 1;
+            continue;
+        }
+        if entry.message_id_hash.is_empty() {
+            report.without_message_id += 1;
+        }
+        catalog.record(entry)?;
+        report.added += 1;
+    }
+    catalog.sync()?;
+    Ok(report)
+}
@@ -1,12 +1,134 @@
+use crate::checkpoint::SyncSchedule;
+use crate::client::{Backend, FetchOrder};
+use crate::diskspace::DEFAULT_MIN_FREE_SPACE;
+use crate::dns::Resolver;
 use crate::error_imap::ClientError;
+use crate::folders::FolderFilter;
+use crate::gmail_api::DEFAULT_GMAIL_API_URL;
+use crate::identity::ClientIdentity;
+use crate::jmap::DEFAULT_JMAP_URL;
+use crate::net::{parse_tor, IpPreference};
+use crate::oauth::OAuthCredentials;
+use crate::pacing::parse_delay;
+use crate::profile::Profile;
+use crate::provider::{AuthMethod, Provider};
+use crate::redact::Redactor;
+use crate::retention::RetentionPolicy;
+use crate::rules::RuleSet;
+use crate::secrets::load_refresh_token;
+use crate::session::{parse_read_buffer, SequenceSet, READ_BUFFER};
+use crate::storage::{ExistingFilePolicy, LineEndings, Organize, OutputFormat, ThreadMode};
+use crate::tls::{client_config, parse_cipher_suites, TlsOptions};
+use crate::views::View;
+use crate::window::DateRange;
+use crate::writer::ensure_io_uring;
 use std::io::{self};
-use std::path::Path;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
 
 pub struct ImapConfig {
     pub email: String,
     pub password: String,
-    pub dir_path: String,
+    pub dir_path: PathBuf,
+    /// Settles the IMAP server and how to sign in to it.
+    pub provider: Provider,
+    /// IMAP server as `host` or `host:port`, instead of the provider's.
+    pub server: Option<String>,
+    /// Name and version sent to servers supporting the ID command.
+    pub identity: ClientIdentity,
+    pub backend: Backend,
+    /// JMAP session resource, used with the JMAP backend.
+    pub jmap_url: String,
+    /// Gmail API endpoint, used with the Gmail API backend.
+    pub gmail_api_url: String,
+    /// OAuth 2.0 client and refresh token. Without them, the password is used
+    /// as an access token by the Gmail API backend. Providers signing in with
+    /// the device flow need at least the client ID.
+    pub oauth: Option<OAuthCredentials>,
     pub max_concurrent: usize,
+    /// Fetch every selectable mailbox returned by LIST instead of just INBOX.
+    pub all_folders: bool,
+    pub folder_filter: FolderFilter,
+    /// Also fetch the Sent and Drafts folders, whatever they are called.
+    pub include_sent: bool,
+    pub include_drafts: bool,
+    /// Per-message rules deciding what to save, skip, move or delete.
+    pub rules: RuleSet,
+    /// WebAssembly message processors, run in order on every message.
+    pub plugins: Vec<PathBuf>,
+    /// Patterns scrubbed from the text of stored messages.
+    pub redact: Redactor,
+    pub format: OutputFormat,
+    pub line_endings: LineEndings,
+    /// Group saved messages by conversation.
+    pub threads: Option<ThreadMode>,
+    /// Group saved messages by who sent them.
+    pub organize: Option<Organize>,
+    /// Store each message once, by content hash, with these views over it.
+    pub views: Vec<View>,
+    /// Also store the decoded text/plain body of each message as UTF-8 `.txt`.
+    pub export_text: bool,
+    /// When a message has no text/plain part, render its HTML body as text instead.
+    pub html_text: bool,
+    /// Don't store messages whose Message-ID or content is already in the catalog.
+    pub skip_duplicates: bool,
+    pub if_exists: ExistingFilePolicy,
+    /// Write message files through io_uring (Linux, `uring` builds).
+    pub io_uring: bool,
+    /// Write small message files in groups, before each commit.
+    pub group_writes: bool,
+    /// Stop fetching when free space on the target filesystem drops below this.
+    pub min_free_space: u64,
+    /// Limits on how much a single run downloads.
+    pub max_messages: Option<u32>,
+    pub max_bytes: Option<u64>,
+    pub order: FetchOrder,
+    /// Only fetch these UIDs, ignoring the checkpoint.
+    pub uids: Option<SequenceSet>,
+    /// Only fetch messages the server received in this range.
+    pub dates: DateRange,
+    /// Plan and fetch each mailbox a calendar month at a time.
+    pub by_month: bool,
+    /// Messages fetched per connection.
+    pub batch_size: usize,
+    /// Pause between starting one batch and the next, plus up to
+    /// `batch_jitter` at random.
+    pub batch_delay: Duration,
+    pub batch_jitter: Duration,
+    /// How much each connection reads from the network at once.
+    pub read_buffer: usize,
+    /// Which IP version to try first when the server has both.
+    pub ip_preference: IpPreference,
+    /// Where the server's name is resolved.
+    pub resolver: Resolver,
+    /// The Tor SOCKS proxy every connection goes through, if any.
+    pub tor: Option<SocketAddr>,
+    /// Client certificate and pinned keys.
+    pub tls: TlsOptions,
+    /// How often the checkpoint and catalog are forced to disk.
+    pub sync: SyncSchedule,
+    /// Fetch only the messages listed in this failure report.
+    pub retry_from: Option<PathBuf>,
+    /// Never send anything that could modify the account, and log every
+    /// command sent.
+    pub read_only: bool,
+    /// Delete each message from the server once its archived copy is
+    /// verified, journaling every step.
+    pub drain: bool,
+    /// Shell command confirming an archived copy is replicated, run before a
+    /// drained message is deleted.
+    pub drain_check: Option<String>,
+    /// Deletes old messages locally or on the server once a fetch is done.
+    pub retention: Vec<RetentionPolicy>,
+    /// Chain-of-custody log of commands and saved messages.
+    pub audit_log: Option<PathBuf>,
+    /// Operator key the audit log is signed with.
+    pub audit_key: Option<PathBuf>,
+    /// Where every IMAP command and response is traced.
+    pub trace_imap: Option<PathBuf>,
+    /// Don't print status messages (something else owns the terminal).
+    pub quiet: bool,
 }
 
 impl ImapConfig {
@@ -14,10 +136,75 @@ impl ImapConfig {
         ImapConfig {
             email: String::new(),
             password: String::new(),
-            dir_path: String::new(),
+            dir_path: PathBuf::new(),
+            provider: Provider::default(),
+            server: None,
+            identity: ClientIdentity::default(),
+            backend: Backend::default(),
+            jmap_url: DEFAULT_JMAP_URL.to_string(),
+            gmail_api_url: DEFAULT_GMAIL_API_URL.to_string(),
+            oauth: None,
             max_concurrent: Self::determine_optimal_concurrency(),
+            all_folders: false,
+            include_sent: false,
+            include_drafts: false,
+            folder_filter: FolderFilter::default(),
+            rules: RuleSet::default(),
+            plugins: Vec::new(),
+            redact: Redactor::default(),
+            format: OutputFormat::default(),
+            line_endings: LineEndings::default(),
+            threads: None,
+            organize: None,
+            views: Vec::new(),
+            export_text: false,
+            html_text: false,
+            skip_duplicates: false,
+            if_exists: ExistingFilePolicy::default(),
+            io_uring: false,
+            group_writes: false,
+            min_free_space: DEFAULT_MIN_FREE_SPACE,
+            max_messages: None,
+            max_bytes: None,
+            order: FetchOrder::default(),
+            uids: None,
+            dates: DateRange::default(),
+            by_month: false,
+            batch_size: 500,
+            batch_delay: Duration::from_millis(50),
+            batch_jitter: Duration::ZERO,
+            read_buffer: READ_BUFFER,
+            ip_preference: IpPreference::default(),
+            resolver: Resolver::default(),
+            tor: None,
+            tls: TlsOptions::default(),
+            sync: SyncSchedule::default(),
+            retry_from: None,
+            read_only: false,
+            drain: false,
+            drain_check: None,
+            retention: Vec::new(),
+            audit_log: None,
+            audit_key: None,
+            trace_imap: None,
+            quiet: false,
         }
     }
+    /// The IMAP server to connect to as `host:port`: the one configured, on
+    /// port 993 unless it names one, or else the provider's.
+    pub fn imap_server(&self) -> Result<String, ClientError> {
+        match &self.server {
+            Some(server) if server.contains(':') => Ok(server.clone()),
+            Some(host) => Ok(format!("{}:{}", host, self.provider.port())),
+            None => self.provider.server().ok_or_else(|| {
+                ClientError::InvalidArgument(format!(
+                    "{} uses the generic provider, which needs a server",
+                    self.email
+                ))
+            }),
+        }
+    }
+
     fn determine_optimal_concurrency() -> usize {
         //if let Ok(parallelism) = std::thread::available_parallelism() {
         //return 2 * parallelism.get();
@@ -26,18 +213,24 @@ impl ImapConfig {
     }
 }
 
-pub fn prompt_imap_config() -> Result<ImapConfig, ClientError> {
+impl Default for ImapConfig {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+pub fn prompt_imap_config(provider: Provider) -> Result<ImapConfig, ClientError> {
     let mut config = ImapConfig::new();
 
     config.email = prompt_email()?;
-    config.password = prompt_password()?;
+    config.password = prompt_password_for(provider)?;
     config.dir_path = prompt_directory_path()?;
 
     Ok(config)
 }
 
 pub fn prompt_email() -> Result<String, ClientError> {
-    println!("Enter your Gmail address: ");
+    println!("Enter your email address: ");
     let input = get_user_input()?;
     validate_email(&input)?;
     Ok(input)
@@ -54,23 +247,286 @@ pub fn prompt_password() -> Result<String, ClientError> {
     Ok(input)
 }
 
-pub fn prompt_directory_path() -> Result<String, ClientError> {
+/// Prompts for the password if `provider` signs in with one. Providers using
+/// OAuth get an empty password, as their sign-in happens in the browser.
+pub fn prompt_password_for(provider: Provider) -> Result<String, ClientError> {
+    match provider.auth() {
+        AuthMethod::Login => prompt_password(),
+        AuthMethod::OAuthDevice(_) => Ok(String::new()),
+    }
+}
+
+pub fn prompt_directory_path() -> Result<PathBuf, ClientError> {
     println!("Enter absolute path for saving emails: ");
-    let dir_path = get_user_input()?;
+    let dir_path = PathBuf::from(get_user_input()?);
+    ensure_directory(&dir_path)?;
+    Ok(dir_path)
+}
 
-    if !Path::new(&dir_path).exists() {
-        log::info!("Directory doesn't exist. Creating: {}", dir_path);
-        std::fs::create_dir_all(&dir_path)?;
+fn ensure_directory(dir_path: &Path) -> Result<(), ClientError> {
+    if !dir_path.exists() {
+        log::info!("Directory doesn't exist. Creating: {}", dir_path.display());
+        std::fs::create_dir_all(dir_path)?;
     } else {
-        log::info!("Directory exists: {}", dir_path);
+        log::info!("Directory exists: {}", dir_path.display());
     }
+    Ok(())
+}
 
-    Ok(dir_path)
+/// Loads several accounts from a file of `[account]` sections:
+///
+/// ```text
+/// [account]
+/// email = alice@gmail.com
+/// password = abcdefghijklmnop
+/// dir = /backups/alice
+/// max_concurrent = 3
+///
+/// [account]
+/// email = bob@fastmail.com
+/// password = fmu1-...
+/// dir = /backups/bob
+/// backend = jmap
+/// all_folders = true
+/// include = Clients/*
+/// exclude = [Gmail]/Spam
+/// exclude = [Gmail]/Trash
+///
+/// [account]
+/// email = carol@contoso.com
+/// dir = /backups/carol
+/// provider = outlook
+/// oauth_client_id = 00000000-0000-0000-0000-000000000000
+///
+/// [account]
+/// email = dave@example.org
+/// password = ...
+/// dir = /backups/dave
+/// provider = generic
+/// server = mail.example.org:993
+/// ```
+///
+/// `provider` (`gmail`, `outlook`, `yahoo`, `fastmail`, `icloud` or `generic`,
+/// which needs `server`), `server` (overriding the provider's), `client_name`
+/// and `client_version` (sent in the ID command; an empty name sends none),
+/// `backend`
+/// (`imap`, `jmap` with `jmap_url` naming the session resource, or
+/// `gmail-api`), the OAuth keys `oauth_client_id`, `oauth_client_secret` and
+/// `oauth_refresh_token` (which replace the password; Outlook needs only the
+/// client ID, and so does Gmail once `login` has kept a refresh token in the
+/// system keyring), `max_concurrent`, `all_folders`, `include_sent`,
+/// `include_drafts`, `include`, `exclude`, `rules` (a rules file, see
+/// [`RuleSet`]), `plugin` (a WebAssembly plugin, see
+/// [`crate::plugin::load_plugins`]), `redact` (`cards`, `ssn` or a
+/// `/REGEX/`, see [`Redactor`]) and `retention` (see [`RetentionPolicy`])
+/// are optional; the folder patterns, plugins, redaction patterns and
+/// retention policies may be repeated. Blank lines and lines starting with `#` are ignored.
+pub fn load_accounts(path: &str) -> Result<Vec<ImapConfig>, ClientError> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut accounts: Vec<ImapConfig> = Vec::new();
+
+    for (number, raw_line) in contents.lines().enumerate() {
+        let line = raw_line.trim();
+        if line.is_empty() || line.starts_with('#') {
+            continue;
+        }
+        if line == "[account]" {
+            accounts.push(ImapConfig::new());
+            continue;
+        }
+
+        let invalid = |reason: &str| {
+            ClientError::InvalidArgument(format!("{}:{}: {}", path, number + 1, reason))
+        };
+        let (key, value) = line
+            .split_once('=')
+            .ok_or_else(|| invalid("expected `key = value`"))?;
+        let account = accounts
+            .last_mut()
+            .ok_or_else(|| invalid("setting outside of an [account] section"))?;
+        let value = value.trim().to_string();
+
+        match key.trim() {
+            "email" => account.email = value,
+            "password" => account.password = value,
+            "dir" => account.dir_path = PathBuf::from(value),
+            "provider" => {
+                account.provider = value.parse().map_err(|_| {
+                    invalid("provider must be gmail, outlook, yahoo, fastmail, icloud or generic")
+                })?
+            }
+            "server" => account.server = Some(value),
+            "client_name" => account.identity.name = value,
+            "client_version" => account.identity.version = value,
+            "backend" => {
+                account.backend = value
+                    .parse()
+                    .map_err(|_| invalid("backend must be imap, jmap or gmail-api"))?
+            }
+            "jmap_url" => account.jmap_url = value,
+            "oauth_client_id" => {
+                account.oauth.get_or_insert_with(Default::default).client_id = value
+            }
+            "oauth_client_secret" => {
+                account
+                    .oauth
+                    .get_or_insert_with(Default::default)
+                    .client_secret = value
+            }
+            "oauth_refresh_token" => {
+                account
+                    .oauth
+                    .get_or_insert_with(Default::default)
+                    .refresh_token = value
+            }
+            "max_concurrent" => {
+                account.max_concurrent = value
+                    .parse()
+                    .map_err(|_| invalid("max_concurrent must be a number"))?
+            }
+            "profile" => value
+                .parse::<Profile>()
+                .map_err(|_| invalid("profile must be gentle, normal or aggressive"))?
+                .apply(account),
+            "batch_delay" => {
+                account.batch_delay = parse_delay(&value)
+                    .map_err(|_| invalid("batch_delay must be a delay, as in 250ms or 2s"))?
+            }
+            "batch_jitter" => {
+                account.batch_jitter = parse_delay(&value)
+                    .map_err(|_| invalid("batch_jitter must be a delay, as in 250ms or 2s"))?
+            }
+            "dns" => {
+                account.resolver = value
+                    .parse()
+                    .map_err(|_| invalid("dns must be an IP address, an https:// URL or system"))?
+            }
+            "prefer_ip" => {
+                account.ip_preference = value
+                    .parse()
+                    .map_err(|_| invalid("prefer_ip must be ipv4, ipv6 or system"))?
+            }
+            "client_cert" => account.tls.client_cert = Some(PathBuf::from(value)),
+            "client_key" => account.tls.client_key = Some(PathBuf::from(value)),
+            "tls_min_version" => {
+                account.tls.min_version = value
+                    .parse()
+                    .map_err(|_| invalid("tls_min_version must be 1.2 or 1.3"))?
+            }
+            "tls_ciphers" => account.tls.cipher_suites = parse_cipher_suites(&value)?,
+            "tls_session_tickets" => {
+                account.tls.session_tickets = value
+                    .parse()
+                    .map_err(|_| invalid("tls_session_tickets must be true or false"))?
+            }
+            "pin" => account.tls.pins.push(
+                value
+                    .parse()
+                    .map_err(|_| invalid("pin must be sha256/ and a base64 SHA-256 hash"))?,
+            ),
+            "tor" => {
+                account.tor = parse_tor(&value)
+                    .map_err(|_| invalid("tor must be true, false or the proxy's address"))?
+            }
+            "read_buffer" => {
+                account.read_buffer = parse_read_buffer(&value)
+                    .map_err(|_| invalid("read_buffer must be a size from 4K to 1G"))?
+            }
+            "group_writes" => {
+                account.group_writes = value
+                    .parse()
+                    .map_err(|_| invalid("group_writes must be true or false"))?
+            }
+            "io_uring" => {
+                account.io_uring = value
+                    .parse()
+                    .map_err(|_| invalid("io_uring must be true or false"))?;
+                if account.io_uring && ensure_io_uring().is_err() {
+                    return Err(invalid(
+                        "io_uring needs a Linux build with --features uring",
+                    ));
+                }
+            }
+            "all_folders" => {
+                account.all_folders = value
+                    .parse()
+                    .map_err(|_| invalid("all_folders must be true or false"))?
+            }
+            "include_sent" => {
+                account.include_sent = value
+                    .parse()
+                    .map_err(|_| invalid("include_sent must be true or false"))?
+            }
+            "include_drafts" => {
+                account.include_drafts = value
+                    .parse()
+                    .map_err(|_| invalid("include_drafts must be true or false"))?
+            }
+            "include" => account.folder_filter.include.push(value),
+            "exclude" => account.folder_filter.exclude.push(value),
+            "rules" => account.rules = RuleSet::load(Path::new(&value))?,
+            "plugin" => account.plugins.push(PathBuf::from(value)),
+            "redact" => account.redact.push(value.parse()?),
+            "retention" => account.retention.push(value.parse()?),
+            other => return Err(invalid(&format!("unknown key `{}`", other))),
+        }
+    }
+
+    for account in &mut accounts {
+        // Refresh tokens `login` kept in the system keyring
+        let Some(oauth) = account.oauth.as_mut() else {
+            continue;
+        };
+        if oauth.refresh_token.is_empty() {
+            match load_refresh_token(&account.email) {
+                Ok(token) => oauth.refresh_token = token.unwrap_or_default(),
+                Err(e) => log::warn!("{}: {}", account.email, e),
+            }
+        }
+    }
+
+    for account in &accounts {
+        validate_email(&account.email)?;
+        account.imap_server()?;
+        client_config(&account.tls)?;
+        // With OAuth credentials the password isn't used, and the device flow
+        // and `login` obtain the refresh token themselves
+        let oauth = account.oauth.as_ref();
+        let device_flow = matches!(account.provider.auth(), AuthMethod::OAuthDevice(_));
+        let browser_flow = account.provider.browser_flow().is_some();
+        let fields = [
+            (
+                "password",
+                account.password.is_empty() && oauth.is_none() && !device_flow,
+            ),
+            ("dir", account.dir_path.as_os_str().is_empty()),
+            (
+                "oauth_client_id",
+                oauth.map_or(device_flow, |o| o.client_id.is_empty()),
+            ),
+            (
+                "oauth_refresh_token",
+                oauth.is_some_and(|o| o.refresh_token.is_empty()) && !device_flow && !browser_flow,
+            ),
+        ];
+        for (field, empty) in fields {
+            if empty {
+                return Err(ClientError::EmptyInput {
+                    field: format!("{} of {}", field, account.email),
+                });
+            }
+        }
+        ensure_directory(&account.dir_path)?;
+    }
+
+    Ok(accounts)
 }
 
 fn get_user_input() -> Result<String, ClientError> {
     let mut input = String::new();
-    io::stdin().read_line(&mut input)?;
+    io::stdin()
+        .read_line(&mut input)
+        .map_err(ClientError::InputError)?;
     let mut trimmed = input.trim().to_string();
     trimmed = trimmed.chars().filter(|c| !c.is_whitespace()).collect();
     if trimmed.is_empty() {
@@ -0,0 +1,269 @@
+use serde::Deserialize;
+use serde_json::{json, Value};
+use std::sync::Arc;
+
+use crate::audit::AuditLog;
+use crate::error_imap::ClientError;
+use crate::http::{check_status, http_error};
+
+/// Session resource used when an account doesn't name one (Fastmail's).
+pub const DEFAULT_JMAP_URL: &str = "https://api.fastmail.com/jmap/session";
+
+const CORE_CAPABILITY: &str = "urn:ietf:params:jmap:core";
+const MAIL_CAPABILITY: &str = "urn:ietf:params:jmap:mail";
+
+/// A mailbox as returned by `Mailbox/get`.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JmapMailbox {
+    pub id: String,
+    pub name: String,
+    pub parent_id: Option<String>,
+    /// Well-known role such as `inbox` or `sent` (RFC 8621 §2).
+    pub role: Option<String>,
+    #[serde(default)]
+    pub total_emails: u32,
+}
+
+/// The properties of an email needed to download and file it.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub struct JmapEmail {
+    pub id: String,
+    pub blob_id: String,
+    pub thread_id: Option<String>,
+    /// Message-ID header values, without angle brackets.
+    pub message_id: Option<Vec<String>>,
+    /// When the server received the message, e.g. `2014-10-30T06:12:00Z`.
+    pub received_at: Option<String>,
+    #[serde(default)]
+    pub size: u64,
+}
+
+/// An authenticated JMAP session (RFC 8620) for one account's mail.
+///
+/// Only `/get` and `/query` methods and blob downloads are ever issued, so a
+/// JMAP fetch can't modify the account; in read-only mode anything else is
+/// refused all the same.
+pub struct JmapSession {
+    http: reqwest::Client,
+    token: String,
+    api_url: String,
+    download_url: String,
+    account_id: String,
+    read_only: bool,
+    audit: Option<(Arc<AuditLog>, u32)>,
+}
+
+impl JmapSession {
+    /// Fetches the session resource at `url`, authenticating with the API
+    /// token `token`.
+    pub async fn connect(
+        http: reqwest::Client,
+        url: &str,
+        token: &str,
+        read_only: bool,
+        audit: Option<Arc<AuditLog>>,
+    ) -> Result<Self, ClientError> {
+        let audit = match audit {
+            Some(log) => {
+                let connection = log.open_connection(url)?;
+                Some((log, connection))
+            }
+            None => None,
+        };
+        let response = http
+            .get(url)
+            .bearer_auth(token)
+            .send()
+            .await
+            .map_err(http_error)?;
+        let session: Value = check_status(response)?.json().await.map_err(http_error)?;
+
+        let field = |name: &str| {
+            session
+                .get(name)
+                .and_then(Value::as_str)
+                .map(str::to_string)
+                .ok_or_else(|| malformed(&format!("session resource has no {}", name)))
+        };
+        let account_id = session
+            .get("primaryAccounts")
+            .and_then(|accounts| accounts.get(MAIL_CAPABILITY))
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| malformed("session resource has no mail account"))?;
+
+        let session = JmapSession {
+            api_url: field("apiUrl")?,
+            download_url: field("downloadUrl")?,
+            http,
+            token: token.to_string(),
+            account_id,
+            read_only,
+            audit,
+        };
+        session.audit(&format!("session account {}", session.account_id))?;
+        Ok(session)
+    }
+
+    /// Records `event` against this session if it is audited.
+    pub fn audit(&self, event: &str) -> Result<(), ClientError> {
+        match &self.audit {
+            Some((log, connection)) => log.record(*connection, event),
+            None => Ok(()),
+        }
+    }
+
+    /// Every mailbox of the account.
+    pub async fn mailboxes(&self) -> Result<Vec<JmapMailbox>, ClientError> {
+        let result = self
+            .call(
+                "Mailbox/get",
+                json!({
+                    "accountId": self.account_id,
+                    "properties": ["name", "parentId", "role", "totalEmails"],
+                }),
+            )
+            .await?;
+        list(result)
+    }
+
+    /// IDs of up to `limit` emails of a mailbox from `position`, by arrival
+    /// time.
+    pub async fn query(
+        &self,
+        mailbox_id: &str,
+        position: usize,
+        limit: usize,
+        oldest_first: bool,
+    ) -> Result<Vec<String>, ClientError> {
+        let result = self
+            .call(
+                "Email/query",
+                json!({
+                    "accountId": self.account_id,
+                    "filter": { "inMailbox": mailbox_id },
+                    "sort": [{ "property": "receivedAt", "isAscending": oldest_first }],
+                    "position": position,
+                    "limit": limit,
+                }),
+            )
+            .await?;
+        serde_json::from_value(result.get("ids").cloned().unwrap_or_default())
+            .map_err(|e| malformed_caused("Email/query", e))
+    }
+
+    pub async fn emails(&self, ids: &[String]) -> Result<Vec<JmapEmail>, ClientError> {
+        let result = self
+            .call(
+                "Email/get",
+                json!({
+                    "accountId": self.account_id,
+                    "ids": ids,
+                    "properties": ["blobId", "threadId", "messageId", "receivedAt", "size"],
+                }),
+            )
+            .await?;
+        list(result)
+    }
+
+    /// Downloads the raw RFC 5322 message stored in `blob_id`.
+    pub async fn download(&self, blob_id: &str) -> Result<Vec<u8>, ClientError> {
+        let url = self
+            .download_url
+            .replace("{accountId}", &self.account_id)
+            .replace("{blobId}", blob_id)
+            .replace("{name}", "message.eml")
+            .replace("{type}", "message/rfc822");
+        self.audit(&format!("download {}", blob_id))?;
+        let response = self
+            .http
+            .get(url)
+            .bearer_auth(&self.token)
+            .send()
+            .await
+            .map_err(http_error)?;
+        let body = check_status(response)?.bytes().await.map_err(http_error)?;
+        Ok(body.to_vec())
+    }
+
+    /// Makes a single method call and returns its arguments, turning a JMAP
+    /// method error into [`ClientError::CommandFailed`].
+    async fn call(&self, method: &str, arguments: Value) -> Result<Value, ClientError> {
+        if self.read_only && !(method.ends_with("/get") || method.ends_with("/query")) {
+            self.audit(&format!("refused {}", method))?;
+            return Err(ClientError::ReadOnly(method.to_string()));
+        }
+        self.audit(&format!("request {}", method))?;
+
+        let request = json!({
+            "using": [CORE_CAPABILITY, MAIL_CAPABILITY],
+            "methodCalls": [[method, arguments, "0"]],
+        });
+        let response = self
+            .http
+            .post(&self.api_url)
+            .bearer_auth(&self.token)
+            .json(&request)
+            .send()
+            .await
+            .map_err(http_error)?;
+        let mut response: Value = check_status(response)?.json().await.map_err(http_error)?;
+
+        let invocation = response
+            .get_mut("methodResponses")
+            .and_then(|responses| responses.get_mut(0))
+            .and_then(Value::as_array_mut)
+            .filter(|invocation| invocation.len() == 3)
+            .ok_or_else(|| malformed(&format!("no response to {}", method)))?;
+        let name = invocation[0].as_str().unwrap_or_default().to_string();
+        let result = invocation[1].take();
+        if name == "error" {
+            let field = |name: &str| result.get(name).and_then(Value::as_str).unwrap_or_default();
+            return Err(ClientError::CommandFailed {
+                tag: invocation[2].as_str().unwrap_or_default().to_string(),
+                command: method.to_string(),
+                text: format!("{} {}", field("type"), field("description"))
+                    .trim()
+                    .to_string(),
+            });
+        }
+        Ok(result)
+    }
+}
+
+/// The `list` of a `/get` response.
+fn list<T: serde::de::DeserializeOwned>(mut result: Value) -> Result<Vec<T>, ClientError> {
+    serde_json::from_value(result.get_mut("list").map(Value::take).unwrap_or_default())
+        .map_err(|e| malformed_caused("list", e))
+}
+
+fn malformed(reason: &str) -> ClientError {
+    ClientError::JmapMalformed {
+        message: reason.to_string(),
+        source: None,
+    }
+}
+
+fn malformed_caused(reason: &str, source: serde_json::Error) -> ClientError {
+    ClientError::JmapMalformed {
+        message: reason.to_string(),
+        source: Some(source.into()),
+    }
+}
+
+/// Path of `mailbox` from the top of the hierarchy, one name per level.
+pub fn mailbox_path(mailbox: &JmapMailbox, mailboxes: &[JmapMailbox]) -> Vec<String> {
+    let mut path = vec![mailbox.name.clone()];
+    let mut parent = mailbox.parent_id.as_deref();
+    // A cycle would be a server bug; don't loop forever on one
+    while let Some(id) = parent.filter(|_| path.len() <= mailboxes.len()) {
+        let Some(found) = mailboxes.iter().find(|m| m.id == id) else {
+            break;
+        };
+        path.insert(0, found.name.clone());
+        parent = found.parent_id.as_deref();
+    }
+    path
+}
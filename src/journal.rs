@@ -0,0 +1,292 @@
+use std::fmt::Write as _;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error_imap::ClientError;
+use crate::session::response_code_arg;
+
+/// Name of the operations journal written to the root of an archive directory.
+pub const JOURNAL_FILE: &str = "operations.journal";
+
+/// Commands that change messages on the server, journaled before they are sent.
+const JOURNALED_COMMANDS: [&str; 5] = ["STORE", "EXPUNGE", "APPEND", "COPY", "MOVE"];
+
+/// Whether `command` (without its tag) is one of [`JOURNALED_COMMANDS`],
+/// with or without `UID`.
+pub fn is_journaled(command: &str) -> bool {
+    let mut words = command.split_whitespace();
+    let mut name = words.next().unwrap_or_default();
+    if name.eq_ignore_ascii_case("UID") {
+        name = words.next().unwrap_or_default();
+    }
+    JOURNALED_COMMANDS
+        .iter()
+        .any(|journaled| name.eq_ignore_ascii_case(journaled))
+}
+
+/// Write-ahead journal of the commands that change messages on the server.
+///
+/// Each command is written and synced to disk as a `begin` line before it is
+/// sent. Its reply follows as `ok`, `no` or `bad`, after any `code` line for
+/// response codes (such as COPYUID) the server sent on the way. A `begin`
+/// without a reply is a command a run was interrupted in, which may or may
+/// not have taken effect.
+///
+/// Lines are `timestamp<TAB>operation<TAB>event<TAB>mailbox<TAB>text`, where
+/// `operation` is `run.connection.tag`, and `mailbox` is the one selected
+/// when the command was sent, as it went over the wire.
+pub struct Journal {
+    path: PathBuf,
+    /// Start of the run, in milliseconds, so operations of different runs
+    /// appending to one journal can't be confused.
+    run: u128,
+    file: Mutex<Option<File>>,
+    connections: AtomicU32,
+}
+
+impl Journal {
+    /// A journal appending to `path`, which is created on the first command.
+    pub fn new(path: PathBuf) -> Self {
+        Journal {
+            path,
+            run: now().as_millis(),
+            file: Mutex::new(None),
+            connections: AtomicU32::new(0),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Numbers a new connection.
+    pub fn open_connection(&self) -> u32 {
+        self.connections.fetch_add(1, Ordering::Relaxed) + 1
+    }
+
+    /// Appends `event` for command `tag` of `connection`, and waits until it
+    /// is on disk.
+    pub fn record(
+        &self,
+        connection: u32,
+        tag: &str,
+        event: &str,
+        mailbox: &str,
+        text: &str,
+    ) -> Result<(), ClientError> {
+        let timestamp = now();
+        let line = format!(
+            "{}.{:03}\t{}.{}.{}\t{}\t{}\t{}\n",
+            timestamp.as_secs(),
+            timestamp.subsec_millis(),
+            self.run,
+            connection,
+            tag,
+            event,
+            mailbox.replace(['\r', '\n', '\t'], " "),
+            text.replace(['\r', '\n', '\t'], " ")
+        );
+
+        let mut file = self.file.lock().map_err(|_| {
+            ClientError::io(&self.path, std::io::Error::other("journal lock poisoned"))
+        })?;
+        if file.is_none() {
+            *file = Some(
+                OpenOptions::new()
+                    .create(true)
+                    .append(true)
+                    .open(&self.path)?,
+            );
+        }
+        if let Some(file) = file.as_mut() {
+            file.write_all(line.as_bytes())?;
+            file.sync_data()?;
+        }
+        Ok(())
+    }
+}
+
+fn now() -> std::time::Duration {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+}
+
+/// One journaled command and what became of it.
+struct Operation {
+    timestamp: String,
+    id: String,
+    mailbox: String,
+    command: String,
+    /// Response codes and the final reply's text.
+    replies: Vec<String>,
+    /// `ok`, `no` or `bad`; `None` when the run was interrupted.
+    outcome: Option<String>,
+}
+
+fn read_operations(path: &Path) -> Result<Vec<Operation>, ClientError> {
+    let contents = std::fs::read_to_string(path)?;
+    let mut operations: Vec<Operation> = Vec::new();
+    for (index, line) in contents.lines().enumerate() {
+        let fields: Vec<&str> = line.splitn(5, '\t').collect();
+        let [timestamp, id, event, mailbox, text] = fields[..] else {
+            return Err(ClientError::InvalidArgument(format!(
+                "{} line {} is malformed",
+                path.display(),
+                index + 1
+            )));
+        };
+        if event == "begin" {
+            operations.push(Operation {
+                timestamp: timestamp.to_string(),
+                id: id.to_string(),
+                mailbox: mailbox.to_string(),
+                command: text.to_string(),
+                replies: Vec::new(),
+                outcome: None,
+            });
+            continue;
+        }
+        let Some(operation) = operations.iter_mut().rev().find(|op| op.id == id) else {
+            log::warn!("{} line {} has no begin line", path.display(), index + 1);
+            continue;
+        };
+        operation.replies.push(text.to_string());
+        if event != "code" {
+            operation.outcome = Some(event.to_string());
+        }
+    }
+    Ok(operations)
+}
+
+/// The commands reversing the operations journaled in `path`, newest first,
+/// each after a comment naming the operation. Operations that failed are
+/// skipped, those a run was interrupted in are reversed with a warning, and
+/// those that can't be reversed, such as expunges, say why.
+///
+/// The plan is for reading before anything is run: removing a flag that
+/// was already set before the run, for instance, would lose it.
+pub fn undo_plan(path: &Path) -> Result<String, ClientError> {
+    let mut plan = String::new();
+    for operation in read_operations(path)?.iter().rev() {
+        let status = match operation.outcome.as_deref() {
+            Some("ok") => "done",
+            Some(_) => "failed",
+            None => "interrupted",
+        };
+        let _ = writeln!(
+            plan,
+            "# {} {} in {}: {} ({})",
+            operation.timestamp, operation.id, operation.mailbox, operation.command, status
+        );
+        match (status, undo(operation)) {
+            ("failed", _) => plan.push_str("# nothing to undo\n"),
+            (_, Err(reason)) => {
+                let _ = writeln!(plan, "# can't be undone: {}", reason);
+            }
+            (status, Ok(commands)) => {
+                if status == "interrupted" {
+                    plan.push_str("# check whether it took effect before undoing it\n");
+                }
+                for command in commands {
+                    let _ = writeln!(plan, "{}", command);
+                }
+            }
+        }
+    }
+    Ok(plan)
+}
+
+/// Commands reversing `operation`, or why there are none.
+fn undo(operation: &Operation) -> Result<Vec<String>, &'static str> {
+    let command = operation.command.as_str();
+    let (uid, command) = match command.split_once(' ') {
+        Some((first, rest)) if first.eq_ignore_ascii_case("UID") => (true, rest),
+        _ => (false, command),
+    };
+    let (name, args) = command.split_once(' ').unwrap_or((command, ""));
+    let name = name.to_ascii_uppercase();
+    let select = |mailbox: &str| format!("SELECT {}", mailbox);
+    let expunge = |set: &str| {
+        vec![
+            format!("UID STORE {} +FLAGS.SILENT (\\Deleted)", set),
+            format!("UID EXPUNGE {}", set),
+        ]
+    };
+
+    match name.as_str() {
+        "EXPUNGE" => Err("expunged messages are gone from the server"),
+        "STORE" if !uid => Err("message sequence numbers change as messages come and go"),
+        "STORE" => {
+            let mut parts = args.splitn(3, ' ');
+            let (Some(set), Some(item), Some(value)) = (parts.next(), parts.next(), parts.next())
+            else {
+                return Err("the command is malformed");
+            };
+            let reversed = match item.split_at_checked(1) {
+                Some(("+", item)) => format!("-{}", item),
+                Some(("-", item)) => format!("+{}", item),
+                _ => return Err("it replaced values that weren't recorded"),
+            };
+            Ok(vec![
+                select(&operation.mailbox),
+                format!("UID STORE {} {} {}", set, reversed, value),
+            ])
+        }
+        "MOVE" | "COPY" if !uid => Err("message sequence numbers change as messages come and go"),
+        "MOVE" | "COPY" => {
+            let (_, target) = args.split_once(' ').ok_or("the command is malformed")?;
+            let copied = copied_uids(operation).ok_or("the server didn't report the new UIDs")?;
+            let mut commands = vec![select(target)];
+            if name == "MOVE" {
+                commands.push(format!("UID MOVE {} {}", copied, operation.mailbox));
+            } else {
+                commands.extend(expunge(&copied));
+            }
+            Ok(commands)
+        }
+        "APPEND" => {
+            let target = first_argument(args).ok_or("the command is malformed")?;
+            let appended = operation
+                .replies
+                .iter()
+                .find_map(|reply| response_code_arg(reply, "APPENDUID"))
+                .and_then(|arg| arg.split_whitespace().nth(1))
+                .ok_or("the server didn't report the new UID")?;
+            let mut commands = vec![select(target)];
+            commands.extend(expunge(appended));
+            Ok(commands)
+        }
+        _ => Err("it isn't a journaled command"),
+    }
+}
+
+/// The first argument in `args`, a quoted string or an atom.
+fn first_argument(args: &str) -> Option<&str> {
+    let Some(quoted) = args.strip_prefix('"') else {
+        return args.split_whitespace().next();
+    };
+    let mut escaped = false;
+    for (i, c) in quoted.char_indices() {
+        match c {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => return Some(&args[..i + 2]),
+            _ => escaped = false,
+        }
+    }
+    None
+}
+
+/// The UIDs messages got in the target mailbox, from COPYUID.
+fn copied_uids(operation: &Operation) -> Option<String> {
+    operation
+        .replies
+        .iter()
+        .find_map(|reply| response_code_arg(reply, "COPYUID"))
+        .and_then(|arg| arg.split_whitespace().nth(2))
+        .map(str::to_string)
+}
@@ -1,3 +1,68 @@
+pub mod args;
+pub mod audit;
+#[cfg(feature = "bench")]
+pub mod bench;
+pub mod catalog;
+pub mod checkpoint;
 pub mod client;
+pub mod control;
+pub mod convert;
+pub mod dedupe;
+pub mod diskspace;
+pub mod dns;
+pub mod drain;
 pub mod error_imap;
+pub mod export;
+pub mod failures;
+#[cfg(feature = "faults")]
+pub mod faults;
+#[cfg(feature = "mock")]
+pub mod fixtures;
+pub mod folders;
+pub mod gmail_api;
+pub mod html;
+pub mod http;
+pub mod i18n;
+pub mod identity;
+pub mod import;
 pub mod input;
+pub mod jmap;
+pub mod journal;
+pub mod lock;
+pub mod maildir;
+pub mod message;
+#[cfg(feature = "mock")]
+pub mod mock;
+pub mod net;
+pub mod notify;
+pub mod oauth;
+pub mod pacing;
+pub mod parser;
+pub mod partial;
+pub mod pdf;
+pub mod plugin;
+pub mod pool;
+pub mod processor;
+pub mod profile;
+pub mod progress;
+pub mod provider;
+pub mod redact;
+pub mod repair;
+pub mod replay;
+pub mod report;
+pub mod retention;
+pub mod rules;
+pub mod scheduler;
+pub mod secrets;
+pub mod session;
+pub mod state;
+pub mod stats;
+pub mod storage;
+pub mod tls;
+pub mod trace;
+#[cfg(feature = "tui")]
+pub mod tui;
+pub mod views;
+pub mod window;
+pub mod workers;
+pub mod writer;
@@ -0,0 +1,84 @@
+use fs2::FileExt;
+use std::fs::{File, OpenOptions};
+use std::io::{Read, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error_imap::ClientError;
+use crate::message::format_timestamp;
+
+/// Name of the lock file kept at the root of an archive directory.
+pub const LOCK_FILE: &str = ".lock";
+
+/// Exclusive hold on an archive directory for the length of a run, so two
+/// runs (say, overlapping cron jobs) never write into the same catalog and
+/// checkpoint at once.
+///
+/// The hold is an advisory lock on [`LOCK_FILE`], which the operating
+/// system releases when its process ends, however it ends. The file also
+/// names the process holding it; a name left behind with nobody holding the
+/// lock is from a run that died, and is taken over.
+pub struct RunLock {
+    file: File,
+    path: PathBuf,
+}
+
+impl RunLock {
+    /// Takes the lock on the archive rooted at `dir`, failing with
+    /// [`ClientError::Locked`] if another run holds it.
+    pub fn acquire(dir: &Path) -> Result<Self, ClientError> {
+        std::fs::create_dir_all(dir)?;
+        let path = dir.join(LOCK_FILE);
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .truncate(false)
+            .open(&path)?;
+        let mut holder = String::new();
+        if let Err(e) = file.try_lock_exclusive() {
+            if e.kind() != fs2::lock_contended_error().kind() {
+                return Err(ClientError::io(&path, e));
+            }
+            let _ = file.read_to_string(&mut holder);
+            return Err(ClientError::Locked {
+                path: dir.to_path_buf(),
+                holder: holder.trim().to_string(),
+            });
+        }
+
+        file.read_to_string(&mut holder)?;
+        if !holder.trim().is_empty() {
+            log::warn!(
+                "Taking over the lock on {} left by a run that didn't finish ({})",
+                dir.display(),
+                holder.trim()
+            );
+        }
+        let since = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs() as i64);
+        file.set_len(0)?;
+        file.rewind()?;
+        writeln!(
+            file,
+            "pid {} since {}",
+            std::process::id(),
+            format_timestamp(since)
+        )?;
+        file.sync_data()?;
+        Ok(RunLock { file, path })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for RunLock {
+    fn drop(&mut self) {
+        // Emptied, so the next run doesn't take it for one that died
+        let _ = self.file.set_len(0);
+        let _ = FileExt::unlock(&self.file);
+    }
+}
@@ -0,0 +1,97 @@
+use std::path::{Path, PathBuf};
+
+use crate::error_imap::ClientError;
+
+/// Separates a Maildir file's unique name from its info (`:2,FS`). Windows
+/// doesn't allow `:` in file names, so mail tools there use `!` instead.
+pub const INFO_SEPARATOR: char = if cfg!(windows) { '!' } else { ':' };
+
+/// The Maildir flag letters for IMAP `flags`, in the ASCII order the format
+/// requires. Flags with no Maildir equivalent are dropped.
+pub fn maildir_flags(flags: &[String]) -> String {
+    let mut letters: Vec<char> = flags
+        .iter()
+        .filter_map(|flag| match flag.to_ascii_lowercase().as_str() {
+            "\\draft" => Some('D'),
+            "\\flagged" => Some('F'),
+            "$forwarded" => Some('P'),
+            "\\answered" => Some('R'),
+            "\\seen" => Some('S'),
+            "\\deleted" => Some('T'),
+            _ => None,
+        })
+        .collect();
+    letters.sort_unstable();
+    letters.dedup();
+    letters.into_iter().collect()
+}
+
+/// File name in `cur/` of the message named `stem` with IMAP `flags`, such
+/// as `email_00001:2,FS`.
+pub fn file_name(stem: &str, flags: &[String]) -> String {
+    format!("{}{}2,{}", stem, INFO_SEPARATOR, maildir_flags(flags))
+}
+
+/// The flag letters in the info of Maildir file name `name`, such as `FS`
+/// for `email_00001:2,FS`; empty when it has none.
+pub fn info_flags(name: &str) -> &str {
+    name.rsplit_once(INFO_SEPARATOR)
+        .and_then(|(_, info)| info.strip_prefix("2,"))
+        .unwrap_or_default()
+}
+
+/// How a Gmail label is written as a keyword: system labels such as
+/// `\Important` lose the backslash and are lowercased, and commas, which
+/// separate keywords, become spaces.
+pub fn keyword(label: &str) -> String {
+    match label.strip_prefix('\\') {
+        Some(system) => system.to_ascii_lowercase(),
+        None => label.replace(',', " "),
+    }
+}
+
+/// `raw` with an `X-Keywords` header listing `labels` in front, which mu,
+/// notmuch and Dovecot read as tags. Returns `raw` as is when there are none.
+pub fn with_keywords(raw: &[u8], labels: &[String]) -> Vec<u8> {
+    if labels.is_empty() {
+        return raw.to_vec();
+    }
+    let eol: &[u8] = if raw.windows(2).any(|w| w == b"\r\n") {
+        b"\r\n"
+    } else {
+        b"\n"
+    };
+    let keywords: Vec<String> = labels.iter().map(|label| keyword(label)).collect();
+    let mut tagged = format!("X-Keywords: {}", keywords.join(", ")).into_bytes();
+    tagged.extend_from_slice(eol);
+    tagged.extend_from_slice(raw);
+    tagged
+}
+
+/// Creates the `cur`, `new` and `tmp` directories of the Maildir at `dir` and
+/// returns where the message `stem` goes. A copy saved earlier with other
+/// flags is renamed first, so a re-run brings flags up to date.
+pub async fn prepare(dir: &Path, stem: &str, flags: &[String]) -> Result<PathBuf, ClientError> {
+    for sub in ["cur", "new", "tmp"] {
+        tokio::fs::create_dir_all(dir.join(sub)).await?;
+    }
+    let target = dir.join("cur").join(file_name(stem, flags));
+    if target.exists() {
+        return Ok(target);
+    }
+
+    let prefix = format!("{}{}2,", stem, INFO_SEPARATOR);
+    let mut entries = tokio::fs::read_dir(dir.join("cur")).await?;
+    while let Some(entry) = entries.next_entry().await? {
+        if entry.file_name().to_string_lossy().starts_with(&prefix) {
+            log::debug!(
+                "Updating flags of {} to {}",
+                entry.path().display(),
+                target.display()
+            );
+            tokio::fs::rename(entry.path(), &target).await?;
+            break;
+        }
+    }
+    Ok(target)
+}
@@ -1,29 +1,1181 @@
-use imap_client::client::ImapClient;
-use imap_client::input::prompt_imap_config;
+use imap_client::args::{parse_args, CliArgs, Command};
+use imap_client::audit::verify_audit_log;
+use imap_client::client::{fetch_accounts, FetchSummary, ImapClient};
+use imap_client::convert::{convert_archive, ArchiveFormat};
+use imap_client::dedupe::dedupe_archive;
+use imap_client::diskspace::format_size;
+use imap_client::error_imap::{ClientError, ErrorKind};
+use imap_client::export::{export_archive, ExportFormat};
+use imap_client::i18n::{set_locale, Locale};
+use imap_client::import::import_messages;
+use imap_client::input::{
+    load_accounts, prompt_directory_path, prompt_email, prompt_imap_config, prompt_password_for,
+    ImapConfig,
+};
+use imap_client::journal::undo_plan;
+use imap_client::net::CONNECTION_LOG;
+use imap_client::notify::notify_finished;
+use imap_client::plugin::load_plugins;
+use imap_client::repair::Damage;
+use imap_client::report::{
+    analyze_archive, write_html_report, write_report, HTML_REPORT, REPORT_DIR,
+};
+use imap_client::scheduler::{Limits, Scheduler};
+use imap_client::session::sequence_set;
+use imap_client::stats::render_histogram;
+use imap_client::tr;
+use std::io::Write;
+use std::process::ExitCode;
+use std::sync::Arc;
 
 #[tokio::main]
-async fn main() -> Result<(), Box<dyn std::error::Error>> {
-    env_logger::init();
+async fn main() -> ExitCode {
+    let args = parse_args(std::env::args().skip(1));
+    // Log lines would scribble over the TUI
+    if !matches!(&args, Ok(args) if args.tui) {
+        let mut logger = env_logger::Builder::from_default_env();
+        if matches!(&args, Ok(args) if args.debug_connection) {
+            logger.filter_module(CONNECTION_LOG, log::LevelFilter::Debug);
+        }
+        logger.init();
+    }
+    let args = match args {
+        Ok(args) => args,
+        Err(e) => {
+            println!("{}", e);
+            return exit_code(&e);
+        }
+    };
+    set_locale(
+        args.locale
+            .as_deref()
+            .map_or_else(Locale::from_env, Locale::new),
+    );
+
+    if let Command::Dedupe { dir, remove } = &args.command {
+        return dedupe(dir, *remove);
+    }
+    if let Command::Export {
+        dir,
+        output,
+        format,
+    } = &args.command
+    {
+        return export(dir, output, *format);
+    }
+    if let Command::Import { dir, archive } = &args.command {
+        return import(dir, archive);
+    }
+    if let Command::Report {
+        dir,
+        output,
+        top,
+        html,
+    } = &args.command
+    {
+        return analytics_report(dir, output.as_deref(), *top, *html);
+    }
+    if let Command::Convert { dir, output, to } = &args.command {
+        return convert(dir, output, *to);
+    }
+    if let Command::VerifyAudit { path } = &args.command {
+        return verify_audit(path);
+    }
+    if let Command::UndoPlan { path } = &args.command {
+        return print_undo_plan(path);
+    }
+    if let Command::Replay { capture, output } = &args.command {
+        return replay(capture, output.as_deref(), &args).await;
+    }
+    #[cfg(feature = "bench")]
+    if args.bench_local {
+        return bench_local().await;
+    }
+    if let Command::Get { message_id, output } = &args.command {
+        return get(message_id, output.as_deref(), &args).await;
+    }
+    if args.command == Command::Folders {
+        return folders(&args).await;
+    }
+    if args.command == Command::Check {
+        return check(&args).await;
+    }
+    if args.command == Command::Login {
+        return login(&args).await;
+    }
+    if let Command::Stats { sample } = args.command {
+        return stats(sample, &args).await;
+    }
+    if args.command == Command::Diff {
+        return diff(&args).await;
+    }
+    if let Command::Label {
+        label,
+        remove,
+        search,
+        dry_run,
+    } = &args.command
+    {
+        return label_messages(label, !remove, search.as_deref(), *dry_run, &args).await;
+    }
+    if let Command::Purge { search, dry_run } = &args.command {
+        return purge_messages(search, *dry_run, &args).await;
+    }
+    if let Command::Repair { prune, dry_run } = &args.command {
+        return repair(*prune, *dry_run, &args).await;
+    }
+
+    println!("Gmail IMAP Email Fetcher (Async Version)");
+    println!("========================================");
+
+    if let Some(accounts_file) = &args.accounts_file {
+        return fetch_multiple_accounts(accounts_file, &args).await;
+    }
 
-    let config = match prompt_imap_config() {
+    let mut config = match prompt_imap_config(args.provider.unwrap_or_default()) {
         Ok(config) => config,
         Err(e) => {
             log::error!("Failed to get configuration: {}", e);
             println!("Failed to get IMAP configuration. Please try again.");
-            return Ok(());
+            return exit_code(&e);
         }
     };
 
-    let client = ImapClient::new(config, "imap.gmail.com:993".to_string());
+    args.apply_to(&mut config);
+    let client = match client_for(config) {
+        Ok(client) => client,
+        Err(e) => {
+            println!("{}", e);
+            return exit_code(&e);
+        }
+    };
+    let clients = schedule(vec![client], &args.limits);
+    #[cfg(feature = "tui")]
+    if args.tui {
+        return report(fetch_with_tui(clients).await, &args);
+    }
 
     log::info!("Starting IMAP email fetch");
-    match client.fetch_all_emails().await {
-        Ok(_) => println!("Email fetching completed successfully"),
+    let results = fetch_accounts(clients, args.progress).await;
+    if args.notify {
+        notify_finished(&results);
+    }
+    let Some((_, result)) = results.into_iter().next() else {
+        return ExitCode::SUCCESS;
+    };
+    match result {
+        Ok(summary) if summary.failed_batches > 0 => {
+            println!("{}", tr!("fetch-partial", failed = summary.failed_batches));
+            ExitCode::from(ErrorKind::Partial.exit_code())
+        }
+        Ok(_) => {
+            println!("{}", tr!("fetch-completed"));
+            ExitCode::SUCCESS
+        }
         Err(e) => {
             log::error!("{}", e);
-            println!("Failed to fetch emails. Please try again.");
+            match e {
+                ClientError::ActionRequired { .. } | ClientError::InsufficientSpace { .. } => {
+                    println!("{}", e)
+                }
+                _ => println!("{}", tr!("fetch-failed")),
+            }
+            exit_code(&e)
+        }
+    }
+}
+
+/// Creates the client for an account, connecting to its provider's server
+/// unless it names its own.
+fn client_for(config: ImapConfig) -> Result<ImapClient, ClientError> {
+    let server = config.imap_server()?;
+    let plugins = load_plugins(&config.plugins)?;
+    Ok(plugins
+        .into_iter()
+        .fold(ImapClient::new(config, server), ImapClient::with_processor))
+}
+
+/// Puts the accounts of a run under one scheduler when limits shared by
+/// all of them were given.
+fn schedule(clients: Vec<ImapClient>, limits: &Limits) -> Vec<ImapClient> {
+    if *limits == Limits::default() {
+        return clients;
+    }
+    let scheduler = Scheduler::new(limits.clone());
+    clients
+        .into_iter()
+        .map(|client| client.with_scheduler(Arc::clone(&scheduler)))
+        .collect()
+}
+
+fn exit_code(error: &ClientError) -> ExitCode {
+    ExitCode::from(error.exit_code())
+}
+
+async fn fetch_multiple_accounts(accounts_file: &str, args: &CliArgs) -> ExitCode {
+    let accounts = match load_accounts(accounts_file) {
+        Ok(accounts) => accounts,
+        Err(e) => {
+            log::error!("Failed to load accounts: {}", e);
+            println!("Failed to load accounts from {}: {}", accounts_file, e);
+            return exit_code(&e);
+        }
+    };
+
+    log::info!("Starting IMAP email fetch for {} accounts", accounts.len());
+    let clients = accounts
+        .into_iter()
+        .map(|mut config| {
+            args.apply_to(&mut config);
+            client_for(config)
+        })
+        .collect::<Result<Vec<_>, _>>();
+    let clients = match clients {
+        Ok(clients) => schedule(clients, &args.limits),
+        Err(e) => {
+            println!("{}", e);
+            return exit_code(&e);
+        }
+    };
+
+    #[cfg(feature = "tui")]
+    if args.tui {
+        return report(fetch_with_tui(clients).await, args);
+    }
+    report(fetch_accounts(clients, args.progress).await, args)
+}
+
+/// Fetches under the terminal UI. Accounts still running when the user quits
+/// are reported as cancelled.
+#[cfg(feature = "tui")]
+async fn fetch_with_tui(
+    clients: Vec<ImapClient>,
+) -> Vec<(String, Result<FetchSummary, ClientError>)> {
+    use imap_client::tui::{self, TuiAccount};
+
+    let accounts = clients
+        .iter()
+        .map(|client| TuiAccount {
+            progress: client.progress(),
+            control: client.control(),
+        })
+        .collect();
+    let names: Vec<String> = clients
+        .iter()
+        .map(|c| c.progress().account().to_string())
+        .collect();
+    let handles: Vec<_> = clients
+        .into_iter()
+        .map(|client| tokio::spawn(async move { client.fetch_all_emails().await }))
+        .collect();
+    let aborts: Vec<_> = handles.iter().map(|h| h.abort_handle()).collect();
+
+    let fetch = async {
+        let mut results = Vec::new();
+        for handle in handles {
+            results.push(match handle.await {
+                Ok(result) => result,
+                Err(e) => Err(ClientError::JoinError(Box::new(e))),
+            });
+        }
+        results
+    };
+    let results = match tui::run(accounts, fetch).await {
+        Ok(Some(results)) => results,
+        Ok(None) => {
+            aborts.iter().for_each(|a| a.abort());
+            names
+                .iter()
+                .map(|_| Err(ClientError::UserCancelled))
+                .collect()
+        }
+        Err(e) => {
+            aborts.iter().for_each(|a| a.abort());
+            names
+                .iter()
+                .map(|_| Err(std::io::Error::new(e.kind(), e.to_string()).into()))
+                .collect()
+        }
+    };
+    names.into_iter().zip(results).collect()
+}
+
+/// Prints a per-account summary and picks the exit status.
+fn report(results: Vec<(String, Result<FetchSummary, ClientError>)>, args: &CliArgs) -> ExitCode {
+    if args.notify {
+        notify_finished(&results);
+    }
+
+    let title = tr!("summary");
+    println!();
+    println!("{}", title);
+    println!("{}", "-".repeat(title.chars().count()));
+    // Report the first account's failure, or a partial run if batches failed
+    let mut status = ExitCode::SUCCESS;
+    let mut failed = false;
+    for (account, result) in results {
+        match result {
+            Ok(summary) => {
+                println!(
+                    "{}",
+                    tr!(
+                        "summary-account",
+                        account = account.as_str(),
+                        saved = summary.saved,
+                        found = summary.found,
+                        failed = summary.failed_batches
+                    )
+                );
+                if let Some(id) = summary.server_id.filter(|id| !id.fields.is_empty()) {
+                    println!("  {}", tr!("summary-server", server = id.to_string()));
+                }
+                for alert in &summary.alerts {
+                    println!("  {}", tr!("summary-alert", alert = alert.as_str()));
+                }
+                if summary.failed_batches > 0 && !failed {
+                    status = ExitCode::from(ErrorKind::Partial.exit_code());
+                }
+            }
+            Err(e) => {
+                log::error!("{}: {}", account, e);
+                println!(
+                    "{}",
+                    tr!(
+                        "summary-account-failed",
+                        account = account.as_str(),
+                        error = e.to_string()
+                    )
+                );
+                if !failed {
+                    status = exit_code(&e);
+                    failed = true;
+                }
+            }
+        }
+    }
+
+    status
+}
+
+/// The accounts a command works on: those of the accounts file, or one
+/// prompted for without asking for an output directory.
+fn command_accounts(args: &CliArgs) -> Result<Vec<ImapConfig>, ClientError> {
+    let accounts = match &args.accounts_file {
+        Some(path) => load_accounts(path),
+        None => prompt_email().and_then(|email| {
+            Ok(vec![ImapConfig {
+                email,
+                password: prompt_password_for(args.provider.unwrap_or_default())?,
+                ..ImapConfig::default()
+            }])
+        }),
+    };
+    if let Err(e) = &accounts {
+        log::error!("Failed to get configuration: {}", e);
+        println!("Failed to get IMAP configuration: {}", e);
+    }
+    accounts
+}
+
+/// Signs in to every account and shows what the server offers, without
+/// fetching anything. The exit status is that of the first account failing.
+async fn check(args: &CliArgs) -> ExitCode {
+    let accounts = match command_accounts(args) {
+        Ok(accounts) => accounts,
+        Err(e) => return exit_code(&e),
+    };
+
+    let mut status = ExitCode::SUCCESS;
+    let mut failed = false;
+    for mut config in accounts {
+        args.apply_to(&mut config);
+        let account = config.email.clone();
+        let result = match client_for(config) {
+            Ok(client) => client.check().await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(report) => {
+                println!(
+                    "{}: signed in to {} in {:.1}s",
+                    account,
+                    report.server,
+                    report.sign_in_time.as_secs_f64()
+                );
+                if let Some(id) = report.server_id.filter(|id| !id.fields.is_empty()) {
+                    println!("  server: {}", id);
+                }
+                println!("  capabilities: {}", report.capabilities.join(" "));
+                let folders: Vec<&str> = report
+                    .folders
+                    .iter()
+                    .map(|mailbox| mailbox.name.as_str())
+                    .collect();
+                println!("  {} folders: {}", folders.len(), folders.join(", "));
+            }
+            Err(e) => {
+                log::error!("{}: {}", account, e);
+                println!(
+                    "{}",
+                    tr!(
+                        "summary-account-failed",
+                        account = account.as_str(),
+                        error = e.to_string()
+                    )
+                );
+                if !failed {
+                    status = exit_code(&e);
+                    failed = true;
+                }
+            }
+        }
+    }
+    status
+}
+
+/// Signs every account in to its provider in the browser and checks the
+/// server accepts the result, so that fetches need no password.
+async fn login(args: &CliArgs) -> ExitCode {
+    // Signing in replaces the password, so only the address is asked for
+    let accounts = match &args.accounts_file {
+        Some(_) => command_accounts(args),
+        None => prompt_email().map(|email| {
+            vec![ImapConfig {
+                email,
+                ..ImapConfig::default()
+            }]
+        }),
+    };
+    let accounts = match accounts {
+        Ok(accounts) => accounts,
+        Err(e) => {
+            println!("Failed to get IMAP configuration: {}", e);
+            return exit_code(&e);
+        }
+    };
+
+    let mut status = ExitCode::SUCCESS;
+    for mut config in accounts {
+        args.apply_to(&mut config);
+        let account = config.email.clone();
+        let result = match client_for(config) {
+            Ok(client) => client.login(|url| open_browser(&account, url)).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(login) => {
+                if login.stored {
+                    println!("{}: refresh token saved in the system keyring", account);
+                } else {
+                    println!(
+                        "{}: the system keyring is unavailable. Add `oauth_refresh_token = {}` \
+                         to the account's section of the accounts file.",
+                        account, login.refresh_token
+                    );
+                }
+                println!("{}: signed in to the server, ready to fetch", account);
+            }
+            Err(e) => {
+                log::error!("{}: {}", account, e);
+                println!("{}: login failed: {}", account, e);
+                status = exit_code(&e);
+            }
+        }
+    }
+    status
+}
+
+/// Opens `url` in the default browser, printing it too for when there is
+/// none, as over SSH.
+fn open_browser(account: &str, url: &str) {
+    println!(
+        "{}: sign in at the page opening in your browser, or open this address:\n  {}",
+        account, url
+    );
+    let command = if cfg!(target_os = "windows") {
+        // Not `cmd /C start`, which would split the URL at each `&`
+        std::process::Command::new("rundll32")
+            .arg("url.dll,FileProtocolHandler")
+            .arg(url)
+            .spawn()
+    } else if cfg!(target_os = "macos") {
+        std::process::Command::new("open").arg(url).spawn()
+    } else {
+        std::process::Command::new("xdg-open").arg(url).spawn()
+    };
+    if let Err(e) = command {
+        log::info!("Failed to open a browser: {}", e);
+    }
+}
+
+/// Lists every account's folders, marking the ones a fetch with the same
+/// options would download and naming what special folders are for.
+async fn folders(args: &CliArgs) -> ExitCode {
+    let accounts = match command_accounts(args) {
+        Ok(accounts) => accounts,
+        Err(e) => return exit_code(&e),
+    };
+
+    let mut status = ExitCode::SUCCESS;
+    for mut config in accounts {
+        args.apply_to(&mut config);
+        let account = config.email.clone();
+        let result = match client_for(config) {
+            Ok(client) => client.list_folders().await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(folders) => {
+                println!("{}:", account);
+                for (mailbox, selected) in folders {
+                    let special = mailbox
+                        .special_use()
+                        .map(|special| special.to_string())
+                        .unwrap_or_default();
+                    println!(
+                        "  {} {:<9} {}",
+                        if selected { '*' } else { ' ' },
+                        special,
+                        mailbox.name
+                    );
+                }
+            }
+            Err(e) => {
+                log::error!("{}: {}", account, e);
+                println!(
+                    "{}",
+                    tr!(
+                        "summary-account-failed",
+                        account = account.as_str(),
+                        error = e.to_string()
+                    )
+                );
+                status = exit_code(&e);
+            }
+        }
+    }
+    status
+}
+
+/// Prints every account's storage usage against its quota and the message
+/// counts of the mailboxes a fetch would work on.
+async fn stats(sample: usize, args: &CliArgs) -> ExitCode {
+    let accounts = match command_accounts(args) {
+        Ok(accounts) => accounts,
+        Err(e) => return exit_code(&e),
+    };
+
+    let mut status = ExitCode::SUCCESS;
+    for mut config in accounts {
+        args.apply_to(&mut config);
+        let account = config.email.clone();
+        let result = match client_for(config) {
+            Ok(client) => client.stats(sample).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(stats) => {
+                println!("{}:", account);
+                if stats.quota.is_empty() {
+                    println!("  Quota: not reported by the server");
+                }
+                for root in &stats.quota {
+                    for resource in &root.resources {
+                        let name = match resource.name.as_str() {
+                            "STORAGE" => "Storage",
+                            "MESSAGE" => "Messages",
+                            other => other,
+                        };
+                        println!("  {}: {}", name, resource.describe());
+                    }
+                }
+                for (mailbox, count) in &stats.mailboxes {
+                    println!("  {}: {} messages", mailbox, count);
+                }
+                let sizes = &stats.sizes;
+                if sizes.messages() > 0 {
+                    println!(
+                        "  Sizes: {} messages, {}",
+                        sizes.messages(),
+                        format_size(sizes.total())
+                    );
+                    for line in render_histogram(sizes, 30) {
+                        println!("    {}", line);
+                    }
+                    println!("  Projected archive size:");
+                    for projection in sizes.projections() {
+                        println!(
+                            "    {}: {}{}",
+                            projection.format,
+                            format_size(projection.bytes),
+                            if projection.estimated {
+                                " (estimated)"
+                            } else {
+                                ""
+                            }
+                        );
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}: {}", account, e);
+                println!(
+                    "{}",
+                    tr!(
+                        "summary-account-failed",
+                        account = account.as_str(),
+                        error = e.to_string()
+                    )
+                );
+                status = exit_code(&e);
+            }
         }
     }
+    status
+}
+
+/// Compares every account's folders with its local archive. Exits with the
+/// partial-run status when the server has messages the archive lacks.
+async fn diff(args: &CliArgs) -> ExitCode {
+    let accounts = match command_accounts(args) {
+        Ok(accounts) => accounts,
+        Err(e) => return exit_code(&e),
+    };
+
+    let mut status = ExitCode::SUCCESS;
+    for mut config in accounts {
+        args.apply_to(&mut config);
+        let account = config.email.clone();
+        if config.dir_path.as_os_str().is_empty() {
+            match prompt_directory_path() {
+                Ok(dir) => config.dir_path = dir,
+                Err(e) => return exit_code(&e),
+            }
+        }
+        let result = match client_for(config) {
+            Ok(client) => client.diff_archive().await,
+            Err(e) => Err(e),
+        };
+        let diffs = match result {
+            Ok(diffs) => diffs,
+            Err(e) => {
+                log::error!("{}: {}", account, e);
+                println!(
+                    "{}",
+                    tr!(
+                        "summary-account-failed",
+                        account = account.as_str(),
+                        error = e.to_string()
+                    )
+                );
+                status = exit_code(&e);
+                continue;
+            }
+        };
+        println!("{}:", account);
+        for diff in diffs {
+            println!(
+                "  {}: {} on the server only, {} gone from the server, {} with other flags",
+                diff.mailbox,
+                diff.server_only.len(),
+                diff.local_only.len(),
+                diff.flag_changes.len()
+            );
+            if !diff.server_only.is_empty() {
+                println!(
+                    "    on the server only: UIDs {}",
+                    sequence_set(&diff.server_only)
+                );
+                status = ExitCode::from(ErrorKind::Partial.exit_code());
+            }
+            if !diff.local_only.is_empty() {
+                println!(
+                    "    gone from the server: UIDs {}",
+                    sequence_set(&diff.local_only)
+                );
+            }
+            for (path, local, server) in &diff.flag_changes {
+                println!(
+                    "    {}: flags \"{}\" locally, \"{}\" on the server",
+                    path, local, server
+                );
+            }
+        }
+    }
+    status
+}
+
+/// Adds or removes `label` on every account's matching messages, or with
+/// `dry_run` only says how many would change.
+async fn label_messages(
+    label: &str,
+    add: bool,
+    search: Option<&str>,
+    dry_run: bool,
+    args: &CliArgs,
+) -> ExitCode {
+    let accounts = match command_accounts(args) {
+        Ok(accounts) => accounts,
+        Err(e) => return exit_code(&e),
+    };
 
-    Ok(())
+    let verb = match (dry_run, add) {
+        (true, true) => "would be labelled",
+        (true, false) => "would be unlabelled",
+        (false, true) => "labelled",
+        (false, false) => "unlabelled",
+    };
+    let mut status = ExitCode::SUCCESS;
+    for mut config in accounts {
+        args.apply_to(&mut config);
+        let account = config.email.clone();
+        let result = match client_for(config) {
+            Ok(client) => client.label_messages(label, add, search, dry_run).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(counts) => {
+                println!("{}:", account);
+                for (mailbox, count) in counts {
+                    println!("  {}: {} messages {} {}", mailbox, count, verb, label);
+                }
+            }
+            Err(e) => {
+                log::error!("{}: {}", account, e);
+                println!(
+                    "{}",
+                    tr!(
+                        "summary-account-failed",
+                        account = account.as_str(),
+                        error = e.to_string()
+                    )
+                );
+                status = exit_code(&e);
+            }
+        }
+    }
+    status
+}
+
+async fn purge_messages(search: &str, dry_run: bool, args: &CliArgs) -> ExitCode {
+    let accounts = match command_accounts(args) {
+        Ok(accounts) => accounts,
+        Err(e) => return exit_code(&e),
+    };
+
+    let verb = if dry_run { "would be purged" } else { "purged" };
+    let mut status = ExitCode::SUCCESS;
+    for mut config in accounts {
+        args.apply_to(&mut config);
+        let account = config.email.clone();
+        // Only messages in this archive are purged
+        if config.dir_path.as_os_str().is_empty() {
+            match prompt_directory_path() {
+                Ok(dir) => config.dir_path = dir,
+                Err(e) => return exit_code(&e),
+            }
+        }
+        let result = match client_for(config) {
+            Ok(client) => client.purge_messages(search, dry_run).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(counts) => {
+                println!("{}:", account);
+                for count in counts {
+                    println!("  {}: {} messages {}", count.mailbox, count.purged, verb);
+                    if count.unarchived > 0 {
+                        println!(
+                            "  {}: {} matching messages kept, not in the local archive",
+                            count.mailbox, count.unarchived
+                        );
+                    }
+                }
+            }
+            Err(e) => {
+                log::error!("{}: {}", account, e);
+                println!(
+                    "{}",
+                    tr!(
+                        "summary-account-failed",
+                        account = account.as_str(),
+                        error = e.to_string()
+                    )
+                );
+                status = exit_code(&e);
+            }
+        }
+    }
+    status
+}
+
+async fn repair(prune: bool, dry_run: bool, args: &CliArgs) -> ExitCode {
+    let accounts = match command_accounts(args) {
+        Ok(accounts) => accounts,
+        Err(e) => return exit_code(&e),
+    };
+
+    let mut status = ExitCode::SUCCESS;
+    for mut config in accounts {
+        args.apply_to(&mut config);
+        let account = config.email.clone();
+        if config.dir_path.as_os_str().is_empty() {
+            match prompt_directory_path() {
+                Ok(dir) => config.dir_path = dir,
+                Err(e) => return exit_code(&e),
+            }
+        }
+        let result = match client_for(config) {
+            Ok(client) => client.repair_archive(prune, dry_run).await,
+            Err(e) => Err(e),
+        };
+        let report = match result {
+            Ok(report) => report,
+            Err(e) => {
+                log::error!("{}: {}", account, e);
+                println!(
+                    "{}",
+                    tr!(
+                        "summary-account-failed",
+                        account = account.as_str(),
+                        error = e.to_string()
+                    )
+                );
+                status = exit_code(&e);
+                continue;
+            }
+        };
+        println!("{}:", account);
+        for (from, to) in &report.relocated {
+            println!("  renamed: {} -> {}", from, to);
+        }
+        for damaged in &report.damaged {
+            match &damaged.damage {
+                Damage::Missing => println!("  missing: {}", damaged.entry.path),
+                Damage::Corrupt(reason) => println!("  corrupt: {}", reason),
+            }
+        }
+        for path in &report.restored {
+            println!("  restored: {}", path);
+        }
+        for path in &report.pruned {
+            println!("  pruned from the catalog: {}", path);
+        }
+        for (path, reason) in &report.unrecovered {
+            println!("  not restored: {}: {}", path, reason);
+        }
+        println!(
+            "  Checked {} files ({} can't be checked), {} damaged",
+            report.checked,
+            report.unchecked,
+            report.damaged.len()
+        );
+        if !report.unrecovered.is_empty() {
+            status = ExitCode::from(ErrorKind::Partial.exit_code());
+        }
+    }
+    status
+}
+
+async fn get(message_id: &str, output: Option<&str>, args: &CliArgs) -> ExitCode {
+    let accounts = match command_accounts(args) {
+        Ok(accounts) => accounts,
+        Err(e) => return exit_code(&e),
+    };
+
+    let mut status = ExitCode::FAILURE;
+    for mut config in accounts {
+        args.apply_to(&mut config);
+        let account = config.email.clone();
+        let result = match client_for(config) {
+            Ok(client) => client.get_message(message_id).await,
+            Err(e) => Err(e),
+        };
+        match result {
+            Ok(Some(body)) => {
+                let written = match output {
+                    Some(path) => std::fs::write(path, &body),
+                    None => std::io::stdout().write_all(&body),
+                };
+                return match written {
+                    Ok(()) => {
+                        if let Some(path) = output {
+                            eprintln!("Saved {} from {} to {}", message_id, account, path);
+                        }
+                        ExitCode::SUCCESS
+                    }
+                    Err(e) => {
+                        eprintln!("Failed to write {}: {}", message_id, e);
+                        exit_code(&e.into())
+                    }
+                };
+            }
+            Ok(None) => log::info!("{} not found in {}", message_id, account),
+            Err(e) => {
+                log::error!("{}: {}", account, e);
+                eprintln!("{}: failed: {}", account, e);
+                status = exit_code(&e);
+            }
+        }
+    }
+
+    eprintln!("No message with Message-ID {} found", message_id);
+    status
+}
+
+#[cfg(feature = "bench")]
+async fn bench_local() -> ExitCode {
+    println!("Benchmarking against a local mock server...");
+    let results = match imap_client::bench::run_local_benchmark().await {
+        Ok(results) => results,
+        Err(e) => {
+            log::error!("Benchmark failed: {}", e);
+            println!("Benchmark failed: {}", e);
+            return exit_code(&e);
+        }
+    };
+
+    println!();
+    println!("batch size  connections  messages  seconds  messages/s");
+    for result in results {
+        println!(
+            "{:>10}  {:>11}  {:>8}  {:>7.2}  {:>10.1}",
+            result.batch_size,
+            result.max_concurrent,
+            result.messages,
+            result.elapsed.as_secs_f64(),
+            result.messages_per_second()
+        );
+    }
+    ExitCode::SUCCESS
+}
+
+fn verify_audit(path: &std::path::Path) -> ExitCode {
+    match verify_audit_log(path) {
+        Ok(report) => {
+            println!("{}: {} lines, chain intact", path.display(), report.lines);
+            if report.signed_by.is_empty() {
+                println!("No signatures");
+            }
+            for key in &report.signed_by {
+                println!("Signed by key {}", key);
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            println!("{}: verification failed: {}", path.display(), e);
+            exit_code(&e)
+        }
+    }
+}
+
+fn print_undo_plan(path: &std::path::Path) -> ExitCode {
+    match undo_plan(path) {
+        Ok(plan) if plan.is_empty() => {
+            println!("{}: no operations journaled", path.display());
+            ExitCode::SUCCESS
+        }
+        Ok(plan) => {
+            print!("{}", plan);
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            println!("{}: {}", path.display(), e);
+            exit_code(&e)
+        }
+    }
+}
+
+async fn replay(
+    capture: &std::path::Path,
+    output: Option<&std::path::Path>,
+    args: &CliArgs,
+) -> ExitCode {
+    let bytes = match std::fs::read(capture) {
+        Ok(bytes) => bytes,
+        Err(e) => {
+            println!("{}: {}", capture.display(), e);
+            return exit_code(&e.into());
+        }
+    };
+    let mut config = ImapConfig {
+        dir_path: output.map(std::path::Path::to_path_buf).unwrap_or_default(),
+        ..ImapConfig::default()
+    };
+    args.apply_to(&mut config);
+    // Nothing is connected to, so no server is needed
+    let result = match load_plugins(&config.plugins) {
+        Ok(plugins) => {
+            let client = plugins.into_iter().fold(
+                ImapClient::new(config, String::new()),
+                ImapClient::with_processor,
+            );
+            client.replay(&bytes, output.is_some()).await
+        }
+        Err(e) => Err(e),
+    };
+    match result {
+        Ok(summary) => {
+            println!(
+                "Replayed {} responses ({}) holding {} messages: parsed in {:.3}s ({:.1} MiB/s)",
+                summary.responses,
+                format_size(summary.bytes as u64),
+                summary.messages,
+                summary.parse_time.as_secs_f64(),
+                summary.parse_rate()
+            );
+            if let Some(output) = output {
+                println!("Saved {} messages to {}", summary.saved, output.display());
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            println!("{}: {}", capture.display(), e);
+            exit_code(&e)
+        }
+    }
+}
+
+fn export(dir: &str, output: &std::path::Path, format: ExportFormat) -> ExitCode {
+    match export_archive(std::path::Path::new(dir), output, format) {
+        Ok(report) => {
+            println!(
+                "Exported {} messages in {} folders to {}",
+                report.messages,
+                report.folders,
+                output.display()
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            log::error!("Export failed: {}", e);
+            println!("Failed to export {}: {}", dir, e);
+            exit_code(&e)
+        }
+    }
+}
+
+fn convert(dir: &str, output: &std::path::Path, to: ArchiveFormat) -> ExitCode {
+    match convert_archive(std::path::Path::new(dir), output, to) {
+        Ok(report) => {
+            println!(
+                "Converted {} messages in {} folders to {}",
+                report.messages,
+                report.folders,
+                output.display()
+            );
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            log::error!("Conversion failed: {}", e);
+            println!("Failed to convert {}: {}", dir, e);
+            exit_code(&e)
+        }
+    }
+}
+
+fn import(dir: &str, archive: &std::path::Path) -> ExitCode {
+    match import_messages(std::path::Path::new(dir), archive) {
+        Ok(report) => {
+            println!(
+                "Scanned {} messages: {} added to the catalog of {}, {} already in it",
+                report.scanned,
+                report.added,
+                archive.display(),
+                report.known
+            );
+            if report.without_message_id > 0 {
+                println!(
+                    "{} added messages have no Message-ID and only match by content",
+                    report.without_message_id
+                );
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            log::error!("Import failed: {}", e);
+            println!("Failed to import {}: {}", dir, e);
+            exit_code(&e)
+        }
+    }
+}
+
+fn analytics_report(
+    dir: &str,
+    output: Option<&std::path::Path>,
+    top: usize,
+    html: bool,
+) -> ExitCode {
+    let archive = std::path::Path::new(dir);
+    let out = output.map_or_else(|| archive.join(REPORT_DIR), std::path::Path::to_path_buf);
+    let result = analyze_archive(archive, top).and_then(|analytics| {
+        let mut written = write_report(&analytics, &out)?;
+        if html {
+            let page = output.map_or(archive, |output| output).join(HTML_REPORT);
+            write_html_report(archive, &analytics, &page)?;
+            written.push(page);
+        }
+        Ok((written, analytics))
+    });
+    match result {
+        Ok((written, analytics)) => {
+            println!(
+                "{}",
+                tr!(
+                    "report-counted",
+                    messages = analytics.messages,
+                    archive = archive.display().to_string()
+                )
+            );
+            if analytics.skipped > 0 {
+                println!("{}", tr!("report-skipped", skipped = analytics.skipped));
+            }
+            for path in written {
+                println!("  {}", path.display());
+            }
+            ExitCode::SUCCESS
+        }
+        Err(e) => {
+            log::error!("Report failed: {}", e);
+            println!(
+                "{}",
+                tr!("report-failed", archive = dir, error = e.to_string())
+            );
+            exit_code(&e)
+        }
+    }
+}
+
+fn dedupe(dir: &str, remove: bool) -> ExitCode {
+    let report = match dedupe_archive(std::path::Path::new(dir), remove) {
+        Ok(report) => report,
+        Err(e) => {
+            log::error!("Dedupe failed: {}", e);
+            println!("Failed to scan {}: {}", dir, e);
+            return exit_code(&e);
+        }
+    };
+
+    for group in &report.groups {
+        println!("{}", group[0].display());
+        for duplicate in &group[1..] {
+            if report.differing.contains(duplicate) {
+                println!(
+                    "  same Message-ID, different content: {}",
+                    duplicate.display()
+                );
+            } else {
+                println!("  duplicate: {}", duplicate.display());
+            }
+        }
+    }
+    println!(
+        "Scanned {} messages, found {} with duplicates",
+        report.scanned,
+        report.groups.len()
+    );
+    if remove {
+        println!("Removed {} duplicate files", report.removed);
+    }
+    ExitCode::SUCCESS
 }
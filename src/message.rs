@@ -0,0 +1,651 @@
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig};
+use base64::engine::DecodePaddingMode;
+use base64::Engine;
+
+/// Lenient base64 for message content, which often arrives with missing or
+/// superfluous padding.
+const LENIENT_BASE64: GeneralPurpose = GeneralPurpose::new(
+    &base64::alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// The header section of a message, with folded lines joined.
+#[derive(Debug, Clone, Default)]
+pub struct Headers {
+    fields: Vec<(String, String)>,
+}
+
+impl Headers {
+    /// Parses the header section at the start of a raw RFC 822 message.
+    pub fn parse(raw: &[u8]) -> Self {
+        let end = header_end(raw);
+        let text = String::from_utf8_lossy(&raw[..end]);
+        let mut fields: Vec<(String, String)> = Vec::new();
+
+        for line in text.split('\n') {
+            let line = line.trim_end_matches('\r');
+            if line.is_empty() {
+                break;
+            }
+            if line.starts_with([' ', '\t']) {
+                if let Some((_, value)) = fields.last_mut() {
+                    value.push(' ');
+                    value.push_str(line.trim());
+                }
+                continue;
+            }
+            if let Some((name, value)) = line.split_once(':') {
+                fields.push((name.trim().to_string(), value.trim().to_string()));
+            }
+        }
+
+        Headers { fields }
+    }
+
+    /// The raw value of the first field called `name` (case-insensitive).
+    pub fn get(&self, name: &str) -> Option<&str> {
+        self.fields
+            .iter()
+            .find(|(field, _)| field.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    /// The value of `name` with RFC 2047 encoded-words decoded to UTF-8.
+    pub fn get_decoded(&self, name: &str) -> Option<String> {
+        self.get(name).map(decode_encoded_words)
+    }
+
+    pub fn subject(&self) -> Option<String> {
+        self.get_decoded("Subject")
+    }
+
+    pub fn from(&self) -> Option<String> {
+        self.get_decoded("From")
+    }
+
+    /// The first address in `From`, which may be internationalized (RFC 6532).
+    pub fn sender(&self) -> Option<Address> {
+        self.get("From").and_then(parse_address)
+    }
+
+    pub fn message_id(&self) -> Option<&str> {
+        self.get("Message-ID")
+    }
+}
+
+/// A mailbox from an address header, e.g. `"Jörg" <jörg@bücher.example>`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Address {
+    /// Display name with encoded-words decoded.
+    pub name: Option<String>,
+    /// The addr-spec, as UTF-8.
+    pub email: String,
+}
+
+impl Address {
+    /// The part after the `@`, lowercased.
+    pub fn domain(&self) -> Option<String> {
+        self.email
+            .rsplit_once('@')
+            .map(|(_, domain)| domain.to_lowercase())
+    }
+}
+
+/// Parses the first mailbox of an address header value. Addresses may be
+/// internationalized (RFC 6532), with UTF-8 in the local part and domain;
+/// display names may be quoted, encoded-words, or both.
+pub fn parse_address(value: &str) -> Option<Address> {
+    let value = strip_comments(value);
+    split_addresses(&value).into_iter().next().and_then(mailbox)
+}
+
+/// Parses every mailbox of an address header value such as `To` or `Cc`,
+/// leaving out groups and anything that isn't an address.
+pub fn parse_addresses(value: &str) -> Vec<Address> {
+    let value = strip_comments(value);
+    split_addresses(&value)
+        .into_iter()
+        .filter_map(mailbox)
+        .collect()
+}
+
+/// One mailbox of an address list, as `name <addr>` or a bare address.
+fn mailbox(address: &str) -> Option<Address> {
+    let (name, email) = match (address.find('<'), address.rfind('>')) {
+        (Some(open), Some(close)) if open < close => {
+            (address[..open].trim(), address[open + 1..close].trim())
+        }
+        _ => ("", address.trim()),
+    };
+    // A group such as `undisclosed-recipients:;` has no address
+    if !email.contains('@') || email.contains(char::is_whitespace) {
+        return None;
+    }
+    let name = name.trim_matches('"').replace("\\\"", "\"");
+    let name = decode_encoded_words(&name).trim().to_string();
+    Some(Address {
+        name: (!name.is_empty()).then_some(name),
+        email: email.to_string(),
+    })
+}
+
+/// Removes `(comments)` outside quoted strings.
+fn strip_comments(value: &str) -> String {
+    let mut stripped = String::with_capacity(value.len());
+    let (mut depth, mut quoted, mut escaped) = (0, false, false);
+    for c in value.chars() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' if depth == 0 => quoted = !quoted,
+            '(' if !quoted => {
+                depth += 1;
+                continue;
+            }
+            ')' if !quoted && depth > 0 => {
+                depth -= 1;
+                continue;
+            }
+            _ => {}
+        }
+        if depth == 0 {
+            stripped.push(c);
+        }
+    }
+    stripped
+}
+
+/// Splits an address list on the commas outside quoted strings and `<>`.
+fn split_addresses(value: &str) -> Vec<&str> {
+    let mut addresses = Vec::new();
+    let (mut start, mut quoted, mut angle, mut escaped) = (0, false, false, false);
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '"' => quoted = !quoted,
+            '<' if !quoted => angle = true,
+            '>' if !quoted => angle = false,
+            ',' if !quoted && !angle => {
+                addresses.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    addresses.push(&value[start..]);
+    addresses
+        .into_iter()
+        .map(str::trim)
+        .filter(|address| !address.is_empty())
+        .collect()
+}
+
+/// A parsed `Content-Type` value such as `text/plain; charset="iso-8859-1"`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ContentType {
+    /// Lowercased `type/subtype`.
+    pub mime_type: String,
+    params: Vec<(String, String)>,
+}
+
+impl ContentType {
+    pub fn parse(value: &str) -> Self {
+        let mut segments = split_params(value).into_iter();
+        let mime_type = segments
+            .next()
+            .unwrap_or_default()
+            .trim()
+            .to_ascii_lowercase();
+        let params = segments
+            .filter_map(|segment| {
+                let (name, value) = segment.split_once('=')?;
+                let value = value.trim().trim_matches('"').to_string();
+                Some((name.trim().to_ascii_lowercase(), value))
+            })
+            .collect();
+        ContentType { mime_type, params }
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(param, _)| param.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn is_multipart(&self) -> bool {
+        self.mime_type.starts_with("multipart/")
+    }
+}
+
+impl Default for ContentType {
+    /// RFC 2045 default for parts without a Content-Type header.
+    fn default() -> Self {
+        ContentType::parse("text/plain; charset=us-ascii")
+    }
+}
+
+/// Splits a header value on `;`, ignoring separators inside quotes.
+fn split_params(value: &str) -> Vec<String> {
+    let mut segments = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in value.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            ';' if !quoted => segments.push(std::mem::take(&mut current)),
+            _ => current.push(c),
+        }
+    }
+    segments.push(current);
+    segments
+}
+
+/// One MIME entity: its headers and the (still transfer-encoded) body.
+#[derive(Debug, Clone)]
+pub struct Part<'a> {
+    pub headers: Headers,
+    pub body: &'a [u8],
+}
+
+impl<'a> Part<'a> {
+    pub fn parse(raw: &'a [u8]) -> Self {
+        let end = header_end(raw);
+        let body_start = if raw[end..].starts_with(b"\r\n") {
+            end + 2
+        } else if raw[end..].starts_with(b"\n") {
+            end + 1
+        } else {
+            end
+        };
+        Part {
+            headers: Headers::parse(raw),
+            body: &raw[body_start..],
+        }
+    }
+
+    pub fn content_type(&self) -> ContentType {
+        self.headers
+            .get("Content-Type")
+            .map(ContentType::parse)
+            .unwrap_or_default()
+    }
+
+    pub fn is_attachment(&self) -> bool {
+        self.headers.get("Content-Disposition").is_some_and(|d| {
+            d.trim_start()
+                .to_ascii_lowercase()
+                .starts_with("attachment")
+        })
+    }
+
+    /// Direct children of a multipart entity (empty for leaf parts).
+    pub fn children(&self) -> Vec<Part<'a>> {
+        let content_type = self.content_type();
+        let boundary = match (content_type.is_multipart(), content_type.param("boundary")) {
+            (true, Some(boundary)) => format!("--{}", boundary),
+            _ => return Vec::new(),
+        };
+
+        let mut children = Vec::new();
+        let mut part_start: Option<usize> = None;
+        let mut offset = 0;
+        for line in self.body.split_inclusive(|&b| b == b'\n') {
+            let trimmed = line.trim_ascii_end();
+            if trimmed.starts_with(boundary.as_bytes()) {
+                if let Some(start) = part_start {
+                    // The CRLF before a boundary belongs to the boundary
+                    let end = trim_line_ending(self.body, offset);
+                    children.push(Part::parse(&self.body[start..end.max(start)]));
+                }
+                if trimmed[boundary.len()..].starts_with(b"--") {
+                    return children;
+                }
+                part_start = Some(offset + line.len());
+            }
+            offset += line.len();
+        }
+        if let Some(start) = part_start {
+            children.push(Part::parse(&self.body[start..]));
+        }
+        children
+    }
+
+    /// The body with its Content-Transfer-Encoding undone.
+    pub fn decoded_body(&self) -> Vec<u8> {
+        let encoding = self
+            .headers
+            .get("Content-Transfer-Encoding")
+            .unwrap_or("7bit")
+            .trim()
+            .to_ascii_lowercase();
+        match encoding.as_str() {
+            "base64" => {
+                let compact: Vec<u8> = self
+                    .body
+                    .iter()
+                    .copied()
+                    .filter(|b| !b.is_ascii_whitespace())
+                    .collect();
+                LENIENT_BASE64
+                    .decode(compact)
+                    .unwrap_or_else(|_| self.body.to_vec())
+            }
+            "quoted-printable" => decode_quoted_printable(self.body),
+            _ => self.body.to_vec(),
+        }
+    }
+
+    /// The body decoded to UTF-8 using the part's declared charset.
+    pub fn text(&self) -> String {
+        let content_type = self.content_type();
+        let charset = content_type.param("charset").unwrap_or("us-ascii");
+        decode_charset(charset, &self.decoded_body())
+    }
+
+    /// Visits this part and all of its descendants depth-first.
+    pub fn walk<F: FnMut(&Part<'a>)>(&self, visit: &mut F) {
+        visit(self);
+        for child in self.children() {
+            child.walk(visit);
+        }
+    }
+}
+
+fn trim_line_ending(body: &[u8], end: usize) -> usize {
+    if end >= 2 && &body[end - 2..end] == b"\r\n" {
+        end - 2
+    } else if end >= 1 && body[end - 1] == b'\n' {
+        end - 1
+    } else {
+        end
+    }
+}
+
+/// Finds the first inline part of the given MIME type (e.g. `text/plain`)
+/// and returns its body decoded to UTF-8.
+pub fn find_text_part(raw: &[u8], mime_type: &str) -> Option<String> {
+    let mut text = None;
+    Part::parse(raw).walk(&mut |part| {
+        if text.is_none() && !part.is_attachment() && part.content_type().mime_type == mime_type {
+            text = Some(part.text());
+        }
+    });
+    text
+}
+
+fn decode_quoted_printable(body: &[u8]) -> Vec<u8> {
+    let mut decoded = Vec::with_capacity(body.len());
+    let mut i = 0;
+    while i < body.len() {
+        if body[i] == b'=' {
+            let rest = &body[i + 1..];
+            if rest.starts_with(b"\r\n") {
+                i += 3;
+                continue;
+            }
+            if rest.starts_with(b"\n") {
+                i += 2;
+                continue;
+            }
+            let hex = rest.get(..2).and_then(|h| std::str::from_utf8(h).ok());
+            if let Some(byte) = hex.and_then(|h| u8::from_str_radix(h, 16).ok()) {
+                decoded.push(byte);
+                i += 3;
+                continue;
+            }
+        }
+        decoded.push(body[i]);
+        i += 1;
+    }
+    decoded
+}
+
+/// Offset of the blank line separating headers from the body (or the whole
+/// input when there is none).
+pub fn header_end(raw: &[u8]) -> usize {
+    if raw.starts_with(b"\r\n") || raw.starts_with(b"\n") {
+        return 0;
+    }
+    raw.windows(4)
+        .position(|w| w == b"\r\n\r\n")
+        .map(|pos| pos + 2)
+        .or_else(|| raw.windows(2).position(|w| w == b"\n\n").map(|pos| pos + 1))
+        .unwrap_or(raw.len())
+}
+
+/// Decodes RFC 2047 encoded-words (`=?UTF-8?B?...?=`, `=?ISO-8859-1?Q?...?=`)
+/// in a header value. Whitespace between adjacent encoded-words is dropped as
+/// the RFC requires; anything that does not decode is kept verbatim.
+pub fn decode_encoded_words(value: &str) -> String {
+    let mut decoded = String::with_capacity(value.len());
+    let mut rest = value;
+    let mut previous_was_word = false;
+
+    while let Some(start) = rest.find("=?") {
+        let before = &rest[..start];
+        match parse_encoded_word(&rest[start..]) {
+            Some((text, consumed)) => {
+                let only_whitespace = before.chars().all(char::is_whitespace);
+                if !(previous_was_word && only_whitespace) {
+                    decoded.push_str(before);
+                }
+                decoded.push_str(&text);
+                rest = &rest[start + consumed..];
+                previous_was_word = true;
+            }
+            None => {
+                decoded.push_str(before);
+                decoded.push_str("=?");
+                rest = &rest[start + 2..];
+                previous_was_word = false;
+            }
+        }
+    }
+
+    decoded.push_str(rest);
+    decoded
+}
+
+/// Parses one encoded-word at the start of `input`, returning the decoded
+/// text and the number of bytes consumed.
+fn parse_encoded_word(input: &str) -> Option<(String, usize)> {
+    let body = input.strip_prefix("=?")?;
+    let (charset, body) = body.split_once('?')?;
+    let (encoding, body) = body.split_once('?')?;
+    let end = body.find("?=")?;
+    let text = &body[..end];
+    if text.contains(char::is_whitespace) {
+        return None;
+    }
+
+    let bytes = match encoding {
+        "B" | "b" => LENIENT_BASE64.decode(text).ok()?,
+        "Q" | "q" => decode_q(text)?,
+        _ => return None,
+    };
+
+    // RFC 2231 allows a language suffix: `UTF-8*en`
+    let charset = charset.split('*').next().unwrap_or_default();
+    let consumed = input.len() - body[end + 2..].len();
+    Some((decode_charset(charset, &bytes), consumed))
+}
+
+fn decode_q(text: &str) -> Option<Vec<u8>> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut iter = text.bytes();
+    while let Some(b) = iter.next() {
+        match b {
+            b'_' => bytes.push(b' '),
+            b'=' => {
+                let hex = [iter.next()?, iter.next()?];
+                let hex = std::str::from_utf8(&hex).ok()?;
+                bytes.push(u8::from_str_radix(hex, 16).ok()?);
+            }
+            _ => bytes.push(b),
+        }
+    }
+    Some(bytes)
+}
+
+/// Converts `bytes` in the named charset to UTF-8, falling back to lossy UTF-8
+/// for unknown charsets.
+pub fn decode_charset(charset: &str, bytes: &[u8]) -> String {
+    match encoding_rs::Encoding::for_label(charset.trim().as_bytes()) {
+        Some(encoding) => encoding.decode_without_bom_handling(bytes).0.into_owned(),
+        None => String::from_utf8_lossy(bytes).into_owned(),
+    }
+}
+
+/// Parses an RFC 2822 date such as `Tue, 1 Jul 2003 10:52:37 +0200` into
+/// seconds since the Unix epoch (UTC). Obsolete two-digit years and the
+/// North American zone names are accepted.
+pub fn parse_date(value: &str) -> Option<i64> {
+    // Drop comments such as "(PDT)" and the optional day-of-week
+    let mut cleaned = String::with_capacity(value.len());
+    let mut depth = 0;
+    for c in value.chars() {
+        match c {
+            '(' => depth += 1,
+            ')' if depth > 0 => depth -= 1,
+            _ if depth == 0 => cleaned.push(c),
+            _ => {}
+        }
+    }
+    let cleaned = match cleaned.split_once(',') {
+        Some((_, rest)) => rest.to_string(),
+        None => cleaned,
+    };
+
+    let mut tokens = cleaned.split_whitespace();
+    let day: u32 = tokens.next()?.parse().ok()?;
+    let month = month_number(tokens.next()?)?;
+    let year: i64 = match tokens.next()?.parse::<i64>().ok()? {
+        y @ 0..=49 => y + 2000,
+        y @ 50..=999 => y + 1900,
+        y => y,
+    };
+
+    let mut time = tokens.next()?.split(':');
+    let hour: i64 = time.next()?.parse().ok()?;
+    let minute: i64 = time.next()?.parse().ok()?;
+    let second: i64 = time.next().map_or(Some(0), |s| s.parse().ok())?;
+
+    let offset = zone_offset(tokens.next().unwrap_or("+0000"));
+    let days = days_from_civil(year, month, day);
+    Some(days * 86_400 + hour * 3600 + minute * 60 + second - offset)
+}
+
+/// Parses an IMAP INTERNALDATE such as `17-Jul-1996 02:44:25 -0700` into a
+/// Unix timestamp.
+pub fn parse_internal_date(value: &str) -> Option<i64> {
+    let (date, time) = value.trim().split_once(' ')?;
+    parse_date(&format!("{} {}", date.replace('-', " "), time))
+}
+
+/// Parses an RFC 3339 UTC timestamp such as `2014-10-30T06:12:00Z`, as used
+/// for JMAP's `receivedAt`, into a Unix timestamp. Fractional seconds are
+/// dropped.
+pub fn parse_utc_date(value: &str) -> Option<i64> {
+    let (date, time) = value.trim().split_once(['T', 't'])?;
+    let time = time.strip_suffix(['Z', 'z'])?;
+    let mut date = date.splitn(3, '-').map(str::parse::<i64>);
+    let (year, month, day) = (date.next()?.ok()?, date.next()?.ok()?, date.next()?.ok()?);
+    let time = time.split('.').next()?;
+    let mut time = time.splitn(3, ':').map(str::parse::<i64>);
+    let (hour, minute, second) = (time.next()?.ok()?, time.next()?.ok()?, time.next()?.ok()?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return None;
+    }
+    Some(
+        days_from_civil(year, month as u32, day as u32) * 86_400
+            + hour * 3600
+            + minute * 60
+            + second,
+    )
+}
+
+fn month_number(name: &str) -> Option<u32> {
+    let months = [
+        "jan", "feb", "mar", "apr", "may", "jun", "jul", "aug", "sep", "oct", "nov", "dec",
+    ];
+    let prefix = name.get(..3)?.to_ascii_lowercase();
+    months
+        .iter()
+        .position(|m| *m == prefix)
+        .map(|i| i as u32 + 1)
+}
+
+/// Offset of a zone from UTC in seconds; unknown zones count as UTC.
+fn zone_offset(zone: &str) -> i64 {
+    let hours = match zone.to_ascii_uppercase().as_str() {
+        "EDT" => -4,
+        "EST" | "CDT" => -5,
+        "CST" | "MDT" => -6,
+        "MST" | "PDT" => -7,
+        "PST" => -8,
+        _ => 0,
+    };
+    if hours != 0 {
+        return hours * 3600;
+    }
+
+    let (sign, digits) = match zone.as_bytes().first() {
+        Some(b'+') => (1, &zone[1..]),
+        Some(b'-') => (-1, &zone[1..]),
+        _ => return 0,
+    };
+    match (digits.get(..2), digits.get(2..4)) {
+        (Some(h), Some(m)) => match (h.parse::<i64>(), m.parse::<i64>()) {
+            (Ok(h), Ok(m)) => sign * (h * 3600 + m * 60),
+            _ => 0,
+        },
+        _ => 0,
+    }
+}
+
+/// Days since 1970-01-01 for a proleptic Gregorian date.
+pub fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = month as i64;
+    let day_of_year =
+        (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Calendar date `(year, month, day)` for days since 1970-01-01.
+pub fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days - era * 146_097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let mp = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Formats a Unix timestamp as a sortable UTC stamp, e.g. `20030701T085237`.
+pub fn format_timestamp(timestamp: i64) -> String {
+    let (year, month, day) = civil_from_days(timestamp.div_euclid(86_400));
+    let seconds = timestamp.rem_euclid(86_400);
+    format!(
+        "{:04}{:02}{:02}T{:02}{:02}{:02}",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60
+    )
+}
@@ -0,0 +1,1123 @@
+use std::net::SocketAddr;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+use base64::Engine;
+
+use crate::folders::encode_mailbox_name;
+use crate::message::{parse_internal_date, parse_utc_date};
+use crate::session::{trailing_literal_len, SequenceSet};
+
+/// A mailbox served by [`MockServer`]. Message `i` has UID `i + 1`.
+#[derive(Debug, Clone)]
+pub struct MockMailbox {
+    pub name: String,
+    pub messages: Vec<Vec<u8>>,
+}
+
+/// A small in-process IMAP server over plain TCP, serving fixed mailboxes to
+/// any login, or to XOAUTH2 with [`MOCK_ACCESS_TOKEN`]. It supports what the
+/// fetcher uses:
+///
+/// - LOGIN, AUTHENTICATE XOAUTH2, ID, CAPABILITY, NOOP and LOGOUT.
+/// - ENABLE UTF8=ACCEPT, after which mailbox names are UTF-8 rather than
+///   modified UTF-7.
+/// - NAMESPACE, and LIST marking Gmail's special folders as Gmail does.
+/// - STATUS, and GETQUOTAROOT with a 15 GiB quota.
+/// - SELECT and EXAMINE.
+/// - FETCH and UID FETCH of sizes, dates, flags, labels and bodies, whole or
+///   part of one with `BODY.PEEK[]<origin.count>`.
+/// - UID SEARCH by a UID set, SINCE, BEFORE and one word.
+/// - CREATE, UID MOVE and COPY (to existing or created mailboxes, with
+///   COPYUID for MOVE), UID STORE and UID EXPUNGE, which are accepted but
+///   leave the mailboxes as they are.
+///
+/// Messages differ by UID:
+///
+/// - Flags and labels cycle: `\Seen`, then `\Seen \Answered \Flagged` in
+///   `\Inbox \Important "Project X"`, then none.
+/// - The message with UID `n` is received at noon UTC on day `n % 28 + 1` of
+///   a month of 2024: January for UIDs 1 to 27, February for 28 to 55 and so
+///   on.
+///
+/// Mailboxes named `INBOX.*` make it behave like Cyrus: the personal namespace
+/// is `INBOX.` and the hierarchy delimiter `.`. Otherwise it is Gmail's, with
+/// no prefix and `/`.
+pub struct MockServer {
+    addr: SocketAddr,
+    handle: JoinHandle<()>,
+    connections: Arc<Connections>,
+    #[cfg_attr(not(feature = "faults"), allow(dead_code))]
+    conduct: Arc<Conduct>,
+}
+
+/// How the server misbehaves.
+#[derive(Default)]
+struct Conduct {
+    /// Bytes of a message body after which the connection is dropped, once;
+    /// 0 for none.
+    cutoff: AtomicUsize,
+    #[cfg(feature = "faults")]
+    faults: Option<crate::faults::Faults>,
+    /// Faults injected so far.
+    #[cfg(feature = "faults")]
+    injected: AtomicUsize,
+}
+
+/// Connections open now, and the most open at once.
+#[derive(Default)]
+struct Connections {
+    open: AtomicUsize,
+    peak: AtomicUsize,
+}
+
+impl MockServer {
+    pub async fn start(mailboxes: Vec<MockMailbox>) -> std::io::Result<Self> {
+        Self::start_with(mailboxes, Conduct::default()).await
+    }
+
+    /// Like [`Self::start`], but the first time it sends a message body
+    /// longer than `cutoff` bytes, it drops the connection after `cutoff`
+    /// bytes of it.
+    pub async fn start_interrupting(
+        mailboxes: Vec<MockMailbox>,
+        cutoff: usize,
+    ) -> std::io::Result<Self> {
+        let conduct = Conduct::default();
+        conduct.cutoff.store(cutoff, Ordering::SeqCst);
+        Self::start_with(mailboxes, conduct).await
+    }
+
+    /// Like [`Self::start`], but injecting `faults` into its responses.
+    #[cfg(feature = "faults")]
+    pub async fn start_faulty(
+        mailboxes: Vec<MockMailbox>,
+        faults: crate::faults::Faults,
+    ) -> std::io::Result<Self> {
+        let conduct = Conduct {
+            faults: Some(faults),
+            ..Conduct::default()
+        };
+        Self::start_with(mailboxes, conduct).await
+    }
+
+    async fn start_with(mailboxes: Vec<MockMailbox>, conduct: Conduct) -> std::io::Result<Self> {
+        let listener = TcpListener::bind("127.0.0.1:0").await?;
+        let addr = listener.local_addr()?;
+        let mailboxes = Arc::new(mailboxes);
+        let connections = Arc::new(Connections::default());
+        let conduct = Arc::new(conduct);
+
+        let counted = Arc::clone(&connections);
+        let behaviour = Arc::clone(&conduct);
+        let handle = tokio::spawn(async move {
+            let mut accepted = 0;
+            while let Ok((stream, _)) = listener.accept().await {
+                let mailboxes = Arc::clone(&mailboxes);
+                let connections = Arc::clone(&counted);
+                let conduct = Arc::clone(&behaviour);
+                accepted += 1;
+                let connection = accepted;
+                tokio::spawn(async move {
+                    let open = connections.open.fetch_add(1, Ordering::SeqCst) + 1;
+                    connections.peak.fetch_max(open, Ordering::SeqCst);
+                    if let Err(e) = serve(stream, &mailboxes, &conduct, connection).await {
+                        log::debug!("Mock connection ended: {}", e);
+                    }
+                    connections.open.fetch_sub(1, Ordering::SeqCst);
+                });
+            }
+        });
+
+        Ok(MockServer {
+            addr,
+            handle,
+            connections,
+            conduct,
+        })
+    }
+
+    /// How many faults were injected so far.
+    #[cfg(feature = "faults")]
+    pub fn faults_injected(&self) -> usize {
+        self.conduct.injected.load(Ordering::SeqCst)
+    }
+
+    /// The most connections that were open at once.
+    pub fn peak_connections(&self) -> usize {
+        self.connections.peak.load(Ordering::SeqCst)
+    }
+
+    pub fn addr(&self) -> SocketAddr {
+        self.addr
+    }
+
+    /// Server address in the form the client accepts for plain connections.
+    pub fn url(&self) -> String {
+        format!("imap://{}", self.addr)
+    }
+}
+
+impl Drop for MockServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// Storage limit of [`MockServer`]'s quota, in KiB (15 GiB, like Gmail's).
+pub const MOCK_STORAGE_LIMIT: u64 = 15 * 1024 * 1024;
+
+/// A plain-text message of roughly `size` bytes with a unique Message-ID.
+pub fn synthetic_message(index: usize, size: usize) -> Vec<u8> {
+    let mut message = format!(
+        "From: Sender {0} <sender{0}@example.com>\r\n\
+         To: bench@example.com\r\n\
+         Subject: Message {0}\r\n\
+         Date: Mon, 1 Jan 2024 12:00:00 +0000\r\n\
+         Message-ID: <{0}@mock.example.com>\r\n\
+         Content-Type: text/plain; charset=utf-8\r\n\r\n",
+        index
+    )
+    .into_bytes();
+    let line = b"Lorem ipsum dolor sit amet, consectetur adipiscing elit.\r\n";
+    while message.len() < size {
+        message.extend_from_slice(line);
+    }
+    message
+}
+
+async fn serve(
+    stream: TcpStream,
+    mailboxes: &[MockMailbox],
+    conduct: &Conduct,
+    connection: usize,
+) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    writer
+        .write_all(
+            format!(
+                "* OK [CAPABILITY {}] Mock IMAP server ready\r\n",
+                CAPABILITIES
+            )
+            .as_bytes(),
+        )
+        .await?;
+    let cyrus = mailboxes.iter().any(|m| m.name.starts_with("INBOX."));
+    let (prefix, delimiter) = if cyrus { ("INBOX.", '.') } else { ("", '/') };
+
+    let mut selected: Option<&MockMailbox> = None;
+    let mut utf8 = false;
+    let mut created: Vec<String> = Vec::new();
+    #[cfg(feature = "faults")]
+    let mut faults = conduct
+        .faults
+        .as_ref()
+        .map(|faults| faults.injector(connection));
+    #[cfg(not(feature = "faults"))]
+    let _ = connection;
+    loop {
+        let Some(line) = read_command(&mut reader, &mut writer).await? else {
+            return Ok(());
+        };
+        let (tag, rest) = line.split_once(' ').unwrap_or((&line, ""));
+        let (mut command, mut args) = rest.split_once(' ').unwrap_or((rest, ""));
+        let uid = command.eq_ignore_ascii_case("UID");
+        if uid {
+            (command, args) = args.split_once(' ').unwrap_or((args, ""));
+        }
+
+        #[cfg(feature = "faults")]
+        let mut dropped_after = None;
+        let mut miscount = false;
+        #[cfg(feature = "faults")]
+        if let Some(fault) = faults.as_mut().and_then(|faults| faults.next(command)) {
+            use crate::faults::Fault;
+            conduct.injected.fetch_add(1, Ordering::SeqCst);
+            match fault {
+                Fault::Drop(fraction) => dropped_after = Some(fraction),
+                Fault::Delay(delay) => tokio::time::sleep(delay).await,
+                Fault::No => {
+                    let response = format!("{} NO [UNAVAILABLE] Injected failure\r\n", tag);
+                    writer.write_all(response.as_bytes()).await?;
+                    continue;
+                }
+                Fault::Bad => {
+                    let response = format!("{} BAD Injected failure\r\n", tag);
+                    writer.write_all(response.as_bytes()).await?;
+                    continue;
+                }
+                Fault::Miscount => miscount = true,
+            }
+        }
+
+        let mut out = Vec::new();
+        match (command.to_ascii_uppercase().as_str(), selected) {
+            ("CAPABILITY", _) => {
+                out.extend_from_slice(format!("* CAPABILITY {}\r\n", CAPABILITIES).as_bytes());
+            }
+            ("ID", _) => {
+                out.extend_from_slice(
+                    format!(
+                        "* ID (\"name\" \"{}\" \"version\" \"{}\" \"vendor\" NIL)\r\n",
+                        MOCK_SERVER_NAME, MOCK_SERVER_VERSION
+                    )
+                    .as_bytes(),
+                );
+            }
+            ("ENABLE", _) => {
+                if args
+                    .split_whitespace()
+                    .any(|capability| capability.eq_ignore_ascii_case("UTF8=ACCEPT"))
+                {
+                    utf8 = true;
+                    out.extend_from_slice(b"* ENABLED UTF8=ACCEPT\r\n");
+                }
+            }
+            ("NAMESPACE", _) => {
+                out.extend_from_slice(
+                    format!(
+                        "* NAMESPACE ((\"{}\" \"{}\")) NIL NIL\r\n",
+                        prefix, delimiter
+                    )
+                    .as_bytes(),
+                );
+            }
+            ("LOGIN" | "NOOP", _) => {}
+            ("AUTHENTICATE", _) => {
+                let bearer = format!("auth=Bearer {}\x01", MOCK_ACCESS_TOKEN);
+                let accepted = args
+                    .strip_prefix("XOAUTH2 ")
+                    .and_then(|response| {
+                        base64::engine::general_purpose::STANDARD
+                            .decode(response.trim())
+                            .ok()
+                    })
+                    .is_some_and(|response| contains(&response, bearer.as_bytes()));
+                if !accepted {
+                    // {"status":"401","schemes":"bearer"}, to be acknowledged
+                    // before the failure is reported
+                    writer
+                        .write_all(b"+ eyJzdGF0dXMiOiI0MDEiLCJzY2hlbWVzIjoiYmVhcmVyIn0=\r\n")
+                        .await?;
+                    read_command(&mut reader, &mut writer).await?;
+                    writer
+                        .write_all(format!("{} NO AUTHENTICATE failed\r\n", tag).as_bytes())
+                        .await?;
+                    continue;
+                }
+            }
+            ("LOGOUT", _) => {
+                out.extend_from_slice(
+                    format!("* BYE Logging out\r\n{} OK LOGOUT\r\n", tag).as_bytes(),
+                );
+                writer.write_all(&out).await?;
+                return Ok(());
+            }
+            ("LIST", _) => {
+                for mailbox in mailboxes {
+                    let special = gmail_special_use(&mailbox.name)
+                        .map(|attribute| format!(" {}", attribute))
+                        .unwrap_or_default();
+                    out.extend_from_slice(
+                        format!(
+                            "* LIST (\\HasNoChildren{}) \"{}\" \"{}\"\r\n",
+                            special,
+                            delimiter,
+                            wire_name(&mailbox.name, utf8)
+                        )
+                        .as_bytes(),
+                    );
+                }
+            }
+            ("STATUS", _) => {
+                let name = args
+                    .split(" (")
+                    .next()
+                    .unwrap_or_default()
+                    .trim_matches('"');
+                if let Some(mailbox) = mailboxes.iter().find(|m| wire_name(&m.name, utf8) == name) {
+                    out.extend_from_slice(
+                        format!(
+                            "* STATUS \"{}\" (MESSAGES {})\r\n",
+                            name,
+                            mailbox.messages.len()
+                        )
+                        .as_bytes(),
+                    );
+                }
+            }
+            ("GETQUOTAROOT", _) => {
+                let bytes: usize = mailboxes
+                    .iter()
+                    .flat_map(|m| &m.messages)
+                    .map(Vec::len)
+                    .sum();
+                out.extend_from_slice(
+                    format!(
+                        "* QUOTAROOT {} \"\"\r\n* QUOTA \"\" (STORAGE {} {})\r\n",
+                        args,
+                        bytes.div_ceil(1024),
+                        MOCK_STORAGE_LIMIT
+                    )
+                    .as_bytes(),
+                );
+            }
+            ("SELECT" | "EXAMINE", _) => {
+                let name = args.trim().trim_matches('"');
+                selected = mailboxes.iter().find(|m| wire_name(&m.name, utf8) == name);
+                match selected {
+                    Some(mailbox) => out.extend_from_slice(
+                        format!(
+                            "* {} EXISTS\r\n* OK [UIDVALIDITY 1] UIDs valid\r\n",
+                            mailbox.messages.len()
+                        )
+                        .as_bytes(),
+                    ),
+                    None => {
+                        out.extend_from_slice(format!("{} NO No such mailbox\r\n", tag).as_bytes());
+                        writer.write_all(&out).await?;
+                        continue;
+                    }
+                }
+            }
+            ("FETCH", Some(mailbox)) => {
+                let (set, items) = args.split_once(' ').unwrap_or((args, ""));
+                if let Ok(set) = set.parse::<SequenceSet>() {
+                    let largest = mailbox.messages.len() as u32;
+                    for (i, message) in mailbox.messages.iter().enumerate() {
+                        let n = i as u32 + 1;
+                        if !set.contains(n, largest) {
+                            continue;
+                        }
+                        let start = out.len();
+                        let body = write_fetch(&mut out, n, message, &items.to_ascii_uppercase());
+                        if let Some(len) = body.filter(|&len| miscount && len > 1) {
+                            miscount = false;
+                            let announced = format!("{{{}}}\r\n", len);
+                            let at = start
+                                + out[start..]
+                                    .windows(announced.len())
+                                    .position(|w| w == announced.as_bytes())
+                                    .unwrap_or(0);
+                            out.splice(
+                                at..at + announced.len(),
+                                format!("{{{}}}\r\n", len / 2).into_bytes(),
+                            );
+                        }
+                        let limit = conduct.cutoff.load(Ordering::SeqCst);
+                        if limit > 0
+                            && body.is_some_and(|len| len > limit)
+                            && conduct.cutoff.swap(0, Ordering::SeqCst) > 0
+                        {
+                            // Cut the body off and go away
+                            let literal = start
+                                + out[start..]
+                                    .windows(3)
+                                    .position(|w| w == b"}\r\n")
+                                    .unwrap_or(0)
+                                + 3;
+                            out.truncate(literal + limit);
+                            writer.write_all(&out).await?;
+                            return Ok(());
+                        }
+                    }
+                }
+            }
+            ("CREATE", _) => {
+                created.push(args.trim().trim_matches('"').to_string());
+            }
+            ("MOVE" | "COPY", Some(_)) => {
+                // Only the target is checked; the mailboxes never change
+                let (set, target) = args
+                    .split_once(' ')
+                    .map(|(set, target)| (set, target.trim().trim_matches('"')))
+                    .unwrap_or_default();
+                let exists = mailboxes.iter().any(|m| wire_name(&m.name, utf8) == target)
+                    || created.iter().any(|name| name == target);
+                if !exists {
+                    out.extend_from_slice(
+                        format!("{} NO [TRYCREATE] No such mailbox\r\n", tag).as_bytes(),
+                    );
+                    writer.write_all(&out).await?;
+                    continue;
+                }
+                // As UIDPLUS servers do, with the moved messages keeping their UIDs
+                if command.eq_ignore_ascii_case("MOVE") {
+                    out.extend_from_slice(
+                        format!("* OK [COPYUID 1 {} {}]\r\n", set, set).as_bytes(),
+                    );
+                }
+            }
+            ("STORE" | "EXPUNGE", Some(_)) => {}
+            ("SEARCH", Some(mailbox)) => {
+                // An optional `UID set`, SINCE and BEFORE dates, then one key
+                // whose last word is looked for anywhere in the message
+                let (set, mut args) = match args.strip_prefix("UID ") {
+                    Some(rest) => {
+                        let (set, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+                        (set.parse::<SequenceSet>().ok(), rest)
+                    }
+                    None => (None, args),
+                };
+                let (mut since, mut before) = (i64::MIN, i64::MAX);
+                while let Some((key, rest)) = args.split_once(' ') {
+                    let (date, rest) = rest.split_once(' ').unwrap_or((rest, ""));
+                    let day = parse_internal_date(&format!("{} 00:00:00 +0000", date));
+                    match (key.to_ascii_uppercase().as_str(), day) {
+                        ("SINCE", Some(day)) => since = day,
+                        ("BEFORE", Some(day)) => before = day,
+                        _ => break,
+                    }
+                    args = rest;
+                }
+                let needle = args
+                    .rsplit(' ')
+                    .next()
+                    .unwrap_or_default()
+                    .trim_matches('"');
+                let largest = mailbox.messages.len() as u32;
+                out.extend_from_slice(b"* SEARCH");
+                for (i, message) in mailbox.messages.iter().enumerate() {
+                    let in_set = set
+                        .as_ref()
+                        .is_none_or(|set| set.contains(i as u32 + 1, largest));
+                    let matches =
+                        matches!(needle, "" | "ALL") || contains(message, needle.as_bytes());
+                    let day = mock_day(i as u32 + 1);
+                    if in_set && matches && (since..before).contains(&day) {
+                        out.extend_from_slice(format!(" {}", i + 1).as_bytes());
+                    }
+                }
+                out.extend_from_slice(b"\r\n");
+            }
+            _ => {
+                out.extend_from_slice(format!("{} BAD Unsupported command\r\n", tag).as_bytes());
+                writer.write_all(&out).await?;
+                continue;
+            }
+        }
+        out.extend_from_slice(format!("{} OK {} completed\r\n", tag, command).as_bytes());
+        #[cfg(feature = "faults")]
+        if let Some(fraction) = dropped_after {
+            let end = (out.len() as f64 * fraction) as usize;
+            writer.write_all(&out[..end]).await?;
+            return Ok(());
+        }
+        writer.write_all(&out).await?;
+    }
+}
+
+/// Reads one command, answering synchronizing literals with a continuation
+/// and inlining their contents. Returns `None` once the client disconnects.
+async fn read_command<R, W>(reader: &mut R, writer: &mut W) -> std::io::Result<Option<String>>
+where
+    R: AsyncBufReadExt + Unpin,
+    W: AsyncWriteExt + Unpin,
+{
+    let mut command = String::new();
+    loop {
+        let mut line = Vec::new();
+        if reader.read_until(b'\n', &mut line).await? == 0 {
+            return Ok(None);
+        }
+        let line = String::from_utf8_lossy(&line).trim_end().to_string();
+        match trailing_literal_len(&line) {
+            Some((len, start)) => {
+                if !line.ends_with("+}") {
+                    writer.write_all(b"+ Ready for literal data\r\n").await?;
+                }
+                let mut literal = vec![0; len];
+                reader.read_exact(&mut literal).await?;
+                command.push_str(&line[..start]);
+                command.push('"');
+                command.push_str(&String::from_utf8_lossy(&literal));
+                command.push('"');
+            }
+            None => {
+                command.push_str(&line);
+                return Ok(Some(command));
+            }
+        }
+    }
+}
+
+/// Midnight UTC of the day the message with `uid` was received.
+fn mock_day(uid: u32) -> i64 {
+    parse_internal_date(&format!("{} 00:00:00 +0000", mock_date(uid))).unwrap_or(0)
+}
+
+/// The day the message with `uid` was received, as in an INTERNALDATE.
+fn mock_date(uid: u32) -> String {
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    format!(
+        "{:02}-{}-2024",
+        uid % 28 + 1,
+        MONTHS[(uid / 28) as usize % 12]
+    )
+}
+
+/// Writes the FETCH response for message `uid`, returning the length of
+/// the body it holds, if any. `BODY.PEEK[]<origin.count>` sends that part.
+fn write_fetch(out: &mut Vec<u8>, uid: u32, message: &[u8], items: &str) -> Option<usize> {
+    let mut fields = format!("* {} FETCH (UID {}", uid, uid);
+    if items.contains("INTERNALDATE") {
+        fields.push_str(&format!(
+            " INTERNALDATE \"{} 12:00:00 +0000\"",
+            mock_date(uid)
+        ));
+    }
+    if items.contains("X-GM-THRID") {
+        fields.push_str(&format!(" X-GM-THRID {}", 1_000_000 + uid));
+    }
+    if items.contains("FLAGS") {
+        fields.push_str(match uid % 3 {
+            1 => " FLAGS (\\Seen)",
+            2 => " FLAGS (\\Seen \\Answered \\Flagged)",
+            _ => " FLAGS ()",
+        });
+    }
+    if items.contains("X-GM-LABELS") {
+        fields.push_str(match uid % 3 {
+            1 => " X-GM-LABELS (\\Inbox)",
+            2 => " X-GM-LABELS (\\Inbox \\Important \"Project X\")",
+            _ => " X-GM-LABELS ()",
+        });
+    }
+    if items.contains("RFC822.SIZE") {
+        fields.push_str(&format!(" RFC822.SIZE {}", message.len()));
+    }
+    if items.contains("BODY") {
+        let (name, body) = match partial_range(items) {
+            Some((origin, count)) => {
+                let start = origin.min(message.len());
+                let end = start.saturating_add(count).min(message.len());
+                (format!("BODY[]<{}>", origin), &message[start..end])
+            }
+            None => ("BODY[]".to_string(), message),
+        };
+        fields.push_str(&format!(" {} {{{}}}\r\n", name, body.len()));
+        out.extend_from_slice(fields.as_bytes());
+        out.extend_from_slice(body);
+        out.extend_from_slice(b")\r\n");
+        Some(body.len())
+    } else {
+        out.extend_from_slice(fields.as_bytes());
+        out.extend_from_slice(b")\r\n");
+        None
+    }
+}
+
+/// The origin and count of a `BODY.PEEK[]<origin.count>` in `items`.
+fn partial_range(items: &str) -> Option<(usize, usize)> {
+    let at = items.find("[]<")? + 3;
+    let range = &items[at..at + items[at..].find('>')?];
+    let (origin, count) = range.split_once('.')?;
+    Some((origin.parse().ok()?, count.parse().ok()?))
+}
+
+/// How the server identifies itself in response to ID.
+pub const MOCK_SERVER_NAME: &str = "Mock IMAP";
+pub const MOCK_SERVER_VERSION: &str = "1.0";
+
+const CAPABILITIES: &str =
+    "IMAP4rev1 ENABLE ID LITERAL+ MOVE NAMESPACE QUOTA SPECIAL-USE UIDPLUS UTF8=ACCEPT X-GM-EXT-1";
+
+/// A mailbox name as it goes over the wire, depending on whether the client
+/// enabled UTF8=ACCEPT.
+fn wire_name(name: &str, utf8: bool) -> String {
+    if utf8 {
+        name.to_string()
+    } else {
+        encode_mailbox_name(name)
+    }
+}
+
+/// The special-use attribute Gmail gives the folder `name` (RFC 6154).
+fn gmail_special_use(name: &str) -> Option<&'static str> {
+    match name {
+        "[Gmail]/All Mail" => Some("\\All"),
+        "[Gmail]/Drafts" => Some("\\Drafts"),
+        "[Gmail]/Sent Mail" => Some("\\Sent"),
+        "[Gmail]/Spam" => Some("\\Junk"),
+        "[Gmail]/Starred" => Some("\\Flagged"),
+        "[Gmail]/Trash" => Some("\\Trash"),
+        _ => None,
+    }
+}
+
+fn contains(haystack: &[u8], needle: &[u8]) -> bool {
+    needle.is_empty() || haystack.windows(needle.len()).any(|w| w == needle)
+}
+
+/// A small in-process JMAP server over plain HTTP, serving fixed mailboxes to
+/// any bearer token. Supports what the JMAP backend uses: the session
+/// resource, Mailbox/get, Email/query, Email/get and blob downloads. Message
+/// `i` of mailbox `m` has the email and blob ID `m-i`, and is dated like the
+/// IMAP mock's UID `i + 1`.
+pub struct MockJmapServer {
+    addr: SocketAddr,
+    handle: JoinHandle<()>,
+}
+
+impl MockJmapServer {
+    pub async fn start(mailboxes: Vec<MockMailbox>) -> std::io::Result<Self> {
+        let (addr, handle) = start_http(mailboxes, jmap_http).await?;
+        Ok(MockJmapServer { addr, handle })
+    }
+
+    /// URL of the session resource.
+    pub fn url(&self) -> String {
+        format!("http://{}/jmap/session", self.addr)
+    }
+}
+
+impl Drop for MockJmapServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+/// An HTTP request received by a mock server.
+struct HttpRequest {
+    method: String,
+    /// Path without the query string.
+    path: String,
+    query: Vec<(String, String)>,
+    bearer: Option<String>,
+    body: Vec<u8>,
+}
+
+impl HttpRequest {
+    fn query(&self, name: &str) -> Option<&str> {
+        self.query
+            .iter()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+type HttpHandler = fn(&HttpRequest, SocketAddr, &[MockMailbox]) -> (&'static str, Vec<u8>);
+
+/// Serves `mailboxes` over HTTP on a local port, answering with `handler`.
+async fn start_http(
+    mailboxes: Vec<MockMailbox>,
+    handler: HttpHandler,
+) -> std::io::Result<(SocketAddr, JoinHandle<()>)> {
+    let listener = TcpListener::bind("127.0.0.1:0").await?;
+    let addr = listener.local_addr()?;
+    let mailboxes = Arc::new(mailboxes);
+
+    let handle = tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let mailboxes = Arc::clone(&mailboxes);
+            tokio::spawn(async move {
+                if let Err(e) = serve_http(stream, addr, &mailboxes, handler).await {
+                    log::debug!("Mock HTTP connection ended: {}", e);
+                }
+            });
+        }
+    });
+    Ok((addr, handle))
+}
+
+/// Answers one HTTP request and closes the connection.
+async fn serve_http(
+    stream: TcpStream,
+    addr: SocketAddr,
+    mailboxes: &[MockMailbox],
+    handler: HttpHandler,
+) -> std::io::Result<()> {
+    let mut reader = BufReader::new(stream);
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line).await?;
+    let mut content_length = 0;
+    let mut bearer = None;
+    loop {
+        let mut header = String::new();
+        if reader.read_line(&mut header).await? == 0 || header.trim().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("content-length") {
+                content_length = value.trim().parse().unwrap_or(0);
+            } else if name.eq_ignore_ascii_case("authorization") {
+                bearer = value.trim().strip_prefix("Bearer ").map(str::to_string);
+            }
+        }
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+
+    let mut parts = request_line.split_whitespace();
+    let method = parts.next().unwrap_or("").to_string();
+    let target = parts.next().unwrap_or("");
+    let (path, query) = target.split_once('?').unwrap_or((target, ""));
+    let request = HttpRequest {
+        method,
+        path: path.to_string(),
+        query: query
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(key, value)| (key.to_string(), value.to_string()))
+            .collect(),
+        bearer,
+        body,
+    };
+    let (status, response) = handler(&request, addr, mailboxes);
+
+    let stream = reader.get_mut();
+    stream
+        .write_all(
+            format!(
+                "HTTP/1.1 {}\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+                status,
+                response.len()
+            )
+            .as_bytes(),
+        )
+        .await?;
+    stream.write_all(&response).await?;
+    stream.shutdown().await
+}
+
+fn jmap_http(
+    request: &HttpRequest,
+    addr: SocketAddr,
+    mailboxes: &[MockMailbox],
+) -> (&'static str, Vec<u8>) {
+    match (request.method.as_str(), request.path.as_str()) {
+        ("GET", "/jmap/session") => (
+            "200 OK",
+            serde_json::json!({
+                "apiUrl": format!("http://{}/jmap/api", addr),
+                "downloadUrl": format!(
+                    "http://{}/jmap/download/{{accountId}}/{{blobId}}/{{name}}?type={{type}}",
+                    addr
+                ),
+                "primaryAccounts": { "urn:ietf:params:jmap:mail": "mock" },
+            })
+            .to_string()
+            .into_bytes(),
+        ),
+        ("POST", "/jmap/api") => match serde_json::from_slice(&request.body) {
+            Ok(request) => (
+                "200 OK",
+                jmap_api(&request, mailboxes).to_string().into_bytes(),
+            ),
+            Err(_) => ("400 Bad Request", Vec::new()),
+        },
+        ("GET", path) => match path
+            .strip_prefix("/jmap/download/mock/")
+            .and_then(|rest| rest.split('/').next())
+            .and_then(|blob_id| mock_email(mailboxes, blob_id))
+        {
+            Some((_, message)) => ("200 OK", message.to_vec()),
+            None => ("404 Not Found", Vec::new()),
+        },
+        _ => ("404 Not Found", Vec::new()),
+    }
+}
+
+/// Responses to every method call of a JMAP API request.
+fn jmap_api(request: &serde_json::Value, mailboxes: &[MockMailbox]) -> serde_json::Value {
+    use serde_json::{json, Value};
+
+    let calls = request["methodCalls"]
+        .as_array()
+        .cloned()
+        .unwrap_or_default();
+    let responses: Vec<Value> = calls
+        .iter()
+        .map(|call| {
+            let (method, args, id) = (call[0].as_str().unwrap_or(""), &call[1], &call[2]);
+            let result = match method {
+                "Mailbox/get" => json!({
+                    "list": mailboxes.iter().enumerate().map(|(m, mailbox)| json!({
+                        "id": m.to_string(),
+                        "name": mailbox.name,
+                        "parentId": null,
+                        "role": (mailbox.name == "INBOX").then_some("inbox"),
+                        "totalEmails": mailbox.messages.len(),
+                    })).collect::<Vec<_>>(),
+                }),
+                "Email/query" => {
+                    let m = args["filter"]["inMailbox"].as_str().unwrap_or("");
+                    let count = m
+                        .parse::<usize>()
+                        .ok()
+                        .and_then(|m| mailboxes.get(m))
+                        .map_or(0, |mailbox| mailbox.messages.len());
+                    let mut ids: Vec<String> = (0..count).map(|i| format!("{}-{}", m, i)).collect();
+                    if args["sort"][0]["isAscending"] == json!(false) {
+                        ids.reverse();
+                    }
+                    let position = args["position"].as_u64().unwrap_or(0) as usize;
+                    let limit = args["limit"].as_u64().map_or(usize::MAX, |l| l as usize);
+                    json!({
+                        "ids": ids.into_iter().skip(position).take(limit).collect::<Vec<_>>(),
+                        "total": count,
+                    })
+                }
+                "Email/get" => json!({
+                    "list": args["ids"].as_array().into_iter().flatten().filter_map(|id| {
+                        let id = id.as_str()?;
+                        let (index, message) = mock_email(mailboxes, id)?;
+                        let message_id = crate::message::Headers::parse(message)
+                            .message_id()
+                            .map(|id| id.trim().trim_matches(['<', '>']).to_string());
+                        Some(json!({
+                            "id": id,
+                            "blobId": id,
+                            "threadId": format!("T{}", id),
+                            "messageId": message_id.map(|id| vec![id]),
+                            "receivedAt": format!("2024-01-{:02}T12:00:00Z", (index + 1) % 28 + 1),
+                            "size": message.len(),
+                        }))
+                    }).collect::<Vec<_>>(),
+                }),
+                _ => return json!(["error", { "type": "unknownMethod" }, id]),
+            };
+            json!([method, result, id])
+        })
+        .collect();
+    json!({ "methodResponses": responses })
+}
+
+/// The message index and message of the email ID `m-i`.
+fn mock_email<'a>(mailboxes: &'a [MockMailbox], id: &str) -> Option<(usize, &'a [u8])> {
+    let (m, i) = id.split_once('-')?;
+    let (m, i): (usize, usize) = (m.parse().ok()?, i.parse().ok()?);
+    let message = mailboxes.get(m)?.messages.get(i)?;
+    Some((i, message))
+}
+
+/// Access token the mock Gmail API accepts, and its token endpoint issues.
+pub const MOCK_ACCESS_TOKEN: &str = "mock-access-token";
+
+/// History ID before the first message of [`MockGmailApiServer`]; earlier
+/// starting points are answered as expired.
+pub const MOCK_FIRST_HISTORY_ID: u64 = 100;
+
+/// A small in-process OAuth 2.0 authorization server over plain HTTP. Its
+/// token endpoint issues [`MOCK_ACCESS_TOKEN`] for any refresh token, and its
+/// device flow is completed as soon as the token endpoint is first polled,
+/// granting [`MOCK_REFRESH_TOKEN`] along with the access token.
+pub struct MockOAuthServer {
+    addr: SocketAddr,
+    handle: JoinHandle<()>,
+}
+
+/// Refresh token granted by [`MockOAuthServer`]'s device flow.
+pub const MOCK_REFRESH_TOKEN: &str = "mock-refresh-token";
+
+impl MockOAuthServer {
+    pub async fn start() -> std::io::Result<Self> {
+        let (addr, handle) = start_http(Vec::new(), oauth_http).await?;
+        Ok(MockOAuthServer { addr, handle })
+    }
+
+    pub fn token_url(&self) -> String {
+        format!("http://{}/token", self.addr)
+    }
+
+    pub fn device_url(&self) -> String {
+        format!("http://{}/devicecode", self.addr)
+    }
+}
+
+impl Drop for MockOAuthServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+fn oauth_http(
+    request: &HttpRequest,
+    addr: SocketAddr,
+    _mailboxes: &[MockMailbox],
+) -> (&'static str, Vec<u8>) {
+    use serde_json::json;
+
+    let body = match request.path.as_str() {
+        "/devicecode" => json!({
+            "device_code": "mock-device-code",
+            "user_code": "MOCK-CODE",
+            "verification_uri": format!("http://{}/device", addr),
+            "expires_in": 60,
+            "interval": 0,
+        }),
+        "/token" if contains(&request.body, b"grant_type=refresh_token") => {
+            json!({ "access_token": MOCK_ACCESS_TOKEN, "expires_in": 3599 })
+        }
+        // Codes from the browser flow only count with their PKCE verifier
+        "/token"
+            if contains(&request.body, b"grant_type=authorization_code")
+                && !contains(&request.body, b"code_verifier=") =>
+        {
+            let error = json!({ "error": "invalid_grant" });
+            return ("400 Bad Request", error.to_string().into_bytes());
+        }
+        "/token" => json!({
+            "access_token": MOCK_ACCESS_TOKEN,
+            "refresh_token": MOCK_REFRESH_TOKEN,
+            "expires_in": 3599,
+        }),
+        _ => return ("404 Not Found", Vec::new()),
+    };
+    ("200 OK", body.to_string().into_bytes())
+}
+
+/// A small in-process Gmail API server over plain HTTP, serving fixed
+/// mailboxes as labels (`INBOX` keeps its ID, others are `Label_m`). Supports
+/// what the Gmail API backend uses: listing labels and messages, the
+/// history, `format=raw` downloads and the token endpoint of
+/// [`MockOAuthServer`]. Message `i` of mailbox `m`
+/// has the ID `m-i` and is dated like the IMAP mock's UID `i + 1`. Messages
+/// were added one per history ID in order, mailbox by mailbox, after
+/// [`MOCK_FIRST_HISTORY_ID`].
+pub struct MockGmailApiServer {
+    addr: SocketAddr,
+    handle: JoinHandle<()>,
+}
+
+impl MockGmailApiServer {
+    pub async fn start(mailboxes: Vec<MockMailbox>) -> std::io::Result<Self> {
+        let (addr, handle) = start_http(mailboxes, gmail_http).await?;
+        Ok(MockGmailApiServer { addr, handle })
+    }
+
+    /// Base URL of the API for the authenticated user.
+    pub fn url(&self) -> String {
+        format!("http://{}/gmail/v1/users/me", self.addr)
+    }
+
+    pub fn token_url(&self) -> String {
+        format!("http://{}/token", self.addr)
+    }
+}
+
+impl Drop for MockGmailApiServer {
+    fn drop(&mut self) {
+        self.handle.abort();
+    }
+}
+
+fn gmail_http(
+    request: &HttpRequest,
+    addr: SocketAddr,
+    mailboxes: &[MockMailbox],
+) -> (&'static str, Vec<u8>) {
+    use serde_json::json;
+
+    if request.path == "/token" {
+        return oauth_http(request, addr, mailboxes);
+    }
+    if request.bearer.as_deref() != Some(MOCK_ACCESS_TOKEN) {
+        return ("401 Unauthorized", Vec::new());
+    }
+
+    let label_id = |m: usize| match mailboxes[m].name.as_str() {
+        "INBOX" => "INBOX".to_string(),
+        _ => format!("Label_{}", m),
+    };
+    let mailbox = |id: &str| (0..mailboxes.len()).find(|&m| label_id(m) == id);
+    let path = request
+        .path
+        .strip_prefix("/gmail/v1/users/me/")
+        .unwrap_or_default();
+    // (history ID, label, message ID) of every message, in the order added
+    let added: Vec<(u64, String, String)> = (0..mailboxes.len())
+        .flat_map(|m| (0..mailboxes[m].messages.len()).map(move |i| (m, i)))
+        .enumerate()
+        .map(|(k, (m, i))| {
+            (
+                MOCK_FIRST_HISTORY_ID + k as u64 + 1,
+                label_id(m),
+                format!("{}-{}", m, i),
+            )
+        })
+        .collect();
+    let latest = MOCK_FIRST_HISTORY_ID + added.len() as u64;
+    let response = match path.split_once('/') {
+        None if path == "profile" => json!({ "historyId": latest.to_string() }),
+        None if path == "history" => {
+            let start: u64 = request
+                .query("startHistoryId")
+                .and_then(|id| id.parse().ok())
+                .unwrap_or(0);
+            if start < MOCK_FIRST_HISTORY_ID {
+                return ("404 Not Found", Vec::new());
+            }
+            let history: Vec<_> = added
+                .iter()
+                .filter(|(id, _, _)| *id > start)
+                .map(|(id, label, message)| {
+                    json!({
+                        "id": id.to_string(),
+                        "messagesAdded": [{ "message": { "id": message, "labelIds": [label] } }],
+                    })
+                })
+                .collect();
+            json!({ "history": history, "historyId": latest.to_string() })
+        }
+        None if path == "labels" => json!({
+            "labels": (0..mailboxes.len()).map(|m| json!({
+                "id": label_id(m),
+                "name": mailboxes[m].name,
+                "type": if label_id(m) == "INBOX" { "system" } else { "user" },
+            })).collect::<Vec<_>>(),
+        }),
+        Some(("labels", id)) => match mailbox(id) {
+            Some(m) => json!({ "id": id, "messagesTotal": mailboxes[m].messages.len() }),
+            None => return ("404 Not Found", Vec::new()),
+        },
+        None if path == "messages" => {
+            let Some(m) = request.query("labelIds").and_then(mailbox) else {
+                return ("404 Not Found", Vec::new());
+            };
+            let start: usize = request
+                .query("pageToken")
+                .and_then(|t| t.parse().ok())
+                .unwrap_or(0);
+            let max: usize = request
+                .query("maxResults")
+                .and_then(|n| n.parse().ok())
+                .unwrap_or(100);
+            // Newest first
+            let ids: Vec<String> = (0..mailboxes[m].messages.len())
+                .rev()
+                .map(|i| format!("{}-{}", m, i))
+                .collect();
+            let page: Vec<_> = ids
+                .iter()
+                .skip(start)
+                .take(max)
+                .map(|id| json!({ "id": id, "threadId": format!("T{}", id) }))
+                .collect();
+            let next = (start + max < ids.len()).then(|| (start + max).to_string());
+            json!({ "messages": page, "nextPageToken": next })
+        }
+        Some(("messages", id)) => match mock_email(mailboxes, id) {
+            Some((index, message)) => {
+                let date =
+                    parse_utc_date(&format!("2024-01-{:02}T12:00:00Z", (index + 1) % 28 + 1))
+                        .unwrap_or_default();
+                json!({
+                    "id": id,
+                    "threadId": format!("T{}", id),
+                    "internalDate": (date * 1000).to_string(),
+                    "raw": base64::engine::general_purpose::URL_SAFE.encode(message),
+                })
+            }
+            None => return ("404 Not Found", Vec::new()),
+        },
+        _ => return ("404 Not Found", Vec::new()),
+    };
+    ("200 OK", response.to_string().into_bytes())
+}
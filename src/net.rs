@@ -0,0 +1,229 @@
+use std::net::SocketAddr;
+use std::str::FromStr;
+use std::time::{Duration, Instant};
+use tokio::net::TcpStream;
+use tokio::task::JoinSet;
+use tokio_socks::tcp::Socks5Stream;
+
+use crate::dns::Resolver;
+use crate::error_imap::ClientError;
+
+/// How long an attempt to connect to one address gets before the next
+/// address is tried alongside it (RFC 8305's Connection Attempt Delay).
+pub const ATTEMPT_DELAY: Duration = Duration::from_millis(250);
+
+/// The log target of what `--debug-connection` shows: how connections are
+/// made, step by step, and how long each step takes.
+pub const CONNECTION_LOG: &str = "imap_client::connection";
+
+/// Where a local Tor client takes SOCKS connections unless told otherwise.
+pub const TOR_PROXY: &str = "127.0.0.1:9050";
+
+/// A connection through Tor first needs a circuit of three relays built,
+/// which can take a while and sometimes fails, so it gets longer than the
+/// system's usual connect timeout and more than one attempt.
+pub const TOR_CONNECT_TIMEOUT: Duration = Duration::from_secs(90);
+pub const TOR_CONNECT_ATTEMPTS: u32 = 3;
+
+/// Which IP version to try first when a host has addresses of both.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum IpPreference {
+    /// Whichever the system resolver lists first, usually IPv6.
+    #[default]
+    System,
+    Ipv4,
+    Ipv6,
+}
+
+impl FromStr for IpPreference {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "system" => Ok(IpPreference::System),
+            "ipv4" | "4" => Ok(IpPreference::Ipv4),
+            "ipv6" | "6" => Ok(IpPreference::Ipv6),
+            other => Err(ClientError::InvalidArgument(format!(
+                "Unknown IP version `{}` (expected ipv4, ipv6 or system)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Connects to `server` (`host:port`), trying all the addresses `resolver`
+/// finds for it.
+pub async fn connect(
+    server: &str,
+    preference: IpPreference,
+    resolver: &Resolver,
+) -> Result<TcpStream, ClientError> {
+    let started = Instant::now();
+    let addrs = resolver.resolve(server).await?;
+    log::debug!(
+        target: CONNECTION_LOG,
+        "resolved {} in {:?}: {:?}",
+        server,
+        started.elapsed(),
+        addrs
+    );
+    if addrs.is_empty() {
+        return Err(ClientError::connection(format!(
+            "{} has no addresses",
+            server
+        )));
+    }
+    let started = Instant::now();
+    let stream = race(order_addresses(addrs, preference)).await?;
+    if let Ok(addr) = stream.peer_addr() {
+        log::debug!(
+            target: CONNECTION_LOG,
+            "connected to {} in {:?}",
+            addr,
+            started.elapsed()
+        );
+    }
+    Ok(stream)
+}
+
+/// Parses where Tor takes SOCKS connections: `true` for [`TOR_PROXY`],
+/// `false` for not using Tor, or the proxy's address.
+pub fn parse_tor(value: &str) -> Result<Option<SocketAddr>, ClientError> {
+    match value {
+        "true" => Ok(TOR_PROXY.parse().ok()),
+        "false" => Ok(None),
+        address => address.parse().map(Some).map_err(|_| {
+            ClientError::InvalidArgument(format!(
+                "Invalid Tor proxy `{}` (expected an address such as {})",
+                address, TOR_PROXY
+            ))
+        }),
+    }
+}
+
+/// Connects to `server` (`host:port`) through the Tor SOCKS proxy `proxy`.
+/// The name is passed to Tor to resolve at the exit relay, so nothing about
+/// the server is looked up from here.
+///
+/// Tor keeps streams given different SOCKS credentials on different
+/// circuits, so `isolation` keeps one account's connections apart from
+/// another's, and an attempt that failed is retried on a fresh circuit.
+pub async fn connect_tor(
+    proxy: SocketAddr,
+    server: &str,
+    isolation: &str,
+) -> Result<TcpStream, ClientError> {
+    let (host, port) = server
+        .rsplit_once(':')
+        .and_then(|(host, port)| Some((host, port.parse::<u16>().ok()?)))
+        .ok_or_else(|| ClientError::connection(format!("{} is not host:port", server)))?;
+    let host = host
+        .strip_prefix('[')
+        .and_then(|host| host.strip_suffix(']'))
+        .unwrap_or(host);
+    let started = Instant::now();
+    let mut attempt = 1;
+    loop {
+        let circuit = format!("{}-{}", isolation, attempt);
+        let connecting = Socks5Stream::connect_with_password(proxy, (host, port), &circuit, "-");
+        let error = match tokio::time::timeout(TOR_CONNECT_TIMEOUT, connecting).await {
+            Ok(Ok(stream)) => {
+                log::debug!(
+                    target: CONNECTION_LOG,
+                    "connected to {} through Tor at {} in {:?}, attempt {}",
+                    server,
+                    proxy,
+                    started.elapsed(),
+                    attempt
+                );
+                return Ok(stream.into_inner());
+            }
+            Ok(Err(tokio_socks::Error::ProxyServerUnreachable)) => {
+                return Err(ClientError::connection(format!(
+                    "cannot reach Tor at {}; is it running?",
+                    proxy
+                )))
+            }
+            Ok(Err(e)) => e.to_string(),
+            Err(_) => format!("no connection within {:?}", TOR_CONNECT_TIMEOUT),
+        };
+        if attempt == TOR_CONNECT_ATTEMPTS {
+            return Err(ClientError::connection(format!(
+                "cannot reach {} through Tor: {}",
+                server, error
+            )));
+        }
+        log::warn!(
+            "Cannot reach {} through Tor ({}); trying a new circuit",
+            server,
+            error
+        );
+        attempt += 1;
+    }
+}
+
+/// Orders `addrs` to be tried in turn: alternating IP versions, starting
+/// with the preferred one, and otherwise in the resolver's order.
+pub fn order_addresses(addrs: Vec<SocketAddr>, preference: IpPreference) -> Vec<SocketAddr> {
+    let first_v6 = match preference {
+        IpPreference::System => addrs.first().is_some_and(SocketAddr::is_ipv6),
+        IpPreference::Ipv4 => false,
+        IpPreference::Ipv6 => true,
+    };
+    let (mut preferred, mut other): (Vec<_>, Vec<_>) = addrs
+        .into_iter()
+        .partition(|addr| addr.is_ipv6() == first_v6);
+    let mut ordered = Vec::with_capacity(preferred.len() + other.len());
+    preferred.reverse();
+    other.reverse();
+    loop {
+        match (preferred.pop(), other.pop()) {
+            (None, None) => return ordered,
+            (a, b) => ordered.extend(a.into_iter().chain(b)),
+        }
+    }
+}
+
+/// Connects to the first of `addrs` that answers, Happy Eyeballs style
+/// (RFC 8305): each address gets [`ATTEMPT_DELAY`] before the next is tried
+/// as well, or less if it fails sooner. A host whose IPv6 path is broken
+/// then costs a quarter of a second rather than a TCP timeout.
+pub async fn race(addrs: Vec<SocketAddr>) -> std::io::Result<TcpStream> {
+    let mut waiting = addrs.into_iter();
+    let mut attempts = JoinSet::new();
+    let mut last_error = None;
+    loop {
+        if attempts.is_empty() {
+            match waiting.next() {
+                Some(addr) => {
+                    attempts.spawn(TcpStream::connect(addr));
+                }
+                None => {
+                    return Err(last_error.unwrap_or_else(|| {
+                        std::io::Error::new(
+                            std::io::ErrorKind::NotFound,
+                            "no address to connect to",
+                        )
+                    }))
+                }
+            }
+        }
+        tokio::select! {
+            Some(attempt) = attempts.join_next() => match attempt {
+                Ok(Ok(stream)) => return Ok(stream),
+                Ok(Err(e)) => {
+                    last_error = Some(e);
+                    if let Some(addr) = waiting.next() {
+                        attempts.spawn(TcpStream::connect(addr));
+                    }
+                }
+                Err(e) => last_error = Some(std::io::Error::other(e)),
+            },
+            _ = tokio::time::sleep(ATTEMPT_DELAY), if waiting.len() > 0 => {
+                if let Some(addr) = waiting.next() {
+                    attempts.spawn(TcpStream::connect(addr));
+                }
+            }
+        }
+    }
+}
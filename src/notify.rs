@@ -0,0 +1,53 @@
+use crate::client::FetchSummary;
+use crate::error_imap::ClientError;
+
+/// Title and body of the notification for a finished run, one body line per
+/// account.
+pub fn run_notification(
+    results: &[(String, Result<FetchSummary, ClientError>)],
+) -> (String, String) {
+    let failed = results.iter().filter(|(_, result)| result.is_err()).count();
+    let partial = results
+        .iter()
+        .any(|(_, result)| matches!(result, Ok(summary) if summary.failed_batches > 0));
+    let title = if failed == results.len() {
+        "Email fetch failed"
+    } else if failed > 0 || partial {
+        "Email fetch finished with errors"
+    } else {
+        "Email fetch finished"
+    };
+
+    let body = results
+        .iter()
+        .map(|(account, result)| match result {
+            Ok(summary) if summary.failed_batches > 0 => format!(
+                "{}: {} of {} saved, {} failed batches",
+                account, summary.saved, summary.found, summary.failed_batches
+            ),
+            Ok(summary) => format!("{}: {} of {} saved", account, summary.saved, summary.found),
+            Err(e) => format!("{}: failed ({} error)", account, e.kind().name()),
+        })
+        .collect::<Vec<_>>()
+        .join("\n");
+    (title.to_string(), body)
+}
+
+/// Shows a desktop notification summarizing the run. Failing to reach the
+/// notification service is only logged.
+#[cfg(feature = "notifications")]
+pub fn notify_finished(results: &[(String, Result<FetchSummary, ClientError>)]) {
+    let (title, body) = run_notification(results);
+    if let Err(e) = notify_rust::Notification::new()
+        .appname("gmail-fetcher")
+        .summary(&title)
+        .body(&body)
+        .show()
+    {
+        log::warn!("Failed to show desktop notification: {}", e);
+    }
+}
+
+/// Built without notification support; `--notify` is rejected up front.
+#[cfg(not(feature = "notifications"))]
+pub fn notify_finished(_results: &[(String, Result<FetchSummary, ClientError>)]) {}
@@ -0,0 +1,352 @@
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use base64::Engine;
+use serde::Deserialize;
+use sha2::{Digest, Sha256};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+
+use crate::error_imap::ClientError;
+use crate::http::{check_status, http_error};
+use crate::provider::{BrowserFlow, DeviceFlow};
+
+/// Google's OAuth 2.0 token endpoint.
+pub const GOOGLE_TOKEN_URL: &str = "https://oauth2.googleapis.com/token";
+
+/// An OAuth 2.0 client and the refresh token it was granted, from which
+/// short-lived access tokens are obtained for each run.
+#[derive(Debug, Clone, Default)]
+pub struct OAuthCredentials {
+    pub client_id: String,
+    /// Empty for public clients, such as those using the device flow.
+    pub client_secret: String,
+    /// Empty until the account has been through the device flow once.
+    pub refresh_token: String,
+    /// Token endpoint, when not the provider's.
+    pub token_url: Option<String>,
+    /// Device authorization endpoint, when not the provider's.
+    pub device_url: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct TokenResponse {
+    access_token: String,
+    refresh_token: Option<String>,
+}
+
+/// How long the user has to sign in in the browser.
+const BROWSER_TIMEOUT: Duration = Duration::from_secs(300);
+
+/// Tokens granted once the user has signed in.
+#[derive(Debug, Clone)]
+pub struct Grant {
+    pub access_token: String,
+    /// Lets later runs sign in without the user, if the provider issued one.
+    pub refresh_token: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct DeviceAuthorization {
+    device_code: String,
+    user_code: String,
+    verification_uri: String,
+    expires_in: u64,
+    #[serde(default = "default_interval")]
+    interval: u64,
+    /// Ready-made instructions, which Microsoft includes.
+    message: Option<String>,
+}
+
+fn default_interval() -> u64 {
+    5
+}
+
+#[derive(Deserialize)]
+struct TokenError {
+    error: String,
+    error_description: Option<String>,
+}
+
+impl OAuthCredentials {
+    /// Exchanges the refresh token for a new access token (RFC 6749 §6) at
+    /// `token_url`, unless the credentials name their own endpoint.
+    pub async fn access_token(
+        &self,
+        http: &reqwest::Client,
+        token_url: &str,
+        scope: Option<&str>,
+    ) -> Result<String, ClientError> {
+        let mut form = vec![
+            ("grant_type", "refresh_token"),
+            ("client_id", &self.client_id),
+            ("refresh_token", &self.refresh_token),
+        ];
+        if !self.client_secret.is_empty() {
+            form.push(("client_secret", &self.client_secret));
+        }
+        if let Some(scope) = scope {
+            form.push(("scope", scope));
+        }
+        let response = http
+            .post(self.token_url.as_deref().unwrap_or(token_url))
+            .form(&form)
+            .send()
+            .await
+            .map_err(http_error)?;
+
+        // A revoked or expired refresh token comes back as 400 invalid_grant
+        if response.status() == reqwest::StatusCode::BAD_REQUEST {
+            let error: TokenError = response.json().await.map_err(http_error)?;
+            return Err(ClientError::AuthenticationError(format!(
+                "the refresh token was rejected ({})",
+                error.describe()
+            )));
+        }
+        let token: TokenResponse = check_status(response)?.json().await.map_err(http_error)?;
+        Ok(token.access_token)
+    }
+
+    /// Runs the device authorization grant (RFC 8628): `prompt` is given the
+    /// instructions for signing in on another device, and the token endpoint
+    /// is polled until the user has done so or the code expires.
+    pub async fn device_flow(
+        &self,
+        http: &reqwest::Client,
+        flow: &DeviceFlow,
+        prompt: impl Fn(&str),
+    ) -> Result<Grant, ClientError> {
+        let response = http
+            .post(self.device_url.as_deref().unwrap_or(flow.device_url))
+            .form(&[
+                ("client_id", self.client_id.as_str()),
+                ("scope", flow.scope),
+            ])
+            .send()
+            .await
+            .map_err(http_error)?;
+        if response.status() == reqwest::StatusCode::BAD_REQUEST {
+            let error: TokenError = response.json().await.map_err(http_error)?;
+            return Err(ClientError::AuthenticationError(format!(
+                "the device sign-in could not be started ({})",
+                error.describe()
+            )));
+        }
+        let authorization: DeviceAuthorization =
+            check_status(response)?.json().await.map_err(http_error)?;
+        prompt(&authorization.message.clone().unwrap_or_else(|| {
+            format!(
+                "To sign in, open {} and enter the code {}",
+                authorization.verification_uri, authorization.user_code
+            )
+        }));
+
+        let deadline = Instant::now() + Duration::from_secs(authorization.expires_in);
+        let mut interval = Duration::from_secs(authorization.interval);
+        loop {
+            tokio::time::sleep(interval).await;
+            let response = http
+                .post(self.token_url.as_deref().unwrap_or(flow.token_url))
+                .form(&[
+                    ("grant_type", "urn:ietf:params:oauth:grant-type:device_code"),
+                    ("client_id", self.client_id.as_str()),
+                    ("device_code", authorization.device_code.as_str()),
+                ])
+                .send()
+                .await
+                .map_err(http_error)?;
+            if response.status() != reqwest::StatusCode::BAD_REQUEST {
+                let token: TokenResponse =
+                    check_status(response)?.json().await.map_err(http_error)?;
+                return Ok(Grant {
+                    access_token: token.access_token,
+                    refresh_token: token.refresh_token,
+                });
+            }
+
+            let error: TokenError = response.json().await.map_err(http_error)?;
+            match error.error.as_str() {
+                "authorization_pending" if Instant::now() < deadline => {}
+                "slow_down" if Instant::now() < deadline => interval += Duration::from_secs(5),
+                _ => {
+                    return Err(ClientError::AuthenticationError(format!(
+                        "the device sign-in did not complete ({})",
+                        error.describe()
+                    )))
+                }
+            }
+        }
+    }
+
+    /// Runs the authorization code grant with PKCE (RFC 6749 §4.1, RFC 7636)
+    /// the way native apps do (RFC 8252): `open` is given the address of the
+    /// provider's sign-in page, which sends the browser back to a listener
+    /// on this machine with a code, exchanged at the token endpoint for
+    /// tokens. `login_hint` preselects the account.
+    pub async fn browser_flow(
+        &self,
+        http: &reqwest::Client,
+        flow: &BrowserFlow,
+        login_hint: &str,
+        open: impl Fn(&str),
+    ) -> Result<Grant, ClientError> {
+        let listener = TcpListener::bind(("127.0.0.1", 0)).await?;
+        let redirect_uri = format!("http://localhost:{}/", listener.local_addr()?.port());
+        let verifier = random_token()?;
+        let state = random_token()?;
+        let challenge = URL_SAFE_NO_PAD.encode(Sha256::digest(verifier.as_bytes()));
+        let url = reqwest::Url::parse_with_params(
+            flow.authorize_url,
+            &[
+                ("client_id", self.client_id.as_str()),
+                ("response_type", "code"),
+                ("redirect_uri", &redirect_uri),
+                ("scope", flow.scope),
+                ("state", &state),
+                ("code_challenge", &challenge),
+                ("code_challenge_method", "S256"),
+                ("login_hint", login_hint),
+                // Google only issues a refresh token with these
+                ("access_type", "offline"),
+                ("prompt", "consent"),
+            ],
+        )
+        .map_err(|e| ClientError::InvalidArgument(format!("{}: {}", flow.authorize_url, e)))?;
+        open(url.as_str());
+
+        let redirect = tokio::time::timeout(BROWSER_TIMEOUT, receive_redirect(&listener))
+            .await
+            .map_err(|_| {
+                ClientError::AuthenticationError(
+                    "the browser sign-in was not completed in time".to_string(),
+                )
+            })??;
+        if redirect.state != state {
+            return Err(ClientError::AuthenticationError(
+                "the browser sign-in came back for another request".to_string(),
+            ));
+        }
+
+        let mut form = vec![
+            ("grant_type", "authorization_code"),
+            ("client_id", &self.client_id),
+            ("code", &redirect.code),
+            ("redirect_uri", &redirect_uri),
+            ("code_verifier", &verifier),
+        ];
+        if !self.client_secret.is_empty() {
+            form.push(("client_secret", &self.client_secret));
+        }
+        let response = http
+            .post(self.token_url.as_deref().unwrap_or(flow.token_url))
+            .form(&form)
+            .send()
+            .await
+            .map_err(http_error)?;
+        if response.status() == reqwest::StatusCode::BAD_REQUEST {
+            let error: TokenError = response.json().await.map_err(http_error)?;
+            return Err(ClientError::AuthenticationError(format!(
+                "the sign-in code was rejected ({})",
+                error.describe()
+            )));
+        }
+        let token: TokenResponse = check_status(response)?.json().await.map_err(http_error)?;
+        Ok(Grant {
+            access_token: token.access_token,
+            refresh_token: token.refresh_token,
+        })
+    }
+}
+
+/// What the browser was sent back with.
+struct Redirect {
+    code: String,
+    state: String,
+}
+
+/// Answers requests to `listener` until one brings the result of the
+/// sign-in, and returns it.
+async fn receive_redirect(listener: &TcpListener) -> Result<Redirect, ClientError> {
+    loop {
+        let (mut stream, _) = listener.accept().await?;
+        let mut request = Vec::new();
+        let mut buf = [0; 1024];
+        while !request.windows(4).any(|w| w == b"\r\n\r\n") && request.len() < 16 * 1024 {
+            let n = stream.read(&mut buf).await?;
+            if n == 0 {
+                break;
+            }
+            request.extend_from_slice(&buf[..n]);
+        }
+        // GET /?code=...&state=... HTTP/1.1
+        let target = String::from_utf8_lossy(&request)
+            .split_whitespace()
+            .nth(1)
+            .unwrap_or_default()
+            .to_string();
+        let query: Vec<(String, String)> =
+            reqwest::Url::parse(&format!("http://localhost{}", target))
+                .map(|url| url.query_pairs().into_owned().collect())
+                .unwrap_or_default();
+        let param = |name: &str| {
+            query
+                .iter()
+                .find(|(key, _)| key == name)
+                .map(|(_, value)| value.clone())
+        };
+
+        let (status, text, result) = match (param("code"), param("error")) {
+            (Some(code), _) => (
+                "200 OK",
+                "Signed in. You can close this window and go back to the terminal.",
+                Some(Ok(Redirect {
+                    code,
+                    state: param("state").unwrap_or_default(),
+                })),
+            ),
+            (None, Some(error)) => {
+                let reason = match param("error_description") {
+                    Some(description) => format!("{}: {}", error, description),
+                    None => error,
+                };
+                (
+                    "200 OK",
+                    "Signing in failed. Go back to the terminal for details.",
+                    Some(Err(ClientError::AuthenticationError(format!(
+                        "the browser sign-in was refused ({})",
+                        reason
+                    )))),
+                )
+            }
+            // Such as the browser asking for a favicon
+            (None, None) => ("404 Not Found", "Not found.", None),
+        };
+        let response = format!(
+            "HTTP/1.1 {}\r\nContent-Type: text/plain; charset=utf-8\r\n\
+             Content-Length: {}\r\nConnection: close\r\n\r\n{}",
+            status,
+            text.len(),
+            text
+        );
+        let _ = stream.write_all(response.as_bytes()).await;
+        if let Some(result) = result {
+            return result;
+        }
+    }
+}
+
+/// 32 random bytes, base64url-encoded, for a PKCE verifier or a state.
+fn random_token() -> Result<String, ClientError> {
+    let mut bytes = [0; 32];
+    getrandom::getrandom(&mut bytes)
+        .map_err(|e| ClientError::AuthenticationError(format!("no randomness available: {}", e)))?;
+    Ok(URL_SAFE_NO_PAD.encode(bytes))
+}
+
+impl TokenError {
+    fn describe(&self) -> String {
+        match &self.error_description {
+            Some(description) => format!("{}: {}", self.error, description),
+            None => self.error.clone(),
+        }
+    }
+}
@@ -0,0 +1,137 @@
+use std::sync::{Mutex, MutexGuard};
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
+
+use crate::error_imap::ClientError;
+
+/// Pause added between batches on the first throttling response, doubled on
+/// each one after.
+const FIRST_BACKOFF: Duration = Duration::from_secs(1);
+
+/// Longest the backoff grows to.
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+
+/// What servers say when they throttle a client, in lower case: RFC 5530's
+/// UNAVAILABLE, Exchange's THROTTLED, and Gmail's wording.
+const THROTTLE_SIGNS: &[&str] = &[
+    "[throttled]",
+    "[unavailable]",
+    "throttl",
+    "too many",
+    "bandwidth limits",
+    "rate limit",
+    "try again later",
+];
+
+/// Spaces out the starts of an account's batches by a fixed delay, a random
+/// jitter so connections don't start in lockstep, and a backoff that grows
+/// while the server throttles the account and shrinks as batches succeed.
+pub struct Pacing {
+    delay: Duration,
+    jitter: Duration,
+    state: Mutex<State>,
+}
+
+struct State {
+    /// When the next batch may start.
+    next_start: Instant,
+    backoff: Duration,
+}
+
+impl Pacing {
+    pub fn new(delay: Duration, jitter: Duration) -> Self {
+        Pacing {
+            delay,
+            jitter,
+            state: Mutex::new(State {
+                next_start: Instant::now(),
+                backoff: Duration::ZERO,
+            }),
+        }
+    }
+
+    /// Waits for a batch's turn to start. The first batch starts straight
+    /// away.
+    pub async fn wait_turn(&self) {
+        let start = {
+            let mut state = lock(&self.state);
+            let start = state.next_start.max(Instant::now());
+            state.next_start = start + self.delay + state.backoff + random_up_to(self.jitter);
+            start
+        };
+        sleep_until(start).await;
+    }
+
+    /// The pause currently added because of throttling.
+    pub fn backoff(&self) -> Duration {
+        lock(&self.state).backoff
+    }
+
+    /// Doubles the backoff after a throttling response, returning it.
+    pub fn throttled(&self) -> Duration {
+        let mut state = lock(&self.state);
+        state.backoff = (state.backoff * 2).clamp(FIRST_BACKOFF, MAX_BACKOFF);
+        state.backoff
+    }
+
+    /// Halves the backoff after a batch went through, dropping it once it
+    /// is small.
+    pub fn succeeded(&self) {
+        let mut state = lock(&self.state);
+        state.backoff /= 2;
+        if state.backoff < FIRST_BACKOFF / 8 {
+            state.backoff = Duration::ZERO;
+        }
+    }
+}
+
+/// Whether `error` is the server asking the client to slow down.
+pub fn is_throttle(error: &ClientError) -> bool {
+    let text = match error.root() {
+        ClientError::CommandFailed { text, .. }
+        | ClientError::ServerBye(text)
+        | ClientError::ImapError(text) => text.to_ascii_lowercase(),
+        _ => return false,
+    };
+    THROTTLE_SIGNS.iter().any(|sign| text.contains(sign))
+}
+
+/// Parses a delay such as `250ms` or `2s`; a bare number is milliseconds.
+pub fn parse_delay(value: &str) -> Result<Duration, ClientError> {
+    let value = value.trim();
+    let (number, scale) = match value.strip_suffix("ms") {
+        Some(number) => (number, 1),
+        None => match value.strip_suffix('s') {
+            Some(number) => (number, 1000),
+            None => (value, 1),
+        },
+    };
+    number
+        .trim()
+        .parse::<u64>()
+        .map(|n| Duration::from_millis(n * scale))
+        .map_err(|_| {
+            ClientError::InvalidArgument(format!(
+                "Invalid delay: {} (expected milliseconds or seconds, as in 250ms or 2s)",
+                value
+            ))
+        })
+}
+
+/// A random duration from zero to `max`.
+fn random_up_to(max: Duration) -> Duration {
+    if max.is_zero() {
+        return Duration::ZERO;
+    }
+    let mut bytes = [0; 8];
+    if getrandom::getrandom(&mut bytes).is_err() {
+        return max / 2;
+    }
+    let nanos = u64::from_le_bytes(bytes) % (max.as_nanos() as u64 + 1);
+    Duration::from_nanos(nanos)
+}
+
+/// The state is only timings, so a panicked holder shouldn't stop anyone.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,377 @@
+use std::borrow::Cow;
+use thiserror::Error;
+
+/// Why a response could not be parsed.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ParseError {
+    /// More bytes are needed to complete the response.
+    #[error("Incomplete response")]
+    Incomplete,
+
+    #[error("Invalid response at byte {offset}: {reason}")]
+    Invalid { offset: usize, reason: &'static str },
+}
+
+/// A value in a FETCH response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Value<'a> {
+    Nil,
+    /// Atoms and numbers, e.g. `12345` or `\Seen`.
+    Atom(&'a str),
+    /// A quoted string, unescaped.
+    String(Cow<'a, [u8]>),
+    Literal(&'a [u8]),
+    List(Vec<Value<'a>>),
+}
+
+impl Value<'_> {
+    /// Contents of a string or literal.
+    pub fn as_bytes(&self) -> Option<&[u8]> {
+        match self {
+            Value::String(bytes) => Some(bytes),
+            Value::Literal(bytes) => Some(bytes),
+            _ => None,
+        }
+    }
+
+    pub fn as_number(&self) -> Option<u64> {
+        match self {
+            Value::Atom(atom) => atom.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+/// An untagged `* n FETCH (...)` response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Fetch<'a> {
+    pub seq: u32,
+    /// Item names as sent by the server (e.g. `BODY[]`) with their values.
+    pub items: Vec<(&'a str, Value<'a>)>,
+}
+
+impl<'a> Fetch<'a> {
+    pub fn get(&self, name: &str) -> Option<&Value<'a>> {
+        self.items
+            .iter()
+            .find(|(item, _)| item.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value)
+    }
+
+    pub fn number(&self, name: &str) -> Option<u64> {
+        self.get(name).and_then(Value::as_number)
+    }
+
+    pub fn uid(&self) -> Option<u32> {
+        self.number("UID").and_then(|uid| u32::try_from(uid).ok())
+    }
+
+    /// The full message, sent as `BODY[]`, `BINARY[]` or `RFC822`. `None` when
+    /// there is no such item or its value is `NIL`.
+    pub fn body(&self) -> Option<&[u8]> {
+        self.body_value().and_then(Value::as_bytes)
+    }
+
+    /// Whether the response carries a full-message item at all, even if the
+    /// server sent `NIL` in place of the message.
+    pub fn has_body(&self) -> bool {
+        self.body_value().is_some()
+    }
+
+    /// Where in the message the body starts: the origin of a partial
+    /// `BODY[]<origin>`, or 0 for the whole message.
+    pub fn body_origin(&self) -> u64 {
+        self.items
+            .iter()
+            .find_map(|(item, _)| partial_origin(item))
+            .unwrap_or(0)
+    }
+
+    fn body_value(&self) -> Option<&Value<'a>> {
+        BODY_ITEMS
+            .iter()
+            .find_map(|name| self.get(name))
+            .or_else(|| {
+                self.items
+                    .iter()
+                    .find(|(item, _)| partial_origin(item).is_some())
+                    .map(|(_, value)| value)
+            })
+    }
+}
+
+/// The origin of a partial `BODY[]<origin>` item name.
+pub(crate) fn partial_origin(item: &str) -> Option<u64> {
+    let rest = item
+        .get(..7)?
+        .eq_ignore_ascii_case("BODY[]<")
+        .then(|| &item[7..])?;
+    rest.strip_suffix('>')?.parse().ok()
+}
+
+/// Item names under which a server may send the full message.
+const BODY_ITEMS: [&str; 3] = ["BODY[]", "BINARY[]", "RFC822"];
+
+/// One complete response read from the start of a buffer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Parsed<'a> {
+    Fetch(Fetch<'a>),
+    /// Any other response, unparsed and including its CRLF.
+    Other(&'a [u8]),
+}
+
+/// Length of the complete response at the start of `input`: its lines, any
+/// literals they announce, and the final CRLF.
+pub fn response_len(input: &[u8]) -> Result<usize, ParseError> {
+    let mut pos = 0;
+    loop {
+        let line_end = pos + find_crlf(&input[pos..]).ok_or(ParseError::Incomplete)?;
+        match literal_announcement(&input[pos..line_end]) {
+            Some(len) => {
+                let end = (line_end + 2).checked_add(len).ok_or(ParseError::Invalid {
+                    offset: line_end,
+                    reason: "literal too large",
+                })?;
+                if input.len() < end {
+                    return Err(ParseError::Incomplete);
+                }
+                pos = end;
+            }
+            None => return Ok(line_end + 2),
+        }
+    }
+}
+
+/// Parses the response at the start of `input`, returning it and the number of
+/// bytes it took up. Never reads past that response.
+pub fn parse_response(input: &[u8]) -> Result<(Parsed<'_>, usize), ParseError> {
+    let len = response_len(input)?;
+    let response = &input[..len - 2];
+    let parsed = match fetch_list_start(response) {
+        Some((seq, start)) => {
+            let mut cursor = Cursor {
+                input: response,
+                pos: start,
+                depth: 0,
+            };
+            let items = cursor.items()?;
+            Parsed::Fetch(Fetch { seq, items })
+        }
+        None => Parsed::Other(&input[..len]),
+    };
+    Ok((parsed, len))
+}
+
+/// If `response` is `* n FETCH (`, the sequence number and the offset of `(`.
+fn fetch_list_start(response: &[u8]) -> Option<(u32, usize)> {
+    let rest = response.strip_prefix(b"* ")?;
+    let digits = rest.iter().take_while(|b| b.is_ascii_digit()).count();
+    let seq = std::str::from_utf8(&rest[..digits]).ok()?.parse().ok()?;
+    let keyword = rest.get(digits..digits + 7)?;
+    if !keyword.eq_ignore_ascii_case(b" FETCH ") {
+        return None;
+    }
+    Some((seq, 2 + digits + 7))
+}
+
+fn find_crlf(input: &[u8]) -> Option<usize> {
+    memchr::memmem::find(input, b"\r\n")
+}
+
+/// `n` if `line` ends with a `{n}` (or `~{n}`) literal announcement.
+fn literal_announcement(line: &[u8]) -> Option<usize> {
+    let body = line.strip_suffix(b"}")?;
+    let start = body.iter().rposition(|&b| b == b'{')?;
+    let digits = &body[start + 1..];
+    if digits.is_empty() || !digits.iter().all(u8::is_ascii_digit) {
+        return None;
+    }
+    std::str::from_utf8(digits).ok()?.parse().ok()
+}
+
+/// Deepest list nesting accepted, so hostile input can't exhaust the stack.
+const MAX_DEPTH: usize = 64;
+
+struct Cursor<'a> {
+    input: &'a [u8],
+    pos: usize,
+    depth: usize,
+}
+
+impl<'a> Cursor<'a> {
+    fn invalid(&self, reason: &'static str) -> ParseError {
+        ParseError::Invalid {
+            offset: self.pos,
+            reason,
+        }
+    }
+
+    fn peek(&self) -> Option<u8> {
+        self.input.get(self.pos).copied()
+    }
+
+    fn skip_spaces(&mut self) {
+        while self.peek() == Some(b' ') {
+            self.pos += 1;
+        }
+    }
+
+    /// The `(name value ...)` list of a FETCH response.
+    fn items(&mut self) -> Result<Vec<(&'a str, Value<'a>)>, ParseError> {
+        if self.peek() != Some(b'(') {
+            return Err(self.invalid("expected `(`"));
+        }
+        self.pos += 1;
+
+        let mut items = Vec::new();
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                Some(b')') => {
+                    self.pos += 1;
+                    break;
+                }
+                None => return Err(self.invalid("unterminated item list")),
+                _ => {}
+            }
+            let name = match self.value()? {
+                Value::Atom(name) => name,
+                _ => return Err(self.invalid("expected an item name")),
+            };
+            self.skip_spaces();
+            let value = self.value()?;
+            items.push((name, value));
+        }
+
+        self.skip_spaces();
+        if self.pos != self.input.len() {
+            return Err(self.invalid("unexpected data after item list"));
+        }
+        Ok(items)
+    }
+
+    fn value(&mut self) -> Result<Value<'a>, ParseError> {
+        match self.peek() {
+            Some(b'(') => self.list(),
+            Some(b'"') => self.quoted(),
+            Some(b'{') => self.literal(),
+            Some(b'~') if self.input.get(self.pos + 1) == Some(&b'{') => {
+                self.pos += 1;
+                self.literal()
+            }
+            Some(_) => self.atom(),
+            None => Err(self.invalid("expected a value")),
+        }
+    }
+
+    fn list(&mut self) -> Result<Value<'a>, ParseError> {
+        if self.depth == MAX_DEPTH {
+            return Err(self.invalid("lists nested too deeply"));
+        }
+        self.depth += 1;
+        self.pos += 1;
+        let mut values = Vec::new();
+        loop {
+            self.skip_spaces();
+            match self.peek() {
+                Some(b')') => {
+                    self.pos += 1;
+                    self.depth -= 1;
+                    return Ok(Value::List(values));
+                }
+                None => return Err(self.invalid("unterminated list")),
+                _ => values.push(self.value()?),
+            }
+        }
+    }
+
+    fn quoted(&mut self) -> Result<Value<'a>, ParseError> {
+        let start = self.pos + 1;
+        let mut escaped = false;
+        let mut pos = start;
+        loop {
+            match self.input.get(pos) {
+                Some(b'"') => break,
+                Some(b'\\') => {
+                    escaped = true;
+                    pos += 2;
+                }
+                Some(_) => pos += 1,
+                None => {
+                    self.pos = pos.min(self.input.len());
+                    return Err(self.invalid("unterminated string"));
+                }
+            }
+        }
+        self.pos = pos + 1;
+
+        let raw = &self.input[start..pos];
+        if !escaped {
+            return Ok(Value::String(Cow::Borrowed(raw)));
+        }
+        let mut unescaped = Vec::with_capacity(raw.len());
+        let mut bytes = raw.iter();
+        while let Some(&b) = bytes.next() {
+            match b {
+                b'\\' => unescaped.extend(bytes.next()),
+                _ => unescaped.push(b),
+            }
+        }
+        Ok(Value::String(Cow::Owned(unescaped)))
+    }
+
+    fn literal(&mut self) -> Result<Value<'a>, ParseError> {
+        let close = self.input[self.pos..]
+            .iter()
+            .position(|&b| b == b'}')
+            .ok_or_else(|| self.invalid("unterminated literal length"))?;
+        let len = std::str::from_utf8(&self.input[self.pos + 1..self.pos + close])
+            .ok()
+            .and_then(|digits| digits.parse::<usize>().ok())
+            .ok_or_else(|| self.invalid("invalid literal length"))?;
+        self.pos += close + 1;
+
+        if !self.input[self.pos..].starts_with(b"\r\n") {
+            return Err(self.invalid("expected CRLF after literal length"));
+        }
+        self.pos += 2;
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.input.len())
+            .ok_or_else(|| self.invalid("literal runs past the response"))?;
+        let data = &self.input[self.pos..end];
+        self.pos = end;
+        Ok(Value::Literal(data))
+    }
+
+    /// An atom, including a `[section]` and `<partial>` suffix such as
+    /// `BODY[HEADER.FIELDS (FROM TO)]<0>`.
+    fn atom(&mut self) -> Result<Value<'a>, ParseError> {
+        let start = self.pos;
+        while let Some(b) = self.peek() {
+            match b {
+                b'[' => {
+                    let close = self.input[self.pos..]
+                        .iter()
+                        .position(|&b| b == b']')
+                        .ok_or_else(|| self.invalid("unterminated section"))?;
+                    self.pos += close + 1;
+                }
+                b' ' | b'(' | b')' | b'"' | b'{' | b'\r' | b'\n' => break,
+                _ => self.pos += 1,
+            }
+        }
+        if self.pos == start {
+            return Err(self.invalid("expected an atom"));
+        }
+
+        let atom = std::str::from_utf8(&self.input[start..self.pos])
+            .map_err(|_| self.invalid("non-UTF-8 atom"))?;
+        if atom.eq_ignore_ascii_case("NIL") {
+            Ok(Value::Nil)
+        } else {
+            Ok(Value::Atom(atom))
+        }
+    }
+}
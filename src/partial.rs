@@ -0,0 +1,147 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::error_imap::ClientError;
+use crate::parser::partial_origin;
+use crate::session::{trailing_literal_len, Interrupted};
+
+/// Where messages a lost connection left partly downloaded are kept, under
+/// a folder's directory, until the rest is fetched.
+pub const PARTIAL_DIR: &str = ".partial";
+
+/// Smallest part of a message worth keeping; smaller ones are fetched again
+/// from the start.
+pub const MIN_PARTIAL: usize = 1 << 20;
+
+/// A message part kept from an interrupted download.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Partial {
+    pub uid: u32,
+    /// Bytes kept so far, which the rest is fetched from.
+    pub offset: u64,
+    /// The message's full size.
+    pub total: u64,
+    pub path: PathBuf,
+}
+
+/// Keeps the part of a message `interrupted` was cut off in, appending to
+/// what was kept before when it was itself the rest of one. Returns the
+/// message's UID, or `None` if the part isn't worth keeping or the response
+/// isn't a message body with its UID ahead of it.
+pub fn keep(
+    dir: &Path,
+    uid_validity: u32,
+    interrupted: &Interrupted,
+) -> Result<Option<u32>, ClientError> {
+    let text = String::from_utf8_lossy(&interrupted.response);
+    let Some((uid, origin)) = body_announcement(&text) else {
+        return Ok(None);
+    };
+    let earlier = pending(dir, uid_validity, &[uid])?.into_iter().next();
+    let (path, total) = match earlier {
+        // The rest of a part kept before
+        Some(partial) if origin > 0 && partial.offset == origin => (partial.path, partial.total),
+        _ if origin > 0 => return Ok(None),
+        _ if interrupted.received.len() < MIN_PARTIAL => return Ok(None),
+        earlier => {
+            if let Some(earlier) = earlier {
+                fs::remove_file(&earlier.path)?;
+            }
+            let total = interrupted.len as u64;
+            let path = partial_dir(dir).join(format!("{}-{}-{}.part", uid_validity, uid, total));
+            (path, total)
+        }
+    };
+    if origin + interrupted.received.len() as u64 >= total {
+        return Ok(None);
+    }
+    fs::create_dir_all(partial_dir(dir))?;
+    let mut file = OpenOptions::new().create(true).append(true).open(&path)?;
+    file.write_all(&interrupted.received)?;
+    file.sync_all()?;
+    Ok(Some(uid))
+}
+
+/// The parts kept of the messages `uids` of the folder in `dir`.
+pub fn pending(dir: &Path, uid_validity: u32, uids: &[u32]) -> Result<Vec<Partial>, ClientError> {
+    let entries = match fs::read_dir(partial_dir(dir)) {
+        Ok(entries) => entries,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Vec::new()),
+        Err(e) => return Err(e.into()),
+    };
+    let mut partials = Vec::new();
+    for entry in entries {
+        let entry = entry?;
+        let name = entry.file_name();
+        let Some(stem) = name.to_str().and_then(|name| name.strip_suffix(".part")) else {
+            continue;
+        };
+        let numbers: Vec<u64> = stem.split('-').filter_map(|n| n.parse().ok()).collect();
+        let [validity, uid, total] = numbers[..] else {
+            continue;
+        };
+        if validity != uid_validity as u64 || !uids.iter().any(|&u| u as u64 == uid) {
+            continue;
+        }
+        partials.push(Partial {
+            uid: uid as u32,
+            offset: entry.metadata()?.len(),
+            total,
+            path: entry.path(),
+        });
+    }
+    partials.sort_by_key(|partial| partial.uid);
+    Ok(partials)
+}
+
+/// What was kept of message `uid`, which the rest fetched from `offset` is
+/// to follow, or `None` if that isn't what is kept.
+pub fn take(
+    dir: &Path,
+    uid_validity: u32,
+    uid: u32,
+    offset: u64,
+) -> Result<Option<Vec<u8>>, ClientError> {
+    let Some(partial) = pending(dir, uid_validity, &[uid])?.into_iter().next() else {
+        return Ok(None);
+    };
+    if partial.offset != offset {
+        fs::remove_file(&partial.path)?;
+        return Ok(None);
+    }
+    let kept = fs::read(&partial.path)?;
+    fs::remove_file(&partial.path)?;
+    Ok(Some(kept))
+}
+
+fn partial_dir(dir: &Path) -> PathBuf {
+    dir.join(PARTIAL_DIR)
+}
+
+/// The UID of the message whose body `response` ends by announcing, and
+/// where in the message that body starts.
+fn body_announcement(response: &str) -> Option<(u32, u64)> {
+    let response = response.trim_end();
+    let (_, literal_start) = trailing_literal_len(response)?;
+    let item = response[..literal_start].trim_end();
+    let item = &item[item.rfind(' ')? + 1..];
+    let origin = if item.eq_ignore_ascii_case("BODY[]") {
+        0
+    } else {
+        partial_origin(item)?
+    };
+    // `UID n` as an item of the FETCH list, before the body
+    let upper = response.to_ascii_uppercase();
+    let uid = upper.match_indices("UID ").find_map(|(at, _)| {
+        if !matches!(upper[..at].chars().last(), Some('(' | ' ')) {
+            return None;
+        }
+        let digits: String = upper[at + 4..]
+            .chars()
+            .take_while(char::is_ascii_digit)
+            .collect();
+        digits.parse().ok()
+    })?;
+    Some((uid, origin))
+}
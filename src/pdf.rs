@@ -0,0 +1,194 @@
+//! Minimal PDF writer: fixed-pitch text pages using the standard Courier
+//! fonts, which every PDF reader provides, so no fonts need to be embedded.
+
+const PAGE_WIDTH: f32 = 595.0;
+const PAGE_HEIGHT: f32 = 842.0;
+const MARGIN: f32 = 50.0;
+const FONT_SIZE: f32 = 9.0;
+const LEADING: f32 = 11.0;
+/// Courier glyphs are 0.6 em wide.
+const CHARS_PER_LINE: usize = ((PAGE_WIDTH - 2.0 * MARGIN) / (FONT_SIZE * 0.6)) as usize;
+const LINES_PER_PAGE: usize = ((PAGE_HEIGHT - 2.0 * MARGIN) / LEADING) as usize;
+
+/// One line of text, optionally in bold.
+pub struct Line {
+    pub text: String,
+    pub bold: bool,
+}
+
+impl Line {
+    pub fn plain(text: impl Into<String>) -> Self {
+        Line {
+            text: text.into(),
+            bold: false,
+        }
+    }
+
+    pub fn bold(text: impl Into<String>) -> Self {
+        Line {
+            text: text.into(),
+            bold: true,
+        }
+    }
+}
+
+/// Lays `lines` out on A4 pages, wrapping long lines, and returns the PDF bytes.
+pub fn render_pdf(title: &str, lines: &[Line]) -> Vec<u8> {
+    let wrapped: Vec<(String, bool)> = lines
+        .iter()
+        .flat_map(|line| wrap(&line.text).into_iter().map(move |l| (l, line.bold)))
+        .collect();
+    let pages: Vec<&[(String, bool)]> = if wrapped.is_empty() {
+        vec![&[]]
+    } else {
+        wrapped.chunks(LINES_PER_PAGE).collect()
+    };
+
+    // Object numbers: 1 catalog, 2 page tree, 3 regular font, 4 bold font,
+    // 5 info, then a page object and a content stream per page.
+    let mut objects: Vec<Vec<u8>> = Vec::new();
+    let page_ids: Vec<usize> = (0..pages.len()).map(|i| 6 + 2 * i).collect();
+
+    objects.push(b"<< /Type /Catalog /Pages 2 0 R >>".to_vec());
+    let kids: Vec<String> = page_ids.iter().map(|id| format!("{} 0 R", id)).collect();
+    objects.push(
+        format!(
+            "<< /Type /Pages /Kids [{}] /Count {} >>",
+            kids.join(" "),
+            pages.len()
+        )
+        .into_bytes(),
+    );
+    objects.push(font("Courier"));
+    objects.push(font("Courier-Bold"));
+    let mut info = b"<< /Producer (imap_client) /Title ".to_vec();
+    info.extend_from_slice(&pdf_string(title));
+    info.extend_from_slice(b" >>");
+    objects.push(info);
+
+    for (page, page_id) in pages.iter().zip(&page_ids) {
+        objects.push(
+            format!(
+                "<< /Type /Page /Parent 2 0 R /MediaBox [0 0 {} {}] \
+                 /Resources << /Font << /F1 3 0 R /F2 4 0 R >> >> /Contents {} 0 R >>",
+                PAGE_WIDTH,
+                PAGE_HEIGHT,
+                page_id + 1
+            )
+            .into_bytes(),
+        );
+
+        let mut content = format!(
+            "BT\n{} TL\n{} {} Td\n",
+            LEADING,
+            MARGIN,
+            PAGE_HEIGHT - MARGIN - FONT_SIZE
+        )
+        .into_bytes();
+        for (text, bold) in page.iter() {
+            let font = if *bold { "F2" } else { "F1" };
+            content.extend_from_slice(format!("/{} {} Tf ", font, FONT_SIZE).as_bytes());
+            content.extend_from_slice(&pdf_string(text));
+            content.extend_from_slice(b" Tj T*\n");
+        }
+        content.extend_from_slice(b"ET");
+
+        let mut stream = format!("<< /Length {} >>\nstream\n", content.len()).into_bytes();
+        stream.extend_from_slice(&content);
+        stream.extend_from_slice(b"\nendstream");
+        objects.push(stream);
+    }
+
+    let mut pdf = b"%PDF-1.4\n%\xe2\xe3\xcf\xd3\n".to_vec();
+    let mut offsets = Vec::with_capacity(objects.len());
+    for (i, object) in objects.iter().enumerate() {
+        offsets.push(pdf.len());
+        pdf.extend_from_slice(format!("{} 0 obj\n", i + 1).as_bytes());
+        pdf.extend_from_slice(object);
+        pdf.extend_from_slice(b"\nendobj\n");
+    }
+
+    let xref_offset = pdf.len();
+    pdf.extend_from_slice(
+        format!("xref\n0 {}\n0000000000 65535 f \n", objects.len() + 1).as_bytes(),
+    );
+    for offset in offsets {
+        pdf.extend_from_slice(format!("{:010} 00000 n \n", offset).as_bytes());
+    }
+    pdf.extend_from_slice(
+        format!(
+            "trailer\n<< /Size {} /Root 1 0 R /Info 5 0 R >>\nstartxref\n{}\n%%EOF\n",
+            objects.len() + 1,
+            xref_offset
+        )
+        .as_bytes(),
+    );
+    pdf
+}
+
+fn font(name: &str) -> Vec<u8> {
+    format!(
+        "<< /Type /Font /Subtype /Type1 /BaseFont /{} /Encoding /WinAnsiEncoding >>",
+        name
+    )
+    .into_bytes()
+}
+
+/// Splits `text` into lines that fit the page width, breaking at spaces
+/// where possible.
+fn wrap(text: &str) -> Vec<String> {
+    let mut lines = Vec::new();
+    for raw in text.split('\n') {
+        let chars: Vec<char> = raw
+            .trim_end_matches('\r')
+            .replace('\t', "    ")
+            .chars()
+            .collect();
+        if chars.is_empty() {
+            lines.push(String::new());
+            continue;
+        }
+        let mut start = 0;
+        while start < chars.len() {
+            let mut end = (start + CHARS_PER_LINE).min(chars.len());
+            if end < chars.len() {
+                if let Some(space) = chars[start..end].iter().rposition(|&c| c == ' ') {
+                    if space > 0 {
+                        end = start + space + 1;
+                    }
+                }
+            }
+            lines.push(
+                chars[start..end]
+                    .iter()
+                    .collect::<String>()
+                    .trim_end()
+                    .to_string(),
+            );
+            start = end;
+        }
+    }
+    lines
+}
+
+/// Encodes `text` as a PDF literal string in WinAnsi (Windows-1252);
+/// characters outside that charset become `?`.
+fn pdf_string(text: &str) -> Vec<u8> {
+    let mut out = vec![b'('];
+    for c in text.chars() {
+        let mut buf = [0u8; 4];
+        let (bytes, _, unmappable) = encoding_rs::WINDOWS_1252.encode(c.encode_utf8(&mut buf));
+        let byte = if unmappable || bytes.len() != 1 {
+            b'?'
+        } else {
+            bytes[0]
+        };
+        match byte {
+            b'(' | b')' | b'\\' => out.extend_from_slice(&[b'\\', byte]),
+            b'\r' | b'\n' => out.push(b' '),
+            _ => out.push(byte),
+        }
+    }
+    out.push(b')');
+    out
+}
@@ -0,0 +1,208 @@
+use std::path::PathBuf;
+use std::sync::Arc;
+
+use crate::error_imap::ClientError;
+use crate::processor::MessageProcessor;
+
+/// Loads the WebAssembly plugins at `paths`, to run in that order.
+#[cfg(feature = "wasm")]
+pub fn load_plugins(paths: &[PathBuf]) -> Result<Vec<Arc<dyn MessageProcessor>>, ClientError> {
+    paths
+        .iter()
+        .map(|path| Ok(Arc::new(wasm::WasmPlugin::load(path)?) as Arc<dyn MessageProcessor>))
+        .collect()
+}
+
+/// Built without WebAssembly support, so any plugin is refused.
+#[cfg(not(feature = "wasm"))]
+pub fn load_plugins(paths: &[PathBuf]) -> Result<Vec<Arc<dyn MessageProcessor>>, ClientError> {
+    match paths.first() {
+        None => Ok(Vec::new()),
+        Some(path) => Err(ClientError::InvalidArgument(format!(
+            "cannot load {}: plugins are not available in this build; rebuild with --features wasm",
+            path.display()
+        ))),
+    }
+}
+
+#[cfg(feature = "wasm")]
+pub use wasm::WasmPlugin;
+
+#[cfg(feature = "wasm")]
+mod wasm {
+    use std::path::Path;
+    use wasmtime::{
+        Caller, Config, Engine, Extern, InstancePre, Linker, Module, Store, StoreLimits,
+        StoreLimitsBuilder,
+    };
+
+    use crate::error_imap::ClientError;
+    use crate::processor::{MessageInfo, MessageProcessor, Processed};
+
+    /// Most memory a plugin may use while handling one message.
+    const MEMORY_LIMIT: usize = 512 << 20;
+
+    /// Instructions a plugin may run per message, so one stuck in a loop
+    /// fails its message instead of hanging the fetch.
+    const FUEL: u64 = 20_000_000_000;
+
+    /// A message processor compiled to WebAssembly, for processing written
+    /// in any language with a wasm32 target.
+    ///
+    /// The module exports its `memory` and two functions:
+    ///
+    /// - `alloc(len: i32) -> i32` returns where `len` bytes may be written;
+    /// - `process(info: i32, info_len: i32, body: i32, body_len: i32) -> i64`
+    ///   gets the message's details as JSON (`mailbox`, `seq`, `size` and
+    ///   `internal_date`) and its raw bytes. It returns 0 to store the message
+    ///   unchanged, a negative number to not store it, or else the address of
+    ///   the message to store in the upper 32 bits and its length in the
+    ///   lower 32.
+    ///
+    /// It may import `env.set_name(ptr: i32, len: i32)` to store the message
+    /// under another file name (without an extension) and
+    /// `env.log(ptr: i32, len: i32)` to write a line to the log.
+    ///
+    /// Every message gets a fresh instance, so nothing carries over between
+    /// messages and plugins need not free what they allocate.
+    pub struct WasmPlugin {
+        name: String,
+        engine: Engine,
+        instance: InstancePre<PluginState>,
+    }
+
+    struct PluginState {
+        plugin: String,
+        limits: StoreLimits,
+        /// File name given with `set_name`.
+        name: Option<String>,
+    }
+
+    impl WasmPlugin {
+        /// Compiles the module at `path`, which may be binary or text.
+        pub fn load(path: &Path) -> Result<Self, ClientError> {
+            let name = path.display().to_string();
+            let failed = |reason: wasmtime::Error| ClientError::PluginError {
+                plugin: name.clone(),
+                reason: format!("{:#}", reason),
+            };
+            let mut config = Config::new();
+            config.consume_fuel(true);
+            let engine = Engine::new(&config).map_err(failed)?;
+            let module = Module::from_file(&engine, path).map_err(failed)?;
+            for export in ["memory", "alloc", "process"] {
+                if module.get_export(export).is_none() {
+                    return Err(failed(wasmtime::Error::msg(format!(
+                        "the module does not export `{}`",
+                        export
+                    ))));
+                }
+            }
+
+            let mut linker = Linker::new(&engine);
+            linker
+                .func_wrap(
+                    "env",
+                    "set_name",
+                    |mut caller: Caller<'_, PluginState>, ptr: i32, len: i32| {
+                        let name = read_string(&mut caller, ptr, len)?;
+                        caller.data_mut().name = Some(name);
+                        Ok(())
+                    },
+                )
+                .map_err(failed)?;
+            linker
+                .func_wrap(
+                    "env",
+                    "log",
+                    |mut caller: Caller<'_, PluginState>, ptr: i32, len: i32| {
+                        let line = read_string(&mut caller, ptr, len)?;
+                        log::info!("{}: {}", caller.data().plugin, line);
+                        Ok(())
+                    },
+                )
+                .map_err(failed)?;
+            let instance = linker.instantiate_pre(&module).map_err(failed)?;
+            Ok(WasmPlugin {
+                name,
+                engine,
+                instance,
+            })
+        }
+
+        fn run(&self, message: &MessageInfo, body: Vec<u8>) -> wasmtime::Result<Processed> {
+            let mut store = Store::new(
+                &self.engine,
+                PluginState {
+                    plugin: self.name.clone(),
+                    limits: StoreLimitsBuilder::new().memory_size(MEMORY_LIMIT).build(),
+                    name: None,
+                },
+            );
+            store.limiter(|state| &mut state.limits);
+            store.set_fuel(FUEL)?;
+            let instance = self.instance.instantiate(&mut store)?;
+            let memory = instance
+                .get_memory(&mut store, "memory")
+                .ok_or_else(|| wasmtime::Error::msg("`memory` is not a memory"))?;
+            let alloc = instance.get_typed_func::<i32, i32>(&mut store, "alloc")?;
+            let process =
+                instance.get_typed_func::<(i32, i32, i32, i32), i64>(&mut store, "process")?;
+
+            let info = serde_json::json!({
+                "mailbox": message.mailbox,
+                "seq": message.seq,
+                "size": message.size,
+                "internal_date": message.internal_date,
+            })
+            .to_string();
+            let mut pass = |bytes: &[u8]| -> wasmtime::Result<(i32, i32)> {
+                let len = i32::try_from(bytes.len())?;
+                let ptr = alloc.call(&mut store, len)?;
+                memory.write(&mut store, ptr as u32 as usize, bytes)?;
+                Ok((ptr, len))
+            };
+            let (info_ptr, info_len) = pass(info.as_bytes())?;
+            let (body_ptr, body_len) = pass(&body)?;
+
+            let result = process.call(&mut store, (info_ptr, info_len, body_ptr, body_len))?;
+            let body = match result {
+                0 => body,
+                result if result < 0 => return Ok(Processed::Skip),
+                result => {
+                    let (ptr, len) = ((result >> 32) as usize, (result & 0xffff_ffff) as usize);
+                    let mut output = vec![0; len];
+                    memory.read(&store, ptr, &mut output)?;
+                    output
+                }
+            };
+            Ok(Processed::Save {
+                body,
+                name: store.into_data().name,
+            })
+        }
+    }
+
+    fn read_string(
+        caller: &mut Caller<'_, PluginState>,
+        ptr: i32,
+        len: i32,
+    ) -> wasmtime::Result<String> {
+        let Some(Extern::Memory(memory)) = caller.get_export("memory") else {
+            return Err(wasmtime::Error::msg("the module does not export `memory`"));
+        };
+        let mut bytes = vec![0; len as u32 as usize];
+        memory.read(&caller, ptr as u32 as usize, &mut bytes)?;
+        Ok(String::from_utf8(bytes)?)
+    }
+
+    impl MessageProcessor for WasmPlugin {
+        fn process(&self, message: &MessageInfo, body: Vec<u8>) -> Result<Processed, ClientError> {
+            self.run(message, body)
+                .map_err(|reason| ClientError::PluginError {
+                    plugin: self.name.clone(),
+                    reason: format!("email {}: {:#}", message.seq, reason),
+                })
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use std::sync::{Mutex, MutexGuard};
+
+/// Signed-in sessions kept open between batches, each with the mailbox it
+/// has selected. SELECT applies to a whole session, so a batch given one
+/// already in its mailbox can start fetching straight away.
+pub struct SessionPool<S> {
+    idle: Mutex<Vec<(String, S)>>,
+}
+
+impl<S> SessionPool<S> {
+    pub fn new() -> Self {
+        SessionPool {
+            idle: Mutex::new(Vec::new()),
+        }
+    }
+
+    /// Takes an idle session for `mailbox`: the latest one returned with it
+    /// selected, or else the one idle longest. Returns the session and
+    /// whether it is already in `mailbox`.
+    pub fn take(&self, mailbox: &str) -> Option<(S, bool)> {
+        let mut idle = lock(&self.idle);
+        if let Some(i) = idle.iter().rposition(|(selected, _)| selected == mailbox) {
+            return Some((idle.remove(i).1, true));
+        }
+        if idle.is_empty() {
+            return None;
+        }
+        Some((idle.remove(0).1, false))
+    }
+
+    /// Keeps `session`, which has `mailbox` selected, for a later batch.
+    /// Once `limit` sessions are idle it is handed back to be closed instead.
+    pub fn put(&self, mailbox: &str, session: S, limit: usize) -> Option<S> {
+        let mut idle = lock(&self.idle);
+        if idle.len() >= limit {
+            return Some(session);
+        }
+        idle.push((mailbox.to_string(), session));
+        None
+    }
+
+    /// Empties the pool, handing back the sessions to be closed.
+    pub fn drain(&self) -> Vec<S> {
+        lock(&self.idle)
+            .drain(..)
+            .map(|(_, session)| session)
+            .collect()
+    }
+
+    pub fn len(&self) -> usize {
+        lock(&self.idle).len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<S> Default for SessionPool<S> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// The pool holds nothing a panic could leave half-updated.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
@@ -0,0 +1,73 @@
+use std::path::Path;
+
+use crate::error_imap::ClientError;
+
+/// A message as it goes through the fetch pipeline.
+#[derive(Debug, Clone, Copy)]
+pub struct MessageInfo<'a> {
+    /// The folder or label it was fetched from.
+    pub mailbox: &'a str,
+    /// Number the saved file is named by (`email_NNNNN`).
+    pub seq: u32,
+    /// Size as downloaded, in bytes.
+    pub size: usize,
+    /// When the server received it, as a Unix timestamp.
+    pub internal_date: Option<i64>,
+}
+
+/// A batch of messages that has been handled, or has failed.
+#[derive(Debug, Clone, Copy)]
+pub struct BatchInfo<'a> {
+    pub mailbox: &'a str,
+    /// How many messages the batch was for.
+    pub messages: usize,
+    pub error: Option<&'a ClientError>,
+}
+
+/// What a processor does with a message.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Processed {
+    /// Store `body`, under the file name `name` (without an extension) if
+    /// given instead of `email_NNNNN`.
+    Save { body: Vec<u8>, name: Option<String> },
+    /// Don't store the message; it is counted as skipped, as with a `skip`
+    /// rule.
+    Skip,
+}
+
+/// Hooks into the fetch pipeline for library users, registered with
+/// [`crate::client::ImapClient::with_processor`]. Every method has a default
+/// that does nothing, so implementations only override what they need.
+///
+/// Messages are handled concurrently, so hooks are called from several tasks
+/// at once and in no particular order.
+pub trait MessageProcessor: Send + Sync {
+    /// A message has been downloaded and is about to be filed.
+    fn on_message_start(&self, _message: &MessageInfo) {}
+
+    /// Rewrites the raw message before it is stored, e.g. to strip tracking
+    /// images or encrypt it. Rules see the message as downloaded. An error
+    /// fails the message's batch.
+    fn transform(&self, _message: &MessageInfo, body: Vec<u8>) -> Result<Vec<u8>, ClientError> {
+        Ok(body)
+    }
+
+    /// Decides what is stored for the message: by default, what
+    /// [`MessageProcessor::transform`] makes of it. Override this to rename or
+    /// filter messages as well. Later processors see what earlier ones made of
+    /// the message; once one skips it, later ones aren't asked.
+    fn process(&self, message: &MessageInfo, body: Vec<u8>) -> Result<Processed, ClientError> {
+        Ok(Processed::Save {
+            body: self.transform(message, body)?,
+            name: None,
+        })
+    }
+
+    /// The message (or, under an `attachments-only` rule, its attachments
+    /// directory) has been written to `path`.
+    fn on_message_saved(&self, _message: &MessageInfo, _path: &Path) {}
+
+    /// A batch has been fetched. With the HTTP backends a batch is one page of
+    /// messages.
+    fn on_batch_complete(&self, _batch: &BatchInfo) {}
+}
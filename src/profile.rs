@@ -0,0 +1,64 @@
+use std::str::FromStr;
+use std::time::Duration;
+
+use crate::error_imap::ClientError;
+use crate::input::ImapConfig;
+use crate::scheduler::Limits;
+
+/// A named set of tuning settings: connections per account, batch size, the
+/// pause between batches, and the limits shared by the run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Profile {
+    /// Few connections, small batches and a capped download rate, for
+    /// accounts in use or a domain close to its limits.
+    Gentle,
+    /// The defaults.
+    #[default]
+    Normal,
+    /// As many connections as Gmail comfortably allows one user, with large
+    /// batches and no pause between them.
+    Aggressive,
+}
+
+impl FromStr for Profile {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "gentle" => Ok(Profile::Gentle),
+            "normal" => Ok(Profile::Normal),
+            "aggressive" => Ok(Profile::Aggressive),
+            other => Err(ClientError::InvalidArgument(format!(
+                "Unknown profile `{}` (expected gentle, normal or aggressive)",
+                other
+            ))),
+        }
+    }
+}
+
+impl Profile {
+    /// Sets an account's connections, batch size and pause between batches.
+    pub fn apply(self, config: &mut ImapConfig) {
+        let (max_concurrent, batch_size, batch_delay, batch_jitter) = match self {
+            Profile::Gentle => (2, 100, Duration::from_secs(1), Duration::from_secs(1)),
+            Profile::Normal => (5, 500, Duration::from_millis(50), Duration::ZERO),
+            Profile::Aggressive => (10, 1000, Duration::ZERO, Duration::ZERO),
+        };
+        config.max_concurrent = max_concurrent;
+        config.batch_size = batch_size;
+        config.batch_delay = batch_delay;
+        config.batch_jitter = batch_jitter;
+    }
+
+    /// The limits shared by the accounts of a run.
+    pub fn limits(self) -> Limits {
+        match self {
+            Profile::Gentle => Limits {
+                connections: None,
+                domain_connections: Some(6),
+                bandwidth: Some(2 << 20),
+            },
+            Profile::Normal | Profile::Aggressive => Limits::default(),
+        }
+    }
+}
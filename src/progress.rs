@@ -0,0 +1,272 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::io::Write;
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU32, Ordering};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::error_imap::ClientError;
+use crate::tr;
+
+/// How progress is shown while accounts are fetched.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum ProgressMode {
+    /// A bar redrawn in place on a terminal; the combined status line every
+    /// few seconds otherwise.
+    #[default]
+    Bar,
+    /// A sentence per update, with no control characters, for screen
+    /// readers and CI logs.
+    Plain,
+    /// Nothing.
+    Off,
+}
+
+impl FromStr for ProgressMode {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "bar" => Ok(ProgressMode::Bar),
+            "plain" => Ok(ProgressMode::Plain),
+            "off" => Ok(ProgressMode::Off),
+            other => Err(ClientError::InvalidArgument(format!(
+                "Unknown progress mode `{}` (expected bar, plain or off)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Per-account progress counters, shared between the fetch tasks of one
+/// account and whoever renders the combined view.
+pub struct Progress {
+    account: String,
+    total: AtomicU32,
+    saved: AtomicU32,
+    skipped: AtomicU32,
+    failed_batches: AtomicU32,
+    next_activity: AtomicU32,
+    /// What each open connection is doing, by activity id.
+    activities: Mutex<BTreeMap<u32, String>>,
+    /// Messages done and planned in each folder, by name.
+    folders: Mutex<BTreeMap<String, (u32, u32)>>,
+    recent_errors: Mutex<VecDeque<String>>,
+}
+
+impl Progress {
+    pub fn new(account: &str) -> Arc<Self> {
+        Arc::new(Progress {
+            account: account.to_string(),
+            total: AtomicU32::new(0),
+            saved: AtomicU32::new(0),
+            skipped: AtomicU32::new(0),
+            failed_batches: AtomicU32::new(0),
+            next_activity: AtomicU32::new(0),
+            activities: Mutex::new(BTreeMap::new()),
+            folders: Mutex::new(BTreeMap::new()),
+            recent_errors: Mutex::new(VecDeque::new()),
+        })
+    }
+
+    pub fn account(&self) -> &str {
+        &self.account
+    }
+
+    pub fn add_total(&self, count: u32) {
+        self.total.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn add_saved(&self, count: u32) {
+        self.saved.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn add_skipped(&self, count: u32) {
+        self.skipped.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn add_failed_batch(&self) {
+        self.failed_batches.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn total(&self) -> u32 {
+        self.total.load(Ordering::Relaxed)
+    }
+
+    pub fn saved(&self) -> u32 {
+        self.saved.load(Ordering::Relaxed)
+    }
+
+    pub fn skipped(&self) -> u32 {
+        self.skipped.load(Ordering::Relaxed)
+    }
+
+    pub fn failed_batches(&self) -> u32 {
+        self.failed_batches.load(Ordering::Relaxed)
+    }
+
+    /// Registers a connection's activity, returning its id for later updates.
+    pub fn start_activity(&self, description: String) -> u32 {
+        let id = self.next_activity.fetch_add(1, Ordering::Relaxed);
+        lock(&self.activities).insert(id, description);
+        id
+    }
+
+    pub fn set_activity(&self, id: u32, description: String) {
+        lock(&self.activities).insert(id, description);
+    }
+
+    pub fn end_activity(&self, id: u32) {
+        lock(&self.activities).remove(&id);
+    }
+
+    pub fn activities(&self) -> Vec<String> {
+        lock(&self.activities).values().cloned().collect()
+    }
+
+    pub fn add_folder_total(&self, folder: &str, count: u32) {
+        lock(&self.folders).entry(folder.to_string()).or_default().1 += count;
+    }
+
+    /// Counts messages of `folder` saved or skipped.
+    pub fn add_folder_done(&self, folder: &str, count: u32) {
+        lock(&self.folders).entry(folder.to_string()).or_default().0 += count;
+    }
+
+    /// How many messages of `folder` are done, and how many were planned.
+    pub fn folder(&self, folder: &str) -> (u32, u32) {
+        lock(&self.folders).get(folder).copied().unwrap_or_default()
+    }
+
+    /// Folders with messages still to fetch, with how many are done and
+    /// how many were planned.
+    pub fn unfinished_folders(&self) -> Vec<(String, u32, u32)> {
+        lock(&self.folders)
+            .iter()
+            .filter(|(_, (done, total))| done < total)
+            .map(|(folder, &(done, total))| (folder.clone(), done, total))
+            .collect()
+    }
+
+    pub fn record_error(&self, error: String) {
+        let mut errors = lock(&self.recent_errors);
+        if errors.len() == RECENT_ERRORS {
+            errors.pop_front();
+        }
+        errors.push_back(error);
+    }
+
+    /// The latest errors, oldest first.
+    pub fn recent_errors(&self) -> Vec<String> {
+        lock(&self.recent_errors).iter().cloned().collect()
+    }
+}
+
+/// How many errors [`Progress::recent_errors`] keeps.
+const RECENT_ERRORS: usize = 20;
+
+/// Characters in each account's bar.
+const BAR_WIDTH: usize = 20;
+
+/// Whether a bar is drawn on the terminal's last line, without a newline.
+static STATUS_DRAWN: AtomicBool = AtomicBool::new(false);
+
+/// Progress is only for display, so a panicked holder shouldn't stop anyone.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Renders one status line covering every account, e.g.
+/// `alice@gmail.com 120/500 | bob@gmail.com 40/40`.
+pub fn render_combined(progress: &[Arc<Progress>]) -> String {
+    progress
+        .iter()
+        .map(|p| {
+            let mut line = tr!(
+                "progress-account",
+                account = p.account(),
+                done = p.saved() + p.skipped(),
+                total = p.total()
+            );
+            if p.failed_batches() > 0 {
+                line.push(' ');
+                line.push_str(&tr!("progress-failed-batches", failed = p.failed_batches()));
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+/// Renders one sentence covering every account, e.g.
+/// `alice@gmail.com: 120 of 500 messages, 24%; bob@gmail.com: 40 of 40
+/// messages, 100%`, with no symbols a screen reader would spell out.
+pub fn render_plain(progress: &[Arc<Progress>]) -> String {
+    progress
+        .iter()
+        .map(|p| {
+            let done = p.saved() + p.skipped();
+            tr!(
+                "progress-plain-account",
+                account = p.account(),
+                done = done,
+                total = p.total(),
+                percent = percent(done, p.total()),
+                failed = p.failed_batches()
+            )
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
+
+/// Renders a bar for every account, e.g.
+/// `alice@gmail.com [#####...............] 120/500 | bob@gmail.com [####################] 40/40`.
+pub fn render_bar(progress: &[Arc<Progress>]) -> String {
+    progress
+        .iter()
+        .map(|p| {
+            let done = p.saved() + p.skipped();
+            let filled = (percent(done, p.total()) as usize * BAR_WIDTH / 100).min(BAR_WIDTH);
+            let mut line = format!(
+                "{} [{}{}] {}/{}",
+                p.account(),
+                "#".repeat(filled),
+                ".".repeat(BAR_WIDTH - filled),
+                done,
+                p.total()
+            );
+            if p.failed_batches() > 0 {
+                line.push(' ');
+                line.push_str(&tr!("progress-failed-batches", failed = p.failed_batches()));
+            }
+            line
+        })
+        .collect::<Vec<_>>()
+        .join(" | ")
+}
+
+/// `done` as a whole percentage of `total`, 0 while nothing is planned.
+fn percent(done: u32, total: u32) -> u32 {
+    if total == 0 {
+        0
+    } else {
+        (u64::from(done) * 100 / u64::from(total)).min(100) as u32
+    }
+}
+
+/// Redraws the status bar on the terminal's last line.
+pub fn draw_status(line: &str) {
+    let mut stdout = std::io::stdout().lock();
+    let _ = write!(stdout, "\r\x1b[2K{}", line);
+    let _ = stdout.flush();
+    STATUS_DRAWN.store(true, Ordering::Relaxed);
+}
+
+/// Erases the status bar, if one is drawn, so that a line can be printed
+/// in its place. The next redraw puts it back below.
+pub fn clear_status() {
+    if STATUS_DRAWN.swap(false, Ordering::Relaxed) {
+        let mut stdout = std::io::stdout().lock();
+        let _ = write!(stdout, "\r\x1b[2K");
+        let _ = stdout.flush();
+    }
+}
@@ -0,0 +1,233 @@
+use std::str::FromStr;
+
+use crate::error_imap::ClientError;
+use crate::oauth::GOOGLE_TOKEN_URL;
+
+/// Mail provider an account is with: where to connect, how to sign in and
+/// what its special folders are called.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Provider {
+    #[default]
+    Gmail,
+    /// Microsoft 365 and Outlook.com, which only accept OAuth 2.0 over IMAP.
+    Outlook,
+    Yahoo,
+    Fastmail,
+    ICloud,
+    /// Any other IMAP server, named by the account's `server`.
+    Generic,
+}
+
+/// Every provider, in the order they are listed to users.
+pub const PROVIDERS: [Provider; 6] = [
+    Provider::Gmail,
+    Provider::Outlook,
+    Provider::Yahoo,
+    Provider::Fastmail,
+    Provider::ICloud,
+    Provider::Generic,
+];
+
+/// How an account signs in to IMAP.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthMethod {
+    /// `LOGIN` with the password (for Gmail, an app password).
+    Login,
+    /// SASL XOAUTH2 with an access token, obtained through the OAuth 2.0
+    /// device flow the first time and from the refresh token after that.
+    OAuthDevice(DeviceFlow),
+}
+
+/// Endpoints and scope of a provider's OAuth 2.0 device flow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DeviceFlow {
+    pub device_url: &'static str,
+    pub token_url: &'static str,
+    pub scope: &'static str,
+    /// How to register the OAuth client the flow needs.
+    pub registration: &'static str,
+}
+
+/// Endpoints and scope of a provider's OAuth 2.0 sign-in in the browser,
+/// the authorization code grant with the redirect sent to this machine.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct BrowserFlow {
+    pub authorize_url: &'static str,
+    pub token_url: &'static str,
+    pub scope: &'static str,
+    /// How to register the OAuth client the flow needs.
+    pub registration: &'static str,
+}
+
+/// Names a provider gives its special folders, for servers that don't mark
+/// them with SPECIAL-USE attributes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SpecialFolders {
+    pub sent: &'static str,
+    pub drafts: &'static str,
+    pub trash: &'static str,
+    pub junk: &'static str,
+    pub archive: &'static str,
+}
+
+impl Provider {
+    /// Name used on the command line and in accounts files.
+    pub fn name(self) -> &'static str {
+        match self {
+            Provider::Gmail => "gmail",
+            Provider::Outlook => "outlook",
+            Provider::Yahoo => "yahoo",
+            Provider::Fastmail => "fastmail",
+            Provider::ICloud => "icloud",
+            Provider::Generic => "generic",
+        }
+    }
+
+    /// Name of the service, for messages to the user.
+    pub fn display_name(self) -> &'static str {
+        match self {
+            Provider::Gmail => "Gmail",
+            Provider::Outlook => "Outlook",
+            Provider::Yahoo => "Yahoo Mail",
+            Provider::Fastmail => "Fastmail",
+            Provider::ICloud => "iCloud Mail",
+            Provider::Generic => "the mail server",
+        }
+    }
+
+    /// Host of the provider's IMAP server; the generic provider has none.
+    pub fn host(self) -> Option<&'static str> {
+        match self {
+            Provider::Gmail => Some("imap.gmail.com"),
+            Provider::Outlook => Some("outlook.office365.com"),
+            Provider::Yahoo => Some("imap.mail.yahoo.com"),
+            Provider::Fastmail => Some("imap.fastmail.com"),
+            Provider::ICloud => Some("imap.mail.me.com"),
+            Provider::Generic => None,
+        }
+    }
+
+    /// Port of IMAP over TLS, which every preset uses.
+    pub fn port(self) -> u16 {
+        993
+    }
+
+    /// `host:port` of the provider's IMAP server.
+    pub fn server(self) -> Option<String> {
+        self.host().map(|host| format!("{}:{}", host, self.port()))
+    }
+
+    /// Where users generate the app passwords the provider requires for
+    /// IMAP when two-step verification is on.
+    pub fn app_passwords_url(self) -> Option<&'static str> {
+        match self {
+            Provider::Gmail => Some("https://myaccount.google.com/apppasswords"),
+            Provider::Yahoo => Some("https://login.yahoo.com/myaccount/security/app-password"),
+            Provider::Fastmail => Some("https://app.fastmail.com/settings/security/apps"),
+            Provider::ICloud => Some("https://account.apple.com/account/manage"),
+            Provider::Outlook | Provider::Generic => None,
+        }
+    }
+
+    pub fn auth(self) -> AuthMethod {
+        match self {
+            Provider::Outlook => AuthMethod::OAuthDevice(DeviceFlow {
+                device_url: "https://login.microsoftonline.com/common/oauth2/v2.0/devicecode",
+                token_url: "https://login.microsoftonline.com/common/oauth2/v2.0/token",
+                scope: "https://outlook.office.com/IMAP.AccessAsUser.All offline_access",
+                registration: "Register an application under App registrations in the \
+                               Microsoft Entra admin center, add the delegated \
+                               IMAP.AccessAsUser.All permission and enable public client \
+                               flows",
+            }),
+            _ => AuthMethod::Login,
+        }
+    }
+
+    /// How to sign in to the provider in a browser, for those offering
+    /// OAuth for IMAP.
+    pub fn browser_flow(self) -> Option<BrowserFlow> {
+        match self {
+            Provider::Gmail => Some(BrowserFlow {
+                authorize_url: "https://accounts.google.com/o/oauth2/v2/auth",
+                token_url: GOOGLE_TOKEN_URL,
+                scope: "https://mail.google.com/",
+                registration: "Create an OAuth client ID of type Desktop app under APIs & \
+                               Services > Credentials in the Google Cloud console",
+            }),
+            Provider::Outlook => Some(BrowserFlow {
+                authorize_url: "https://login.microsoftonline.com/common/oauth2/v2.0/authorize",
+                token_url: "https://login.microsoftonline.com/common/oauth2/v2.0/token",
+                scope: "https://outlook.office.com/IMAP.AccessAsUser.All offline_access",
+                registration: "Register an application under App registrations in the \
+                               Microsoft Entra admin center, add the delegated \
+                               IMAP.AccessAsUser.All permission and a Mobile and desktop \
+                               applications platform with the redirect URI http://localhost",
+            }),
+            _ => None,
+        }
+    }
+
+    pub fn folders(self) -> SpecialFolders {
+        match self {
+            Provider::Gmail => SpecialFolders {
+                sent: "[Gmail]/Sent Mail",
+                drafts: "[Gmail]/Drafts",
+                trash: "[Gmail]/Trash",
+                junk: "[Gmail]/Spam",
+                archive: "[Gmail]/All Mail",
+            },
+            Provider::Outlook => SpecialFolders {
+                sent: "Sent Items",
+                drafts: "Drafts",
+                trash: "Deleted Items",
+                junk: "Junk Email",
+                archive: "Archive",
+            },
+            Provider::Yahoo => SpecialFolders {
+                sent: "Sent",
+                drafts: "Draft",
+                trash: "Trash",
+                junk: "Bulk Mail",
+                archive: "Archive",
+            },
+            Provider::ICloud => SpecialFolders {
+                sent: "Sent Messages",
+                drafts: "Drafts",
+                trash: "Deleted Messages",
+                junk: "Junk",
+                archive: "Archive",
+            },
+            Provider::Fastmail | Provider::Generic => SpecialFolders {
+                sent: "Sent",
+                drafts: "Drafts",
+                trash: "Trash",
+                junk: "Spam",
+                archive: "Archive",
+            },
+        }
+    }
+}
+
+impl FromStr for Provider {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_ascii_lowercase();
+        match lower.as_str() {
+            "office365" | "microsoft365" => return Ok(Provider::Outlook),
+            "other" => return Ok(Provider::Generic),
+            _ => {}
+        }
+        PROVIDERS
+            .into_iter()
+            .find(|provider| provider.name() == lower)
+            .ok_or_else(|| {
+                ClientError::InvalidArgument(format!(
+                    "Unknown provider `{}` (expected {})",
+                    s,
+                    PROVIDERS.map(Provider::name).join(", ")
+                ))
+            })
+    }
+}
@@ -0,0 +1,246 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use regex::bytes::{Captures, Regex};
+use std::str::FromStr;
+use std::sync::LazyLock;
+
+use crate::error_imap::ClientError;
+use crate::message::Part;
+use crate::processor::{MessageInfo, MessageProcessor};
+
+/// What each redacted match is replaced with.
+pub const REDACTED: &str = "[REDACTED]";
+
+static CARD: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?-u:\b)[0-9](?:[ -]?[0-9]){12,18}(?-u:\b)").unwrap());
+static SSN: LazyLock<Regex> =
+    LazyLock::new(|| Regex::new(r"(?-u:\b)([0-9]{3})-([0-9]{2})-([0-9]{4})(?-u:\b)").unwrap());
+
+/// Something to scrub from message text.
+#[derive(Debug, Clone)]
+pub enum Pattern {
+    /// Payment card numbers: 13 to 19 digits, possibly grouped with spaces or
+    /// dashes, that pass the Luhn check.
+    Cards,
+    /// US Social Security numbers written as `123-45-6789`.
+    Ssn,
+    Custom(Regex),
+}
+
+impl Pattern {
+    fn regex(&self) -> &Regex {
+        match self {
+            Pattern::Cards => &CARD,
+            Pattern::Ssn => &SSN,
+            Pattern::Custom(regex) => regex,
+        }
+    }
+
+    /// Whether a match of the pattern's regex really is what it looks for.
+    fn accepts(&self, captures: &Captures) -> bool {
+        match self {
+            Pattern::Cards => {
+                let digits: Vec<u32> = captures[0]
+                    .iter()
+                    .filter(|b| b.is_ascii_digit())
+                    .map(|b| u32::from(b - b'0'))
+                    .collect();
+                (13..=19).contains(&digits.len()) && luhn(&digits)
+            }
+            // Numbers the SSA never issues
+            Pattern::Ssn => {
+                let area = &captures[1];
+                area != b"000"
+                    && area != b"666"
+                    && area[0] != b'9'
+                    && &captures[2] != b"00"
+                    && &captures[3] != b"0000"
+            }
+            Pattern::Custom(_) => true,
+        }
+    }
+}
+
+fn luhn(digits: &[u32]) -> bool {
+    let sum: u32 = digits
+        .iter()
+        .rev()
+        .enumerate()
+        .map(|(i, &d)| match (i % 2, d * 2) {
+            (0, _) => d,
+            (_, doubled) if doubled > 9 => doubled - 9,
+            (_, doubled) => doubled,
+        })
+        .sum();
+    sum.is_multiple_of(10)
+}
+
+impl FromStr for Pattern {
+    type Err = ClientError;
+
+    /// `cards`, `ssn` or a regular expression between slashes.
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(pattern) = s.strip_prefix('/').and_then(|p| p.strip_suffix('/')) {
+            return Regex::new(pattern).map(Pattern::Custom).map_err(|e| {
+                ClientError::InvalidArgument(format!("Invalid redaction pattern `{}`: {}", s, e))
+            });
+        }
+        match s.to_ascii_lowercase().as_str() {
+            "cards" | "credit-cards" => Ok(Pattern::Cards),
+            "ssn" | "ssns" => Ok(Pattern::Ssn),
+            _ => Err(ClientError::InvalidArgument(format!(
+                "Unknown redaction pattern `{}` (expected cards, ssn or /REGEX/)",
+                s
+            ))),
+        }
+    }
+}
+
+/// Scrubs patterns from the text parts of messages before they are stored,
+/// for archives that are shared. Headers and attachments are left alone.
+///
+/// The catalog still records the hash of each message as downloaded, so a
+/// redacted archive can be matched against the original.
+#[derive(Debug, Clone, Default)]
+pub struct Redactor {
+    patterns: Vec<Pattern>,
+}
+
+impl Redactor {
+    pub fn new(patterns: Vec<Pattern>) -> Self {
+        Redactor { patterns }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.patterns.is_empty()
+    }
+
+    pub fn push(&mut self, pattern: Pattern) {
+        self.patterns.push(pattern);
+    }
+
+    /// `text` with every match replaced by [`REDACTED`], and how many there
+    /// were.
+    pub fn redact_text(&self, text: &[u8]) -> (Vec<u8>, usize) {
+        let mut text = text.to_vec();
+        let mut count = 0;
+        for pattern in &self.patterns {
+            let redacted = pattern
+                .regex()
+                .replace_all(&text, |captures: &Captures| {
+                    if pattern.accepts(captures) {
+                        count += 1;
+                        REDACTED.as_bytes().to_vec()
+                    } else {
+                        captures[0].to_vec()
+                    }
+                })
+                .into_owned();
+            text = redacted;
+        }
+        (text, count)
+    }
+
+    /// The message `raw` with its text parts redacted, or `None` if there was
+    /// nothing to redact. Parts keep their transfer encoding.
+    pub fn redact_message(&self, raw: &[u8]) -> Option<Vec<u8>> {
+        let eol: &[u8] = if raw.windows(2).any(|w| w == b"\r\n") {
+            b"\r\n"
+        } else {
+            b"\n"
+        };
+        let mut edits = Vec::new();
+        Part::parse(raw).walk(&mut |part| {
+            let content_type = part.content_type();
+            if !content_type.mime_type.starts_with("text/") || part.is_attachment() {
+                return;
+            }
+            if let Some(body) = self.redact_part(part, eol) {
+                let start = part.body.as_ptr() as usize - raw.as_ptr() as usize;
+                edits.push((start..start + part.body.len(), body));
+            }
+        });
+        if edits.is_empty() {
+            return None;
+        }
+
+        let mut redacted = raw.to_vec();
+        for (range, body) in edits.into_iter().rev() {
+            redacted.splice(range, body);
+        }
+        Some(redacted)
+    }
+
+    fn redact_part(&self, part: &Part, eol: &[u8]) -> Option<Vec<u8>> {
+        let encoding = part
+            .headers
+            .get("Content-Transfer-Encoding")
+            .unwrap_or("7bit")
+            .trim()
+            .to_ascii_lowercase();
+        let (text, count) = match encoding.as_str() {
+            "base64" | "quoted-printable" => self.redact_text(&part.decoded_body()),
+            _ => self.redact_text(part.body),
+        };
+        if count == 0 {
+            return None;
+        }
+        log::debug!("Redacted {} matches in a {} part", count, encoding);
+        Some(match encoding.as_str() {
+            "base64" => {
+                let mut encoded = STANDARD
+                    .encode(text)
+                    .into_bytes()
+                    .chunks(76)
+                    .collect::<Vec<_>>()
+                    .join(eol);
+                if part.body.ends_with(b"\n") {
+                    encoded.extend_from_slice(eol);
+                }
+                encoded
+            }
+            "quoted-printable" => encode_quoted_printable(&text, eol),
+            _ => text,
+        })
+    }
+}
+
+impl MessageProcessor for Redactor {
+    fn transform(&self, _message: &MessageInfo, body: Vec<u8>) -> Result<Vec<u8>, ClientError> {
+        Ok(self.redact_message(&body).unwrap_or(body))
+    }
+}
+
+/// Quoted-printable encoding (RFC 2045 §6.7) with lines of at most 76
+/// characters, ending in `eol`.
+fn encode_quoted_printable(text: &[u8], eol: &[u8]) -> Vec<u8> {
+    let mut encoded = Vec::with_capacity(text.len());
+    for (index, line) in text.split(|&b| b == b'\n').enumerate() {
+        if index > 0 {
+            encoded.extend_from_slice(eol);
+        }
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let mut width = 0;
+        for (position, &byte) in line.iter().enumerate() {
+            // Trailing whitespace would be stripped in transit
+            let literal = match byte {
+                b' ' | b'\t' => position + 1 < line.len(),
+                b'=' => false,
+                byte => (33..=126).contains(&byte),
+            };
+            let len = if literal { 1 } else { 3 };
+            if width + len > 75 {
+                encoded.push(b'=');
+                encoded.extend_from_slice(eol);
+                width = 0;
+            }
+            if literal {
+                encoded.push(byte);
+            } else {
+                encoded.extend_from_slice(format!("={:02X}", byte).as_bytes());
+            }
+            width += len;
+        }
+    }
+    encoded
+}
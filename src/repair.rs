@@ -0,0 +1,140 @@
+use std::collections::HashSet;
+use std::path::Path;
+
+use crate::catalog::{Catalog, CatalogEntry};
+use crate::dedupe::relative_path;
+use crate::error_imap::ClientError;
+use crate::maildir::INFO_SEPARATOR;
+
+/// What is wrong with a catalogued file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Damage {
+    Missing,
+    /// The file is there but isn't what was saved, for this reason.
+    Corrupt(String),
+}
+
+/// A catalog entry whose file doesn't check out.
+#[derive(Debug, Clone)]
+pub struct Damaged {
+    pub entry: CatalogEntry,
+    pub damage: Damage,
+}
+
+/// What checking an archive against its catalog found, and what repairing
+/// it did.
+#[derive(Debug, Default)]
+pub struct RepairReport {
+    /// Files checked against their catalogued size and hash.
+    pub checked: usize,
+    /// Files that don't hold a message as it was saved, such as PDFs and
+    /// thread mailboxes, and can't be checked.
+    pub unchecked: usize,
+    /// Maildir files a mail program renamed when their flags changed, as
+    /// their old and new paths. The catalog now points at the new ones.
+    pub relocated: Vec<(String, String)>,
+    pub damaged: Vec<Damaged>,
+    /// Paths written again from the server.
+    pub restored: Vec<String>,
+    /// Paths of missing files dropped from the catalog.
+    pub pruned: Vec<String>,
+    /// Paths that couldn't be restored, and why.
+    pub unrecovered: Vec<(String, String)>,
+}
+
+/// Checks the file of every catalog entry under `root` against the size
+/// and SHA-256 recorded when it was saved. Only the latest entry for each
+/// path counts, as a re-fetch may have overwritten the file since. Maildir
+/// files found under another name are relocated in the catalog rather than
+/// reported missing.
+pub fn check_archive(root: &Path, catalog: &mut Catalog) -> Result<RepairReport, ClientError> {
+    let mut report = RepairReport::default();
+    let mut seen = HashSet::new();
+    let entries = catalog.entries().to_vec();
+    for entry in entries.iter().rev() {
+        if !seen.insert(entry.path.as_str()) {
+            continue;
+        }
+        if !is_checkable(Path::new(&entry.path)) {
+            report.unchecked += 1;
+            continue;
+        }
+        report.checked += 1;
+        let damage = if !root.join(&entry.path).exists() {
+            if let Some(moved) = find_renamed(root, entry) {
+                log::info!("{} was renamed {}", entry.path, moved);
+                catalog.relocate(&entry.path, &moved)?;
+                report.relocated.push((entry.path.clone(), moved));
+                continue;
+            }
+            Damage::Missing
+        } else {
+            match entry.verify(root) {
+                Ok(()) => continue,
+                Err(reason) => Damage::Corrupt(reason),
+            }
+        };
+        log::warn!("{}: {:?}", entry.path, damage);
+        report.damaged.push(Damaged {
+            entry: entry.clone(),
+            damage,
+        });
+    }
+    report.damaged.reverse();
+    report.relocated.reverse();
+    Ok(report)
+}
+
+/// Where the file of `entry` is now, relative to `root`: its catalogued
+/// path, or for a Maildir message, wherever a mail program moved it.
+pub fn current_path(root: &Path, entry: &CatalogEntry) -> Option<String> {
+    if root.join(&entry.path).exists() {
+        return Some(entry.path.clone());
+    }
+    find_renamed(root, entry)
+}
+
+/// Whether the file at `path` holds a message exactly as saved: `.eml`
+/// files and Maildir messages do.
+pub(crate) fn is_checkable(path: &Path) -> bool {
+    path.extension().is_some_and(|extension| extension == "eml") || in_maildir(path)
+}
+
+/// Whether `path` is a message in a Maildir's `cur/` or `new/`.
+pub fn in_maildir(path: &Path) -> bool {
+    path.parent()
+        .and_then(Path::file_name)
+        .is_some_and(|dir| dir == "cur" || dir == "new")
+}
+
+/// Where the Maildir message of `entry` went, if a mail program moved it
+/// between `new/` and `cur/` or changed the flags in its name. The file
+/// found must still match the entry.
+fn find_renamed(root: &Path, entry: &CatalogEntry) -> Option<String> {
+    let path = Path::new(&entry.path);
+    if !in_maildir(path) {
+        return None;
+    }
+    let name = path.file_name()?.to_str()?;
+    let unique = name.split(INFO_SEPARATOR).next()?;
+    let maildir = root.join(path.parent()?.parent()?);
+    ["cur", "new"].iter().find_map(|dir| {
+        std::fs::read_dir(maildir.join(dir))
+            .ok()?
+            .flatten()
+            .filter(|file| {
+                file.file_name()
+                    .to_str()
+                    .is_some_and(|name| name.split(INFO_SEPARATOR).next() == Some(unique))
+            })
+            .map(|file| relative_path(root, &file.path()))
+            .find(|moved| {
+                CatalogEntry {
+                    path: moved.clone(),
+                    ..entry.clone()
+                }
+                .verify(root)
+                .is_ok()
+            })
+    })
+}
@@ -0,0 +1,125 @@
+use std::collections::HashMap;
+use std::time::Duration;
+
+/// What replaying a capture found.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct ReplaySummary {
+    /// Responses parsed, of any kind.
+    pub responses: usize,
+    /// FETCH responses carrying a message.
+    pub messages: usize,
+    /// Messages saved, when saving.
+    pub saved: usize,
+    /// Bytes of server responses replayed.
+    pub bytes: usize,
+    /// Time spent in the parser alone.
+    pub parse_time: Duration,
+    /// Time for the whole replay, saving included.
+    pub elapsed: Duration,
+}
+
+impl ReplaySummary {
+    /// How fast the parser went through the capture, in MiB a second.
+    pub fn parse_rate(&self) -> f64 {
+        self.bytes as f64 / (1 << 20) as f64 / self.parse_time.as_secs_f64().max(1e-9)
+    }
+}
+
+/// The bytes the server sent, from `capture`: a trace written with
+/// `--trace-imap`, or raw server responses as they came over the wire, such
+/// as the output of `openssl s_client`.
+///
+/// A trace's connections are replayed one after another. The literals it
+/// cut short are padded with spaces to their announced length, so they
+/// parse as the originals did, though the messages in them are incomplete.
+pub fn server_bytes(capture: &[u8]) -> Vec<u8> {
+    let text = String::from_utf8_lossy(capture);
+    let Some(first) = text.lines().find(|line| !line.is_empty()) else {
+        return Vec::new();
+    };
+    if trace_line(first).is_none() {
+        return capture.to_vec();
+    }
+    let mut order = Vec::new();
+    let mut connections: HashMap<&str, Vec<u8>> = HashMap::new();
+    for (connection, direction, text) in text.lines().filter_map(trace_line) {
+        if direction != "S" {
+            continue;
+        }
+        let bytes = connections.entry(connection).or_insert_with(|| {
+            order.push(connection);
+            Vec::new()
+        });
+        match literal(text) {
+            Some((len, head)) => {
+                bytes.extend_from_slice(&head);
+                bytes.resize(bytes.len() + len.saturating_sub(head.len()), b' ');
+            }
+            None => {
+                bytes.extend_from_slice(&unescape(text));
+                bytes.extend_from_slice(b"\r\n");
+            }
+        }
+    }
+    order
+        .iter()
+        .flat_map(|connection| connections.remove(connection).unwrap_or_default())
+        .collect()
+}
+
+/// The connection, direction and text of a trace line, which reads
+/// `timestamp connection direction: text`.
+fn trace_line(line: &str) -> Option<(&str, &str, &str)> {
+    let mut fields = line.splitn(3, ' ');
+    let timestamp = fields.next()?;
+    let connection = fields.next()?;
+    let rest = fields.next()?;
+    // An empty line sent may have lost the space after its colon
+    let (direction, text) = rest
+        .split_once(": ")
+        .or_else(|| Some((rest.strip_suffix(':')?, "")))?;
+    let (seconds, millis) = timestamp.split_once('.')?;
+    let numeric = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let known = matches!(direction, "C" | "S" | "*");
+    (numeric(seconds) && numeric(millis) && numeric(connection) && known)
+        .then_some((connection, direction, text))
+}
+
+/// The length and traced head of a literal, shown as `<literal of 5120
+/// bytes: head...>`.
+fn literal(text: &str) -> Option<(usize, Vec<u8>)> {
+    let rest = text.strip_prefix("<literal of ")?.strip_suffix('>')?;
+    let (len, head) = rest.split_once(" bytes: ")?;
+    let len = len.parse().ok()?;
+    let mut head = unescape(head);
+    // The marker for a literal cut short, unless the literal ends that way
+    if head.len() < len && head.ends_with(b"...") {
+        head.truncate(head.len() - 3);
+    }
+    Some((len, head))
+}
+
+/// Reverses the escaping of control characters in traced text.
+fn unescape(text: &str) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(text.len());
+    let mut chars = text.chars().peekable();
+    while let Some(c) = chars.next() {
+        let c = match (c, chars.next_if(|_| c == '\\')) {
+            (_, None) => c,
+            (_, Some('r')) => '\r',
+            (_, Some('n')) => '\n',
+            (_, Some('t')) => '\t',
+            (_, Some('x')) => {
+                let hex: String = chars.by_ref().take(2).collect();
+                u32::from_str_radix(&hex, 16)
+                    .ok()
+                    .and_then(char::from_u32)
+                    .unwrap_or(char::REPLACEMENT_CHARACTER)
+            }
+            (_, Some(other)) => other,
+        };
+        let mut buf = [0; 4];
+        bytes.extend_from_slice(c.encode_utf8(&mut buf).as_bytes());
+    }
+    bytes
+}
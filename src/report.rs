@@ -0,0 +1,454 @@
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt::Write as _;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+
+use crate::catalog::Catalog;
+use crate::error_imap::ClientError;
+use crate::failures::{read_failures, Failure, FAILURES_FILE};
+use crate::i18n::{locale, Locale};
+use crate::message::{civil_from_days, parse_addresses, parse_date, Headers};
+use crate::repair::is_checkable;
+
+/// Directory the report is written to, under the archive, unless another
+/// is given.
+pub const REPORT_DIR: &str = "report";
+
+/// Name of the HTML report written to the root of an archive directory.
+pub const HTML_REPORT: &str = "report.html";
+
+/// Name of the record of the last fetch, written to the root of an archive
+/// directory.
+pub const LAST_RUN_FILE: &str = "last-run.json";
+
+/// Bytes read from the start of each file for its headers.
+const HEADER_LIMIT: u64 = 64 * 1024;
+
+/// How the last fetch into an archive went.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RunRecord {
+    pub account: String,
+    /// When it started and ended, as Unix timestamps.
+    pub started: i64,
+    pub finished: i64,
+    pub found: u32,
+    pub saved: u32,
+    pub failed_batches: u32,
+    /// Why the run stopped, if it failed.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// Writes `record` to the root of the archive at `dir`.
+pub fn write_last_run(dir: &Path, record: &RunRecord) -> Result<(), ClientError> {
+    let path = dir.join(LAST_RUN_FILE);
+    let json = serde_json::to_string_pretty(record).map_err(|source| ClientError::Json {
+        path: path.clone(),
+        source,
+    })?;
+    std::fs::write(&path, json + "\n").map_err(|e| ClientError::io(&path, e))?;
+    Ok(())
+}
+
+/// The record of the last fetch into the archive at `dir`, if there was one.
+pub fn read_last_run(dir: &Path) -> Result<Option<RunRecord>, ClientError> {
+    let path = dir.join(LAST_RUN_FILE);
+    if !path.exists() {
+        return Ok(None);
+    }
+    let json = std::fs::read_to_string(&path).map_err(|e| ClientError::io(&path, e))?;
+    serde_json::from_str(&json)
+        .map(Some)
+        .map_err(|source| ClientError::Json { path, source })
+}
+
+/// Messages and bytes sent from, or to, one address.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct AddressCount {
+    pub address: String,
+    pub messages: usize,
+    pub bytes: u64,
+}
+
+/// Messages and bytes received in one month, as `YYYY-MM`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct MonthCount {
+    pub month: String,
+    pub messages: usize,
+    pub bytes: u64,
+}
+
+/// Who an archive's mail is from and to, and how it spreads over time.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize)]
+pub struct Analytics {
+    /// Distinct messages counted; copies in several folders count once.
+    pub messages: usize,
+    pub bytes: u64,
+    /// Catalogued messages left out, as their file is gone or holds more
+    /// than one message.
+    pub skipped: usize,
+    /// The busiest senders, most messages first.
+    pub senders: Vec<AddressCount>,
+    /// The busiest `To` and `Cc` recipients, most messages first.
+    pub recipients: Vec<AddressCount>,
+    /// Every month from the first message to the last, in order, including
+    /// months without any.
+    pub months: Vec<MonthCount>,
+}
+
+/// Counts the messages in the catalog of the archive at `dir` by sender,
+/// recipient and month received, keeping the `top` busiest addresses. The
+/// catalog gives each message's size and date; addresses come from the
+/// headers of its file. Messages without a date from either are left out
+/// of the months only.
+pub fn analyze_archive(dir: &Path, top: usize) -> Result<Analytics, ClientError> {
+    let catalog = Catalog::open(dir)?;
+    let mut analytics = Analytics::default();
+    let mut seen = HashSet::new();
+    let mut senders: HashMap<String, (usize, u64)> = HashMap::new();
+    let mut recipients: HashMap<String, (usize, u64)> = HashMap::new();
+    let mut months: BTreeMap<(i64, u32), (usize, u64)> = BTreeMap::new();
+    for entry in catalog.entries() {
+        let key = match entry.message_id_hash.as_str() {
+            "" => &entry.content_hash,
+            hash => hash,
+        };
+        if !seen.insert(key) {
+            continue;
+        }
+        let headers = is_checkable(Path::new(&entry.path))
+            .then(|| read_headers(&dir.join(&entry.path)))
+            .flatten();
+        let Some(headers) = headers else {
+            analytics.skipped += 1;
+            continue;
+        };
+        analytics.messages += 1;
+        analytics.bytes += entry.size;
+
+        let count = |counts: &mut HashMap<String, (usize, u64)>, address: String| {
+            let count = counts.entry(address).or_default();
+            count.0 += 1;
+            count.1 += entry.size;
+        };
+        if let Some(sender) = headers.sender() {
+            count(&mut senders, sender.email.to_lowercase());
+        }
+        let mut addressed = HashSet::new();
+        for field in ["To", "Cc"] {
+            for recipient in headers.get(field).map(parse_addresses).unwrap_or_default() {
+                let address = recipient.email.to_lowercase();
+                if addressed.insert(address.clone()) {
+                    count(&mut recipients, address);
+                }
+            }
+        }
+        let date = entry
+            .internal_date
+            .or_else(|| headers.get("Date").and_then(parse_date));
+        if let Some(date) = date {
+            let (year, month, _) = civil_from_days(date.div_euclid(86_400));
+            let count = months.entry((year, month)).or_default();
+            count.0 += 1;
+            count.1 += entry.size;
+        }
+    }
+
+    analytics.senders = busiest(senders, top);
+    analytics.recipients = busiest(recipients, top);
+    if let (Some(&first), Some(&last)) = (months.keys().next(), months.keys().next_back()) {
+        let (mut year, mut month) = first;
+        while (year, month) <= last {
+            let (messages, bytes) = months.get(&(year, month)).copied().unwrap_or_default();
+            analytics.months.push(MonthCount {
+                month: format!("{:04}-{:02}", year, month),
+                messages,
+                bytes,
+            });
+            (year, month) = if month == 12 {
+                (year + 1, 1)
+            } else {
+                (year, month + 1)
+            };
+        }
+    }
+    Ok(analytics)
+}
+
+/// The header section of the message in `path`, or `None` if it can't be
+/// read.
+fn read_headers(path: &Path) -> Option<Headers> {
+    let mut start = Vec::new();
+    std::fs::File::open(path)
+        .ok()?
+        .take(HEADER_LIMIT)
+        .read_to_end(&mut start)
+        .ok()?;
+    Some(Headers::parse(&start))
+}
+
+/// The `top` addresses with the most messages, ties broken by address.
+fn busiest(counts: HashMap<String, (usize, u64)>, top: usize) -> Vec<AddressCount> {
+    let mut counts: Vec<AddressCount> = counts
+        .into_iter()
+        .map(|(address, (messages, bytes))| AddressCount {
+            address,
+            messages,
+            bytes,
+        })
+        .collect();
+    counts.sort_by(|a, b| {
+        b.messages
+            .cmp(&a.messages)
+            .then_with(|| a.address.cmp(&b.address))
+    });
+    counts.truncate(top);
+    counts
+}
+
+/// Writes `analytics` to `out` as `senders.csv`, `recipients.csv`,
+/// `months.csv` and `analytics.json`, returning the paths written.
+pub fn write_report(analytics: &Analytics, out: &Path) -> Result<Vec<PathBuf>, ClientError> {
+    std::fs::create_dir_all(out)?;
+    let mut written = Vec::new();
+    let mut write = |name: &str, contents: String| -> Result<(), ClientError> {
+        let path = out.join(name);
+        std::fs::write(&path, contents).map_err(|e| ClientError::io(&path, e))?;
+        written.push(path);
+        Ok(())
+    };
+
+    let addresses = |counts: &[AddressCount]| {
+        let mut csv = String::from("address,messages,bytes\n");
+        for count in counts {
+            let _ = writeln!(
+                csv,
+                "{},{},{}",
+                csv_field(&count.address),
+                count.messages,
+                count.bytes
+            );
+        }
+        csv
+    };
+    write("senders.csv", addresses(&analytics.senders))?;
+    write("recipients.csv", addresses(&analytics.recipients))?;
+    let mut csv = String::from("month,messages,bytes\n");
+    for month in &analytics.months {
+        let _ = writeln!(csv, "{},{},{}", month.month, month.messages, month.bytes);
+    }
+    write("months.csv", csv)?;
+    let json = serde_json::to_string_pretty(analytics).map_err(|source| ClientError::Json {
+        path: out.join("analytics.json"),
+        source,
+    })?;
+    write("analytics.json", json + "\n")?;
+    Ok(written)
+}
+
+/// Writes a self-contained HTML page to `path` showing how the last fetch
+/// into the archive at `dir` went, the messages it failed to fetch, and
+/// `analytics`.
+pub fn write_html_report(
+    dir: &Path,
+    analytics: &Analytics,
+    path: &Path,
+) -> Result<(), ClientError> {
+    let last_run = read_last_run(dir)?;
+    let failures_file = dir.join(FAILURES_FILE);
+    let failures = if failures_file.exists() {
+        read_failures(&failures_file)?
+    } else {
+        Vec::new()
+    };
+    let page = render_html(analytics, last_run.as_ref(), &failures, locale());
+    std::fs::write(path, page)?;
+    Ok(())
+}
+
+/// A value as a CSV field, quoted when it holds a comma, quote or line break.
+fn csv_field(value: &str) -> String {
+    if value.contains([',', '"', '\n', '\r']) {
+        format!("\"{}\"", value.replace('"', "\"\""))
+    } else {
+        value.to_string()
+    }
+}
+
+/// `text` with the characters special to HTML escaped.
+pub fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+const STYLE: &str =
+    "body{font-family:system-ui,sans-serif;margin:2em auto;max-width:60em;color:#222}\
+table{border-collapse:collapse;width:100%;margin-bottom:2em}\
+th,td{text-align:left;padding:.2em .6em;border-bottom:1px solid #ddd}\
+td.n{text-align:right;white-space:nowrap}\
+td.bar{width:40%}\
+.bar div{background:#4a7bd0;height:.9em}";
+
+/// A page with the last run, the failures left from it and the tables and
+/// charts of `analytics`, with its style inline, in the language of `locale`.
+pub fn render_html(
+    analytics: &Analytics,
+    last_run: Option<&RunRecord>,
+    failures: &[Failure],
+    locale: &Locale,
+) -> String {
+    let text = |id: &str| escape_html(&locale.message(id, &[]));
+    let mut html = String::new();
+    let _ = write!(
+        html,
+        "<!DOCTYPE html>\n<html lang=\"{}\"><head><meta charset=\"utf-8\"><title>{}</title>\
+         <style>{}</style></head><body>\n<h1>{}</h1>\n",
+        locale.tag(),
+        text("html-title"),
+        STYLE,
+        text("html-title")
+    );
+
+    let _ = writeln!(html, "<h2>{}</h2>", text("html-last-run"));
+    match last_run {
+        None => {
+            let _ = writeln!(html, "<p>{}</p>", text("html-no-run"));
+        }
+        Some(run) => {
+            let outcome = match (&run.error, run.failed_batches) {
+                (Some(error), _) => {
+                    locale.message("html-outcome-failed", &[("error", error.as_str().into())])
+                }
+                (None, 0) => locale.message("html-outcome-completed", &[]),
+                (None, failed) => {
+                    locale.message("html-outcome-partial", &[("failed", failed.into())])
+                }
+            };
+            let rows = [
+                ("html-account", run.account.clone()),
+                ("html-started", locale.datetime(run.started)),
+                ("html-finished", locale.datetime(run.finished)),
+                ("html-found", locale.number(run.found.into())),
+                ("html-saved", locale.number(run.saved.into())),
+                ("html-outcome", outcome),
+            ];
+            html.push_str("<table>\n");
+            for (name, value) in rows {
+                let _ = writeln!(
+                    html,
+                    "<tr><th>{}</th><td>{}</td></tr>",
+                    text(name),
+                    escape_html(&value)
+                );
+            }
+            html.push_str("</table>\n");
+        }
+    }
+
+    let _ = writeln!(html, "<h2>{}</h2>", text("html-errors"));
+    if failures.is_empty() {
+        let _ = writeln!(html, "<p>{}</p>", text("html-no-errors"));
+    } else {
+        let _ = writeln!(
+            html,
+            "<table><tr><th>{}</th><th>{}</th><th>{}</th><th>{}</th></tr>",
+            text("html-folder"),
+            text("html-messages"),
+            text("html-error"),
+            text("html-server-response")
+        );
+        for failure in failures {
+            let _ = writeln!(
+                html,
+                "<tr><td>{}</td><td class=\"n\">{}</td><td>{} ({})</td><td>{}</td></tr>",
+                escape_html(&failure.mailbox),
+                locale.number(failure.uids.len() as u64),
+                escape_html(&failure.error),
+                escape_html(&failure.error_class),
+                escape_html(failure.server_response.as_deref().unwrap_or_default())
+            );
+        }
+        html.push_str("</table>\n");
+    }
+
+    let _ = writeln!(html, "<h2>{}</h2>", text("html-archive"));
+    html.push_str(&analytics_html(analytics, locale));
+    html.push_str("</body></html>\n");
+    html
+}
+
+/// The sections of a page showing `analytics`: totals, the busiest senders
+/// and recipients, and messages per month.
+fn analytics_html(analytics: &Analytics, locale: &Locale) -> String {
+    let mut html = String::new();
+    let totals = locale.message(
+        "html-totals",
+        &[
+            ("messages", analytics.messages.into()),
+            ("size", locale.size(analytics.bytes).into()),
+        ],
+    );
+    let _ = writeln!(html, "<p>{}</p>", escape_html(&totals));
+    for (title, counts) in [
+        ("html-top-senders", &analytics.senders),
+        ("html-top-recipients", &analytics.recipients),
+    ] {
+        let rows: Vec<(String, usize, u64)> = counts
+            .iter()
+            .map(|count| (count.address.clone(), count.messages, count.bytes))
+            .collect();
+        table(&mut html, locale, title, "html-address", &rows);
+    }
+    let rows: Vec<(String, usize, u64)> = analytics
+        .months
+        .iter()
+        .map(|month| (month.month.clone(), month.messages, month.bytes))
+        .collect();
+    table(&mut html, locale, "html-per-month", "html-month", &rows);
+    html
+}
+
+/// Appends a table of `rows` (label, messages, bytes) with a bar showing
+/// each row's messages against the most, under the messages `title` and
+/// `label`.
+fn table(
+    html: &mut String,
+    locale: &Locale,
+    title: &str,
+    label: &str,
+    rows: &[(String, usize, u64)],
+) {
+    let text = |id: &str| escape_html(&locale.message(id, &[]));
+    let most = rows.iter().map(|row| row.1).max().unwrap_or(0).max(1);
+    let _ = write!(
+        html,
+        "<h2>{}</h2>\n<table><tr><th>{}</th><th>{}</th><th>{}</th><th></th></tr>\n",
+        text(title),
+        text(label),
+        text("html-messages"),
+        text("html-size")
+    );
+    for (name, messages, bytes) in rows {
+        let _ = writeln!(
+            html,
+            "<tr><td>{}</td><td class=\"n\">{}</td><td class=\"n\">{}</td>\
+             <td class=\"bar\"><div style=\"width:{:.1}%\"></div></td></tr>",
+            escape_html(name),
+            locale.number(*messages as u64),
+            locale.size(*bytes),
+            *messages as f64 * 100.0 / most as f64
+        );
+    }
+    html.push_str("</table>\n");
+}
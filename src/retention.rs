@@ -0,0 +1,142 @@
+use std::collections::HashSet;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::catalog::CatalogEntry;
+use crate::error_imap::ClientError;
+use crate::folders::glob_match;
+use crate::repair::is_checkable;
+use crate::rules::parse_age;
+
+/// Which copy of old messages a retention policy deletes.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Scope {
+    /// Files in the local archive.
+    Local,
+    /// Messages on the server, once their archived copy checks out.
+    Server,
+}
+
+/// Deletes messages received longer ago than `max_age` from one side, in
+/// the folders or labels matching `label`.
+///
+/// Written `SCOPE:AGE` or `SCOPE:AGE:LABEL`, as in `server:2y` or
+/// `local:10y:Newsletters/*`, where the scope is `local` or `server`, the
+/// age takes the units of rules (`d`, `w`, `m` or `y`) and the label is a
+/// glob matched case-insensitively, `*` when left out.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RetentionPolicy {
+    pub scope: Scope,
+    /// Seconds since a message was received past which it goes.
+    pub max_age: i64,
+    /// Lowercase glob on the folder or label.
+    pub label: String,
+}
+
+impl RetentionPolicy {
+    pub fn applies_to(&self, mailbox: &str) -> bool {
+        glob_match(&self.label, &mailbox.to_lowercase())
+    }
+
+    /// Messages received before this Unix timestamp are expired at `now`.
+    pub fn cutoff(&self, now: i64) -> i64 {
+        now - self.max_age
+    }
+}
+
+impl FromStr for RetentionPolicy {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = |reason: String| {
+            ClientError::InvalidArgument(format!("Invalid retention policy `{}`: {}", s, reason))
+        };
+        let mut parts = s.splitn(3, ':');
+        let scope = match parts.next().map(str::trim).unwrap_or_default() {
+            scope if scope.eq_ignore_ascii_case("local") => Scope::Local,
+            scope if scope.eq_ignore_ascii_case("server") => Scope::Server,
+            _ => return Err(invalid("expected local or server first".to_string())),
+        };
+        let max_age = parts
+            .next()
+            .ok_or_else(|| invalid("expected an age, as in `server:2y`".to_string()))
+            .and_then(|age| parse_age(age).map_err(invalid))?;
+        let label = parts
+            .next()
+            .map(str::trim)
+            .filter(|label| !label.is_empty());
+        Ok(RetentionPolicy {
+            scope,
+            max_age,
+            label: label.unwrap_or("*").to_lowercase(),
+        })
+    }
+}
+
+/// The first of `policies` deleting `scope` copies in `mailbox`, if any.
+pub fn policy_for<'a>(
+    policies: &'a [RetentionPolicy],
+    scope: Scope,
+    mailbox: &str,
+) -> Option<&'a RetentionPolicy> {
+    policies
+        .iter()
+        .find(|policy| policy.scope == scope && policy.applies_to(mailbox))
+}
+
+/// What retention deleted in one folder.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct RetentionCount {
+    pub mailbox: String,
+    /// Files deleted from the archive.
+    pub local: usize,
+    /// Messages deleted from the server.
+    pub server: usize,
+    /// Expired messages kept on the server because the archive has no copy
+    /// of them that checks out.
+    pub kept: usize,
+}
+
+/// The archived files of the folders in `dirs` (folder name and directory
+/// relative to the archive root) that `policies` expire at `now`, by folder. A file
+/// belongs to the folder with the deepest directory holding it. Only files
+/// of a single message count, and only the latest entry for each path.
+pub fn expired_files(
+    entries: &[CatalogEntry],
+    dirs: &[(String, String)],
+    policies: &[RetentionPolicy],
+    now: i64,
+) -> Vec<(String, Vec<String>)> {
+    let mut expired: Vec<(String, Vec<String>)> = dirs
+        .iter()
+        .map(|(name, _)| (name.clone(), Vec::new()))
+        .collect();
+    let mut seen = HashSet::new();
+    for entry in entries.iter().rev() {
+        let path = Path::new(&entry.path);
+        if !seen.insert(entry.path.as_str()) || !is_checkable(path) {
+            continue;
+        }
+        let Some((owner, _)) = dirs
+            .iter()
+            .enumerate()
+            .filter(|(_, (_, dir))| path.starts_with(dir))
+            .max_by_key(|(_, (_, dir))| dir.len())
+        else {
+            continue;
+        };
+        let policy = policy_for(policies, Scope::Local, &dirs[owner].0);
+        // Messages of unknown age are kept
+        let old = entry
+            .internal_date
+            .zip(policy)
+            .is_some_and(|(date, policy)| date < policy.cutoff(now));
+        if old {
+            expired[owner].1.push(entry.path.clone());
+        }
+    }
+    for (_, paths) in &mut expired {
+        paths.reverse();
+    }
+    expired
+}
@@ -0,0 +1,396 @@
+use regex::Regex;
+use std::fmt;
+use std::path::Path;
+
+use crate::diskspace::parse_size;
+use crate::error_imap::ClientError;
+use crate::folders::glob_match;
+use crate::message::{parse_date, Headers};
+
+/// What to do with a message, as chosen by the first rule it matches.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum Action {
+    #[default]
+    Save,
+    /// Don't download it into the archive; it stays on the server.
+    Skip,
+    /// Save the message's attachments but not the message itself.
+    AttachmentsOnly,
+    /// Save the message, then move it to this folder (a label on Gmail).
+    Move(String),
+    /// Save the message, then delete it from the server.
+    Delete,
+    /// Save the message, then set this flag or keyword on it on the server,
+    /// such as `\Flagged` (starred on Gmail) or `$Invoice`.
+    Flag(String),
+    /// Save the message, then add this Gmail label to it.
+    AddLabel(String),
+}
+
+impl Action {
+    /// Whether the action changes the account on the server.
+    pub fn modifies_account(&self) -> bool {
+        matches!(
+            self,
+            Action::Move(_) | Action::Delete | Action::Flag(_) | Action::AddLabel(_)
+        )
+    }
+}
+
+impl fmt::Display for Action {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Action::Save => write!(f, "save"),
+            Action::Skip => write!(f, "skip"),
+            Action::AttachmentsOnly => write!(f, "attachments-only"),
+            Action::Move(folder) => write!(f, "move:{}", folder),
+            Action::Delete => write!(f, "delete"),
+            Action::Flag(flag) => write!(f, "flag:{}", flag),
+            Action::AddLabel(label) => write!(f, "add-label:{}", label),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Comparison {
+    Above,
+    Below,
+}
+
+impl Comparison {
+    fn holds<T: PartialOrd>(self, value: T, bound: T) -> bool {
+        match self {
+            Comparison::Above => value > bound,
+            Comparison::Below => value < bound,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+enum Condition {
+    /// Glob on the sender's address, case-insensitive.
+    From(String),
+    Subject(Regex),
+    /// Glob on the folder or label the message is fetched from.
+    Label(String),
+    Size(Comparison, u64),
+    /// Seconds since the message was received.
+    Age(Comparison, i64),
+}
+
+/// One line of a rules file.
+#[derive(Debug, Clone)]
+pub struct Rule {
+    conditions: Vec<Condition>,
+    pub action: Action,
+    /// Line of the rules file, for logs.
+    pub line: usize,
+}
+
+/// What rules can match a message on.
+#[derive(Debug, Clone, Copy)]
+pub struct MessageFacts<'a> {
+    pub sender: Option<&'a str>,
+    pub subject: &'a str,
+    /// The folder or label it is fetched from.
+    pub label: &'a str,
+    pub size: u64,
+    /// When it was received (or else sent), as a Unix timestamp.
+    pub date: Option<i64>,
+    pub now: i64,
+}
+
+impl<'a> MessageFacts<'a> {
+    /// Facts of the message with `headers` and `size` bytes in `label`.
+    /// `sender` and `subject` are borrowed from values decoded by the caller.
+    pub fn new(
+        headers: &Headers,
+        sender: Option<&'a str>,
+        subject: &'a str,
+        label: &'a str,
+        size: usize,
+        internal_date: Option<i64>,
+    ) -> Self {
+        MessageFacts {
+            sender,
+            subject,
+            label,
+            size: size as u64,
+            date: internal_date.or_else(|| headers.get("Date").and_then(parse_date)),
+            now: std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .map_or(0, |d| d.as_secs() as i64),
+        }
+    }
+}
+
+impl Rule {
+    fn matches(&self, facts: &MessageFacts) -> bool {
+        self.conditions.iter().all(|condition| match condition {
+            Condition::From(pattern) => facts
+                .sender
+                .is_some_and(|sender| glob_match(pattern, &sender.to_lowercase())),
+            Condition::Subject(regex) => regex.is_match(facts.subject),
+            Condition::Label(pattern) => glob_match(pattern, &facts.label.to_lowercase()),
+            Condition::Size(comparison, bound) => comparison.holds(facts.size, *bound),
+            // Messages of unknown age match neither bound
+            Condition::Age(comparison, bound) => facts
+                .date
+                .is_some_and(|date| comparison.holds(facts.now - date, *bound)),
+        })
+    }
+}
+
+/// Per-message rules, checked in order; the first match decides the action
+/// and messages matching none are saved.
+///
+/// Each non-empty line not starting with `#` is a rule: conditions that must
+/// all hold, `->`, and an action.
+///
+/// ```text
+/// from:*@newsletter.example.com           -> move:Newsletters
+/// subject:/invoice|receipt/ label:INBOX   -> save
+/// size:>25M                               -> skip
+/// from:reports@example.com age:>1y        -> delete
+/// from:scanner@example.com                -> attachments-only
+/// ```
+///
+/// Conditions are `from:GLOB` (the sender's address), `subject:/REGEX/` or
+/// `subject:TEXT` (case-insensitive), `label:GLOB` (the folder or label the
+/// message is fetched from), `size:>N` or `size:<N` (such as `25M`) and
+/// `age:>N` or `age:<N` (in days, or with a `d`, `w`, `m` or `y` suffix; a
+/// month is 30 days). A rule without conditions, `* -> action`, matches every
+/// message. Values containing spaces go in double quotes.
+///
+/// Actions are `save`, `skip`, `attachments-only` (also
+/// `save-attachments-only`), `move:FOLDER` (also `move-to-label:FOLDER`) and
+/// `delete`, `flag:FLAG` (a system flag such as `\Flagged`, or a keyword
+/// such as `$Invoice`) and `add-label:LABEL` (Gmail only). Messages are
+/// moved, deleted, flagged or labelled only once they are in the archive.
+#[derive(Debug, Clone, Default)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+}
+
+impl RuleSet {
+    pub fn load(path: &Path) -> Result<Self, ClientError> {
+        let text = std::fs::read_to_string(path).map_err(|e| {
+            ClientError::InvalidArgument(format!("cannot read {}: {}", path.display(), e))
+        })?;
+        RuleSet::parse(&text, &path.display().to_string())
+    }
+
+    /// Parses the rules in `text`, naming `source` in errors.
+    pub fn parse(text: &str, source: &str) -> Result<Self, ClientError> {
+        let mut rules = Vec::new();
+        for (number, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let invalid = |reason: String| {
+                ClientError::InvalidArgument(format!("{}:{}: {}", source, number + 1, reason))
+            };
+            let (conditions, action) = line
+                .rsplit_once("->")
+                .ok_or_else(|| invalid("expected `conditions -> action`".to_string()))?;
+            rules.push(Rule {
+                conditions: parse_conditions(conditions).map_err(invalid)?,
+                action: parse_action(action.trim()).map_err(invalid)?,
+                line: number + 1,
+            });
+        }
+        Ok(RuleSet { rules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    pub fn rules(&self) -> &[Rule] {
+        &self.rules
+    }
+
+    /// The first rule `facts` match, if any.
+    pub fn matching(&self, facts: &MessageFacts) -> Option<&Rule> {
+        self.rules.iter().find(|rule| rule.matches(facts))
+    }
+
+    /// Whether any rule moves, deletes, flags or labels messages on the server.
+    pub fn modifies_account(&self) -> bool {
+        self.rules.iter().any(|rule| rule.action.modifies_account())
+    }
+}
+
+fn parse_action(action: &str) -> Result<Action, String> {
+    let (name, argument) = match action.split_once(':') {
+        Some((name, argument)) => (name.trim(), Some(unquote(argument.trim()))),
+        None => (action, None),
+    };
+    match (name.to_ascii_lowercase().as_str(), argument) {
+        ("save", None) => Ok(Action::Save),
+        ("skip", None) => Ok(Action::Skip),
+        ("attachments-only" | "save-attachments-only", None) => Ok(Action::AttachmentsOnly),
+        ("delete", None) => Ok(Action::Delete),
+        ("move" | "move-to-label", Some(folder)) if !folder.is_empty() => {
+            Ok(Action::Move(folder.to_string()))
+        }
+        ("move" | "move-to-label", _) => Err("move needs a folder, as in `move:Archive`".into()),
+        ("flag", Some(flag)) => parse_flag(flag).map(Action::Flag),
+        ("flag", None) => Err("flag needs a flag, as in `flag:\\Flagged`".into()),
+        ("add-label", Some(label)) if !label.is_empty() => Ok(Action::AddLabel(label.to_string())),
+        ("add-label", _) => Err("add-label needs a label, as in `add-label:Receipts`".into()),
+        _ => Err(format!(
+            "unknown action `{}` (expected save, skip, attachments-only, move:FOLDER, delete, \
+             flag:FLAG or add-label:LABEL)",
+            action
+        )),
+    }
+}
+
+/// A flag a rule may set: one of the system flags a client can set, other
+/// than `\Deleted` (use `delete`), or a keyword (an IMAP atom).
+pub fn parse_flag(flag: &str) -> Result<String, String> {
+    const SYSTEM: [&str; 4] = ["\\Seen", "\\Answered", "\\Flagged", "\\Draft"];
+    if let Some(system) = SYSTEM.iter().find(|s| s.eq_ignore_ascii_case(flag)) {
+        return Ok(system.to_string());
+    }
+    let is_atom = !flag.is_empty()
+        && flag.bytes().all(|b| {
+            b.is_ascii_graphic()
+                && !matches!(b, b'(' | b')' | b'{' | b'%' | b'*' | b'"' | b'\\' | b']')
+        });
+    if is_atom {
+        Ok(flag.to_string())
+    } else {
+        Err(format!(
+            "invalid flag `{}` (expected \\Seen, \\Answered, \\Flagged, \\Draft or a keyword)",
+            flag
+        ))
+    }
+}
+
+fn parse_conditions(text: &str) -> Result<Vec<Condition>, String> {
+    let mut conditions = Vec::new();
+    for token in tokenize(text)? {
+        if token == "*" {
+            continue;
+        }
+        let (key, value) = token
+            .split_once(':')
+            .ok_or_else(|| format!("expected `key:value`, got `{}`", token))?;
+        let value = unquote(value);
+        let condition = match key.to_ascii_lowercase().as_str() {
+            "from" => Condition::From(value.to_lowercase()),
+            "label" => Condition::Label(value.to_lowercase()),
+            "subject" => Condition::Subject(subject_regex(value)?),
+            "size" => {
+                let (comparison, bound) = comparison(value)?;
+                let bound = parse_size(bound).map_err(|_| format!("invalid size `{}`", bound))?;
+                Condition::Size(comparison, bound)
+            }
+            "age" => {
+                let (comparison, bound) = comparison(value)?;
+                Condition::Age(comparison, parse_age(bound)?)
+            }
+            other => {
+                return Err(format!(
+                    "unknown condition `{}` (expected from, subject, label, size or age)",
+                    other
+                ))
+            }
+        };
+        conditions.push(condition);
+    }
+    Ok(conditions)
+}
+
+/// Splits conditions on whitespace, keeping `"quoted values"` and
+/// `/regular expressions/` whole.
+fn tokenize(text: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut current = String::new();
+    let mut chars = text.trim().chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                if !current.is_empty() {
+                    tokens.push(std::mem::take(&mut current));
+                }
+            }
+            '"' | '/' if current.ends_with(':') => {
+                current.push(c);
+                let mut closed = false;
+                while let Some(next) = chars.next() {
+                    current.push(next);
+                    if next == '\\' {
+                        if let Some(escaped) = chars.next() {
+                            current.push(escaped);
+                        }
+                    } else if next == c {
+                        closed = true;
+                        break;
+                    }
+                }
+                if !closed {
+                    return Err(format!("unterminated {} in `{}`", c, current));
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    if !current.is_empty() {
+        tokens.push(current);
+    }
+    Ok(tokens)
+}
+
+fn unquote(value: &str) -> &str {
+    value
+        .strip_prefix('"')
+        .and_then(|v| v.strip_suffix('"'))
+        .unwrap_or(value)
+}
+
+/// `/regex/` as a case-insensitive regular expression, anything else as text
+/// to look for.
+fn subject_regex(value: &str) -> Result<Regex, String> {
+    let pattern = match value.strip_prefix('/').and_then(|v| v.strip_suffix('/')) {
+        Some(pattern) => pattern.replace("\\/", "/"),
+        None => regex::escape(value),
+    };
+    Regex::new(&format!("(?i){}", pattern)).map_err(|e| format!("invalid subject pattern: {}", e))
+}
+
+fn comparison(value: &str) -> Result<(Comparison, &str), String> {
+    if let Some(bound) = value.strip_prefix('>') {
+        Ok((Comparison::Above, bound))
+    } else if let Some(bound) = value.strip_prefix('<') {
+        Ok((Comparison::Below, bound))
+    } else {
+        Err(format!("expected `>` or `<` before `{}`", value))
+    }
+}
+
+/// Parses an age such as `30d`, `2w`, `6m` or `1y` into seconds. A month is
+/// 30 days and a bare number counts days.
+pub(crate) fn parse_age(value: &str) -> Result<i64, String> {
+    const DAY: i64 = 24 * 60 * 60;
+    let value = value.trim();
+    let split = value
+        .find(|c: char| !c.is_ascii_digit())
+        .unwrap_or(value.len());
+    let (number, unit) = value.split_at(split);
+    let days = match unit.to_ascii_lowercase().as_str() {
+        "" | "d" => 1,
+        "w" => 7,
+        "m" => 30,
+        "y" => 365,
+        _ => return Err(format!("invalid age `{}`", value)),
+    };
+    number
+        .parse::<i64>()
+        .ok()
+        .and_then(|n| n.checked_mul(days * DAY))
+        .ok_or_else(|| format!("invalid age `{}`", value))
+}
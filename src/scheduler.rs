@@ -0,0 +1,161 @@
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant};
+use tokio::sync::{OwnedSemaphorePermit, Semaphore};
+use tokio::time::sleep;
+
+use crate::error_imap::ClientError;
+
+/// Limits shared by every account of a run. Each account still keeps to its
+/// own `max_concurrent`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Limits {
+    /// Connections open at once across all accounts.
+    pub connections: Option<usize>,
+    /// Connections open at once to the accounts of one domain, as Google
+    /// Workspace counts them.
+    pub domain_connections: Option<usize>,
+    /// Bytes per second downloaded across all accounts.
+    pub bandwidth: Option<u64>,
+}
+
+impl Limits {
+    /// These limits, with those not set taken from `defaults`.
+    pub fn or(self, defaults: Limits) -> Limits {
+        Limits {
+            connections: self.connections.or(defaults.connections),
+            domain_connections: self.domain_connections.or(defaults.domain_connections),
+            bandwidth: self.bandwidth.or(defaults.bandwidth),
+        }
+    }
+}
+
+/// Spreads connections and bandwidth across the accounts of a run, so that
+/// backing up a whole domain doesn't open every account's connections at
+/// once. Waiting accounts are served in turn.
+pub struct Scheduler {
+    limits: Limits,
+    connections: Option<Arc<Semaphore>>,
+    domains: Mutex<HashMap<String, Arc<Semaphore>>>,
+    bandwidth: Option<Mutex<Bucket>>,
+}
+
+/// Slots taken from the run's and the domain's limits, given back on drop.
+pub struct Permit {
+    _domain: Option<OwnedSemaphorePermit>,
+    _run: Option<OwnedSemaphorePermit>,
+}
+
+/// Bytes that may be downloaded now; negative when downloads are ahead of
+/// the rate.
+struct Bucket {
+    available: f64,
+    updated: Instant,
+}
+
+impl Scheduler {
+    pub fn new(limits: Limits) -> Arc<Self> {
+        Arc::new(Scheduler {
+            connections: limits
+                .connections
+                .map(|n| Arc::new(Semaphore::new(n.max(1)))),
+            domains: Mutex::new(HashMap::new()),
+            bandwidth: limits.bandwidth.map(|rate| {
+                Mutex::new(Bucket {
+                    available: rate as f64,
+                    updated: Instant::now(),
+                })
+            }),
+            limits,
+        })
+    }
+
+    pub fn limits(&self) -> &Limits {
+        &self.limits
+    }
+
+    /// Whether connections, and not just bandwidth, are limited.
+    pub fn limits_connections(&self) -> bool {
+        self.limits.connections.is_some() || self.limits.domain_connections.is_some()
+    }
+
+    /// Waits for a connection slot for an account of `domain`. The domain's
+    /// slot is taken first, so accounts of a busy domain don't hold slots
+    /// other domains could use.
+    pub async fn connection(&self, domain: &str) -> Result<Permit, ClientError> {
+        let domain = match self.domain(domain) {
+            Some(semaphore) => Some(acquire(semaphore).await?),
+            None => None,
+        };
+        let run = match &self.connections {
+            Some(semaphore) => Some(acquire(Arc::clone(semaphore)).await?),
+            None => None,
+        };
+        Ok(Permit {
+            _domain: domain,
+            _run: run,
+        })
+    }
+
+    /// Accounts for `bytes` just downloaded, waiting as long as the
+    /// bandwidth limit needs to catch up. Up to a second's worth of bytes
+    /// may go through at once.
+    pub async fn transfer(&self, bytes: usize) {
+        let (Some(bucket), Some(rate)) = (&self.bandwidth, self.limits.bandwidth) else {
+            return;
+        };
+        let rate = rate.max(1) as f64;
+        let wait = {
+            let mut bucket = lock(bucket);
+            let now = Instant::now();
+            let refill = now.duration_since(bucket.updated).as_secs_f64() * rate;
+            bucket.available = (bucket.available + refill).min(rate) - bytes as f64;
+            bucket.updated = now;
+            -bucket.available / rate
+        };
+        if wait > 0.0 {
+            sleep(Duration::from_secs_f64(wait)).await;
+        }
+    }
+
+    fn domain(&self, domain: &str) -> Option<Arc<Semaphore>> {
+        let limit = self.limits.domain_connections?;
+        let mut domains = lock(&self.domains);
+        let semaphore = domains
+            .entry(domain.to_ascii_lowercase())
+            .or_insert_with(|| Arc::new(Semaphore::new(limit.max(1))));
+        Some(Arc::clone(semaphore))
+    }
+}
+
+/// The state is only counters, so a panicked holder shouldn't stop anyone.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+async fn acquire(semaphore: Arc<Semaphore>) -> Result<OwnedSemaphorePermit, ClientError> {
+    semaphore
+        .acquire_owned()
+        .await
+        .map_err(|e| ClientError::JoinError(Box::new(e)))
+}
+
+/// The domain of `email`, which Workspace limits are counted by.
+pub fn domain_of(email: &str) -> &str {
+    email.rsplit_once('@').map_or(email, |(_, domain)| domain)
+}
+
+/// Parses a bandwidth such as `500K` or `10M` per second, in bytes.
+pub fn parse_bandwidth(value: &str) -> Result<u64, ClientError> {
+    let size = value
+        .strip_suffix("/s")
+        .or_else(|| value.strip_suffix("/S"))
+        .unwrap_or(value);
+    match crate::diskspace::parse_size(size) {
+        Ok(0) | Err(_) => Err(ClientError::InvalidArgument(format!(
+            "Invalid bandwidth: {} (expected bytes per second, as in 500K or 10M)",
+            value
+        ))),
+        Ok(bytes) => Ok(bytes),
+    }
+}
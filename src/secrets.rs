@@ -0,0 +1,39 @@
+use crate::error_imap::ClientError;
+
+/// Service the refresh tokens are filed under in the system keyring, with
+/// the account's address as the user.
+pub const KEYRING_SERVICE: &str = "imap_client";
+
+/// Keeps `account`'s OAuth refresh token in the system keyring, replacing
+/// any kept before.
+#[cfg(feature = "keyring")]
+pub fn store_refresh_token(account: &str, token: &str) -> Result<(), ClientError> {
+    keyring::Entry::new(KEYRING_SERVICE, account)
+        .and_then(|entry| entry.set_password(token))
+        .map_err(ClientError::keyring)
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn store_refresh_token(_account: &str, _token: &str) -> Result<(), ClientError> {
+    Err(ClientError::KeyringError {
+        message: "not available in this build; rebuild with --features keyring".to_string(),
+        source: None,
+    })
+}
+
+/// `account`'s OAuth refresh token from the system keyring, if one is kept
+/// there.
+#[cfg(feature = "keyring")]
+pub fn load_refresh_token(account: &str) -> Result<Option<String>, ClientError> {
+    let entry = keyring::Entry::new(KEYRING_SERVICE, account).map_err(ClientError::keyring)?;
+    match entry.get_password() {
+        Ok(token) => Ok(Some(token)),
+        Err(keyring::Error::NoEntry) => Ok(None),
+        Err(e) => Err(ClientError::keyring(e)),
+    }
+}
+
+#[cfg(not(feature = "keyring"))]
+pub fn load_refresh_token(_account: &str) -> Result<Option<String>, ClientError> {
+    Ok(None)
+}
@@ -0,0 +1,918 @@
+use bytes::{Buf, BytesMut};
+use memchr::memmem;
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+use crate::audit::AuditLog;
+use crate::error_imap::ClientError;
+use crate::folders::encode_mailbox_name;
+use crate::journal::{is_journaled, Journal};
+use crate::trace::WireTrace;
+
+/// Generates unique command tags (`A0001`, `A0002`, ...) for one session.
+pub struct TagGenerator {
+    prefix: char,
+    counter: u32,
+}
+
+impl TagGenerator {
+    pub fn new(prefix: char) -> Self {
+        TagGenerator { prefix, counter: 0 }
+    }
+
+    pub fn next_tag(&mut self) -> String {
+        self.counter += 1;
+        format!("{}{:04}", self.prefix, self.counter)
+    }
+}
+
+impl Default for TagGenerator {
+    fn default() -> Self {
+        Self::new('A')
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    No,
+    Bad,
+}
+
+/// Tagged completion of a previously issued command.
+#[derive(Debug, Clone)]
+pub struct Completion {
+    pub tag: String,
+    pub command: String,
+    pub status: Status,
+    pub text: String,
+}
+
+/// One argument of a command line: raw protocol text, a string value that is
+/// quoted (or sent as a literal when it cannot be quoted), or a literal whose
+/// bytes are sent as `{n}` (synchronizing) or `{n+}` (LITERAL+) as the server allows.
+#[derive(Debug, Clone, Copy)]
+pub enum Arg<'a> {
+    Raw(&'a str),
+    String(&'a str),
+    Literal(&'a [u8]),
+}
+
+/// Encodes `value` as an IMAP quoted string, escaping `\` and `"`. Returns
+/// `None` when the value cannot appear in a quoted string (non-ASCII, CR, LF
+/// or NUL) and has to be sent as a literal instead.
+pub fn quote_string(value: &str) -> Option<String> {
+    if value
+        .bytes()
+        .any(|b| !b.is_ascii() || matches!(b, b'\r' | b'\n' | 0))
+    {
+        return None;
+    }
+
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        if c == '"' || c == '\\' {
+            quoted.push('\\');
+        }
+        quoted.push(c);
+    }
+    quoted.push('"');
+    Some(quoted)
+}
+
+/// Commands that change a mailbox or its messages, or (SELECT) open it in a
+/// mode where fetching can.
+const MODIFYING_COMMANDS: [&str; 15] = [
+    "SELECT",
+    "STORE",
+    "EXPUNGE",
+    "APPEND",
+    "COPY",
+    "MOVE",
+    "CREATE",
+    "DELETE",
+    "RENAME",
+    "SUBSCRIBE",
+    "UNSUBSCRIBE",
+    "SETACL",
+    "DELETEACL",
+    "SETQUOTA",
+    "SETMETADATA",
+];
+
+/// Whether `command` (without its tag) could modify the account: one of
+/// [`MODIFYING_COMMANDS`], or a FETCH of message contents without `.PEEK`,
+/// which sets `\Seen`.
+pub fn modifies_mailbox(command: &str) -> bool {
+    let mut words = command.split_whitespace();
+    let mut name = words.next().unwrap_or_default().to_ascii_uppercase();
+    if name == "UID" {
+        name = words.next().unwrap_or_default().to_ascii_uppercase();
+    }
+    if MODIFYING_COMMANDS.contains(&name.as_str()) {
+        return true;
+    }
+    name == "FETCH"
+        && words.any(|word| {
+            let item = word.trim_matches(['(', ')']).to_ascii_uppercase();
+            item.starts_with("BODY[")
+                || item.starts_with("BINARY[")
+                || item == "RFC822"
+                || item == "RFC822.TEXT"
+        })
+}
+
+/// `args` as text for logging, with credentials masked and literal
+/// contents left out.
+fn command_text(args: &[Arg<'_>]) -> String {
+    // The password of LOGIN and the initial response of AUTHENTICATE
+    let secret = matches!(args.first(), Some(Arg::Raw(raw))
+        if raw.eq_ignore_ascii_case("LOGIN") || raw.eq_ignore_ascii_case("AUTHENTICATE"));
+    args.iter()
+        .enumerate()
+        .map(|(i, arg)| match arg {
+            _ if secret && i == 2 => "***".to_string(),
+            Arg::Raw(raw) => raw.to_string(),
+            Arg::String(value) => {
+                quote_string(value).unwrap_or_else(|| format!("{{{}}}", value.len()))
+            }
+            Arg::Literal(bytes) => format!("{{{}}}", bytes.len()),
+        })
+        .collect::<Vec<_>>()
+        .join(" ")
+}
+
+/// Largest literal that may be sent non-synchronizing under LITERAL- (RFC 7888).
+const LITERAL_MINUS_MAX: usize = 4096;
+
+#[derive(Debug)]
+pub enum Response {
+    Untagged(String),
+    Continuation(String),
+    Tagged(Completion),
+}
+
+/// An IMAP session over any async byte stream. Every command is given a fresh
+/// tag, and tagged responses are correlated back to the command that issued them.
+pub struct Session<S> {
+    stream: S,
+    tags: TagGenerator,
+    pending: HashMap<String, String>,
+    /// Bytes received and not yet handed out.
+    read_buf: BytesMut,
+    /// How much is read from the connection at once.
+    read_size: usize,
+    /// `[ALERT]` texts received, possibly shared with other sessions.
+    alerts: Arc<Mutex<Vec<String>>>,
+    capabilities: HashSet<String>,
+    /// Whether UTF8=ACCEPT is enabled, so mailbox names and strings are UTF-8.
+    utf8: bool,
+    read_only: bool,
+    /// Where sent commands are logged, with this connection's number.
+    audit: Option<(Arc<AuditLog>, u32)>,
+    /// Where commands changing messages are journaled before they are sent,
+    /// with this connection's number.
+    journal: Option<(Arc<Journal>, u32)>,
+    /// Where every line sent and received is traced, with this connection's
+    /// number.
+    trace: Option<(Arc<WireTrace>, u32)>,
+    /// The mailbox last selected or examined, as sent.
+    selected: String,
+    /// Tags of journaled commands still waiting for their reply.
+    journaled: HashSet<String>,
+    /// The response the connection was lost in the middle of a literal of.
+    interrupted: Option<Interrupted>,
+}
+
+/// A response cut off partway through a literal.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Interrupted {
+    /// What was read before the literal, up to its `{len}` announcement.
+    pub response: Vec<u8>,
+    /// The part of the literal that arrived.
+    pub received: Vec<u8>,
+    /// The literal's announced length.
+    pub len: usize,
+}
+
+impl<S: AsyncRead + AsyncWrite + Unpin> Session<S> {
+    pub fn new(stream: S) -> Self {
+        Session {
+            stream,
+            tags: TagGenerator::default(),
+            pending: HashMap::new(),
+            read_buf: BytesMut::new(),
+            read_size: READ_BUFFER,
+            alerts: Arc::default(),
+            capabilities: HashSet::new(),
+            utf8: false,
+            read_only: false,
+            audit: None,
+            journal: None,
+            trace: None,
+            selected: String::new(),
+            journaled: HashSet::new(),
+            interrupted: None,
+        }
+    }
+
+    /// Refuses, from now on, to send any command that could modify the
+    /// account (see [`modifies_mailbox`]).
+    pub fn set_read_only(&mut self, read_only: bool) {
+        self.read_only = read_only;
+    }
+
+    /// Reads up to `size` bytes from the connection at once, instead of
+    /// [`READ_BUFFER`].
+    pub fn set_read_buffer(&mut self, size: usize) {
+        self.read_size = size.max(MIN_READ_BUFFER);
+    }
+
+    /// Logs every command sent from now on to `log`, as connection `connection`.
+    pub fn set_audit(&mut self, log: Arc<AuditLog>, connection: u32) {
+        self.audit = Some((log, connection));
+    }
+
+    /// Journals every command changing messages from now on to `journal`, as
+    /// connection `connection`.
+    pub fn set_journal(&mut self, journal: Arc<Journal>, connection: u32) {
+        self.journal = Some((journal, connection));
+    }
+
+    /// Traces every line sent and received from now on to `trace`, as
+    /// connection `connection`.
+    pub fn set_trace(&mut self, trace: Arc<WireTrace>, connection: u32) {
+        self.trace = Some((trace, connection));
+    }
+
+    /// Records `event` against this connection if it is audited.
+    pub fn audit(&self, event: &str) -> Result<(), ClientError> {
+        match &self.audit {
+            Some((log, connection)) => log.record(*connection, event),
+            None => Ok(()),
+        }
+    }
+
+    /// Records the capabilities advertised so far if the connection is audited.
+    pub fn audit_capabilities(&self) -> Result<(), ClientError> {
+        if self.audit.is_none() || self.capabilities.is_empty() {
+            return Ok(());
+        }
+        let mut capabilities: Vec<&str> = self.capabilities.iter().map(String::as_str).collect();
+        capabilities.sort_unstable();
+        self.audit(&format!("capabilities {}", capabilities.join(" ")))
+    }
+
+    /// Whether the server has advertised `capability` (case-insensitive).
+    pub fn has_capability(&self, capability: &str) -> bool {
+        self.capabilities.contains(&capability.to_ascii_uppercase())
+    }
+
+    /// Capabilities advertised so far, from the greeting, response codes or an
+    /// explicit CAPABILITY command.
+    pub fn capabilities(&self) -> &HashSet<String> {
+        &self.capabilities
+    }
+
+    /// Issues CAPABILITY unless the server already advertised its capabilities.
+    pub async fn ensure_capabilities(&mut self) -> Result<(), ClientError> {
+        if self.capabilities.is_empty() {
+            self.execute("CAPABILITY").await?;
+        }
+        Ok(())
+    }
+
+    /// Enables UTF8=ACCEPT (RFC 6855) if the server supports it. From then on
+    /// mailbox names are UTF-8 instead of modified UTF-7, and strings may hold
+    /// UTF-8 without a CHARSET. Returns whether it is enabled.
+    pub async fn enable_utf8(&mut self) -> Result<bool, ClientError> {
+        if self.utf8 {
+            return Ok(true);
+        }
+        self.ensure_capabilities().await?;
+        if !self.has_capability("ENABLE") || !self.has_capability("UTF8=ACCEPT") {
+            return Ok(false);
+        }
+        let untagged = self.execute("ENABLE UTF8=ACCEPT").await?;
+        self.utf8 = untagged.iter().any(|line| {
+            let mut words = line.split_whitespace();
+            words
+                .next()
+                .is_some_and(|word| word.eq_ignore_ascii_case("ENABLED"))
+                && words.any(|word| word.eq_ignore_ascii_case("UTF8=ACCEPT"))
+        });
+        Ok(self.utf8)
+    }
+
+    pub fn utf8_enabled(&self) -> bool {
+        self.utf8
+    }
+
+    /// `name` as it has to be sent: UTF-8 once UTF8=ACCEPT is enabled, modified
+    /// UTF-7 before.
+    pub fn mailbox_name(&self, name: &str) -> String {
+        if self.utf8 {
+            name.to_string()
+        } else {
+            encode_mailbox_name(name)
+        }
+    }
+
+    /// Collects `[ALERT]`s into `alerts` from now on, so that those of all
+    /// of a run's sessions can be shown together.
+    pub fn share_alerts(&mut self, alerts: Arc<Mutex<Vec<String>>>) {
+        self.alerts = alerts;
+    }
+
+    /// `[ALERT]` texts received so far, in arrival order and each once.
+    pub fn alerts(&self) -> Vec<String> {
+        self.alerts
+            .lock()
+            .map(|alerts| alerts.clone())
+            .unwrap_or_default()
+    }
+
+    /// Reads the untagged server greeting sent on connect.
+    pub async fn read_greeting(&mut self) -> Result<String, ClientError> {
+        let line = self.read_line().await?;
+        let greeting = String::from_utf8_lossy(&line).trim_end().to_string();
+        if let Some(rest) = greeting.strip_prefix("* ") {
+            self.inspect_untagged(rest)?;
+        }
+        Ok(greeting)
+    }
+
+    /// Sends `command` under a newly generated tag and returns that tag.
+    pub async fn send(&mut self, command: &str) -> Result<String, ClientError> {
+        self.send_args(&[Arg::Raw(command)]).await
+    }
+
+    /// Sends a command built from space-separated `args` under a newly
+    /// generated tag and returns that tag. Literals are sent non-synchronizing
+    /// when LITERAL+ (or LITERAL- for small literals) is advertised; otherwise
+    /// the server's `+` continuation is awaited before the literal bytes.
+    pub async fn send_args(&mut self, args: &[Arg<'_>]) -> Result<String, ClientError> {
+        let text = command_text(args);
+        if self.read_only && modifies_mailbox(&text) {
+            self.audit(&format!("refused {}", text))?;
+            return Err(ClientError::ReadOnly(text));
+        }
+
+        let tag = self.tags.next_tag();
+        self.audit(&format!("send {} {}", tag, text))?;
+        if let Some((trace, connection)) = &self.trace {
+            trace.sent(*connection, &format!("{} {}", tag, text));
+        }
+        let name = match args.first() {
+            Some(Arg::Raw(raw)) => raw.split_whitespace().next().unwrap_or_default(),
+            _ => "",
+        };
+        if name.eq_ignore_ascii_case("SELECT") || name.eq_ignore_ascii_case("EXAMINE") {
+            self.selected = text
+                .split_once(' ')
+                .map(|(_, mailbox)| mailbox.to_string())
+                .unwrap_or_default();
+        }
+        if let Some((journal, connection)) = &self.journal {
+            if is_journaled(&text) {
+                journal.record(*connection, &tag, "begin", &self.selected, &text)?;
+                self.journaled.insert(tag.clone());
+            }
+        }
+        self.pending.insert(tag.clone(), name.to_ascii_uppercase());
+
+        let mut line = tag.clone().into_bytes();
+        for arg in args {
+            line.push(b' ');
+            let literal = match arg {
+                Arg::Raw(raw) => {
+                    line.extend_from_slice(raw.as_bytes());
+                    None
+                }
+                Arg::String(value) => match quote_string(value) {
+                    Some(quoted) => {
+                        line.extend_from_slice(quoted.as_bytes());
+                        None
+                    }
+                    None => Some(value.as_bytes()),
+                },
+                Arg::Literal(bytes) => Some(*bytes),
+            };
+            if let Some(bytes) = literal {
+                if self.literal_plus_allowed(bytes.len()) {
+                    line.extend_from_slice(format!("{{{}+}}\r\n", bytes.len()).as_bytes());
+                } else {
+                    line.extend_from_slice(format!("{{{}}}\r\n", bytes.len()).as_bytes());
+                    self.stream.write_all(&line).await?;
+                    self.stream.flush().await?;
+                    line.clear();
+                    self.wait_for_continuation(&tag).await?;
+                }
+                line.extend_from_slice(bytes);
+            }
+        }
+        line.extend_from_slice(b"\r\n");
+        self.stream.write_all(&line).await?;
+        self.stream.flush().await?;
+        Ok(tag)
+    }
+
+    fn literal_plus_allowed(&self, len: usize) -> bool {
+        self.has_capability("LITERAL+")
+            || (self.has_capability("LITERAL-") && len <= LITERAL_MINUS_MAX)
+    }
+
+    async fn wait_for_continuation(&mut self, tag: &str) -> Result<(), ClientError> {
+        loop {
+            match self.next_response().await? {
+                Response::Continuation(_) => return Ok(()),
+                Response::Untagged(_) => {}
+                Response::Tagged(completion) => {
+                    if completion.tag == tag {
+                        return Err(ClientError::ImapError(format!(
+                            "{} rejected literal: {}",
+                            completion.command, completion.text
+                        )));
+                    }
+                }
+            }
+        }
+    }
+
+    /// Reads responses until the completion for `tag` arrives, returning the
+    /// untagged lines received in the meantime alongside the completion.
+    pub async fn wait_for(&mut self, tag: &str) -> Result<(Vec<String>, Completion), ClientError> {
+        let mut untagged = Vec::new();
+        loop {
+            match self.next_response().await? {
+                Response::Untagged(line) => untagged.push(line),
+                Response::Continuation(_) => {}
+                Response::Tagged(completion) => {
+                    if completion.tag == tag {
+                        return Ok((untagged, completion));
+                    }
+                    log::debug!(
+                        "Completion for {} ({}) while waiting for {}",
+                        completion.tag,
+                        completion.command,
+                        tag
+                    );
+                }
+            }
+        }
+    }
+
+    /// Authenticates with SASL XOAUTH2 and an OAuth 2.0 access token, sent as
+    /// the initial response. A rejected token is answered with a challenge
+    /// holding the error details, which must be acknowledged with an empty
+    /// line before the server sends its tagged `NO`.
+    pub async fn authenticate_xoauth2(
+        &mut self,
+        user: &str,
+        token: &str,
+    ) -> Result<Completion, ClientError> {
+        use base64::Engine;
+        let response = base64::engine::general_purpose::STANDARD
+            .encode(format!("user={}\x01auth=Bearer {}\x01\x01", user, token));
+        let tag = self
+            .send_args(&[
+                Arg::Raw("AUTHENTICATE"),
+                Arg::Raw("XOAUTH2"),
+                Arg::Raw(&response),
+            ])
+            .await?;
+        loop {
+            match self.next_response().await? {
+                Response::Continuation(challenge) => {
+                    log::debug!("XOAUTH2 challenge: {}", challenge);
+                    if let Some((trace, connection)) = &self.trace {
+                        trace.sent(*connection, "");
+                    }
+                    self.stream.write_all(b"\r\n").await?;
+                    self.stream.flush().await?;
+                }
+                Response::Untagged(_) => {}
+                Response::Tagged(completion) if completion.tag == tag => return Ok(completion),
+                Response::Tagged(_) => {}
+            }
+        }
+    }
+
+    /// Sends `command` and waits for it to complete, failing unless the server
+    /// answers `OK`.
+    pub async fn execute(&mut self, command: &str) -> Result<Vec<String>, ClientError> {
+        self.execute_args(&[Arg::Raw(command)]).await
+    }
+
+    /// Like [`Session::execute`], for a command built from `args`.
+    pub async fn execute_args(&mut self, args: &[Arg<'_>]) -> Result<Vec<String>, ClientError> {
+        let tag = self.send_args(args).await?;
+        let (untagged, completion) = self.wait_for(&tag).await?;
+        match completion.status {
+            Status::Ok => Ok(untagged),
+            _ => Err(ClientError::CommandFailed {
+                tag: completion.tag,
+                command: completion.command,
+                text: completion.text,
+            }),
+        }
+    }
+
+    /// Reads and classifies the next response. Literals embedded in the
+    /// response (e.g. a mailbox name in a LIST reply) are inlined as quoted
+    /// strings so the result is a single line.
+    pub async fn next_response(&mut self) -> Result<Response, ClientError> {
+        let mut line = String::new();
+        loop {
+            let raw = self.read_line().await?;
+            line.push_str(String::from_utf8_lossy(&raw).trim_end());
+            match trailing_literal_len(&line) {
+                Some((len, start)) => {
+                    line.truncate(start);
+                    let literal = self.read_literal(len).await?;
+                    line.push('"');
+                    for c in String::from_utf8_lossy(&literal).chars() {
+                        if c == '"' || c == '\\' {
+                            line.push('\\');
+                        }
+                        line.push(c);
+                    }
+                    line.push('"');
+                }
+                None => return self.classify(line),
+            }
+        }
+    }
+
+    /// Classifies an already-read response line, resolving tagged completions
+    /// against the pending command table. An untagged `BYE` is returned as
+    /// [`ClientError::ServerBye`] so callers never wait for a tag that will not come.
+    pub fn classify(&mut self, line: String) -> Result<Response, ClientError> {
+        if let Some(rest) = line.strip_prefix("* ") {
+            self.inspect_untagged(rest)?;
+            return Ok(Response::Untagged(rest.to_string()));
+        }
+        if let Some(rest) = line.strip_prefix('+') {
+            return Ok(Response::Continuation(rest.trim_start().to_string()));
+        }
+
+        let mut parts = line.splitn(3, ' ');
+        let tag = parts.next().unwrap_or_default().to_string();
+        let status = match parts.next().map(|s| s.to_ascii_uppercase()).as_deref() {
+            Some("OK") => Status::Ok,
+            Some("NO") => Status::No,
+            Some("BAD") => Status::Bad,
+            _ => {
+                return Err(ClientError::ImapError(format!(
+                    "Malformed response line: {}",
+                    line
+                )))
+            }
+        };
+        let text = parts.next().unwrap_or_default().to_string();
+        self.record_capability_code(&text);
+        if self.journaled.remove(&tag) {
+            if let Some((journal, connection)) = &self.journal {
+                let event = match status {
+                    Status::Ok => "ok",
+                    Status::No => "no",
+                    Status::Bad => "bad",
+                };
+                journal.record(*connection, &tag, event, &self.selected, &text)?;
+            }
+        }
+
+        match self.pending.remove(&tag) {
+            Some(command) => Ok(Response::Tagged(Completion {
+                tag,
+                command,
+                status,
+                text,
+            })),
+            None => Err(ClientError::ImapError(format!(
+                "Response for unknown tag {}: {}",
+                tag, line
+            ))),
+        }
+    }
+
+    fn inspect_untagged(&mut self, rest: &str) -> Result<(), ClientError> {
+        let (status, text) = rest.split_once(' ').unwrap_or((rest, ""));
+        let status = status.to_ascii_uppercase();
+
+        if status == "BYE" {
+            return Err(ClientError::ServerBye(text.to_string()));
+        }
+        if status == "CAPABILITY" {
+            self.set_capabilities(text);
+            return Ok(());
+        }
+        if !matches!(status.as_str(), "OK" | "NO" | "BAD") {
+            return Ok(());
+        }
+        self.record_capability_code(text);
+        // Such as the COPYUID of a MOVE, which comes before its reply
+        if let (Some((journal, connection)), true) = (&self.journal, text.starts_with('[')) {
+            for tag in &self.journaled {
+                journal.record(*connection, tag, "code", &self.selected, text)?;
+            }
+        }
+
+        if let Some(alert) = strip_response_code(text, "ALERT") {
+            log::warn!("Server alert: {}", alert);
+            if let Ok(mut alerts) = self.alerts.lock() {
+                if !alerts.iter().any(|seen| seen == alert) {
+                    alerts.push(alert.to_string());
+                }
+            }
+        } else if status != "OK" {
+            log::warn!("Server warning: {} {}", status, text);
+        }
+        Ok(())
+    }
+
+    fn record_capability_code(&mut self, text: &str) {
+        if let Some(inner) = text.strip_prefix('[') {
+            if let Some(close) = inner.find(']') {
+                let mut words = inner[..close].splitn(2, ' ');
+                if words
+                    .next()
+                    .is_some_and(|w| w.eq_ignore_ascii_case("CAPABILITY"))
+                {
+                    self.set_capabilities(words.next().unwrap_or_default());
+                }
+            }
+        }
+    }
+
+    fn set_capabilities(&mut self, list: &str) {
+        self.capabilities = list
+            .split_whitespace()
+            .map(|c| c.to_ascii_uppercase())
+            .collect();
+    }
+
+    /// Reads one CRLF-terminated line (terminator included).
+    pub async fn read_line(&mut self) -> Result<Vec<u8>, ClientError> {
+        let mut line = Vec::new();
+        self.read_line_into(&mut line).await?;
+        Ok(line)
+    }
+
+    /// Appends one CRLF-terminated line (terminator included) to `out`.
+    async fn read_line_into(&mut self, out: &mut Vec<u8>) -> Result<(), ClientError> {
+        // Where to look for the CRLF from, past what was already searched
+        let mut scanned = 0;
+        loop {
+            if let Some(pos) = memmem::find(&self.read_buf[scanned..], b"\r\n") {
+                let end = scanned + pos + 2;
+                if let Some((trace, connection)) = &self.trace {
+                    trace.received(*connection, &self.read_buf[..end]);
+                }
+                out.extend_from_slice(&self.read_buf[..end]);
+                self.read_buf.advance(end);
+                return Ok(());
+            }
+            scanned = self.read_buf.len().saturating_sub(1);
+            self.fill_buf().await?;
+        }
+    }
+
+    /// Reads one complete response as raw bytes: its lines, the literals they
+    /// announce and the final CRLF, ready for [`crate::parser::parse_response`].
+    /// Literals are read straight into the response, without passing
+    /// through another buffer.
+    pub async fn read_response(&mut self) -> Result<Vec<u8>, ClientError> {
+        let mut response = Vec::new();
+        loop {
+            let line_start = response.len();
+            self.read_line_into(&mut response).await?;
+            let line = String::from_utf8_lossy(&response[line_start..]);
+            let Some((len, _)) = trailing_literal_len(line.trim_end()) else {
+                return Ok(response);
+            };
+            let literal_start = response.len();
+            if let Err(e) = self.read_literal_into(len, &mut response).await {
+                // Keep what arrived, so the rest can be asked for elsewhere
+                let received = response.split_off(literal_start);
+                self.interrupted = Some(Interrupted {
+                    response,
+                    received,
+                    len,
+                });
+                return Err(e);
+            }
+        }
+    }
+
+    /// The response a failed [`Self::read_response`] was cut off in, if the
+    /// connection was lost partway through one of its literals.
+    pub fn take_interrupted(&mut self) -> Option<Interrupted> {
+        self.interrupted.take()
+    }
+
+    /// Reads exactly `len` bytes, as announced by a `{len}` literal.
+    pub async fn read_literal(&mut self, len: usize) -> Result<Vec<u8>, ClientError> {
+        let mut literal = Vec::new();
+        self.read_literal_into(len, &mut literal).await?;
+        Ok(literal)
+    }
+
+    /// Appends exactly `len` bytes to `out`, as announced by a `{len}`
+    /// literal: what is buffered already, then the rest, which goes straight
+    /// from the connection into `out` when there is much of it.
+    async fn read_literal_into(
+        &mut self,
+        len: usize,
+        out: &mut Vec<u8>,
+    ) -> Result<(), ClientError> {
+        out.reserve(len);
+        let start = out.len();
+        let mut remaining = len;
+        loop {
+            let buffered = self.read_buf.len().min(remaining);
+            out.extend_from_slice(&self.read_buf[..buffered]);
+            self.read_buf.advance(buffered);
+            remaining -= buffered;
+            if remaining == 0 {
+                if let Some((trace, connection)) = &self.trace {
+                    trace.received_literal(*connection, len, &out[start..]);
+                }
+                return Ok(());
+            }
+            if remaining < self.read_size {
+                // A short rest comes with whatever follows it
+                self.fill_buf().await?;
+                continue;
+            }
+            let n = (&mut self.stream)
+                .take(remaining as u64)
+                .read_buf(out)
+                .await
+                .map_err(|e| ClientError::connection_caused("lost the connection", e))?;
+            if n == 0 {
+                return Err(connection_closed());
+            }
+            remaining -= n;
+        }
+    }
+
+    /// Sends LOGOUT without waiting for the server to close the connection.
+    pub async fn logout(mut self) -> Result<(), ClientError> {
+        self.send("LOGOUT").await?;
+        Ok(())
+    }
+
+    /// Reads more of the connection into the buffer's spare room, which
+    /// is reused rather than zeroed for each read.
+    async fn fill_buf(&mut self) -> Result<(), ClientError> {
+        self.read_buf.reserve(self.read_size);
+        let n = self
+            .stream
+            .read_buf(&mut self.read_buf)
+            .await
+            .map_err(|e| ClientError::connection_caused("lost the connection", e))?;
+        if n == 0 {
+            return Err(connection_closed());
+        }
+        Ok(())
+    }
+}
+
+/// How much is read from the connection at once, unless set otherwise.
+pub const READ_BUFFER: usize = 64 << 10;
+
+/// Smallest read buffer a session accepts.
+pub const MIN_READ_BUFFER: usize = 4 << 10;
+
+/// Parses a read buffer size such as `256K` or `1M`.
+pub fn parse_read_buffer(value: &str) -> Result<usize, ClientError> {
+    match crate::diskspace::parse_size(value) {
+        Ok(size) if size >= MIN_READ_BUFFER as u64 && size <= 1 << 30 => Ok(size as usize),
+        _ => Err(ClientError::InvalidArgument(format!(
+            "Invalid read buffer: {} (expected a size from 4K to 1G, as in 256K)",
+            value
+        ))),
+    }
+}
+
+fn connection_closed() -> ClientError {
+    ClientError::connection("Connection closed by server")
+}
+
+/// Returns the human-readable text following a `[CODE]` response code, if the
+/// response text starts with that code (case-insensitive).
+pub fn strip_response_code<'a>(text: &'a str, code: &str) -> Option<&'a str> {
+    let inner = text.strip_prefix('[')?;
+    let close = inner.find(']')?;
+    let name = inner[..close].split_whitespace().next().unwrap_or_default();
+    if name.eq_ignore_ascii_case(code) {
+        Some(inner[close + 1..].trim())
+    } else {
+        None
+    }
+}
+
+/// Argument of a `[CODE arg]` response code anywhere in an untagged response,
+/// e.g. the number in `* OK [UIDVALIDITY 3857529045] UIDs valid`.
+pub fn response_code_arg<'a>(line: &'a str, code: &str) -> Option<&'a str> {
+    let start = line.find('[')?;
+    let inner = &line[start + 1..];
+    let inner = &inner[..inner.find(']')?];
+    let (name, arg) = inner.split_once(' ')?;
+    name.eq_ignore_ascii_case(code).then(|| arg.trim())
+}
+
+/// Compresses numbers into an IMAP sequence set, e.g. `1:3,7,9:10`.
+pub fn sequence_set(numbers: &[u32]) -> String {
+    let mut sorted = numbers.to_vec();
+    sorted.sort_unstable();
+    sorted.dedup();
+
+    let mut ranges: Vec<(u32, u32)> = Vec::new();
+    for n in sorted {
+        match ranges.last_mut() {
+            Some((_, end)) if *end + 1 == n => *end = n,
+            _ => ranges.push((n, n)),
+        }
+    }
+    ranges
+        .iter()
+        .map(|&(start, end)| {
+            if start == end {
+                start.to_string()
+            } else {
+                format!("{}:{}", start, end)
+            }
+        })
+        .collect::<Vec<_>>()
+        .join(",")
+}
+
+/// A parsed sequence set such as `1500:1600,1720,1800:*`, where `*` stands
+/// for the largest number in use.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SequenceSet {
+    ranges: Vec<(Option<u32>, Option<u32>)>,
+}
+
+impl SequenceSet {
+    /// Whether `n` is in the set, given the largest number currently in use.
+    pub fn contains(&self, n: u32, largest: u32) -> bool {
+        self.ranges.iter().any(|&(start, end)| {
+            let start = start.unwrap_or(largest);
+            let end = end.unwrap_or(largest);
+            (start.min(end)..=start.max(end)).contains(&n)
+        })
+    }
+}
+
+impl std::fmt::Display for SequenceSet {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let number = |n: Option<u32>| n.map_or("*".to_string(), |n| n.to_string());
+        let ranges: Vec<String> = self
+            .ranges
+            .iter()
+            .map(|&(start, end)| match start == end {
+                true => number(start),
+                false => format!("{}:{}", number(start), number(end)),
+            })
+            .collect();
+        write!(f, "{}", ranges.join(","))
+    }
+}
+
+impl std::str::FromStr for SequenceSet {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let invalid = || ClientError::InvalidArgument(format!("Invalid sequence set: {}", s));
+        let number = |value: &str| match value {
+            "*" => Ok(None),
+            _ => match value.parse::<u32>() {
+                Ok(n) if n > 0 => Ok(Some(n)),
+                _ => Err(invalid()),
+            },
+        };
+
+        let ranges = s
+            .split(',')
+            .map(|item| match item.split_once(':') {
+                Some((start, end)) => Ok((number(start)?, number(end)?)),
+                None => number(item).map(|n| (n, n)),
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(SequenceSet { ranges })
+    }
+}
+
+/// If `line` ends with a literal announcement `{n}`, returns `n` and the byte
+/// offset where the announcement starts.
+pub fn trailing_literal_len(line: &str) -> Option<(usize, usize)> {
+    let body = line.strip_suffix('}')?;
+    let start = body.rfind('{')?;
+    let len = body[start + 1..].trim_end_matches('+').parse().ok()?;
+    Some((len, start))
+}
@@ -0,0 +1,204 @@
+use rusqlite::Connection;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::Duration;
+
+use crate::checkpoint::{SyncSchedule, SyncTimer};
+use crate::error_imap::ClientError;
+use crate::writer::WriteGroup;
+
+/// Name of the state database kept at the root of an archive directory.
+pub const STATE_FILE: &str = ".state.db";
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS catalog (
+    id INTEGER PRIMARY KEY,
+    message_id_hash TEXT NOT NULL,
+    content_hash TEXT NOT NULL,
+    size INTEGER NOT NULL,
+    line_endings TEXT NOT NULL,
+    internal_date INTEGER,
+    original_hash TEXT,
+    path TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS checkpoint (
+    mailbox TEXT NOT NULL,
+    uid_validity INTEGER NOT NULL,
+    uid INTEGER NOT NULL,
+    PRIMARY KEY (mailbox, uid_validity, uid)
+) WITHOUT ROWID;
+";
+
+/// The SQLite database holding an archive's catalog and checkpoint.
+///
+/// It runs in WAL mode, and records go into one open transaction that is
+/// committed at the end of each batch, and on the sync schedule in between.
+/// A commit is on disk once it returns, and a crash or power cut at any
+/// point leaves the database as of the last commit, with no partial record
+/// to repair. Clones share the connection, so the catalog and checkpoint of
+/// a message are committed together. Message files held back to be written
+/// as a group are written before each commit.
+#[derive(Clone)]
+pub struct StateDb {
+    inner: Arc<Mutex<Inner>>,
+    dir: PathBuf,
+}
+
+struct Inner {
+    connection: Connection,
+    sync: SyncTimer,
+    files: Arc<WriteGroup>,
+}
+
+impl StateDb {
+    /// Opens the state database of the archive rooted at `dir`, creating it
+    /// if there is none yet.
+    pub fn open(dir: &Path) -> Result<Self, ClientError> {
+        std::fs::create_dir_all(dir)?;
+        let connection = Connection::open(dir.join(STATE_FILE))?;
+        // Other commands reading the archive wait for a commit in progress
+        connection.busy_timeout(Duration::from_secs(30))?;
+        let mode: String =
+            connection.query_row("PRAGMA journal_mode = WAL", [], |row| row.get(0))?;
+        if !mode.eq_ignore_ascii_case("wal") {
+            log::warn!(
+                "{} is in {} mode instead of WAL",
+                dir.join(STATE_FILE).display(),
+                mode
+            );
+        }
+        connection.pragma_update(None, "synchronous", "FULL")?;
+        connection.execute_batch(SCHEMA)?;
+        Ok(StateDb {
+            inner: Arc::new(Mutex::new(Inner {
+                connection,
+                sync: SyncTimer::default(),
+                files: Arc::new(WriteGroup::new()),
+            })),
+            dir: dir.to_path_buf(),
+        })
+    }
+
+    /// Root of the archive the database belongs to.
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
+
+    /// The group message files are held back in until the next commit.
+    pub fn write_group(&self) -> Result<Arc<WriteGroup>, ClientError> {
+        Ok(Arc::clone(&self.lock()?.files))
+    }
+
+    pub fn set_sync_schedule(&self, schedule: SyncSchedule) {
+        if let Ok(mut inner) = self.lock() {
+            inner.sync = SyncTimer::new(schedule);
+        }
+    }
+
+    /// Runs `update` in the open transaction, starting one if there is
+    /// none, and commits if the sync schedule is due.
+    pub(crate) fn write<T>(
+        &self,
+        update: impl FnOnce(&Connection) -> rusqlite::Result<T>,
+    ) -> Result<T, ClientError> {
+        let mut inner = self.lock()?;
+        if inner.connection.is_autocommit() {
+            inner.connection.execute_batch("BEGIN IMMEDIATE")?;
+        }
+        let value = update(&inner.connection)?;
+        if inner.sync.is_due() {
+            inner.commit()?;
+        }
+        Ok(value)
+    }
+
+    /// Runs `query`, which also sees the records not committed yet.
+    pub(crate) fn read<T>(
+        &self,
+        query: impl FnOnce(&Connection) -> rusqlite::Result<T>,
+    ) -> Result<T, ClientError> {
+        Ok(query(&self.lock()?.connection)?)
+    }
+
+    /// Commits everything recorded so far.
+    pub fn commit(&self) -> Result<(), ClientError> {
+        let mut inner = self.lock()?;
+        inner.sync.reset();
+        inner.commit()
+    }
+
+    fn lock(&self) -> Result<MutexGuard<'_, Inner>, ClientError> {
+        self.inner.lock().map_err(|_| {
+            let poisoned = std::io::Error::other("state database lock poisoned");
+            ClientError::io(self.dir.join(STATE_FILE), poisoned)
+        })
+    }
+}
+
+impl Inner {
+    /// Writes the files held back, then commits the open transaction, if
+    /// any. If the files can't be written, the records since the last
+    /// commit are rolled back instead, and their messages are fetched again
+    /// next run.
+    fn commit(&mut self) -> Result<(), ClientError> {
+        let open = !self.connection.is_autocommit();
+        if let Err(e) = self.files.flush() {
+            self.files.discard();
+            if open {
+                self.connection.execute_batch("ROLLBACK")?;
+            }
+            return Err(e);
+        }
+        if open {
+            self.connection.execute_batch("COMMIT")?;
+        }
+        Ok(())
+    }
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        if let Err(e) = self.commit() {
+            log::error!("Failed to commit the state database: {}", e);
+        }
+    }
+}
+
+/// Reads a file of tab-separated state left by an older version into
+/// `table`, if there is one, handing each line to `import` in a single
+/// transaction, then renames the file so it is only imported once.
+pub(crate) fn import_legacy(
+    state: &StateDb,
+    name: &str,
+    table: &str,
+    mut import: impl FnMut(&Connection, &str) -> rusqlite::Result<()>,
+) -> Result<(), ClientError> {
+    let path = state.dir().join(name);
+    let contents = match std::fs::read_to_string(&path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+        Err(e) => return Err(e.into()),
+    };
+    state.commit()?;
+    state.write(|connection| {
+        // Already imported by a run that stopped before the rename
+        let query = format!("SELECT EXISTS (SELECT 1 FROM {})", table);
+        if connection.query_row(&query, [], |row| row.get(0))? {
+            return Ok(());
+        }
+        for line in contents.lines() {
+            import(connection, line)?;
+        }
+        Ok(())
+    })?;
+    state.commit()?;
+    let imported = state.dir().join(format!("{}.imported", name));
+    std::fs::rename(&path, &imported)?;
+    log::info!(
+        "Imported {} into {}; the original is kept as {}",
+        path.display(),
+        STATE_FILE,
+        imported.display()
+    );
+    Ok(())
+}
@@ -0,0 +1,311 @@
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use std::io::Write;
+
+use crate::diskspace::format_size;
+use crate::folders::{decode_mailbox_name, parse_string};
+use crate::storage::append_mbox;
+
+/// One resource of a quota root (RFC 9208), e.g. `STORAGE` in units of
+/// 1024 octets or `MESSAGE` in messages.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaResource {
+    pub name: String,
+    pub usage: u64,
+    pub limit: u64,
+}
+
+impl QuotaResource {
+    pub fn is_storage(&self) -> bool {
+        self.name.eq_ignore_ascii_case("STORAGE")
+    }
+
+    /// Share of the limit in use, in percent.
+    pub fn percent_used(&self) -> f64 {
+        if self.limit == 0 {
+            return 100.0;
+        }
+        self.usage as f64 * 100.0 / self.limit as f64
+    }
+
+    /// Usage against the limit, e.g. `1.2 GiB of 15.0 GiB (8%)`.
+    pub fn describe(&self) -> String {
+        let (usage, limit) = if self.is_storage() {
+            (
+                format_size(self.usage * 1024),
+                format_size(self.limit * 1024),
+            )
+        } else {
+            (self.usage.to_string(), self.limit.to_string())
+        };
+        format!("{} of {} ({:.0}%)", usage, limit, self.percent_used())
+    }
+}
+
+/// A quota root and the resources it limits.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaRoot {
+    pub name: String,
+    pub resources: Vec<QuotaResource>,
+}
+
+/// Usage of an account as reported by the server.
+#[derive(Debug, Clone, Default)]
+pub struct AccountStats {
+    pub account: String,
+    /// Quota roots applying to INBOX; empty when the server has no QUOTA
+    /// extension or sets no limits.
+    pub quota: Vec<QuotaRoot>,
+    /// Name and message count of each mailbox a fetch would work on.
+    pub mailboxes: Vec<(String, u32)>,
+    /// Sizes of the messages in those mailboxes.
+    pub sizes: SizeHistogram,
+}
+
+impl AccountStats {
+    /// The storage resource of the first quota root limiting storage.
+    pub fn storage(&self) -> Option<&QuotaResource> {
+        self.quota
+            .iter()
+            .flat_map(|root| &root.resources)
+            .find(|resource| resource.is_storage())
+    }
+}
+
+/// Parses the untagged part of a QUOTA response, e.g.
+/// `QUOTA "" (STORAGE 10 512 MESSAGE 3 100)`.
+pub fn parse_quota_response(line: &str) -> Option<QuotaRoot> {
+    let (word, rest) = line.split_once(' ')?;
+    if !word.eq_ignore_ascii_case("QUOTA") {
+        return None;
+    }
+    let (name, rest) = parse_string(rest.trim_start())?;
+    let list = rest.trim().strip_prefix('(')?.strip_suffix(')')?;
+
+    let words: Vec<&str> = list.split_whitespace().collect();
+    if !words.len().is_multiple_of(3) {
+        return None;
+    }
+    let resources = words
+        .chunks(3)
+        .map(|triple| {
+            Some(QuotaResource {
+                name: triple[0].to_ascii_uppercase(),
+                usage: triple[1].parse().ok()?,
+                limit: triple[2].parse().ok()?,
+            })
+        })
+        .collect::<Option<Vec<_>>>()?;
+    Some(QuotaRoot {
+        name: name.unwrap_or_default(),
+        resources,
+    })
+}
+
+/// Parses the untagged part of a STATUS response for its message count, e.g.
+/// `STATUS "INBOX" (MESSAGES 231)`, returning the mailbox name and count.
+pub fn parse_status_messages(line: &str) -> Option<(String, u32)> {
+    let (word, rest) = line.split_once(' ')?;
+    if !word.eq_ignore_ascii_case("STATUS") {
+        return None;
+    }
+    let (name, rest) = parse_string(rest.trim_start())?;
+    let list = rest.trim().strip_prefix('(')?.strip_suffix(')')?;
+    let words: Vec<&str> = list.split_whitespace().collect();
+    let count = words
+        .chunks(2)
+        .find(|pair| pair[0].eq_ignore_ascii_case("MESSAGES"))
+        .and_then(|pair| pair.get(1)?.parse().ok())?;
+    Some((decode_mailbox_name(&name?), count))
+}
+
+/// Upper bounds of the buckets of [`SizeHistogram`]; the last bucket has none.
+pub const SIZE_BUCKETS: [u64; 4] = [10 << 10, 100 << 10, 1 << 20, 10 << 20];
+
+const BUCKET_LABELS: [&str; 5] = [
+    "under 10 KiB",
+    "10-100 KiB",
+    "100 KiB-1 MiB",
+    "1-10 MiB",
+    "10 MiB and over",
+];
+
+/// Filesystem block size each file is assumed to take up in whole.
+const BLOCK_SIZE: u64 = 4096;
+
+/// Length of an mbox `From ` line and the blank line after the message.
+const MBOX_OVERHEAD: u64 = 43;
+
+/// Bytes of sampled messages as downloaded, in an mbox, and compressed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Sample {
+    pub raw: u64,
+    pub mbox: u64,
+    pub compressed: u64,
+}
+
+/// How an account's messages spread over sizes, and what archiving them
+/// would take on disk.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct SizeHistogram {
+    /// Messages in each bucket of [`SIZE_BUCKETS`].
+    pub counts: [u64; 5],
+    pub bytes: [u64; 5],
+    /// Bytes one file per message takes, in whole blocks.
+    pub on_disk: u64,
+    /// Messages downloaded to measure the other formats on, by bucket.
+    pub samples: [Sample; 5],
+}
+
+/// What an archive of an account would take in one format.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Projection {
+    pub format: &'static str,
+    pub bytes: u64,
+    /// Whether the size is extrapolated from samples or a rough guess
+    /// rather than worked out from every message's size.
+    pub estimated: bool,
+}
+
+impl SizeHistogram {
+    /// The bucket a message of `size` bytes goes in.
+    pub fn bucket(size: u64) -> usize {
+        SIZE_BUCKETS
+            .iter()
+            .position(|&bound| size < bound)
+            .unwrap_or(SIZE_BUCKETS.len())
+    }
+
+    pub fn label(bucket: usize) -> &'static str {
+        BUCKET_LABELS[bucket]
+    }
+
+    pub fn add(&mut self, size: u64) {
+        let bucket = Self::bucket(size);
+        self.counts[bucket] += 1;
+        self.bytes[bucket] += size;
+        self.on_disk += size.div_ceil(BLOCK_SIZE) * BLOCK_SIZE;
+    }
+
+    /// Measures the downloaded message `raw` in an mbox and compressed with
+    /// deflate, for projections of the whole account.
+    pub fn add_sample(&mut self, raw: &[u8]) -> std::io::Result<()> {
+        let mut mbox = Vec::new();
+        append_mbox(&mut mbox, raw);
+        let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(raw)?;
+        let compressed = encoder.finish()?;
+
+        let sample = &mut self.samples[Self::bucket(raw.len() as u64)];
+        sample.raw += raw.len() as u64;
+        sample.mbox += mbox.len() as u64;
+        sample.compressed += compressed.len() as u64;
+        Ok(())
+    }
+
+    pub fn messages(&self) -> u64 {
+        self.counts.iter().sum()
+    }
+
+    pub fn total(&self) -> u64 {
+        self.bytes.iter().sum()
+    }
+
+    /// What the account would take stored one message per file, as an mbox
+    /// and compressed. The mbox is measured on the samples when there are
+    /// any; compression can only be projected from samples.
+    pub fn projections(&self) -> Vec<Projection> {
+        let mut projections = vec![Projection {
+            format: "eml or Maildir files",
+            bytes: self.on_disk,
+            estimated: false,
+        }];
+        let mbox = self
+            .scaled(|sample| sample.mbox)
+            .unwrap_or(self.total() + self.messages() * MBOX_OVERHEAD);
+        projections.push(Projection {
+            format: "mbox",
+            bytes: mbox,
+            estimated: true,
+        });
+        if let Some(compressed) = self.scaled(|sample| sample.compressed) {
+            projections.push(Projection {
+                format: "gzip-compressed",
+                bytes: compressed,
+                estimated: true,
+            });
+        }
+        projections
+    }
+
+    /// Every bucket's bytes scaled by what `measure` gives for its samples,
+    /// or for all samples in buckets without any.
+    fn scaled(&self, measure: impl Fn(&Sample) -> u64) -> Option<u64> {
+        let sampled = self.samples.iter().map(|sample| sample.raw).sum::<u64>();
+        if sampled == 0 {
+            return None;
+        }
+        let overall = self.samples.iter().map(&measure).sum::<u64>() as f64 / sampled as f64;
+        let total = self
+            .bytes
+            .iter()
+            .zip(&self.samples)
+            .map(|(&bytes, sample)| {
+                let ratio = match sample.raw {
+                    0 => overall,
+                    raw => measure(sample) as f64 / raw as f64,
+                };
+                bytes as f64 * ratio
+            })
+            .sum::<f64>();
+        Some(total.round() as u64)
+    }
+}
+
+/// Indices of up to `count` of the messages of `sizes` to sample, spread
+/// evenly over the buckets holding any and evenly within each.
+pub fn pick_samples(sizes: &[u64], count: usize) -> Vec<usize> {
+    let mut buckets: [Vec<usize>; 5] = Default::default();
+    for (index, &size) in sizes.iter().enumerate() {
+        buckets[SizeHistogram::bucket(size)].push(index);
+    }
+    let mut quotas = [0; 5];
+    let mut left = count.min(sizes.len());
+    while left > 0 {
+        for (quota, bucket) in quotas.iter_mut().zip(&buckets) {
+            if left > 0 && *quota < bucket.len() {
+                *quota += 1;
+                left -= 1;
+            }
+        }
+    }
+    let mut picked: Vec<usize> = buckets
+        .iter()
+        .zip(quotas)
+        .flat_map(|(bucket, quota)| (0..quota).map(move |i| bucket[i * bucket.len() / quota]))
+        .collect();
+    picked.sort_unstable();
+    picked
+}
+
+/// One line per bucket of `histogram`, with a bar `width` characters long
+/// for the fullest.
+pub fn render_histogram(histogram: &SizeHistogram, width: usize) -> Vec<String> {
+    let most = histogram.counts.iter().copied().max().unwrap_or(0).max(1);
+    histogram
+        .counts
+        .iter()
+        .zip(&histogram.bytes)
+        .enumerate()
+        .map(|(bucket, (&count, &bytes))| {
+            let bar = (count * width as u64).div_ceil(most) as usize;
+            format!(
+                "{:<16} {:>8} {:>10}  {}",
+                SizeHistogram::label(bucket),
+                count,
+                format_size(bytes),
+                "#".repeat(bar)
+            )
+        })
+        .collect()
+}
@@ -0,0 +1,515 @@
+use std::borrow::Cow;
+use std::io::{BufRead, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, UNIX_EPOCH};
+
+use crate::error_imap::ClientError;
+use crate::html::html_to_text;
+use crate::message::{civil_from_days, find_text_part, parse_date, Address, Headers};
+use crate::pdf::{render_pdf, Line};
+
+/// How each fetched message is stored on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum OutputFormat {
+    /// The raw RFC 822 message, byte for byte.
+    #[default]
+    Eml,
+    /// A human-readable rendering of headers and body, one PDF per message.
+    Pdf,
+    /// The raw message in a Maildir per folder, with its IMAP flags in the
+    /// file name and its Gmail labels as keywords, as mu and notmuch expect.
+    Maildir,
+}
+
+impl OutputFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::Eml | OutputFormat::Maildir => "eml",
+            OutputFormat::Pdf => "pdf",
+        }
+    }
+
+    /// Converts a raw message into the bytes stored for this format.
+    pub fn render(&self, raw: &[u8]) -> Vec<u8> {
+        match self {
+            OutputFormat::Eml | OutputFormat::Maildir => raw.to_vec(),
+            OutputFormat::Pdf => message_pdf(raw),
+        }
+    }
+}
+
+impl FromStr for OutputFormat {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "eml" => Ok(OutputFormat::Eml),
+            "pdf" => Ok(OutputFormat::Pdf),
+            "maildir" => Ok(OutputFormat::Maildir),
+            other => Err(ClientError::InvalidArgument(format!(
+                "Unknown format `{}` (expected eml, pdf or maildir)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Renders the main headers and the text body of a message as a PDF exhibit.
+fn message_pdf(raw: &[u8]) -> Vec<u8> {
+    let headers = Headers::parse(raw);
+    let mut lines = Vec::new();
+
+    for name in ["From", "To", "Cc", "Date", "Subject", "Message-ID"] {
+        if let Some(value) = headers.get_decoded(name) {
+            lines.push(Line::bold(format!("{}: {}", name, value)));
+        }
+    }
+    lines.push(Line::plain(""));
+
+    let body = find_text_part(raw, "text/plain")
+        .or_else(|| find_text_part(raw, "text/html").map(|html| html_to_text(&html)))
+        .unwrap_or_default();
+    lines.extend(body.lines().map(Line::plain));
+
+    render_pdf(&headers.subject().unwrap_or_default(), &lines)
+}
+
+/// How messages are grouped into conversations.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadMode {
+    /// One directory per thread, messages named in chronological order.
+    Directory,
+    /// One mbox file per thread with messages in chronological order.
+    File,
+}
+
+impl FromStr for ThreadMode {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "dir" | "directory" => Ok(ThreadMode::Directory),
+            "file" => Ok(ThreadMode::File),
+            other => Err(ClientError::InvalidArgument(format!(
+                "Unknown thread mode `{}` (expected dir or file)",
+                other
+            ))),
+        }
+    }
+}
+
+/// How messages are grouped by who sent them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Organize {
+    /// One directory per From address, such as `alice@example.com/`.
+    BySender,
+    /// One directory per domain of the From address, such as `example.com/`.
+    ByDomain,
+}
+
+impl Organize {
+    /// Directory for a message from `sender`, whose address is unknown when
+    /// `None`.
+    pub fn directory(self, sender: Option<&Address>) -> String {
+        let key = sender.and_then(|sender| match self {
+            Organize::BySender => Some(sender.email.to_lowercase()),
+            Organize::ByDomain => sender.domain(),
+        });
+        match key.filter(|key| !key.is_empty()) {
+            Some(key) => sanitize_filename(&key),
+            None => "unknown-sender".to_string(),
+        }
+    }
+}
+
+impl FromStr for Organize {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "by-sender" | "sender" => Ok(Organize::BySender),
+            "by-domain" | "by-sender-domain" | "domain" => Ok(Organize::ByDomain),
+            other => Err(ClientError::InvalidArgument(format!(
+                "Unknown organization `{}` (expected by-sender or by-domain)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Line endings of stored `.eml` files. IMAP always sends CRLF.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineEndings {
+    /// Store messages exactly as received.
+    #[default]
+    Keep,
+    Lf,
+    Crlf,
+}
+
+impl LineEndings {
+    pub fn name(&self) -> &'static str {
+        match self {
+            LineEndings::Keep => "keep",
+            LineEndings::Lf => "lf",
+            LineEndings::Crlf => "crlf",
+        }
+    }
+
+    /// Converts `raw` to these line endings. Lone CRs are left alone.
+    pub fn apply<'a>(&self, raw: &'a [u8]) -> Cow<'a, [u8]> {
+        match self {
+            LineEndings::Keep => Cow::Borrowed(raw),
+            LineEndings::Lf => {
+                if !raw.windows(2).any(|w| w == b"\r\n") {
+                    return Cow::Borrowed(raw);
+                }
+                let mut out = Vec::with_capacity(raw.len());
+                for (i, &b) in raw.iter().enumerate() {
+                    if !(b == b'\r' && raw.get(i + 1) == Some(&b'\n')) {
+                        out.push(b);
+                    }
+                }
+                Cow::Owned(out)
+            }
+            LineEndings::Crlf => {
+                let lone_lf = |i: usize| raw[i] == b'\n' && (i == 0 || raw[i - 1] != b'\r');
+                if !(0..raw.len()).any(lone_lf) {
+                    return Cow::Borrowed(raw);
+                }
+                let mut out = Vec::with_capacity(raw.len() + raw.len() / 32);
+                for (i, &b) in raw.iter().enumerate() {
+                    if lone_lf(i) {
+                        out.push(b'\r');
+                    }
+                    out.push(b);
+                }
+                Cow::Owned(out)
+            }
+        }
+    }
+}
+
+impl FromStr for LineEndings {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "keep" => Ok(LineEndings::Keep),
+            "lf" => Ok(LineEndings::Lf),
+            "crlf" => Ok(LineEndings::Crlf),
+            other => Err(ClientError::InvalidArgument(format!(
+                "Unknown line endings `{}` (expected keep, lf or crlf)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Key identifying the conversation a message belongs to: Gmail's thread id
+/// when known, otherwise the root of its References chain.
+pub fn thread_key(gmail_thread_id: Option<&str>, headers: &Headers) -> String {
+    if let Some(id) = gmail_thread_id {
+        return id.to_string();
+    }
+
+    let root = headers
+        .get("References")
+        .and_then(|refs| refs.split_whitespace().next())
+        .or_else(|| headers.get("In-Reply-To"))
+        .or_else(|| headers.message_id())
+        .unwrap_or("unthreaded");
+
+    let key: String = root
+        .trim_matches(['<', '>'])
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || c == '.' || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .take(100)
+        .collect();
+    if key.is_empty() {
+        "unthreaded".to_string()
+    } else {
+        sanitize_filename(&key)
+    }
+}
+
+/// Sets the modification time of `path` to the Unix `timestamp`.
+pub fn set_modified(path: &Path, timestamp: i64) -> std::io::Result<()> {
+    let offset = Duration::from_secs(timestamp.unsigned_abs());
+    let time = if timestamp >= 0 {
+        UNIX_EPOCH + offset
+    } else {
+        UNIX_EPOCH - offset
+    };
+    std::fs::File::options()
+        .write(true)
+        .open(path)?
+        .set_modified(time)
+}
+
+/// Names Windows reserves for devices, with or without an extension.
+const RESERVED_NAMES: [&str; 22] = [
+    "CON", "PRN", "AUX", "NUL", "COM1", "COM2", "COM3", "COM4", "COM5", "COM6", "COM7", "COM8",
+    "COM9", "LPT1", "LPT2", "LPT3", "LPT4", "LPT5", "LPT6", "LPT7", "LPT8", "LPT9",
+];
+
+/// Longest file name produced, in bytes, leaving room for suffixes within the
+/// usual 255-byte limit.
+const MAX_FILENAME_LEN: usize = 200;
+
+/// Makes `name` safe to use as a single path component on Windows as well as
+/// Unix: separators, `<>:"|?*`, control characters and the bidirectional
+/// controls that can make a name display as another become `_`, trailing dots
+/// and spaces are dropped, reserved device names get a `_` prefix, and
+/// `.`/`..` can't escape the directory.
+pub fn sanitize_filename(name: &str) -> String {
+    let mut sanitized: String = name
+        .chars()
+        .map(|c| match c {
+            '/' | '\\' | '<' | '>' | ':' | '"' | '|' | '?' | '*' => '_',
+            c if c.is_control() || is_bidi_control(c) => '_',
+            c => c,
+        })
+        .collect();
+
+    if sanitized.len() > MAX_FILENAME_LEN {
+        let mut end = MAX_FILENAME_LEN;
+        while !sanitized.is_char_boundary(end) {
+            end -= 1;
+        }
+        sanitized.truncate(end);
+    }
+    let trimmed = sanitized.trim_end_matches(['.', ' ']).len();
+    sanitized.truncate(trimmed);
+
+    let base = sanitized.split('.').next().unwrap_or_default().trim_end();
+    if sanitized.is_empty() {
+        "_".to_string()
+    } else if RESERVED_NAMES.iter().any(|r| base.eq_ignore_ascii_case(r)) {
+        format!("_{}", sanitized)
+    } else {
+        sanitized
+    }
+}
+
+/// Unicode's explicit directional marks, embeddings, overrides and isolates.
+fn is_bidi_control(c: char) -> bool {
+    matches!(c, '\u{200E}' | '\u{200F}' | '\u{061C}' | '\u{202A}'..='\u{202E}' | '\u{2066}'..='\u{2069}')
+}
+
+/// Appends `raw` to an mbox (mboxrd) buffer: a `From ` separator line, the
+/// message with LF line endings and `From ` lines quoted, then a blank line.
+pub fn append_mbox(mbox: &mut Vec<u8>, raw: &[u8]) {
+    let date = parse_date(Headers::parse(raw).get("Date").unwrap_or_default()).unwrap_or(0);
+    mbox.extend_from_slice(format!("From MAILER-DAEMON {}\n", asctime(date)).as_bytes());
+
+    for line in raw.split_inclusive(|&b| b == b'\n') {
+        let line = line
+            .strip_suffix(b"\r\n")
+            .or_else(|| line.strip_suffix(b"\n"))
+            .unwrap_or(line);
+        let unquoted = line.iter().position(|&b| b != b'>').unwrap_or(line.len());
+        if line[unquoted..].starts_with(b"From ") {
+            mbox.push(b'>');
+        }
+        mbox.extend_from_slice(line);
+        mbox.push(b'\n');
+    }
+    mbox.push(b'\n');
+}
+
+/// Reads the messages of an mbox written by [`append_mbox`] (or any mboxrd or
+/// mboxo file) one at a time, passing each to `message` with its `From `
+/// separator dropped, quoted `From ` lines restored and LF line endings.
+pub fn read_mbox<R, F>(mut reader: R, mut message: F) -> Result<usize, ClientError>
+where
+    R: BufRead,
+    F: FnMut(Vec<u8>) -> Result<(), ClientError>,
+{
+    let mut count = 0;
+    let mut current: Option<Vec<u8>> = None;
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        let end = reader.read_until(b'\n', &mut line)? == 0;
+        if end || line.starts_with(b"From ") {
+            if let Some(mut raw) = current.take() {
+                // The blank line the separator needs isn't part of the message
+                if raw.ends_with(b"\n\n") {
+                    raw.pop();
+                }
+                message(raw)?;
+                count += 1;
+            }
+            if end {
+                return Ok(count);
+            }
+            current = Some(Vec::new());
+            continue;
+        }
+        let Some(raw) = current.as_mut() else {
+            continue;
+        };
+        let content = line
+            .strip_suffix(b"\r\n")
+            .or_else(|| line.strip_suffix(b"\n"))
+            .unwrap_or(&line);
+        let quotes = content.iter().position(|&b| b != b'>').unwrap_or(0);
+        let unquoted = if quotes > 0 && content[quotes..].starts_with(b"From ") {
+            &content[1..]
+        } else {
+            content
+        };
+        raw.extend_from_slice(unquoted);
+        raw.push(b'\n');
+    }
+}
+
+/// C `asctime` layout used by mbox separators, e.g. `Thu Jan  1 00:00:00 1970`.
+fn asctime(timestamp: i64) -> String {
+    const DAYS: [&str; 7] = ["Thu", "Fri", "Sat", "Sun", "Mon", "Tue", "Wed"];
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let days = timestamp.div_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    let seconds = timestamp.rem_euclid(86_400);
+    format!(
+        "{} {} {:2} {:02}:{:02}:{:02} {}",
+        DAYS[days.rem_euclid(7) as usize],
+        MONTHS[month as usize - 1],
+        day,
+        seconds / 3600,
+        seconds % 3600 / 60,
+        seconds % 60,
+        year
+    )
+}
+
+/// Merges the messages of each thread directory under `threads_dir` into a
+/// single `<thread>.mbox` in chronological (file name) order, appending to
+/// the mbox earlier runs left. Other files, such as plain-text copies, are
+/// left in place.
+pub fn merge_thread_directories(threads_dir: &Path) -> Result<(), ClientError> {
+    if !threads_dir.exists() {
+        return Ok(());
+    }
+
+    for entry in std::fs::read_dir(threads_dir).map_err(|e| ClientError::io(threads_dir, e))? {
+        let thread_dir = entry.map_err(|e| ClientError::io(threads_dir, e))?.path();
+        if !thread_dir.is_dir() {
+            continue;
+        }
+
+        let mut files: Vec<_> = std::fs::read_dir(&thread_dir)
+            .map_err(|e| ClientError::io(&thread_dir, e))?
+            .filter_map(|e| e.ok().map(|e| e.path()))
+            .filter(|p| p.extension().is_some_and(|ext| ext == "eml"))
+            .collect();
+        files.sort();
+        if files.is_empty() {
+            continue;
+        }
+
+        let mut mbox = Vec::new();
+        for file in &files {
+            append_mbox(
+                &mut mbox,
+                &std::fs::read(file).map_err(|e| ClientError::io(file, e))?,
+            );
+        }
+        let mut mbox_name = thread_dir.file_name().unwrap_or_default().to_os_string();
+        mbox_name.push(".mbox");
+        let mbox_path = threads_dir.join(mbox_name);
+        std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&mbox_path)
+            .and_then(|mut file| file.write_all(&mbox))
+            .map_err(|e| ClientError::io(&mbox_path, e))?;
+
+        for file in &files {
+            std::fs::remove_file(file).map_err(|e| ClientError::io(file, e))?;
+        }
+        // Only succeeds once nothing else is left in the directory
+        let _ = std::fs::remove_dir(&thread_dir);
+    }
+    Ok(())
+}
+
+/// What to do when the file a message would be saved to already exists.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ExistingFilePolicy {
+    /// Leave the existing file alone and don't store the message.
+    Skip,
+    /// Replace the existing file (logged as a warning).
+    #[default]
+    Overwrite,
+    /// Store the message under a new name with a numeric suffix.
+    Rename,
+    /// Fail the batch.
+    Error,
+}
+
+impl FromStr for ExistingFilePolicy {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "skip" => Ok(ExistingFilePolicy::Skip),
+            "overwrite" => Ok(ExistingFilePolicy::Overwrite),
+            "rename" => Ok(ExistingFilePolicy::Rename),
+            "error" => Ok(ExistingFilePolicy::Error),
+            other => Err(ClientError::InvalidArgument(format!(
+                "Unknown --if-exists policy `{}` (expected skip, overwrite, rename or error)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Applies `policy` to the intended target `path`, returning the path to write
+/// to, or `None` when the message should be skipped.
+pub fn resolve_target(
+    path: PathBuf,
+    policy: ExistingFilePolicy,
+) -> Result<Option<PathBuf>, ClientError> {
+    if !path.exists() {
+        return Ok(Some(path));
+    }
+
+    match policy {
+        ExistingFilePolicy::Skip => Ok(None),
+        ExistingFilePolicy::Overwrite => {
+            log::warn!("Overwriting existing file {}", path.display());
+            Ok(Some(path))
+        }
+        ExistingFilePolicy::Rename => {
+            let stem = path
+                .file_stem()
+                .unwrap_or_default()
+                .to_string_lossy()
+                .into_owned();
+            let extension = path
+                .extension()
+                .map(|ext| format!(".{}", ext.to_string_lossy()))
+                .unwrap_or_default();
+            let renamed = (1..)
+                .map(|n| path.with_file_name(format!("{}_{}{}", stem, n, extension)))
+                .find(|candidate| !candidate.exists())
+                .unwrap_or(path);
+            Ok(Some(renamed))
+        }
+        ExistingFilePolicy::Error => Err(ClientError::io(
+            path,
+            std::io::ErrorKind::AlreadyExists.into(),
+        )),
+    }
+}
@@ -0,0 +1,569 @@
+use std::fmt;
+use std::fs::File;
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use rustls::client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier};
+use rustls::client::{Resumption, WebPkiServerVerifier};
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime};
+use rustls::{
+    CipherSuite, DigitallySignedStruct, SignatureScheme, SupportedCipherSuite,
+    SupportedProtocolVersion,
+};
+use sha2::{Digest, Sha256};
+
+use crate::error_imap::ClientError;
+use crate::net::CONNECTION_LOG;
+
+/// TLS settings beyond the defaults.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TlsOptions {
+    /// PEM file with a client certificate for servers requiring mutual TLS,
+    /// and the file with its key unless the certificate's holds it too.
+    pub client_cert: Option<PathBuf>,
+    pub client_key: Option<PathBuf>,
+    /// Public keys the server's certificate chain must include one of.
+    pub pins: Vec<Pin>,
+    /// Oldest TLS version accepted.
+    pub min_version: TlsVersion,
+    /// Cipher suites offered, in order of preference; every one rustls
+    /// supports if empty.
+    pub cipher_suites: Vec<CipherSuite>,
+    /// Whether connections resume the TLS sessions of earlier ones, from
+    /// the tickets or session IDs servers hand out.
+    pub session_tickets: bool,
+}
+
+impl Default for TlsOptions {
+    fn default() -> Self {
+        TlsOptions {
+            client_cert: None,
+            client_key: None,
+            pins: Vec::new(),
+            min_version: TlsVersion::default(),
+            cipher_suites: Vec::new(),
+            session_tickets: true,
+        }
+    }
+}
+
+/// A TLS protocol version.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub enum TlsVersion {
+    #[default]
+    Tls12,
+    Tls13,
+}
+
+impl FromStr for TlsVersion {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let lower = s.to_ascii_lowercase();
+        match lower.strip_prefix("tls").unwrap_or(&lower) {
+            "1.2" | "12" => Ok(TlsVersion::Tls12),
+            "1.3" | "13" => Ok(TlsVersion::Tls13),
+            _ => Err(ClientError::InvalidArgument(format!(
+                "Unknown TLS version `{}` (expected 1.2 or 1.3)",
+                s
+            ))),
+        }
+    }
+}
+
+/// Parses a comma-separated list of cipher suites, by their IANA names as in
+/// `TLS13_AES_256_GCM_SHA384`.
+pub fn parse_cipher_suites(value: &str) -> Result<Vec<CipherSuite>, ClientError> {
+    let supported = rustls::crypto::ring::ALL_CIPHER_SUITES;
+    let name = |suite: &SupportedCipherSuite| suite.suite().as_str().unwrap_or_default();
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|wanted| !wanted.is_empty())
+        .map(|wanted| {
+            supported
+                .iter()
+                .find(|suite| name(suite).eq_ignore_ascii_case(wanted))
+                .map(|suite| suite.suite())
+                .ok_or_else(|| {
+                    let names: Vec<&str> = supported.iter().map(name).collect();
+                    ClientError::InvalidArgument(format!(
+                        "Unknown cipher suite `{}` (expected one of {})",
+                        wanted,
+                        names.join(", ")
+                    ))
+                })
+        })
+        .collect()
+}
+
+/// The TLS settings connections to IMAP servers are made with: servers are
+/// checked against the Mozilla root certificates, and against any pinned
+/// keys, and the client certificate is presented to those that ask for one.
+/// Building them checks `options` go together, and that the certificate's
+/// files can be read.
+pub fn client_config(options: &TlsOptions) -> Result<rustls::ClientConfig, ClientError> {
+    let mut provider = rustls::crypto::ring::default_provider();
+    if !options.cipher_suites.is_empty() {
+        provider.cipher_suites = options
+            .cipher_suites
+            .iter()
+            .filter_map(|wanted| {
+                let supported = provider.cipher_suites.iter();
+                supported.copied().find(|suite| suite.suite() == *wanted)
+            })
+            .collect();
+    }
+    let versions: &[&SupportedProtocolVersion] = match options.min_version {
+        TlsVersion::Tls12 => &[&rustls::version::TLS13, &rustls::version::TLS12],
+        TlsVersion::Tls13 => &[&rustls::version::TLS13],
+    };
+    let provider = Arc::new(provider);
+    let builder = rustls::ClientConfig::builder_with_provider(Arc::clone(&provider))
+        .with_protocol_versions(versions)
+        .map_err(|e| ClientError::tls_caused("unusable TLS settings", e))?;
+
+    let root_store = rustls::RootCertStore {
+        roots: webpki_roots::TLS_SERVER_ROOTS.into(),
+    };
+    let mut verifier: Arc<dyn ServerCertVerifier> =
+        WebPkiServerVerifier::builder_with_provider(Arc::new(root_store), provider)
+            .build()
+            .map_err(|e| ClientError::tls_caused("cannot verify certificates", e))?;
+    if !options.pins.is_empty() {
+        verifier = Arc::new(PinnedVerifier {
+            verifier,
+            pins: options.pins.clone(),
+        });
+    }
+    if log::log_enabled!(target: CONNECTION_LOG, log::Level::Debug) {
+        verifier = Arc::new(LoggedVerifier { verifier });
+    }
+    let builder = builder
+        .dangerous()
+        .with_custom_certificate_verifier(verifier);
+    let mut config = match (&options.client_cert, &options.client_key) {
+        (Some(cert), key) => {
+            let (chain, key) = load_client_cert(cert, key.as_deref())?;
+            builder.with_client_auth_cert(chain, key).map_err(|e| {
+                ClientError::tls_caused(format!("client certificate {}", cert.display()), e)
+            })?
+        }
+        (None, Some(_)) => {
+            return Err(ClientError::InvalidArgument(
+                "a client key needs a client certificate to go with it".to_string(),
+            ))
+        }
+        (None, None) => builder.with_no_client_auth(),
+    };
+    if !options.session_tickets {
+        config.resumption = Resumption::disabled();
+    }
+    Ok(config)
+}
+
+/// Reads the client certificate in PEM file `cert`, followed by any
+/// intermediate certificates, and its private key from PEM file `key`, or
+/// from `cert` as well if it holds both.
+pub fn load_client_cert(
+    cert: &Path,
+    key: Option<&Path>,
+) -> Result<(Vec<CertificateDer<'static>>, PrivateKeyDer<'static>), ClientError> {
+    let unreadable = |path: &Path, e: std::io::Error| {
+        ClientError::tls_caused(format!("cannot read {}", path.display()), e)
+    };
+    let mut cert_file = open(cert).map_err(|e| unreadable(cert, e))?;
+    let chain = rustls_pemfile::certs(&mut cert_file)
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| unreadable(cert, e))?;
+    if chain.is_empty() {
+        return Err(ClientError::tls(format!(
+            "{} holds no certificate",
+            cert.display()
+        )));
+    }
+    let key_path = key.unwrap_or(cert);
+    let mut key_file = open(key_path).map_err(|e| unreadable(key_path, e))?;
+    let key = rustls_pemfile::private_key(&mut key_file)
+        .map_err(|e| unreadable(key_path, e))?
+        .ok_or_else(|| ClientError::tls(format!("{} holds no private key", key_path.display())))?;
+    Ok((chain, key))
+}
+
+fn open(path: &Path) -> std::io::Result<BufReader<File>> {
+    Ok(BufReader::new(File::open(path)?))
+}
+
+/// A pinned public key: the SHA-256 hash of a certificate's
+/// SubjectPublicKeyInfo, written `sha256/` and then in base64, as HPKP
+/// (RFC 7469) and curl's `--pinnedpubkey` have it.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Pin([u8; 32]);
+
+impl Pin {
+    /// The pin of the key DER certificate `cert` is for, or `None` if it
+    /// isn't a certificate.
+    pub fn of(cert: &[u8]) -> Option<Pin> {
+        Some(Pin(Sha256::digest(public_key_info(cert)?).into()))
+    }
+}
+
+impl FromStr for Pin {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let encoded = s.strip_prefix("sha256/").unwrap_or(s);
+        STANDARD
+            .decode(encoded.trim_start_matches('/'))
+            .ok()
+            .and_then(|hash| hash.try_into().ok())
+            .map(Pin)
+            .ok_or_else(|| {
+                ClientError::InvalidArgument(format!(
+                    "Invalid pin `{}` (expected sha256/ and a base64 SHA-256 hash)",
+                    s
+                ))
+            })
+    }
+}
+
+impl fmt::Display for Pin {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "sha256/{}", STANDARD.encode(self.0))
+    }
+}
+
+/// Whether the chain a server presented, `end_entity` and then
+/// `intermediates`, has a key in `pins`. Pinning an intermediate's key
+/// allows for the server's own key changing, as long as the same CA signs
+/// it.
+pub fn check_pins(
+    end_entity: &[u8],
+    intermediates: &[CertificateDer<'_>],
+    pins: &[Pin],
+) -> Result<(), rustls::Error> {
+    let chain = std::iter::once(end_entity).chain(intermediates.iter().map(|cert| cert.as_ref()));
+    let presented: Vec<Pin> = chain.filter_map(Pin::of).collect();
+    if presented.iter().any(|pin| pins.contains(pin)) {
+        return Ok(());
+    }
+    let presented: Vec<String> = presented.iter().map(Pin::to_string).collect();
+    Err(rustls::Error::General(format!(
+        "the server's certificates match no pinned key (they have {})",
+        presented.join(", ")
+    )))
+}
+
+/// Checks servers' certificates as usual, then against the pinned keys, so
+/// a certificate a trusted CA issued wrongly is refused as well.
+#[derive(Debug)]
+struct PinnedVerifier {
+    verifier: Arc<dyn ServerCertVerifier>,
+    pins: Vec<Pin>,
+}
+
+impl ServerCertVerifier for PinnedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let verified = self.verifier.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+        check_pins(end_entity, intermediates, &self.pins)?;
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verifier.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verifier.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.verifier.supported_verify_schemes()
+    }
+}
+
+/// Logs the certificates a server presents before checking them, so that
+/// `--debug-connection` shows them even when they are refused.
+#[derive(Debug)]
+struct LoggedVerifier {
+    verifier: Arc<dyn ServerCertVerifier>,
+}
+
+impl ServerCertVerifier for LoggedVerifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let chain = std::iter::once(end_entity).chain(intermediates);
+        for (depth, cert) in chain.enumerate() {
+            let summary = describe_certificate(cert).unwrap_or_else(|| "unreadable".to_string());
+            log::debug!(target: CONNECTION_LOG, "certificate {}: {}", depth, summary);
+        }
+        let verified = self.verifier.verify_server_cert(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        );
+        if let Err(e) = &verified {
+            log::debug!(target: CONNECTION_LOG, "certificate check failed: {}", e);
+        }
+        verified
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verifier.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.verifier.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.verifier.supported_verify_schemes()
+    }
+}
+
+/// What a client with `options` offers a server, as in `TLS 1.2 or later,
+/// every cipher suite, session tickets`.
+pub fn describe_options(options: &TlsOptions) -> String {
+    let version = match options.min_version {
+        TlsVersion::Tls12 => "TLS 1.2 or later",
+        TlsVersion::Tls13 => "TLS 1.3",
+    };
+    let suites = match options.cipher_suites.is_empty() {
+        true => "every cipher suite".to_string(),
+        false => {
+            let names: Vec<_> = options
+                .cipher_suites
+                .iter()
+                .map(|suite| suite.as_str().unwrap_or("unknown"))
+                .collect();
+            format!("cipher suites {}", names.join(" "))
+        }
+    };
+    let mut described = format!("{}, {}", version, suites);
+    if options.session_tickets {
+        described.push_str(", session tickets");
+    }
+    if options.client_cert.is_some() {
+        described.push_str(", a client certificate");
+    }
+    if !options.pins.is_empty() {
+        described.push_str(&format!(", {} pinned keys", options.pins.len()));
+    }
+    described
+}
+
+/// What was agreed on in the handshake of `connection`, as in `TLSv1_3,
+/// TLS13_AES_256_GCM_SHA384, no ALPN`.
+pub fn describe_connection(connection: &rustls::ClientConnection) -> String {
+    let version = connection
+        .protocol_version()
+        .map_or("unknown version".to_string(), |version| {
+            format!("{:?}", version)
+        });
+    let suite = connection
+        .negotiated_cipher_suite()
+        .and_then(|suite| suite.suite().as_str())
+        .unwrap_or("unknown cipher suite");
+    let alpn = match connection.alpn_protocol() {
+        Some(protocol) => format!("ALPN {}", String::from_utf8_lossy(protocol)),
+        None => "no ALPN".to_string(),
+    };
+    format!("{}, {}, {}", version, suite, alpn)
+}
+
+/// A one-line summary of DER certificate `cert`: whom it is for, who issued
+/// it, when it is valid and its key's pin.
+pub fn describe_certificate(cert: &[u8]) -> Option<String> {
+    let fields = tbs_fields(cert)?;
+    let (_, validity, _) = der_element(fields.validity)?;
+    let (not_before, rest) = time(validity)?;
+    let (not_after, _) = time(rest)?;
+    Some(format!(
+        "{}, issued by {}, valid {} to {}, {}",
+        name(fields.subject)?,
+        name(fields.issuer)?,
+        not_before,
+        not_after,
+        Pin::of(cert)?
+    ))
+}
+
+/// The fields of a certificate's TBSCertificate (RFC 5280, section 4.1) up
+/// to its key, each with its DER header.
+struct TbsFields<'a> {
+    issuer: &'a [u8],
+    validity: &'a [u8],
+    subject: &'a [u8],
+    public_key_info: &'a [u8],
+}
+
+fn tbs_fields(cert: &[u8]) -> Option<TbsFields<'_>> {
+    let (_, certificate, _) = der_element(cert)?;
+    let (_, tbs, _) = der_element(certificate)?;
+    let mut fields = tbs;
+    // The version is explicitly tagged [0], and absent from v1 certificates
+    if fields.first() == Some(&0xa0) {
+        fields = der_element(fields)?.2;
+    }
+    let mut next = || {
+        let (tag, _, rest) = der_element(fields)?;
+        let field = &fields[..fields.len() - rest.len()];
+        fields = rest;
+        Some((tag, field))
+    };
+    // The serial number and signature algorithm come first
+    next()?;
+    next()?;
+    let (_, issuer) = next()?;
+    let (_, validity) = next()?;
+    let (_, subject) = next()?;
+    let (tag, public_key_info) = next()?;
+    (tag == 0x30).then_some(TbsFields {
+        issuer,
+        validity,
+        subject,
+        public_key_info,
+    })
+}
+
+/// The DER-encoded SubjectPublicKeyInfo of DER certificate `cert`.
+fn public_key_info(cert: &[u8]) -> Option<&[u8]> {
+    Some(tbs_fields(cert)?.public_key_info)
+}
+
+/// The common attributes of an X.501 name, as in `CN=imap.gmail.com`.
+fn name(der: &[u8]) -> Option<String> {
+    const ATTRIBUTES: [(&[u8], &str); 4] = [
+        (&[0x55, 0x04, 0x03], "CN"),
+        (&[0x55, 0x04, 0x0b], "OU"),
+        (&[0x55, 0x04, 0x0a], "O"),
+        (&[0x55, 0x04, 0x06], "C"),
+    ];
+    let (_, mut sets, _) = der_element(der)?;
+    let mut parts = Vec::new();
+    while !sets.is_empty() {
+        let (_, set, rest) = der_element(sets)?;
+        sets = rest;
+        let (_, attribute, _) = der_element(set)?;
+        let (_, oid, value) = der_element(attribute)?;
+        let (tag, value, _) = der_element(value)?;
+        let Some((_, label)) = ATTRIBUTES.iter().find(|(known, _)| *known == oid) else {
+            continue;
+        };
+        let value = match tag {
+            // BMPString, which is UTF-16
+            0x1e => {
+                let units: Vec<u16> = value
+                    .chunks_exact(2)
+                    .map(|pair| u16::from_be_bytes([pair[0], pair[1]]))
+                    .collect();
+                String::from_utf16_lossy(&units)
+            }
+            _ => String::from_utf8_lossy(value).into_owned(),
+        };
+        parts.push((*label, value));
+    }
+    // Most specific first, as certificates are usually shown
+    parts.sort_by_key(|(label, _)| ATTRIBUTES.iter().position(|(_, known)| known == label));
+    let parts: Vec<String> = parts
+        .iter()
+        .map(|(label, value)| format!("{}={}", label, value))
+        .collect();
+    Some(match parts.is_empty() {
+        true => "(no name)".to_string(),
+        false => parts.join(", "),
+    })
+}
+
+/// The UTCTime or GeneralizedTime `der` starts with, as `2025-01-31
+/// 23:59:59 UTC`, and what follows it.
+fn time(der: &[u8]) -> Option<(String, &[u8])> {
+    let (tag, value, rest) = der_element(der)?;
+    let value = std::str::from_utf8(value).ok()?;
+    let full = match tag {
+        0x17 => {
+            let year: u32 = value.get(..2)?.parse().ok()?;
+            let century = if year >= 50 { "19" } else { "20" };
+            format!("{}{}", century, value)
+        }
+        0x18 => value.to_string(),
+        _ => return None,
+    };
+    let digits = full.get(..14)?;
+    Some((
+        format!(
+            "{}-{}-{} {}:{}:{} UTC",
+            &digits[..4],
+            &digits[4..6],
+            &digits[6..8],
+            &digits[8..10],
+            &digits[10..12],
+            &digits[12..14]
+        ),
+        rest,
+    ))
+}
+
+/// The tag and contents of the DER element `der` starts with, and what
+/// follows it.
+fn der_element(der: &[u8]) -> Option<(u8, &[u8], &[u8])> {
+    let tag = *der.first()?;
+    let first = *der.get(1)?;
+    let (len, header) = match first {
+        0..=0x7f => (first as usize, 2),
+        0x81..=0x84 => {
+            let count = (first & 0x7f) as usize;
+            let bytes = der.get(2..2 + count)?;
+            let len = bytes.iter().fold(0usize, |len, &b| len << 8 | b as usize);
+            (len, 2 + count)
+        }
+        _ => return None,
+    };
+    let contents = der.get(header..header.checked_add(len)?)?;
+    Some((tag, contents, &der[header + len..]))
+}
@@ -0,0 +1,122 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// How much of a literal's contents a trace shows.
+pub const LITERAL_HEAD: usize = 80;
+
+/// Numbers connections across every trace of a run, so that accounts
+/// tracing to the same file don't share numbers.
+static CONNECTIONS: AtomicU32 = AtomicU32::new(0);
+
+/// A protocol trace: every line sent and received over IMAP connections,
+/// one `timestamp connection direction: text` line each, where the
+/// direction is `C` for the client, `S` for the server and `*` for notes
+/// such as which server a connection went to.
+///
+/// Commands are traced as [`crate::session::Session`] logs them, with the
+/// password of LOGIN and the initial response of AUTHENTICATE masked.
+/// Literals show their length and first [`LITERAL_HEAD`] bytes only, so a
+/// trace of a whole fetch stays small and holds no message bodies.
+///
+/// The trace is for debugging, so a failure to write it is a warning rather
+/// than an error, given once.
+pub struct WireTrace {
+    path: PathBuf,
+    file: Mutex<Option<File>>,
+    failed: std::sync::Once,
+}
+
+impl WireTrace {
+    /// A trace appending to `path`, which is created on the first line.
+    pub fn new(path: PathBuf) -> Self {
+        WireTrace {
+            path,
+            file: Mutex::new(None),
+            failed: std::sync::Once::new(),
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// Numbers a new connection, noting which server it went to.
+    pub fn open_connection(&self, server: &str) -> u32 {
+        let connection = CONNECTIONS.fetch_add(1, Ordering::Relaxed) + 1;
+        self.record(connection, '*', &format!("connected to {}", server));
+        connection
+    }
+
+    /// Traces `text` sent by the client on `connection`.
+    pub fn sent(&self, connection: u32, text: &str) {
+        self.record(connection, 'C', text);
+    }
+
+    /// Traces a line received on `connection`, without its CRLF.
+    pub fn received(&self, connection: u32, line: &[u8]) {
+        let line = line.strip_suffix(b"\r\n").unwrap_or(line);
+        self.record(connection, 'S', &escape(line));
+    }
+
+    /// Traces a literal of `len` bytes received on `connection`, starting
+    /// with `head`.
+    pub fn received_literal(&self, connection: u32, len: usize, head: &[u8]) {
+        let head = &head[..head.len().min(LITERAL_HEAD)];
+        let more = if len > head.len() { "..." } else { "" };
+        let text = format!("<literal of {} bytes: {}{}>", len, escape(head), more);
+        self.record(connection, 'S', &text);
+    }
+
+    fn record(&self, connection: u32, direction: char, text: &str) {
+        let timestamp = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default();
+        let line = format!(
+            "{}.{:03} {} {}: {}\n",
+            timestamp.as_secs(),
+            timestamp.subsec_millis(),
+            connection,
+            direction,
+            text
+        );
+        let mut file = self.file.lock().unwrap_or_else(|e| e.into_inner());
+        let written = match file.as_mut() {
+            Some(file) => file.write_all(line.as_bytes()),
+            None => OpenOptions::new()
+                .create(true)
+                .append(true)
+                .open(&self.path)
+                .and_then(|mut opened| {
+                    opened.write_all(line.as_bytes())?;
+                    *file = Some(opened);
+                    Ok(())
+                }),
+        };
+        if let Err(e) = written {
+            self.failed.call_once(|| {
+                log::warn!("Cannot write the IMAP trace {}: {}", self.path.display(), e)
+            });
+        }
+    }
+}
+
+/// `bytes` as text, with control characters escaped so each traced line
+/// stays one line and shows exactly what was sent.
+fn escape(bytes: &[u8]) -> String {
+    let mut escaped = String::with_capacity(bytes.len());
+    for c in String::from_utf8_lossy(bytes).chars() {
+        match c {
+            '\r' => escaped.push_str("\\r"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            '\\' => escaped.push_str("\\\\"),
+            c if c.is_control() => escaped.push_str(&format!("\\x{:02x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
@@ -0,0 +1,180 @@
+use ratatui::crossterm::event::{self, Event, KeyCode, KeyEventKind};
+use ratatui::layout::{Constraint, Layout};
+use ratatui::style::{Color, Style, Stylize};
+use ratatui::text::Line;
+use ratatui::widgets::{Block, Gauge, List, ListItem, Paragraph};
+use ratatui::Frame;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+use crate::control::Control;
+use crate::progress::Progress;
+
+/// One account shown in the TUI.
+pub struct TuiAccount {
+    pub progress: Arc<Progress>,
+    pub control: Arc<Control>,
+}
+
+/// Shows live progress while `fetch` runs, until it completes or the user
+/// quits. Returns the fetch's output, or `None` if the user quit first.
+///
+/// Keys: `p` pauses/resumes, `+`/`-` change the number of connections per
+/// account, `q` quits.
+pub async fn run<F: Future>(
+    accounts: Vec<TuiAccount>,
+    fetch: F,
+) -> std::io::Result<Option<F::Output>> {
+    let mut terminal = ratatui::init();
+    let result = drive(&mut terminal, &accounts, fetch).await;
+    ratatui::restore();
+    result
+}
+
+async fn drive<F: Future>(
+    terminal: &mut ratatui::DefaultTerminal,
+    accounts: &[TuiAccount],
+    fetch: F,
+) -> std::io::Result<Option<F::Output>> {
+    let mut fetch = std::pin::pin!(fetch);
+    loop {
+        terminal.draw(|frame| draw(frame, accounts))?;
+
+        while event::poll(Duration::ZERO)? {
+            let Event::Key(key) = event::read()? else {
+                continue;
+            };
+            if key.kind != KeyEventKind::Press {
+                continue;
+            }
+            match key.code {
+                KeyCode::Char('q') | KeyCode::Esc => return Ok(None),
+                KeyCode::Char('p') | KeyCode::Char(' ') => {
+                    for account in accounts {
+                        if account.control.is_paused() {
+                            account.control.resume();
+                        } else {
+                            account.control.pause();
+                        }
+                    }
+                }
+                KeyCode::Char('+') | KeyCode::Char('=') => {
+                    for account in accounts {
+                        let current = account.control.max_concurrent();
+                        account.control.set_max_concurrent(current + 1);
+                    }
+                }
+                KeyCode::Char('-') => {
+                    for account in accounts {
+                        let current = account.control.max_concurrent();
+                        account
+                            .control
+                            .set_max_concurrent(current.saturating_sub(1));
+                    }
+                }
+                _ => {}
+            }
+        }
+
+        tokio::select! {
+            output = &mut fetch => return Ok(Some(output)),
+            _ = tokio::time::sleep(Duration::from_millis(200)) => {}
+        }
+    }
+}
+
+fn draw(frame: &mut Frame, accounts: &[TuiAccount]) {
+    // Each account, followed by the folders it is still fetching
+    let rows: Vec<ListItem> = accounts
+        .iter()
+        .flat_map(|a| {
+            let p = &a.progress;
+            let mut line = format!(
+                "{}  saved {}  skipped {}  of {}  failed batches {}  connections {}",
+                p.account(),
+                p.saved(),
+                p.skipped(),
+                p.total(),
+                p.failed_batches(),
+                a.control.max_concurrent()
+            );
+            if a.control.is_paused() {
+                line.push_str("  [paused]");
+            }
+            let folders =
+                a.progress
+                    .unfinished_folders()
+                    .into_iter()
+                    .map(|(folder, done, total)| {
+                        ListItem::new(format!("  {}  {}/{}", folder, done, total))
+                    });
+            std::iter::once(ListItem::new(line)).chain(folders)
+        })
+        .collect();
+
+    let [gauge_area, accounts_area, activity_area, errors_area, help_area] = Layout::vertical([
+        Constraint::Length(3),
+        Constraint::Length(rows.len() as u16 + 2),
+        Constraint::Min(5),
+        Constraint::Length(8),
+        Constraint::Length(1),
+    ])
+    .areas(frame.area());
+
+    let done: u32 = accounts
+        .iter()
+        .map(|a| a.progress.saved() + a.progress.skipped())
+        .sum();
+    let total: u32 = accounts.iter().map(|a| a.progress.total()).sum();
+    let ratio = if total == 0 {
+        0.0
+    } else {
+        (done as f64 / total as f64).min(1.0)
+    };
+    frame.render_widget(
+        Gauge::default()
+            .block(Block::bordered().title(" Overall progress "))
+            .gauge_style(Style::default().fg(Color::Green))
+            .ratio(ratio)
+            .label(format!("{}/{}", done, total)),
+        gauge_area,
+    );
+
+    frame.render_widget(
+        List::new(rows).block(Block::bordered().title(" Accounts ")),
+        accounts_area,
+    );
+
+    let activity: Vec<ListItem> = accounts
+        .iter()
+        .flat_map(|a| {
+            let account = a.progress.account().to_string();
+            a.progress
+                .activities()
+                .into_iter()
+                .map(move |activity| ListItem::new(format!("{}  {}", account, activity)))
+        })
+        .collect();
+    frame.render_widget(
+        List::new(activity).block(Block::bordered().title(" Connections ")),
+        activity_area,
+    );
+
+    let errors: Vec<ListItem> = accounts
+        .iter()
+        .flat_map(|a| a.progress.recent_errors())
+        .rev()
+        .map(|error| ListItem::new(Line::from(error).red()))
+        .collect();
+    frame.render_widget(
+        List::new(errors).block(Block::bordered().title(" Recent errors ")),
+        errors_area,
+    );
+
+    frame.render_widget(
+        Paragraph::new("p pause/resume   + more connections   - fewer connections   q quit")
+            .dark_gray(),
+        help_area,
+    );
+}
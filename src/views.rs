@@ -0,0 +1,141 @@
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use crate::error_imap::ClientError;
+use crate::message::{civil_from_days, format_timestamp, Address};
+use crate::storage::Organize;
+
+/// Directory of the content-addressed store, under the archive root.
+pub const OBJECTS_DIR: &str = "objects";
+/// Directory of the views over it, under the archive root.
+pub const VIEWS_DIR: &str = "views";
+
+/// A way of browsing a content-addressed archive: a tree of links to the
+/// stored messages, under `views/<name>/`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum View {
+    /// By folder or label, with the usual `email_NNNNN` names.
+    Label,
+    /// By year and month received.
+    Date,
+    /// By From address.
+    Sender,
+}
+
+/// What view links are named and placed by.
+pub struct ViewEntry<'a> {
+    /// Directory of the message's folder, relative to the archive root;
+    /// empty for INBOX.
+    pub folder: &'a Path,
+    /// Name in the folder, such as `email_00012`.
+    pub stem: &'a str,
+    pub hash: &'a str,
+    pub extension: &'a str,
+    /// When it was received (or else sent), as a Unix timestamp.
+    pub date: Option<i64>,
+    pub sender: Option<&'a Address>,
+}
+
+impl View {
+    pub fn name(self) -> &'static str {
+        match self {
+            View::Label => "label",
+            View::Date => "date",
+            View::Sender => "sender",
+        }
+    }
+
+    /// Where the link for `entry` goes, under the archive root `root`.
+    pub fn link_path(self, root: &Path, entry: &ViewEntry) -> PathBuf {
+        let dir = root.join(VIEWS_DIR).join(self.name());
+        // Outside the label view, messages from several folders meet, so they
+        // are named by date and hash instead
+        let name = match entry.date {
+            Some(date) => format!("{}_{}", format_timestamp(date), &entry.hash[..12]),
+            None => entry.hash[..12].to_string(),
+        };
+        let file = |stem: &str| format!("{}.{}", stem, entry.extension);
+        match self {
+            View::Label if entry.folder.as_os_str().is_empty() => {
+                dir.join("INBOX").join(file(entry.stem))
+            }
+            View::Label => dir.join(entry.folder).join(file(entry.stem)),
+            View::Date => match entry.date {
+                Some(date) => {
+                    let (year, month, _) = civil_from_days(date.div_euclid(86_400));
+                    dir.join(format!("{:04}", year))
+                        .join(format!("{:02}", month))
+                        .join(file(&name))
+                }
+                None => dir.join("unknown").join(file(&name)),
+            },
+            View::Sender => dir
+                .join(Organize::BySender.directory(entry.sender))
+                .join(file(&name)),
+        }
+    }
+}
+
+impl FromStr for View {
+    type Err = ClientError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.trim().to_ascii_lowercase().as_str() {
+            "label" | "labels" | "folder" => Ok(View::Label),
+            "date" => Ok(View::Date),
+            "sender" => Ok(View::Sender),
+            other => Err(ClientError::InvalidArgument(format!(
+                "Unknown view `{}` (expected label, date or sender)",
+                other
+            ))),
+        }
+    }
+}
+
+/// Parses a comma-separated list of views, such as `label,date,sender`.
+pub fn parse_views(s: &str) -> Result<Vec<View>, ClientError> {
+    let mut views = Vec::new();
+    for view in s.split(',').map(str::parse) {
+        let view = view?;
+        if !views.contains(&view) {
+            views.push(view);
+        }
+    }
+    Ok(views)
+}
+
+/// Path in the content-addressed store of the message with SHA-256 `hash`,
+/// fanned out by the hash's first two digits.
+pub fn object_path(root: &Path, hash: &str, extension: &str) -> PathBuf {
+    root.join(OBJECTS_DIR)
+        .join(&hash[..2])
+        .join(format!("{}.{}", hash, extension))
+}
+
+/// Links `link` to `object`, both under `root`. The link is relative, so the
+/// archive can be moved. Where symlinks can't be made (Windows without the
+/// privilege, some network filesystems) a hard link is made instead. An
+/// existing link is left alone.
+pub fn link_object(root: &Path, object: &Path, link: &Path) -> std::io::Result<()> {
+    if link.symlink_metadata().is_ok() {
+        return Ok(());
+    }
+    let parent = link.parent().unwrap_or(root);
+    std::fs::create_dir_all(parent)?;
+    let depth = parent
+        .strip_prefix(root)
+        .map_or(0, |p| p.components().count());
+    let mut target: PathBuf = std::iter::repeat_n("..", depth).collect();
+    target.push(object.strip_prefix(root).unwrap_or(object));
+
+    #[cfg(unix)]
+    let linked = std::os::unix::fs::symlink(&target, link);
+    #[cfg(windows)]
+    let linked = std::os::windows::fs::symlink_file(&target, link);
+    #[cfg(not(any(unix, windows)))]
+    let linked: std::io::Result<()> = Err(std::io::ErrorKind::Unsupported.into());
+    linked.or_else(|e| {
+        log::debug!("Hard linking {} instead: {}", link.display(), e);
+        std::fs::hard_link(object, link)
+    })
+}
@@ -0,0 +1,188 @@
+use std::fmt;
+
+use crate::error_imap::ClientError;
+use crate::message::{civil_from_days, days_from_civil};
+
+const SECONDS_PER_DAY: i64 = 86_400;
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+/// Which messages to fetch by when the server received them: from `since`,
+/// inclusive, up to `before`, exclusive, as Unix timestamps.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DateRange {
+    pub since: Option<i64>,
+    pub before: Option<i64>,
+}
+
+impl DateRange {
+    pub fn is_empty(&self) -> bool {
+        self.since.is_none() && self.before.is_none()
+    }
+
+    pub fn contains(&self, timestamp: i64) -> bool {
+        self.since.is_none_or(|since| timestamp >= since)
+            && self.before.is_none_or(|before| timestamp < before)
+    }
+
+    /// IMAP SEARCH keys for the range, or `None` when it is unbounded.
+    ///
+    /// SEARCH compares whole days in the server's time zone, which may be up
+    /// to a day off from UTC either way, so the keys widen the range by that
+    /// much: they match every message in it and some just outside, which
+    /// [`DateRange::contains`] then drops using the exact INTERNALDATE.
+    pub fn search_keys(&self) -> Option<String> {
+        let mut keys = Vec::new();
+        if let Some(since) = self.since {
+            keys.push(format!("SINCE {}", imap_date(since - SECONDS_PER_DAY)));
+        }
+        if let Some(before) = self.before {
+            // The last instant in range, a day later, and BEFORE is exclusive
+            keys.push(format!(
+                "BEFORE {}",
+                imap_date(before - 1 + 2 * SECONDS_PER_DAY)
+            ));
+        }
+        (!keys.is_empty()).then(|| keys.join(" "))
+    }
+
+    /// Splits the range into calendar months (UTC) for a mailbox whose first
+    /// message was received at `first`, up to `now`. The first window also
+    /// holds anything received earlier, such as imported mail, and the last
+    /// anything later, so together they cover the whole range.
+    pub fn months(&self, first: i64, now: i64) -> Vec<DateRange> {
+        let end = self.before.unwrap_or(now);
+        let start = self.since.map_or(first, |since| since.max(first)).min(end);
+        let mut windows = Vec::new();
+        let mut since = self.since;
+        let mut boundary = next_month(start);
+        while boundary < end {
+            windows.push(DateRange {
+                since,
+                before: Some(boundary),
+            });
+            since = Some(boundary);
+            boundary = next_month(boundary);
+        }
+        windows.push(DateRange {
+            since,
+            before: self.before,
+        });
+        windows
+    }
+}
+
+impl fmt::Display for DateRange {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match (self.since, self.before) {
+            (Some(since), Some(before)) => {
+                write!(f, "{} to {}", iso_date(since), iso_date(before))
+            }
+            (Some(since), None) => write!(f, "from {}", iso_date(since)),
+            (None, Some(before)) => write!(f, "before {}", iso_date(before)),
+            (None, None) => write!(f, "any date"),
+        }
+    }
+}
+
+/// Midnight UTC on the first of the month after the one `timestamp` is in.
+fn next_month(timestamp: i64) -> i64 {
+    let (year, month, _) = civil_from_days(timestamp.div_euclid(SECONDS_PER_DAY));
+    let (year, month) = if month == 12 {
+        (year + 1, 1)
+    } else {
+        (year, month + 1)
+    };
+    days_from_civil(year, month, 1) * SECONDS_PER_DAY
+}
+
+/// `timestamp` as `2024-03-05`, or `2024-03-05T14:30:00Z` when it isn't
+/// midnight UTC.
+fn iso_date(timestamp: i64) -> String {
+    let (year, month, day) = civil_from_days(timestamp.div_euclid(SECONDS_PER_DAY));
+    let seconds = timestamp.rem_euclid(SECONDS_PER_DAY);
+    if seconds == 0 {
+        return format!("{}-{:02}-{:02}", year, month, day);
+    }
+    format!(
+        "{}-{:02}-{:02}T{:02}:{:02}:{:02}Z",
+        year,
+        month,
+        day,
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60
+    )
+}
+
+/// The UTC day of `timestamp` as an IMAP date, e.g. `5-Mar-2024`.
+pub fn imap_date(timestamp: i64) -> String {
+    let (year, month, day) = civil_from_days(timestamp.div_euclid(SECONDS_PER_DAY));
+    format!("{}-{}-{}", day, MONTHS[month as usize - 1], year)
+}
+
+/// Parses a `--since` or `--before` bound: a date, `2024-03-05`, meaning
+/// midnight UTC, or a date and time, `2024-03-05T14:30:00`, in UTC unless it
+/// ends in an offset such as `+01:00`. Seconds are optional.
+pub fn parse_bound(value: &str) -> Result<i64, ClientError> {
+    let invalid = || {
+        ClientError::InvalidArgument(format!(
+            "Invalid date `{}` (expected YYYY-MM-DD or YYYY-MM-DDTHH:MM[:SS][Z|±HH:MM])",
+            value
+        ))
+    };
+    let (date, time) = match value.split_once(['T', 't', ' ']) {
+        Some((date, time)) => (date, Some(time)),
+        None => (value, None),
+    };
+    let mut parts = date.splitn(3, '-').map(str::parse::<u32>);
+    let (Some(Ok(year)), Some(Ok(month)), Some(Ok(day))) =
+        (parts.next(), parts.next(), parts.next())
+    else {
+        return Err(invalid());
+    };
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+        return Err(invalid());
+    }
+    let mut timestamp = days_from_civil(year as i64, month, day) * SECONDS_PER_DAY;
+    let Some(time) = time else {
+        return Ok(timestamp);
+    };
+
+    let (clock, offset) = match time.find(['Z', 'z', '+', '-']) {
+        Some(at) => time.split_at(at),
+        None => (time, ""),
+    };
+    let mut fields = clock.splitn(3, ':').map(str::parse::<i64>);
+    let (Some(Ok(hour)), Some(Ok(minute))) = (fields.next(), fields.next()) else {
+        return Err(invalid());
+    };
+    let second = match fields.next() {
+        Some(Ok(second)) => second,
+        Some(Err(_)) => return Err(invalid()),
+        None => 0,
+    };
+    if hour > 23 || minute > 59 || second > 60 {
+        return Err(invalid());
+    }
+    timestamp += hour * 3600 + minute * 60 + second;
+
+    match offset.split_at_checked(1) {
+        None | Some(("Z" | "z", "")) => Ok(timestamp),
+        Some((sign @ ("+" | "-"), zone)) => {
+            let (hours, minutes) = zone.split_once(':').ok_or_else(invalid)?;
+            let (Ok(hours), Ok(minutes)) = (hours.parse::<i64>(), minutes.parse::<i64>()) else {
+                return Err(invalid());
+            };
+            let offset = hours * 3600 + minutes * 60;
+            Ok(if sign == "+" {
+                timestamp - offset
+            } else {
+                timestamp + offset
+            })
+        }
+        _ => Err(invalid()),
+    }
+}
@@ -0,0 +1,31 @@
+use std::sync::OnceLock;
+use tokio::sync::Semaphore;
+
+use crate::error_imap::ClientError;
+
+/// Runs CPU-heavy `work`, such as message processors, plugins or PDF
+/// rendering, on tokio's blocking threads, at most one job per core at a
+/// time. The tasks reading responses off connections then never wait behind
+/// it for a worker thread.
+pub async fn run<T, F>(work: F) -> Result<T, ClientError>
+where
+    F: FnOnce() -> T + Send + 'static,
+    T: Send + 'static,
+{
+    let _slot = slots()
+        .acquire()
+        .await
+        .map_err(|e| ClientError::JoinError(Box::new(e)))?;
+    tokio::task::spawn_blocking(work)
+        .await
+        .map_err(|e| ClientError::JoinError(Box::new(e)))
+}
+
+/// Shared by every account of the run, so they don't oversubscribe the
+/// cores between them.
+fn slots() -> &'static Semaphore {
+    static SLOTS: OnceLock<Semaphore> = OnceLock::new();
+    SLOTS.get_or_init(|| {
+        Semaphore::new(std::thread::available_parallelism().map_or(4, |cores| cores.get()))
+    })
+}
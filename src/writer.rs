@@ -0,0 +1,264 @@
+use std::collections::VecDeque;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, MutexGuard};
+
+use crate::error_imap::ClientError;
+use crate::storage::set_modified;
+
+/// Largest file a [`WriteGroup`] holds back; larger ones are written
+/// straight away.
+pub const GROUPED_FILE_SIZE: usize = 64 << 10;
+
+/// A group is written once it holds this many files, or this many bytes.
+const GROUP_FILES: usize = 256;
+const GROUP_BYTES: usize = 4 << 20;
+
+/// How message files are written.
+pub enum FileWriter {
+    /// Through tokio's blocking thread pool, a system call at a time.
+    Standard,
+    /// Through io_uring on a thread of its own, which submits the opens,
+    /// writes and closes of many files to the kernel together.
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    Uring(uring::Ring),
+    /// Small files are held back in a group, written together.
+    Grouped(Arc<WriteGroup>),
+}
+
+impl FileWriter {
+    /// A writer going through io_uring if `io_uring` is set.
+    #[cfg(all(feature = "uring", target_os = "linux"))]
+    pub fn new(io_uring: bool) -> Self {
+        match io_uring {
+            true => FileWriter::Uring(uring::Ring::start()),
+            false => FileWriter::Standard,
+        }
+    }
+
+    /// Built without io_uring support, so files are written as usual.
+    #[cfg(not(all(feature = "uring", target_os = "linux")))]
+    pub fn new(_io_uring: bool) -> Self {
+        FileWriter::Standard
+    }
+
+    /// Creates the file at `path`, or replaces it, holding `contents`, and
+    /// dates it `modified` if given.
+    pub async fn write(
+        &self,
+        path: &Path,
+        contents: Vec<u8>,
+        modified: Option<i64>,
+    ) -> Result<(), ClientError> {
+        match self {
+            FileWriter::Grouped(group) if contents.len() <= GROUPED_FILE_SIZE => {
+                if group.hold(path, contents, modified) {
+                    group.flush()?;
+                }
+                return Ok(());
+            }
+            FileWriter::Standard | FileWriter::Grouped(_) => {
+                tokio::fs::write(path, contents).await?
+            }
+            #[cfg(all(feature = "uring", target_os = "linux"))]
+            FileWriter::Uring(ring) => ring.write(path, contents).await?,
+        }
+        set_received_time(path, modified);
+        Ok(())
+    }
+}
+
+/// Small files held back to be written together, rather than each as it
+/// comes. They are written once there are enough of them, and at the latest
+/// before the state database commits, so the catalog and checkpoint never
+/// name a file that isn't on disk.
+#[derive(Default)]
+pub struct WriteGroup {
+    held: Mutex<Held>,
+}
+
+#[derive(Default)]
+struct Held {
+    files: VecDeque<HeldFile>,
+    bytes: usize,
+}
+
+struct HeldFile {
+    path: PathBuf,
+    contents: Vec<u8>,
+    modified: Option<i64>,
+}
+
+impl WriteGroup {
+    pub fn new() -> Self {
+        WriteGroup::default()
+    }
+
+    /// Holds `contents` back for `path`, returning whether the group is due
+    /// to be written.
+    pub fn hold(&self, path: &Path, contents: Vec<u8>, modified: Option<i64>) -> bool {
+        let mut held = lock(&self.held);
+        held.bytes += contents.len();
+        held.files.push_back(HeldFile {
+            path: path.to_path_buf(),
+            contents,
+            modified,
+        });
+        held.files.len() >= GROUP_FILES || held.bytes >= GROUP_BYTES
+    }
+
+    /// Files held back, not written yet.
+    pub fn len(&self) -> usize {
+        lock(&self.held).files.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Writes the files held back, in the order they came. Those that
+    /// couldn't be written are still held.
+    pub fn flush(&self) -> Result<(), ClientError> {
+        let mut held = lock(&self.held);
+        while let Some(file) = held.files.front() {
+            std::fs::write(&file.path, &file.contents)?;
+            set_received_time(&file.path, file.modified);
+            if let Some(file) = held.files.pop_front() {
+                held.bytes -= file.contents.len();
+            }
+        }
+        Ok(())
+    }
+
+    /// Drops the files held back without writing them, as when the records
+    /// naming them are rolled back.
+    pub fn discard(&self) {
+        *lock(&self.held) = Held::default();
+    }
+}
+
+/// Dates a saved file by when the server received the message. Only worth a
+/// warning if it fails: the message itself is safely stored.
+pub(crate) fn set_received_time(path: &Path, internal_date: Option<i64>) {
+    if let Some(date) = internal_date {
+        if let Err(e) = set_modified(path, date) {
+            log::warn!(
+                "Failed to set modification time of {}: {}",
+                path.display(),
+                e
+            );
+        }
+    }
+}
+
+/// Held files are whole messages, so a panicked holder leaves nothing
+/// half-done to guard against.
+fn lock<T>(mutex: &Mutex<T>) -> MutexGuard<'_, T> {
+    mutex.lock().unwrap_or_else(|e| e.into_inner())
+}
+
+/// Fails unless this build can write files through io_uring.
+pub fn ensure_io_uring() -> Result<(), ClientError> {
+    if cfg!(all(feature = "uring", target_os = "linux")) {
+        return Ok(());
+    }
+    Err(ClientError::InvalidArgument(
+        "io_uring is not available in this build; rebuild on Linux with --features uring"
+            .to_string(),
+    ))
+}
+
+#[cfg(all(feature = "uring", target_os = "linux"))]
+mod uring {
+    use std::io;
+    use std::path::{Path, PathBuf};
+    use tokio::sync::{mpsc, oneshot};
+    use tokio_uring::buf::IoBuf;
+
+    struct Job {
+        path: PathBuf,
+        contents: Vec<u8>,
+        done: oneshot::Sender<io::Result<()>>,
+    }
+
+    /// A thread running a tokio-uring runtime, which writes each file it
+    /// is sent in a task of its own so their operations share submissions.
+    pub struct Ring {
+        jobs: mpsc::UnboundedSender<Job>,
+    }
+
+    impl Ring {
+        /// Starts the thread. It stops once the ring is dropped.
+        pub fn start() -> Self {
+            let (jobs, queue) = mpsc::unbounded_channel();
+            let started = std::thread::Builder::new()
+                .name("io-uring".to_string())
+                .spawn(move || run(queue));
+            if let Err(e) = started {
+                log::warn!(
+                    "Cannot start the io_uring thread ({}); writing files as usual",
+                    e
+                );
+            }
+            Ring { jobs }
+        }
+
+        pub async fn write(&self, path: &Path, contents: Vec<u8>) -> io::Result<()> {
+            let (done, result) = oneshot::channel();
+            let job = Job {
+                path: path.to_path_buf(),
+                contents,
+                done,
+            };
+            // Without the thread, the file is written the usual way
+            if let Err(mpsc::error::SendError(job)) = self.jobs.send(job) {
+                return tokio::fs::write(&job.path, &job.contents).await;
+            }
+            result
+                .await
+                .unwrap_or_else(|_| Err(io::Error::other("the io_uring thread stopped")))
+        }
+    }
+
+    fn run(mut queue: mpsc::UnboundedReceiver<Job>) {
+        // Kernels before 5.6, and sandboxes filtering system calls, refuse
+        // io_uring; tokio-uring would panic on them
+        if let Err(e) = tokio_uring::uring_builder().build(8) {
+            log::warn!("io_uring is unavailable ({}); writing files as usual", e);
+            while let Some(job) = queue.blocking_recv() {
+                let _ = job.done.send(std::fs::write(&job.path, &job.contents));
+            }
+            return;
+        }
+        tokio_uring::start(async move {
+            while let Some(job) = queue.recv().await {
+                tokio_uring::spawn(async move {
+                    let _ = job.done.send(write(&job.path, job.contents).await);
+                });
+            }
+        });
+    }
+
+    async fn write(path: &Path, contents: Vec<u8>) -> io::Result<()> {
+        let file = tokio_uring::fs::File::create(path).await?;
+        let mut contents = contents;
+        let mut written = 0;
+        while written < contents.len() {
+            let (result, slice) = file
+                .write_at(contents.slice(written..), written as u64)
+                .await;
+            contents = slice.into_inner();
+            match result {
+                Ok(0) => {
+                    let _ = file.close().await;
+                    return Err(io::ErrorKind::WriteZero.into());
+                }
+                Ok(n) => written += n,
+                Err(e) => {
+                    let _ = file.close().await;
+                    return Err(e);
+                }
+            }
+        }
+        file.close().await
+    }
+}
@@ -0,0 +1,50 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, config};
+use imap_client::audit::verify_audit_log;
+use imap_client::client::ImapClient;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+
+#[tokio::test]
+async fn signed_log_verifies_until_altered() {
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: (0..3).map(|i| synthetic_message(i, 200)).collect(),
+    }])
+    .await
+    .unwrap();
+    let dir = archive_dir("audit");
+    let key = dir.join("operator.key");
+    std::fs::write(&key, format!("{}\n", "4f".repeat(32))).unwrap();
+    let log = dir.join("custody.log");
+
+    let config = ImapConfig {
+        audit_log: Some(log.clone()),
+        audit_key: Some(key),
+        ..config(&dir)
+    };
+    ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+
+    let contents = std::fs::read_to_string(&log).unwrap();
+    assert!(contents.contains("\tgreeting * OK"));
+    assert!(contents
+        .contains("\tcapabilities ENABLE ID IMAP4REV1 LITERAL+ MOVE NAMESPACE QUOTA SPECIAL-USE UIDPLUS UTF8=ACCEPT X-GM-EXT-1\t"));
+    assert_eq!(contents.matches("\tsaved INBOX UID ").count(), 3);
+    let report = verify_audit_log(&log).unwrap();
+    assert_eq!(report.signed_by.len(), 1);
+
+    // Dropping a saved message's line breaks the chain from there on
+    let altered: Vec<&str> = contents
+        .lines()
+        .filter(|line| !line.contains("saved INBOX UID 2 "))
+        .collect();
+    std::fs::write(&log, altered.join("\n") + "\n").unwrap();
+    assert!(verify_audit_log(&log).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
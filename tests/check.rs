@@ -0,0 +1,55 @@
+#![cfg(feature = "mock")]
+
+use imap_client::client::ImapClient;
+use imap_client::error_imap::ErrorKind;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+
+fn config() -> ImapConfig {
+    ImapConfig {
+        email: "test@example.com".to_string(),
+        password: "test".to_string(),
+        quiet: true,
+        ..ImapConfig::default()
+    }
+}
+
+#[tokio::test]
+async fn check_lists_capabilities_and_folders() {
+    let server = MockServer::start(vec![
+        MockMailbox {
+            name: "INBOX".to_string(),
+            messages: (0..2).map(|i| synthetic_message(i, 200)).collect(),
+        },
+        MockMailbox {
+            name: "Archive".to_string(),
+            messages: Vec::new(),
+        },
+    ])
+    .await
+    .unwrap();
+
+    let report = ImapClient::new(config(), server.url())
+        .check()
+        .await
+        .unwrap();
+    assert_eq!(report.server, server.url());
+    assert!(report.capabilities.iter().any(|c| c == "UIDPLUS"));
+    assert!(report.capabilities.windows(2).all(|w| w[0] <= w[1]));
+    let folders: Vec<&str> = report.folders.iter().map(|m| m.name.as_str()).collect();
+    assert_eq!(folders, ["INBOX", "Archive"]);
+}
+
+#[tokio::test]
+async fn unreachable_servers_fail_as_network_errors() {
+    // A port nothing listens on any more
+    let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    drop(listener);
+
+    let error = ImapClient::new(config(), addr.to_string())
+        .check()
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Network);
+}
@@ -0,0 +1,187 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, config, inbox};
+use imap_client::args::parse_args;
+use imap_client::checkpoint::{Checkpoint, Quota, SyncSchedule, SyncTimer, CHECKPOINT_FILE};
+use imap_client::client::ImapClient;
+use imap_client::error_imap::ClientError;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use std::time::Duration;
+
+#[test]
+fn state_is_synced_every_so_many_records() {
+    let mut timer = SyncTimer::new(SyncSchedule {
+        records: 3,
+        interval: Duration::from_secs(3600),
+    });
+    let due: Vec<bool> = (0..7).map(|_| timer.is_due()).collect();
+    assert_eq!(due, [false, false, true, false, false, true, false]);
+
+    // A zero interval has every record synced
+    let mut timer = SyncTimer::new(SyncSchedule {
+        records: 100,
+        interval: Duration::ZERO,
+    });
+    assert!(timer.is_due() && timer.is_due());
+}
+
+#[tokio::test]
+async fn synced_checkpoints_cover_the_whole_run() {
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: (0..5).map(|i| synthetic_message(i, 200)).collect(),
+    }])
+    .await
+    .unwrap();
+    let dir = archive_dir("checkpoint-sync");
+    let config = ImapConfig {
+        sync: SyncSchedule {
+            records: 2,
+            interval: Duration::from_secs(60),
+        },
+        ..config(&dir)
+    };
+    ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+
+    // The mock server's UIDVALIDITY is 1
+    let checkpoint = Checkpoint::open(&dir).unwrap();
+    assert_eq!(checkpoint.fetched_uids("INBOX", 1).len(), 5);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn quotas_stop_at_the_first_message_that_does_not_fit() {
+    let mut unlimited = Quota::new(None, None);
+    assert!((0..1000).all(|_| unlimited.take(u64::MAX / 2000)));
+    assert!(!unlimited.is_exhausted());
+
+    let mut messages = Quota::new(Some(2), None);
+    assert_eq!(
+        [1, 1, 1].map(|size| messages.take(size)),
+        [true, true, false]
+    );
+    assert!(messages.is_exhausted());
+
+    // Once one message is left over, smaller ones after it are too, so the
+    // next run continues in order
+    let mut bytes = Quota::new(None, Some(100));
+    assert_eq!(
+        [60, 40, 1, 0].map(|size| bytes.take(size)),
+        [true, true, false, false]
+    );
+    let mut bytes = Quota::new(None, Some(100));
+    assert_eq!(
+        [60, 50, 10].map(|size| bytes.take(size)),
+        [true, false, false]
+    );
+
+    let mut nothing = Quota::new(Some(0), None);
+    assert!(!nothing.take(0));
+    let mut too_big = Quota::new(Some(10), Some(100));
+    assert!(!too_big.take(101) && too_big.is_exhausted());
+}
+
+#[test]
+fn run_limits_must_be_counts_and_sizes() {
+    let parsed = parse_args(
+        ["--max-messages", "25", "--max-bytes", "2G"]
+            .iter()
+            .map(|arg| arg.to_string()),
+    )
+    .unwrap();
+    assert_eq!(
+        (parsed.max_messages, parsed.max_bytes),
+        (Some(25), Some(2 << 30))
+    );
+
+    for given in [
+        ["--max-messages", "many"],
+        ["--max-messages", "-1"],
+        ["--max-messages", "1.5"],
+        ["--max-bytes", "lots"],
+        ["--max-bytes", "2X"],
+    ] {
+        assert!(
+            matches!(
+                parse_args(given.iter().map(|arg| arg.to_string())),
+                Err(ClientError::InvalidArgument(_))
+            ),
+            "{:?}",
+            given
+        );
+    }
+}
+
+#[tokio::test]
+async fn a_message_over_the_byte_limit_is_left_for_later() {
+    let server = inbox(3).await;
+    let dir = archive_dir("checkpoint-bytes");
+    let capped = ImapConfig {
+        max_bytes: Some(100),
+        ..config(&dir)
+    };
+    let summary = ImapClient::new(capped, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!((summary.saved, summary.failed_batches), (0, 0));
+    assert!(Checkpoint::open(&dir)
+        .unwrap()
+        .fetched_uids("INBOX", 1)
+        .is_empty());
+
+    // Without the limit everything is fetched
+    let summary = ImapClient::new(config(&dir), server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 3);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn empty_mailboxes_leave_the_checkpoint_empty() {
+    let server = inbox(0).await;
+    let dir = archive_dir("checkpoint-empty");
+    let capped = ImapConfig {
+        max_messages: Some(1),
+        ..config(&dir)
+    };
+    let summary = ImapClient::new(capped, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!((summary.found, summary.saved), (0, 0));
+    assert!(Checkpoint::open(&dir)
+        .unwrap()
+        .fetched_uids("INBOX", 1)
+        .is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn malformed_legacy_checkpoint_lines_are_skipped() {
+    let dir = archive_dir("checkpoint-legacy");
+    std::fs::write(
+        dir.join(CHECKPOINT_FILE),
+        "1\t7\tINBOX\nnot a line\n1\tx\tINBOX\n\n1\t9\tWork\tand tabs\n",
+    )
+    .unwrap();
+    let checkpoint = Checkpoint::open(&dir).unwrap();
+    assert_eq!(
+        checkpoint
+            .fetched_uids("INBOX", 1)
+            .into_iter()
+            .collect::<Vec<_>>(),
+        [7]
+    );
+    assert_eq!(checkpoint.fetched_uids("Work\tand tabs", 1).len(), 1);
+    assert!(checkpoint.fetched_uids("INBOX", 2).is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,68 @@
+//! Factories shared by the integration tests. Each test file uses some of
+//! them.
+#![allow(dead_code)]
+
+use imap_client::input::ImapConfig;
+#[cfg(feature = "mock")]
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use imap_client::session::Session;
+use std::path::{Path, PathBuf};
+use tokio::io::{AsyncReadExt, AsyncWriteExt, DuplexStream};
+
+/// A fresh, empty directory for the test called `name`. Test files run as
+/// separate processes, so the process id keeps their directories apart.
+pub fn archive_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("imap-test-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/// A mock server with one INBOX of `count` small synthetic messages.
+#[cfg(feature = "mock")]
+pub async fn inbox(count: usize) -> MockServer {
+    MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: (0..count).map(|i| synthetic_message(i, 200)).collect(),
+    }])
+    .await
+    .unwrap()
+}
+
+/// Settings for fetching from the mock server into `dir`, without output.
+pub fn config(dir: &Path) -> ImapConfig {
+    ImapConfig {
+        email: "test@example.com".to_string(),
+        password: "test".to_string(),
+        dir_path: dir.to_path_buf(),
+        quiet: true,
+        ..ImapConfig::default()
+    }
+}
+
+/// [`config`] with an audit log in `dir`.
+pub fn audited(dir: &Path) -> ImapConfig {
+    ImapConfig {
+        audit_log: Some(dir.join("audit.log")),
+        ..config(dir)
+    }
+}
+
+/// A session whose server greeted it with `capabilities`, and the server's end.
+pub async fn greeted(capabilities: &str) -> (Session<DuplexStream>, DuplexStream) {
+    let (client, mut server) = tokio::io::duplex(16 * 1024);
+    server
+        .write_all(format!("* OK [CAPABILITY IMAP4rev1 {}] ready\r\n", capabilities).as_bytes())
+        .await
+        .unwrap();
+    let mut session = Session::new(client);
+    session.read_greeting().await.unwrap();
+    (session, server)
+}
+
+/// Reads the next bytes the client sent, which must be `sent`.
+pub async fn expect(server: &mut DuplexStream, sent: &[u8]) {
+    let mut buf = vec![0u8; sent.len()];
+    server.read_exact(&mut buf).await.unwrap();
+    assert_eq!(String::from_utf8_lossy(&buf), String::from_utf8_lossy(sent));
+}
@@ -0,0 +1,109 @@
+mod common;
+
+use common::archive_dir;
+use imap_client::catalog::Catalog;
+use imap_client::convert::{convert_archive, ArchiveFormat};
+use imap_client::error_imap::ClientError;
+use std::path::Path;
+
+fn message(index: usize) -> Vec<u8> {
+    format!(
+        "From: alice@example.com\n\
+         Subject: Message {}\n\
+         Message-ID: <{}@example.com>\n\
+         \n\
+         From the top\n",
+        index, index
+    )
+    .into_bytes()
+}
+
+fn read_sorted(dir: &Path) -> Vec<String> {
+    let mut names: Vec<String> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    names
+}
+
+#[test]
+fn eml_survives_a_round_trip_through_mbox_and_maildir() {
+    let eml = archive_dir("eml");
+    std::fs::create_dir_all(eml.join("Work")).unwrap();
+    std::fs::write(eml.join("email_00001.eml"), message(1)).unwrap();
+    std::fs::write(eml.join("email_00002.eml"), message(2)).unwrap();
+    std::fs::write(eml.join("Work/email_00001.eml"), message(3)).unwrap();
+
+    let mbox = archive_dir("mbox");
+    let report = convert_archive(&eml, &mbox, ArchiveFormat::Mbox).unwrap();
+    assert_eq!((report.messages, report.folders), (3, 2));
+    let inbox = std::fs::read_to_string(mbox.join("INBOX.mbox")).unwrap();
+    assert_eq!(inbox.matches("From MAILER-DAEMON ").count(), 2);
+    assert!(inbox.contains("\n>From the top\n"));
+
+    let maildir = archive_dir("maildir");
+    convert_archive(&mbox, &maildir, ArchiveFormat::Maildir).unwrap();
+    let separator = if cfg!(windows) { '!' } else { ':' };
+    assert_eq!(
+        read_sorted(&maildir.join("INBOX/cur")),
+        [
+            format!("email_00001{}2,", separator),
+            format!("email_00002{}2,", separator)
+        ]
+    );
+
+    let back = archive_dir("back");
+    convert_archive(&maildir, &back, ArchiveFormat::Eml).unwrap();
+    assert_eq!(
+        std::fs::read(back.join("INBOX/email_00002.eml")).unwrap(),
+        message(2)
+    );
+    assert_eq!(
+        std::fs::read(back.join("Work/email_00001.eml")).unwrap(),
+        message(3)
+    );
+    // The new archive has its own catalog
+    let catalog = Catalog::open(&back).unwrap();
+    assert_eq!(catalog.entries().len(), 3);
+    assert_eq!(catalog.entries()[0].path, "INBOX/email_00001.eml");
+
+    for dir in [eml, mbox, maildir, back] {
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
+
+#[cfg(unix)]
+#[test]
+fn maildir_flags_are_kept() {
+    let source = archive_dir("flags");
+    for sub in ["cur", "new", "tmp"] {
+        std::fs::create_dir_all(source.join(sub)).unwrap();
+    }
+    std::fs::write(source.join("cur/1700000000.abc:2,FS"), message(1)).unwrap();
+    std::fs::write(source.join("new/1700000001.def"), message(2)).unwrap();
+    std::fs::write(source.join("tmp/1700000002.ghi"), message(3)).unwrap();
+
+    let output = archive_dir("flags-out");
+    let report = convert_archive(&source, &output, ArchiveFormat::Maildir).unwrap();
+    assert_eq!(report.messages, 2);
+    assert_eq!(
+        read_sorted(&output.join("cur")),
+        ["email_00001:2,FS", "email_00002:2,"]
+    );
+    std::fs::remove_dir_all(&source).unwrap();
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[test]
+fn output_must_be_empty() {
+    let source = archive_dir("source");
+    let output = archive_dir("taken");
+    std::fs::write(output.join("email_00001.eml"), message(1)).unwrap();
+    assert!(matches!(
+        convert_archive(&source, &output, ArchiveFormat::Eml),
+        Err(ClientError::InvalidArgument(_))
+    ));
+    std::fs::remove_dir_all(&source).unwrap();
+    std::fs::remove_dir_all(&output).unwrap();
+}
@@ -0,0 +1,89 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::archive_dir;
+use imap_client::client::ImapClient;
+use imap_client::error_imap::ClientError;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use imap_client::window::{imap_date, parse_bound, DateRange};
+use std::path::Path;
+
+fn config(dir: &Path, dates: DateRange) -> ImapConfig {
+    ImapConfig {
+        dates,
+        ..common::audited(dir)
+    }
+}
+
+#[test]
+fn bounds_accept_dates_times_and_offsets() {
+    assert_eq!(parse_bound("2024-03-05").unwrap(), 1_709_596_800);
+    assert_eq!(parse_bound("2024-03-05T14:30:00Z").unwrap(), 1_709_649_000);
+    assert_eq!(parse_bound("2024-03-05T14:30").unwrap(), 1_709_649_000);
+    assert_eq!(
+        parse_bound("2024-03-05T15:30:00+01:00").unwrap(),
+        1_709_649_000
+    );
+    assert_eq!(
+        parse_bound("2024-03-05T09:30:00-05:00").unwrap(),
+        1_709_649_000
+    );
+    for invalid in [
+        "5 March",
+        "2024-13-01",
+        "2024-03-05T25:00",
+        "2024-03-05T10:00+1",
+    ] {
+        assert!(
+            matches!(parse_bound(invalid), Err(ClientError::InvalidArgument(_))),
+            "{}",
+            invalid
+        );
+    }
+    assert_eq!(imap_date(1_709_649_000), "5-Mar-2024");
+}
+
+#[test]
+fn search_keys_widen_the_range_by_a_day() {
+    let range = DateRange {
+        since: Some(parse_bound("2024-03-05").unwrap()),
+        before: Some(parse_bound("2024-04-01").unwrap()),
+    };
+    assert_eq!(
+        range.search_keys().as_deref(),
+        Some("SINCE 4-Mar-2024 BEFORE 2-Apr-2024")
+    );
+    assert!(range.contains(parse_bound("2024-03-05").unwrap()));
+    assert!(!range.contains(parse_bound("2024-04-01").unwrap()));
+    assert_eq!(DateRange::default().search_keys(), None);
+}
+
+#[tokio::test]
+async fn boundaries_are_exact() {
+    // Received at noon UTC on 2 to 6 January
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: (0..5).map(|i| synthetic_message(i, 200)).collect(),
+    }])
+    .await
+    .unwrap();
+    let dir = archive_dir("exact");
+    let dates = DateRange {
+        since: Some(parse_bound("2024-01-03T12:00:00Z").unwrap()),
+        before: Some(parse_bound("2024-01-05T11:59:59Z").unwrap()),
+    };
+
+    let summary = ImapClient::new(config(&dir, dates), server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 2);
+    let audit = std::fs::read_to_string(dir.join("audit.log")).unwrap();
+    // The server's answer covers a day either side
+    assert!(audit.contains("UID SEARCH SINCE 2-Jan-2024 BEFORE 7-Jan-2024"));
+    assert!(audit.contains("UID FETCH 1:5 (RFC822.SIZE INTERNALDATE)"));
+    assert!(audit.contains("UID FETCH 2:3 (INTERNALDATE BODY.PEEK[])"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,69 @@
+mod common;
+
+use common::archive_dir;
+use imap_client::dedupe::dedupe_archive;
+
+fn message(body: &str) -> String {
+    format!(
+        "From: alice@example.com\r\n\
+         Message-ID: <plans@example.com>\r\n\
+         Subject: Plans\r\n\
+         \r\n\
+         {}\r\n",
+        body
+    )
+}
+
+#[test]
+fn only_identical_copies_in_one_folder_are_removed() {
+    let dir = archive_dir("dedupe");
+    for folder in ["INBOX", "Work"] {
+        std::fs::create_dir_all(dir.join(folder)).unwrap();
+    }
+    let files = [
+        ("INBOX/email_00001.eml", message("See you there.")),
+        ("INBOX/email_00002.eml", message("See you there.")),
+        ("INBOX/email_00002.txt", "See you there.".to_string()),
+        // A broken generator reusing the Message-ID
+        ("INBOX/email_00003.eml", message("Something else entirely.")),
+        // The same message filed in another folder
+        ("Work/email_00001.eml", message("See you there.")),
+    ];
+    for (path, contents) in &files {
+        std::fs::write(dir.join(path), contents).unwrap();
+    }
+
+    let listed = dedupe_archive(&dir, false).unwrap();
+    assert_eq!(listed.scanned, 4);
+    assert_eq!(
+        listed.groups,
+        [vec![
+            dir.join("INBOX/email_00001.eml"),
+            dir.join("INBOX/email_00002.eml"),
+            dir.join("INBOX/email_00003.eml"),
+        ]]
+    );
+    assert_eq!(
+        listed.differing.iter().collect::<Vec<_>>(),
+        [&dir.join("INBOX/email_00003.eml")]
+    );
+    assert!(dir.join("INBOX/email_00002.eml").exists());
+
+    let report = dedupe_archive(&dir, true).unwrap();
+    assert_eq!(report.removed, 1);
+    assert!(!dir.join("INBOX/email_00002.eml").exists());
+    // The text copy goes with it rather than being left behind
+    assert!(!dir.join("INBOX/email_00002.txt").exists());
+    for kept in [
+        "INBOX/email_00001.eml",
+        "INBOX/email_00003.eml",
+        "Work/email_00001.eml",
+    ] {
+        assert!(dir.join(kept).exists(), "{}", kept);
+    }
+
+    // What is left isn't removed on another pass
+    let again = dedupe_archive(&dir, true).unwrap();
+    assert_eq!((again.scanned, again.removed), (3, 0));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
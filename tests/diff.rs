@@ -0,0 +1,85 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, audited, inbox};
+use imap_client::catalog::Catalog;
+use imap_client::client::{ImapClient, MailboxDiff};
+use imap_client::input::ImapConfig;
+use imap_client::storage::OutputFormat;
+
+#[tokio::test]
+async fn messages_on_one_side_only_are_listed() {
+    let dir = archive_dir("sides");
+    let full = inbox(6).await;
+    let capped = ImapConfig {
+        max_messages: Some(4),
+        ..audited(&dir)
+    };
+    ImapClient::new(capped, full.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+
+    let diffs = ImapClient::new(audited(&dir), full.url())
+        .diff_archive()
+        .await
+        .unwrap();
+    assert_eq!(
+        diffs,
+        [MailboxDiff {
+            mailbox: "INBOX".to_string(),
+            server_only: vec![5, 6],
+            ..Default::default()
+        }]
+    );
+    // Nothing was downloaded to find out
+    let audit = std::fs::read_to_string(dir.join("audit.log")).unwrap();
+    let downloads = audit.lines().filter(|line| line.contains("BODY.PEEK[]"));
+    assert_eq!(downloads.count(), 1);
+
+    // A server that has since lost a message
+    let shrunk = inbox(3).await;
+    let diffs = ImapClient::new(audited(&dir), shrunk.url())
+        .diff_archive()
+        .await
+        .unwrap();
+    assert!(diffs[0].server_only.is_empty());
+    assert_eq!(diffs[0].local_only, [4]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn maildir_flags_are_compared() {
+    let dir = archive_dir("flags");
+    let server = inbox(3).await;
+    let config = || ImapConfig {
+        format: OutputFormat::Maildir,
+        ..audited(&dir)
+    };
+    ImapClient::new(config(), server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    let diffs = ImapClient::new(config(), server.url())
+        .diff_archive()
+        .await
+        .unwrap();
+    assert!(diffs[0].flag_changes.is_empty());
+
+    // Flagged in a mail program since
+    let path = Catalog::open(&dir).unwrap().entries()[0].path.clone();
+    let (unique, flags) = path.rsplit_once(",").unwrap();
+    let flagged = format!("{},F{}", unique, flags);
+    std::fs::rename(dir.join(&path), dir.join(&flagged)).unwrap();
+
+    let diffs = ImapClient::new(config(), server.url())
+        .diff_archive()
+        .await
+        .unwrap();
+    assert_eq!(
+        diffs[0].flag_changes,
+        [(flagged, format!("F{}", flags), flags.to_string())]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,118 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, config, inbox};
+use imap_client::client::ImapClient;
+use imap_client::diskspace::{ensure_free_space, format_size, free_space, parse_size};
+use imap_client::error_imap::{ClientError, ErrorKind};
+use imap_client::input::ImapConfig;
+
+#[test]
+fn sizes_are_parsed_in_powers_of_1024() {
+    for (value, bytes) in [
+        ("0", 0),
+        ("500", 500),
+        ("500B", 500),
+        ("64K", 64 << 10),
+        ("64 kib", 64 << 10),
+        ("100MB", 100 << 20),
+        (" 2G ", 2 << 30),
+        ("1T", 1 << 40),
+    ] {
+        assert_eq!(parse_size(value).unwrap(), bytes, "{:?}", value);
+    }
+    for value in ["", "M", "-1", "1.5G", "10X", "12 34", "99999999999T"] {
+        assert!(
+            matches!(parse_size(value), Err(ClientError::InvalidArgument(_))),
+            "{:?}",
+            value
+        );
+    }
+    assert_eq!(format_size(0), "0 B");
+    assert_eq!(format_size(1023), "1023 B");
+    assert_eq!(format_size(1536), "1.5 KiB");
+    assert_eq!(format_size(100 << 20), "100.0 MiB");
+    assert_eq!(format_size(u64::MAX), "16777216.0 TiB");
+}
+
+#[test]
+fn too_little_space_is_refused_with_what_was_needed() {
+    let dir = archive_dir("diskspace-check");
+    let available = free_space(&dir).unwrap();
+    ensure_free_space(&dir, 0, 0).unwrap();
+
+    // Space for the messages alone, but not the reserve on top
+    let error = ensure_free_space(&dir, available, 1).unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Storage);
+    match &error {
+        ClientError::InsufficientSpace { path, needed, .. } => {
+            assert_eq!(*path, dir);
+            assert_eq!(*needed, format_size(available + 1))
+        }
+        other => panic!("expected too little space, got {:?}", other),
+    }
+    // Sizes too large to add up don't wrap around
+    assert!(ensure_free_space(&dir, u64::MAX, u64::MAX).is_err());
+
+    let missing = ensure_free_space(&dir.join("missing"), 0, 0).unwrap_err();
+    assert!(matches!(missing, ClientError::Io { .. }));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn runs_stop_before_downloading_when_space_is_short() {
+    let server = inbox(3).await;
+    let dir = archive_dir("diskspace-run");
+    let config = ImapConfig {
+        min_free_space: u64::MAX / 2,
+        ..config(&dir)
+    };
+
+    let error = ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(error.root(), ClientError::InsufficientSpace { .. }),
+        "{}",
+        error
+    );
+    let saved = walk(&dir);
+    assert!(
+        !saved
+            .iter()
+            .any(|path| path.extension().is_some_and(|ext| ext == "eml")),
+        "{:?}",
+        saved
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn empty_mailboxes_need_only_the_reserve() {
+    let server = inbox(0).await;
+    let dir = archive_dir("diskspace-empty");
+    let summary = ImapClient::new(config(&dir), server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(
+        (summary.found, summary.saved, summary.failed_batches),
+        (0, 0, 0)
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+fn walk(dir: &std::path::Path) -> Vec<std::path::PathBuf> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(walk(&path));
+        } else {
+            files.push(path);
+        }
+    }
+    files
+}
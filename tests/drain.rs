@@ -0,0 +1,89 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, audited, inbox};
+use imap_client::audit::verify_audit_log;
+use imap_client::client::ImapClient;
+use imap_client::error_imap::ClientError;
+use imap_client::input::ImapConfig;
+use imap_client::storage::ExistingFilePolicy;
+
+#[tokio::test]
+async fn only_verified_copies_are_drained() {
+    let server = inbox(5).await;
+    let dir = archive_dir("verified");
+    ImapClient::new(audited(&dir), server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    // An archived copy changed since it was saved doesn't count
+    std::fs::write(dir.join("email_00003.eml"), "edited").unwrap();
+
+    let config = ImapConfig {
+        drain: true,
+        uids: Some("1:*".parse().unwrap()),
+        if_exists: ExistingFilePolicy::Skip,
+        ..audited(&dir)
+    };
+    ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+
+    let audit = std::fs::read_to_string(dir.join("audit.log")).unwrap();
+    assert!(audit.contains("SELECT \"INBOX\""));
+    assert!(audit.contains("UID STORE 1:2,4:5 +FLAGS.SILENT (\\Deleted)"));
+    assert!(audit.contains("UID EXPUNGE 1:2,4:5"));
+    let journal_path = dir.join("drain.journal");
+    let journal = std::fs::read_to_string(&journal_path).unwrap();
+    assert!(journal.contains("\tverified INBOX UID 1 as email_00001.eml\t"));
+    assert!(journal.contains("\tkept INBOX UID 3: email_00003.eml is 6 bytes"));
+    assert!(journal.contains("\tdeleted INBOX UID 5\t"));
+    assert!(!journal.contains("deleted INBOX UID 3"));
+    // The journal is chained like the audit log
+    verify_audit_log(&journal_path).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn failed_replication_checks_keep_messages() {
+    let server = inbox(5).await;
+    let dir = archive_dir("replica");
+    let config = ImapConfig {
+        drain: true,
+        drain_check: Some(
+            "test \"$DRAIN_PATH\" != email_00002.eml -a -f \"$DRAIN_FILE\"".to_string(),
+        ),
+        ..audited(&dir)
+    };
+    ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+
+    let audit = std::fs::read_to_string(dir.join("audit.log")).unwrap();
+    assert!(audit.contains("UID STORE 1,3:5 +FLAGS.SILENT (\\Deleted)"));
+    let journal = std::fs::read_to_string(dir.join("drain.journal")).unwrap();
+    assert!(journal.contains("\treplicated INBOX UID 1\t"));
+    assert!(journal.contains("\tkept INBOX UID 2: replication check failed"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn draining_needs_a_writable_session() {
+    let server = inbox(5).await;
+    let dir = archive_dir("read-only");
+    let config = ImapConfig {
+        drain: true,
+        read_only: true,
+        ..audited(&dir)
+    };
+    let error = ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ClientError::InvalidArgument(_)));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,57 @@
+use imap_client::message::{decode_encoded_words, Headers};
+
+#[test]
+fn encoded_words_decode_in_any_charset_and_encoding() {
+    for (value, decoded) in [
+        ("=?UTF-8?B?R3LDvMOfZQ==?=", "Grüße"),
+        ("=?utf-8?b?R3LDvMOfZQ?=", "Grüße"),
+        ("=?ISO-8859-1?Q?Caf=E9_cr=E8me?=", "Café crème"),
+        ("=?windows-1252?q?=80_5?=", "€ 5"),
+        ("=?UTF-8*en?Q?Hello?=", "Hello"),
+        ("Re: =?UTF-8?Q?Z=C3=BCrich?= trip", "Re: Zürich trip"),
+        // Whitespace between adjacent words is dropped, not between words and text
+        ("=?UTF-8?Q?Gr=C3=BC?= =?UTF-8?Q?=C3=9Fe?=", "Grüße"),
+        ("=?UTF-8?Q?a?=\t =?UTF-8?Q?b?= c", "ab c"),
+        ("", ""),
+        ("plain ASCII", "plain ASCII"),
+    ] {
+        assert_eq!(decode_encoded_words(value), decoded, "{:?}", value);
+    }
+}
+
+#[test]
+fn words_that_do_not_decode_are_kept_verbatim() {
+    for value in [
+        "=?UTF-8?X?abc?=",
+        "=?UTF-8?Q?unterminated",
+        "=?UTF-8?Q?bad=ZZhex?=",
+        "=?UTF-8?B?not base64?=",
+        "=?UTF-8?B?!!!!?=",
+        "=?",
+        "1 + 1 =? 2",
+    ] {
+        assert_eq!(decode_encoded_words(value), value, "{:?}", value);
+    }
+    // An unknown charset still gives text rather than nothing
+    assert_eq!(decode_encoded_words("=?x-unknown?Q?abc?="), "abc");
+    // A broken word doesn't stop later ones from decoding
+    assert_eq!(
+        decode_encoded_words("=?UTF-8?X?a?= =?UTF-8?Q?Z=C3=BCrich?="),
+        "=?UTF-8?X?a?= Zürich"
+    );
+}
+
+#[test]
+fn folded_subjects_are_joined_and_decoded() {
+    let headers = Headers::parse(
+        b"Subject: =?UTF-8?Q?Gr=C3=BC?=\r\n =?UTF-8?Q?=C3=9Fe?= aus\r\n\tZ=?x?\r\n\
+          From: =?ISO-8859-1?Q?J=F6rg?= <jorg@example.com>\r\n\r\nbody",
+    );
+    assert_eq!(headers.subject().as_deref(), Some("Grüße aus Z=?x?"));
+    assert_eq!(headers.from().as_deref(), Some("Jörg <jorg@example.com>"));
+    assert_eq!(
+        headers.sender().and_then(|sender| sender.name).as_deref(),
+        Some("Jörg")
+    );
+    assert_eq!(Headers::parse(b"").subject(), None);
+}
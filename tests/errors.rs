@@ -0,0 +1,151 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, config};
+use imap_client::client::ImapClient;
+use imap_client::error_imap::{ClientError, ErrorContext, ErrorKind, Phase};
+use imap_client::failures::{read_failures, write_failures, FAILURES_FILE};
+use imap_client::input::ImapConfig;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use imap_client::processor::{BatchInfo, MessageInfo, MessageProcessor};
+use std::error::Error;
+use std::io;
+use std::sync::{Arc, Mutex};
+
+#[test]
+fn sources_are_kept_and_can_be_downcast() {
+    let lost = ClientError::connection_caused(
+        "lost the connection",
+        io::Error::new(io::ErrorKind::ConnectionReset, "reset by peer"),
+    );
+    assert_eq!(
+        lost.to_string(),
+        "Failed to connect to IMAP server: lost the connection: reset by peer"
+    );
+    assert_eq!(
+        lost.find::<io::Error>().map(io::Error::kind),
+        Some(io::ErrorKind::ConnectionReset)
+    );
+    assert_eq!(
+        ClientError::connection("refused").source().map(|_| ()),
+        None
+    );
+
+    let batch = ClientError::Batch {
+        context: ErrorContext {
+            mailbox: "Work".to_string(),
+            uids: (101, 200),
+            phase: Phase::Fetch,
+        },
+        source: Box::new(lost),
+    };
+    assert_eq!(
+        batch.to_string(),
+        "Work, UIDs 101:200 (fetch): Failed to connect to IMAP server: \
+         lost the connection: reset by peer"
+    );
+    assert_eq!(batch.context().unwrap().uids, (101, 200));
+    assert_eq!(batch.kind(), ErrorKind::Network);
+    // Through the batch, the connection error and then the io error
+    let source = batch.source().unwrap();
+    assert!(matches!(
+        source.downcast_ref::<ClientError>(),
+        Some(ClientError::ConnectionError { .. })
+    ));
+    assert!(source.source().unwrap().is::<io::Error>());
+    assert!(batch.find::<io::Error>().is_some());
+    assert!(batch.find::<std::fmt::Error>().is_none());
+}
+
+/// Refuses the second message, and keeps the context of failed batches.
+#[derive(Default)]
+struct Refuse {
+    failed: Mutex<Vec<ErrorContext>>,
+}
+
+impl MessageProcessor for Refuse {
+    fn transform(&self, message: &MessageInfo, body: Vec<u8>) -> Result<Vec<u8>, ClientError> {
+        if message.seq == 2 {
+            return Err(ClientError::InvalidArgument("refused".to_string()));
+        }
+        Ok(body)
+    }
+
+    fn on_batch_complete(&self, batch: &BatchInfo) {
+        if let Some(context) = batch.error.and_then(ClientError::context) {
+            self.failed.lock().unwrap().push(context.clone());
+        }
+    }
+}
+
+#[tokio::test]
+async fn failed_batches_say_where_and_when_they_failed() {
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: (0..2).map(|i| synthetic_message(i, 200)).collect(),
+    }])
+    .await
+    .unwrap();
+    let dir = std::env::temp_dir().join(format!("imap-test-errors-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let refuse = Arc::new(Refuse::default());
+    let config = ImapConfig {
+        batch_size: 2,
+        ..config(&dir)
+    };
+
+    let summary = ImapClient::new(config, server.url())
+        .with_processor(refuse.clone())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.failed_batches, 1);
+    let failed = refuse.failed.lock().unwrap().clone();
+    assert_eq!(
+        failed,
+        vec![ErrorContext {
+            mailbox: "INBOX".to_string(),
+            uids: (1, 2),
+            phase: Phase::Save,
+        }]
+    );
+
+    let failures = read_failures(&dir.join(FAILURES_FILE)).unwrap();
+    assert_eq!(failures.len(), 1);
+    assert_eq!(failures[0].phase.as_deref(), Some("save"));
+    assert!(failures[0].error.ends_with("Invalid argument: refused"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn file_errors_keep_their_path_and_cause() {
+    let dir = archive_dir("errors-io");
+    let path = dir.join(FAILURES_FILE);
+
+    let missing = read_failures(&path).unwrap_err();
+    assert!(matches!(&missing, ClientError::Io { path: Some(p), .. } if *p == path));
+    assert_eq!(
+        missing.find::<io::Error>().map(io::Error::kind),
+        Some(io::ErrorKind::NotFound)
+    );
+    assert_eq!(missing.kind(), ErrorKind::Storage);
+    assert!(missing.to_string().starts_with("File operation failed: "));
+
+    std::fs::write(&path, "[{").unwrap();
+    let invalid = read_failures(&path).unwrap_err();
+    assert!(invalid.find::<serde_json::Error>().is_some());
+    assert!(invalid.to_string().contains(FAILURES_FILE));
+
+    // A path that is a directory can't be written over
+    std::fs::remove_file(&path).unwrap();
+    std::fs::create_dir(&path).unwrap();
+    let failure = read_failures(&path).unwrap_err();
+    assert!(failure.find::<io::Error>().is_some());
+    assert!(write_failures(&dir, &[]).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+
+    // Plain `?` on an I/O error isn't taken for a problem reading input
+    let plain = ClientError::from(io::Error::other("disk on fire"));
+    assert_eq!(plain.to_string(), "I/O error: disk on fire");
+}
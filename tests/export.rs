@@ -0,0 +1,77 @@
+mod common;
+
+use common::archive_dir;
+use imap_client::export::{emlx, export_archive, ExportFormat};
+
+fn message(subject: &str) -> Vec<u8> {
+    format!(
+        "From: alice@example.com\r\n\
+         Subject: {}\r\n\
+         Date: Tue, 5 Mar 2024 10:00:00 +0000\r\n\
+         \r\n\
+         Hello\r\n",
+        subject
+    )
+    .into_bytes()
+}
+
+#[test]
+fn emlx_has_a_length_prefix_and_a_plist() {
+    let emlx =
+        String::from_utf8(emlx(b"Subject: Hi\r\n\r\nBody\r\n", Some(1_700_000_000))).unwrap();
+    let (length, rest) = emlx.split_once('\n').unwrap();
+    assert_eq!(length, "18        ");
+    let (message, plist) = rest.split_at(18);
+    assert_eq!(message, "Subject: Hi\n\nBody\n");
+    assert!(plist.starts_with("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n"));
+    assert!(plist.contains("<key>date-received</key>\n\t<integer>1700000000</integer>"));
+    assert!(plist.ends_with("</dict>\n</plist>\n"));
+}
+
+#[test]
+fn folders_become_nested_mailboxes() {
+    let dir = archive_dir("emlx");
+    std::fs::create_dir_all(dir.join("Work/Projects")).unwrap();
+    std::fs::write(dir.join("email_00001.eml"), message("One")).unwrap();
+    std::fs::write(dir.join("email_00002.eml"), message("Two")).unwrap();
+    std::fs::write(dir.join("Work/Projects/email_00001.eml"), message("Three")).unwrap();
+    let output = dir.with_extension("out");
+    let _ = std::fs::remove_dir_all(&output);
+
+    let report = export_archive(&dir, &output, ExportFormat::Emlx).unwrap();
+    assert_eq!((report.messages, report.folders), (3, 2));
+    let second = std::fs::read_to_string(output.join("INBOX.mbox/Messages/2.emlx")).unwrap();
+    assert!(second.contains("Subject: Two\n"));
+    // Dated by the Date header, without a catalog entry
+    assert!(second.contains("<integer>1709632800</integer>"));
+    assert!(output
+        .join("Work.mbox/Projects.mbox/Messages/1.emlx")
+        .exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::remove_dir_all(&output).unwrap();
+}
+
+#[test]
+fn thunderbird_gets_mbox_files_and_sbd_directories() {
+    let dir = archive_dir("thunderbird");
+    std::fs::create_dir_all(dir.join("INBOX")).unwrap();
+    std::fs::create_dir_all(dir.join("Work/Projects")).unwrap();
+    std::fs::write(dir.join("INBOX/email_00001.eml"), message("One")).unwrap();
+    std::fs::write(dir.join("INBOX/email_00002.eml"), message("From here")).unwrap();
+    std::fs::write(dir.join("Work/Projects/email_00001.eml"), message("Three")).unwrap();
+    let output = dir.with_extension("out");
+    let _ = std::fs::remove_dir_all(&output);
+
+    let report = export_archive(&dir, &output, ExportFormat::Thunderbird).unwrap();
+    assert_eq!((report.messages, report.folders), (3, 2));
+    let inbox = std::fs::read_to_string(output.join("Inbox")).unwrap();
+    assert_eq!(inbox.matches("\nFrom MAILER-DAEMON ").count(), 1);
+    assert!(inbox.starts_with("From MAILER-DAEMON "));
+    assert!(inbox.contains("Subject: From here\n"));
+    // Work has no messages of its own, but Thunderbird needs its mbox
+    assert_eq!(std::fs::read(output.join("Work")).unwrap(), b"");
+    let projects = std::fs::read_to_string(output.join("Work.sbd/Projects")).unwrap();
+    assert!(projects.contains("Subject: Three\n"));
+    std::fs::remove_dir_all(&dir).unwrap();
+    std::fs::remove_dir_all(&output).unwrap();
+}
@@ -0,0 +1,184 @@
+#![cfg(feature = "faults")]
+
+mod common;
+
+use common::archive_dir;
+use imap_client::checkpoint::Checkpoint;
+use imap_client::client::ImapClient;
+use imap_client::faults::{Fault, Faults};
+use imap_client::fixtures::Fixture;
+use imap_client::input::ImapConfig;
+use imap_client::mock::MockServer;
+use std::path::Path;
+use std::time::Duration;
+
+#[test]
+fn faults_follow_the_seed_and_their_chances() {
+    let faults = Faults {
+        seed: 7,
+        drop: 0.1,
+        delay: 0.2,
+        no: 0.3,
+        bad: 0.1,
+        commands: vec!["FETCH".to_string()],
+        ..Faults::default()
+    };
+    let draw = |connection| {
+        let mut injector = faults.injector(connection);
+        (0..10_000)
+            .map(|_| injector.next("FETCH"))
+            .collect::<Vec<_>>()
+    };
+    let drawn = draw(1);
+    assert_eq!(drawn, draw(1));
+    assert_ne!(drawn, draw(2));
+
+    let share = |kind: fn(&Fault) -> bool| {
+        drawn.iter().flatten().filter(|fault| kind(fault)).count() as f64 / drawn.len() as f64
+    };
+    let near = |share: f64, chance: f64| (share - chance).abs() < 0.02;
+    assert!(near(share(|f| matches!(f, Fault::Drop(_))), 0.1));
+    assert!(near(share(|f| matches!(f, Fault::Delay(_))), 0.2));
+    assert!(near(share(|f| *f == Fault::No), 0.3));
+    assert!(near(share(|f| *f == Fault::Bad), 0.1));
+    assert!(drawn.iter().flatten().all(|fault| match fault {
+        Fault::Drop(fraction) => (0.0..1.0).contains(fraction),
+        Fault::Delay(delay) => *delay < faults.max_delay,
+        _ => true,
+    }));
+
+    // Other commands are left alone
+    let mut injector = faults.injector(1);
+    assert!((0..1000).all(|_| injector.next("LOGIN").is_none()));
+}
+
+fn config(dir: &Path) -> ImapConfig {
+    ImapConfig {
+        all_folders: true,
+        batch_size: 4,
+        max_concurrent: 2,
+        batch_delay: Duration::ZERO,
+        ..common::config(dir)
+    }
+}
+
+#[tokio::test]
+async fn a_faulty_server_is_archived_completely_in_a_few_runs() {
+    let fixture = Fixture {
+        messages: 30,
+        sizes: 500..=4000,
+        ..Fixture::default()
+    };
+    let server = MockServer::start_faulty(
+        fixture.mailboxes(&["INBOX", "Work"]),
+        Faults {
+            seed: 3,
+            drop: 0.04,
+            delay: 0.2,
+            max_delay: Duration::from_millis(20),
+            no: 0.04,
+            bad: 0.02,
+            ..Faults::default()
+        },
+    )
+    .await
+    .unwrap();
+    let dir = std::env::temp_dir().join(format!("imap-test-faults-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    // Each run fetches what the ones before it didn't, until one finishes
+    // without failures
+    let mut saved = 0;
+    let mut runs = 0;
+    loop {
+        runs += 1;
+        assert!(runs <= 40, "still failing after {} runs", runs - 1);
+        match ImapClient::new(config(&dir), server.url())
+            .fetch_all_emails()
+            .await
+        {
+            Ok(summary) => {
+                saved += summary.saved as usize;
+                if summary.failed_batches == 0 {
+                    break;
+                }
+            }
+            Err(e) => log::info!("Run {} failed: {}", runs, e),
+        }
+    }
+    assert!(runs > 1);
+    assert!(server.faults_injected() > 0);
+
+    let checkpoint = Checkpoint::open(&dir).unwrap();
+    let mut archived = 0;
+    for mailbox in fixture.mailboxes(&["INBOX", "Work"]) {
+        let fetched = checkpoint.fetched_uids(&mailbox.name, 1);
+        assert_eq!(fetched.len(), mailbox.messages.len(), "{}", mailbox.name);
+        for (i, message) in mailbox.messages.iter().enumerate() {
+            let path = dir
+                .join(&mailbox.name)
+                .join(format!("email_{:05}.eml", i + 1));
+            assert_eq!(
+                &std::fs::read(&path).unwrap(),
+                message,
+                "{}",
+                path.display()
+            );
+            archived += 1;
+        }
+    }
+    assert_eq!(archived, fixture.messages);
+    // Messages are only saved again when a run failed before committing them
+    assert!(saved >= fixture.messages);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn miscounted_literals_are_fetched_again_not_saved() {
+    let fixture = Fixture {
+        messages: 20,
+        sizes: 500..=4000,
+        ..Fixture::default()
+    };
+    let mailboxes = fixture.mailboxes(&["INBOX"]);
+    let server = MockServer::start_faulty(
+        mailboxes.clone(),
+        Faults {
+            seed: 5,
+            miscount: 0.3,
+            commands: vec!["FETCH".to_string()],
+            ..Faults::default()
+        },
+    )
+    .await
+    .unwrap();
+    let dir = archive_dir("faults-miscount");
+
+    let mut runs = 0;
+    loop {
+        runs += 1;
+        assert!(runs <= 20, "still failing after {} runs", runs - 1);
+        let summary = ImapClient::new(config(&dir), server.url())
+            .fetch_all_emails()
+            .await
+            .unwrap();
+        if summary.failed_batches == 0 {
+            break;
+        }
+    }
+    assert!(server.faults_injected() > 0);
+
+    // Every body that was cut short was refetched rather than saved misread
+    let checkpoint = Checkpoint::open(&dir).unwrap();
+    assert_eq!(checkpoint.fetched_uids("INBOX", 1).len(), fixture.messages);
+    for (i, message) in mailboxes[0].messages.iter().enumerate() {
+        let path = dir.join("INBOX").join(format!("email_{:05}.eml", i + 1));
+        assert_eq!(
+            &std::fs::read(&path).unwrap(),
+            message,
+            "{}",
+            path.display()
+        );
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
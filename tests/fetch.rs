@@ -0,0 +1,96 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, config};
+use imap_client::catalog::Catalog;
+use imap_client::client::ImapClient;
+use imap_client::message::parse_internal_date;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use std::time::UNIX_EPOCH;
+
+#[tokio::test]
+async fn empty_and_lookalike_messages_do_not_corrupt_the_batch() {
+    let lookalike =
+        b"Subject: Tricky\r\n\r\n)\r\n* 3 FETCH (UID 3 BODY[] {5}\r\nA0001 OK done\r\n\xff\xfe\r\n"
+            .to_vec();
+    let messages = vec![
+        synthetic_message(0, 200),
+        Vec::new(),
+        lookalike.clone(),
+        synthetic_message(3, 200),
+    ];
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: messages.clone(),
+    }])
+    .await
+    .unwrap();
+    let dir = archive_dir("lookalike");
+
+    let client = ImapClient::new(config(&dir), server.url());
+    let summary = client.fetch_all_emails().await.unwrap();
+
+    assert_eq!(summary.saved, 3);
+    assert_eq!(summary.failed_batches, 0);
+    assert!(!dir.join("email_00002.eml").exists());
+    for (seq, expected) in [(1, &messages[0]), (3, &lookalike), (4, &messages[3])] {
+        let stored = std::fs::read(dir.join(format!("email_{:05}.eml", seq))).unwrap();
+        assert_eq!(&stored, expected, "message {}", seq);
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn files_are_dated_by_internaldate() {
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: vec![synthetic_message(0, 200), synthetic_message(1, 200)],
+    }])
+    .await
+    .unwrap();
+    let dir = archive_dir("internaldate");
+
+    let client = ImapClient::new(config(&dir), server.url());
+    client.fetch_all_emails().await.unwrap();
+
+    // The mock server dates UID n at 12:00 UTC on n+1 January 2024
+    let expected = parse_internal_date("03-Jan-2024 12:00:00 +0000").unwrap();
+    assert_eq!(expected, 1_704_283_200);
+    let modified = std::fs::metadata(dir.join("email_00002.eml"))
+        .unwrap()
+        .modified()
+        .unwrap();
+    assert_eq!(
+        modified.duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        expected as u64
+    );
+
+    let catalog = Catalog::open(&dir).unwrap();
+    let entry = catalog
+        .entries()
+        .iter()
+        .find(|entry| entry.path == "email_00002.eml")
+        .unwrap();
+    assert_eq!(entry.internal_date, Some(expected));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn get_fetches_the_one_body_asked_for() {
+    let messages: Vec<Vec<u8>> = (0..3).map(|i| synthetic_message(i, 400)).collect();
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: messages.clone(),
+    }])
+    .await
+    .unwrap();
+    let dir = archive_dir("get");
+
+    let client = ImapClient::new(config(&dir), server.url());
+    let found = client.get_message("<1@mock.example.com>").await.unwrap();
+    assert_eq!(found.as_ref(), Some(&messages[1]));
+    let missing = client.get_message("<9@mock.example.com>").await.unwrap();
+    assert_eq!(missing, None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,130 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use imap_client::client::ImapClient;
+use imap_client::fixtures::{Charset, Fixture, Structure};
+use imap_client::input::ImapConfig;
+use imap_client::message::Part;
+use imap_client::mock::MockServer;
+use std::path::Path;
+
+#[test]
+fn fixtures_are_reproducible_and_sized() {
+    let fixture = Fixture {
+        messages: 60,
+        sizes: 4 << 10..=16 << 10,
+        ..Fixture::default()
+    };
+    let first = fixture.mailbox("INBOX");
+    assert_eq!(first.messages, fixture.mailbox("INBOX").messages);
+    let longer = Fixture {
+        messages: 80,
+        ..fixture.clone()
+    };
+    assert_eq!(longer.mailbox("INBOX").messages[..60], first.messages[..]);
+    let reseeded = Fixture {
+        seed: 2,
+        ..fixture.clone()
+    };
+    assert_ne!(reseeded.message(0), fixture.message(0));
+
+    for message in &first.messages {
+        // Within a line of text of the range
+        assert!(
+            ((4 << 10) - 1000..=16 << 10).contains(&message.len()),
+            "{}",
+            message.len()
+        );
+    }
+}
+
+#[test]
+fn every_structure_and_charset_parses_back() {
+    for structure in Structure::ALL {
+        for charset in Charset::ALL {
+            let fixture = Fixture {
+                messages: 4,
+                structures: vec![structure],
+                charsets: vec![charset],
+                ..Fixture::default()
+            };
+            for i in 0..fixture.messages {
+                let raw = fixture.message(i);
+                let message = Part::parse(&raw);
+                let subject = message.headers.subject().unwrap();
+                assert!(!subject.contains("=?"), "{}", subject);
+                assert!(subject.contains("ok"), "{}", subject);
+
+                let mut texts = Vec::new();
+                let mut attachments = 0;
+                message.walk(&mut |part| {
+                    let content_type = part.content_type();
+                    if part.is_attachment() {
+                        attachments += 1;
+                    } else if !content_type.is_multipart()
+                        && content_type.param("charset") == Some(charset.label())
+                    {
+                        texts.push(part.text());
+                    }
+                });
+                let expected_texts = match structure {
+                    Structure::Alternative | Structure::Nested => 2,
+                    _ => 1,
+                };
+                assert_eq!(texts.len(), expected_texts, "{:?} {:?}", structure, charset);
+                for text in texts {
+                    assert!(text.contains("ok"));
+                    assert!(!text.contains('\u{fffd}'), "{:?}: {}", charset, text);
+                }
+                let expected_attachments = match structure {
+                    Structure::Attachment | Structure::Nested => 1,
+                    _ => 0,
+                };
+                assert_eq!(attachments, expected_attachments);
+            }
+        }
+    }
+}
+
+fn config(dir: &Path) -> ImapConfig {
+    ImapConfig {
+        all_folders: true,
+        skip_duplicates: true,
+        ..common::config(dir)
+    }
+}
+
+#[tokio::test]
+async fn a_fixture_account_resumes_and_skips_duplicates() {
+    let fixture = Fixture {
+        messages: 40,
+        sizes: 1 << 10..=8 << 10,
+        duplicate_every: 5,
+        ..Fixture::default()
+    };
+    let duplicates = (0..fixture.messages)
+        .filter(|&i| fixture.is_duplicate(i))
+        .count();
+    assert_eq!(duplicates, 8);
+    let server = MockServer::start(fixture.mailboxes(&["INBOX", "Work", "Receipts"]))
+        .await
+        .unwrap();
+    let dir = std::env::temp_dir().join(format!("imap-test-fixtures-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+
+    let summary = ImapClient::new(config(&dir), server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.found, 40);
+    assert_eq!(summary.saved as usize, 40 - duplicates);
+
+    // A second run picks up where the first left off
+    let summary = ImapClient::new(config(&dir), server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 0);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,170 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::archive_dir;
+use imap_client::client::ImapClient;
+use imap_client::error_imap::ClientError;
+use imap_client::folders::FolderFilter;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+async fn server() -> MockServer {
+    MockServer::start(
+        ["INBOX", "Work", "Receipts"]
+            .iter()
+            .enumerate()
+            .map(|(n, name)| MockMailbox {
+                name: name.to_string(),
+                messages: (n * 3..n * 3 + 3)
+                    .map(|i| synthetic_message(i, 200))
+                    .collect(),
+            })
+            .collect(),
+    )
+    .await
+    .unwrap()
+}
+
+fn config(dir: &Path) -> ImapConfig {
+    ImapConfig {
+        all_folders: true,
+        max_concurrent: 3,
+        ..common::config(dir)
+    }
+}
+
+#[tokio::test]
+async fn folders_share_the_connection_pool() {
+    let server = server().await;
+    let dir = archive_dir("pool");
+    let client = ImapClient::new(config(&dir), server.url());
+    let control = client.control();
+    // Paused batches wait holding their connection, so with one batch per
+    // folder, the pool filling up means every folder is being fetched at once
+    control.pause();
+    let watch = async {
+        let mut full = false;
+        for _ in 0..100 {
+            full = control.connections().available_permits() == 0;
+            if full {
+                break;
+            }
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+        control.resume();
+        full
+    };
+    let (summary, full) = tokio::join!(client.fetch_all_emails(), watch);
+
+    assert!(full);
+    assert_eq!(summary.unwrap().saved, 9);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn progress_is_kept_per_folder() {
+    let server = server().await;
+    let dir = archive_dir("progress");
+    let config = ImapConfig {
+        max_messages: Some(5),
+        ..config(&dir)
+    };
+    let client = ImapClient::new(config, server.url());
+    client.fetch_all_emails().await.unwrap();
+
+    let progress = client.progress();
+    assert_eq!(progress.folder("INBOX"), (3, 3));
+    assert_eq!(progress.folder("Work"), (2, 2));
+    assert_eq!(progress.folder("Receipts"), (0, 0));
+    assert!(progress.unfinished_folders().is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// The `.eml` files under `dir`, at any depth.
+fn saved_files(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(saved_files(&path));
+        } else if path.extension().is_some_and(|ext| ext == "eml") {
+            files.push(path);
+        }
+    }
+    files
+}
+
+#[tokio::test]
+async fn hostile_folder_names_stay_inside_the_output_directory() {
+    let server = MockServer::start(
+        ["INBOX", "..", "../../escape", "/tmp/absolute"]
+            .iter()
+            .enumerate()
+            .map(|(n, name)| MockMailbox {
+                name: name.to_string(),
+                messages: vec![synthetic_message(n, 200)],
+            })
+            .collect(),
+    )
+    .await
+    .unwrap();
+    let parent = archive_dir("hostile");
+    let dir = parent.join("archive");
+    let client = ImapClient::new(config(&dir), server.url());
+    assert_eq!(client.fetch_all_emails().await.unwrap().saved, 4);
+
+    let saved = saved_files(&dir);
+    assert_eq!(saved.len(), 4);
+    for path in &saved {
+        let relative = path.strip_prefix(&dir).unwrap();
+        assert!(
+            relative
+                .components()
+                .all(|c| matches!(c, std::path::Component::Normal(_))),
+            "{}",
+            path.display()
+        );
+    }
+    // Nothing was written next to the archive
+    let beside: Vec<_> = std::fs::read_dir(&parent).unwrap().collect();
+    assert_eq!(beside.len(), 1);
+    std::fs::remove_dir_all(&parent).unwrap();
+}
+
+#[tokio::test]
+async fn uids_select_messages_of_exactly_one_folder() {
+    let server = server().await;
+    let dir = archive_dir("uids");
+    let everywhere = || ImapConfig {
+        uids: Some("2:*".parse().unwrap()),
+        ..config(&dir)
+    };
+    let error = ImapClient::new(everywhere(), server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&error, ClientError::InvalidArgument(message) if message.contains("exactly one folder")),
+        "{}",
+        error
+    );
+    assert!(saved_files(&dir).is_empty());
+
+    let work = ImapConfig {
+        folder_filter: FolderFilter {
+            include: vec!["Work".to_string()],
+            exclude: Vec::new(),
+        },
+        ..everywhere()
+    };
+    let summary = ImapClient::new(work, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 2);
+    assert_eq!(saved_files(&dir.join("Work")).len(), 2);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
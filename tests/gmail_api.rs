@@ -0,0 +1,144 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::archive_dir;
+use imap_client::client::{Backend, ImapClient};
+use imap_client::error_imap::ErrorKind;
+use imap_client::gmail_api::GMAIL_STATE_FILE;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{
+    synthetic_message, MockGmailApiServer, MockMailbox, MOCK_ACCESS_TOKEN, MOCK_FIRST_HISTORY_ID,
+};
+use imap_client::oauth::OAuthCredentials;
+
+fn config(dir: &std::path::Path, server: &MockGmailApiServer) -> ImapConfig {
+    ImapConfig {
+        email: "test@gmail.com".to_string(),
+        password: MOCK_ACCESS_TOKEN.to_string(),
+        dir_path: dir.to_path_buf(),
+        backend: Backend::GmailApi,
+        gmail_api_url: server.url(),
+        batch_size: 2,
+        quiet: true,
+        ..ImapConfig::default()
+    }
+}
+
+fn mailboxes() -> Vec<MockMailbox> {
+    vec![
+        MockMailbox {
+            name: "INBOX".to_string(),
+            messages: (0..5).map(|i| synthetic_message(i, 300)).collect(),
+        },
+        MockMailbox {
+            name: "Work/Clients".to_string(),
+            messages: (5..8).map(|i| synthetic_message(i, 300)).collect(),
+        },
+    ]
+}
+
+#[tokio::test]
+async fn gmail_api_fetch_numbers_messages_oldest_first_and_resumes() {
+    let server = MockGmailApiServer::start(mailboxes()).await.unwrap();
+    let dir = archive_dir("inbox");
+
+    let client = ImapClient::new(config(&dir, &server), "unused".to_string());
+    let summary = client.fetch_all_emails().await.unwrap();
+    assert_eq!((summary.found, summary.saved), (5, 5));
+    for i in 0..5 {
+        let stored = std::fs::read(dir.join(format!("email_{:05}.eml", i + 1))).unwrap();
+        assert_eq!(stored, synthetic_message(i, 300));
+    }
+
+    let client = ImapClient::new(config(&dir, &server), "unused".to_string());
+    let summary = client.fetch_all_emails().await.unwrap();
+    assert_eq!((summary.found, summary.saved), (5, 0));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn gmail_api_refreshes_oauth_token_and_files_labels_as_folders() {
+    let server = MockGmailApiServer::start(mailboxes()).await.unwrap();
+    let dir = archive_dir("labels");
+    let config = ImapConfig {
+        password: String::new(),
+        all_folders: true,
+        oauth: Some(OAuthCredentials {
+            client_id: "client".to_string(),
+            client_secret: "secret".to_string(),
+            refresh_token: "refresh".to_string(),
+            token_url: Some(server.token_url()),
+            device_url: None,
+        }),
+        ..config(&dir, &server)
+    };
+
+    let summary = ImapClient::new(config, "unused".to_string())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!((summary.found, summary.saved), (8, 8));
+    assert!(dir
+        .join("Work")
+        .join("Clients")
+        .join("email_00003.eml")
+        .exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn gmail_api_rejected_token_is_an_auth_error() {
+    let server = MockGmailApiServer::start(mailboxes()).await.unwrap();
+    let dir = archive_dir("rejected");
+    let config = ImapConfig {
+        password: "expired".to_string(),
+        ..config(&dir, &server)
+    };
+
+    let error = ImapClient::new(config, "unused".to_string())
+        .fetch_all_emails()
+        .await
+        .unwrap_err();
+    assert_eq!(error.kind(), ErrorKind::Auth);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn gmail_api_syncs_only_history_since_the_last_run() {
+    let server = MockGmailApiServer::start(mailboxes()).await.unwrap();
+    let dir = archive_dir("history");
+    // As if an earlier run had synced up to the first three INBOX messages
+    std::fs::write(
+        dir.join(GMAIL_STATE_FILE),
+        format!("history\t{}\n", MOCK_FIRST_HISTORY_ID + 3),
+    )
+    .unwrap();
+
+    let summary = ImapClient::new(config(&dir, &server), "unused".to_string())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!((summary.found, summary.saved), (5, 2));
+    assert!(!dir.join("email_00003.eml").exists());
+    let stored = std::fs::read(dir.join("email_00005.eml")).unwrap();
+    assert_eq!(stored, synthetic_message(4, 300));
+
+    let state = std::fs::read_to_string(dir.join(GMAIL_STATE_FILE)).unwrap();
+    assert!(state.ends_with(&format!("history\t{}\n", MOCK_FIRST_HISTORY_ID + 8)));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn gmail_api_lists_everything_when_history_expired() {
+    let server = MockGmailApiServer::start(mailboxes()).await.unwrap();
+    let dir = archive_dir("expired");
+    std::fs::write(dir.join(GMAIL_STATE_FILE), "history\t1\n").unwrap();
+
+    let summary = ImapClient::new(config(&dir, &server), "unused".to_string())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!((summary.found, summary.saved), (5, 5));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,77 @@
+#![cfg(all(unix, feature = "mock"))]
+
+mod common;
+
+use common::archive_dir;
+use imap_client::client::ImapClient;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{MockMailbox, MockServer};
+use imap_client::storage::ExistingFilePolicy;
+use std::os::unix::fs::MetadataExt;
+use std::path::Path;
+
+const MESSAGE: &[u8] = b"From: alice@example.com\r\n\
+    Subject: Plans\r\n\
+    Message-ID: <plans@example.com>\r\n\
+    \r\n\
+    See you there.\r\n";
+
+fn config(dir: &Path, if_exists: ExistingFilePolicy) -> ImapConfig {
+    ImapConfig {
+        all_folders: true,
+        if_exists,
+        ..common::config(dir)
+    }
+}
+
+async fn server() -> MockServer {
+    MockServer::start(vec![
+        MockMailbox {
+            name: "INBOX".to_string(),
+            messages: vec![MESSAGE.to_vec()],
+        },
+        MockMailbox {
+            name: "Work".to_string(),
+            messages: vec![MESSAGE.to_vec()],
+        },
+    ])
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn identical_content_is_hard_linked_under_skip() {
+    let server = server().await;
+    let dir = archive_dir("skip");
+    let summary = ImapClient::new(config(&dir, ExistingFilePolicy::Skip), server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 2);
+
+    let inbox = std::fs::metadata(dir.join("INBOX/email_00001.eml")).unwrap();
+    let work = std::fs::metadata(dir.join("Work/email_00001.eml")).unwrap();
+    assert_eq!(inbox.ino(), work.ino());
+    assert_eq!(inbox.nlink(), 2);
+    assert_eq!(
+        std::fs::read(dir.join("Work/email_00001.eml")).unwrap(),
+        MESSAGE
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn other_policies_write_copies() {
+    let server = server().await;
+    let dir = archive_dir("overwrite");
+    ImapClient::new(config(&dir, ExistingFilePolicy::Overwrite), server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+
+    let inbox = std::fs::metadata(dir.join("INBOX/email_00001.eml")).unwrap();
+    let work = std::fs::metadata(dir.join("Work/email_00001.eml")).unwrap();
+    assert_ne!(inbox.ino(), work.ino());
+    assert_eq!(inbox.nlink(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,67 @@
+use imap_client::i18n::Locale;
+use imap_client::report::{render_html, Analytics, RunRecord};
+
+#[test]
+fn messages_are_translated_with_plurals_and_grouped_numbers() {
+    let english = Locale::new("en-US");
+    assert_eq!(english.tag(), "en");
+    assert_eq!(
+        english.message("fetch-partial", &[("failed", 1234.into())]),
+        "Email fetching finished with 1,234 failed batches"
+    );
+
+    let portuguese = Locale::new("pt_BR");
+    assert_eq!(portuguese.tag(), "pt-BR");
+    assert_eq!(
+        portuguese.message("fetch-partial", &[("failed", 1.into())]),
+        "Download de e-mails concluído com 1 lote com falha"
+    );
+    assert_eq!(
+        portuguese.message("progress-failed-batches", &[("failed", 2500.into())]),
+        "(2.500 lotes com falha)"
+    );
+    // Another region of a translated language gets the translation
+    assert_eq!(Locale::new("pt-PT").tag(), "pt-BR");
+    // Anything else falls back to English
+    assert_eq!(Locale::new("ja-JP").tag(), "en");
+    assert_eq!(Locale::new("not a tag").tag(), "en");
+    assert_eq!(english.message("no-such-message", &[]), "no-such-message");
+}
+
+#[test]
+fn numbers_sizes_and_dates_follow_the_locale() {
+    let english = Locale::new("en");
+    let portuguese = Locale::new("pt-BR");
+    assert_eq!(english.number(1_234_567), "1,234,567");
+    assert_eq!(english.number(999), "999");
+    assert_eq!(portuguese.number(1_234_567), "1.234.567");
+    assert_eq!(english.size(1536), "1.5 KiB");
+    assert_eq!(portuguese.size(1536), "1,5 KiB");
+    // 2024-01-02 09:05 UTC
+    assert_eq!(english.datetime(1_704_186_300), "2024-01-02 09:05 UTC");
+    assert_eq!(portuguese.datetime(1_704_186_300), "02/01/2024 09:05 UTC");
+}
+
+#[test]
+fn the_html_report_is_translated() {
+    let analytics = Analytics {
+        messages: 1200,
+        bytes: 1536,
+        ..Analytics::default()
+    };
+    let run = RunRecord {
+        account: "me@example.com".to_string(),
+        started: 1_704_186_000,
+        finished: 1_704_186_125,
+        found: 1200,
+        saved: 1200,
+        failed_batches: 0,
+        error: None,
+    };
+    let html = render_html(&analytics, Some(&run), &[], &Locale::new("pt-BR"));
+    assert!(html.contains("<html lang=\"pt-BR\">"));
+    assert!(html.contains("<h2>Última execução</h2>"));
+    assert!(html.contains("<td>02/01/2024 09:00 UTC</td>"));
+    assert!(html.contains("<p>1.200 mensagens, 1,5 KiB.</p>"));
+    assert!(!html.contains("Last run"));
+}
@@ -0,0 +1,84 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, config};
+use imap_client::audit::AUDIT_FILE;
+use imap_client::client::ImapClient;
+use imap_client::identity::{parse_id_response, ClientIdentity, ServerId};
+use imap_client::input::ImapConfig;
+use imap_client::mock::{
+    synthetic_message, MockMailbox, MockServer, MOCK_SERVER_NAME, MOCK_SERVER_VERSION,
+};
+
+fn fields(pairs: &[(&str, &str)]) -> ServerId {
+    ServerId {
+        fields: pairs
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect(),
+    }
+}
+
+#[test]
+fn server_id_is_parsed() {
+    let id = parse_id_response(r#"ID ("name" "Cyrus" "version" "1.5" "os" NIL)"#).unwrap();
+    assert_eq!(id, fields(&[("name", "Cyrus"), ("version", "1.5")]));
+    assert_eq!(id.to_string(), "Cyrus 1.5");
+
+    let id = parse_id_response(r#"ID ("vendor" "Example" "name" "Dovecot")"#).unwrap();
+    assert_eq!(id.get("Name"), Some("Dovecot"));
+    assert_eq!(id.to_string(), "Dovecot (vendor: Example)");
+
+    assert_eq!(parse_id_response("ID NIL"), Some(ServerId::default()));
+    assert_eq!(parse_id_response("CAPABILITY IMAP4rev1"), None);
+}
+
+#[test]
+fn client_identity_is_quoted_or_left_out() {
+    let identity = ClientIdentity {
+        name: "Archiver \"2\"".to_string(),
+        version: String::new(),
+    };
+    assert_eq!(
+        identity.command().as_deref(),
+        Some(r#"ID ("name" "Archiver \"2\"")"#)
+    );
+
+    let identity = ClientIdentity {
+        name: String::new(),
+        version: "1.0".to_string(),
+    };
+    assert_eq!(identity.command(), None);
+}
+
+#[tokio::test]
+async fn fetch_sends_id_and_reports_the_server() {
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: (0..2).map(|i| synthetic_message(i, 200)).collect(),
+    }])
+    .await
+    .unwrap();
+    let dir = archive_dir("identity");
+    let config = ImapConfig {
+        identity: ClientIdentity {
+            name: "archiver".to_string(),
+            version: "2.0".to_string(),
+        },
+        read_only: true,
+        ..config(&dir)
+    };
+
+    let summary = ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    let id = summary.server_id.unwrap();
+    assert_eq!(id.get("name"), Some(MOCK_SERVER_NAME));
+    assert_eq!(id.get("version"), Some(MOCK_SERVER_VERSION));
+
+    let audit = std::fs::read_to_string(dir.join(AUDIT_FILE)).unwrap();
+    assert!(audit.contains(r#"ID ("name" "archiver" "version" "2.0")"#));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
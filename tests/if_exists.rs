@@ -0,0 +1,86 @@
+mod common;
+
+use common::archive_dir;
+use imap_client::error_imap::ClientError;
+use imap_client::storage::{resolve_target, ExistingFilePolicy};
+
+#[test]
+fn policies_are_parsed_from_the_command_line() {
+    for (value, policy) in [
+        ("skip", ExistingFilePolicy::Skip),
+        ("overwrite", ExistingFilePolicy::Overwrite),
+        ("Rename", ExistingFilePolicy::Rename),
+        ("ERROR", ExistingFilePolicy::Error),
+    ] {
+        assert_eq!(value.parse::<ExistingFilePolicy>().unwrap(), policy);
+    }
+    for value in ["", "replace", "skip "] {
+        assert!(matches!(
+            value.parse::<ExistingFilePolicy>(),
+            Err(ClientError::InvalidArgument(_))
+        ));
+    }
+    assert_eq!(ExistingFilePolicy::default(), ExistingFilePolicy::Overwrite);
+}
+
+#[test]
+fn each_policy_decides_what_happens_to_an_existing_file() {
+    let dir = archive_dir("if-exists");
+    std::fs::create_dir_all(&dir).unwrap();
+    let new = dir.join("new.eml");
+    let taken = dir.join("1.eml");
+    std::fs::write(&taken, "first").unwrap();
+
+    // A free name is used whatever the policy
+    for policy in [
+        ExistingFilePolicy::Skip,
+        ExistingFilePolicy::Overwrite,
+        ExistingFilePolicy::Rename,
+        ExistingFilePolicy::Error,
+    ] {
+        assert_eq!(
+            resolve_target(new.clone(), policy).unwrap(),
+            Some(new.clone())
+        );
+    }
+
+    assert_eq!(
+        resolve_target(taken.clone(), ExistingFilePolicy::Skip).unwrap(),
+        None
+    );
+    assert_eq!(
+        resolve_target(taken.clone(), ExistingFilePolicy::Overwrite).unwrap(),
+        Some(taken.clone())
+    );
+    let error = resolve_target(taken.clone(), ExistingFilePolicy::Error).unwrap_err();
+    assert!(error.to_string().contains("already exists"), "{}", error);
+    match &error {
+        ClientError::Io {
+            path: Some(path),
+            source,
+        } => {
+            assert_eq!(*path, taken);
+            assert_eq!(source.kind(), std::io::ErrorKind::AlreadyExists);
+        }
+        other => panic!("expected an I/O error, got {:?}", other),
+    }
+
+    // Renaming finds the first free suffix
+    assert_eq!(
+        resolve_target(taken.clone(), ExistingFilePolicy::Rename).unwrap(),
+        Some(dir.join("1_1.eml"))
+    );
+    std::fs::write(dir.join("1_1.eml"), "second").unwrap();
+    std::fs::write(dir.join("1_2.eml"), "third").unwrap();
+    assert_eq!(
+        resolve_target(taken, ExistingFilePolicy::Rename).unwrap(),
+        Some(dir.join("1_3.eml"))
+    );
+    // Names without an extension keep having none
+    std::fs::write(dir.join("README"), "").unwrap();
+    assert_eq!(
+        resolve_target(dir.join("README"), ExistingFilePolicy::Rename).unwrap(),
+        Some(dir.join("README_1"))
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
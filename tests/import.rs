@@ -0,0 +1,93 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, audited};
+use imap_client::catalog::Catalog;
+use imap_client::client::ImapClient;
+use imap_client::import::import_messages;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use std::path::PathBuf;
+
+/// A manual export of the first `count` mock messages, one in a subfolder.
+fn exports(name: &str, count: usize) -> PathBuf {
+    let dir = archive_dir(name);
+    std::fs::create_dir_all(dir.join("2019")).unwrap();
+    for i in 0..count {
+        let folder = if i == 0 {
+            dir.join("2019")
+        } else {
+            dir.clone()
+        };
+        std::fs::write(
+            folder.join(format!("saved {}.eml", i)),
+            synthetic_message(i, 200),
+        )
+        .unwrap();
+    }
+    dir
+}
+
+#[test]
+fn messages_are_catalogued_once() {
+    let source = exports("source", 2);
+    std::fs::write(source.join("note.eml"), b"Subject: no id\r\n\r\nhi\r\n").unwrap();
+    std::fs::write(source.join("readme.txt"), b"not a message").unwrap();
+    let archive = archive_dir("archive");
+
+    let report = import_messages(&source, &archive).unwrap();
+    assert_eq!((report.scanned, report.added, report.known), (3, 3, 0));
+    assert_eq!(report.without_message_id, 1);
+    let catalog = Catalog::open(&archive).unwrap();
+    assert!(catalog.has_message_id("<0@mock.example.com>"));
+    // Outside the archive, so by full path
+    let first = PathBuf::from(&catalog.entries()[0].path);
+    assert_eq!(
+        first,
+        source.canonicalize().unwrap().join("2019/saved 0.eml")
+    );
+
+    let again = import_messages(&source, &archive).unwrap();
+    assert_eq!((again.added, again.known), (0, 3));
+    std::fs::remove_dir_all(&source).unwrap();
+    std::fs::remove_dir_all(&archive).unwrap();
+}
+
+#[tokio::test]
+async fn imported_messages_are_not_downloaded() {
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: (0..4).map(|i| synthetic_message(i, 200)).collect(),
+    }])
+    .await
+    .unwrap();
+    let archive = archive_dir("fetch");
+    let source = exports("old", 2);
+    std::fs::rename(&source, archive.join("old")).unwrap();
+    let report = import_messages(&archive.join("old"), &archive).unwrap();
+    assert_eq!(report.added, 2);
+    assert_eq!(
+        Catalog::open(&archive).unwrap().entries()[1].path,
+        "old/saved 1.eml"
+    );
+
+    let config = ImapConfig {
+        skip_duplicates: true,
+        ..audited(&archive)
+    };
+    let summary = ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 2);
+    let audit = std::fs::read_to_string(archive.join("audit.log")).unwrap();
+    let downloads: Vec<&str> = audit
+        .lines()
+        .filter_map(|line| line.split('\t').nth(2))
+        .filter(|event| event.contains("BODY.PEEK[]"))
+        .collect();
+    assert_eq!(downloads.len(), 1);
+    assert!(downloads[0].ends_with("UID FETCH 3:4 (INTERNALDATE BODY.PEEK[])"));
+    std::fs::remove_dir_all(&archive).unwrap();
+}
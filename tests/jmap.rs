@@ -0,0 +1,91 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::archive_dir;
+use imap_client::catalog::Catalog;
+use imap_client::client::{Backend, ImapClient};
+use imap_client::input::ImapConfig;
+use imap_client::message::parse_utc_date;
+use imap_client::mock::{synthetic_message, MockJmapServer, MockMailbox};
+use std::time::UNIX_EPOCH;
+
+fn config(dir: &std::path::Path, server: &MockJmapServer) -> ImapConfig {
+    ImapConfig {
+        email: "test@example.com".to_string(),
+        password: "token".to_string(),
+        dir_path: dir.to_path_buf(),
+        backend: Backend::Jmap,
+        jmap_url: server.url(),
+        batch_size: 2,
+        quiet: true,
+        ..ImapConfig::default()
+    }
+}
+
+fn mailboxes() -> Vec<MockMailbox> {
+    vec![
+        MockMailbox {
+            name: "INBOX".to_string(),
+            messages: (0..5).map(|i| synthetic_message(i, 300)).collect(),
+        },
+        MockMailbox {
+            name: "Archive".to_string(),
+            messages: (5..7).map(|i| synthetic_message(i, 300)).collect(),
+        },
+    ]
+}
+
+#[tokio::test]
+async fn jmap_fetch_saves_inbox_like_imap() {
+    let server = MockJmapServer::start(mailboxes()).await.unwrap();
+    let dir = archive_dir("inbox");
+
+    let client = ImapClient::new(config(&dir, &server), "unused".to_string());
+    let summary = client.fetch_all_emails().await.unwrap();
+
+    assert_eq!(
+        (summary.found, summary.saved, summary.failed_batches),
+        (5, 5, 0)
+    );
+    let stored = std::fs::read(dir.join("email_00003.eml")).unwrap();
+    assert_eq!(stored, synthetic_message(2, 300));
+
+    // The mock dates message i at 12:00 UTC on i+2 January 2024
+    let expected = parse_utc_date("2024-01-04T12:00:00Z").unwrap();
+    let modified = std::fs::metadata(dir.join("email_00003.eml"))
+        .unwrap()
+        .modified()
+        .unwrap();
+    assert_eq!(
+        modified.duration_since(UNIX_EPOCH).unwrap().as_secs(),
+        expected as u64
+    );
+    let catalog = Catalog::open(&dir).unwrap();
+    assert_eq!(catalog.entries().len(), 5);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn jmap_rerun_skips_messages_in_the_catalog() {
+    let server = MockJmapServer::start(mailboxes()).await.unwrap();
+    let dir = archive_dir("rerun");
+    let all_folders = |dir: &std::path::Path| ImapConfig {
+        all_folders: true,
+        ..config(dir, &server)
+    };
+
+    let first = ImapClient::new(all_folders(&dir), "unused".to_string())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!((first.found, first.saved), (7, 7));
+    assert!(dir.join("Archive").join("email_00002.eml").exists());
+
+    let second = ImapClient::new(all_folders(&dir), "unused".to_string())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!((second.found, second.saved), (7, 0));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
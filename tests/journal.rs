@@ -0,0 +1,91 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, config, inbox};
+use imap_client::client::ImapClient;
+use imap_client::input::ImapConfig;
+use imap_client::journal::undo_plan;
+use imap_client::rules::RuleSet;
+
+#[tokio::test]
+async fn moves_are_journaled_and_reversible() {
+    let server = inbox(3).await;
+    let dir = archive_dir("move");
+    let config = ImapConfig {
+        rules: RuleSet::parse("* -> move:Archive", "rules.txt").unwrap(),
+        ..config(&dir)
+    };
+    ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+
+    let journal = std::fs::read_to_string(dir.join("operations.journal")).unwrap();
+    let events: Vec<&str> = journal
+        .lines()
+        .map(|line| line.split('\t').nth(2).unwrap())
+        .collect();
+    // The first MOVE fails until Archive is created
+    assert_eq!(events, ["begin", "no", "begin", "code", "ok"]);
+    assert!(journal.contains("\tbegin\t\"INBOX\"\tUID MOVE 1:3 \"Archive\"\n"));
+
+    let plan = undo_plan(&dir.join("operations.journal")).unwrap();
+    let mut lines = plan.lines();
+    assert!(lines
+        .next()
+        .unwrap()
+        .ends_with("in \"INBOX\": UID MOVE 1:3 \"Archive\" (done)"));
+    assert_eq!(lines.next(), Some("SELECT \"Archive\""));
+    assert_eq!(lines.next(), Some("UID MOVE 1:3 \"INBOX\""));
+    assert!(lines.next().unwrap().ends_with("(failed)"));
+    assert_eq!(lines.next(), Some("# nothing to undo"));
+    assert_eq!(lines.next(), None);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn read_only_runs_write_no_journal() {
+    let server = inbox(3).await;
+    let dir = archive_dir("read-only");
+    let config = ImapConfig {
+        read_only: true,
+        ..config(&dir)
+    };
+    ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert!(!dir.join("operations.journal").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn interrupted_and_irreversible_operations_are_called_out() {
+    let dir = archive_dir("plan");
+    let path = dir.join("operations.journal");
+    std::fs::write(
+        &path,
+        "1700000000.000\t1.1.A0003\tbegin\t\"INBOX\"\tUID STORE 4 +FLAGS.SILENT (\\Seen)\n\
+         1700000000.001\t1.1.A0003\tok\t\"INBOX\"\tSTORE completed\n\
+         1700000000.002\t1.1.A0004\tbegin\t\"INBOX\"\tAPPEND \"My \\\"Mail\\\"\" (\\Seen) {120}\n\
+         1700000000.003\t1.1.A0004\tok\t\"INBOX\"\t[APPENDUID 7 42] APPEND completed\n\
+         1700000000.004\t1.2.A0005\tbegin\t\"INBOX\"\tUID EXPUNGE 4\n",
+    )
+    .unwrap();
+
+    let plan = undo_plan(&path).unwrap();
+    let expected = [
+        "# 1700000000.004 1.2.A0005 in \"INBOX\": UID EXPUNGE 4 (interrupted)",
+        "# can't be undone: expunged messages are gone from the server",
+        "# 1700000000.002 1.1.A0004 in \"INBOX\": APPEND \"My \\\"Mail\\\"\" (\\Seen) {120} (done)",
+        "SELECT \"My \\\"Mail\\\"\"",
+        "UID STORE 42 +FLAGS.SILENT (\\Deleted)",
+        "UID EXPUNGE 42",
+        "# 1700000000.000 1.1.A0003 in \"INBOX\": UID STORE 4 +FLAGS.SILENT (\\Seen) (done)",
+        "SELECT \"INBOX\"",
+        "UID STORE 4 -FLAGS.SILENT (\\Seen)",
+    ];
+    assert_eq!(plan.lines().collect::<Vec<_>>(), expected);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,106 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, audited, inbox};
+use imap_client::client::ImapClient;
+use imap_client::error_imap::ClientError;
+use imap_client::folders::FolderFilter;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+
+#[tokio::test]
+async fn matching_messages_are_labelled() {
+    let server = inbox(5).await;
+    let dir = archive_dir("search");
+    let config = ImapConfig {
+        uids: Some("2:*".parse().unwrap()),
+        ..audited(&dir)
+    };
+
+    // The mock matches the search's last word anywhere in a message
+    let counts = ImapClient::new(config, server.url())
+        .label_messages("cold-archive", true, Some("BODY Lorem"), false)
+        .await
+        .unwrap();
+    assert_eq!(counts, [("INBOX".to_string(), 4)]);
+    let audit = std::fs::read_to_string(dir.join("audit.log")).unwrap();
+    assert!(audit.contains("UID SEARCH UID 2:* BODY Lorem"));
+    assert!(audit.contains("UID STORE 2:5 +X-GM-LABELS.SILENT \"cold-archive\""));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn dry_runs_only_count() {
+    let server = inbox(5).await;
+    let dir = archive_dir("dry-run");
+
+    let counts = ImapClient::new(audited(&dir), server.url())
+        .label_messages(
+            "cold-archive",
+            false,
+            Some("HEADER Message-ID <3@mock.example.com>"),
+            true,
+        )
+        .await
+        .unwrap();
+    assert_eq!(counts, [("INBOX".to_string(), 1)]);
+    let audit = std::fs::read_to_string(dir.join("audit.log")).unwrap();
+    assert!(audit.contains("EXAMINE \"INBOX\""));
+    assert!(!audit.contains("STORE"));
+
+    // Without a search or UIDs, nothing is chosen
+    let error = ImapClient::new(audited(&dir), server.url())
+        .label_messages("cold-archive", true, None, false)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ClientError::InvalidArgument(_)));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn uids_label_messages_of_one_folder_only() {
+    let server = MockServer::start(
+        ["INBOX", "Work"]
+            .iter()
+            .map(|name| MockMailbox {
+                name: name.to_string(),
+                messages: (0..3).map(|i| synthetic_message(i, 200)).collect(),
+            })
+            .collect(),
+    )
+    .await
+    .unwrap();
+    let dir = archive_dir("one-folder");
+    let everywhere = || ImapConfig {
+        all_folders: true,
+        uids: Some("2".parse().unwrap()),
+        ..audited(&dir)
+    };
+
+    let error = ImapClient::new(everywhere(), server.url())
+        .label_messages("cold-archive", true, None, false)
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&error, ClientError::InvalidArgument(message) if message.contains("exactly one folder")),
+        "{}",
+        error
+    );
+    let audit = std::fs::read_to_string(dir.join("audit.log")).unwrap_or_default();
+    assert!(!audit.contains("STORE"));
+
+    let work = ImapConfig {
+        folder_filter: FolderFilter {
+            include: vec!["Work".to_string()],
+            exclude: Vec::new(),
+        },
+        ..everywhere()
+    };
+    let counts = ImapClient::new(work, server.url())
+        .label_messages("cold-archive", true, None, false)
+        .await
+        .unwrap();
+    assert_eq!(counts, [("Work".to_string(), 1)]);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
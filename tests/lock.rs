@@ -0,0 +1,64 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, config};
+use imap_client::client::ImapClient;
+use imap_client::error_imap::ClientError;
+use imap_client::lock::{RunLock, LOCK_FILE};
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+
+#[test]
+fn one_run_at_a_time() {
+    let dir = archive_dir("held");
+    let lock = RunLock::acquire(&dir).unwrap();
+    let holder = format!("pid {} since ", std::process::id());
+    assert!(std::fs::read_to_string(lock.path())
+        .unwrap()
+        .starts_with(&holder));
+
+    match RunLock::acquire(&dir) {
+        Err(ClientError::Locked { path, holder: held }) => {
+            assert_eq!(path, dir);
+            assert!(held.starts_with(&holder))
+        }
+        other => panic!("expected Locked, got {:?}", other.map(|_| ())),
+    }
+    drop(lock);
+    assert_eq!(std::fs::read_to_string(dir.join(LOCK_FILE)).unwrap(), "");
+    RunLock::acquire(&dir).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn locks_left_by_dead_runs_are_taken_over() {
+    let dir = archive_dir("stale");
+    std::fs::write(dir.join(LOCK_FILE), "pid 1 since 20240101T000000\n").unwrap();
+    let lock = RunLock::acquire(&dir).unwrap();
+    let contents = std::fs::read_to_string(lock.path()).unwrap();
+    assert!(contents.starts_with(&format!("pid {} ", std::process::id())));
+    drop(lock);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn fetching_into_a_locked_archive_is_refused() {
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: (0..3).map(|i| synthetic_message(i, 200)).collect(),
+    }])
+    .await
+    .unwrap();
+    let dir = archive_dir("fetch");
+    let config = config(&dir);
+    let lock = RunLock::acquire(&dir).unwrap();
+    let error = ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ClientError::Locked { .. }));
+    assert_eq!(error.exit_code(), 7);
+    assert!(!dir.join("email_00001.eml").exists());
+    drop(lock);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,168 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::archive_dir;
+use imap_client::client::ImapClient;
+use imap_client::error_imap::ClientError;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{
+    synthetic_message, MockMailbox, MockOAuthServer, MockServer, MOCK_ACCESS_TOKEN,
+    MOCK_REFRESH_TOKEN,
+};
+use imap_client::oauth::OAuthCredentials;
+use imap_client::provider::Provider;
+use std::path::Path;
+
+fn config(dir: &Path, oauth: &MockOAuthServer) -> ImapConfig {
+    ImapConfig {
+        email: "test@example.com".to_string(),
+        dir_path: dir.to_path_buf(),
+        oauth: Some(OAuthCredentials {
+            client_id: "client".to_string(),
+            token_url: Some(oauth.token_url()),
+            ..OAuthCredentials::default()
+        }),
+        audit_log: Some(dir.join("audit.log")),
+        quiet: true,
+        ..ImapConfig::default()
+    }
+}
+
+async fn mock_server() -> MockServer {
+    MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: (0..1).map(|i| synthetic_message(i, 200)).collect(),
+    }])
+    .await
+    .unwrap()
+}
+
+/// Plays the browser: signs in at `url`, and is sent back to the client
+/// with `response` as the query, where `{state}` is the state asked for.
+fn browser(url: &str, response: &'static str) {
+    let url = reqwest::Url::parse(url).unwrap();
+    let param = |name: &str| {
+        url.query_pairs()
+            .find(|(key, _)| key == name)
+            .map(|(_, value)| value.into_owned())
+            .unwrap()
+    };
+    assert_eq!(param("client_id"), "client");
+    assert_eq!(param("code_challenge_method"), "S256");
+    assert_eq!(param("login_hint"), "test@example.com");
+    let redirect = format!(
+        "{}?{}",
+        param("redirect_uri"),
+        response.replace("{state}", &param("state"))
+    );
+    tokio::spawn(async move {
+        // Browsers ask for other things too
+        let favicon = reqwest::Url::parse(&redirect)
+            .unwrap()
+            .join("/favicon.ico")
+            .unwrap();
+        let response = reqwest::get(favicon).await.unwrap();
+        assert_eq!(response.status(), 404);
+        reqwest::get(redirect).await.unwrap();
+    });
+}
+
+#[tokio::test]
+async fn the_browser_sign_in_is_checked_against_the_server() {
+    let server = mock_server().await;
+    let oauth = MockOAuthServer::start().await.unwrap();
+    let dir = archive_dir("signin");
+    let client = ImapClient::new(config(&dir, &oauth), server.url());
+
+    let login = client
+        .login(|url| browser(url, "code=mock-code&state={state}"))
+        .await
+        .unwrap();
+    assert_eq!(login.refresh_token, MOCK_REFRESH_TOKEN);
+
+    // The server saw the new access token, which stays out of the log
+    let audit = std::fs::read_to_string(dir.join("audit.log")).unwrap();
+    assert!(audit.contains("AUTHENTICATE XOAUTH2 ***"));
+    assert!(!audit.contains(MOCK_ACCESS_TOKEN));
+
+    // Later runs sign in with the refresh token instead of a password
+    let signed_in = ImapConfig {
+        oauth: Some(OAuthCredentials {
+            client_id: "client".to_string(),
+            refresh_token: login.refresh_token,
+            token_url: Some(oauth.token_url()),
+            ..OAuthCredentials::default()
+        }),
+        ..config(&dir, &oauth)
+    };
+    let summary = ImapClient::new(signed_in, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn refused_and_forged_redirects_fail() {
+    let server = mock_server().await;
+    let oauth = MockOAuthServer::start().await.unwrap();
+    let dir = archive_dir("refused");
+
+    let client = ImapClient::new(config(&dir, &oauth), server.url());
+    let error = client
+        .login(|url| browser(url, "error=access_denied&state={state}"))
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&error, ClientError::AuthenticationError(text) if text.contains("access_denied"))
+    );
+
+    let client = ImapClient::new(config(&dir, &oauth), server.url());
+    let error = client
+        .login(|url| browser(url, "code=mock-code&state=forged"))
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ClientError::AuthenticationError(_)));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn login_needs_a_provider_with_oauth_and_a_client_id() {
+    let server = mock_server().await;
+    let oauth = MockOAuthServer::start().await.unwrap();
+    let dir = archive_dir("needs");
+    let yahoo = ImapConfig {
+        provider: Provider::Yahoo,
+        ..config(&dir, &oauth)
+    };
+    let error = ImapClient::new(yahoo, server.url())
+        .login(|_| panic!("no sign-in page for Yahoo"))
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ClientError::InvalidArgument(_)));
+
+    let no_client = ImapConfig {
+        oauth: None,
+        ..config(&dir, &oauth)
+    };
+    let error = ImapClient::new(no_client, server.url())
+        .login(|_| panic!("no client to sign in with"))
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ClientError::ActionRequired { .. }));
+
+    // Without a refresh token, runs ask for a login instead of trying a
+    // password
+    let never_signed_in = ImapConfig {
+        email: "new@example.com".to_string(),
+        ..config(&dir, &oauth)
+    };
+    let error = ImapClient::new(never_signed_in, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("imap_client login"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
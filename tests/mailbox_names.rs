@@ -0,0 +1,40 @@
+use imap_client::folders::{decode_mailbox_name, encode_mailbox_name};
+
+#[test]
+fn names_round_trip_through_modified_utf7() {
+    for (decoded, encoded) in [
+        ("INBOX", "INBOX"),
+        ("", ""),
+        ("Facturas España", "Facturas Espa&APE-a"),
+        ("Tom & Jerry", "Tom &- Jerry"),
+        ("&", "&-"),
+        ("日本語", "&ZeVnLIqe-"),
+        ("~peter/mail/台北/日本語", "~peter/mail/&U,BTFw-/&ZeVnLIqe-"),
+        // Outside the BMP: one surrogate pair
+        ("📬 Inbox", "&2D3c7A- Inbox"),
+    ] {
+        assert_eq!(encode_mailbox_name(decoded), encoded, "{:?}", decoded);
+        assert_eq!(decode_mailbox_name(encoded), decoded, "{:?}", encoded);
+    }
+}
+
+#[test]
+fn malformed_names_are_kept_as_they_came() {
+    for name in [
+        // No closing dash
+        "Drafts &ZeVnLIqe",
+        // Not base64
+        "Bad &!!!-",
+        // An odd number of bytes is not UTF-16
+        "Odd &AA-",
+        // A lone high surrogate
+        "Lone &2D0-",
+    ] {
+        assert_eq!(decode_mailbox_name(name), name);
+    }
+    // Only the broken run is kept; the rest still decodes
+    assert_eq!(
+        decode_mailbox_name("&!!!- and Espa&APE-a"),
+        "&!!!- and España"
+    );
+}
@@ -0,0 +1,77 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, config};
+use imap_client::client::ImapClient;
+use imap_client::input::ImapConfig;
+use imap_client::maildir::{file_name, maildir_flags, with_keywords};
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use imap_client::storage::OutputFormat;
+
+fn flags(flags: &[&str]) -> Vec<String> {
+    flags.iter().map(|flag| flag.to_string()).collect()
+}
+
+#[test]
+fn imap_flags_map_to_sorted_maildir_letters() {
+    assert_eq!(
+        maildir_flags(&flags(&["\\Seen", "\\Flagged", "\\Answered", "$Label1"])),
+        "FRS"
+    );
+    assert_eq!(maildir_flags(&flags(&["\\Draft", "\\Deleted"])), "DT");
+    assert_eq!(maildir_flags(&[]), "");
+    #[cfg(unix)]
+    assert_eq!(
+        file_name("email_00001", &flags(&["\\seen"])),
+        "email_00001:2,S"
+    );
+
+    let tagged = with_keywords(
+        b"Subject: Hi\r\n\r\nBody\r\n",
+        &flags(&["\\Inbox", "Project X", "a,b"]),
+    );
+    assert_eq!(
+        tagged,
+        b"X-Keywords: inbox, Project X, a b\r\nSubject: Hi\r\n\r\nBody\r\n"
+    );
+}
+
+#[cfg(unix)]
+#[tokio::test]
+async fn messages_are_written_with_flags_and_labels() {
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: (1..=3).map(|i| synthetic_message(i, 200)).collect(),
+    }])
+    .await
+    .unwrap();
+    let dir = archive_dir("fetch");
+    let config = ImapConfig {
+        format: OutputFormat::Maildir,
+        ..config(&dir)
+    };
+
+    let summary = ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 3);
+    assert!(dir.join("new").is_dir() && dir.join("tmp").is_dir());
+
+    // The mock's flags and labels cycle with the UID
+    let mut names: Vec<String> = std::fs::read_dir(dir.join("cur"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    names.sort();
+    assert_eq!(
+        names,
+        ["email_00001:2,S", "email_00002:2,FRS", "email_00003:2,"]
+    );
+    let flagged = std::fs::read_to_string(dir.join("cur/email_00002:2,FRS")).unwrap();
+    assert!(flagged.starts_with("X-Keywords: inbox, important, Project X\r\nFrom: "));
+    let unlabelled = std::fs::read(dir.join("cur/email_00003:2,")).unwrap();
+    assert_eq!(unlabelled, synthetic_message(3, 200));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,108 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, config};
+use imap_client::client::ImapClient;
+use imap_client::folders::{parse_list_response, parse_namespace_response, Namespace};
+use imap_client::input::ImapConfig;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use imap_client::provider::Provider;
+
+fn mailbox(name: &str, messages: std::ops::Range<usize>) -> MockMailbox {
+    MockMailbox {
+        name: name.to_string(),
+        messages: messages.map(|i| synthetic_message(i, 200)).collect(),
+    }
+}
+
+fn cyrus() -> Namespace {
+    Namespace {
+        prefix: "INBOX.".to_string(),
+        delimiter: Some('.'),
+    }
+}
+
+#[test]
+fn personal_namespace_is_parsed() {
+    assert_eq!(
+        parse_namespace_response(r#"NAMESPACE (("" "/")) NIL NIL"#),
+        Some(Namespace::default())
+    );
+    assert_eq!(
+        parse_namespace_response(r#"NAMESPACE (("INBOX." ".")) NIL (("shared." "."))"#),
+        Some(cyrus())
+    );
+    assert_eq!(parse_namespace_response("NAMESPACE NIL NIL NIL"), None);
+}
+
+#[test]
+fn names_are_made_relative_to_the_prefix() {
+    let namespace = cyrus();
+    assert_eq!(namespace.relative("INBOX.Work.2024"), "Work.2024");
+    assert_eq!(namespace.relative("inbox.Work"), "Work");
+    assert_eq!(namespace.relative("INBOX"), "INBOX");
+    assert_eq!(namespace.full_name("Work/2024"), "INBOX.Work.2024");
+
+    let mailbox = parse_list_response(r#"LIST (\HasNoChildren) "." "INBOX.Work.2024""#).unwrap();
+    assert_eq!(mailbox.components_in(&namespace), vec!["Work", "2024"]);
+    assert_eq!(
+        mailbox.components_in(&Namespace::default()),
+        vec!["INBOX", "Work", "2024"]
+    );
+}
+
+#[tokio::test]
+async fn cyrus_folders_are_saved_without_the_prefix() {
+    let server = MockServer::start(vec![
+        mailbox("INBOX", 0..2),
+        mailbox("INBOX.Work.2024", 2..3),
+    ])
+    .await
+    .unwrap();
+    let dir = archive_dir("all");
+    let config = ImapConfig {
+        all_folders: true,
+        ..config(&dir)
+    };
+
+    let summary = ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 3);
+    assert!(dir.join("INBOX").join("email_00002.eml").exists());
+    assert!(dir
+        .join("Work")
+        .join("2024")
+        .join("email_00001.eml")
+        .exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn special_folders_are_found_under_the_prefix() {
+    let server = MockServer::start(vec![
+        mailbox("INBOX", 0..2),
+        mailbox("INBOX.Work", 2..3),
+        mailbox("INBOX.Sent", 3..5),
+    ])
+    .await
+    .unwrap();
+    let dir = archive_dir("cyrus");
+    let config = ImapConfig {
+        provider: Provider::Fastmail,
+        include_sent: true,
+        ..config(&dir)
+    };
+
+    let summary = ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    // The unmarked "Sent" is found under the namespace prefix
+    assert_eq!((summary.found, summary.saved), (4, 4));
+    assert!(dir.join("Sent").join("email_00002.eml").exists());
+    assert!(!dir.join("INBOX.Sent").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,231 @@
+use imap_client::args::parse_args;
+use imap_client::dns::{Resolver, TYPE_A};
+use imap_client::input::ImapConfig;
+use imap_client::net::{connect_tor, order_addresses, race, IpPreference};
+use std::net::SocketAddr;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+fn addrs(list: &[&str]) -> Vec<SocketAddr> {
+    list.iter().map(|addr| addr.parse().unwrap()).collect()
+}
+
+#[test]
+fn address_families_alternate_starting_with_the_preferred_one() {
+    let resolved = addrs(&["[2001:db8::1]:993", "[2001:db8::2]:993", "192.0.2.1:993"]);
+    assert_eq!(
+        order_addresses(resolved.clone(), IpPreference::System),
+        addrs(&["[2001:db8::1]:993", "192.0.2.1:993", "[2001:db8::2]:993"])
+    );
+    assert_eq!(
+        order_addresses(resolved, IpPreference::Ipv4),
+        addrs(&["192.0.2.1:993", "[2001:db8::1]:993", "[2001:db8::2]:993"])
+    );
+
+    let preference = |flag: &str| parse_args([flag.to_string()]).unwrap().ip_preference;
+    assert_eq!(preference("--prefer-ipv4"), Some(IpPreference::Ipv4));
+    assert_eq!(preference("--prefer-ipv6"), Some(IpPreference::Ipv6));
+}
+
+#[tokio::test]
+async fn an_address_that_fails_or_hangs_does_not_hold_up_the_next() {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let working = listener.local_addr().unwrap();
+    // Nothing listens on a port just freed, and TEST-NET-1 goes nowhere
+    let refused = {
+        let closed = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        closed.local_addr().unwrap()
+    };
+    let nowhere: SocketAddr = "192.0.2.1:993".parse().unwrap();
+
+    let started = Instant::now();
+    let stream = race(vec![nowhere, refused, working]).await.unwrap();
+    assert_eq!(stream.peer_addr().unwrap(), working);
+    assert!(started.elapsed() < Duration::from_secs(2));
+
+    assert!(race(vec![refused]).await.is_err());
+}
+
+#[test]
+fn a_resolver_is_an_address_or_a_url() {
+    let resolver = |s: &str| s.parse::<Resolver>();
+    assert_eq!(resolver("system").unwrap(), Resolver::System);
+    assert_eq!(
+        resolver("9.9.9.9").unwrap(),
+        Resolver::Dns("9.9.9.9:53".parse().unwrap())
+    );
+    assert_eq!(
+        resolver("[2620:fe::fe]:5353").unwrap(),
+        Resolver::Dns("[2620:fe::fe]:5353".parse().unwrap())
+    );
+    assert_eq!(
+        resolver("https://1.1.1.1/dns-query").unwrap(),
+        Resolver::Https("https://1.1.1.1/dns-query".to_string())
+    );
+    assert!(resolver("dns.example.org").is_err());
+    assert!(resolver("https://").is_err());
+
+    let given = parse_args(["--dns", "127.0.0.53"].map(String::from)).unwrap();
+    assert_eq!(given.resolver, "127.0.0.53".parse().ok());
+}
+
+/// Answers every A query for `imap.example.org` with an alias whose address
+/// is 127.0.0.7, every other query for it with no records, and any other
+/// name as one that doesn't exist.
+async fn dns_server() -> SocketAddr {
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let addr = socket.local_addr().unwrap();
+    tokio::spawn(async move {
+        let mut buf = [0u8; 512];
+        loop {
+            let (len, from) = socket.recv_from(&mut buf).await.unwrap();
+            let query = &buf[..len];
+            let question = &query[12..];
+            let name_end = question.iter().position(|&b| b == 0).unwrap();
+            let qtype = u16::from_be_bytes([question[name_end + 1], question[name_end + 2]]);
+            let known = &question[..name_end] == b"\x04imap\x07example\x03org";
+
+            let mut answer = query[..2].to_vec();
+            let rcode = if known { 0 } else { 3 };
+            let records = if known && qtype == TYPE_A { 2u16 } else { 0 };
+            for field in [0x8180 | rcode, 1, records, 0, 0] {
+                answer.extend_from_slice(&u16::to_be_bytes(field));
+            }
+            answer.extend_from_slice(question);
+            if records > 0 {
+                // imap.example.org is an alias of mail.example.org, its own
+                // name given by a pointer to the question's
+                answer.extend_from_slice(&[0xc0, 12, 0, 5, 0, 1, 0, 0, 1, 0, 0, 7]);
+                let alias_at = answer.len() as u8;
+                answer.extend_from_slice(b"\x04mail\xc0\x11");
+                answer.extend_from_slice(&[0xc0, alias_at, 0, 1, 0, 1, 0, 0, 1, 0, 0, 4]);
+                answer.extend_from_slice(&[127, 0, 0, 7]);
+            }
+            socket.send_to(&answer, from).await.unwrap();
+        }
+    });
+    addr
+}
+
+#[tokio::test]
+async fn names_can_be_resolved_by_a_given_dns_server() {
+    let resolver = Resolver::Dns(dns_server().await);
+    assert_eq!(
+        resolver.resolve("imap.example.org:993").await.unwrap(),
+        vec!["127.0.0.7:993".parse().unwrap()]
+    );
+    // Addresses are used as they are, without asking
+    assert_eq!(
+        resolver.resolve("[::1]:143").await.unwrap(),
+        vec!["[::1]:143".parse().unwrap()]
+    );
+    let error = resolver
+        .resolve("nowhere.example.org:993")
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("no such host"), "{}", error);
+}
+
+#[test]
+fn tor_is_on_its_usual_port_unless_told_otherwise() {
+    let tor = |args: &[&str]| {
+        let mut config = ImapConfig::default();
+        parse_args(args.iter().map(|arg| arg.to_string()))
+            .unwrap()
+            .apply_to(&mut config);
+        config.tor
+    };
+    assert_eq!(tor(&[]), None);
+    assert_eq!(tor(&["--tor"]), "127.0.0.1:9050".parse().ok());
+    assert_eq!(
+        tor(&["--tor-proxy", "127.0.0.1:9150"]),
+        "127.0.0.1:9150".parse().ok()
+    );
+    assert!(parse_args(["--tor-proxy", "localhost"].map(String::from)).is_err());
+}
+
+/// A SOCKS5 proxy that refuses the first connection it is asked for, as
+/// Tor does when a circuit fails, and greets the client on the others. It
+/// records the user name and target of each request.
+async fn socks_proxy() -> (SocketAddr, Arc<Mutex<Vec<(String, String)>>>) {
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let requests = Arc::new(Mutex::new(Vec::new()));
+    let seen = Arc::clone(&requests);
+    tokio::spawn(async move {
+        loop {
+            let (mut stream, _) = listener.accept().await.unwrap();
+            let mut head = [0u8; 2];
+            stream.read_exact(&mut head).await.unwrap();
+            let mut methods = vec![0u8; head[1] as usize];
+            stream.read_exact(&mut methods).await.unwrap();
+            assert!(methods.contains(&2), "no user name offered");
+            stream.write_all(&[5, 2]).await.unwrap();
+
+            stream.read_exact(&mut head).await.unwrap();
+            let mut user = vec![0u8; head[1] as usize];
+            stream.read_exact(&mut user).await.unwrap();
+            let len = stream.read_u8().await.unwrap();
+            stream
+                .read_exact(&mut vec![0u8; len as usize])
+                .await
+                .unwrap();
+            stream.write_all(&[1, 0]).await.unwrap();
+
+            let mut request = [0u8; 4];
+            stream.read_exact(&mut request).await.unwrap();
+            assert_eq!(request[3], 3, "the name was resolved locally");
+            let len = stream.read_u8().await.unwrap();
+            let mut host = vec![0u8; len as usize];
+            stream.read_exact(&mut host).await.unwrap();
+            let port = stream.read_u16().await.unwrap();
+
+            let first = {
+                let mut requests = seen.lock().unwrap();
+                requests.push((
+                    String::from_utf8(user).unwrap(),
+                    format!("{}:{}", String::from_utf8(host).unwrap(), port),
+                ));
+                requests.len() == 1
+            };
+            let reply = if first { 4 } else { 0 };
+            stream
+                .write_all(&[5, reply, 0, 1, 0, 0, 0, 0, 0, 0])
+                .await
+                .unwrap();
+            if !first {
+                stream.write_all(b"* OK ready\r\n").await.unwrap();
+            }
+        }
+    });
+    (addr, requests)
+}
+
+#[tokio::test]
+async fn connections_through_tor_retry_on_a_new_circuit() {
+    let (proxy, requests) = socks_proxy().await;
+    let mut stream = connect_tor(proxy, "imap.example.org:993", "account")
+        .await
+        .unwrap();
+    let mut greeting = [0u8; 12];
+    stream.read_exact(&mut greeting).await.unwrap();
+    assert_eq!(&greeting, b"* OK ready\r\n");
+
+    let requests = requests.lock().unwrap().clone();
+    assert_eq!(requests.len(), 2);
+    assert!(requests
+        .iter()
+        .all(|(_, target)| target == "imap.example.org:993"));
+    // A different user name is what gets Tor to build another circuit
+    assert_ne!(requests[0].0, requests[1].0);
+
+    let closed = std::net::TcpListener::bind("127.0.0.1:0")
+        .unwrap()
+        .local_addr()
+        .unwrap();
+    let error = connect_tor(closed, "imap.example.org:993", "account")
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("is it running"), "{}", error);
+}
@@ -0,0 +1,105 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::archive_dir;
+use imap_client::client::ImapClient;
+use imap_client::error_imap::ClientError;
+use imap_client::input::ImapConfig;
+use imap_client::message::parse_address;
+use imap_client::mock::{MockMailbox, MockServer};
+use imap_client::storage::{Organize, ThreadMode};
+use std::path::Path;
+
+fn message(from: &str, index: usize) -> Vec<u8> {
+    format!(
+        "From: {}\r\nSubject: Message {}\r\nMessage-ID: <{}@example.com>\r\n\r\nHello\r\n",
+        from, index, index
+    )
+    .into_bytes()
+}
+
+fn config(dir: &Path, organize: Organize) -> ImapConfig {
+    ImapConfig {
+        organize: Some(organize),
+        ..common::config(dir)
+    }
+}
+
+#[test]
+fn directories_are_keyed_by_address_or_domain() {
+    let alice = parse_address("Alice <Alice@Example.COM>").unwrap();
+    assert_eq!(
+        Organize::BySender.directory(Some(&alice)),
+        "alice@example.com"
+    );
+    assert_eq!(Organize::ByDomain.directory(Some(&alice)), "example.com");
+    assert_eq!(Organize::ByDomain.directory(None), "unknown-sender");
+    assert_eq!("by-sender".parse::<Organize>().unwrap(), Organize::BySender);
+    assert_eq!("by-domain".parse::<Organize>().unwrap(), Organize::ByDomain);
+    assert!("by-date".parse::<Organize>().is_err());
+}
+
+#[tokio::test]
+async fn messages_are_grouped_by_sender() {
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: vec![
+            message("Alice <alice@example.com>", 0),
+            message("bob@example.com", 1),
+            message("ALICE@example.com", 2),
+            message("undisclosed-recipients:;", 3),
+        ],
+    }])
+    .await
+    .unwrap();
+
+    let by_sender = archive_dir("sender");
+    let summary = ImapClient::new(config(&by_sender, Organize::BySender), server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 4);
+    assert!(by_sender.join("alice@example.com/email_00001.eml").exists());
+    assert!(by_sender.join("bob@example.com/email_00002.eml").exists());
+    assert!(by_sender.join("alice@example.com/email_00003.eml").exists());
+    assert!(by_sender.join("unknown-sender/email_00004.eml").exists());
+
+    let by_domain = archive_dir("domain");
+    ImapClient::new(config(&by_domain, Organize::ByDomain), server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    let mut entries: Vec<String> = std::fs::read_dir(by_domain.join("example.com"))
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name().to_string_lossy().into_owned())
+        .collect();
+    entries.sort();
+    assert_eq!(
+        entries,
+        ["email_00001.eml", "email_00002.eml", "email_00003.eml"]
+    );
+    std::fs::remove_dir_all(&by_sender).unwrap();
+    std::fs::remove_dir_all(&by_domain).unwrap();
+}
+
+#[tokio::test]
+async fn organize_and_threads_are_exclusive() {
+    let server = MockServer::start(Vec::new()).await.unwrap();
+    let dir = archive_dir("threads");
+    let config = ImapConfig {
+        threads: Some(ThreadMode::Directory),
+        ..config(&dir, Organize::BySender)
+    };
+
+    let error = ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(error, ClientError::InvalidArgument(_)),
+        "{:?}",
+        error
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
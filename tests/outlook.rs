@@ -0,0 +1,129 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::archive_dir;
+use imap_client::audit::AUDIT_FILE;
+use imap_client::client::ImapClient;
+use imap_client::error_imap::ClientError;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{
+    synthetic_message, MockMailbox, MockOAuthServer, MockServer, MOCK_ACCESS_TOKEN,
+};
+use imap_client::oauth::OAuthCredentials;
+use imap_client::provider::Provider;
+use imap_client::session::{Session, Status};
+use tokio::net::TcpStream;
+
+fn config(dir: &std::path::Path, oauth: Option<OAuthCredentials>) -> ImapConfig {
+    ImapConfig {
+        email: "test@contoso.com".to_string(),
+        dir_path: dir.to_path_buf(),
+        provider: Provider::Outlook,
+        oauth,
+        quiet: true,
+        ..ImapConfig::default()
+    }
+}
+
+async fn mock_server() -> MockServer {
+    MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: (0..3).map(|i| synthetic_message(i, 200)).collect(),
+    }])
+    .await
+    .unwrap()
+}
+
+#[tokio::test]
+async fn device_flow_signs_in_with_xoauth2() {
+    let server = mock_server().await;
+    let oauth = MockOAuthServer::start().await.unwrap();
+    let dir = archive_dir("device");
+    let config = ImapConfig {
+        read_only: true,
+        ..config(
+            &dir,
+            Some(OAuthCredentials {
+                client_id: "client".to_string(),
+                token_url: Some(oauth.token_url()),
+                device_url: Some(oauth.device_url()),
+                ..OAuthCredentials::default()
+            }),
+        )
+    };
+
+    let summary = ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!((summary.found, summary.saved), (3, 3));
+
+    // The audit log shows the mechanism but never the token
+    let audit = std::fs::read_to_string(dir.join(AUDIT_FILE)).unwrap();
+    assert!(audit.contains("AUTHENTICATE XOAUTH2 ***"));
+    assert!(!audit.contains(MOCK_ACCESS_TOKEN));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn rejected_access_token_is_answered_and_reported() {
+    let server = mock_server().await;
+    let stream = TcpStream::connect(server.addr()).await.unwrap();
+    let mut session = Session::new(stream);
+    session.read_greeting().await.unwrap();
+
+    // The error challenge has to be acknowledged before the NO arrives
+    let completion = session
+        .authenticate_xoauth2("test@contoso.com", "expired-token")
+        .await
+        .unwrap();
+    assert_eq!(completion.status, Status::No);
+    let completion = session
+        .authenticate_xoauth2("test@contoso.com", MOCK_ACCESS_TOKEN)
+        .await
+        .unwrap();
+    assert_eq!(completion.status, Status::Ok);
+}
+
+#[tokio::test]
+async fn refresh_token_skips_the_device_flow() {
+    let server = mock_server().await;
+    let oauth = MockOAuthServer::start().await.unwrap();
+    let dir = archive_dir("refresh");
+    // The device endpoint is unreachable, so only the refresh grant can succeed
+    let config = config(
+        &dir,
+        Some(OAuthCredentials {
+            client_id: "client".to_string(),
+            refresh_token: "refresh".to_string(),
+            token_url: Some(oauth.token_url()),
+            device_url: Some("http://127.0.0.1:1/devicecode".to_string()),
+            ..OAuthCredentials::default()
+        }),
+    );
+
+    let summary = ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 3);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn outlook_without_client_id_asks_for_app_registration() {
+    let server = mock_server().await;
+    let dir = archive_dir("unregistered");
+
+    let error = ImapClient::new(config(&dir, None), server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&error, ClientError::ActionRequired { guidance, .. } if guidance.contains("oauth_client_id")),
+        "{:?}",
+        error
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
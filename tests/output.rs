@@ -0,0 +1,55 @@
+use imap_client::args::parse_args;
+use imap_client::input::ImapConfig;
+use imap_client::storage::{OutputFormat, ThreadMode};
+
+/// An account's settings after `given` is applied on top of `config`.
+fn applied(given: &[&str], mut config: ImapConfig) -> ImapConfig {
+    parse_args(given.iter().map(|arg| arg.to_string()))
+        .unwrap()
+        .apply_to(&mut config);
+    config
+}
+
+#[test]
+fn text_copies_are_turned_on_from_the_command_line() {
+    assert!(applied(&["--text"], ImapConfig::default()).export_text);
+    assert!(!applied(&[], ImapConfig::default()).export_text);
+    // An account that asks for them keeps them
+    let account = ImapConfig {
+        export_text: true,
+        ..ImapConfig::default()
+    };
+    assert!(applied(&[], account).export_text);
+}
+
+#[test]
+fn html_text_turns_on_text_copies_too() {
+    let config = applied(&["--html-text"], ImapConfig::default());
+    assert!(config.html_text);
+    assert!(config.export_text);
+    assert!(!applied(&["--text"], ImapConfig::default()).html_text);
+}
+
+#[test]
+fn format_replaces_the_account_format_only_when_given() {
+    let config = applied(&["--format", "pdf"], ImapConfig::default());
+    assert_eq!(config.format, OutputFormat::Pdf);
+    let account = ImapConfig {
+        format: OutputFormat::Maildir,
+        ..ImapConfig::default()
+    };
+    assert_eq!(applied(&[], account).format, OutputFormat::Maildir);
+    assert!(parse_args(["--format", "docx"].map(String::from)).is_err());
+}
+
+#[test]
+fn threads_replace_the_account_mode_only_when_given() {
+    let config = applied(&["--threads", "file"], ImapConfig::default());
+    assert_eq!(config.threads, Some(ThreadMode::File));
+    let account = ImapConfig {
+        threads: Some(ThreadMode::Directory),
+        ..ImapConfig::default()
+    };
+    assert_eq!(applied(&[], account).threads, Some(ThreadMode::Directory));
+    assert_eq!(applied(&[], ImapConfig::default()).threads, None);
+}
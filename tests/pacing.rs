@@ -0,0 +1,68 @@
+use imap_client::error_imap::{ClientError, ErrorContext, Phase};
+use imap_client::pacing::{is_throttle, parse_delay, Pacing};
+use std::time::{Duration, Instant};
+
+#[tokio::test]
+async fn batch_starts_are_spaced_with_jitter() {
+    let pacing = Pacing::new(Duration::from_millis(100), Duration::from_millis(50));
+    let started = Instant::now();
+    pacing.wait_turn().await;
+    // The first batch doesn't wait
+    assert!(started.elapsed() < Duration::from_millis(50));
+    pacing.wait_turn().await;
+    pacing.wait_turn().await;
+    let elapsed = started.elapsed();
+    assert!(elapsed >= Duration::from_millis(200), "{:?}", elapsed);
+    assert!(elapsed < Duration::from_millis(400), "{:?}", elapsed);
+}
+
+#[test]
+fn throttling_backs_off_until_batches_succeed() {
+    let pacing = Pacing::new(Duration::ZERO, Duration::ZERO);
+    assert_eq!(pacing.backoff(), Duration::ZERO);
+    assert_eq!(pacing.throttled(), Duration::from_secs(1));
+    assert_eq!(pacing.throttled(), Duration::from_secs(2));
+    for _ in 0..10 {
+        pacing.throttled();
+    }
+    assert_eq!(pacing.backoff(), Duration::from_secs(60));
+
+    pacing.succeeded();
+    assert_eq!(pacing.backoff(), Duration::from_secs(30));
+    for _ in 0..10 {
+        pacing.succeeded();
+    }
+    assert_eq!(pacing.backoff(), Duration::ZERO);
+}
+
+#[test]
+fn throttling_responses_are_recognised() {
+    let failed = |text: &str| ClientError::CommandFailed {
+        tag: "A0007".to_string(),
+        command: "UID FETCH".to_string(),
+        text: text.to_string(),
+    };
+    assert!(is_throttle(&failed("NO [THROTTLED] Request is throttled")));
+    assert!(is_throttle(&failed(
+        "NO Account exceeded command or bandwidth limits"
+    )));
+    assert!(is_throttle(&ClientError::ServerBye(
+        "[UNAVAILABLE] Too many simultaneous connections".to_string()
+    )));
+    let batch = ClientError::Batch {
+        context: ErrorContext {
+            mailbox: "INBOX".to_string(),
+            uids: (1, 500),
+            phase: Phase::Fetch,
+        },
+        source: Box::new(failed("NO [THROTTLED] Slow down")),
+    };
+    assert!(is_throttle(&batch));
+    assert!(!is_throttle(&failed("NO [NONEXISTENT] No such mailbox")));
+    assert!(!is_throttle(&ClientError::ParseError));
+
+    assert_eq!(parse_delay("250ms").unwrap(), Duration::from_millis(250));
+    assert_eq!(parse_delay("2s").unwrap(), Duration::from_secs(2));
+    assert_eq!(parse_delay("75").unwrap(), Duration::from_millis(75));
+    assert!(parse_delay("soon").is_err());
+}
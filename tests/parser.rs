@@ -0,0 +1,272 @@
+use imap_client::parser::{parse_response, response_len, ParseError, Parsed, Value};
+use std::path::Path;
+
+fn fetch(input: &[u8]) -> imap_client::parser::Fetch<'_> {
+    match parse_response(input) {
+        Ok((Parsed::Fetch(fetch), used)) => {
+            assert_eq!(used, input.len());
+            fetch
+        }
+        other => panic!("expected a FETCH response, got {:?}", other),
+    }
+}
+
+fn corpus(name: &str) -> Vec<u8> {
+    let path = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("fuzz/corpus/parse_response")
+        .join(name);
+    std::fs::read(path).unwrap()
+}
+
+#[test]
+fn body_literal_and_uid() {
+    let input = corpus("fetch_body.imap");
+    let fetch = fetch(&input);
+    assert_eq!(fetch.seq, 1);
+    assert_eq!(fetch.uid(), Some(101));
+    assert_eq!(
+        fetch.body().unwrap(),
+        b"Subject: Hello\r\n\r\nJust a short message.\r\n"
+    );
+}
+
+#[test]
+fn partial_body_with_its_origin() {
+    let input = b"* 3 FETCH (UID 7 BODY[]<2048> {5}\r\nworld)\r\n";
+    let part = fetch(input);
+    assert!(part.has_body());
+    assert_eq!(part.body().unwrap(), b"world");
+    assert_eq!(part.body_origin(), 2048);
+
+    let whole = b"* 3 FETCH (UID 7 BODY[] {5}\r\nhello)\r\n";
+    assert_eq!(fetch(whole).body_origin(), 0);
+}
+
+#[test]
+fn items_after_the_literal() {
+    let input = corpus("fetch_gmail_items.imap");
+    let fetch = fetch(&input);
+    assert_eq!(fetch.number("X-GM-THRID"), Some(1278455344230334865));
+    assert_eq!(fetch.uid(), Some(102));
+    match fetch.get("X-GM-LABELS") {
+        Some(Value::List(labels)) => {
+            assert_eq!(labels.len(), 3);
+            assert_eq!(labels[0].as_bytes().unwrap(), br"\Inbox");
+            assert_eq!(labels[2], Value::Atom("Work"));
+        }
+        other => panic!("unexpected labels {:?}", other),
+    }
+}
+
+#[test]
+fn several_literals_in_one_response() {
+    let input = corpus("fetch_multi_literal.imap");
+    let fetch = fetch(&input);
+    assert_eq!(
+        fetch.get("BODY[HEADER]").unwrap().as_bytes().unwrap(),
+        b"Subject: Split\r\n\r\n"
+    );
+    assert_eq!(
+        fetch.get("BODY[TEXT]").unwrap().as_bytes().unwrap(),
+        b"Body.\r\n"
+    );
+}
+
+#[test]
+fn binary_data_and_literal_lookalikes_stay_in_the_literal() {
+    let input = corpus("fetch_8bit.imap");
+    let body = fetch(&input).body().unwrap().to_vec();
+    assert!(body.contains(&0));
+    assert!(body.ends_with(b"\xff\xfe {3}\r\n"));
+}
+
+#[test]
+fn sections_quoted_strings_and_flags() {
+    let input = corpus("fetch_header_fields.imap");
+    let fields = fetch(&input);
+    assert_eq!(fields.number("RFC822.SIZE"), Some(2048));
+    assert!(fields
+        .get("BODY[HEADER.FIELDS (FROM SUBJECT)]<0>")
+        .is_some());
+
+    let input = corpus("fetch_quoted_body.imap");
+    assert_eq!(
+        fetch(&input).body().unwrap(),
+        br#"quoted "body" with \ escapes"#
+    );
+
+    let input = corpus("fetch_flags.imap");
+    let flags = fetch(&input);
+    assert!(flags.body().is_none());
+    assert_eq!(
+        flags.get("FLAGS"),
+        Some(&Value::List(vec![
+            Value::Atom(r"\Seen"),
+            Value::Atom(r"\Flagged")
+        ]))
+    );
+}
+
+#[test]
+fn other_responses_are_passed_through_one_at_a_time() {
+    let input = corpus("select_responses.imap");
+    let mut rest = &input[..];
+    let mut lines = Vec::new();
+    while !rest.is_empty() {
+        let (parsed, used) = parse_response(rest).unwrap();
+        match parsed {
+            Parsed::Other(line) => lines.push(line.to_vec()),
+            Parsed::Fetch(_) => panic!("unexpected FETCH"),
+        }
+        rest = &rest[used..];
+    }
+    assert_eq!(lines.len(), 3);
+    assert_eq!(lines[0], b"* 12 EXISTS\r\n");
+}
+
+#[test]
+fn truncated_input_is_incomplete() {
+    let input = corpus("fetch_body.imap");
+    for end in 0..input.len() {
+        assert_eq!(
+            parse_response(&input[..end]).unwrap_err(),
+            ParseError::Incomplete,
+            "prefix of {} bytes",
+            end
+        );
+    }
+    assert_eq!(response_len(&input), Ok(input.len()));
+}
+
+#[test]
+fn malformed_fetch_is_an_error_not_a_panic() {
+    for input in [
+        &b"* 1 FETCH (UID\r\n"[..],
+        b"* 1 FETCH (UID 1 BODY[] \"unterminated)\r\n",
+        b"* 1 FETCH UID 1\r\n",
+        b"* 1 FETCH (BODY[HEADER 1)\r\n",
+        b"* 1 FETCH ((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((((\r\n",
+    ] {
+        assert!(matches!(
+            parse_response(input),
+            Err(ParseError::Invalid { .. })
+        ));
+    }
+}
+
+#[test]
+fn whole_corpus_parses() {
+    let dir = Path::new(env!("CARGO_MANIFEST_DIR")).join("fuzz/corpus/parse_response");
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        let input = std::fs::read(&path).unwrap();
+        let mut rest = &input[..];
+        while !rest.is_empty() {
+            let (_, used) =
+                parse_response(rest).unwrap_or_else(|e| panic!("{}: {}", path.display(), e));
+            rest = &rest[used..];
+        }
+    }
+}
+
+#[test]
+fn nil_and_empty_bodies_are_distinguished_from_missing_ones() {
+    let input = corpus("fetch_nil_body.imap");
+    let nil = fetch(&input);
+    assert!(nil.has_body());
+    assert_eq!(nil.body(), None);
+    assert_eq!(nil.uid(), Some(104));
+
+    let input = corpus("fetch_empty_literal.imap");
+    let empty = fetch(&input);
+    assert!(empty.has_body());
+    assert_eq!(empty.body(), Some(&b""[..]));
+
+    let input = corpus("fetch_flags.imap");
+    assert!(!fetch(&input).has_body());
+}
+
+#[test]
+fn literal_contents_never_shift_response_boundaries() {
+    let input = corpus("fetch_literal_lookalikes.imap");
+    let mut rest = &input[..];
+    let mut responses = Vec::new();
+    while !rest.is_empty() {
+        let (parsed, used) = parse_response(rest).unwrap();
+        responses.push(parsed);
+        rest = &rest[used..];
+    }
+
+    assert_eq!(responses.len(), 4);
+    match &responses[0] {
+        Parsed::Fetch(fetch) => {
+            assert_eq!(fetch.uid(), Some(107));
+            assert!(fetch
+                .body()
+                .unwrap()
+                .ends_with(b"{5}\r\nA0001 OK FETCH completed\r\n"));
+        }
+        other => panic!("expected a FETCH response, got {:?}", other),
+    }
+    match (&responses[1], &responses[2]) {
+        (Parsed::Fetch(empty), Parsed::Fetch(nil)) => {
+            assert_eq!((empty.uid(), empty.body()), (Some(108), Some(&b""[..])));
+            assert_eq!((nil.uid(), nil.body()), (Some(109), None));
+        }
+        other => panic!("expected two FETCH responses, got {:?}", other),
+    }
+    assert_eq!(responses[3], Parsed::Other(b"A0001 OK FETCH completed\r\n"));
+}
+
+#[tokio::test]
+async fn responses_arriving_in_pieces_are_read_whole() {
+    use imap_client::session::Session;
+    use tokio::io::AsyncWriteExt;
+
+    let large = vec![b'x'; 100_000];
+    let mut responses = vec![
+        b"* 1 FETCH (UID 7 BODY[] {5}\r\nhello)\r\n".to_vec(),
+        format!("* 2 FETCH (UID 8 BODY[] {{{}}}\r\n", large.len()).into_bytes(),
+        b"A0001 OK FETCH completed\r\n".to_vec(),
+    ];
+    responses[1].extend_from_slice(&large);
+    responses[1].extend_from_slice(b" FLAGS (\\Seen))\r\n");
+    let stream = responses.concat();
+
+    // A narrow pipe splits lines and literals at arbitrary points
+    let (client, mut server) = tokio::io::duplex(7);
+    let writer = tokio::spawn(async move { server.write_all(&stream).await.unwrap() });
+    let mut session = Session::new(client);
+    for expected in &responses {
+        assert_eq!(&session.read_response().await.unwrap(), expected);
+    }
+    writer.await.unwrap();
+    assert_eq!(fetch(&responses[1]).body(), Some(&large[..]));
+}
+
+#[test]
+fn literal_announcements_need_both_braces_and_a_size() {
+    use imap_client::session::trailing_literal_len;
+
+    assert_eq!(
+        trailing_literal_len("* 1 FETCH (BODY[] {12}"),
+        Some((12, 18))
+    );
+    assert_eq!(
+        trailing_literal_len("* 1 FETCH (BODY[] {12+}"),
+        Some((12, 18))
+    );
+    for line in [
+        "* 1 FETCH (BODY[] }",
+        "}",
+        "* 1 FETCH (BODY[] {}",
+        "* 1 FETCH (BODY[] {x}",
+        "* 1 FETCH (BODY[] {12",
+    ] {
+        assert_eq!(trailing_literal_len(line), None, "{:?}", line);
+    }
+    assert!(matches!(
+        parse_response(b"* 1 FETCH (BODY[] }\r\n"),
+        Err(ParseError::Invalid { .. })
+    ));
+}
@@ -0,0 +1,105 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, audited};
+use imap_client::client::ImapClient;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use imap_client::partial::{keep, pending, MIN_PARTIAL, PARTIAL_DIR};
+use imap_client::session::Interrupted;
+use std::path::{Path, PathBuf};
+
+fn saved_messages(dir: &Path) -> Vec<Vec<u8>> {
+    let mut paths: Vec<PathBuf> = std::fs::read_dir(dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "eml"))
+        .collect();
+    paths.sort();
+    paths
+        .iter()
+        .map(|path| std::fs::read(path).unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn a_large_message_resumes_where_the_connection_dropped() {
+    let large = synthetic_message(1, 3 * MIN_PARTIAL);
+    let messages = vec![
+        synthetic_message(0, 500),
+        large.clone(),
+        synthetic_message(2, 500),
+    ];
+    let server = MockServer::start_interrupting(
+        vec![MockMailbox {
+            name: "INBOX".to_string(),
+            messages: messages.clone(),
+        }],
+        2 * MIN_PARTIAL,
+    )
+    .await
+    .unwrap();
+    let dir = archive_dir("resume");
+
+    let summary = ImapClient::new(audited(&dir), server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 3);
+    assert_eq!(summary.failed_batches, 0);
+
+    // Only the rest of the message was asked for again
+    let audit = std::fs::read_to_string(dir.join("audit.log")).unwrap();
+    let rest = format!(
+        "BODY.PEEK[]<{}.{}>",
+        2 * MIN_PARTIAL,
+        large.len() - 2 * MIN_PARTIAL
+    );
+    assert!(audit.contains(&format!("UID FETCH 2 (INTERNALDATE {})", rest)));
+    let saved = saved_messages(&dir);
+    assert!(saved.contains(&large));
+    assert!(pending(&dir, 1, &[2]).unwrap().is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn only_large_message_bodies_are_kept() {
+    let dir = archive_dir("keep");
+    let interrupted = |response: &str, received: usize, len: usize| Interrupted {
+        response: response.as_bytes().to_vec(),
+        received: vec![b'x'; received],
+        len,
+    };
+
+    // Too little arrived to be worth keeping
+    let small = interrupted("* 1 FETCH (UID 9 BODY[] {3000000}\r\n", 1000, 3_000_000);
+    assert_eq!(keep(&dir, 5, &small).unwrap(), None);
+    // Not a message body
+    let header = interrupted(
+        "* 1 FETCH (UID 9 BODY[HEADER] {3000000}\r\n",
+        MIN_PARTIAL,
+        3_000_000,
+    );
+    assert_eq!(keep(&dir, 5, &header).unwrap(), None);
+    assert!(!dir.join(PARTIAL_DIR).exists());
+
+    let first = interrupted(
+        "* 1 FETCH (X-GM-THRID 4 UID 9 BODY[] {3000000}\r\n",
+        MIN_PARTIAL,
+        3_000_000,
+    );
+    assert_eq!(keep(&dir, 5, &first).unwrap(), Some(9));
+    // The rest of it, cut off again, adds to what was kept
+    let rest = format!("* 1 FETCH (UID 9 BODY[]<{}> {{2000000}}\r\n", MIN_PARTIAL);
+    assert_eq!(
+        keep(&dir, 5, &interrupted(&rest, 1000, 2_000_000)).unwrap(),
+        Some(9)
+    );
+    let kept = pending(&dir, 5, &[9]).unwrap();
+    assert_eq!(kept.len(), 1);
+    assert_eq!(kept[0].offset, MIN_PARTIAL as u64 + 1000);
+    assert_eq!(kept[0].total, 3_000_000);
+    // Kept parts belong to one UIDVALIDITY
+    assert!(pending(&dir, 6, &[9]).unwrap().is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,114 @@
+#![cfg(all(feature = "wasm", feature = "mock"))]
+
+mod common;
+
+use common::{archive_dir, config};
+use imap_client::client::ImapClient;
+use imap_client::error_imap::ClientError;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use imap_client::plugin::load_plugins;
+use std::path::{Path, PathBuf};
+
+/// A plugin whose `process` has `body` as its body, with a bump allocator.
+fn write_plugin(dir: &Path, data: &str, body: &str) -> PathBuf {
+    let path = dir.join("plugin.wat");
+    let module = format!(
+        r#"(module
+          (import "env" "set_name" (func $set_name (param i32 i32)))
+          (memory (export "memory") 1)
+          (global $next (mut i32) (i32.const 1024))
+          {data}
+          (func (export "alloc") (param $len i32) (result i32)
+            (local $ptr i32)
+            (local.set $ptr (global.get $next))
+            (global.set $next (i32.add (global.get $next) (local.get $len)))
+            (if (i32.gt_u (global.get $next) (i32.mul (memory.size) (i32.const 65536)))
+              (then (drop (memory.grow (i32.add (i32.shr_u (local.get $len) (i32.const 16))
+                                                (i32.const 1))))))
+            (local.get $ptr))
+          (func (export "process") (param i32 i32 i32 i32) (result i64)
+            {body}))"#
+    );
+    std::fs::write(&path, module).unwrap();
+    path
+}
+
+async fn fetch(dir: &Path, plugin: PathBuf, messages: Vec<Vec<u8>>) -> u32 {
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages,
+    }])
+    .await
+    .unwrap();
+    let config = ImapConfig {
+        plugins: vec![plugin],
+        ..config(dir)
+    };
+    let plugins = load_plugins(&config.plugins).unwrap();
+    let client = plugins.into_iter().fold(
+        ImapClient::new(config, server.url()),
+        ImapClient::with_processor,
+    );
+    client.fetch_all_emails().await.unwrap().saved
+}
+
+#[tokio::test]
+async fn plugin_renames_and_redacts() {
+    let dir = archive_dir("redact");
+    let plugin = write_plugin(
+        &dir,
+        r#"(data (i32.const 0) "redacted")
+           (data (i32.const 16) "X-Redacted: yes\r\n\r\nredacted\r\n")"#,
+        "(call $set_name (i32.const 0) (i32.const 8))
+         (i64.or (i64.shl (i64.const 16) (i64.const 32)) (i64.const 29))",
+    );
+
+    let saved = fetch(&dir, plugin, vec![synthetic_message(0, 200)]).await;
+    assert_eq!(saved, 1);
+    assert!(!dir.join("email_00001.eml").exists());
+    assert_eq!(
+        std::fs::read(dir.join("redacted.eml")).unwrap(),
+        b"X-Redacted: yes\r\n\r\nredacted\r\n"
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn plugin_filters_messages() {
+    let dir = archive_dir("filter");
+    // Messages over 1000 bytes are dropped, others kept as they are
+    let plugin = write_plugin(
+        &dir,
+        "",
+        "(if (result i64) (i32.gt_u (local.get 3) (i32.const 1000))
+           (then (i64.const -1))
+           (else (i64.const 0)))",
+    );
+    let small = synthetic_message(0, 200);
+
+    let saved = fetch(
+        &dir,
+        plugin,
+        vec![small.clone(), synthetic_message(1, 2000)],
+    )
+    .await;
+    assert_eq!(saved, 1);
+    assert_eq!(std::fs::read(dir.join("email_00001.eml")).unwrap(), small);
+    assert!(!dir.join("email_00002.eml").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn modules_without_the_interface_are_refused() {
+    let dir = archive_dir("refused");
+    let path = dir.join("empty.wat");
+    std::fs::write(&path, "(module (memory (export \"memory\") 1))").unwrap();
+    let error = load_plugins(&[path]).err().unwrap();
+    assert!(
+        matches!(&error, ClientError::PluginError { reason, .. } if reason.contains("`alloc`")),
+        "{:?}",
+        error
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
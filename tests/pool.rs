@@ -0,0 +1,92 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::archive_dir;
+use imap_client::client::ImapClient;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use imap_client::pool::SessionPool;
+use std::path::Path;
+
+fn config(dir: &Path) -> ImapConfig {
+    ImapConfig {
+        all_folders: true,
+        max_concurrent: 1,
+        batch_size: 2,
+        ..common::audited(dir)
+    }
+}
+
+#[test]
+fn sessions_go_to_batches_in_their_mailbox_first() {
+    let pool = SessionPool::new();
+    assert_eq!(pool.put("INBOX", 1, 3), None);
+    assert_eq!(pool.put("Work", 2, 3), None);
+    assert_eq!(pool.put("INBOX", 3, 3), None);
+    // Full, so the caller closes it
+    assert_eq!(pool.put("Work", 4, 3), Some(4));
+
+    assert_eq!(pool.take("Work"), Some((2, true)));
+    assert_eq!(pool.take("INBOX"), Some((3, true)));
+    assert_eq!(pool.take("Receipts"), Some((1, false)));
+    assert_eq!(pool.take("INBOX"), None);
+}
+
+#[tokio::test]
+async fn batches_reuse_one_connection_across_folders() {
+    let server = MockServer::start(vec![
+        MockMailbox {
+            name: "INBOX".to_string(),
+            messages: (0..4).map(|i| synthetic_message(i, 200)).collect(),
+        },
+        MockMailbox {
+            name: "Work".to_string(),
+            messages: (4..8).map(|i| synthetic_message(i, 200)).collect(),
+        },
+    ])
+    .await
+    .unwrap();
+    let dir = archive_dir("reuse");
+    let summary = ImapClient::new(config(&dir), server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 8);
+
+    let audit = std::fs::read_to_string(dir.join("audit.log")).unwrap();
+    let events: Vec<Vec<&str>> = audit
+        .lines()
+        .map(|line| line.split('\t').collect())
+        .collect();
+    // Listing folders and planning each of them, then one batch connection
+    let greetings = events.iter().filter(|e| e[2].starts_with("greeting"));
+    assert_eq!(greetings.count(), 4);
+    let batch_connection = events
+        .iter()
+        .find(|e| e[2].contains("BODY.PEEK[]"))
+        .map(|e| e[1])
+        .unwrap();
+    let commands: Vec<&str> = events
+        .iter()
+        .filter(|e| e[1] == batch_connection)
+        .filter_map(|e| e[2].strip_prefix("send "))
+        .map(|command| command.split_once(' ').unwrap().1)
+        .skip_while(|command| !command.starts_with("EXAMINE"))
+        .collect();
+    assert_eq!(
+        commands,
+        [
+            "EXAMINE \"INBOX\"",
+            "UID FETCH 1:2 (INTERNALDATE BODY.PEEK[])",
+            "NOOP",
+            "UID FETCH 3:4 (INTERNALDATE BODY.PEEK[])",
+            "EXAMINE \"Work\"",
+            "UID FETCH 1:2 (INTERNALDATE BODY.PEEK[])",
+            "NOOP",
+            "UID FETCH 3:4 (INTERNALDATE BODY.PEEK[])",
+            "LOGOUT",
+        ]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,169 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::archive_dir;
+use imap_client::client::ImapClient;
+use imap_client::error_imap::ClientError;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use imap_client::processor::{BatchInfo, MessageInfo, MessageProcessor};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+
+fn config(dir: &Path) -> ImapConfig {
+    ImapConfig {
+        batch_size: 2,
+        ..common::config(dir)
+    }
+}
+
+async fn mock_server(count: usize) -> MockServer {
+    MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: (0..count).map(|i| synthetic_message(i, 200)).collect(),
+    }])
+    .await
+    .unwrap()
+}
+
+/// Records what it is called with and tags every message it sees.
+#[derive(Default)]
+struct Recorder {
+    started: Mutex<Vec<u32>>,
+    saved: Mutex<Vec<PathBuf>>,
+    batches: Mutex<Vec<(String, usize, bool)>>,
+}
+
+impl MessageProcessor for Recorder {
+    fn on_message_start(&self, message: &MessageInfo) {
+        self.started.lock().unwrap().push(message.seq);
+    }
+
+    fn transform(&self, _message: &MessageInfo, body: Vec<u8>) -> Result<Vec<u8>, ClientError> {
+        let mut tagged = b"X-Processed: yes\r\n".to_vec();
+        tagged.extend(body);
+        Ok(tagged)
+    }
+
+    fn on_message_saved(&self, _message: &MessageInfo, path: &Path) {
+        self.saved.lock().unwrap().push(path.to_path_buf());
+    }
+
+    fn on_batch_complete(&self, batch: &BatchInfo) {
+        self.batches.lock().unwrap().push((
+            batch.mailbox.to_string(),
+            batch.messages,
+            batch.error.is_some(),
+        ));
+    }
+}
+
+#[tokio::test]
+async fn processors_see_and_rewrite_every_message() {
+    let server = mock_server(3).await;
+    let dir = archive_dir("rewrite");
+    let recorder = Arc::new(Recorder::default());
+
+    let summary = ImapClient::new(config(&dir), server.url())
+        .with_processor(recorder.clone())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 3);
+
+    let mut started = recorder.started.lock().unwrap().clone();
+    started.sort();
+    assert_eq!(started, vec![1, 2, 3]);
+    let saved = recorder.saved.lock().unwrap().clone();
+    assert_eq!(saved.len(), 3);
+    for path in &saved {
+        assert!(std::fs::read(path)
+            .unwrap()
+            .starts_with(b"X-Processed: yes\r\n"));
+    }
+
+    let mut batches = recorder.batches.lock().unwrap().clone();
+    batches.sort();
+    assert_eq!(
+        batches,
+        vec![
+            ("INBOX".to_string(), 1, false),
+            ("INBOX".to_string(), 2, false)
+        ]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+struct Refuse;
+
+impl MessageProcessor for Refuse {
+    fn transform(&self, message: &MessageInfo, body: Vec<u8>) -> Result<Vec<u8>, ClientError> {
+        if message.seq == 2 {
+            return Err(ClientError::InvalidArgument("refused".to_string()));
+        }
+        Ok(body)
+    }
+}
+
+#[tokio::test]
+async fn transform_errors_fail_the_batch() {
+    let server = mock_server(2).await;
+    let dir = archive_dir("refuse");
+    let recorder = Arc::new(Recorder::default());
+
+    let summary = ImapClient::new(config(&dir), server.url())
+        .with_processor(Arc::new(Refuse))
+        .with_processor(recorder.clone())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.failed_batches, 1);
+    assert!(!dir.join("email_00002.eml").exists());
+    assert_eq!(
+        *recorder.batches.lock().unwrap(),
+        vec![("INBOX".to_string(), 2, true)]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Takes a while over every message, noting how many it works on at once.
+#[derive(Default)]
+struct Slow {
+    running: Mutex<(usize, usize)>,
+}
+
+impl MessageProcessor for Slow {
+    fn transform(&self, _message: &MessageInfo, body: Vec<u8>) -> Result<Vec<u8>, ClientError> {
+        {
+            let mut running = self.running.lock().unwrap();
+            running.0 += 1;
+            running.1 = running.1.max(running.0);
+        }
+        std::thread::sleep(std::time::Duration::from_millis(50));
+        self.running.lock().unwrap().0 -= 1;
+        Ok(body)
+    }
+}
+
+#[tokio::test]
+async fn slow_processors_run_off_the_connections_thread() {
+    let server = mock_server(8).await;
+    let dir = archive_dir("slow");
+    let slow = Arc::new(Slow::default());
+
+    // A single-threaded runtime, as here, would run them one at a time
+    let summary = ImapClient::new(config(&dir), server.url())
+        .with_processor(slow.clone())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 8);
+    let peak = slow.running.lock().unwrap().1;
+    let cores = std::thread::available_parallelism().map_or(1, |n| n.get());
+    assert!(peak <= cores, "{} at once on {} cores", peak, cores);
+    if cores > 1 {
+        assert!(peak > 1, "one at a time");
+    }
+    std::fs::remove_dir_all(&dir).unwrap();
+}
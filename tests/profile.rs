@@ -0,0 +1,83 @@
+use imap_client::args::parse_args;
+use imap_client::input::{load_accounts, ImapConfig};
+use imap_client::profile::Profile;
+use imap_client::scheduler::Limits;
+use std::time::Duration;
+
+fn args(args: &[&str]) -> imap_client::args::CliArgs {
+    parse_args(args.iter().map(|arg| arg.to_string())).unwrap()
+}
+
+#[test]
+fn a_profile_sets_every_knob_and_flags_win() {
+    assert_eq!("Gentle".parse::<Profile>().unwrap(), Profile::Gentle);
+    assert!("reckless".parse::<Profile>().is_err());
+
+    // The normal profile is what an account gets anyway
+    let mut normal = ImapConfig::default();
+    Profile::Normal.apply(&mut normal);
+    let defaults = ImapConfig::default();
+    assert_eq!(normal.max_concurrent, defaults.max_concurrent);
+    assert_eq!(normal.batch_size, defaults.batch_size);
+    assert_eq!(normal.batch_delay, defaults.batch_delay);
+    assert_eq!(normal.batch_jitter, defaults.batch_jitter);
+    assert_eq!(Profile::Normal.limits(), Limits::default());
+
+    let gentle = args(&["--profile", "gentle"]);
+    let mut config = ImapConfig::default();
+    gentle.apply_to(&mut config);
+    assert_eq!(config.max_concurrent, 2);
+    assert_eq!(config.batch_size, 100);
+    assert_eq!(config.batch_delay, Duration::from_secs(1));
+    assert_eq!(gentle.limits, Profile::Gentle.limits());
+
+    // Limits given on their own replace the profile's, in any order
+    let tuned = args(&["--max-bandwidth", "8M", "--profile=gentle"]);
+    assert_eq!(tuned.limits.bandwidth, Some(8 << 20));
+    assert_eq!(
+        tuned.limits.domain_connections,
+        Profile::Gentle.limits().domain_connections
+    );
+}
+
+#[test]
+fn accounts_can_pick_a_profile() {
+    let dir = std::env::temp_dir().join(format!("profile-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("accounts.ini");
+    let account = |settings: &str| {
+        format!(
+            "[account]\nemail = test@example.org\npassword = secret\ndir = {}\n{}",
+            dir.join("archive").display(),
+            settings
+        )
+    };
+
+    std::fs::write(&path, account("profile = aggressive\n")).unwrap();
+    let accounts = load_accounts(path.to_str().unwrap()).unwrap();
+    assert_eq!(accounts[0].max_concurrent, 10);
+    assert_eq!(accounts[0].batch_delay, Duration::ZERO);
+
+    // Settings after the profile adjust it
+    std::fs::write(&path, account("profile = gentle\nmax_concurrent = 3\n")).unwrap();
+    let accounts = load_accounts(path.to_str().unwrap()).unwrap();
+    assert_eq!(accounts[0].max_concurrent, 3);
+    assert_eq!(accounts[0].batch_size, 100);
+
+    std::fs::write(&path, account("profile = turbo\n")).unwrap();
+    assert!(load_accounts(path.to_str().unwrap()).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn the_read_buffer_can_be_sized() {
+    let mut config = ImapConfig::default();
+    assert_eq!(config.read_buffer, 64 << 10);
+    args(&["--read-buffer", "1M"]).apply_to(&mut config);
+    assert_eq!(config.read_buffer, 1 << 20);
+
+    for size in ["1K", "2G", "lots"] {
+        let given = ["--read-buffer", size].map(String::from);
+        assert!(parse_args(given.into_iter()).is_err(), "{}", size);
+    }
+}
@@ -0,0 +1,47 @@
+use imap_client::progress::{render_bar, render_combined, render_plain, Progress, ProgressMode};
+
+fn accounts() -> Vec<std::sync::Arc<Progress>> {
+    let alice = Progress::new("alice@example.com");
+    alice.add_total(500);
+    alice.add_saved(110);
+    alice.add_skipped(10);
+    alice.add_failed_batch();
+    let bob = Progress::new("bob@example.com");
+    bob.add_total(40);
+    bob.add_saved(40);
+    let carol = Progress::new("carol@example.com");
+    vec![alice, bob, carol]
+}
+
+#[test]
+fn modes_are_parsed() {
+    assert_eq!(
+        "plain".parse::<ProgressMode>().unwrap(),
+        ProgressMode::Plain
+    );
+    assert_eq!("BAR".parse::<ProgressMode>().unwrap(), ProgressMode::Bar);
+    assert_eq!("off".parse::<ProgressMode>().unwrap(), ProgressMode::Off);
+    assert!("fancy".parse::<ProgressMode>().is_err());
+    assert_eq!(ProgressMode::default(), ProgressMode::Bar);
+}
+
+#[test]
+fn plain_progress_is_words_without_control_characters() {
+    let progress = accounts();
+    let plain = render_plain(&progress);
+    assert_eq!(
+        plain,
+        "alice@example.com: 120 of 500 messages, 24%, 1 failed batches; \
+         bob@example.com: 40 of 40 messages, 100%; \
+         carol@example.com: 0 of 0 messages, 0%"
+    );
+    assert!(!plain.chars().any(char::is_control));
+
+    assert_eq!(render_combined(&progress[1..2]), "bob@example.com 40/40");
+    let bar = render_bar(&progress[..2]);
+    assert_eq!(
+        bar,
+        "alice@example.com [####................] 120/500 (1 failed batches) | \
+         bob@example.com [####################] 40/40"
+    );
+}
@@ -0,0 +1,287 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use imap_client::checkpoint::Checkpoint;
+use imap_client::client::{FetchOrder, ImapClient};
+use imap_client::control::Control;
+use imap_client::fixtures::Fixture;
+use imap_client::input::ImapConfig;
+use imap_client::mock::MockServer;
+use imap_client::scheduler::{Limits, Scheduler};
+use imap_client::state::{StateDb, STATE_FILE};
+use imap_client::storage::ExistingFilePolicy;
+use proptest::prelude::*;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+/// Each case runs whole fetches, so fewer cases than proptest's default.
+fn cases(cases: u32) -> ProptestConfig {
+    ProptestConfig {
+        cases,
+        ..ProptestConfig::default()
+    }
+}
+
+fn runtime() -> tokio::runtime::Runtime {
+    tokio::runtime::Builder::new_multi_thread()
+        .enable_all()
+        .build()
+        .unwrap()
+}
+
+fn archive_dir(name: &str) -> PathBuf {
+    static CASES: AtomicUsize = AtomicUsize::new(0);
+    let case = CASES.fetch_add(1, Ordering::Relaxed);
+    let dir = std::env::temp_dir().join(format!(
+        "imap-test-property-{}-{}-{}",
+        name,
+        std::process::id(),
+        case
+    ));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn config(dir: &Path, batch_size: usize, max_concurrent: usize) -> ImapConfig {
+    ImapConfig {
+        batch_size,
+        max_concurrent,
+        batch_delay: Duration::ZERO,
+        ..common::config(dir)
+    }
+}
+
+fn fixture(messages: usize) -> Fixture {
+    Fixture {
+        messages,
+        sizes: 300..=1500,
+        ..Fixture::default()
+    }
+}
+
+/// The messages saved in `dir`, by file name.
+fn saved_messages(dir: &Path) -> HashMap<String, Vec<u8>> {
+    std::fs::read_dir(dir)
+        .unwrap()
+        .filter_map(Result::ok)
+        .map(|entry| entry.path())
+        .filter(|path| path.extension().is_some_and(|ext| ext == "eml"))
+        .map(|path| {
+            let name = path.file_name().unwrap().to_string_lossy().into_owned();
+            (name, std::fs::read(&path).unwrap())
+        })
+        .collect()
+}
+
+/// The checkpointed UIDs of INBOX, which the mock server gives UIDVALIDITY 1.
+fn checkpointed(dir: &Path) -> BTreeSet<u32> {
+    Checkpoint::open(dir)
+        .unwrap()
+        .fetched_uids("INBOX", 1)
+        .into_iter()
+        .collect()
+}
+
+/// Checks that `dir` holds message `i` of `fixture` as UID `i + 1`, once
+/// each, and that all of them are checkpointed.
+fn assert_complete(dir: &Path, fixture: &Fixture) -> Result<(), TestCaseError> {
+    let saved = saved_messages(dir);
+    prop_assert_eq!(saved.len(), fixture.messages);
+    for i in 0..fixture.messages {
+        let name = format!("email_{:05}.eml", i + 1);
+        prop_assert!(
+            saved.get(&name) == Some(&fixture.message(i)),
+            "{} differs",
+            name
+        );
+    }
+    prop_assert_eq!(checkpointed(dir), (1..=fixture.messages as u32).collect());
+    Ok(())
+}
+
+proptest! {
+    #![proptest_config(cases(24))]
+
+    #[test]
+    fn every_uid_is_fetched_once_across_batches(
+        messages in 1usize..60,
+        batch_size in 1usize..20,
+        max_concurrent in 1usize..5,
+        newest_first in any::<bool>(),
+    ) {
+        let fixture = fixture(messages);
+        let dir = archive_dir("batches");
+        let summary = runtime().block_on(async {
+            let server = MockServer::start(vec![fixture.mailbox("INBOX")]).await.unwrap();
+            let config = ImapConfig {
+                order: match newest_first {
+                    true => FetchOrder::NewestFirst,
+                    false => FetchOrder::OldestFirst,
+                },
+                // A message saved twice fails its batch
+                if_exists: ExistingFilePolicy::Error,
+                ..config(&dir, batch_size, max_concurrent)
+            };
+            ImapClient::new(config, server.url()).fetch_all_emails().await.unwrap()
+        });
+        prop_assert_eq!(summary.found as usize, messages);
+        prop_assert_eq!(summary.saved as usize, messages);
+        prop_assert_eq!(summary.failed_batches, 0);
+        assert_complete(&dir, &fixture)?;
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn resuming_after_any_stop_neither_resaves_nor_skips(
+        messages in 1usize..50,
+        batch_size in 1usize..12,
+        stop_after in 0u32..50,
+        lost in proptest::collection::vec(any::<bool>(), 50),
+    ) {
+        let fixture = fixture(messages);
+        let dir = archive_dir("resume");
+        let server = |runtime: &tokio::runtime::Runtime| {
+            runtime.block_on(MockServer::start(vec![fixture.mailbox("INBOX")])).unwrap()
+        };
+        let fetch = |runtime: &tokio::runtime::Runtime, config: ImapConfig, url: String| {
+            runtime.block_on(ImapClient::new(config, url).fetch_all_emails()).unwrap()
+        };
+
+        // A run stopped after some messages, as by --max-messages
+        let runtime = runtime();
+        let first = server(&runtime);
+        let capped = ImapConfig {
+            max_messages: Some(stop_after),
+            ..config(&dir, batch_size, 2)
+        };
+        let stopped = fetch(&runtime, capped, first.url());
+        let expected = (stop_after as usize).min(messages);
+        prop_assert_eq!(stopped.saved as usize, expected);
+        prop_assert_eq!(checkpointed(&dir).len(), expected);
+
+        // Then a crash rolling back some of its records, whose messages are
+        // on disk but not checkpointed
+        let state = rusqlite::Connection::open(dir.join(STATE_FILE)).unwrap();
+        let rolled_back: Vec<u32> = checkpointed(&dir)
+            .into_iter()
+            .filter(|&uid| lost[uid as usize - 1])
+            .collect();
+        for uid in &rolled_back {
+            state.execute("DELETE FROM checkpoint WHERE uid = ?1", [uid]).unwrap();
+        }
+        drop(state);
+
+        // Only what is missing is fetched, and saved over what the crash left
+        let resumed = fetch(&runtime, config(&dir, batch_size, 2), first.url());
+        prop_assert_eq!(
+            resumed.saved as usize,
+            messages - expected + rolled_back.len()
+        );
+        assert_complete(&dir, &fixture)?;
+        drop(first);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn the_scheduler_keeps_to_every_limit(
+        connections in 1usize..5,
+        domain_connections in 1usize..4,
+        accounts in proptest::collection::vec((0usize..3, 1usize..4, 1usize..6), 1..6),
+    ) {
+        let scheduler = Scheduler::new(Limits {
+            connections: Some(connections),
+            domain_connections: Some(domain_connections),
+            ..Limits::default()
+        });
+        let domains = ["example.com", "example.org", "example.net"];
+        let open = Arc::new(Mutex::new((0, HashMap::new(), HashMap::new())));
+        let peak = Arc::new(Mutex::new((0, HashMap::new(), HashMap::new())));
+
+        let served: usize = runtime().block_on(async {
+            let mut tasks = Vec::new();
+            for (n, &(domain, max_concurrent, batches)) in accounts.iter().enumerate() {
+                let email = format!("user{}@{}", n, domains[domain]);
+                let control = Control::scheduled(max_concurrent, scheduler.clone(), &email);
+                for _ in 0..batches {
+                    let (control, open, peak) =
+                        (Arc::clone(&control), Arc::clone(&open), Arc::clone(&peak));
+                    tasks.push(tokio::spawn(async move {
+                        let _connection = control.connection().await.unwrap();
+                        {
+                            let (mut open, mut peak) = (open.lock().unwrap(), peak.lock().unwrap());
+                            open.0 += 1;
+                            *open.1.entry(domain).or_insert(0) += 1;
+                            *open.2.entry(n).or_insert(0) += 1;
+                            peak.0 = peak.0.max(open.0);
+                            let by_domain = peak.1.entry(domain).or_insert(0);
+                            *by_domain = open.1[&domain].max(*by_domain);
+                            let by_account = peak.2.entry(n).or_insert(0);
+                            *by_account = open.2[&n].max(*by_account);
+                        }
+                        tokio::time::sleep(Duration::from_millis(2)).await;
+                        let mut open = open.lock().unwrap();
+                        open.0 -= 1;
+                        *open.1.get_mut(&domain).unwrap() -= 1;
+                        *open.2.get_mut(&n).unwrap() -= 1;
+                    }));
+                }
+            }
+            let mut served = 0;
+            for task in tasks {
+                tokio::time::timeout(Duration::from_secs(10), task).await.unwrap().unwrap();
+                served += 1;
+            }
+            served
+        });
+
+        let batches: usize = accounts.iter().map(|&(_, _, batches)| batches).sum();
+        prop_assert_eq!(served, batches);
+        let peak = peak.lock().unwrap();
+        prop_assert!(peak.0 <= connections);
+        prop_assert!(peak.1.values().all(|&peak| peak <= domain_connections));
+        for (n, &(_, max_concurrent, _)) in accounts.iter().enumerate() {
+            prop_assert!(peak.2[&n] <= max_concurrent);
+        }
+    }
+}
+
+proptest! {
+    #![proptest_config(cases(64))]
+
+    #[test]
+    fn checkpoint_records_survive_reopening(
+        records in proptest::collection::vec(
+            (prop_oneof!["INBOX", "Work", "Åpen"], 1u32..3, 1u32..200, any::<bool>()),
+            0..80,
+        ),
+    ) {
+        let dir = archive_dir("state");
+        let mut expected: HashMap<(String, u32), BTreeSet<u32>> = HashMap::new();
+        {
+            let state = StateDb::open(&dir).unwrap();
+            let mut checkpoint = Checkpoint::load(&state).unwrap();
+            for (mailbox, uid_validity, uid, sync) in &records {
+                checkpoint.record(mailbox, *uid_validity, *uid).unwrap();
+                if *sync {
+                    checkpoint.sync().unwrap();
+                }
+                expected.entry((mailbox.clone(), *uid_validity)).or_default().insert(*uid);
+            }
+        }
+
+        let checkpoint = Checkpoint::open(&dir).unwrap();
+        for mailbox in ["INBOX", "Work", "Åpen"] {
+            for uid_validity in 1..3 {
+                let fetched: BTreeSet<u32> =
+                    checkpoint.fetched_uids(mailbox, uid_validity).into_iter().collect();
+                let key = (mailbox.to_string(), uid_validity);
+                prop_assert_eq!(&fetched, &expected.get(&key).cloned().unwrap_or_default());
+            }
+        }
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
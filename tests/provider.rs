@@ -0,0 +1,86 @@
+use imap_client::input::{load_accounts, ImapConfig};
+use imap_client::provider::{AuthMethod, Provider, PROVIDERS};
+
+#[test]
+fn every_provider_parses_from_its_name() {
+    for provider in PROVIDERS {
+        assert_eq!(provider.name().parse::<Provider>().unwrap(), provider);
+    }
+    assert_eq!("Office365".parse::<Provider>().unwrap(), Provider::Outlook);
+    assert!("aol".parse::<Provider>().is_err());
+}
+
+#[test]
+fn presets_name_server_auth_and_folders() {
+    assert_eq!(
+        Provider::Outlook.server().as_deref(),
+        Some("outlook.office365.com:993")
+    );
+    assert_eq!(
+        Provider::ICloud.server().as_deref(),
+        Some("imap.mail.me.com:993")
+    );
+    assert!(matches!(
+        Provider::Outlook.auth(),
+        AuthMethod::OAuthDevice(_)
+    ));
+    assert_eq!(Provider::Yahoo.auth(), AuthMethod::Login);
+    assert_eq!(Provider::default().folders().sent, "[Gmail]/Sent Mail");
+    assert_eq!(Provider::Outlook.folders().trash, "Deleted Items");
+    assert_eq!(Provider::Yahoo.folders().junk, "Bulk Mail");
+    assert_eq!(Provider::Generic.server(), None);
+}
+
+#[test]
+fn configured_server_overrides_the_preset() {
+    let config = |provider, server: Option<&str>| ImapConfig {
+        email: "test@example.org".to_string(),
+        provider,
+        server: server.map(str::to_string),
+        ..ImapConfig::default()
+    };
+
+    assert_eq!(
+        config(Provider::Fastmail, None).imap_server().unwrap(),
+        "imap.fastmail.com:993"
+    );
+    assert_eq!(
+        config(Provider::Generic, Some("mail.example.org"))
+            .imap_server()
+            .unwrap(),
+        "mail.example.org:993"
+    );
+    assert_eq!(
+        config(Provider::Gmail, Some("127.0.0.1:1143"))
+            .imap_server()
+            .unwrap(),
+        "127.0.0.1:1143"
+    );
+    assert!(config(Provider::Generic, None).imap_server().is_err());
+}
+
+#[test]
+fn accounts_file_selects_providers() {
+    let dir = std::env::temp_dir().join(format!("provider-test-{}", std::process::id()));
+    std::fs::create_dir_all(&dir).unwrap();
+    let path = dir.join("accounts.ini");
+    let account = |provider: &str, server: &str| {
+        format!(
+            "[account]\nemail = test@example.org\npassword = secret\ndir = {}\nprovider = {}\n{}",
+            dir.join("archive").display(),
+            provider,
+            server
+        )
+    };
+
+    std::fs::write(&path, account("generic", "server = mail.example.org\n")).unwrap();
+    let accounts = load_accounts(path.to_str().unwrap()).unwrap();
+    assert_eq!(accounts[0].provider, Provider::Generic);
+    assert_eq!(accounts[0].imap_server().unwrap(), "mail.example.org:993");
+
+    std::fs::write(&path, account("generic", "")).unwrap();
+    assert!(load_accounts(path.to_str().unwrap()).is_err());
+    std::fs::write(&path, account("hotmail", "")).unwrap();
+    assert!(load_accounts(path.to_str().unwrap()).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,125 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, audited, inbox};
+use imap_client::client::{ImapClient, PurgeCount};
+use imap_client::error_imap::ClientError;
+use imap_client::folders::FolderFilter;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+
+#[tokio::test]
+async fn only_archived_messages_are_purged() {
+    let server = inbox(5).await;
+    let dir = archive_dir("archived");
+    ImapClient::new(audited(&dir), server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    // A copy deleted locally no longer counts as archived
+    std::fs::remove_file(dir.join("email_00002.eml")).unwrap();
+
+    let counts = ImapClient::new(audited(&dir), server.url())
+        .purge_messages("BODY Lorem", false)
+        .await
+        .unwrap();
+    assert_eq!(
+        counts,
+        [PurgeCount {
+            mailbox: "INBOX".to_string(),
+            purged: 4,
+            unarchived: 1,
+        }]
+    );
+    let audit = std::fs::read_to_string(dir.join("audit.log")).unwrap();
+    assert!(audit.contains("UID STORE 1,3:5 +FLAGS.SILENT (\\Deleted)"));
+    assert!(audit.contains("UID EXPUNGE 1,3:5"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn dry_runs_change_nothing() {
+    let server = inbox(5).await;
+    let dir = archive_dir("dry-run");
+
+    // Nothing has been fetched, so every match is kept
+    let counts = ImapClient::new(audited(&dir), server.url())
+        .purge_messages("BODY Lorem", true)
+        .await
+        .unwrap();
+    assert_eq!((counts[0].purged, counts[0].unarchived), (0, 5));
+    let audit = std::fs::read_to_string(dir.join("audit.log")).unwrap();
+    assert!(audit.contains("EXAMINE \"INBOX\""));
+    assert!(!audit.contains("STORE"));
+
+    let error = ImapClient::new(audited(&dir), server.url())
+        .purge_messages(" ", true)
+        .await
+        .unwrap_err();
+    assert!(matches!(error, ClientError::InvalidArgument(_)));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn uids_purge_messages_of_one_folder_only() {
+    let server = MockServer::start(
+        ["INBOX", "Work"]
+            .iter()
+            .map(|name| MockMailbox {
+                name: name.to_string(),
+                messages: (0..3).map(|i| synthetic_message(i, 200)).collect(),
+            })
+            .collect(),
+    )
+    .await
+    .unwrap();
+    let dir = archive_dir("one-folder");
+    let all_folders = || ImapConfig {
+        all_folders: true,
+        ..audited(&dir)
+    };
+    ImapClient::new(all_folders(), server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+
+    let everywhere = || ImapConfig {
+        uids: Some("2".parse().unwrap()),
+        ..all_folders()
+    };
+    let error = ImapClient::new(everywhere(), server.url())
+        .purge_messages("ALL", false)
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(&error, ClientError::InvalidArgument(message) if message.contains("exactly one folder")),
+        "{}",
+        error
+    );
+    let audit = std::fs::read_to_string(dir.join("audit.log")).unwrap();
+    assert!(!audit.contains("EXPUNGE"));
+
+    let work = ImapConfig {
+        folder_filter: FolderFilter {
+            include: vec!["Work".to_string()],
+            exclude: Vec::new(),
+        },
+        ..everywhere()
+    };
+    let counts = ImapClient::new(work, server.url())
+        .purge_messages("ALL", false)
+        .await
+        .unwrap();
+    assert_eq!(
+        counts,
+        [PurgeCount {
+            mailbox: "Work".to_string(),
+            purged: 1,
+            unarchived: 0,
+        }]
+    );
+    let audit = std::fs::read_to_string(dir.join("audit.log")).unwrap();
+    assert_eq!(audit.matches("UID EXPUNGE 2").count(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
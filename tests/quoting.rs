@@ -0,0 +1,134 @@
+mod common;
+
+use common::{expect, greeted};
+use imap_client::session::{quote_string, Arg, Session};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+
+#[test]
+fn plain_values_are_quoted() {
+    assert_eq!(
+        quote_string("user@gmail.com").unwrap(),
+        "\"user@gmail.com\""
+    );
+    assert_eq!(quote_string("").unwrap(), "\"\"");
+}
+
+#[test]
+fn spaces_and_specials_stay_inside_quotes() {
+    assert_eq!(quote_string("a b c").unwrap(), "\"a b c\"");
+    assert_eq!(quote_string("p(a)s{s}%*").unwrap(), "\"p(a)s{s}%*\"");
+}
+
+#[test]
+fn quotes_and_backslashes_are_escaped() {
+    assert_eq!(quote_string(r#"pa"ss"#).unwrap(), r#""pa\"ss""#);
+    assert_eq!(quote_string(r"pa\ss").unwrap(), r#""pa\\ss""#);
+    assert_eq!(quote_string(r#"\""#).unwrap(), r#""\\\"""#);
+}
+
+#[test]
+fn unquotable_values_need_a_literal() {
+    assert!(quote_string("contraseña").is_none());
+    assert!(quote_string("line\r\nA002 DELETE INBOX").is_none());
+    assert!(quote_string("nul\0byte").is_none());
+}
+
+#[tokio::test]
+async fn login_sends_non_ascii_password_as_literal() {
+    let (client, mut server) = tokio::io::duplex(256);
+    let script = tokio::spawn(async move {
+        let mut buf = [0u8; 256];
+        let n = server.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], b"A0001 LOGIN \"me@example.com\" {11}\r\n");
+        server.write_all(b"+ Ready\r\n").await.unwrap();
+        let n = server.read(&mut buf).await.unwrap();
+        assert_eq!(&buf[..n], "contraseña\r\n".as_bytes());
+        server
+            .write_all(b"A0001 OK LOGIN completed\r\n")
+            .await
+            .unwrap();
+    });
+
+    let mut session = Session::new(client);
+    let tag = session
+        .send_args(&[
+            Arg::Raw("LOGIN"),
+            Arg::String("me@example.com"),
+            Arg::String("contraseña"),
+        ])
+        .await
+        .unwrap();
+    session.wait_for(&tag).await.unwrap();
+    script.await.unwrap();
+}
+
+#[tokio::test]
+async fn literal_plus_sends_literals_without_waiting() {
+    let (mut session, mut server) = greeted("LITERAL+").await;
+    let tag = session
+        .send_args(&[Arg::Raw("APPEND INBOX"), Arg::Literal(b"hello")])
+        .await
+        .unwrap();
+    expect(&mut server, b"A0001 APPEND INBOX {5+}\r\nhello\r\n").await;
+
+    // An empty literal is still a literal
+    session
+        .send_args(&[Arg::Raw("APPEND INBOX"), Arg::Literal(b"")])
+        .await
+        .unwrap();
+    expect(&mut server, b"A0002 APPEND INBOX {0+}\r\n\r\n").await;
+
+    server
+        .write_all(b"A0001 OK done\r\nA0002 OK done\r\n")
+        .await
+        .unwrap();
+    session.wait_for(&tag).await.unwrap();
+    session.wait_for("A0002").await.unwrap();
+}
+
+#[tokio::test]
+async fn literal_minus_only_covers_small_literals() {
+    let (mut session, mut server) = greeted("LITERAL-").await;
+    session
+        .send_args(&[Arg::Raw("APPEND INBOX"), Arg::Literal(b"small")])
+        .await
+        .unwrap();
+    expect(&mut server, b"A0001 APPEND INBOX {5+}\r\nsmall\r\n").await;
+
+    let large = vec![b'x'; 4097];
+    let script = tokio::spawn(async move {
+        expect(&mut server, b"A0002 APPEND INBOX {4097}\r\n").await;
+        server.write_all(b"+ go ahead\r\n").await.unwrap();
+        expect(&mut server, &[vec![b'x'; 4097], b"\r\n".to_vec()].concat()).await;
+    });
+    session
+        .send_args(&[Arg::Raw("APPEND INBOX"), Arg::Literal(&large)])
+        .await
+        .unwrap();
+    script.await.unwrap();
+}
+
+#[tokio::test]
+async fn refused_literals_are_an_error() {
+    let (mut session, mut server) = greeted("").await;
+    let script = tokio::spawn(async move {
+        expect(&mut server, b"A0001 APPEND INBOX {5}\r\n").await;
+        server
+            .write_all(b"* 3 EXISTS\r\nA0001 NO [TOOBIG] Message too large\r\n")
+            .await
+            .unwrap();
+        server
+    });
+    let error = session
+        .send_args(&[Arg::Raw("APPEND INBOX"), Arg::Literal(b"hello")])
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("rejected literal"), "{}", error);
+    let mut server = script.await.unwrap();
+    // The literal itself was never sent
+    server.write_all(b"* BYE\r\n").await.unwrap();
+    drop(session);
+    let mut rest = Vec::new();
+    server.read_to_end(&mut rest).await.unwrap();
+    assert!(rest.is_empty());
+}
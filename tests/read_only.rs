@@ -0,0 +1,88 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, config};
+use imap_client::audit::AUDIT_FILE;
+use imap_client::client::ImapClient;
+use imap_client::error_imap::ClientError;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use imap_client::session::{modifies_mailbox, Session};
+
+#[test]
+fn modifying_commands_are_recognised() {
+    for command in [
+        "SELECT \"INBOX\"",
+        "STORE 1 +FLAGS (\\Deleted)",
+        "UID STORE 1:5 +X-GM-LABELS (Done)",
+        "EXPUNGE",
+        "UID EXPUNGE 4",
+        "APPEND \"INBOX\" {120}",
+        "UID MOVE 1 \"[Gmail]/Trash\"",
+        "UID FETCH 1:* (UID BODY[])",
+        "FETCH 1 RFC822",
+        "FETCH 1 (BINARY[1])",
+    ] {
+        assert!(modifies_mailbox(command), "{}", command);
+    }
+}
+
+#[test]
+fn reading_commands_are_allowed() {
+    for command in [
+        "EXAMINE \"INBOX\"",
+        "LIST \"\" \"*\"",
+        "UID FETCH 1:* (RFC822.SIZE)",
+        "UID FETCH 1:10 (INTERNALDATE BODY.PEEK[])",
+        "FETCH 1 (BODY.PEEK[HEADER.FIELDS (SUBJECT)])",
+        "UID SEARCH HEADER Message-ID \"<a@b>\"",
+        "LOGOUT",
+    ] {
+        assert!(!modifies_mailbox(command), "{}", command);
+    }
+}
+
+#[tokio::test]
+async fn read_only_session_refuses_before_sending() {
+    let (client, _server) = tokio::io::duplex(256);
+    let mut session = Session::new(client);
+    session.set_read_only(true);
+    assert!(matches!(
+        session.send("UID STORE 1 +FLAGS (\\Seen)").await,
+        Err(ClientError::ReadOnly(_))
+    ));
+}
+
+#[tokio::test]
+async fn read_only_run_logs_every_command() {
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: (0..3).map(|i| synthetic_message(i, 200)).collect(),
+    }])
+    .await
+    .unwrap();
+    let dir = archive_dir("read-only");
+
+    let config = ImapConfig {
+        read_only: true,
+        ..config(&dir)
+    };
+    let summary = ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 3);
+
+    let log = std::fs::read_to_string(dir.join(AUDIT_FILE)).unwrap();
+    let commands: Vec<&str> = log
+        .lines()
+        .filter_map(|line| line.splitn(3, '\t').nth(2))
+        .filter_map(|event| event.strip_prefix("send "))
+        .map(|sent| sent.split_once(' ').unwrap().1)
+        .collect();
+    assert!(commands.iter().any(|c| c.starts_with("EXAMINE")));
+    assert!(commands.iter().all(|c| !modifies_mailbox(c)));
+    assert!(!log.contains("secret"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,111 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::config;
+use imap_client::catalog::{sha256_hex, Catalog};
+use imap_client::client::ImapClient;
+use imap_client::error_imap::ClientError;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{MockMailbox, MockServer};
+use imap_client::redact::{Pattern, Redactor};
+use std::path::PathBuf;
+
+fn redactor(patterns: &[&str]) -> Redactor {
+    Redactor::new(patterns.iter().map(|p| p.parse().unwrap()).collect())
+}
+
+#[test]
+fn only_real_card_numbers_and_ssns_are_redacted() {
+    let (text, count) = redactor(&["cards", "ssn"]).redact_text(
+        b"Card 4111 1111 1111 1111, not 4111 1111 1111 1112.\n\
+          SSN 123-45-6789, not 000-12-3456 or 987-65-4320.\n",
+    );
+    assert_eq!(count, 2);
+    assert_eq!(
+        text,
+        b"Card [REDACTED], not 4111 1111 1111 1112.\n\
+          SSN [REDACTED], not 000-12-3456 or 987-65-4320.\n"
+    );
+
+    let custom = redactor(&[r"/ACCT-\d+/"]);
+    assert_eq!(
+        custom.redact_text(b"ref ACCT-991 ok").0,
+        b"ref [REDACTED] ok"
+    );
+    assert!(matches!(
+        "phone".parse::<Pattern>(),
+        Err(ClientError::InvalidArgument(_))
+    ));
+    assert!("/unclosed(/".parse::<Pattern>().is_err());
+}
+
+#[test]
+fn encoded_text_parts_are_redacted_and_reencoded() {
+    let message = b"Subject: SSN 123-45-6789\r\n\
+        Content-Type: multipart/mixed; boundary=b\r\n\
+        \r\n\
+        --b\r\n\
+        Content-Type: text/plain\r\n\
+        Content-Transfer-Encoding: quoted-printable\r\n\
+        \r\n\
+        My SSN is 123-45-=\r\n6789 =E2=80=94 thanks\r\n\
+        --b\r\n\
+        Content-Type: text/html\r\n\
+        Content-Transfer-Encoding: base64\r\n\
+        \r\n\
+        PHA+MTIzLTQ1LTY3ODk8L3A+\r\n\
+        --b\r\n\
+        Content-Type: text/plain\r\n\
+        Content-Disposition: attachment; filename=\"ssn.txt\"\r\n\
+        \r\n\
+        123-45-6789\r\n\
+        --b--\r\n";
+
+    let redacted = redactor(&["ssn"]).redact_message(message).unwrap();
+    let text = String::from_utf8(redacted).unwrap();
+    // Headers and attachments are left alone
+    assert!(text.starts_with("Subject: SSN 123-45-6789\r\n"));
+    assert!(text.contains("attachment; filename=\"ssn.txt\"\r\n\r\n123-45-6789\r\n"));
+    assert!(text.contains("My SSN is [REDACTED] =E2=80=94 thanks\r\n--b"));
+    // "<p>[REDACTED]</p>"
+    assert!(text.contains("PHA+W1JFREFDVEVEXTwvcD4=\r\n--b"));
+    assert!(redactor(&["cards"]).redact_message(message).is_none());
+}
+
+#[tokio::test]
+async fn catalog_keeps_the_hash_of_the_original() {
+    let original = b"Subject: Order\r\n\
+        Message-ID: <order@example.com>\r\n\
+        \r\n\
+        Paid with 5500 0000 0000 0004.\r\n"
+        .to_vec();
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: vec![original.clone()],
+    }])
+    .await
+    .unwrap();
+    let dir: PathBuf =
+        std::env::temp_dir().join(format!("redact-test-catalog-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    let config = ImapConfig {
+        redact: redactor(&["cards"]),
+        ..config(&dir)
+    };
+
+    let summary = ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 1);
+    let stored = std::fs::read(dir.join("email_00001.eml")).unwrap();
+    assert!(stored.ends_with(b"Paid with [REDACTED].\r\n"));
+
+    let catalog = Catalog::open(&dir).unwrap();
+    let entry = &catalog.entries()[0];
+    assert_eq!(entry.content_hash, sha256_hex(&stored));
+    assert_eq!(entry.original_hash, Some(sha256_hex(&original)));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,97 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, config, inbox};
+use imap_client::catalog::Catalog;
+use imap_client::client::ImapClient;
+use imap_client::input::ImapConfig;
+use imap_client::repair::{check_archive, Damage};
+use imap_client::storage::OutputFormat;
+use std::path::Path;
+
+fn paths(dir: &Path) -> Vec<String> {
+    Catalog::open(dir)
+        .unwrap()
+        .entries()
+        .iter()
+        .map(|entry| entry.path.clone())
+        .collect()
+}
+
+#[tokio::test]
+async fn missing_and_corrupt_files_are_fetched_again() {
+    let server = inbox(4).await;
+    let dir = archive_dir("restore");
+    let client = ImapClient::new(config(&dir), server.url());
+    client.fetch_all_emails().await.unwrap();
+    let paths = paths(&dir);
+    let original = std::fs::read(dir.join(&paths[1])).unwrap();
+    std::fs::remove_file(dir.join(&paths[0])).unwrap();
+    std::fs::write(dir.join(&paths[1]), b"garbage").unwrap();
+
+    let checked = client.repair_archive(false, true).await.unwrap();
+    assert_eq!(checked.checked, 4);
+    assert_eq!(checked.damaged.len(), 2);
+    assert_eq!(checked.damaged[0].damage, Damage::Missing);
+    assert!(checked.restored.is_empty());
+
+    let repaired = client.repair_archive(false, false).await.unwrap();
+    assert_eq!(repaired.restored, [paths[0].clone(), paths[1].clone()]);
+    assert!(repaired.unrecovered.is_empty());
+    assert_eq!(std::fs::read(dir.join(&paths[1])).unwrap(), original);
+    let again = client.repair_archive(false, true).await.unwrap();
+    assert!(again.damaged.is_empty());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn files_deleted_on_purpose_are_pruned() {
+    let server = inbox(4).await;
+    let dir = archive_dir("prune");
+    let client = ImapClient::new(config(&dir), server.url());
+    client.fetch_all_emails().await.unwrap();
+    let deleted = paths(&dir).remove(2);
+    std::fs::remove_file(dir.join(&deleted)).unwrap();
+
+    let report = client.repair_archive(true, false).await.unwrap();
+    assert_eq!(report.pruned.len(), 1);
+    assert_eq!(report.pruned[0], deleted);
+    assert!(report.restored.is_empty());
+    assert!(!paths(&dir).contains(&deleted));
+
+    // The checkpoint still has it, so it isn't fetched again
+    let summary = ImapClient::new(config(&dir), server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 0);
+    assert!(!dir.join(&deleted).exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn renamed_maildir_files_are_followed() {
+    let server = inbox(4).await;
+    let dir = archive_dir("maildir");
+    let config = ImapConfig {
+        format: OutputFormat::Maildir,
+        ..config(&dir)
+    };
+    ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    let old = paths(&dir).remove(0);
+    // A mail program marking it seen
+    let new = format!("{}S", old);
+    std::fs::rename(dir.join(&old), dir.join(&new)).unwrap();
+
+    let mut catalog = Catalog::open(&dir).unwrap();
+    let report = check_archive(&dir, &mut catalog).unwrap();
+    catalog.sync().unwrap();
+    assert_eq!(report.relocated, [(old, new.clone())]);
+    assert!(report.damaged.is_empty());
+    assert_eq!(paths(&dir)[0], new);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,142 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::archive_dir;
+use imap_client::args::{parse_args, Command};
+use imap_client::client::ImapClient;
+use imap_client::error_imap::ClientError;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use imap_client::replay::server_bytes;
+use std::path::PathBuf;
+
+fn fetch_response(seq: u32, uid: u32, body: &[u8]) -> Vec<u8> {
+    let mut response = format!(
+        "* {} FETCH (UID {} INTERNALDATE \"01-Jan-2024 12:00:00 +0000\" BODY[] {{{}}}\r\n",
+        seq,
+        uid,
+        body.len()
+    )
+    .into_bytes();
+    response.extend_from_slice(body);
+    response.extend_from_slice(b")\r\n");
+    response
+}
+
+#[test]
+fn a_trace_gives_back_what_the_server_sent() {
+    let trace = "\
+1736155200.412 1 *: connected to imap.example.org:993
+1736155200.455 1 S: * OK \\x07ready\\tnow
+1736155200.456 1 C: A0001 UID FETCH 1 (BODY[])
+1736155200.464 1 S: * 1 FETCH (BODY[] {9}
+1736155200.464 1 S: <literal of 9 bytes: ab\\r\\ncd...>
+1736155200.464 1 S: )
+1736155200.465 1 S: * 2 FETCH (BODY[] {100}
+1736155200.465 1 S: <literal of 100 bytes: Subject: x\\r\\n...>
+1736155200.466 2 *: connected to imap.example.org:993
+1736155200.466 2 S: * OK second
+1736155200.466 1 S: )
+1736155200.467 1 S: A0001 OK done
+";
+    let mut expected = b"* OK \x07ready\tnow\r\n* 1 FETCH (BODY[] {9}\r\nab\r\ncd...)\r\n".to_vec();
+    expected.extend_from_slice(b"* 2 FETCH (BODY[] {100}\r\nSubject: x\r\n");
+    expected.extend_from_slice(&[b' '; 88]);
+    expected.extend_from_slice(b")\r\nA0001 OK done\r\n* OK second\r\n");
+    assert_eq!(
+        String::from_utf8_lossy(&server_bytes(trace.as_bytes())),
+        String::from_utf8_lossy(&expected)
+    );
+
+    // Anything else is taken as raw responses
+    let raw = b"* OK ready\r\n* 1 EXISTS\r\n";
+    assert_eq!(server_bytes(raw), raw);
+}
+
+#[tokio::test]
+async fn raw_responses_are_parsed_offline() {
+    let mut capture = b"* OK [CAPABILITY IMAP4rev1] ready\r\n".to_vec();
+    capture.extend(fetch_response(1, 10, &synthetic_message(0, 500)));
+    capture.extend(fetch_response(2, 11, &synthetic_message(1, 500)));
+    capture.extend_from_slice(b"* 3 FETCH (FLAGS (\\Seen))\r\nA0003 OK FETCH done\r\n");
+
+    let client = ImapClient::new(ImapConfig::default(), String::new());
+    let summary = client.replay(&capture, false).await.unwrap();
+    assert_eq!(summary.responses, 5);
+    assert_eq!(summary.messages, 2);
+    assert_eq!(summary.saved, 0);
+    assert_eq!(summary.bytes, capture.len());
+
+    // A literal longer than what follows it, as from a miscounting server
+    let good = fetch_response(1, 10, b"Subject: a\r\n\r\nbody\r\n");
+    let mut broken = good.clone();
+    broken.extend_from_slice(b"* 2 FETCH (UID 11 BODY[] {500}\r\nshort)\r\n");
+    match client.replay(&broken, false).await {
+        Err(ClientError::Desync { offset, .. }) => assert_eq!(offset, good.len() as u64),
+        other => panic!("expected a desync, got {:?}", other.map(|s| s.responses)),
+    }
+}
+
+#[tokio::test]
+async fn a_traced_fetch_is_replayed_into_an_archive() {
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: (0..3).map(|i| synthetic_message(i, 1000)).collect(),
+    }])
+    .await
+    .unwrap();
+    let dir = archive_dir("replay");
+    let trace = dir.join("imap.trace");
+
+    let config = ImapConfig {
+        email: "test@example.com".to_string(),
+        password: "secret".to_string(),
+        dir_path: dir.join("fetched"),
+        trace_imap: Some(trace.clone()),
+        quiet: true,
+        ..ImapConfig::default()
+    };
+    ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+
+    let replayed = dir.join("replayed");
+    let path = |path: &PathBuf| path.display().to_string();
+    let args = parse_args([
+        "--replay".to_string(),
+        path(&trace),
+        "--output".to_string(),
+        path(&replayed),
+    ])
+    .unwrap();
+    assert_eq!(
+        args.command,
+        Command::Replay {
+            capture: trace.clone(),
+            output: Some(replayed.clone()),
+        }
+    );
+    let config = ImapConfig {
+        dir_path: replayed.clone(),
+        quiet: true,
+        ..ImapConfig::default()
+    };
+    let capture = std::fs::read(&trace).unwrap();
+    let summary = ImapClient::new(config, String::new())
+        .replay(&capture, true)
+        .await
+        .unwrap();
+    assert_eq!(summary.messages, 3);
+    assert_eq!(summary.saved, 3);
+    let saved = std::fs::read_dir(&replayed)
+        .unwrap()
+        .filter_map(Result::ok)
+        .filter(|entry| entry.path().extension().is_some_and(|ext| ext == "eml"))
+        .count();
+    assert_eq!(saved, 3);
+
+    assert!(parse_args(["dedupe", "x", "--replay", "y"].map(String::from)).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
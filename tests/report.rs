@@ -0,0 +1,209 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, config};
+use imap_client::client::ImapClient;
+use imap_client::error_imap::ClientError;
+use imap_client::failures::{write_failures, Failure};
+use imap_client::import::import_messages;
+use imap_client::message::parse_addresses;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use imap_client::report::{
+    analyze_archive, read_last_run, write_html_report, write_last_run, write_report, AddressCount,
+    RunRecord,
+};
+use std::path::{Path, PathBuf};
+
+fn write_message(dir: &Path, id: u32, from: &str, to: &str, day: &str) {
+    let message = format!(
+        "From: {}\r\nTo: {}\r\nDate: {} 09:00:00 +0000\r\n\
+         Message-ID: <{}@report.example>\r\nSubject: test\r\n\r\nHello\r\n",
+        from, to, day, id
+    );
+    std::fs::create_dir_all(dir).unwrap();
+    std::fs::write(dir.join(format!("{}.eml", id)), message).unwrap();
+}
+
+/// An archive of four messages, one of them stored twice.
+fn archive(name: &str) -> PathBuf {
+    let dir = archive_dir(name);
+    let to = "me@example.com, \"Carol, C.\" <Carol@example.com>";
+    let messages = [
+        (
+            "INBOX",
+            1,
+            "Alice <alice@example.com>",
+            to,
+            "Tue, 2 Jan 2024",
+        ),
+        (
+            "INBOX",
+            2,
+            "alice@EXAMPLE.com",
+            "me@example.com",
+            "Wed, 3 Jan 2024",
+        ),
+        (
+            "INBOX",
+            3,
+            "Bob <bob@example.com>",
+            "me@example.com",
+            "Fri, 1 Mar 2024",
+        ),
+        (
+            "INBOX",
+            4,
+            "Bob <bob@example.com>",
+            "undisclosed-recipients:;",
+            "Sat, 2 Mar 2024",
+        ),
+        (
+            "Label",
+            1,
+            "Alice <alice@example.com>",
+            to,
+            "Tue, 2 Jan 2024",
+        ),
+    ];
+    for (folder, id, from, to, date) in messages {
+        write_message(&dir.join(folder), id, from, to, date);
+    }
+    import_messages(&dir, &dir).unwrap();
+    dir
+}
+
+#[test]
+fn address_lists_are_split() {
+    let addresses =
+        parse_addresses("a@example.com, \"Doe, Jane\" <jane@example.com> (work), group:;");
+    let emails: Vec<&str> = addresses.iter().map(|a| a.email.as_str()).collect();
+    assert_eq!(emails, ["a@example.com", "jane@example.com"]);
+    assert_eq!(addresses[1].name.as_deref(), Some("Doe, Jane"));
+}
+
+#[test]
+fn messages_are_counted_by_address_and_month() {
+    let dir = archive("counts");
+    std::fs::remove_file(dir.join("INBOX/4.eml")).unwrap();
+
+    let analytics = analyze_archive(&dir, 10).unwrap();
+    assert_eq!(analytics.messages, 3);
+    assert_eq!(analytics.skipped, 1);
+    let senders: Vec<(&str, usize)> = analytics
+        .senders
+        .iter()
+        .map(|count| (count.address.as_str(), count.messages))
+        .collect();
+    assert_eq!(senders, [("alice@example.com", 2), ("bob@example.com", 1)]);
+    let recipients: Vec<(&str, usize)> = analytics
+        .recipients
+        .iter()
+        .map(|count| (count.address.as_str(), count.messages))
+        .collect();
+    assert_eq!(
+        recipients,
+        [("me@example.com", 3), ("carol@example.com", 1)]
+    );
+    let months: Vec<(&str, usize)> = analytics
+        .months
+        .iter()
+        .map(|month| (month.month.as_str(), month.messages))
+        .collect();
+    assert_eq!(months, [("2024-01", 2), ("2024-02", 0), ("2024-03", 1)]);
+
+    let top = analyze_archive(&dir, 1).unwrap();
+    assert_eq!(top.senders.len(), 1);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn tables_are_written_as_csv_and_json() {
+    let dir = archive("files");
+    let mut analytics = analyze_archive(&dir, 10).unwrap();
+    analytics.senders.push(AddressCount {
+        address: "\"odd, <name>\"@example.com".to_string(),
+        messages: 1,
+        bytes: 10,
+    });
+    let out = dir.join("report");
+    let written = write_report(&analytics, &out).unwrap();
+    assert_eq!(written.len(), 4);
+
+    let senders = std::fs::read_to_string(out.join("senders.csv")).unwrap();
+    let lines: Vec<&str> = senders.lines().collect();
+    assert_eq!(lines[0], "address,messages,bytes");
+    assert!(lines[1].starts_with("alice@example.com,2,"));
+    assert!(lines[3].starts_with("\"\"\"odd, <name>\"\"@example.com\",1,10"));
+    let months = std::fs::read_to_string(out.join("months.csv")).unwrap();
+    assert!(months.contains("\n2024-02,0,0\n"));
+    let json: serde_json::Value =
+        serde_json::from_str(&std::fs::read_to_string(out.join("analytics.json")).unwrap())
+            .unwrap();
+    assert_eq!(json["messages"], 4);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn the_page_shows_the_last_run_and_its_failures() {
+    let dir = archive("html");
+    let mut analytics = analyze_archive(&dir, 10).unwrap();
+    analytics.senders.push(AddressCount {
+        address: "\"odd, <name>\"@example.com".to_string(),
+        messages: 1,
+        bytes: 10,
+    });
+    let page = dir.join("report.html");
+    write_html_report(&dir, &analytics, &page).unwrap();
+    let html = std::fs::read_to_string(&page).unwrap();
+    assert!(html.contains("No fetch has been recorded"));
+    assert!(html.contains("&quot;odd, &lt;name&gt;&quot;@example.com"));
+    assert!(!html.contains("<name>"));
+
+    write_last_run(
+        &dir,
+        &RunRecord {
+            account: "me@example.com".to_string(),
+            started: 1_704_186_000,
+            finished: 1_704_186_125,
+            found: 12,
+            saved: 10,
+            failed_batches: 1,
+            error: None,
+        },
+    )
+    .unwrap();
+    let error = ClientError::ImapError("<busy>".to_string());
+    let failure = Failure::new("INBOX", &dir.join("INBOX"), 1, vec![11, 12], &error);
+    write_failures(&dir, &[failure]).unwrap();
+    write_html_report(&dir, &analytics, &page).unwrap();
+    let html = std::fs::read_to_string(&page).unwrap();
+    assert!(html.contains("2024-01-02 09:00 UTC"));
+    assert!(html.contains("Completed with 1 failed batches"));
+    assert!(html.contains("&lt;busy&gt;"));
+    assert!(!html.contains("<busy>"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn every_fetch_records_the_run() {
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: (0..3).map(|i| synthetic_message(i, 200)).collect(),
+    }])
+    .await
+    .unwrap();
+    let dir = archive_dir("run");
+    let config = config(&dir);
+    ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+
+    let run = read_last_run(&dir).unwrap().unwrap();
+    assert_eq!(run.account, "test@example.com");
+    assert_eq!((run.found, run.saved, run.failed_batches), (3, 3, 0));
+    assert!(run.error.is_none());
+    assert!(run.finished >= run.started);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
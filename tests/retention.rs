@@ -0,0 +1,129 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::archive_dir;
+use imap_client::catalog::Catalog;
+use imap_client::client::ImapClient;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use imap_client::retention::{RetentionPolicy, Scope};
+use std::path::Path;
+
+fn mailbox(name: &str, count: usize) -> MockMailbox {
+    MockMailbox {
+        name: name.to_string(),
+        messages: (0..count).map(|i| synthetic_message(i, 200)).collect(),
+    }
+}
+
+fn config(dir: &Path, retention: &[&str]) -> ImapConfig {
+    ImapConfig {
+        retention: retention
+            .iter()
+            .map(|policy| policy.parse().unwrap())
+            .collect(),
+        ..common::audited(dir)
+    }
+}
+
+fn catalogued(dir: &Path) -> Vec<String> {
+    Catalog::open(dir)
+        .unwrap()
+        .entries()
+        .iter()
+        .map(|entry| entry.path.clone())
+        .collect()
+}
+
+#[test]
+fn policies_are_parsed() {
+    let policy: RetentionPolicy = "server:2y".parse().unwrap();
+    assert_eq!(policy.scope, Scope::Server);
+    assert_eq!(policy.max_age, 2 * 365 * 24 * 60 * 60);
+    assert_eq!(policy.label, "*");
+    let policy: RetentionPolicy = "Local:30d:[Gmail]/Promotions".parse().unwrap();
+    assert_eq!(policy.scope, Scope::Local);
+    assert_eq!(policy.label, "[gmail]/promotions");
+    assert!(policy.applies_to("[Gmail]/Promotions"));
+
+    for invalid in ["remote:1y", "local", "server:soon"] {
+        assert!(invalid.parse::<RetentionPolicy>().is_err(), "{}", invalid);
+    }
+}
+
+#[tokio::test]
+async fn old_archived_files_are_deleted_per_folder() {
+    // The mock's messages are from 2024
+    let server = MockServer::start(vec![mailbox("INBOX", 3), mailbox("Archive", 2)])
+        .await
+        .unwrap();
+    let dir = archive_dir("local");
+    let config = || ImapConfig {
+        all_folders: true,
+        ..config(&dir, &["local:100y:INBOX", "local:1y:arch*"])
+    };
+    let summary = ImapClient::new(config(), server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 5);
+
+    let kept = catalogued(&dir);
+    assert_eq!(kept.len(), 3);
+    assert!(kept.iter().all(|path| path.starts_with("INBOX/")));
+    let archive = std::fs::read_dir(dir.join("Archive")).unwrap();
+    assert_eq!(archive.count(), 0);
+
+    // The checkpoint keeps them from coming back
+    let summary = ImapClient::new(config(), server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 0);
+    assert_eq!(catalogued(&dir).len(), 3);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn only_archived_messages_are_deleted_from_the_server() {
+    let server = MockServer::start(vec![mailbox("INBOX", 4)]).await.unwrap();
+    let dir = archive_dir("server");
+    let capped = ImapConfig {
+        max_messages: Some(3),
+        ..config(&dir, &["server:1y"])
+    };
+    ImapClient::new(capped, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    let audit = std::fs::read_to_string(dir.join("audit.log")).unwrap();
+    let deletes: Vec<&str> = audit
+        .lines()
+        .filter(|line| line.contains("\\Deleted"))
+        .collect();
+    assert_eq!(deletes.len(), 1);
+    assert!(deletes[0].contains("UID STORE 1:3 +FLAGS.SILENT (\\Deleted)"));
+
+    // The second file no longer checks out
+    let paths = catalogued(&dir);
+    std::fs::write(dir.join(&paths[1]), b"damaged").unwrap();
+    let again = ImapConfig {
+        audit_log: Some(dir.join("audit2.log")),
+        ..config(&dir, &["server:1y"])
+    };
+    ImapClient::new(again, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    let audit = std::fs::read_to_string(dir.join("audit2.log")).unwrap();
+    assert!(audit.contains("UID STORE 1,3:4 +FLAGS.SILENT (\\Deleted)"));
+
+    let both = config(&dir, &["server:1y", "local:5y"]);
+    let error = ImapClient::new(both, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap_err();
+    assert!(error.to_string().contains("both"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,242 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, audited, config};
+use imap_client::client::ImapClient;
+use imap_client::error_imap::ClientError;
+use imap_client::input::ImapConfig;
+use imap_client::message::Headers;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use imap_client::rules::{Action, MessageFacts, RuleSet};
+
+const NOW: i64 = 1_700_000_000;
+const DAY: i64 = 24 * 60 * 60;
+
+fn facts<'a>(sender: &'a str, subject: &'a str, size: u64, age_days: i64) -> MessageFacts<'a> {
+    MessageFacts {
+        sender: Some(sender),
+        subject,
+        label: "INBOX",
+        size,
+        date: Some(NOW - age_days * DAY),
+        now: NOW,
+    }
+}
+
+fn action(rules: &RuleSet, facts: &MessageFacts) -> Action {
+    rules
+        .matching(facts)
+        .map(|rule| rule.action.clone())
+        .unwrap_or_default()
+}
+
+#[test]
+fn first_matching_rule_decides() {
+    let rules = RuleSet::parse(
+        r#"
+        # Newsletters go to their own label
+        from:*@news.example.com                 -> move:Newsletters
+        subject:/invoice|receipt/ label:inbox   -> save
+        subject:"weekly digest"                 -> skip
+        size:>25M                               -> skip
+        from:reports@example.com age:>1y        -> delete
+        from:scanner@example.com                -> save-attachments-only
+        "#,
+        "rules.txt",
+    )
+    .unwrap();
+
+    let news = facts("Editor@News.Example.com", "Big news", 1000, 1);
+    assert_eq!(
+        action(&rules, &news),
+        Action::Move("Newsletters".to_string())
+    );
+    let invoice = facts("shop@example.com", "Your INVOICE #12", 30 << 20, 1);
+    assert_eq!(action(&rules, &invoice), Action::Save);
+    let digest = facts("x@example.com", "The Weekly Digest", 1000, 1);
+    assert_eq!(action(&rules, &digest), Action::Skip);
+    let video = facts("x@example.com", "Holiday video", 30 << 20, 1);
+    assert_eq!(action(&rules, &video), Action::Skip);
+    let old = facts("reports@example.com", "Report", 1000, 400);
+    assert_eq!(action(&rules, &old), Action::Delete);
+    let recent = facts("reports@example.com", "Report", 1000, 10);
+    assert_eq!(action(&rules, &recent), Action::Save);
+    let scan = facts("scanner@example.com", "Scan", 1000, 1);
+    assert_eq!(action(&rules, &scan), Action::AttachmentsOnly);
+    assert!(rules.modifies_account());
+}
+
+#[test]
+fn errors_name_the_line() {
+    let error = RuleSet::parse("* -> save\nsize:25M -> skip\n", "rules.txt").unwrap_err();
+    assert!(
+        matches!(&error, ClientError::InvalidArgument(m) if m.starts_with("rules.txt:2:")),
+        "{:?}",
+        error
+    );
+    assert!(RuleSet::parse("colour:red -> skip", "r").is_err());
+    assert!(RuleSet::parse("from:a@b.c -> archive", "r").is_err());
+    assert!(RuleSet::parse("from:a@b.c -> move", "r").is_err());
+    assert!(RuleSet::parse("subject:/unclosed -> skip", "r").is_err());
+    let catch_all = RuleSet::parse("* -> skip", "r").unwrap();
+    let headers = Headers::parse(b"Subject: hi\r\n\r\n");
+    let facts = MessageFacts::new(&headers, None, "hi", "INBOX", 10, None);
+    assert_eq!(action(&catch_all, &facts), Action::Skip);
+}
+
+fn with_attachment() -> Vec<u8> {
+    b"From: Scanner <scanner@example.com>\r\n\
+      Subject: Scan\r\n\
+      Message-ID: <scan@example.com>\r\n\
+      Content-Type: multipart/mixed; boundary=b\r\n\
+      \r\n\
+      --b\r\n\
+      Content-Type: text/plain\r\n\
+      \r\n\
+      See attached.\r\n\
+      --b\r\n\
+      Content-Type: application/pdf\r\n\
+      Content-Disposition: attachment; filename=\"scan.pdf\"\r\n\
+      Content-Transfer-Encoding: base64\r\n\
+      \r\n\
+      JVBERi0xLjQ=\r\n\
+      --b--\r\n"
+        .to_vec()
+}
+
+#[tokio::test]
+async fn rules_act_on_messages_during_fetch() {
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: vec![
+            synthetic_message(0, 200),
+            synthetic_message(1, 200),
+            synthetic_message(2, 200),
+            with_attachment(),
+        ],
+    }])
+    .await
+    .unwrap();
+    let dir = archive_dir("fetch");
+    let rules = RuleSet::parse(
+        "from:sender0@example.com -> skip\n\
+         subject:/^message 1$/ -> move:Archive/Old\n\
+         from:sender2@* -> delete\n\
+         from:scanner@example.com -> attachments-only\n",
+        "rules.txt",
+    )
+    .unwrap();
+    let config = ImapConfig {
+        rules,
+        ..audited(&dir)
+    };
+
+    let summary = ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 3);
+    assert!(!dir.join("email_00001.eml").exists());
+    assert!(dir.join("email_00002.eml").exists());
+    assert!(dir.join("email_00003.eml").exists());
+    assert!(!dir.join("email_00004.eml").exists());
+    let pdf = dir.join("email_00004.attachments").join("scan.pdf");
+    assert_eq!(std::fs::read(pdf).unwrap(), b"%PDF-1.4");
+
+    // The missing folder is created when the server asks for it
+    let audit = std::fs::read_to_string(dir.join("audit.log")).unwrap();
+    assert!(audit.contains("SELECT \"INBOX\""));
+    assert!(audit.contains("UID MOVE 2 \"Archive/Old\""));
+    assert!(audit.contains("CREATE \"Archive/Old\""));
+    assert!(audit.contains("UID STORE 3 +FLAGS.SILENT (\\Deleted)"));
+    assert!(audit.contains("UID EXPUNGE 3"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn modifying_rules_are_refused_in_read_only_mode() {
+    let server = MockServer::start(Vec::new()).await.unwrap();
+    let dir = archive_dir("read-only");
+    let config = ImapConfig {
+        rules: RuleSet::parse("* -> delete", "rules.txt").unwrap(),
+        read_only: true,
+        ..config(&dir)
+    };
+
+    let error = ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap_err();
+    assert!(
+        matches!(error, ClientError::InvalidArgument(_)),
+        "{:?}",
+        error
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn flags_must_be_settable() {
+    let rules = RuleSet::parse(
+        "from:*@shop.example.com -> flag:\\flagged\n\
+         subject:invoice -> flag:$Invoice\n\
+         * -> add-label:\"Cold archive\"\n",
+        "rules.txt",
+    )
+    .unwrap();
+    let actions: Vec<Action> = rules.rules().iter().map(|r| r.action.clone()).collect();
+    assert_eq!(
+        actions,
+        [
+            Action::Flag("\\Flagged".to_string()),
+            Action::Flag("$Invoice".to_string()),
+            Action::AddLabel("Cold archive".to_string())
+        ]
+    );
+    assert!(rules.modifies_account());
+    for invalid in [
+        "flag:\\Deleted",
+        "flag:\\Recent",
+        "flag:two words",
+        "flag:(x)",
+    ] {
+        assert!(
+            RuleSet::parse(&format!("* -> {}", invalid), "rules.txt").is_err(),
+            "{}",
+            invalid
+        );
+    }
+}
+
+#[tokio::test]
+async fn rules_flag_and_label_archived_messages() {
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: (0..3).map(|i| synthetic_message(i, 200)).collect(),
+    }])
+    .await
+    .unwrap();
+    let dir = archive_dir("flag");
+    let rules = RuleSet::parse(
+        "from:sender0@example.com -> flag:\\Flagged\n\
+         from:sender1@example.com -> flag:\\Flagged\n\
+         from:sender2@example.com -> add-label:\"Cold archive\"\n",
+        "rules.txt",
+    )
+    .unwrap();
+    let config = ImapConfig {
+        rules,
+        ..audited(&dir)
+    };
+
+    let summary = ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 3);
+    let audit = std::fs::read_to_string(dir.join("audit.log")).unwrap();
+    assert!(audit.contains("UID STORE 1:2 +FLAGS.SILENT (\\Flagged)"));
+    assert!(audit.contains("UID STORE 3 +X-GM-LABELS.SILENT \"Cold archive\""));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,49 @@
+use imap_client::storage::sanitize_filename;
+
+#[test]
+fn ordinary_names_are_unchanged() {
+    assert_eq!(sanitize_filename("INBOX"), "INBOX");
+    assert_eq!(sanitize_filename("[Gmail] Sent Mail"), "[Gmail] Sent Mail");
+    assert_eq!(sanitize_filename("Résumés"), "Résumés");
+}
+
+#[test]
+fn invalid_characters_are_replaced() {
+    assert_eq!(sanitize_filename("Re: a/b\\c"), "Re_ a_b_c");
+    assert_eq!(sanitize_filename("<what?>|*\"x\""), "_what_____x_");
+    assert_eq!(sanitize_filename("tab\there"), "tab_here");
+}
+
+#[test]
+fn reserved_names_are_prefixed() {
+    assert_eq!(sanitize_filename("CON"), "_CON");
+    assert_eq!(sanitize_filename("nul.txt"), "_nul.txt");
+    assert_eq!(sanitize_filename("com1"), "_com1");
+    assert_eq!(sanitize_filename("CONSOLE"), "CONSOLE");
+}
+
+#[test]
+fn dots_cannot_escape_the_directory() {
+    assert_eq!(sanitize_filename(".."), "_");
+    assert_eq!(sanitize_filename("."), "_");
+    assert_eq!(sanitize_filename("name. "), "name");
+    assert_eq!(sanitize_filename(""), "_");
+}
+
+#[test]
+fn long_names_are_truncated_on_a_char_boundary() {
+    let name = "é".repeat(150);
+    let sanitized = sanitize_filename(&name);
+    assert!(sanitized.len() <= 200);
+    assert!(name.starts_with(&sanitized));
+}
+
+#[test]
+fn bidirectional_controls_are_replaced() {
+    assert_eq!(
+        sanitize_filename("invoice\u{202E}fdp.exe"),
+        "invoice_fdp.exe"
+    );
+    assert_eq!(sanitize_filename("שלום\u{200F}"), "שלום_");
+    assert_eq!(sanitize_filename("日本語の件名"), "日本語の件名");
+}
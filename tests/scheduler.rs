@@ -0,0 +1,105 @@
+#![cfg(feature = "mock")]
+
+use imap_client::args::parse_args;
+use imap_client::client::{fetch_accounts, ImapClient};
+use imap_client::control::Control;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use imap_client::progress::ProgressMode;
+use imap_client::scheduler::{Limits, Scheduler};
+use std::time::{Duration, Instant};
+use tokio::time::timeout;
+
+#[tokio::test]
+async fn accounts_of_a_domain_share_its_connections() {
+    let scheduler = Scheduler::new(Limits {
+        connections: Some(2),
+        domain_connections: Some(1),
+        ..Limits::default()
+    });
+    let alice = Control::scheduled(5, scheduler.clone(), "alice@example.com");
+    let bob = Control::scheduled(5, scheduler.clone(), "bob@Example.com");
+    let carol = Control::scheduled(5, scheduler.clone(), "carol@other.org");
+    let dave = Control::scheduled(5, scheduler.clone(), "dave@third.net");
+    let short = Duration::from_millis(100);
+
+    let first = alice.connection().await.unwrap();
+    // Bob is in Alice's domain, whatever the case
+    assert!(timeout(short, bob.connection()).await.is_err());
+    let second = carol.connection().await.unwrap();
+    // Another domain, but the run's two connections are taken
+    assert!(timeout(short, dave.connection()).await.is_err());
+
+    drop(first);
+    let third = timeout(short, bob.connection()).await.unwrap().unwrap();
+    drop((second, third));
+    timeout(short, dave.connection()).await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn bandwidth_is_spread_over_time() {
+    let scheduler = Scheduler::new(Limits {
+        bandwidth: Some(100_000),
+        ..Limits::default()
+    });
+    let started = Instant::now();
+    // A second's worth goes through at once
+    scheduler.transfer(100_000).await;
+    assert!(started.elapsed() < Duration::from_millis(200));
+    // The rest waits for the rate to catch up
+    scheduler.transfer(50_000).await;
+    assert!(started.elapsed() >= Duration::from_millis(450));
+
+    let args = ["--max-bandwidth", "10M/s", "--domain-connections=4"].map(String::from);
+    let limits = parse_args(args).unwrap().limits;
+    assert_eq!(limits.bandwidth, Some(10 << 20));
+    assert_eq!(limits.domain_connections, Some(4));
+    assert!(parse_args(["--max-connections", "0"].map(String::from)).is_err());
+    assert!(parse_args(["--max-bandwidth", "fast"].map(String::from)).is_err());
+}
+
+#[tokio::test]
+async fn a_domain_is_fetched_within_its_limit() {
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: (0..20).map(|i| synthetic_message(i, 300)).collect(),
+    }])
+    .await
+    .unwrap();
+    let scheduler = Scheduler::new(Limits {
+        domain_connections: Some(2),
+        ..Limits::default()
+    });
+    let dirs: Vec<_> = (0..3)
+        .map(|i| {
+            let dir =
+                std::env::temp_dir().join(format!("scheduler-test-{}-{}", i, std::process::id()));
+            let _ = std::fs::remove_dir_all(&dir);
+            dir
+        })
+        .collect();
+    let clients = dirs
+        .iter()
+        .enumerate()
+        .map(|(i, dir)| {
+            let config = ImapConfig {
+                email: format!("user{}@example.com", i),
+                password: "test".to_string(),
+                dir_path: dir.clone(),
+                max_concurrent: 5,
+                batch_size: 4,
+                quiet: true,
+                ..ImapConfig::default()
+            };
+            ImapClient::new(config, server.url()).with_scheduler(scheduler.clone())
+        })
+        .collect();
+
+    for (_, result) in fetch_accounts(clients, ProgressMode::Off).await {
+        assert_eq!(result.unwrap().saved, 20);
+    }
+    assert!(server.peak_connections() <= 2);
+    for dir in dirs {
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+}
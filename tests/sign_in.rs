@@ -0,0 +1,140 @@
+use imap_client::client::auth_failure;
+use imap_client::error_imap::ClientError;
+use imap_client::provider::{Provider, PROVIDERS};
+
+/// The message and guidance of an error the user has to act on.
+fn action(error: ClientError) -> (String, String) {
+    match error {
+        ClientError::ActionRequired { message, guidance } => (message, guidance),
+        other => panic!("expected an action for the user, got {:?}", other),
+    }
+}
+
+#[test]
+fn app_passwords_are_asked_for_where_the_provider_has_them() {
+    let (message, guidance) = action(auth_failure(
+        "[ALERT] Application-specific password required: \
+         https://support.google.com/accounts/answer/185833 (Failure)",
+        Provider::Gmail,
+    ));
+    assert!(message.starts_with("Application-specific password required"));
+    assert!(
+        guidance.contains("requires an app password"),
+        "{}",
+        guidance
+    );
+    assert!(guidance.contains("https://myaccount.google.com/apppasswords"));
+
+    // Without a page of its own, the account's settings are named
+    let (_, guidance) = action(auth_failure(
+        "[ALERT] Application-specific password required",
+        Provider::Generic,
+    ));
+    assert!(guidance.contains("in your account's security settings"));
+}
+
+#[test]
+fn bare_rejections_mean_app_passwords_only_for_providers_that_require_them() {
+    let text = "[AUTHENTICATIONFAILED] Invalid credentials (Failure)";
+    for provider in PROVIDERS {
+        let error = auth_failure(text, provider);
+        match provider {
+            Provider::Yahoo | Provider::Fastmail | Provider::ICloud => {
+                let (message, guidance) = action(error);
+                assert_eq!(message, "Invalid credentials (Failure)");
+                assert!(
+                    guidance.starts_with(&format!(
+                        "{} only accepts app passwords",
+                        provider.display_name()
+                    )),
+                    "{}",
+                    guidance
+                );
+                assert!(guidance.contains(provider.app_passwords_url().unwrap()));
+            }
+            // Gmail says when it wants an app password; the others have none
+            Provider::Gmail | Provider::Outlook | Provider::Generic => assert!(
+                matches!(&error, ClientError::AuthenticationError(message)
+                    if message == "Invalid credentials (Failure)"),
+                "{:?}: {:?}",
+                provider,
+                error
+            ),
+        }
+    }
+}
+
+#[test]
+fn turned_off_imap_is_reported_as_such() {
+    let (message, guidance) = action(auth_failure(
+        "[ALERT] Your account is not enabled for IMAP use. Please visit your \
+         Gmail settings page and enable your account for IMAP access. (Failure)",
+        Provider::Gmail,
+    ));
+    assert!(message.starts_with("Your account is not enabled for IMAP use"));
+    assert_eq!(
+        guidance,
+        "IMAP access is turned off for this account. Turn it on in Gmail's settings, then retry."
+    );
+}
+
+#[test]
+fn web_sign_ins_point_at_the_browser() {
+    let (message, guidance) = action(auth_failure(
+        "[WEBALERT https://accounts.google.com/ContinueSignIn?sarp=1&scc=1] \
+         Web login required.",
+        Provider::Gmail,
+    ));
+    assert_eq!(message, "Web login required.");
+    assert!(guidance.starts_with("Gmail blocked this sign-in."));
+    assert!(guidance.ends_with("Details: https://accounts.google.com/ContinueSignIn?sarp=1&scc=1"));
+
+    let (_, guidance) = action(auth_failure(
+        "[ALERT] Please log in via your web browser: \
+         https://support.google.com/mail/accounts/answer/78754 (Failure)",
+        Provider::Gmail,
+    ));
+    assert!(guidance.ends_with("Details: https://support.google.com/mail/accounts/answer/78754"));
+
+    // Without a link there is nothing to add
+    let (_, guidance) = action(auth_failure(
+        "[ALERT] Please log in via your web browser",
+        Provider::Yahoo,
+    ));
+    assert!(guidance.ends_with("from a web browser, confirm the activity if asked, then retry."));
+}
+
+#[test]
+fn other_alerts_and_plain_rejections() {
+    let (message, guidance) = action(auth_failure(
+        "[ALERT] Unusual activity, see https://help.example.com/security.",
+        Provider::Generic,
+    ));
+    assert_eq!(
+        message,
+        "Unusual activity, see https://help.example.com/security."
+    );
+    assert_eq!(
+        guidance,
+        "Follow the instructions at https://help.example.com/security"
+    );
+
+    let (_, guidance) = action(auth_failure("[ALERT] Unusual activity", Provider::ICloud));
+    assert_eq!(
+        guidance,
+        "Check for security notifications from iCloud Mail, then retry."
+    );
+
+    for text in [
+        "LOGIN failed.",
+        "  LOGIN failed.  ",
+        "[UNAVAILABLE] LOGIN failed.",
+    ] {
+        assert!(
+            matches!(auth_failure(text, Provider::Gmail),
+                ClientError::AuthenticationError(message) if message == "LOGIN failed."),
+            "{:?}",
+            text
+        );
+    }
+}
@@ -0,0 +1,165 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, config};
+use imap_client::client::ImapClient;
+use imap_client::folders::{parse_list_response, FolderFilter, SpecialUse};
+use imap_client::input::ImapConfig;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use imap_client::provider::Provider;
+
+fn mailbox(name: &str, messages: std::ops::Range<usize>) -> MockMailbox {
+    MockMailbox {
+        name: name.to_string(),
+        messages: messages.map(|i| synthetic_message(i, 200)).collect(),
+    }
+}
+
+#[tokio::test]
+async fn include_sent_adds_the_folder_marked_sent() {
+    let server = MockServer::start(vec![
+        mailbox("INBOX", 0..3),
+        mailbox("[Gmail]/Sent Mail", 3..5),
+        mailbox("[Gmail]/Drafts", 5..6),
+        mailbox("Work", 6..7),
+    ])
+    .await
+    .unwrap();
+    let dir = archive_dir("sent");
+    // Fastmail calls the folder "Sent", so only the \Sent attribute finds it
+    let config = ImapConfig {
+        provider: Provider::Fastmail,
+        include_sent: true,
+        ..config(&dir)
+    };
+
+    let summary = ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!((summary.found, summary.saved), (5, 5));
+    // INBOX stays where INBOX-only runs put it; Sent gets its own directory
+    assert!(dir.join("email_00003.eml").exists());
+    assert!(dir
+        .join("[Gmail]")
+        .join("Sent Mail")
+        .join("email_00002.eml")
+        .exists());
+    assert!(!dir.join("[Gmail]").join("Drafts").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn unmarked_folders_are_found_by_the_provider_name() {
+    let server = MockServer::start(vec![
+        mailbox("INBOX", 0..1),
+        mailbox("Sent", 1..3),
+        mailbox("Draft", 3..4),
+    ])
+    .await
+    .unwrap();
+    let dir = archive_dir("fallback");
+    let config = ImapConfig {
+        provider: Provider::Yahoo,
+        include_sent: true,
+        include_drafts: true,
+        ..config(&dir)
+    };
+
+    let summary = ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!((summary.found, summary.saved), (4, 4));
+    assert!(dir.join("Draft").join("email_00001.eml").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn requested_special_folders_survive_the_folder_filter() {
+    let server = MockServer::start(vec![
+        mailbox("INBOX", 0..2),
+        mailbox("[Gmail]/Sent Mail", 2..3),
+        mailbox("[Gmail]/Trash", 3..5),
+    ])
+    .await
+    .unwrap();
+    let dir = archive_dir("filter");
+    let mut config = ImapConfig {
+        all_folders: true,
+        include_sent: true,
+        ..config(&dir)
+    };
+    config.folder_filter.exclude.push("[Gmail]/*".to_string());
+
+    let summary = ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!((summary.found, summary.saved), (3, 3));
+    assert!(dir.join("[Gmail]").join("Sent Mail").exists());
+    assert!(!dir.join("[Gmail]").join("Trash").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn special_use_attributes_are_parsed_from_list_and_xlist() {
+    let sent =
+        parse_list_response(r#"LIST (\HasNoChildren \Sent) "/" "[Gmail]/Gesendet""#).unwrap();
+    assert_eq!(sent.special_use(), Some(SpecialUse::Sent));
+    let spam = parse_list_response(r#"XLIST (\HasNoChildren \Spam) "/" "[Gmail]/Spam""#).unwrap();
+    assert_eq!(spam.special_use(), Some(SpecialUse::Junk));
+    let work = parse_list_response(r#"LIST (\HasNoChildren) "/" "Work""#).unwrap();
+    assert_eq!(work.special_use(), None);
+    assert_eq!(
+        SpecialUse::from_attribute("\\ALLMAIL"),
+        Some(SpecialUse::All)
+    );
+}
+
+#[test]
+fn filter_patterns_can_name_special_uses() {
+    let filter = FolderFilter {
+        include: Vec::new(),
+        exclude: vec!["\\Junk".to_string(), "\\Trash".to_string()],
+    };
+    let spam = parse_list_response(r#"LIST (\Junk) "/" "Courrier ind&AOk-sirable""#).unwrap();
+    let inbox = parse_list_response(r#"LIST () "/" "INBOX""#).unwrap();
+    assert!(!filter.matches_mailbox(&spam));
+    assert!(filter.matches_mailbox(&inbox));
+}
+
+#[tokio::test]
+async fn folders_are_listed_with_their_special_use() {
+    let server = MockServer::start(vec![
+        mailbox("INBOX", 0..1),
+        mailbox("[Gmail]/Sent Mail", 1..2),
+        mailbox("[Gmail]/Spam", 2..3),
+    ])
+    .await
+    .unwrap();
+    let dir = archive_dir("folders");
+    let config = ImapConfig {
+        include_sent: true,
+        ..config(&dir)
+    };
+
+    let folders = ImapClient::new(config, server.url())
+        .list_folders()
+        .await
+        .unwrap();
+    let listed: Vec<(&str, Option<SpecialUse>, bool)> = folders
+        .iter()
+        .map(|(mailbox, selected)| (mailbox.name.as_str(), mailbox.special_use(), *selected))
+        .collect();
+    assert_eq!(
+        listed,
+        [
+            ("INBOX", None, true),
+            ("[Gmail]/Sent Mail", Some(SpecialUse::Sent), true),
+            ("[Gmail]/Spam", Some(SpecialUse::Junk), false),
+        ]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,96 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, config};
+use imap_client::catalog::{Catalog, CatalogEntry, CATALOG_FILE};
+use imap_client::checkpoint::{Checkpoint, CHECKPOINT_FILE};
+use imap_client::client::ImapClient;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use imap_client::state::{StateDb, STATE_FILE};
+use imap_client::storage::LineEndings;
+
+#[test]
+fn records_are_seen_by_other_readers_once_committed() {
+    let dir = archive_dir("commit");
+    let state = StateDb::open(&dir).unwrap();
+    let mut catalog = Catalog::load(&state).unwrap();
+    let mut checkpoint = Checkpoint::load(&state).unwrap();
+    let entry = CatalogEntry::new(b"body", Some("<a@x>"), LineEndings::Keep, "a.eml".into());
+    catalog.record(entry.clone()).unwrap();
+    checkpoint.record("INBOX", 1, 7).unwrap();
+
+    assert!(Catalog::open(&dir).unwrap().entries().is_empty());
+    assert!(Checkpoint::open(&dir)
+        .unwrap()
+        .fetched_uids("INBOX", 1)
+        .is_empty());
+
+    // One commit covers both, as they share the database
+    checkpoint.sync().unwrap();
+    assert_eq!(Catalog::open(&dir).unwrap().entries(), [entry]);
+    assert!(Checkpoint::open(&dir)
+        .unwrap()
+        .fetched_uids("INBOX", 1)
+        .contains(&7));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn files_of_older_versions_are_imported_once() {
+    let dir = archive_dir("import");
+    std::fs::write(dir.join(CHECKPOINT_FILE), "1\t5\tINBOX\n1\t6\tINBOX\n").unwrap();
+    let hash = "a".repeat(64);
+    std::fs::write(
+        dir.join(CATALOG_FILE),
+        format!("\t{}\t10\tcrlf\t1700000000\t\tINBOX/1.eml\n", hash),
+    )
+    .unwrap();
+
+    for _ in 0..2 {
+        let checkpoint = Checkpoint::open(&dir).unwrap();
+        assert_eq!(checkpoint.fetched_uids("INBOX", 1).len(), 2);
+        let catalog = Catalog::open(&dir).unwrap();
+        assert_eq!(catalog.entries().len(), 1);
+        assert_eq!(catalog.entries()[0].line_endings, LineEndings::Crlf);
+        assert_eq!(catalog.path_of(&hash), Some("INBOX/1.eml"));
+    }
+    assert!(!dir.join(CHECKPOINT_FILE).exists());
+    assert!(dir.join(".catalog.tsv.imported").exists());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn a_run_keeps_its_state_in_a_wal_database() {
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: (0..5).map(|i| synthetic_message(i, 200)).collect(),
+    }])
+    .await
+    .unwrap();
+    let dir = archive_dir("run");
+    let config = ImapConfig {
+        batch_size: 2,
+        ..config(&dir)
+    };
+    ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+
+    let connection = rusqlite::Connection::open(dir.join(STATE_FILE)).unwrap();
+    let mode: String = connection
+        .query_row("PRAGMA journal_mode", [], |row| row.get(0))
+        .unwrap();
+    assert_eq!(mode, "wal");
+    let counts: (u32, u32) = connection
+        .query_row(
+            "SELECT (SELECT COUNT(*) FROM catalog), (SELECT COUNT(*) FROM checkpoint)",
+            [],
+            |row| Ok((row.get(0)?, row.get(1)?)),
+        )
+        .unwrap();
+    assert_eq!(counts, (5, 5));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
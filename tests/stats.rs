@@ -0,0 +1,126 @@
+#![cfg(feature = "mock")]
+
+use imap_client::client::ImapClient;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer, MOCK_STORAGE_LIMIT};
+use imap_client::stats::{
+    parse_quota_response, parse_status_messages, pick_samples, QuotaResource, SizeHistogram,
+};
+
+#[test]
+fn quota_responses_are_parsed() {
+    let root =
+        parse_quota_response(r#"QUOTA "" (STORAGE 1048576 15728640 MESSAGE 3 100)"#).unwrap();
+    assert_eq!(root.name, "");
+    assert_eq!(root.resources.len(), 2);
+    assert!(root.resources[0].is_storage());
+    assert_eq!(root.resources[0].describe(), "1.0 GiB of 15.0 GiB (7%)");
+    assert_eq!(root.resources[1].describe(), "3 of 100 (3%)");
+
+    let root = parse_quota_response("QUOTA user.alice (STORAGE 10 512)").unwrap();
+    assert_eq!(root.name, "user.alice");
+    assert!(parse_quota_response("QUOTA \"\" (STORAGE 10)").is_none());
+    assert!(parse_quota_response("QUOTAROOT INBOX \"\"").is_none());
+}
+
+#[test]
+fn status_message_counts_are_parsed() {
+    assert_eq!(
+        parse_status_messages(r#"STATUS "[Gmail]/Sent Mail" (MESSAGES 231 UNSEEN 4)"#),
+        Some(("[Gmail]/Sent Mail".to_string(), 231))
+    );
+    assert_eq!(
+        parse_status_messages("STATUS Entw&APw-rfe (MESSAGES 2)"),
+        Some(("Entwürfe".to_string(), 2))
+    );
+}
+
+#[tokio::test]
+async fn stats_report_quota_and_message_counts() {
+    let messages: Vec<Vec<u8>> = (0..4).map(|i| synthetic_message(i, 2048)).collect();
+    let used: usize = messages.iter().map(Vec::len).sum();
+    let server = MockServer::start(vec![
+        MockMailbox {
+            name: "INBOX".to_string(),
+            messages: messages[..3].to_vec(),
+        },
+        MockMailbox {
+            name: "Work".to_string(),
+            messages: messages[3..].to_vec(),
+        },
+    ])
+    .await
+    .unwrap();
+    let config = ImapConfig {
+        email: "test@example.com".to_string(),
+        password: "test".to_string(),
+        all_folders: true,
+        quiet: true,
+        ..ImapConfig::default()
+    };
+
+    let stats = ImapClient::new(config, server.url())
+        .stats(2)
+        .await
+        .unwrap();
+    assert_eq!(
+        stats.storage(),
+        Some(&QuotaResource {
+            name: "STORAGE".to_string(),
+            usage: used.div_ceil(1024) as u64,
+            limit: MOCK_STORAGE_LIMIT,
+        })
+    );
+    assert_eq!(
+        stats.mailboxes,
+        [("INBOX".to_string(), 3), ("Work".to_string(), 1)]
+    );
+    assert_eq!(stats.sizes.messages(), 4);
+    assert_eq!(stats.sizes.total(), used as u64);
+    assert_eq!(stats.sizes.counts[0], 4);
+    let sampled: u64 = stats.sizes.samples.iter().map(|sample| sample.raw).sum();
+    assert!(sampled > 0 && sampled < used as u64);
+    let formats: Vec<&str> = stats
+        .sizes
+        .projections()
+        .iter()
+        .map(|projection| projection.format)
+        .collect();
+    assert_eq!(formats, ["eml or Maildir files", "mbox", "gzip-compressed"]);
+}
+
+#[test]
+fn sizes_are_projected_per_format() {
+    let mut sizes = SizeHistogram::default();
+    for size in [500, 9_000, 50_000, 2 << 20] {
+        sizes.add(size);
+    }
+    assert_eq!(sizes.counts, [2, 1, 0, 1, 0]);
+    assert_eq!(SizeHistogram::label(3), "1-10 MiB");
+    let projections = sizes.projections();
+    // Every file takes whole 4 KiB blocks
+    assert_eq!(projections[0].bytes, 4096 + 12288 + 53248 + (2 << 20));
+    assert!(!projections[0].estimated);
+    assert_eq!(projections.len(), 2);
+
+    // A compressible sample brings the compressed projection well down
+    let message = format!(
+        "Subject: hi\r\n\r\n{}",
+        "All work and no play.\r\n".repeat(300)
+    );
+    sizes.add_sample(message.as_bytes()).unwrap();
+    let projections = sizes.projections();
+    assert_eq!(projections[2].format, "gzip-compressed");
+    assert!(projections[2].bytes < sizes.total() / 10);
+    // The mbox has LF line endings
+    assert!(projections[1].bytes < sizes.total());
+}
+
+#[test]
+fn samples_spread_over_sizes() {
+    let sizes = [100, 200, 300, 400, 20_000, 30_000, 5 << 20];
+    assert_eq!(pick_samples(&sizes, 3), [0, 4, 6]);
+    assert_eq!(pick_samples(&sizes, 5), [0, 2, 4, 5, 6]);
+    assert_eq!(pick_samples(&sizes, 50).len(), sizes.len());
+    assert!(pick_samples(&sizes, 0).is_empty());
+}
@@ -0,0 +1,198 @@
+mod common;
+
+use common::{expect, greeted};
+use imap_client::error_imap::ClientError;
+use imap_client::session::{Response, Status, TagGenerator};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use tokio::io::AsyncWriteExt;
+use tokio::time::timeout;
+
+#[test]
+fn tags_count_up_from_one() {
+    let mut tags = TagGenerator::default();
+    assert_eq!([tags.next_tag(), tags.next_tag()], ["A0001", "A0002"]);
+
+    let mut tags = TagGenerator::new('B');
+    let many: Vec<String> = (0..10_000).map(|_| tags.next_tag()).collect();
+    assert_eq!(many[0], "B0001");
+    assert_eq!(many[9_998], "B9999");
+    // Past four digits tags just grow, and stay unique
+    assert_eq!(many[9_999], "B10000");
+    let unique: std::collections::HashSet<&String> = many.iter().collect();
+    assert_eq!(unique.len(), many.len());
+}
+
+#[tokio::test]
+async fn each_command_gets_the_next_tag() {
+    let (mut session, mut server) = greeted("").await;
+    for expected in ["A0001", "A0002", "A0003"] {
+        let tag = session.send("NOOP").await.unwrap();
+        assert_eq!(tag, expected);
+        expect(&mut server, format!("{} NOOP\r\n", tag).as_bytes()).await;
+    }
+}
+
+#[tokio::test]
+async fn completions_are_matched_to_their_own_command() {
+    let (mut session, mut server) = greeted("").await;
+    let noop = session.send("NOOP").await.unwrap();
+    let select = session.send("SELECT INBOX").await.unwrap();
+    expect(&mut server, b"A0001 NOOP\r\nA0002 SELECT INBOX\r\n").await;
+
+    // The later command finishes first
+    server
+        .write_all(
+            b"* 3 EXISTS\r\nA0002 OK [READ-WRITE] SELECT completed\r\n\
+              * 4 EXISTS\r\nA0001 NO NOOP refused\r\n",
+        )
+        .await
+        .unwrap();
+    let (untagged, completion) = session.wait_for(&select).await.unwrap();
+    assert_eq!(untagged, ["3 EXISTS"]);
+    assert_eq!(
+        (completion.tag.as_str(), completion.command.as_str()),
+        ("A0002", "SELECT")
+    );
+    assert_eq!(completion.status, Status::Ok);
+    assert_eq!(completion.text, "[READ-WRITE] SELECT completed");
+
+    let (untagged, completion) = session.wait_for(&noop).await.unwrap();
+    assert_eq!(untagged, ["4 EXISTS"]);
+    assert_eq!(
+        (completion.tag.as_str(), completion.command.as_str()),
+        ("A0001", "NOOP")
+    );
+    assert_eq!(completion.status, Status::No);
+}
+
+#[tokio::test]
+async fn completions_of_other_commands_are_passed_over() {
+    let (mut session, mut server) = greeted("").await;
+    session.send("NOOP").await.unwrap();
+    let select = session.send("SELECT INBOX").await.unwrap();
+    server
+        .write_all(b"A0001 OK NOOP completed\r\nA0002 OK SELECT completed\r\n")
+        .await
+        .unwrap();
+    let (_, completion) = session.wait_for(&select).await.unwrap();
+    assert_eq!(completion.command, "SELECT");
+}
+
+#[tokio::test]
+async fn stray_tags_are_an_error() {
+    let (mut session, mut server) = greeted("").await;
+    let tag = session.send("NOOP").await.unwrap();
+
+    // A tag that was never sent
+    server.write_all(b"Z0042 OK what?\r\n").await.unwrap();
+    let error = session.wait_for(&tag).await.unwrap_err();
+    assert!(
+        matches!(&error, ClientError::ImapError(message)
+            if message == "Response for unknown tag Z0042: Z0042 OK what?"),
+        "{}",
+        error
+    );
+
+    // The real completion still arrives, but only once
+    server
+        .write_all(b"A0001 OK NOOP completed\r\nA0001 OK again\r\n")
+        .await
+        .unwrap();
+    session.wait_for(&tag).await.unwrap();
+    let tag = session.send("NOOP").await.unwrap();
+    let error = session.wait_for(&tag).await.unwrap_err();
+    assert!(error.to_string().contains("unknown tag A0001"), "{}", error);
+
+    // Nor is a line that isn't a response at all
+    server.write_all(b"A0002 MAYBE\r\n").await.unwrap();
+    let error = session.wait_for(&tag).await.unwrap_err();
+    assert!(
+        error.to_string().contains("Malformed response line"),
+        "{}",
+        error
+    );
+}
+
+#[tokio::test]
+async fn bye_while_waiting_ends_the_wait() {
+    let (mut session, mut server) = greeted("").await;
+    let tag = session.send("SELECT INBOX").await.unwrap();
+    expect(&mut server, b"A0001 SELECT INBOX\r\n").await;
+
+    // The connection stays open, but the completion will never come
+    server
+        .write_all(b"* 3 EXISTS\r\n* BYE Server shutting down\r\n")
+        .await
+        .unwrap();
+    let waited = timeout(Duration::from_secs(5), session.wait_for(&tag))
+        .await
+        .expect("waited past the BYE");
+    assert!(
+        matches!(&waited, Err(ClientError::ServerBye(text)) if text == "Server shutting down"),
+        "{:?}",
+        waited
+    );
+}
+
+#[tokio::test]
+async fn bye_in_the_middle_of_a_fetch_ends_it() {
+    let (mut session, mut server) = greeted("").await;
+    session
+        .send("UID FETCH 1:2 (UID BODY.PEEK[])")
+        .await
+        .unwrap();
+    expect(&mut server, b"A0001 UID FETCH 1:2 (UID BODY.PEEK[])\r\n").await;
+
+    server
+        .write_all(
+            b"* 1 FETCH (UID 1 BODY[] {5}\r\nhello)\r\n\
+              * BYE Too many simultaneous connections\r\n",
+        )
+        .await
+        .unwrap();
+    let mut fetched = Vec::new();
+    let ended: Result<(), ClientError> = timeout(Duration::from_secs(5), async {
+        loop {
+            match session.next_response().await? {
+                Response::Untagged(line) => fetched.push(line),
+                other => panic!("unexpected {:?}", other),
+            }
+        }
+    })
+    .await
+    .expect("read past the BYE");
+    assert_eq!(fetched, [r#"1 FETCH (UID 1 BODY[] "hello")"#]);
+    assert!(
+        matches!(ended, Err(ClientError::ServerBye(_))),
+        "{:?}",
+        ended
+    );
+}
+
+#[tokio::test]
+async fn alerts_are_kept_once_each_in_arrival_order() {
+    let (mut session, mut server) = greeted("").await;
+    let shared = Arc::new(Mutex::new(vec!["From another connection".to_string()]));
+    session.share_alerts(Arc::clone(&shared));
+    let tag = session.send("NOOP").await.unwrap();
+    expect(&mut server, b"A0001 NOOP\r\n").await;
+
+    server
+        .write_all(
+            b"* OK [ALERT] Your account is almost full\r\n\
+              * OK [ALERT] Your account is almost full\r\n\
+              * NO [ALERT] Maintenance tonight\r\n\
+              A0001 OK NOOP completed\r\n",
+        )
+        .await
+        .unwrap();
+    session.wait_for(&tag).await.unwrap();
+    let expected = [
+        "From another connection",
+        "Your account is almost full",
+        "Maintenance tonight",
+    ];
+    assert_eq!(session.alerts(), expected);
+    assert_eq!(*shared.lock().unwrap(), expected);
+}
@@ -0,0 +1,73 @@
+use imap_client::message::{decode_charset, find_text_part};
+
+#[test]
+fn text_bodies_are_converted_from_their_declared_charset() {
+    let latin1 = b"Content-Type: text/plain; charset=\"ISO-8859-1\"\r\n\
+                   Content-Transfer-Encoding: quoted-printable\r\n\r\n\
+                   Caf=E9 cr=E8me=\r\n br=FBl=E9e";
+    assert_eq!(
+        find_text_part(latin1, "text/plain").as_deref(),
+        Some("Café crème brûlée")
+    );
+
+    // "€ 5" in windows-1252, base64 over two lines
+    let cp1252 = b"Content-Type: text/plain; charset=windows-1252\r\n\
+                   Content-Transfer-Encoding: base64\r\n\r\ngCA\r\n1\r\n";
+    assert_eq!(find_text_part(cp1252, "text/plain").as_deref(), Some("€ 5"));
+
+    let shift_jis = [
+        &b"Content-Type: text/plain; charset=Shift_JIS\r\n\r\n"[..],
+        &[0x93, 0xfa, 0x96, 0x7b],
+    ]
+    .concat();
+    assert_eq!(
+        find_text_part(&shift_jis, "text/plain").as_deref(),
+        Some("日本")
+    );
+}
+
+#[test]
+fn missing_and_unknown_charsets_still_give_text() {
+    // No Content-Type at all is US-ASCII text
+    assert_eq!(
+        find_text_part(b"Subject: hi\r\n\r\nplain", "text/plain").as_deref(),
+        Some("plain")
+    );
+    let unknown = "Content-Type: text/plain; charset=x-no-such\r\n\r\nGrüße".as_bytes();
+    assert_eq!(
+        find_text_part(unknown, "text/plain").as_deref(),
+        Some("Grüße")
+    );
+    // Bytes that aren't valid in the charset are replaced, not dropped
+    assert_eq!(decode_charset("utf-8", b"a\xffb"), "a\u{fffd}b");
+    assert_eq!(decode_charset(" UTF-8 ", "ü".as_bytes()), "ü");
+}
+
+#[test]
+fn the_first_inline_part_of_the_type_is_used() {
+    let multipart = b"Content-Type: multipart/mixed; boundary=\"b\"\r\n\r\n\
+                      --b\r\n\
+                      Content-Type: text/plain; charset=utf-8\r\n\
+                      Content-Disposition: attachment; filename=notes.txt\r\n\r\n\
+                      attached\r\n\
+                      --b\r\n\
+                      Content-Type: multipart/alternative; boundary=\"c\"\r\n\r\n\
+                      --c\r\n\
+                      Content-Type: text/plain; charset=iso-8859-1\r\n\r\n\
+                      Gr\xfc\xdfe\r\n\
+                      --c\r\n\
+                      Content-Type: text/html; charset=utf-8\r\n\r\n\
+                      <p>Gr\xc3\xbc\xc3\x9fe</p>\r\n\
+                      --c--\r\n\
+                      --b--\r\n";
+    assert_eq!(
+        find_text_part(multipart, "text/plain").as_deref(),
+        Some("Grüße")
+    );
+    assert_eq!(
+        find_text_part(multipart, "text/html").as_deref(),
+        Some("<p>Grüße</p>")
+    );
+    assert_eq!(find_text_part(multipart, "text/calendar"), None);
+    assert_eq!(find_text_part(b"", "text/plain").as_deref(), Some(""));
+}
@@ -0,0 +1,59 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::archive_dir;
+use imap_client::mock::synthetic_message;
+use imap_client::storage::{merge_thread_directories, read_mbox};
+use std::path::Path;
+
+/// Subjects of the messages in the mbox at `path`, in order.
+fn subjects(path: &Path) -> Vec<String> {
+    let mut subjects = Vec::new();
+    let file = std::io::BufReader::new(std::fs::File::open(path).unwrap());
+    read_mbox(file, |message| {
+        let text = String::from_utf8_lossy(&message);
+        let subject = text.lines().find_map(|line| line.strip_prefix("Subject: "));
+        subjects.push(subject.unwrap_or_default().to_string());
+        Ok(())
+    })
+    .unwrap();
+    subjects
+}
+
+#[test]
+fn later_runs_add_to_a_thread_mbox() {
+    let threads = archive_dir("append");
+    let save = |thread: &str, index: usize| {
+        let dir = threads.join(thread);
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join(format!("email_{:05}.eml", index + 1));
+        std::fs::write(path, synthetic_message(index, 200)).unwrap();
+    };
+
+    save("a", 0);
+    save("a", 1);
+    save("b", 2);
+    merge_thread_directories(&threads).unwrap();
+    assert_eq!(
+        subjects(&threads.join("a.mbox")),
+        ["Message 0", "Message 1"]
+    );
+
+    // A second run with a new message in one thread, and one in which the
+    // other thread's directory is back but has nothing new
+    save("a", 3);
+    std::fs::create_dir_all(threads.join("b")).unwrap();
+    merge_thread_directories(&threads).unwrap();
+    assert_eq!(
+        subjects(&threads.join("a.mbox")),
+        ["Message 0", "Message 1", "Message 3"]
+    );
+    assert_eq!(subjects(&threads.join("b.mbox")), ["Message 2"]);
+    assert!(!threads.join("a").exists());
+
+    // A run with nothing new at all leaves them alone
+    merge_thread_directories(&threads).unwrap();
+    assert_eq!(subjects(&threads.join("a.mbox")).len(), 3);
+    std::fs::remove_dir_all(&threads).unwrap();
+}
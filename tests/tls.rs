@@ -0,0 +1,246 @@
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use imap_client::args::parse_args;
+use imap_client::input::ImapConfig;
+use imap_client::tls::{
+    check_pins, client_config, describe_certificate, load_client_cert, Pin, TlsOptions, TlsVersion,
+};
+use rustls::pki_types::CertificateDer;
+use sha2::{Digest, Sha256};
+use std::path::PathBuf;
+use std::sync::Arc;
+
+/// Writes a self-signed client certificate to `dir`, as `client.pem` with
+/// its key and as `cert.pem` and `key.pem` apart.
+fn client_files(dir: &PathBuf) {
+    std::fs::create_dir_all(dir).unwrap();
+    let cert = rcgen::generate_simple_self_signed(vec!["client.example.org".to_string()]).unwrap();
+    let (pem, key) = (
+        cert.serialize_pem().unwrap(),
+        cert.serialize_private_key_pem(),
+    );
+    std::fs::write(dir.join("client.pem"), format!("{}{}", pem, key)).unwrap();
+    std::fs::write(dir.join("cert.pem"), pem).unwrap();
+    std::fs::write(dir.join("key.pem"), key).unwrap();
+}
+
+#[test]
+fn a_client_certificate_is_read_with_its_key() {
+    let dir = std::env::temp_dir().join(format!("tls-test-{}", std::process::id()));
+    client_files(&dir);
+
+    let options = TlsOptions {
+        client_cert: Some(dir.join("client.pem")),
+        ..TlsOptions::default()
+    };
+    let config = client_config(&options).unwrap();
+    assert!(config.client_auth_cert_resolver.has_certs());
+    let (chain, _) = load_client_cert(&dir.join("cert.pem"), Some(&dir.join("key.pem"))).unwrap();
+    assert_eq!(chain.len(), 1);
+    let default = client_config(&TlsOptions::default()).unwrap();
+    assert!(!default.client_auth_cert_resolver.has_certs());
+
+    let path = |file: &str| dir.join(file).to_string_lossy().into_owned();
+    let given = [
+        "--client-cert".to_string(),
+        path("cert.pem"),
+        "--client-key".to_string(),
+        path("key.pem"),
+    ];
+    let args = parse_args(given).unwrap();
+    assert_eq!(args.client_cert, Some(dir.join("cert.pem")));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_client_certificate_needs_its_key() {
+    let dir = std::env::temp_dir().join(format!("tls-key-test-{}", std::process::id()));
+    client_files(&dir);
+
+    let error = load_client_cert(&dir.join("cert.pem"), None).unwrap_err();
+    assert!(error.to_string().contains("no private key"), "{}", error);
+    assert!(load_client_cert(&dir.join("key.pem"), None).is_err());
+    assert!(load_client_cert(&dir.join("missing.pem"), None).is_err());
+
+    let key = dir.join("key.pem").to_string_lossy().into_owned();
+    assert!(parse_args(["--client-key".to_string(), key]).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn a_pin_is_the_hash_of_the_certificates_public_key() {
+    let cert = rcgen::generate_simple_self_signed(vec!["imap.example.org".to_string()]).unwrap();
+    let der = cert.serialize_der().unwrap();
+    let spki_hash: [u8; 32] = Sha256::digest(cert.get_key_pair().public_key_der()).into();
+    let pin = Pin::of(&der).unwrap();
+    assert_eq!(pin.to_string().parse::<Pin>().unwrap(), pin);
+    assert_eq!(
+        pin.to_string(),
+        format!("sha256/{}", STANDARD.encode(spki_hash))
+    );
+    // curl writes the prefix with two slashes
+    let curl = pin.to_string().replacen('/', "//", 1);
+    assert_eq!(curl.parse::<Pin>().unwrap(), pin);
+    assert!("sha256/c2hvcnQ=".parse::<Pin>().is_err());
+    assert!(Pin::of(b"not a certificate").is_none());
+}
+
+#[test]
+fn a_certificate_without_a_pinned_key_is_refused() {
+    let server = rcgen::generate_simple_self_signed(vec!["imap.example.org".to_string()]).unwrap();
+    let other = rcgen::generate_simple_self_signed(vec!["imap.example.org".to_string()]).unwrap();
+    let (server, other) = (
+        server.serialize_der().unwrap(),
+        other.serialize_der().unwrap(),
+    );
+    let pin = Pin::of(&server).unwrap();
+
+    assert!(check_pins(&server, &[], &[pin]).is_ok());
+    // A pinned intermediate counts as much as the server's own key
+    assert!(check_pins(&other, &[CertificateDer::from(server.clone())], &[pin]).is_ok());
+    let error = check_pins(&other, &[], &[pin]).unwrap_err();
+    let other_pin = Pin::of(&other).unwrap().to_string();
+    assert!(error.to_string().contains(&other_pin), "{}", error);
+
+    let given = parse_args(["--pin".to_string(), pin.to_string()]).unwrap();
+    assert_eq!(given.pins, vec![pin]);
+    let pinned = TlsOptions {
+        pins: vec![pin],
+        ..TlsOptions::default()
+    };
+    assert!(client_config(&pinned).is_ok());
+}
+
+#[test]
+fn a_certificate_is_summed_up_for_debugging() {
+    let mut params = rcgen::CertificateParams::new(vec!["imap.example.org".to_string()]);
+    params.distinguished_name = rcgen::DistinguishedName::new();
+    params
+        .distinguished_name
+        .push(rcgen::DnType::OrganizationName, "Example Mail");
+    params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, "imap.example.org");
+    params.not_before = rcgen::date_time_ymd(2024, 1, 2);
+    // GeneralizedTime, which dates from 2050 on are written in
+    params.not_after = rcgen::date_time_ymd(2051, 3, 4);
+    let cert = rcgen::Certificate::from_params(params).unwrap();
+    let der = cert.serialize_der().unwrap();
+
+    let summary = describe_certificate(&der).unwrap();
+    let pin = Pin::of(&der).unwrap();
+    assert_eq!(
+        summary,
+        format!(
+            "CN=imap.example.org, O=Example Mail, issued by CN=imap.example.org, O=Example Mail, \
+             valid 2024-01-02 00:00:00 UTC to 2051-03-04 00:00:00 UTC, {}",
+            pin
+        )
+    );
+    assert!(describe_certificate(&der[..der.len() / 2]).is_none());
+
+    assert!(
+        parse_args(["--debug-connection".to_string()])
+            .unwrap()
+            .debug_connection
+    );
+}
+
+/// Accepts one TLS connection on a local port, speaking only `versions`
+/// and `suites`, and returns the port.
+async fn tls_server(
+    versions: &[&'static rustls::SupportedProtocolVersion],
+    suites: &[rustls::SupportedCipherSuite],
+) -> u16 {
+    let cert = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let key = rustls::pki_types::PrivateKeyDer::Pkcs8(cert.serialize_private_key_der().into());
+    let mut provider = rustls::crypto::ring::default_provider();
+    provider.cipher_suites = suites.to_vec();
+    let config = rustls::ServerConfig::builder_with_provider(Arc::new(provider))
+        .with_protocol_versions(versions)
+        .unwrap()
+        .with_no_client_auth()
+        .with_single_cert(vec![cert.serialize_der().unwrap().into()], key)
+        .unwrap();
+    let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let _ = tokio_rustls::TlsAcceptor::from(Arc::new(config))
+            .accept(stream)
+            .await;
+    });
+    port
+}
+
+/// How a handshake with a server speaking only `versions` and `suites`
+/// fails for a client with `options`.
+async fn handshake_error(
+    options: &TlsOptions,
+    versions: &[&'static rustls::SupportedProtocolVersion],
+    suites: &[rustls::SupportedCipherSuite],
+) -> String {
+    let port = tls_server(versions, suites).await;
+    let stream = tokio::net::TcpStream::connect(("127.0.0.1", port))
+        .await
+        .unwrap();
+    let connector = tokio_rustls::TlsConnector::from(Arc::new(client_config(options).unwrap()));
+    let name = rustls::pki_types::ServerName::try_from("localhost").unwrap();
+    connector
+        .connect(name, stream)
+        .await
+        .unwrap_err()
+        .to_string()
+}
+
+#[tokio::test]
+async fn servers_below_the_tls_policy_are_refused() {
+    use rustls::crypto::ring::cipher_suite::{TLS13_AES_128_GCM_SHA256, TLS13_AES_256_GCM_SHA384};
+    use rustls::crypto::ring::ALL_CIPHER_SUITES;
+    use rustls::version::{TLS12, TLS13};
+
+    let mut config = ImapConfig::default();
+    let given = [
+        "--tls-min-version",
+        "1.3",
+        "--tls-ciphers",
+        "TLS13_AES_256_GCM_SHA384, tls13_chacha20_poly1305_sha256",
+        "--no-session-tickets",
+    ];
+    parse_args(given.map(String::from))
+        .unwrap()
+        .apply_to(&mut config);
+    assert_eq!(config.tls.min_version, TlsVersion::Tls13);
+    assert_eq!(
+        config.tls.cipher_suites[0],
+        TLS13_AES_256_GCM_SHA384.suite()
+    );
+    assert!(!config.tls.session_tickets);
+    let resumption = format!("{:?}", client_config(&config.tls).unwrap().resumption);
+    assert!(
+        resumption.contains("NoClientSessionStorage"),
+        "{}",
+        resumption
+    );
+
+    // Without a policy, both servers get as far as showing their (untrusted)
+    // certificate
+    let default = TlsOptions::default();
+    let error = handshake_error(&default, &[&TLS12], ALL_CIPHER_SUITES).await;
+    assert!(error.contains("UnknownIssuer"), "{}", error);
+    let error = handshake_error(&default, &[&TLS13], &[TLS13_AES_128_GCM_SHA256]).await;
+    assert!(error.contains("UnknownIssuer"), "{}", error);
+
+    let error = handshake_error(&config.tls, &[&TLS12], ALL_CIPHER_SUITES).await;
+    assert!(error.contains("ProtocolVersion"), "{}", error);
+    let error = handshake_error(&config.tls, &[&TLS13], &[TLS13_AES_128_GCM_SHA256]).await;
+    assert!(error.contains("HandshakeFailure"), "{}", error);
+
+    assert!(parse_args(["--tls-ciphers", "TLS_RSA_WITH_RC4_128_MD5"].map(String::from)).is_err());
+    // TLS 1.3 has no suite in common with TLS 1.2's
+    let given = [
+        "--tls-min-version=1.3",
+        "--tls-ciphers=TLS_ECDHE_RSA_WITH_AES_256_GCM_SHA384",
+    ];
+    assert!(parse_args(given.map(String::from)).is_err());
+}
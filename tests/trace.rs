@@ -0,0 +1,101 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::archive_dir;
+use imap_client::args::parse_args;
+use imap_client::client::ImapClient;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use imap_client::session::Session;
+use imap_client::trace::{WireTrace, LITERAL_HEAD};
+use std::sync::Arc;
+use tokio::io::AsyncWriteExt;
+
+#[tokio::test]
+async fn a_fetch_is_traced_without_credentials_or_bodies() {
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: (0..2).map(|i| synthetic_message(i, 2000)).collect(),
+    }])
+    .await
+    .unwrap();
+    let dir = archive_dir("trace");
+    let trace = dir.join("imap.trace");
+
+    let mut config = ImapConfig {
+        email: "test@example.com".to_string(),
+        password: "secret".to_string(),
+        dir_path: dir.join("mail"),
+        quiet: true,
+        ..ImapConfig::default()
+    };
+    let given = ["--trace-imap".to_string(), trace.display().to_string()];
+    parse_args(given).unwrap().apply_to(&mut config);
+    let summary = ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 2);
+
+    let log = std::fs::read_to_string(&trace).unwrap();
+    let entries: Vec<&str> = log
+        .lines()
+        .map(|line| line.splitn(3, ' ').nth(2).unwrap())
+        .collect();
+    assert!(entries.iter().any(|e| e.starts_with("*: connected to ")));
+    assert!(entries.iter().any(|e| e.starts_with("S: * OK")));
+    assert!(entries
+        .iter()
+        .any(|e| e.starts_with("C: ") && e.ends_with(" LOGIN \"test@example.com\" ***")));
+    assert!(!log.contains("secret"));
+    // Message bodies are cut short
+    assert!(entries
+        .iter()
+        .any(|e| e.starts_with("S: <literal of 20") && e.contains("From: Sender 0")));
+    assert!(!log.contains("Lorem ipsum"));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn literals_are_cut_short_and_control_characters_escaped() {
+    let path = std::env::temp_dir().join(format!("imap-test-literal-{}.trace", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    let (client, mut server) = tokio::io::duplex(4096);
+    let mut session = Session::new(client);
+    let trace = Arc::new(WireTrace::new(path.clone()));
+    session.set_trace(
+        Arc::clone(&trace),
+        trace.open_connection("imap.example.org:993"),
+    );
+
+    let literal = format!("Subject: \x1b[31mred\r\n\r\n{}", "x".repeat(200));
+    let response = format!(
+        "* 1 FETCH (BODY[] {{{}}}\r\n{})\r\n* OK \x07bell\r\n",
+        literal.len(),
+        literal
+    );
+    server.write_all(response.as_bytes()).await.unwrap();
+    session.read_response().await.unwrap();
+    session.read_response().await.unwrap();
+
+    let log = std::fs::read_to_string(&path).unwrap();
+    let lines: Vec<&str> = log.lines().collect();
+    assert_eq!(lines.len(), 5, "{}", log);
+    assert!(lines[1].ends_with(&format!(" S: * 1 FETCH (BODY[] {{{}}}", literal.len())));
+    let head = "Subject: \\x1b[31mred\\r\\n\\r\\n";
+    let shown = LITERAL_HEAD - "Subject: \x1b[31mred\r\n\r\n".len();
+    assert!(
+        lines[2].ends_with(&format!(
+            " S: <literal of {} bytes: {}{}...>",
+            literal.len(),
+            head,
+            "x".repeat(shown)
+        )),
+        "{}",
+        lines[2]
+    );
+    assert!(lines[3].ends_with(" S: )"));
+    assert!(lines[4].ends_with(" S: * OK \\x07bell"));
+    std::fs::remove_file(&path).unwrap();
+}
@@ -0,0 +1,111 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::archive_dir;
+use imap_client::client::ImapClient;
+use imap_client::folders::{parse_list_response, parse_utf8_list_response};
+use imap_client::input::ImapConfig;
+use imap_client::message::{parse_address, Headers};
+use imap_client::mock::{MockMailbox, MockServer};
+use imap_client::session::Session;
+use tokio::net::TcpStream;
+
+fn config(dir: &std::path::Path) -> ImapConfig {
+    ImapConfig {
+        email: "jörg@bücher.example".to_string(),
+        password: "test".to_string(),
+        dir_path: dir.to_path_buf(),
+        quiet: true,
+        ..ImapConfig::default()
+    }
+}
+
+/// A message with a raw UTF-8 header section, as SMTPUTF8 delivers them.
+fn international_message() -> Vec<u8> {
+    "From: \"Jörg Müller\" <jörg@bücher.example>\r\n\
+     To: 用户@例子.广告\r\n\
+     Subject: Grüße aus Zürich\r\n\
+     Date: Mon, 1 Jan 2024 12:00:00 +0000\r\n\
+     Message-ID: <grüße@bücher.example>\r\n\
+     \r\n\
+     Hallo!\r\n"
+        .as_bytes()
+        .to_vec()
+}
+
+#[test]
+fn internationalized_headers_are_parsed() {
+    let headers = Headers::parse(&international_message());
+    assert_eq!(headers.subject().as_deref(), Some("Grüße aus Zürich"));
+    let sender = headers.sender().unwrap();
+    assert_eq!(sender.name.as_deref(), Some("Jörg Müller"));
+    assert_eq!(sender.email, "jörg@bücher.example");
+    assert_eq!(sender.domain().as_deref(), Some("bücher.example"));
+
+    let address = parse_address("用户@例子.广告 (Zhang), other@example.com").unwrap();
+    assert_eq!(
+        (address.name, address.email.as_str()),
+        (None, "用户@例子.广告")
+    );
+    let address = parse_address("=?UTF-8?B?w4lsb2TDqWU=?= <elodee@example.fr>").unwrap();
+    assert_eq!(address.name.as_deref(), Some("Élodée"));
+    assert_eq!(parse_address("undisclosed-recipients:;"), None);
+}
+
+#[test]
+fn utf8_mailbox_names_are_taken_as_sent() {
+    // Once UTF-8 is enabled, what looks like modified UTF-7 is taken literally
+    let line = r#"LIST (\HasNoChildren) "/" "Caf&AOk-""#;
+    assert_eq!(parse_list_response(line).unwrap().name, "Café");
+    assert_eq!(parse_utf8_list_response(line).unwrap().name, "Caf&AOk-");
+    let line = "LIST () \"/\" \"Entwürfe\"";
+    assert_eq!(parse_utf8_list_response(line).unwrap().name, "Entwürfe");
+}
+
+#[tokio::test]
+async fn utf8_accept_is_enabled_when_advertised() {
+    let server = MockServer::start(Vec::new()).await.unwrap();
+    let stream = TcpStream::connect(server.addr()).await.unwrap();
+    let mut session = Session::new(stream);
+    session.read_greeting().await.unwrap();
+    session.execute("LOGIN test test").await.unwrap();
+
+    assert_eq!(session.mailbox_name("Entwürfe"), "Entw&APw-rfe");
+    assert!(session.enable_utf8().await.unwrap());
+    assert!(session.utf8_enabled());
+    assert_eq!(session.mailbox_name("Entwürfe"), "Entwürfe");
+}
+
+#[tokio::test]
+async fn international_folders_and_message_ids_work_end_to_end() {
+    let server = MockServer::start(vec![
+        MockMailbox {
+            name: "INBOX".to_string(),
+            messages: Vec::new(),
+        },
+        MockMailbox {
+            name: "Entwürfe & Notizen".to_string(),
+            messages: vec![international_message()],
+        },
+    ])
+    .await
+    .unwrap();
+    let dir = archive_dir("folders");
+    let config = ImapConfig {
+        all_folders: true,
+        ..config(&dir)
+    };
+
+    let client = ImapClient::new(config, server.url());
+    let summary = client.fetch_all_emails().await.unwrap();
+    assert_eq!(summary.saved, 1);
+    assert!(dir
+        .join("Entwürfe & Notizen")
+        .join("email_00001.eml")
+        .exists());
+
+    let found = client.get_message("<grüße@bücher.example>").await.unwrap();
+    assert_eq!(found, Some(international_message()));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,148 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, config};
+use imap_client::catalog::sha256_hex;
+use imap_client::client::ImapClient;
+use imap_client::dedupe::dedupe_archive;
+use imap_client::export::{export_archive, ExportFormat};
+use imap_client::import::import_messages;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{MockMailbox, MockServer};
+use imap_client::views::{parse_views, View};
+use std::path::{Path, PathBuf};
+
+const MESSAGE: &[u8] = b"From: Alice <alice@example.com>\r\n\
+    Subject: Plans\r\n\
+    Date: Tue, 5 Mar 2024 10:00:00 +0000\r\n\
+    Message-ID: <plans@example.com>\r\n\
+    \r\n\
+    See you there.\r\n";
+
+#[test]
+fn views_parse_from_a_list() {
+    assert_eq!(
+        parse_views("label, date,sender,label").unwrap(),
+        vec![View::Label, View::Date, View::Sender]
+    );
+    assert!(parse_views("label,size").is_err());
+}
+
+#[tokio::test]
+async fn messages_are_stored_once_and_linked_into_views() {
+    // The same message under two labels
+    let server = MockServer::start(vec![
+        MockMailbox {
+            name: "INBOX".to_string(),
+            messages: vec![MESSAGE.to_vec()],
+        },
+        MockMailbox {
+            name: "Work".to_string(),
+            messages: vec![MESSAGE.to_vec()],
+        },
+    ])
+    .await
+    .unwrap();
+    let dir = archive_dir("store");
+    let config = ImapConfig {
+        all_folders: true,
+        views: vec![View::Label, View::Date, View::Sender],
+        ..config(&dir)
+    };
+
+    let summary = ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 2);
+
+    let hash = sha256_hex(MESSAGE);
+    let object = dir
+        .join("objects")
+        .join(&hash[..2])
+        .join(format!("{}.eml", hash));
+    assert_eq!(std::fs::read(&object).unwrap(), MESSAGE);
+    let objects: Vec<_> = std::fs::read_dir(object.parent().unwrap())
+        .unwrap()
+        .collect();
+    assert_eq!(objects.len(), 1);
+
+    // Named and dated by INTERNALDATE, which the mock sets to 2 January 2024
+    let name = format!("20240102T120000_{}.eml", &hash[..12]);
+    let links = [
+        dir.join("views/label/INBOX/email_00001.eml"),
+        dir.join("views/label/Work/email_00001.eml"),
+        dir.join("views/date/2024/01").join(&name),
+        dir.join("views/sender/alice@example.com").join(&name),
+    ];
+    for link in &links {
+        assert!(
+            link.symlink_metadata().unwrap().file_type().is_symlink(),
+            "{}",
+            link.display()
+        );
+        assert_eq!(std::fs::read(link).unwrap(), MESSAGE);
+    }
+
+    // Views aren't duplicates of what they link to
+    let report = dedupe_archive(&dir, true).unwrap();
+    assert_eq!((report.scanned, report.removed), (1, 0));
+    assert!(links.iter().all(|link| link.exists()));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+/// Every file under `dir`, at any depth.
+fn files_under(dir: &Path) -> Vec<PathBuf> {
+    let mut files = Vec::new();
+    for entry in std::fs::read_dir(dir).unwrap() {
+        let path = entry.unwrap().path();
+        if path.is_dir() {
+            files.extend(files_under(&path));
+        } else {
+            files.push(path);
+        }
+    }
+    files
+}
+
+#[tokio::test]
+async fn hard_linked_views_are_not_taken_for_messages() {
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: vec![MESSAGE.to_vec()],
+    }])
+    .await
+    .unwrap();
+    let dir = archive_dir("hard-links");
+    let config = ImapConfig {
+        views: vec![View::Label, View::Date, View::Sender],
+        ..config(&dir)
+    };
+    ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+
+    // What views look like where symlinks can't be made
+    let links = files_under(&dir.join("views"));
+    assert_eq!(links.len(), 3);
+    for link in &links {
+        let object = std::fs::canonicalize(link).unwrap();
+        std::fs::remove_file(link).unwrap();
+        std::fs::hard_link(&object, link).unwrap();
+    }
+
+    let report = dedupe_archive(&dir, true).unwrap();
+    assert_eq!((report.scanned, report.removed), (1, 0));
+    assert!(links.iter().all(|link| link.is_file()));
+
+    let report = import_messages(&dir, &dir).unwrap();
+    assert_eq!((report.scanned, report.added), (1, 0));
+
+    let output = dir.with_extension("export");
+    let report = export_archive(&dir, &output, ExportFormat::Thunderbird).unwrap();
+    assert_eq!(report.messages, 1);
+    std::fs::remove_dir_all(&output).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+}
@@ -0,0 +1,100 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, inbox};
+use imap_client::checkpoint::Checkpoint;
+use imap_client::client::ImapClient;
+use imap_client::input::ImapConfig;
+use imap_client::window::{parse_bound, DateRange};
+use std::path::Path;
+
+// Received at noon UTC from 2 January to 3 March 2024, 28 days to a month
+fn config(dir: &Path) -> ImapConfig {
+    ImapConfig {
+        dates: DateRange {
+            since: Some(parse_bound("2024-01-15").unwrap()),
+            before: Some(parse_bound("2024-03-03").unwrap()),
+        },
+        by_month: true,
+        ..common::audited(dir)
+    }
+}
+
+#[test]
+fn months_cover_the_whole_range() {
+    let first = parse_bound("2024-01-10T08:00").unwrap();
+    let now = parse_bound("2024-03-05").unwrap();
+    let windows: Vec<String> = DateRange::default()
+        .months(first, now)
+        .iter()
+        .map(DateRange::to_string)
+        .collect();
+    assert_eq!(
+        windows,
+        [
+            "before 2024-02-01",
+            "2024-02-01 to 2024-03-01",
+            "from 2024-03-01"
+        ]
+    );
+
+    let range = DateRange {
+        since: Some(parse_bound("2023-12-31T23:00").unwrap()),
+        before: Some(parse_bound("2024-01-20").unwrap()),
+    };
+    assert_eq!(range.months(first, now), [range]);
+}
+
+#[tokio::test]
+async fn each_month_is_searched_and_fetched_on_its_own() {
+    let server = inbox(60).await;
+    let dir = archive_dir("months");
+    let summary = ImapClient::new(config(&dir), server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+
+    // 15 to 28 January, all of February, and 1 and 2 March
+    assert_eq!(summary.saved, 14 + 28 + 2);
+    assert_eq!(summary.found, 14 + 28 + 2);
+    let audit = std::fs::read_to_string(dir.join("audit.log")).unwrap();
+    let searches: Vec<&str> = audit
+        .lines()
+        .filter_map(|line| line.split('\t').nth(2))
+        .filter(|command| command.contains("UID SEARCH"))
+        .collect();
+    assert_eq!(
+        searches,
+        [
+            "send A0005 UID SEARCH SINCE 14-Jan-2024 BEFORE 2-Feb-2024",
+            "send A0005 UID SEARCH SINCE 31-Jan-2024 BEFORE 2-Mar-2024",
+            "send A0005 UID SEARCH SINCE 29-Feb-2024 BEFORE 4-Mar-2024",
+        ]
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn a_capped_run_resumes_in_the_month_it_stopped_in() {
+    let server = inbox(60).await;
+    let dir = archive_dir("resume");
+    let capped = ImapConfig {
+        max_messages: Some(20),
+        ..config(&dir)
+    };
+    let first = ImapClient::new(capped, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(first.saved, 20);
+
+    let second = ImapClient::new(config(&dir), server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(second.saved, 24);
+    let checkpoint = Checkpoint::open(&dir).unwrap();
+    assert_eq!(checkpoint.fetched_uids("INBOX", 1).len(), 44);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
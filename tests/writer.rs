@@ -0,0 +1,112 @@
+#![cfg(feature = "mock")]
+
+mod common;
+
+use common::{archive_dir, config};
+use imap_client::args::parse_args;
+use imap_client::catalog::Catalog;
+use imap_client::client::ImapClient;
+use imap_client::input::ImapConfig;
+use imap_client::mock::{synthetic_message, MockMailbox, MockServer};
+use imap_client::state::StateDb;
+use imap_client::writer::{ensure_io_uring, FileWriter, GROUPED_FILE_SIZE};
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+#[tokio::test]
+async fn files_are_written_whole_and_replaced() {
+    let dir = archive_dir("whole");
+    let writer = Arc::new(FileWriter::new(ensure_io_uring().is_ok()));
+
+    // Many files in flight at once, as batches save them
+    let mut writes = tokio::task::JoinSet::new();
+    for i in 0..50 {
+        let writer = Arc::clone(&writer);
+        let path = dir.join(format!("email_{:05}.eml", i));
+        let contents = format!("Subject: {}\r\n\r\n{}", i, "x".repeat(i * 1000)).into_bytes();
+        writes.spawn(async move { writer.write(&path, contents, None).await });
+    }
+    while let Some(result) = writes.join_next().await {
+        result.unwrap().unwrap();
+    }
+    let path = dir.join("email_00049.eml");
+    let large = std::fs::read(&path).unwrap();
+    assert_eq!(large.len(), "Subject: 49\r\n\r\n".len() + 49_000);
+
+    writer.write(&path, b"short".to_vec(), None).await.unwrap();
+    assert_eq!(std::fs::read(&path).unwrap(), b"short");
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn io_uring_is_refused_by_builds_without_it() {
+    let given = ["--io-uring"].map(String::from);
+    assert_eq!(
+        parse_args(given.into_iter()).is_ok(),
+        ensure_io_uring().is_ok()
+    );
+}
+
+#[tokio::test]
+async fn small_files_wait_for_the_next_commit() {
+    let dir = archive_dir("held");
+    let state = StateDb::open(&dir).unwrap();
+    let group = state.write_group().unwrap();
+    let writer = FileWriter::Grouped(Arc::clone(&group));
+
+    let small = dir.join("small.eml");
+    let large = dir.join("large.eml");
+    writer
+        .write(
+            &small,
+            b"Subject: hi\r\n\r\nhello".to_vec(),
+            Some(1_704_283_200),
+        )
+        .await
+        .unwrap();
+    writer
+        .write(&large, vec![b'x'; GROUPED_FILE_SIZE + 1], None)
+        .await
+        .unwrap();
+    assert!(!small.exists());
+    assert!(large.exists());
+    assert_eq!(group.len(), 1);
+
+    state.commit().unwrap();
+    assert!(group.is_empty());
+    assert_eq!(std::fs::read(&small).unwrap(), b"Subject: hi\r\n\r\nhello");
+    let modified = std::fs::metadata(&small).unwrap().modified().unwrap();
+    assert_eq!(
+        modified.duration_since(UNIX_EPOCH).unwrap(),
+        Duration::from_secs(1_704_283_200)
+    );
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[tokio::test]
+async fn a_grouped_fetch_saves_every_message() {
+    let messages: Vec<Vec<u8>> = (0..300).map(|i| synthetic_message(i, 300)).collect();
+    let server = MockServer::start(vec![MockMailbox {
+        name: "INBOX".to_string(),
+        messages: messages.clone(),
+    }])
+    .await
+    .unwrap();
+    let dir = archive_dir("grouped");
+    let config = ImapConfig {
+        group_writes: true,
+        ..config(&dir)
+    };
+
+    let summary = ImapClient::new(config, server.url())
+        .fetch_all_emails()
+        .await
+        .unwrap();
+    assert_eq!(summary.saved, 300);
+    for (i, expected) in messages.iter().enumerate() {
+        let stored = std::fs::read(dir.join(format!("email_{:05}.eml", i + 1))).unwrap();
+        assert_eq!(&stored, expected, "message {}", i + 1);
+    }
+    assert_eq!(Catalog::open(&dir).unwrap().entries().len(), 300);
+    std::fs::remove_dir_all(&dir).unwrap();
+}